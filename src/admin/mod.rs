@@ -0,0 +1,314 @@
+//! Admin API
+//!
+//! Runtime control endpoints, served on their own listener (see
+//! [`AdminConfig`](crate::config::AdminConfig)). Responses are JSON.
+//!
+//! - `GET /deployments`: every blue-green deployment and its active pool
+//! - `GET /deployments/{name}`: a single deployment
+//! - `POST /deployments/{name}/switch`: flip the active pool, or set it with
+//!   a body of `{"active": "green"}`
+//! - `GET /backends`: every backend of every pool (the default pool, named
+//!   upstreams, route and deployment pools), with its state and requests in
+//!   flight
+//! - `POST /backends/{backend}/drain`: stop sending new requests to a
+//!   backend, named by its `name` or `host:port`, in every pool it belongs
+//!   to, while in-flight ones finish
+//! - `POST /backends/{backend}/resume`: put a drained backend back in rotation
+//! - `POST /tls/reload`: load the listener's TLS certificates again if their
+//!   files changed, responding with `{"reloaded": true}` if they did
+//! - `GET /metrics`: Prometheus metrics in the text format, with
+//!   `metrics.listener: admin`
+//!
+//! # Example
+//!
+//! ```text
+//! curl -X POST -H 'Authorization: Bearer s3cret' \
+//!     http://127.0.0.1:9901/deployments/web/switch -d '{"active":"green"}'
+//! ```
+
+use crate::config::DeploymentColor;
+use crate::http::connection::Connection;
+use crate::http::handler::Handler;
+use crate::http::request::{Method, Request};
+use crate::http::response::{Response, StatusCode};
+use crate::metrics::MetricsEndpoint;
+use crate::proxy::backend::{Backend, BackendPool, BackendPools, BackendState};
+use crate::proxy::blue_green::BlueGreen;
+use crate::tls::CertReloader;
+use async_trait::async_trait;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+/// Handler for the admin endpoints
+#[derive(Default)]
+pub struct AdminApi {
+    deployments: Vec<Arc<BlueGreen>>,
+    pools: BackendPools,
+    tls: Option<Arc<CertReloader>>,
+    metrics: Option<MetricsEndpoint>,
+    token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SwitchRequest {
+    active: DeploymentColor,
+}
+
+impl AdminApi {
+    /// Create an admin API with no deployments and no authentication
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Require `Authorization: Bearer <token>` on every request
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Expose a blue-green deployment for inspection and switching
+    pub fn deployment(mut self, deployment: Arc<BlueGreen>) -> Self {
+        self.deployments.push(deployment);
+        self
+    }
+
+    /// Expose a backend pool for inspection and draining
+    pub fn backends(self, pool: BackendPool) -> Self {
+        self.pools.register(pool);
+        self
+    }
+
+    /// Expose every pool in `pools`, including ones registered later
+    pub fn pools(mut self, pools: BackendPools) -> Self {
+        self.pools = pools;
+        self
+    }
+
+    /// Expose reloading of the listener's TLS certificates
+    pub fn tls(mut self, reloader: Arc<CertReloader>) -> Self {
+        self.tls = Some(reloader);
+        self
+    }
+
+    /// Serve Prometheus metrics at `/metrics`
+    pub fn metrics(mut self, endpoint: MetricsEndpoint) -> Self {
+        self.metrics = Some(endpoint);
+        self
+    }
+
+    /// Serve admin requests from `listener` until `shutdown` is cancelled
+    pub async fn serve(self, listener: TcpListener, shutdown: CancellationToken) {
+        let handler: Arc<dyn Handler> = Arc::new(self);
+        loop {
+            let (socket, peer) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        tracing::warn!(error = %e, "Admin accept failed");
+                        continue;
+                    }
+                },
+                _ = shutdown.cancelled() => return,
+            };
+
+            let handler = handler.clone();
+            let cancel = shutdown.child_token();
+            tokio::spawn(async move {
+                let mut conn = Connection::with_handler(socket, handler).with_cancellation(cancel);
+                if let Err(e) = conn.run().await {
+                    tracing::debug!(peer = %peer, error = %e, "Admin connection error");
+                }
+            });
+        }
+    }
+
+    fn is_authorized(&self, req: &Request) -> bool {
+        let Some(token) = &self.token else {
+            return true;
+        };
+        req.headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Authorization"))
+            .and_then(|(_, value)| value.strip_prefix("Bearer "))
+            .is_some_and(|presented| tokens_match(presented.trim(), token))
+    }
+
+    fn find(&self, name: &str) -> Option<&Arc<BlueGreen>> {
+        self.deployments.iter().find(|d| d.name() == name)
+    }
+
+    async fn set_state(&self, name: &str, state: BackendState) -> Response {
+        let mut changed = None;
+        for pool in self.pools.all() {
+            let backends = pool.get_backends().await;
+            for backend in backends.into_iter().filter(|b| is_named(b, name)) {
+                pool.set_state(&backend.url, state).await;
+                changed.get_or_insert(backend);
+            }
+        }
+        let Some(backend) = changed else {
+            return error(StatusCode::NotFound, "unknown backend");
+        };
+
+        let previous = backend.state;
+        tracing::info!(
+            backend = backend.display_name(),
+            state = state.as_str(),
+            "Backend state changed through the admin API"
+        );
+        json(
+            StatusCode::Ok,
+            serde_json::json!({
+                "url": backend.url,
+                "state": state.as_str(),
+                "previous": previous.as_str(),
+            }),
+        )
+    }
+
+    fn reload_tls(&self) -> Response {
+        let Some(reloader) = &self.tls else {
+            return error(StatusCode::NotFound, "TLS is not configured");
+        };
+        match reloader.reload() {
+            Ok(reloaded) => json(StatusCode::Ok, serde_json::json!({ "reloaded": reloaded })),
+            Err(e) => error(StatusCode::UnprocessableEntity, &format!("{:#}", e)),
+        }
+    }
+
+    fn switch(&self, deployment: &BlueGreen, body: &[u8]) -> Response {
+        let target = if body.iter().all(u8::is_ascii_whitespace) {
+            deployment.active().other()
+        } else {
+            match serde_json::from_slice::<SwitchRequest>(body) {
+                Ok(request) => request.active,
+                Err(e) => return error(StatusCode::BadRequest, &e.to_string()),
+            }
+        };
+
+        let previous = deployment.switch_to(target);
+        json(
+            StatusCode::Ok,
+            serde_json::json!({
+                "name": deployment.name(),
+                "active": target,
+                "previous": previous,
+            }),
+        )
+    }
+}
+
+#[async_trait]
+impl Handler for AdminApi {
+    async fn handle(&self, req: Request) -> Response {
+        if !self.is_authorized(&req) {
+            let mut response = error(StatusCode::Unauthorized, "missing or invalid token");
+            response
+                .headers
+                .insert("WWW-Authenticate".to_string(), "Bearer".to_string());
+            return response;
+        }
+
+        let path = req.path.split('?').next().unwrap_or(&req.path);
+        if path.trim_matches('/') == "metrics"
+            && let Some(metrics) = &self.metrics
+        {
+            return metrics.handle(req).await;
+        }
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+        match (&req.method, segments.as_slice()) {
+            (Method::GET, ["deployments"]) => {
+                let deployments: Vec<_> = self.deployments.iter().map(|d| describe(d)).collect();
+                json(StatusCode::Ok, serde_json::Value::Array(deployments))
+            }
+            (Method::GET, ["deployments", name]) => match self.find(name) {
+                Some(deployment) => json(StatusCode::Ok, describe(deployment)),
+                None => error(StatusCode::NotFound, "unknown deployment"),
+            },
+            (Method::POST, ["deployments", name, "switch"]) => match self.find(name) {
+                Some(deployment) => self.switch(deployment, &req.body),
+                None => error(StatusCode::NotFound, "unknown deployment"),
+            },
+            (_, ["deployments", ..]) => error(StatusCode::MethodNotAllowed, "method not allowed"),
+            (Method::GET, ["backends"]) => {
+                let mut backends = Vec::new();
+                for pool in self.pools.all() {
+                    for backend in pool.get_backends().await {
+                        // A backend shared by several pools is listed once
+                        if !backends.iter().any(|b: &Backend| b.url == backend.url) {
+                            backends.push(backend);
+                        }
+                    }
+                }
+                let backends: Vec<_> = backends.iter().map(describe_backend).collect();
+                json(StatusCode::Ok, serde_json::Value::Array(backends))
+            }
+            (Method::POST, ["backends", name, "drain"]) => {
+                self.set_state(name, BackendState::Draining).await
+            }
+            (Method::POST, ["backends", name, "resume"]) => {
+                self.set_state(name, BackendState::Up).await
+            }
+            (_, ["backends", ..]) => error(StatusCode::MethodNotAllowed, "method not allowed"),
+            (Method::POST, ["tls", "reload"]) => self.reload_tls(),
+            (_, ["tls", "reload"]) => error(StatusCode::MethodNotAllowed, "method not allowed"),
+            _ => error(StatusCode::NotFound, "not found"),
+        }
+    }
+}
+
+fn describe(deployment: &BlueGreen) -> serde_json::Value {
+    serde_json::json!({
+        "name": deployment.name(),
+        "active": deployment.active(),
+    })
+}
+
+fn describe_backend(backend: &Backend) -> serde_json::Value {
+    serde_json::json!({
+        "name": backend.name,
+        "url": backend.url,
+        "state": backend.state.as_str(),
+        "active_requests": backend.active_requests(),
+    })
+}
+
+/// Compare bearer tokens in constant time
+///
+/// Both sides are hashed first so neither the contents nor the length of
+/// the configured token can be learned from response timings.
+fn tokens_match(presented: &str, token: &str) -> bool {
+    Sha256::digest(presented)
+        .ct_eq(&Sha256::digest(token))
+        .into()
+}
+
+/// Whether `name` is the backend's configured name or its `host:port`
+fn is_named(backend: &Backend, name: &str) -> bool {
+    if backend.name.as_deref() == Some(name) {
+        return true;
+    }
+    url::Url::parse(&backend.url).is_ok_and(|url| {
+        let host = url.host_str().unwrap_or_default();
+        match url.port_or_known_default() {
+            Some(port) => name == format!("{}:{}", host, port),
+            None => name == host,
+        }
+    })
+}
+
+fn json(status: StatusCode, value: serde_json::Value) -> Response {
+    Response::new(status)
+        .header("Content-Type", "application/json")
+        .body(value.to_string().into_bytes())
+        .build()
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    json(status, serde_json::json!({ "error": message }))
+}
@@ -1,114 +1,4250 @@
+use crate::http::forwarded::TrustedProxies;
+use crate::http::parser::{
+    DEFAULT_MAX_HEADER_BYTES, DEFAULT_MAX_HEADERS, DEFAULT_MAX_URI_LENGTH, HeadLimits, ParseMode,
+};
+use crate::http::request::Method;
+use crate::http::response::StatusCode;
+use crate::http::router::{Predicate, ValueMatch};
+use crate::http::writer::ServerIdentity;
+use crate::middleware::rewrite::PathRewrite;
+use crate::middleware::slo::{MAX_SLO_WINDOW_SECS, SLO_BUCKET_SECS};
+use crate::proxy::maintenance::{MAX_MAINTENANCE_MINS, Schedule};
+use crate::tls::DEFAULT_SESSION_CACHE_SIZE;
+use crate::tls::client_cert::{
+    CLIENT_CERT_ISSUER_HEADER, CLIENT_CERT_PEM_HEADER, CLIENT_CERT_SHA256_HEADER,
+    CLIENT_CERT_SUBJECT_HEADER,
+};
+use anyhow::Context;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::PathBuf;
+use std::time::Duration;
 
 impl ProxyConfig {
     /// Validate backend URLs
     pub fn validate(&self) -> anyhow::Result<()> {
-        if self.backends.is_empty() {
+        // Discovery may start with an empty list and fill it at runtime
+        if self.backends.is_empty()
+            && self.discovery.is_none()
+            && self.blue_green.is_empty()
+            && self.upstreams.is_empty()
+        {
             anyhow::bail!("At least one backend must be configured");
         }
 
-        for (idx, backend) in self.backends.iter().enumerate() {
-            // Basic URL validation
-            if !backend.url.starts_with("http://") && !backend.url.starts_with("https://") {
+        if let Some(consul) = self.discovery.as_ref().and_then(|d| d.consul.as_ref()) {
+            if consul.service.is_empty() {
+                anyhow::bail!("Consul discovery requires a service name");
+            }
+            if let Err(e) = url::Url::parse(&consul.address) {
+                anyhow::bail!("Invalid Consul address '{}': {}", consul.address, e);
+            }
+        }
+
+        if let Some(docker) = self.discovery.as_ref().and_then(|d| d.docker.as_ref()) {
+            if !docker.endpoint.starts_with("unix://") && !docker.endpoint.starts_with("http://") {
+                anyhow::bail!(
+                    "Docker endpoint must start with unix:// or http://: {}",
+                    docker.endpoint
+                );
+            }
+            if docker.label_prefix.is_empty() {
+                anyhow::bail!("Docker discovery requires a label prefix");
+            }
+        }
+
+        if let Some(file) = self.discovery.as_ref().and_then(|d| d.file.as_ref())
+            && file.path.as_os_str().is_empty()
+        {
+            anyhow::bail!("File discovery requires a path");
+        }
+
+        if let Some(health) = &self.health_check {
+            health.validate()?;
+        }
+
+        if let Some(keepalive) = &self.keepalive
+            && (keepalive.max_idle_per_backend == 0 || keepalive.idle_timeout_ms == 0)
+        {
+            anyhow::bail!(
+                "Proxy keepalive max_idle_per_backend and idle_timeout_ms must be greater than 0"
+            );
+        }
+
+        for (name, upstream) in &self.upstreams {
+            if name.is_empty() {
+                anyhow::bail!("Upstream names must not be empty");
+            }
+            if upstream.backends.is_empty() {
+                anyhow::bail!("Upstream {} has no backends", name);
+            }
+            if let Some(health) = &upstream.health_check {
+                health
+                    .validate()
+                    .with_context(|| format!("Invalid health check for upstream {}", name))?;
+            }
+            validate_backends(&upstream.backends)?;
+        }
+
+        if self.unhealthy_threshold == 0 || self.healthy_threshold == 0 {
+            anyhow::bail!("Proxy unhealthy_threshold and healthy_threshold must be at least 1");
+        }
+
+        if let HashKey::Header(name) | HashKey::Cookie(name) = &self.hash_key
+            && name.is_empty()
+        {
+            anyhow::bail!("hash_key requires a header or cookie name");
+        }
+
+        if !is_token(&self.via) {
+            anyhow::bail!(
+                "Proxy via must be non-empty and use only letters, digits, '-' and '_': {:?}",
+                self.via
+            );
+        }
+
+        if let Some(locality) = &self.locality {
+            if locality.zone.is_empty() {
+                anyhow::bail!("Locality requires a zone");
+            }
+            if locality.min_healthy_percent > 100 {
+                anyhow::bail!(
+                    "Locality min_healthy_percent must be at most 100, got {}",
+                    locality.min_healthy_percent
+                );
+            }
+        }
+
+        if let Some(feedback) = &self.load_feedback {
+            if feedback.header.is_empty() {
+                anyhow::bail!("Load feedback requires a header name");
+            }
+            if !(feedback.smoothing > 0.0 && feedback.smoothing <= 1.0) {
+                anyhow::bail!(
+                    "Load feedback smoothing must be in (0, 1], got {}",
+                    feedback.smoothing
+                );
+            }
+            if feedback.min_weight_percent == 0 || feedback.min_weight_percent > 100 {
+                anyhow::bail!(
+                    "Load feedback min_weight_percent must be between 1 and 100, got {}",
+                    feedback.min_weight_percent
+                );
+            }
+        }
+
+        if self.connection_timeout_ms == 0 {
+            anyhow::bail!("Proxy connection_timeout_ms must be greater than 0");
+        }
+
+        for route in &self.route_timeouts {
+            if !route.path_prefix.starts_with('/') {
+                anyhow::bail!(
+                    "Route timeout path_prefix must start with '/': {}",
+                    route.path_prefix
+                );
+            }
+            if route.connection_timeout_ms == Some(0) {
+                anyhow::bail!(
+                    "Route timeout for {} has a connection_timeout_ms of 0",
+                    route.path_prefix
+                );
+            }
+        }
+
+        for window in &self.maintenance {
+            if window.backends.is_empty() {
+                anyhow::bail!("Maintenance window '{}' lists no backends", window.schedule);
+            }
+            if window.duration_mins == 0 {
+                anyhow::bail!(
+                    "Maintenance window '{}' must last at least a minute",
+                    window.schedule
+                );
+            }
+            if window.duration_mins > MAX_MAINTENANCE_MINS {
+                anyhow::bail!(
+                    "Maintenance window '{}' may last at most {} minutes",
+                    window.schedule,
+                    MAX_MAINTENANCE_MINS
+                );
+            }
+            Schedule::parse(&window.schedule)
+                .with_context(|| format!("Invalid maintenance schedule '{}'", window.schedule))?;
+        }
+
+        for rewrite in &self.location_rewrites {
+            if !rewrite.path_prefix.starts_with('/') {
+                anyhow::bail!(
+                    "Location rewrite path_prefix must start with '/': {}",
+                    rewrite.path_prefix
+                );
+            }
+            if rewrite.from.as_deref() == Some("") {
+                anyhow::bail!(
+                    "Location rewrite for {} has an empty 'from'",
+                    rewrite.path_prefix
+                );
+            }
+        }
+
+        for intercept in &self.intercept_errors {
+            if !intercept.path_prefix.starts_with('/') {
+                anyhow::bail!(
+                    "Intercept errors path_prefix must start with '/': {}",
+                    intercept.path_prefix
+                );
+            }
+            if !(400..=599).contains(&intercept.min_status) {
+                anyhow::bail!(
+                    "Intercept errors min_status must be 400-599, got {}",
+                    intercept.min_status
+                );
+            }
+        }
+
+        if let Some(retry_after) = &self.retry_after
+            && retry_after.secs == 0
+            && retry_after.jitter_secs == 0
+        {
+            anyhow::bail!("Retry-After requires secs or jitter_secs");
+        }
+
+        self.retry.validate()?;
+        if let Some(backoff) = &self.retry_backoff {
+            backoff.validate()?;
+        }
+
+        if let Some(mirror) = &self.mirror {
+            if mirror.backends.is_empty() {
+                anyhow::bail!("Mirror requires at least one backend");
+            }
+            if mirror.percent > 100 {
+                anyhow::bail!("Mirror percent must be at most 100, got {}", mirror.percent);
+            }
+            validate_backends(&mirror.backends)?;
+        }
+
+        for (idx, rule) in self.routing_rules.iter().enumerate() {
+            if rule.percent.is_some_and(|percent| percent > 100) {
+                anyhow::bail!("Routing rule {} percent must be at most 100", idx);
+            }
+        }
+
+        let mut names = std::collections::HashSet::new();
+        for deployment in &self.blue_green {
+            if deployment.name.is_empty() || !names.insert(deployment.name.as_str()) {
+                anyhow::bail!(
+                    "Blue-green deployment names must be unique and non-empty: {:?}",
+                    deployment.name
+                );
+            }
+            if !deployment.path_prefix.starts_with('/') {
+                anyhow::bail!(
+                    "Blue-green deployment {} path_prefix must start with '/'",
+                    deployment.name
+                );
+            }
+            if deployment.preview_header.is_empty() {
+                anyhow::bail!(
+                    "Blue-green deployment {} requires a preview header",
+                    deployment.name
+                );
+            }
+            for (color, backends) in [("blue", &deployment.blue), ("green", &deployment.green)] {
+                if backends.is_empty() {
+                    anyhow::bail!(
+                        "Blue-green deployment {} has no {} backends",
+                        deployment.name,
+                        color
+                    );
+                }
+                validate_backends(backends)?;
+            }
+        }
+
+        let mut names = std::collections::HashSet::new();
+        for experiment in &self.experiments {
+            experiment.validate()?;
+            if !names.insert(experiment.name.as_str()) {
+                anyhow::bail!("Duplicate experiment name: {}", experiment.name);
+            }
+        }
+
+        validate_backends(&self.backends)
+    }
+}
+
+/// Validate backend URLs from configuration or a discovery source
+pub fn validate_backends(backends: &[BackendConfig]) -> anyhow::Result<()> {
+    for (idx, backend) in backends.iter().enumerate() {
+        // Basic URL validation
+        if !backend.url.starts_with("http://")
+            && !backend.url.starts_with("https://")
+            && !backend.url.starts_with("uwsgi://")
+        {
+            anyhow::bail!(
+                "Backend {} URL must start with http://, https://, or uwsgi://: {}",
+                idx,
+                backend.url
+            );
+        }
+
+        // Check URL can be parsed
+        let url = match url::Url::parse(&backend.url) {
+            Ok(url) => url,
+            Err(e) => anyhow::bail!("Backend {} has invalid URL '{}': {}", idx, backend.url, e),
+        };
+
+        // uwsgi has no well-known port
+        if url.scheme() == "uwsgi" && url.port().is_none() {
+            anyhow::bail!(
+                "Backend {} uwsgi URL must include a port: {}",
+                idx,
+                backend.url
+            );
+        }
+
+        // Labels become metric label names
+        for key in backend.labels.keys() {
+            if !is_label_name(key) || matches!(key.as_str(), "backend" | "outcome") {
+                anyhow::bail!("Backend {} has invalid label name '{}'", idx, key);
+            }
+        }
+
+        if backend.max_connections == Some(0) {
+            anyhow::bail!("Backend {} max_connections must be at least 1", idx);
+        }
+
+        // h2c is HTTP/2 without TLS
+        if backend.h2c && url.scheme() != "http" {
+            anyhow::bail!(
+                "Backend {} h2c requires an http:// URL: {}",
+                idx,
+                backend.url
+            );
+        }
+
+        if backend.unhealthy_threshold == Some(0) || backend.healthy_threshold == Some(0) {
+            anyhow::bail!(
+                "Backend {} unhealthy_threshold and healthy_threshold must be at least 1",
+                idx
+            );
+        }
+        if let Some(probe) = &backend.health_check
+            && (probe.interval_ms == Some(0) || probe.timeout_ms == Some(0))
+        {
+            anyhow::bail!(
+                "Backend {} health check interval_ms and timeout_ms must be greater than 0",
+                idx
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Check a label name is usable as a Prometheus label (`[a-zA-Z_][a-zA-Z0-9_]*`)
+fn is_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Main configuration for the Sentinel server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Config {
+    /// Server listening configuration
+    pub server: ServerConfig,
+
+    /// Static file serving configuration
+    pub static_files: StaticFilesConfig,
+
+    /// Reverse proxy configuration
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<ProxyConfig>,
+
+    /// Fault injection for resilience testing (disabled unless present and enabled)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chaos: Option<ChaosConfig>,
+
+    /// CONNECT tunnelling for use as an egress proxy (disabled unless present and enabled)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forward_proxy: Option<ForwardProxyConfig>,
+
+    /// Canonical host and trailing-slash redirects, applied before routing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirects: Option<RedirectConfig>,
+
+    /// Security headers added to every response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub security_headers: Option<SecurityHeadersConfig>,
+
+    /// Replay responses for retried requests carrying an idempotency key
+    /// (disabled unless present and enabled)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency: Option<IdempotencyConfig>,
+
+    /// Allow and deny TLS clients by JA3/JA4 fingerprint
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprints: Option<FingerprintRulesConfig>,
+
+    /// Rule-based bot detection (disabled unless present)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bots: Option<BotConfig>,
+
+    /// Authorization by an external policy engine (disabled unless present)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy: Option<PolicyConfig>,
+
+    /// Record sampled traffic into HAR files (disabled unless present)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub traffic_capture: Option<TrafficCaptureConfig>,
+
+    /// Admin API on a separate listener (disabled unless present)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub admin: Option<AdminConfig>,
+
+    /// Prometheus metrics endpoint (disabled unless present)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<MetricsConfig>,
+
+    /// Routes answered with a fixed response, without touching disk or
+    /// backends
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub static_responses: Vec<StaticResponseConfig>,
+
+    /// Directories shared over WebDAV, each under its own path prefix
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub webdav: Vec<WebDavConfig>,
+
+    /// Availability and latency objectives, tracked per route
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub slos: Vec<SloConfig>,
+
+    /// Path prefixes served by their own backend pool or by static files
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<RouteConfig>,
+}
+
+/// A route answered straight from configuration
+///
+/// The body is inline text or a file read once at startup, so requests
+/// never touch the disk or a backend.
+///
+/// # Example
+///
+/// ```yaml
+/// static_responses:
+///   - path: /ping
+///     body: "ok"
+///   - path: /robots.txt
+///     file: robots.txt
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StaticResponseConfig {
+    /// Exact request path (the query string is ignored)
+    pub path: String,
+
+    /// Response status code
+    #[serde(default = "default_static_response_status")]
+    pub status: u16,
+
+    /// Response headers
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+
+    /// Inline response body
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+
+    /// File to serve as the body (relative to the static root)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+}
+
+impl StaticResponseConfig {
+    /// Check the path, status, and body source
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if !self.path.starts_with('/') {
+            anyhow::bail!("Static response path must start with '/': {}", self.path);
+        }
+        if StatusCode::from_u16(self.status).is_none() {
+            anyhow::bail!(
+                "Static response for {} has unsupported status {}",
+                self.path,
+                self.status
+            );
+        }
+        if self.body.is_some() && self.file.is_some() {
+            anyhow::bail!(
+                "Static response for {} cannot set both body and file",
+                self.path
+            );
+        }
+        Ok(())
+    }
+}
+
+/// A directory shared over WebDAV
+///
+/// Requests under `prefix` may list (`PROPFIND`), read, upload (`PUT`),
+/// create directories (`MKCOL`), delete, copy and move files beneath `root`,
+/// so the mount works as a file share for desktop WebDAV clients. Every
+/// request must carry `Authorization: Basic` credentials of one of `users`.
+///
+/// # Example
+///
+/// ```yaml
+/// webdav:
+///   - prefix: /files
+///     root: "/srv/share"
+///     users:
+///       - username: "alice"
+///         password: "s3cret"
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebDavConfig {
+    /// Path prefix of the mount, e.g. `/files`
+    pub prefix: String,
+
+    /// Directory served (relative to the working directory, like the static
+    /// root)
+    pub root: PathBuf,
+
+    /// Accepted `Authorization: Basic` credentials (at least one)
+    pub users: Vec<ProxyUser>,
+
+    /// Refuse methods that modify files
+    #[serde(default = "default_false")]
+    pub read_only: bool,
+}
+
+impl WebDavConfig {
+    /// Check the prefix and that the mount requires credentials
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if !self.prefix.starts_with('/') || self.prefix.len() < 2 || self.prefix.ends_with('/') {
+            anyhow::bail!(
+                "WebDAV prefix must start with '/' and not end with one: {}",
+                self.prefix
+            );
+        }
+        if self.users.is_empty() {
+            anyhow::bail!("WebDAV mount {} must list at least one user", self.prefix);
+        }
+        if let Some(user) = self
+            .users
+            .iter()
+            .find(|u| u.username.is_empty() || u.username.contains(':'))
+        {
+            anyhow::bail!(
+                "WebDAV mount {} has an invalid username '{}'",
+                self.prefix,
+                user.username
+            );
+        }
+        if !self.root.is_dir() {
+            anyhow::bail!(
+                "WebDAV root for {} is not a directory: {}",
+                self.prefix,
+                self.root.display()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// A path prefix or pattern with its own handling
+///
+/// Requests under `prefix`, or with a path `path_regex` matches, are
+/// handled by the route's one target (see [`RouteTarget`]): the upstream
+/// named by `pool` (or the route's own `backends`), upstreams sharing it
+/// by weight (`split`), the static root (`static_files: true`) or another
+/// directory (`static_dir`), both with the full request path, a
+/// `redirect`, or a fixed response (`respond`).
+/// Regex routes are tried in order before prefix routes, among which the
+/// longest matching prefix wins. Requests matching no route get the
+/// default handling.
+///
+/// Backends get the client's path unless the route rewrites it. A prefix
+/// route may `strip_prefix` (`/api/foo` is forwarded as `/foo`) or
+/// `rewrite` the prefix to another one. A regex route may `rewrite` the
+/// part of the path the expression matches with a template, in which `$1`
+/// or `${name}` stand for capture groups.
+///
+/// A route listing `methods` or `headers` only takes requests meeting all
+/// of them (or any, with `match: any`), and wins over a route for the same
+/// prefix without conditions.
+///
+/// # Example
+///
+/// ```yaml
+/// routes:
+///   - prefix: /api/
+///     pool: staging
+///     headers:
+///       - name: X-Beta
+///         value: "true"
+///     backends:
+///       - url: "http://127.0.0.1:3001"
+///   - prefix: /api/
+///     pool: api
+///     backends:
+///       - url: "http://127.0.0.1:3000"
+///   - prefix: /static/
+///     methods: [GET, HEAD]
+///     static_files: true
+///   - prefix: /billing/
+///     strip_prefix: true
+///     pool: billing
+///     backends:
+///       - url: "http://127.0.0.1:3003"
+///   - path_regex: '^/v1/users/(\d+)'
+///     rewrite: "/users/$1"
+///     pool: users
+///     backends:
+///       - url: "http://127.0.0.1:3002"
+///   - prefix: /docs/
+///     redirect: { location: "https://docs.example.com$path", status: 302 }
+///   - prefix: /robots.txt
+///     respond: { body: "User-agent: *\nDisallow: /" }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteConfig {
+    /// Name of the route in `$route` header templates (default: its prefix
+    /// or expression)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    /// Path prefix the route serves
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+
+    /// Regular expression matching the paths the route serves (instead of
+    /// a prefix)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_regex: Option<String>,
+
+    /// Replacement for the prefix, or template replacing the part of the
+    /// path `path_regex` matches
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rewrite: Option<String>,
+
+    /// Remove the prefix from the path before passing the request on
+    #[serde(default)]
+    pub strip_prefix: bool,
+
+    /// Upstream (under `proxy.upstreams`) the route sends requests to, or
+    /// the name of its own `backends`, used in logs and metrics
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool: Option<String>,
+
+    /// Backends of a pool used only by this route
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub backends: Vec<BackendConfig>,
+
+    /// Serve the route from the static root
+    #[serde(default)]
+    pub static_files: bool,
+
+    /// Serve the route from this directory, with the static section's
+    /// other settings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub static_dir: Option<PathBuf>,
+
+    /// Redirect the route's requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirect: Option<RouteRedirect>,
+
+    /// Answer the route's requests with a fixed response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub respond: Option<RouteResponse>,
+
+    /// Split the route's requests between upstreams by weight
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub split: Vec<SplitTarget>,
+
+    /// Request attribute keeping a client on one side of the split
+    /// (default: the client address)
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "serde_yaml::with::singleton_map"
+    )]
+    pub split_key: Option<HashKey>,
+
+    /// Methods the request must use (any of them)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub methods: Vec<String>,
+
+    /// Headers the request must carry
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub headers: Vec<HeaderMatchConfig>,
+
+    /// Whether the request must meet all conditions or any of them
+    #[serde(default, rename = "match")]
+    pub match_mode: MatchMode,
+
+    /// Headers added, replaced, or removed before the request is passed on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_headers: Option<HeaderRules>,
+
+    /// Headers added, replaced, or removed before the response is written
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_headers: Option<HeaderRules>,
+
+    /// Security headers for the route's responses, taking precedence over
+    /// the top-level `security_headers`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub security_headers: Option<SecurityHeadersConfig>,
+
+    /// Cross-origin requests allowed on the route; preflights are answered
+    /// without reaching the backend
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cors: Option<CorsConfig>,
+
+    /// Whether the route needs a client certificate, and passes its
+    /// details on (needs `server.tls.client_auth`)
+    #[serde(default)]
+    pub client_cert: ClientCertRequirement,
+}
+
+/// Header changes applied to a route's requests or responses
+///
+/// Headers are removed first, then set (replacing any value), then added
+/// (appended to an existing value as a comma-separated list). Values may
+/// use `$client_ip`, `$request_id` (the client's `X-Request-Id`, or a
+/// generated one), and `$route` (the route's name).
+///
+/// # Example
+///
+/// ```yaml
+/// routes:
+///   - prefix: /api/
+///     name: api
+///     pool: api
+///     request_headers:
+///       set: { X-Tenant: acme, X-Client-Ip: "$client_ip" }
+///       add: { X-Trace: "route=$route" }
+///       remove: [X-Internal-Debug]
+///     response_headers:
+///       remove: [X-Powered-By]
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HeaderRules {
+    /// Values appended to the header, or set when it is absent
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub add: BTreeMap<String, String>,
+
+    /// Values replacing the header
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub set: BTreeMap<String, String>,
+
+    /// Headers removed (case-insensitive)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remove: Vec<String>,
+}
+
+impl HeaderRules {
+    /// Check header names and that values fit on one line
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let names = self
+            .add
+            .keys()
+            .chain(self.set.keys())
+            .chain(self.remove.iter());
+        for name in names {
+            if !is_token(name) {
+                anyhow::bail!(
+                    "Header names must be non-empty and use only letters, digits, '-' and '_': {:?}",
+                    name
+                );
+            }
+        }
+        for (name, value) in self.add.iter().chain(self.set.iter()) {
+            if value.chars().any(|c| c == '\r' || c == '\n' || c == '\0') {
+                anyhow::bail!("Header {} value must not contain line breaks", name);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Cross-origin resource sharing policy for a route
+///
+/// # Example
+///
+/// ```yaml
+/// cors:
+///   allow_origins: ["https://app.example.com", "https://*.example.org"]
+///   allow_methods: [GET, POST, DELETE]
+///   allow_headers: [Content-Type, Authorization]
+///   expose_headers: [X-Request-Id]
+///   allow_credentials: true
+///   max_age_secs: 3600
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CorsConfig {
+    /// Origins allowed to make requests: exact origins,
+    /// `https://*.example.com` for subdomains, or `*` for any
+    pub allow_origins: Vec<String>,
+
+    /// Methods allowed in cross-origin requests
+    #[serde(default = "default_cors_methods")]
+    pub allow_methods: Vec<String>,
+
+    /// Request headers allowed beyond the CORS-safelisted ones; `*` allows
+    /// any
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow_headers: Vec<String>,
+
+    /// Response headers scripts may read beyond the safelisted ones
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub expose_headers: Vec<String>,
+
+    /// Allow cookies and other credentials
+    #[serde(default)]
+    pub allow_credentials: bool,
+
+    /// How long browsers may cache a preflight response (0 to not say)
+    #[serde(default = "default_cors_max_age")]
+    pub max_age_secs: u64,
+}
+
+impl CorsConfig {
+    /// Whether requests from `origin` are allowed
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        self.allow_origins.iter().any(|allowed| {
+            if allowed == "*" {
+                return true;
+            }
+            match allowed.split_once("://*.") {
+                Some((scheme, domain)) => origin.split_once("://").is_some_and(|(s, host)| {
+                    s.eq_ignore_ascii_case(scheme)
+                        && host
+                            .to_ascii_lowercase()
+                            .strip_suffix(&domain.to_ascii_lowercase())
+                            .and_then(|sub| sub.strip_suffix('.'))
+                            .is_some_and(|sub| !sub.is_empty())
+                }),
+                None => allowed.eq_ignore_ascii_case(origin),
+            }
+        })
+    }
+
+    /// Whether any origin is allowed, so responses need not vary by origin
+    pub fn allows_any_origin(&self) -> bool {
+        self.allow_origins.iter().any(|origin| origin == "*")
+    }
+
+    /// Whether the request header `name` is allowed
+    pub fn allows_header(&self, name: &str) -> bool {
+        self.allow_headers
+            .iter()
+            .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(name))
+    }
+
+    /// Check origins, methods, and header names
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.allow_origins.is_empty() {
+            anyhow::bail!("CORS needs at least one allowed origin");
+        }
+        for origin in &self.allow_origins {
+            let host = origin
+                .split_once("://")
+                .filter(|(scheme, _)| {
+                    scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https")
+                })
+                .map(|(_, host)| host.strip_prefix("*.").unwrap_or(host));
+            let valid = origin == "*"
+                || host.is_some_and(|host| {
+                    !host.is_empty() && !host.contains(['/', '?', '#', ' ', '*'])
+                });
+            if !valid {
+                anyhow::bail!(
+                    "CORS origins must be `*` or a scheme and host like https://example.com: {:?}",
+                    origin
+                );
+            }
+        }
+        if self.allow_credentials && self.allows_any_origin() {
+            anyhow::bail!("CORS credentials cannot be allowed for any origin (`*`)");
+        }
+        for method in &self.allow_methods {
+            if Method::from_str(method).is_none() {
+                anyhow::bail!("Unknown CORS method: {}", method);
+            }
+        }
+        let names = self.allow_headers.iter().chain(&self.expose_headers);
+        for name in names.filter(|name| *name != "*") {
+            if !is_token(name) {
+                anyhow::bail!("Invalid CORS header name: {:?}", name);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What handles a route's requests
+#[derive(Debug, Clone, Copy)]
+pub enum RouteTarget<'a> {
+    /// Proxy to the upstream or the route's own backends of this name
+    Proxy(&'a str),
+    /// Serve files from the static root, or from the given directory
+    Static(Option<&'a std::path::Path>),
+    /// Redirect the client
+    Redirect(&'a RouteRedirect),
+    /// Answer with a fixed response
+    Respond(&'a RouteResponse),
+    /// Split requests between upstreams by weight
+    Split(&'a [SplitTarget]),
+}
+
+/// Upstream receiving a weighted share of a split route's requests
+///
+/// Targets own consecutive shares of the key space in the order listed,
+/// so with the canary last, raising its weight only moves clients onto it.
+///
+/// # Example
+///
+/// ```yaml
+/// routes:
+///   - prefix: /
+///     split:
+///       - { pool: stable, weight: 95 }
+///       - { pool: canary, weight: 5 }
+///     split_key: { cookie: session }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SplitTarget {
+    /// Upstream under `proxy.upstreams`, also the metrics label
+    pub pool: String,
+
+    /// Relative share of requests
+    pub weight: u32,
+}
+
+/// Redirect answering a route's requests
+///
+/// `location` may use `$scheme`, `$host`, `$path`, `$query`, and `$1` to
+/// `$9` for capture groups of the route's `path_regex` (see
+/// [`RedirectRule`]).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteRedirect {
+    /// Template for the `Location` header
+    pub location: String,
+
+    /// Redirect status: 301, 302, 303, 307, or 308
+    #[serde(default = "default_redirect_status")]
+    pub status: u16,
+}
+
+/// Fixed response answering a route's requests, like
+/// [`StaticResponseConfig`] without a path
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteResponse {
+    /// Response status code
+    #[serde(default = "default_static_response_status")]
+    pub status: u16,
+
+    /// Response headers
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+
+    /// Inline response body
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+
+    /// File to serve as the body (relative to the static root)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+}
+
+/// A header a route requires
+///
+/// With neither `value` nor `regex`, any value matches.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeaderMatchConfig {
+    /// Header name (case-insensitive)
+    pub name: String,
+
+    /// Exact value required
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+
+    /// Regular expression the value must match
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+}
+
+/// How a route's conditions combine
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    /// Every condition must hold
+    #[default]
+    All,
+    /// At least one condition must hold
+    Any,
+}
+
+impl RouteConfig {
+    /// The route's target, failing unless exactly one is set
+    pub fn target(&self) -> anyhow::Result<RouteTarget<'_>> {
+        let pool = self.pool.as_deref().filter(|pool| !pool.is_empty());
+        let mut targets = Vec::new();
+        if let Some(pool) = pool {
+            targets.push(RouteTarget::Proxy(pool));
+        }
+        if self.static_files {
+            targets.push(RouteTarget::Static(None));
+        }
+        if let Some(dir) = &self.static_dir {
+            targets.push(RouteTarget::Static(Some(dir)));
+        }
+        if let Some(redirect) = &self.redirect {
+            targets.push(RouteTarget::Redirect(redirect));
+        }
+        if let Some(respond) = &self.respond {
+            targets.push(RouteTarget::Respond(respond));
+        }
+        if !self.split.is_empty() {
+            targets.push(RouteTarget::Split(&self.split));
+        }
+
+        match targets[..] {
+            [target] => {
+                if pool.is_none() && !self.backends.is_empty() {
+                    anyhow::bail!("Route {} lists backends without a pool", self.pattern());
+                }
+                Ok(target)
+            }
+            [] => anyhow::bail!(
+                "Route {} needs a pool, split, static_files, static_dir, redirect, or respond",
+                self.pattern()
+            ),
+            _ => anyhow::bail!("Route {} has more than one target", self.pattern()),
+        }
+    }
+
+    /// The static response answering the route's requests, for a fixed
+    /// response target (regex routes use `/` as its path)
+    pub fn static_response(&self) -> Option<StaticResponseConfig> {
+        self.respond.as_ref().map(|respond| StaticResponseConfig {
+            path: self.prefix.clone().unwrap_or_else(|| "/".to_string()),
+            status: respond.status,
+            headers: respond.headers.clone(),
+            body: respond.body.clone(),
+            file: respond.file.clone(),
+        })
+    }
+
+    /// The redirect rule answering the route's requests, for a redirect
+    /// target
+    pub fn redirect_rule(&self) -> Option<RedirectRule> {
+        self.redirect.as_ref().map(|redirect| RedirectRule {
+            host: None,
+            scheme: None,
+            path_regex: self.path_regex.clone(),
+            location: redirect.location.clone(),
+            status: redirect.status,
+        })
+    }
+
+    /// The route's name, or else its prefix or expression
+    pub fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or_else(|| self.pattern())
+    }
+
+    /// The prefix or expression identifying the route in logs and errors
+    pub fn pattern(&self) -> &str {
+        self.prefix
+            .as_deref()
+            .or(self.path_regex.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// The compiled `path_regex`, if the route has one
+    pub fn regex(&self) -> anyhow::Result<Option<regex::Regex>> {
+        self.path_regex
+            .as_deref()
+            .map(|pattern| {
+                regex::Regex::new(pattern)
+                    .with_context(|| format!("Invalid route path_regex: {}", pattern))
+            })
+            .transpose()
+    }
+
+    /// How the route rewrites request paths, if it does
+    pub fn path_rewrite(&self) -> anyhow::Result<Option<PathRewrite>> {
+        if self.strip_prefix && self.rewrite.is_some() {
+            anyhow::bail!(
+                "Route {} cannot both strip_prefix and rewrite",
+                self.pattern()
+            );
+        }
+        let replacement = match (&self.rewrite, self.strip_prefix) {
+            (Some(rewrite), _) => rewrite.clone(),
+            (None, true) => String::new(),
+            (None, false) => return Ok(None),
+        };
+
+        if let Some(prefix) = &self.prefix {
+            return Ok(Some(PathRewrite::Prefix(prefix.clone(), replacement)));
+        }
+        match self.regex()? {
+            Some(_) if self.strip_prefix => anyhow::bail!(
+                "Route {} can only strip_prefix with a prefix",
+                self.pattern()
+            ),
+            Some(regex) => Ok(Some(PathRewrite::Regex(regex, replacement))),
+            None => Ok(None),
+        }
+    }
+
+    /// The route's method and header conditions, if it has any
+    ///
+    /// Fails on unknown methods, headers with both a value and a regex, and
+    /// invalid regular expressions.
+    pub fn predicate(&self) -> anyhow::Result<Option<Predicate>> {
+        let mut predicates = Vec::new();
+        if !self.methods.is_empty() {
+            let mut methods: Vec<Method> = self
+                .methods
+                .iter()
+                .map(|m| {
+                    Method::from_str(m).with_context(|| {
+                        format!("Unknown method in route {}: {}", self.pattern(), m)
+                    })
+                })
+                .collect::<anyhow::Result<_>>()?;
+            // Preflights for the route come in as OPTIONS
+            if self.cors.is_some() && !methods.contains(&Method::OPTIONS) {
+                methods.push(Method::OPTIONS);
+            }
+            predicates.push(Predicate::Method(methods));
+        }
+        for header in &self.headers {
+            let value = match (&header.value, &header.regex) {
+                (Some(_), Some(_)) => anyhow::bail!(
+                    "Route {} header {} cannot have both a value and a regex",
+                    self.pattern(),
+                    header.name
+                ),
+                (Some(value), None) => ValueMatch::Exact(value.clone()),
+                (None, Some(regex)) => {
+                    ValueMatch::Regex(regex::Regex::new(regex).with_context(|| {
+                        format!(
+                            "Invalid regex for route {} header {}",
+                            self.pattern(),
+                            header.name
+                        )
+                    })?)
+                }
+                (None, None) => ValueMatch::Present,
+            };
+            predicates.push(Predicate::Header(header.name.clone(), value));
+        }
+
+        Ok((!predicates.is_empty()).then(|| match self.match_mode {
+            MatchMode::All => Predicate::All(predicates),
+            MatchMode::Any => Predicate::Any(predicates),
+        }))
+    }
+
+    /// Check the path matching, conditions, and that the route has exactly
+    /// one target
+    pub fn validate(&self) -> anyhow::Result<()> {
+        match &self.prefix {
+            Some(_) if self.path_regex.is_some() => anyhow::bail!(
+                "Route {} cannot have both a prefix and a path_regex",
+                self.pattern()
+            ),
+            Some(prefix) if !prefix.starts_with('/') => {
+                anyhow::bail!("Route prefix must start with '/': {}", prefix)
+            }
+            Some(_) => {}
+            None if self.path_regex.is_none() => {
+                anyhow::bail!("Every route needs a prefix or a path_regex")
+            }
+            None => {}
+        }
+        self.path_rewrite()?;
+        self.regex()?;
+        if let Some(header) = self.headers.iter().find(|h| h.name.is_empty()) {
+            anyhow::bail!(
+                "Route {} has a header without a name: {:?}",
+                self.pattern(),
+                header
+            );
+        }
+        self.predicate()?;
+        if let Some(rules) = &self.request_headers {
+            rules
+                .validate()
+                .with_context(|| format!("Invalid request_headers for route {}", self.pattern()))?;
+        }
+        if let Some(rules) = &self.response_headers {
+            rules.validate().with_context(|| {
+                format!("Invalid response_headers for route {}", self.pattern())
+            })?;
+        }
+        if let Some(security) = &self.security_headers {
+            security.validate().with_context(|| {
+                format!("Invalid security_headers for route {}", self.pattern())
+            })?;
+        }
+        if let Some(cors) = &self.cors {
+            cors.validate()
+                .with_context(|| format!("Invalid cors for route {}", self.pattern()))?;
+        }
+        match self.target()? {
+            RouteTarget::Proxy(_) => validate_backends(&self.backends)?,
+            RouteTarget::Static(Some(dir)) if !dir.is_dir() => anyhow::bail!(
+                "Route {} static_dir is not a directory: {}",
+                self.pattern(),
+                dir.display()
+            ),
+            RouteTarget::Static(_) => {}
+            RouteTarget::Redirect(_) => {
+                let rule = self.redirect_rule().expect("redirect target");
+                RedirectConfig {
+                    rules: vec![rule],
+                    ..Default::default()
+                }
+                .validate()
+                .with_context(|| format!("Invalid redirect for route {}", self.pattern()))?;
+            }
+            RouteTarget::Split(targets) => {
+                let mut pools = std::collections::HashSet::new();
+                for target in targets {
+                    if target.pool.is_empty() || !pools.insert(target.pool.as_str()) {
+                        anyhow::bail!(
+                            "Route {} split pools must be named and unique: {:?}",
+                            self.pattern(),
+                            target.pool
+                        );
+                    }
+                }
+                if targets.iter().all(|target| target.weight == 0) {
+                    anyhow::bail!("Route {} split needs a positive weight", self.pattern());
+                }
+                if let Some(HashKey::Header(name) | HashKey::Cookie(name)) = &self.split_key
+                    && name.is_empty()
+                {
+                    anyhow::bail!(
+                        "Route {} split_key requires a header or cookie name",
+                        self.pattern()
+                    );
+                }
+            }
+            RouteTarget::Respond(_) => {
+                let response = self.static_response().expect("respond target");
+                response
+                    .validate()
+                    .with_context(|| format!("Invalid response for route {}", self.pattern()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Server listening settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerConfig {
+    /// Address to bind to (e.g., "127.0.0.1:8080")
+    pub listen_addr: String,
+
+    /// Maximum time to produce a response, in milliseconds (unlimited if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_timeout_ms: Option<u64>,
+
+    /// Close keep-alive connections after this many requests (unlimited if
+    /// unset), so long-lived clients reconnect and spread across instances
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_requests_per_connection: Option<u64>,
+
+    /// Write the raw bytes of requests the parser rejects to a capture file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub malformed_capture: Option<MalformedCaptureConfig>,
+
+    /// Fork into the background with a pidfile (Unix only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daemon: Option<DaemonConfig>,
+
+    /// Terminate TLS on the listener (plain HTTP if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfig>,
+
+    /// Spool large request bodies to disk and cap their size (bodies are
+    /// held in memory without a limit if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_body: Option<RequestBodyConfig>,
+
+    /// Largest request line plus headers, in bytes; larger requests get
+    /// `431 Request Header Fields Too Large`
+    #[serde(default = "default_max_header_bytes")]
+    pub max_header_bytes: usize,
+
+    /// Most headers in a request; more get `431 Request Header Fields Too Large`
+    #[serde(default = "default_max_headers")]
+    pub max_headers: usize,
+
+    /// Longest request target; longer ones get `414 URI Too Long`
+    #[serde(default = "default_max_uri_length")]
+    pub max_uri_length: usize,
+
+    /// Accept LF-only line endings and folded header lines from legacy
+    /// clients (built-in engine only; strict CRLF parsing if unset)
+    #[serde(default)]
+    pub lenient_parsing: bool,
+
+    /// `Server` header on responses (`sentinel/<version>` by default; empty
+    /// to send none)
+    #[serde(default = "default_server_header")]
+    pub server_header: String,
+
+    /// Whether a backend's own `Server` header is kept, replaced by
+    /// `server_header`, or stripped
+    #[serde(default)]
+    pub backend_server_header: BackendServerHeader,
+
+    /// Headers removed from every response, e.g. `X-Powered-By`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scrub_response_headers: Vec<String>,
+
+    /// Addresses and CIDR ranges of proxies whose `X-Forwarded-Proto` is
+    /// believed (nobody's if empty)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trusted_proxies: Vec<String>,
+}
+
+impl ServerConfig {
+    /// How request heads are parsed
+    pub fn parse_mode(&self) -> ParseMode {
+        if self.lenient_parsing {
+            ParseMode::Lenient
+        } else {
+            ParseMode::Strict
+        }
+    }
+
+    /// Limits on request heads
+    pub fn head_limits(&self) -> HeadLimits {
+        HeadLimits {
+            max_header_bytes: self.max_header_bytes,
+            max_headers: self.max_headers,
+            max_uri_length: self.max_uri_length,
+        }
+    }
+
+    /// How responses identify the server, checking the header value and
+    /// names
+    pub fn server_identity(&self) -> anyhow::Result<ServerIdentity> {
+        if self.server_header.chars().any(|c| c.is_ascii_control()) {
+            anyhow::bail!("server.server_header must be on one line");
+        }
+        if let Some(name) = self.scrub_response_headers.iter().find(|n| !is_token(n)) {
+            anyhow::bail!(
+                "Invalid header name in server.scrub_response_headers: {:?}",
+                name
+            );
+        }
+        let server = self.server_header.trim();
+        Ok(ServerIdentity {
+            server: (!server.is_empty()).then(|| server.to_string()),
+            backend: self.backend_server_header,
+            scrub: self.scrub_response_headers.clone(),
+        })
+    }
+
+    /// Proxies whose forwarding headers are believed, checking the entries
+    pub fn trusted_proxies(&self) -> anyhow::Result<TrustedProxies> {
+        TrustedProxies::new(&self.trusted_proxies).context("Invalid server.trusted_proxies")
+    }
+}
+
+/// What happens to a `Server` header a response already has
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendServerHeader {
+    /// Send it as it is
+    #[default]
+    Keep,
+    /// Send `server_header` instead
+    Replace,
+    /// Send no `Server` header
+    Strip,
+}
+
+/// Limits on request bodies
+///
+/// Bodies up to `memory_limit_bytes` are held in memory; larger ones are
+/// streamed into a temporary file under `spool_dir` and streamed from there
+/// to the backend, so large uploads do not grow proxy memory. Requests
+/// declaring more than `max_bytes` are answered with `413 Payload Too Large`.
+///
+/// # Example
+///
+/// ```yaml
+/// server:
+///   request_body:
+///     memory_limit_bytes: 1048576
+///     max_bytes: 2147483648
+///     spool_dir: /var/spool/sentinel
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestBodyConfig {
+    /// Largest body held in memory
+    #[serde(default = "default_body_memory_limit_bytes")]
+    pub memory_limit_bytes: usize,
+
+    /// Largest body accepted
+    #[serde(default = "default_body_max_bytes")]
+    pub max_bytes: u64,
+
+    /// Directory for spooled bodies (the system temp directory if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spool_dir: Option<PathBuf>,
+}
+
+impl Default for RequestBodyConfig {
+    fn default() -> Self {
+        Self {
+            memory_limit_bytes: default_body_memory_limit_bytes(),
+            max_bytes: default_body_max_bytes(),
+            spool_dir: None,
+        }
+    }
+}
+
+impl RequestBodyConfig {
+    /// Check that the limits are consistent
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.max_bytes == 0 {
+            anyhow::bail!("Request body max_bytes must be greater than 0");
+        }
+        if self.memory_limit_bytes as u64 > self.max_bytes {
+            anyhow::bail!(
+                "Request body memory_limit_bytes ({}) exceeds max_bytes ({})",
+                self.memory_limit_bytes,
+                self.max_bytes
+            );
+        }
+        Ok(())
+    }
+}
+
+/// TLS termination for client connections
+///
+/// Clients asking for a hostname in `certificates` (by SNI) get that
+/// certificate; all others get `cert_file`. A `*.example.com` entry covers
+/// one level of subdomains, and an exact entry wins over a wildcard.
+///
+/// Certificate and key files are checked for changes every
+/// `reload_interval_secs`, on `SIGHUP`, and on `POST /tls/reload` through
+/// the admin API. Changed files are loaded and swapped in for new
+/// handshakes; established connections are not affected, and files that
+/// fail to load leave the current certificates in place.
+///
+/// TLS 1.2 and 1.3 are supported (1.0 and 1.1 never are), with every
+/// cipher suite rustls offers unless `cipher_suites` narrows them down.
+/// ALPN advertises `http/1.1`; `h2` needs the `hyper-engine` feature.
+///
+/// # Example
+///
+/// ```yaml
+/// server:
+///   tls:
+///     cert_file: /etc/sentinel/cert.pem
+///     key_file: /etc/sentinel/key.pem
+///     min_version: "1.3"
+///     alpn: [h2, http/1.1]
+///     certificates:
+///       shop.example.com:
+///         cert_file: /etc/sentinel/shop.pem
+///         key_file: /etc/sentinel/shop-key.pem
+///       "*.tenants.example.com":
+///         cert_file: /etc/sentinel/tenants.pem
+///         key_file: /etc/sentinel/tenants-key.pem
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// PEM certificate chain, leaf first
+    pub cert_file: PathBuf,
+
+    /// PEM private key (PKCS#8, PKCS#1, or SEC1)
+    pub key_file: PathBuf,
+
+    /// Certificates for other hostnames, chosen by SNI
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub certificates: BTreeMap<String, CertificateConfig>,
+
+    /// Write session secrets here in NSS key log format, for decrypting
+    /// captures while debugging (`SSLKEYLOGFILE` is used if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_log_file: Option<PathBuf>,
+
+    /// Fetch OCSP responses for the default certificate and staple them
+    /// to handshakes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ocsp: Option<OcspConfig>,
+
+    /// Verify client certificates (mutual TLS)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_auth: Option<ClientAuthConfig>,
+
+    /// How often certificate and key files are checked for changes (0
+    /// only reloads on `SIGHUP` or through the admin API)
+    #[serde(default = "default_tls_reload_interval")]
+    pub reload_interval_secs: u64,
+
+    /// Oldest protocol version accepted
+    #[serde(default = "default_tls_min_version")]
+    pub min_version: TlsVersion,
+
+    /// Newest protocol version accepted
+    #[serde(default = "default_tls_max_version")]
+    pub max_version: TlsVersion,
+
+    /// Cipher suites by IANA name (e.g. `TLS13_AES_256_GCM_SHA384`,
+    /// `TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256`), most preferred first; all
+    /// supported ones if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cipher_suites: Vec<String>,
+
+    /// ALPN protocols offered, most preferred first (`http/1.1`, `h2`)
+    #[serde(default = "default_alpn")]
+    pub alpn: Vec<String>,
+}
+
+impl TlsConfig {
+    /// Check the version range, ALPN protocols, and client certificate
+    /// headers
+    ///
+    /// Cipher suite names are checked against what rustls supports when
+    /// the server config is built.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let Some(client_auth) = &self.client_auth {
+            client_auth.headers.validate()?;
+        }
+        if self.min_version > self.max_version {
+            anyhow::bail!(
+                "TLS min_version {} is newer than max_version {}",
+                self.min_version,
+                self.max_version
+            );
+        }
+        for (i, protocol) in self.alpn.iter().enumerate() {
+            if !ALPN_PROTOCOLS.contains(&protocol.as_str()) {
+                anyhow::bail!(
+                    "Unsupported ALPN protocol {:?}; expected one of {}",
+                    protocol,
+                    ALPN_PROTOCOLS.join(", ")
+                );
+            }
+            if self.alpn[..i].contains(protocol) {
+                anyhow::bail!("ALPN protocol {} is listed twice", protocol);
+            }
+        }
+        for (i, suite) in self.cipher_suites.iter().enumerate() {
+            if self.cipher_suites[..i]
+                .iter()
+                .any(|s| s.eq_ignore_ascii_case(suite))
+            {
+                anyhow::bail!("TLS cipher suite {} is listed twice", suite);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// ALPN protocol IDs the listener can speak
+pub const ALPN_PROTOCOLS: [&str; 2] = ["http/1.1", "h2"];
+
+/// A TLS protocol version, written `"1.2"` or `"1.3"`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "TlsVersionText", into = "String")]
+pub enum TlsVersion {
+    /// TLS 1.2
+    Tls12,
+    /// TLS 1.3
+    Tls13,
+}
+
+impl std::fmt::Display for TlsVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Tls12 => "1.2",
+            Self::Tls13 => "1.3",
+        })
+    }
+}
+
+impl From<TlsVersion> for String {
+    fn from(version: TlsVersion) -> Self {
+        version.to_string()
+    }
+}
+
+/// The forms a TLS version may be written in: YAML reads an unquoted
+/// `1.2` as a number
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TlsVersionText {
+    Text(String),
+    Number(f64),
+}
+
+impl TryFrom<TlsVersionText> for TlsVersion {
+    type Error = String;
+
+    fn try_from(text: TlsVersionText) -> Result<Self, Self::Error> {
+        let text = match text {
+            TlsVersionText::Text(text) => text,
+            TlsVersionText::Number(number) => format!("{:.1}", number),
+        };
+        let version = text.trim();
+        let version = version
+            .strip_prefix("TLSv")
+            .or_else(|| version.strip_prefix("TLS"))
+            .unwrap_or(version)
+            .trim();
+        match version {
+            "1.2" => Ok(Self::Tls12),
+            "1.3" => Ok(Self::Tls13),
+            "1.0" | "1.1" => Err(format!("TLS {} is not supported; use 1.2 or 1.3", version)),
+            _ => Err(format!("Unknown TLS version {:?}; use 1.2 or 1.3", text)),
+        }
+    }
+}
+
+/// A certificate served for the hostnames it is configured under
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CertificateConfig {
+    /// PEM certificate chain, leaf first
+    pub cert_file: PathBuf,
+
+    /// PEM private key (PKCS#8, PKCS#1, or SEC1)
+    pub key_file: PathBuf,
+}
+
+/// Client certificate verification on the TLS listener
+///
+/// Certificates must chain to a CA in `ca_file`. With `mode: required`,
+/// handshakes without one fail; with `optional`, clients may connect
+/// without a certificate and routes decide (see
+/// [`RouteConfig::client_cert`]).
+///
+/// # Example
+///
+/// ```yaml
+/// server:
+///   tls:
+///     cert_file: /etc/sentinel/cert.pem
+///     key_file: /etc/sentinel/key.pem
+///     client_auth:
+///       ca_file: /etc/sentinel/clients-ca.pem
+///       mode: optional
+///       headers:
+///         subject: X-Client-Cert-DN
+///         pem: ""
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientAuthConfig {
+    /// PEM bundle of the CAs client certificates must chain to
+    pub ca_file: PathBuf,
+
+    /// Whether every handshake must present a certificate
+    #[serde(default)]
+    pub mode: ClientAuthMode,
+
+    /// Request headers passing the certificate's details to backends
+    #[serde(default)]
+    pub headers: ClientCertHeaders,
+}
+
+/// Request headers carrying a verified client certificate's details to
+/// the backends of routes with `client_cert` set
+///
+/// An empty name leaves that detail out. Whatever clients send under
+/// these names is removed on every route, so backends can trust them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClientCertHeaders {
+    /// Subject distinguished name, RFC 4514 (`CN=billing,O=Example`)
+    pub subject: String,
+
+    /// Issuer distinguished name, RFC 4514
+    pub issuer: String,
+
+    /// Lowercase hex SHA-256 of the DER certificate
+    pub sha256: String,
+
+    /// The certificate as URL-encoded PEM
+    pub pem: String,
+}
+
+impl Default for ClientCertHeaders {
+    fn default() -> Self {
+        Self {
+            subject: CLIENT_CERT_SUBJECT_HEADER.to_string(),
+            issuer: CLIENT_CERT_ISSUER_HEADER.to_string(),
+            sha256: CLIENT_CERT_SHA256_HEADER.to_string(),
+            pem: CLIENT_CERT_PEM_HEADER.to_string(),
+        }
+    }
+}
+
+impl ClientCertHeaders {
+    /// The header names in use
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        [&self.subject, &self.issuer, &self.sha256, &self.pem]
+            .into_iter()
+            .map(String::as_str)
+            .filter(|name| !name.is_empty())
+    }
+
+    /// Check the names are valid and distinct
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut seen = std::collections::HashSet::new();
+        for name in self.names() {
+            if !is_token(name) {
+                anyhow::bail!("Invalid client certificate header name: {:?}", name);
+            }
+            if !seen.insert(name.to_ascii_lowercase()) {
+                anyhow::bail!("Client certificate header {} is used twice", name);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether the TLS listener requires client certificates
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientAuthMode {
+    /// Fail handshakes without a valid certificate
+    #[default]
+    Required,
+    /// Accept handshakes without a certificate, but not with an invalid one
+    Optional,
+}
+
+/// Whether a route needs a verified client certificate
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientCertRequirement {
+    /// No requirement; details of a certificate the client presented are
+    /// passed on as for every request on the listener
+    #[default]
+    Off,
+    /// The same as `Off`, stating that the route expects a certificate
+    /// from some clients
+    Optional,
+    /// Answer `403 Forbidden` without a certificate
+    Require,
+}
+
+/// OCSP stapling
+///
+/// The responder is taken from the certificate's Authority Information
+/// Access extension unless `responder_url` is set, and the certificate
+/// file must include the issuer after the leaf. Responses are fetched in
+/// the background and a staple that has passed its `nextUpdate` is dropped
+/// rather than served.
+///
+/// # Example
+///
+/// ```yaml
+/// server:
+///   tls:
+///     cert_file: /etc/sentinel/fullchain.pem
+///     key_file: /etc/sentinel/key.pem
+///     ocsp:
+///       refresh_secs: 3600
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OcspConfig {
+    /// Interval between fetches after a success (in seconds); a response
+    /// is refetched sooner if it expires first
+    #[serde(default = "default_ocsp_refresh")]
+    pub refresh_secs: u64,
+
+    /// Interval between fetches after a failure (in seconds)
+    #[serde(default = "default_ocsp_retry")]
+    pub retry_secs: u64,
+
+    /// Timeout for a fetch (in milliseconds)
+    #[serde(default = "default_ocsp_timeout")]
+    pub timeout_ms: u64,
+
+    /// `http://` responder to use instead of the certificate's
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub responder_url: Option<String>,
+}
+
+impl Default for OcspConfig {
+    fn default() -> Self {
+        Self {
+            refresh_secs: default_ocsp_refresh(),
+            retry_secs: default_ocsp_retry(),
+            timeout_ms: default_ocsp_timeout(),
+            responder_url: None,
+        }
+    }
+}
+
+/// TLS settings for `https://` backends
+///
+/// Backend certificates are verified against the Mozilla root store plus
+/// any certificates in `ca_file`. Each backend remembers up to
+/// `session_cache_size` sessions, so new connections to it resume one
+/// instead of doing a full handshake.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpstreamTlsConfig {
+    /// Extra PEM CA certificates to trust (e.g. an internal CA)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_file: Option<PathBuf>,
+
+    /// Write session secrets here in NSS key log format, for decrypting
+    /// captures while debugging (`SSLKEYLOGFILE` is used if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_log_file: Option<PathBuf>,
+
+    /// Sessions kept per backend for resumption; 0 disables resumption
+    #[serde(default = "default_tls_session_cache_size")]
+    pub session_cache_size: usize,
+}
+
+impl Default for UpstreamTlsConfig {
+    fn default() -> Self {
+        Self {
+            ca_file: None,
+            key_log_file: None,
+            session_cache_size: default_tls_session_cache_size(),
+        }
+    }
+}
+
+/// Reuse of HTTP/1.1 connections to backends
+///
+/// A connection whose response was read to its end is kept open and used
+/// for a later request to the same backend, saving a connect (and a TLS
+/// handshake for `https://` backends) per request. Keep `idle_timeout_ms`
+/// below the backends' own keep-alive timeout so they don't close
+/// connections as they are reused.
+///
+/// # Example
+///
+/// ```yaml
+/// proxy:
+///   keepalive:
+///     max_idle_per_backend: 16
+///     idle_timeout_ms: 30000
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpstreamKeepaliveConfig {
+    /// Idle connections kept open per backend
+    #[serde(default = "default_keepalive_max_idle")]
+    pub max_idle_per_backend: usize,
+
+    /// How long a connection may sit idle before it is closed (in
+    /// milliseconds)
+    #[serde(default = "default_keepalive_idle_timeout")]
+    pub idle_timeout_ms: u64,
+}
+
+impl Default for UpstreamKeepaliveConfig {
+    fn default() -> Self {
+        Self {
+            max_idle_per_backend: default_keepalive_max_idle(),
+            idle_timeout_ms: default_keepalive_idle_timeout(),
+        }
+    }
+}
+
+/// Classic daemon mode, for init scripts rather than systemd
+///
+/// Sentinel detaches from the terminal, writes its PID to `pid_file`, and
+/// logs to `log_file`, reopening it on `SIGHUP` so it can be rotated. The
+/// working directory is kept, so relative paths in the config still
+/// resolve.
+///
+/// # Example
+///
+/// ```yaml
+/// server:
+///   daemon:
+///     pid_file: /var/run/sentinel.pid
+///     log_file: /var/log/sentinel/sentinel.log
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DaemonConfig {
+    /// File the daemon's PID is written to, removed on exit
+    pub pid_file: PathBuf,
+
+    /// Log file, appended to (logs are discarded if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_file: Option<PathBuf>,
+}
+
+/// Capture of requests rejected by the parser
+///
+/// Each rejected request is appended to `path` as one JSON line holding the
+/// peer address, the parse error, and the first `max_bytes` of the request
+/// with the values of `redact_headers` replaced. At most `per_minute`
+/// requests are written; the rest are counted in the next line written.
+/// Only the built-in engine can capture; hyper does its own parsing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MalformedCaptureConfig {
+    /// Capture file, appended to
+    pub path: PathBuf,
+
+    /// Largest number of request bytes written per capture
+    #[serde(default = "default_capture_max_bytes")]
+    pub max_bytes: usize,
+
+    /// Most captures written per minute
+    #[serde(default = "default_capture_per_minute")]
+    pub per_minute: u32,
+
+    /// Headers whose values are never written
+    #[serde(default = "default_capture_redact_headers")]
+    pub redact_headers: Vec<String>,
+}
+
+/// Capture of sampled traffic into HAR files
+///
+/// `sample_percent` of requests are recorded with their responses and
+/// timing. Entries are buffered and written as a new HAR file in `dir`
+/// every `entries_per_file` entries, every `flush_secs`, and at shutdown.
+/// Header values in `redact_headers` are never written; bodies are only
+/// written when `bodies` is set, truncated to `max_body_bytes`, with the
+/// values of `redact_fields` replaced in JSON and form bodies.
+///
+/// Captures can be re-sent with `sentinel replay <file.har> <target-url>`.
+///
+/// # Example
+///
+/// ```yaml
+/// traffic_capture:
+///   dir: "captures"
+///   sample_percent: 5
+///   bodies: true
+///   redact_fields: ["password", "card_number"]
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrafficCaptureConfig {
+    /// Directory HAR files are written to (created if missing)
+    pub dir: PathBuf,
+
+    /// Percentage of requests recorded
+    #[serde(default = "default_traffic_sample_percent")]
+    pub sample_percent: u8,
+
+    /// Entries per HAR file
+    #[serde(default = "default_traffic_entries_per_file")]
+    pub entries_per_file: usize,
+
+    /// Longest time an entry is buffered before being written, in seconds
+    #[serde(default = "default_traffic_flush_secs")]
+    pub flush_secs: u64,
+
+    /// Record request and response bodies
+    #[serde(default)]
+    pub bodies: bool,
+
+    /// Largest body recorded, in bytes; longer bodies are truncated
+    #[serde(default = "default_traffic_max_body_bytes")]
+    pub max_body_bytes: usize,
+
+    /// Headers whose values are never written
+    #[serde(default = "default_capture_redact_headers")]
+    pub redact_headers: Vec<String>,
+
+    /// JSON and form fields whose values are never written
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redact_fields: Vec<String>,
+}
+
+impl TrafficCaptureConfig {
+    /// Check sampling and file settings
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.sample_percent > 100 {
+            anyhow::bail!(
+                "Traffic capture sample_percent must be at most 100, got {}",
+                self.sample_percent
+            );
+        }
+        if self.entries_per_file == 0 || self.flush_secs == 0 {
+            anyhow::bail!("Traffic capture entries_per_file and flush_secs must be greater than 0");
+        }
+        Ok(())
+    }
+}
+
+/// Admin API settings
+///
+/// The admin API changes routing at runtime, so it listens on its own
+/// address (keep it off public interfaces) and can require a bearer token.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminConfig {
+    /// Address to bind to (e.g., "127.0.0.1:9901")
+    pub listen_addr: String,
+
+    /// Token required as `Authorization: Bearer <token>` (no auth if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+/// Prometheus metrics endpoint
+///
+/// Request, route, upstream, and backend health metrics are aggregated in
+/// memory and served in the Prometheus text format at `path` on the main
+/// listener, or at `/metrics` on the admin listener (behind its token).
+///
+/// # Example
+///
+/// ```yaml
+/// metrics:
+///   listener: admin
+///   buckets: [0.01, 0.05, 0.1, 0.5, 1, 5]
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetricsConfig {
+    /// Which listener serves the metrics
+    #[serde(default)]
+    pub listener: MetricsListener,
+
+    /// Path of the endpoint on the main listener
+    #[serde(default = "default_metrics_path")]
+    pub path: String,
+
+    /// Upper bounds of the duration histograms' buckets (in seconds; the
+    /// Prometheus client defaults if empty)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub buckets: Vec<f64>,
+}
+
+impl MetricsConfig {
+    /// Check the path and buckets
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if !self.path.starts_with('/') {
+            anyhow::bail!("Metrics path must start with '/': {}", self.path);
+        }
+        if let Some(bucket) = self.buckets.iter().find(|b| !b.is_finite() || **b <= 0.0) {
+            anyhow::bail!("Metrics buckets must be positive numbers, got {}", bucket);
+        }
+        Ok(())
+    }
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            listener: MetricsListener::default(),
+            path: default_metrics_path(),
+            buckets: Vec::new(),
+        }
+    }
+}
+
+/// Listener serving the metrics endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricsListener {
+    /// The main listener, at `path`
+    #[default]
+    Main,
+    /// The admin API's listener, at `/metrics`
+    Admin,
+}
+
+/// Configuration for serving static files
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StaticFilesConfig {
+    /// Root directory for static files
+    pub root: PathBuf,
+
+    /// Default file to serve for directory requests
+    pub index: String,
+
+    /// Custom error pages
+    #[serde(default)]
+    pub error_pages: ErrorPages,
+
+    /// Enable or disable directory listings (for future implementation)
+    #[serde(default = "default_false")]
+    pub directory_listing: bool,
+
+    /// Resize and convert images on request (requires the `images` feature)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub images: Option<ImageConfig>,
+}
+
+/// On-the-fly image transformation for static files
+///
+/// Images under the static root are transformed when requested with query
+/// parameters: `w` and `h` bound the size (the aspect ratio is kept and
+/// images are never enlarged), and `format` converts to `webp`, `avif`,
+/// `png` or `jpeg`, or picks the best the client accepts with `auto`.
+/// Results are cached in memory, keyed by the source's content.
+///
+/// # Example
+///
+/// ```yaml
+/// static_files:
+///   images:
+///     widths: [320, 640, 1280]
+///     quality: 75
+/// ```
+///
+/// `/photos/cat.jpg?w=640&format=auto` then serves a 640px wide AVIF or
+/// WebP to clients that accept one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageConfig {
+    /// Largest width or height that may be requested
+    #[serde(default = "default_image_max_dimension")]
+    pub max_dimension: u32,
+
+    /// Widths that may be requested (any up to `max_dimension` when empty);
+    /// listing them keeps clients from filling the cache with variants
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub widths: Vec<u32>,
+
+    /// Quality of lossy encodings (JPEG and AVIF), 1-100
+    #[serde(default = "default_image_quality")]
+    pub quality: u8,
+
+    /// Largest source file transformed, in bytes
+    #[serde(default = "default_image_max_source_bytes")]
+    pub max_source_bytes: usize,
+
+    /// Transformed images kept in memory
+    #[serde(default = "default_image_cache_entries")]
+    pub cache_entries: usize,
+}
+
+impl ImageConfig {
+    /// Check limits and that this build can transform images
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if !cfg!(feature = "images") {
+            anyhow::bail!("Image transformation requires building with the images feature");
+        }
+        if self.max_dimension == 0 {
+            anyhow::bail!("Image max_dimension must be greater than 0");
+        }
+        if let Some(width) = self
+            .widths
+            .iter()
+            .find(|w| **w == 0 || **w > self.max_dimension)
+        {
+            anyhow::bail!(
+                "Image width {} must be between 1 and max_dimension ({})",
+                width,
+                self.max_dimension
+            );
+        }
+        if !(1..=100).contains(&self.quality) {
+            anyhow::bail!("Image quality must be 1-100, got {}", self.quality);
+        }
+        Ok(())
+    }
+}
+
+/// Custom error page configuration
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ErrorPages {
+    /// Custom 404 Not Found page (relative to static root)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub not_found: Option<String>,
+
+    /// Custom 400 Bad Request page (relative to static root)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bad_request: Option<String>,
+
+    /// Template for each status code (relative to static root), used for
+    /// every error Sentinel generates itself. Templates may reference
+    /// `$status`, `$reason`, `$request_id`, and `$path`; entries here take
+    /// precedence over `not_found` and `bad_request`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub pages: BTreeMap<u16, String>,
+}
+
+impl ErrorPages {
+    /// Template path for every configured status code
+    pub fn templates(&self) -> BTreeMap<u16, String> {
+        let mut templates = BTreeMap::new();
+        if let Some(page) = &self.bad_request {
+            templates.insert(400, page.clone());
+        }
+        if let Some(page) = &self.not_found {
+            templates.insert(404, page.clone());
+        }
+        templates.extend(self.pages.clone());
+        templates
+    }
+
+    /// Check that every page is for an error status
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (code, page) in &self.pages {
+            if !(400..=599).contains(code) {
+                anyhow::bail!("Error page status must be 400-599, got {}", code);
+            }
+            if page.is_empty() {
+                anyhow::bail!("Error page for {} requires a path", code);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reverse proxy configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProxyConfig {
+    /// List of backend servers
+    #[serde(default)]
+    pub backends: Vec<BackendConfig>,
+
+    /// Named backend pools that routes send requests to
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub upstreams: BTreeMap<String, UpstreamConfig>,
+
+    /// Connection timeout for backend servers (in milliseconds)
+    #[serde(default = "default_connection_timeout")]
+    pub connection_timeout_ms: u64,
+
+    /// Overall time allowed for a backend exchange, from sending the request
+    /// to the last byte of the response (in milliseconds, 0 for no limit)
+    #[serde(default = "default_request_timeout")]
+    pub request_timeout_ms: u64,
+
+    /// Time to wait for the first byte of the response after sending the
+    /// request (in milliseconds, unlimited if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_byte_timeout_ms: Option<u64>,
+
+    /// Longest pause allowed between reads of the response once it has
+    /// started (in milliseconds, unlimited if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_timeout_ms: Option<u64>,
+
+    /// Timeout overrides for path prefixes
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub route_timeouts: Vec<RouteTimeouts>,
+
+    /// Rewrite backend URLs in `Location` headers to the client-facing origin
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub location_rewrites: Vec<LocationRewrite>,
+
+    /// Replace the body of backend error responses with Sentinel's error
+    /// pages
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub intercept_errors: Vec<InterceptErrors>,
+
+    /// Tell clients when to retry after a 503 because no backend could
+    /// take the request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<RetryAfterConfig>,
+
+    /// Which failed requests are retried on another backend
+    #[serde(default)]
+    pub retry: RetryPolicy,
+
+    /// Wait between attempts on successive backends (no wait if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_backoff: Option<RetryBackoffConfig>,
+
+    /// Name this proxy adds to `Via` headers; requests already carrying it
+    /// have looped and are refused with 508 (give chained instances
+    /// different names)
+    #[serde(default = "default_via")]
+    pub via: String,
+
+    /// Recurring windows during which backends are drained
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub maintenance: Vec<MaintenanceWindow>,
+
+    /// Copy requests to a shadow pool, optionally comparing its responses
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirror: Option<MirrorConfig>,
+
+    /// Sources that keep the backend list up to date at runtime
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub discovery: Option<DiscoveryConfig>,
+
+    /// Periodically probe backends so failed ones are detected and recovered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_check: Option<HealthCheckConfig>,
+
+    /// Consecutive failed requests or probes that mark a backend down
+    #[serde(default = "default_unhealthy_threshold")]
+    pub unhealthy_threshold: u32,
+
+    /// Consecutive successes that bring a down backend back
+    #[serde(default = "default_healthy_threshold")]
+    pub healthy_threshold: u32,
+
+    /// When backends taken down by failures are tried again
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerConfig,
+
+    /// Backends added while running get a share of requests that grows
+    /// linearly to their full weight over this many milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warmup_ms: Option<u64>,
+
+    /// Caching of backend hostname lookups
+    #[serde(default)]
+    pub dns: DnsConfig,
+
+    /// How a backend is picked for each request (`load_balancing` is
+    /// accepted as an older name)
+    #[serde(default, alias = "load_balancing")]
+    pub strategy: LoadBalancing,
+
+    /// Seed for the `random` strategy, making its picks reproducible
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub random_seed: Option<u64>,
+
+    /// What `consistent_hash` balancing hashes (the path by default)
+    #[serde(default, with = "serde_yaml::with::singleton_map")]
+    pub hash_key: HashKey,
+
+    /// Prefer backends in Sentinel's own zone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locality: Option<LocalityConfig>,
+
+    /// Scale backend weights by the load they report in responses
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub load_feedback: Option<LoadFeedbackConfig>,
+
+    /// Rules that send matching requests to backends with given labels
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub routing_rules: Vec<RoutingRule>,
+
+    /// Path prefixes served by a switchable pair of backend pools
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub blue_green: Vec<BlueGreenConfig>,
+
+    /// A/B experiments that split clients between variants by cookie
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub experiments: Vec<ExperimentConfig>,
+
+    /// Trust, resumption, and debugging settings for `https://` backends
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<UpstreamTlsConfig>,
+
+    /// Keep connections to backends open for later requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keepalive: Option<UpstreamKeepaliveConfig>,
+}
+
+/// Active health checking
+///
+/// Every backend is probed on an interval. A failed probe counts like a
+/// failed request (`unhealthy_threshold` in a row mark the backend down) and
+/// `healthy_threshold` successful probes bring a down backend back.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthCheckConfig {
+    /// Interval between probe rounds (in seconds)
+    #[serde(default = "default_health_interval")]
+    pub interval_secs: u64,
+
+    /// Interval between probe rounds in milliseconds, overriding
+    /// `interval_secs`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval_ms: Option<u64>,
+
+    /// Timeout for a single probe (in milliseconds)
+    #[serde(default = "default_health_timeout")]
+    pub timeout_ms: u64,
+
+    /// Path requested by HTTP probes; any 2xx or 3xx status is healthy
+    #[serde(default = "default_health_path")]
+    pub path: String,
+}
+
+impl HealthCheckConfig {
+    /// Check the interval and path
+    pub fn validate(&self) -> anyhow::Result<()> {
+        match self.interval_ms {
+            Some(0) => anyhow::bail!("Health check interval_ms must be greater than 0"),
+            None if self.interval_secs == 0 => {
+                anyhow::bail!("Health check interval must be at least 1 second")
+            }
+            _ => {}
+        }
+        if !self.path.starts_with('/') {
+            anyhow::bail!("Health check path must start with '/': {}", self.path);
+        }
+        Ok(())
+    }
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: default_health_interval(),
+            interval_ms: None,
+            timeout_ms: default_health_timeout(),
+            path: default_health_path(),
+        }
+    }
+}
+
+/// A named backend pool under `proxy.upstreams`
+///
+/// The pool balances with its own `strategy` and `health_check` where set,
+/// and otherwise with the proxy section's; all other settings (timeouts,
+/// retries, circuit breaking) are shared. A plain list of backends is short
+/// for a pool with only `backends`.
+///
+/// # Example
+///
+/// ```yaml
+/// proxy:
+///   upstreams:
+///     api:
+///       - url: "http://127.0.0.1:3000"
+///       - url: "http://127.0.0.1:3001"
+///     auth:
+///       strategy: least_conn
+///       health_check: { path: /healthz, interval_secs: 5 }
+///       backends:
+///         - url: "http://127.0.0.1:4000"
+/// routes:
+///   - prefix: /api/
+///     pool: api
+///   - prefix: /auth/
+///     pool: auth
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(from = "UpstreamDefinition")]
+pub struct UpstreamConfig {
+    /// Backends of the pool
+    pub backends: Vec<BackendConfig>,
+
+    /// Load balancing strategy, instead of the proxy section's
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<LoadBalancing>,
+
+    /// Active health checking, instead of the proxy section's
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_check: Option<HealthCheckConfig>,
+}
+
+/// The forms an upstream may be written in
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum UpstreamDefinition {
+    Backends(Vec<BackendConfig>),
+    Pool {
+        backends: Vec<BackendConfig>,
+        #[serde(default, alias = "load_balancing")]
+        strategy: Option<LoadBalancing>,
+        #[serde(default)]
+        health_check: Option<HealthCheckConfig>,
+    },
+}
+
+impl From<UpstreamDefinition> for UpstreamConfig {
+    fn from(definition: UpstreamDefinition) -> Self {
+        match definition {
+            UpstreamDefinition::Backends(backends) => Self {
+                backends,
+                ..Default::default()
+            },
+            UpstreamDefinition::Pool {
+                backends,
+                strategy,
+                health_check,
+            } => Self {
+                backends,
+                strategy,
+                health_check,
+            },
+        }
+    }
+}
+
+/// Circuit breaking for failing backends
+///
+/// A backend that fails `unhealthy_threshold` requests in a row is taken
+/// out of rotation: its circuit opens. After `cooldown_ms` the circuit is
+/// half-open and up to `half_open_requests` requests at a time are sent to
+/// the backend as probes. `healthy_threshold` successes close the circuit;
+/// a failure opens it for another cooldown.
+///
+/// # Example
+///
+/// ```yaml
+/// proxy:
+///   circuit_breaker:
+///     cooldown_ms: 5000
+///     half_open_requests: 2
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CircuitBreakerConfig {
+    /// Time an open circuit waits before probing (in milliseconds)
+    #[serde(default = "default_circuit_cooldown")]
+    pub cooldown_ms: u64,
+
+    /// Probe requests allowed at once while half-open; 0 leaves recovery to
+    /// active health checks
+    #[serde(default = "default_half_open_requests")]
+    pub half_open_requests: u32,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            cooldown_ms: default_circuit_cooldown(),
+            half_open_requests: default_half_open_requests(),
+        }
+    }
+}
+
+/// Resolution of backend hostnames and connecting to their addresses
+///
+/// Addresses are reused for `ttl_ms`, then re-resolved in the background
+/// while the cached ones stay in use. Hosts with several addresses are
+/// connected to with Happy Eyeballs (RFC 8305), starting another attempt
+/// every `happy_eyeballs_delay_ms` until one succeeds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsConfig {
+    /// How long resolved addresses are reused (in milliseconds); 0 resolves
+    /// on every connect
+    #[serde(default = "default_dns_ttl")]
+    pub ttl_ms: u64,
+
+    /// Delay before also trying a host's next address (in milliseconds)
+    #[serde(default = "default_happy_eyeballs_delay")]
+    pub happy_eyeballs_delay_ms: u64,
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        Self {
+            ttl_ms: default_dns_ttl(),
+            happy_eyeballs_delay_ms: default_happy_eyeballs_delay(),
+        }
+    }
+}
+
+/// Protocol spoken by a backend's health probe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthCheckProtocol {
+    /// `GET <path>` over HTTP/1.1
+    #[default]
+    Http,
+    /// `grpc.health.v1.Health/Check` over cleartext HTTP/2
+    Grpc,
+    /// A TCP connect, for backends without a health endpoint
+    Tcp,
+}
+
+/// Per-backend health probe settings
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BackendHealthCheck {
+    /// Probe protocol
+    #[serde(default)]
+    pub protocol: HealthCheckProtocol,
+
+    /// HTTP probe path, overriding `health_check.path`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+
+    /// gRPC service name to check; empty checks the server as a whole
+    #[serde(default)]
+    pub service: String,
+
+    /// Probe timeout (in milliseconds), overriding `health_check.timeout_ms`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+
+    /// Probe interval (in milliseconds), overriding the `health_check`
+    /// interval
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval_ms: Option<u64>,
+}
+
+/// Backend selection strategy
+///
+/// # Example
+///
+/// ```yaml
+/// proxy:
+///   strategy: least_conn
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadBalancing {
+    /// Smooth weighted round-robin
+    #[default]
+    RoundRobin,
+    /// Pick a backend at random in proportion to its weight
+    Random,
+    /// Pick the backend with the fewest requests in flight relative to its
+    /// weight
+    LeastConn,
+    /// Hash the client's IP address so each client keeps reaching the same
+    /// backend while it stays available
+    IpHash,
+    /// Place backends on a hash ring and send each request to the backend
+    /// owning its `hash_key`, so the same key keeps reaching the same backend
+    ConsistentHash,
+    /// Compare two random backends and pick the one with the lower moving
+    /// average response time (relative to its weight), occasionally trying
+    /// any backend so slow ones are measured again
+    LeastLatency,
+}
+
+/// Request attribute hashed by [`LoadBalancing::ConsistentHash`]
+///
+/// Requests without the header or cookie are balanced round-robin.
+///
+/// # Example
+///
+/// ```yaml
+/// proxy:
+///   strategy: consistent_hash
+///   hash_key:
+///     header: X-Tenant-Id
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum HashKey {
+    /// Request path, without the query string
+    #[default]
+    Path,
+    /// Value of the named request header
+    Header(String),
+    /// Value of the named cookie
+    Cookie(String),
+}
+
+/// Zone-aware backend selection
+///
+/// Requests go to backends whose `zone` matches this instance's zone. Other
+/// zones are used only while the local zone has no healthy backend, or
+/// fewer than `min_healthy_percent` of its backends are healthy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocalityConfig {
+    /// Zone this Sentinel instance runs in
+    pub zone: String,
+
+    /// Spill to other zones below this share of healthy local backends (0-100)
+    #[serde(default)]
+    pub min_healthy_percent: u8,
+}
+
+/// Backend-reported load feedback
+///
+/// Backends report their utilization in a response header, either as a bare
+/// number between 0 and 1 or in ORCA text format (`TEXT cpu_utilization=0.3,
+/// mem_utilization=0.5`). A backend's effective weight is its configured
+/// weight scaled by `1 - load`, never below `min_weight_percent`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoadFeedbackConfig {
+    /// Response header carrying the load report
+    #[serde(default = "default_load_header")]
+    pub header: String,
+
+    /// ORCA metric to use when the header is in ORCA format
+    #[serde(default = "default_load_metric")]
+    pub metric: String,
+
+    /// Weight of each new report in the moving average (0-1; 1 disables smoothing)
+    #[serde(default = "default_load_smoothing")]
+    pub smoothing: f64,
+
+    /// Lowest effective weight, as a percentage of the configured weight
+    #[serde(default = "default_min_weight_percent")]
+    pub min_weight_percent: u8,
+
+    /// Remove the header before the response reaches the client
+    #[serde(default = "default_true")]
+    pub strip_header: bool,
+}
+
+impl Default for LoadFeedbackConfig {
+    fn default() -> Self {
+        Self {
+            header: default_load_header(),
+            metric: default_load_metric(),
+            smoothing: default_load_smoothing(),
+            min_weight_percent: default_min_weight_percent(),
+            strip_header: true,
+        }
+    }
+}
+
+/// Send requests matching a path prefix and/or headers to labelled backends
+///
+/// Rules are checked in order and the first match applies. A rule with no
+/// conditions matches every request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoutingRule {
+    /// Path prefix the request must start with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_prefix: Option<String>,
+
+    /// Headers the request must carry with exactly these values
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+
+    /// Labels a backend must have (all of them) to receive the request
+    pub backend_labels: BTreeMap<String, String>,
+
+    /// Share of matching requests sent to the labelled backends, the rest
+    /// going to backends without those labels (default: all of them)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub percent: Option<u8>,
+
+    /// Use any backend when no labelled backend is available
+    #[serde(default = "default_true")]
+    pub fallback: bool,
+}
+
+/// Rewriting of `Location` and `Content-Location` in backend responses,
+/// like nginx's `proxy_redirect`
+///
+/// The rule with the longest prefix matching the request path applies;
+/// requests no rule matches are left alone. By default, URLs pointing at the
+/// backend that answered are moved to the scheme (`X-Forwarded-Proto`, else
+/// `http`) and `Host` the client used.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocationRewrite {
+    /// Path prefix of the requests this rule applies to
+    #[serde(default = "default_path_prefix")]
+    pub path_prefix: String,
+
+    /// Replace header values starting with this instead of the backend URL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+
+    /// Replacement for the matched part (default: the client-facing origin;
+    /// may be empty to make the URL relative)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+}
+
+/// Backend errors to intercept for a path prefix
+///
+/// Responses from a backend with a status of at least `min_status` keep
+/// their status, but their body is replaced with the configured error page
+/// for it (see [`ErrorPages`]), hiding backend stack traces and default
+/// server pages from clients.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InterceptErrors {
+    /// Path prefix of the requests this rule applies to
+    #[serde(default = "default_path_prefix")]
+    pub path_prefix: String,
+
+    /// Lowest backend status to intercept
+    #[serde(default = "default_intercept_min_status")]
+    pub min_status: u16,
+}
+
+/// `Retry-After` hint sent with 503 responses
+///
+/// Each response gets `secs` plus a random extra of up to `jitter_secs`, so
+/// clients turned away together do not all come back at the same moment.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetryAfterConfig {
+    /// Base delay (in seconds)
+    #[serde(default = "default_retry_after_secs")]
+    pub secs: u64,
+
+    /// Largest random delay added to `secs` (in seconds)
+    #[serde(default)]
+    pub jitter_secs: u64,
+}
+
+/// When a failed request is tried again on another backend
+///
+/// Requests using one of `methods` are retried after a connection error or
+/// a response with one of `statuses`, up to `max_attempts` in all. Retries
+/// are also limited to `budget_percent` of requests overall, with
+/// `budget_burst` retries available at once, so an outage does not turn
+/// into a retry storm.
+///
+/// # Example
+///
+/// ```yaml
+/// proxy:
+///   retry:
+///     methods: [GET, HEAD]
+///     statuses: [502, 503]
+///     max_attempts: 3
+///     budget_percent: 20
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// Methods that may be retried (the idempotent ones by default)
+    #[serde(default = "default_retry_methods")]
+    pub methods: Vec<String>,
+
+    /// Backend response statuses that are retried like connection errors
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub statuses: Vec<u16>,
+
+    /// Most attempts per request, the first included (default: one per
+    /// available backend)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<u32>,
+
+    /// Retries allowed as a percentage of requests
+    #[serde(default = "default_retry_budget_percent")]
+    pub budget_percent: u32,
+
+    /// Retries allowed at once, before the percentage applies
+    #[serde(default = "default_retry_budget_burst")]
+    pub budget_burst: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            methods: default_retry_methods(),
+            statuses: Vec::new(),
+            max_attempts: None,
+            budget_percent: default_retry_budget_percent(),
+            budget_burst: default_retry_budget_burst(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether requests with `method` may be retried
+    pub fn allows(&self, method: &Method) -> bool {
+        self.methods
+            .iter()
+            .any(|m| Method::from_str(m).as_ref() == Some(method))
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if let Some(method) = self.methods.iter().find(|m| Method::from_str(m).is_none()) {
+            anyhow::bail!("Unknown method in retry policy: {}", method);
+        }
+        if let Some(status) = self.statuses.iter().find(|s| !(100..600).contains(*s)) {
+            anyhow::bail!("Invalid status in retry policy: {}", status);
+        }
+        if self.max_attempts == Some(0) {
+            anyhow::bail!("Retry policy max_attempts must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+/// Exponential backoff between attempts on successive backends
+///
+/// The n-th retry waits `base_ms * multiplier^(n-1)`, capped at `max_ms`,
+/// less a random share of up to `jitter` of that delay so retries from
+/// many requests spread out. No further attempt is made once retrying
+/// would take longer than `max_total_ms`.
+///
+/// # Example
+///
+/// ```yaml
+/// proxy:
+///   retry_backoff:
+///     base_ms: 50
+///     multiplier: 2.0
+///     jitter: 0.5
+///     max_ms: 1000
+///     max_total_ms: 3000
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetryBackoffConfig {
+    /// Wait before the first retry (in milliseconds)
+    #[serde(default = "default_backoff_base")]
+    pub base_ms: u64,
+
+    /// Growth of the wait with each further retry
+    #[serde(default = "default_backoff_multiplier")]
+    pub multiplier: f64,
+
+    /// Largest share (0-1) of each wait removed at random
+    #[serde(default = "default_backoff_jitter")]
+    pub jitter: f64,
+
+    /// Longest single wait (in milliseconds)
+    #[serde(default = "default_backoff_max")]
+    pub max_ms: u64,
+
+    /// Longest time spent retrying one request (in milliseconds)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_total_ms: Option<u64>,
+}
+
+impl Default for RetryBackoffConfig {
+    fn default() -> Self {
+        Self {
+            base_ms: default_backoff_base(),
+            multiplier: default_backoff_multiplier(),
+            jitter: default_backoff_jitter(),
+            max_ms: default_backoff_max(),
+            max_total_ms: None,
+        }
+    }
+}
+
+impl RetryBackoffConfig {
+    /// Wait before retry number `retry` (1 for the first), jitter applied
+    pub fn delay(&self, retry: u32) -> Duration {
+        let exponent = retry.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let delay = (self.base_ms as f64 * self.multiplier.powi(exponent)).min(self.max_ms as f64);
+        let jittered = delay * (1.0 - self.jitter * rand::random::<f64>());
+        Duration::from_secs_f64(jittered.max(0.0) / 1000.0)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if !(self.multiplier >= 1.0 && self.multiplier.is_finite()) {
+            anyhow::bail!(
+                "Retry backoff multiplier must be at least 1, got {}",
+                self.multiplier
+            );
+        }
+        if !(0.0..=1.0).contains(&self.jitter) {
+            anyhow::bail!(
+                "Retry backoff jitter must be between 0 and 1, got {}",
+                self.jitter
+            );
+        }
+        if self.max_ms < self.base_ms {
+            anyhow::bail!("Retry backoff max_ms must be at least base_ms");
+        }
+        Ok(())
+    }
+}
+
+/// Traffic mirroring (dark launch)
+///
+/// A share of requests is also sent to the shadow backends. Shadow
+/// responses never reach the client; with `compare` set they are checked
+/// against the primary response and mismatches are logged and counted.
+/// Mirrored requests are sent as-is, so shadows must tolerate replayed
+/// writes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MirrorConfig {
+    /// Backends receiving the copies
+    pub backends: Vec<BackendConfig>,
+
+    /// Percentage of requests to mirror (default: 100)
+    #[serde(default = "default_mirror_percent")]
+    pub percent: u8,
+
+    /// Compare shadow responses against primary ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compare: Option<MirrorCompareConfig>,
+}
+
+/// How mirrored responses are compared
+///
+/// Status codes are always compared.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MirrorCompareConfig {
+    /// Also compare bodies. JSON bodies are compared structurally, others
+    /// byte for byte after trimming surrounding whitespace.
+    #[serde(default = "default_false")]
+    pub bodies: bool,
+
+    /// JSON fields dropped at any depth before comparing (e.g. timestamps)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignore_fields: Vec<String>,
+}
+
+/// A recurring maintenance window for some backends
+///
+/// While the window is open the backends get no new requests; their weight
+/// is restored when it closes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaintenanceWindow {
+    /// Backends to drain, by name or URL
+    pub backends: Vec<String>,
+
+    /// When the window opens, as a five-field cron expression
+    /// (`minute hour day-of-month month day-of-week`) evaluated in UTC
+    pub schedule: String,
+
+    /// How long the window stays open (in minutes)
+    pub duration_mins: u64,
+}
+
+/// Backend timeouts for requests under a path prefix
+///
+/// The longest matching prefix applies. Fields left unset keep the
+/// proxy-wide value; 0 removes the limit (except for connecting).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteTimeouts {
+    /// Path prefix the request must start with
+    pub path_prefix: String,
+
+    /// Connection timeout (in milliseconds)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connection_timeout_ms: Option<u64>,
+
+    /// Overall exchange timeout (in milliseconds)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_timeout_ms: Option<u64>,
+
+    /// Time to first response byte (in milliseconds)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_byte_timeout_ms: Option<u64>,
+
+    /// Longest pause between response reads (in milliseconds)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_timeout_ms: Option<u64>,
+
+    /// Pass responses through as they arrive (long polling, streaming
+    /// APIs); the overall and idle limits are lifted unless set here
+    #[serde(default)]
+    pub stream: bool,
+}
+
+/// Blue-green deployment: two backend pools for a path prefix, one of which
+/// receives traffic
+///
+/// The active pool is switched at runtime through the admin API. Requests
+/// carrying `preview_header` go to the inactive pool instead, so a release
+/// can be checked before the flip.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlueGreenConfig {
+    /// Name used by the admin API and in metrics
+    pub name: String,
+
+    /// Path prefix served by this deployment
+    #[serde(default = "default_path_prefix")]
+    pub path_prefix: String,
+
+    /// Backends of the blue pool
+    pub blue: Vec<BackendConfig>,
+
+    /// Backends of the green pool
+    pub green: Vec<BackendConfig>,
+
+    /// Pool receiving traffic at startup
+    #[serde(default)]
+    pub active: DeploymentColor,
+
+    /// Header that sends a request to the inactive pool
+    #[serde(default = "default_preview_header")]
+    pub preview_header: String,
+}
+
+/// One side of a blue-green deployment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeploymentColor {
+    #[default]
+    Blue,
+    Green,
+}
+
+impl DeploymentColor {
+    /// The other pool
+    pub fn other(self) -> Self {
+        match self {
+            DeploymentColor::Blue => DeploymentColor::Green,
+            DeploymentColor::Green => DeploymentColor::Blue,
+        }
+    }
+
+    /// Lowercase name, as used in configuration
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeploymentColor::Blue => "blue",
+            DeploymentColor::Green => "green",
+        }
+    }
+}
+
+/// A/B experiment on a path prefix
+///
+/// New clients are assigned a variant at random according to the variant
+/// percentages and keep it through a signed cookie. The variant name is
+/// passed upstream in `header`; variants with their own backends are served
+/// from them, the rest by the main backend pool.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExperimentConfig {
+    /// Name used in the cookie and in metrics
+    pub name: String,
+
+    /// Path prefix the experiment runs on
+    #[serde(default = "default_path_prefix")]
+    pub path_prefix: String,
+
+    /// Key used to sign assignment cookies
+    pub secret: String,
+
+    /// Cookie holding the assignment (default: `sentinel_exp_<name>`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cookie: Option<String>,
+
+    /// Request header naming the variant for the upstream
+    #[serde(default = "default_variant_header")]
+    pub header: String,
+
+    /// How long an assignment lasts, in seconds
+    #[serde(default = "default_experiment_max_age")]
+    pub max_age_secs: u64,
+
+    /// Variants; percentages must add up to 100
+    pub variants: Vec<VariantConfig>,
+}
+
+/// One arm of an experiment
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VariantConfig {
+    /// Variant name, passed upstream and used in metrics
+    pub name: String,
+
+    /// Share of new clients assigned to this variant
+    pub percent: u8,
+
+    /// Backends serving this variant (default: the main backend pool)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub backends: Vec<BackendConfig>,
+}
+
+impl ExperimentConfig {
+    /// Name of the assignment cookie
+    pub fn cookie_name(&self) -> String {
+        self.cookie
+            .clone()
+            .unwrap_or_else(|| format!("sentinel_exp_{}", self.name))
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if !is_token(&self.name) {
+            anyhow::bail!(
+                "Experiment name must be non-empty and use only letters, digits, '-' and '_': {:?}",
+                self.name
+            );
+        }
+        if !self.path_prefix.starts_with('/') {
+            anyhow::bail!("Experiment {} path_prefix must start with '/'", self.name);
+        }
+        if self.secret.is_empty() {
+            anyhow::bail!("Experiment {} requires a secret", self.name);
+        }
+        if !is_token(&self.cookie_name()) {
+            anyhow::bail!("Experiment {} has an invalid cookie name", self.name);
+        }
+        if self.header.is_empty() {
+            anyhow::bail!("Experiment {} requires a variant header", self.name);
+        }
+        if self.variants.is_empty() {
+            anyhow::bail!("Experiment {} has no variants", self.name);
+        }
+
+        let mut names = std::collections::HashSet::new();
+        for variant in &self.variants {
+            if !is_token(&variant.name) || !names.insert(variant.name.as_str()) {
+                anyhow::bail!(
+                    "Experiment {} variant names must be unique and use only letters, digits, '-' and '_': {:?}",
+                    self.name,
+                    variant.name
+                );
+            }
+            validate_backends(&variant.backends)?;
+        }
+
+        let total: u32 = self.variants.iter().map(|v| u32::from(v.percent)).sum();
+        if total != 100 {
+            anyhow::bail!(
+                "Experiment {} variant percentages must add up to 100, got {}",
+                self.name,
+                total
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether `s` is a non-empty run of letters, digits, `-` and `_`
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Dynamic backend discovery sources
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct DiscoveryConfig {
+    /// Populate the pool from a Consul service
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub consul: Option<ConsulConfig>,
+
+    /// Populate the pool (and per-prefix routes) from labelled Docker containers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub docker: Option<DockerConfig>,
+
+    /// Populate the pool from a JSON/YAML file that is re-read when it changes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<FileDiscoveryConfig>,
+}
+
+/// Consul service discovery settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConsulConfig {
+    /// Consul HTTP API address
+    #[serde(default = "default_consul_address")]
+    pub address: String,
+
+    /// Service name to watch
+    pub service: String,
+
+    /// Datacenter to query (defaults to the agent's own)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub datacenter: Option<String>,
+
+    /// Only use instances carrying all of these tags
+    #[serde(default)]
+    pub tags: Vec<String>,
+
+    /// Exclude instances whose checks are in `warning` (critical is always excluded)
+    #[serde(default = "default_true")]
+    pub only_passing: bool,
+
+    /// Blocking-query wait and retry interval (in seconds)
+    #[serde(default = "default_refresh_interval")]
+    pub refresh_interval_secs: u64,
+
+    /// ACL token sent as `X-Consul-Token`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+
+    /// Scheme used to build backend URLs
+    #[serde(default = "default_backend_scheme")]
+    pub scheme: String,
+}
+
+/// Docker container discovery settings
+///
+/// Containers labelled `<label_prefix>.enable=true` become backends. The
+/// port comes from `<label_prefix>.port` (or the only exposed TCP port), and
+/// containers with `<label_prefix>.route=/prefix` get their own route
+/// instead of joining the main pool.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DockerConfig {
+    /// Docker API endpoint: `unix:///path/to/docker.sock` or `http://host:port`
+    #[serde(default = "default_docker_endpoint")]
+    pub endpoint: String,
+
+    /// Network whose container IP is used (defaults to the first one with an IP)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network: Option<String>,
+
+    /// Label namespace, e.g. `sentinel` for `sentinel.enable`
+    #[serde(default = "default_label_prefix")]
+    pub label_prefix: String,
+
+    /// Interval between container list refreshes (in seconds)
+    #[serde(default = "default_docker_refresh_interval")]
+    pub refresh_interval_secs: u64,
+
+    /// Scheme used to build backend URLs
+    #[serde(default = "default_backend_scheme")]
+    pub scheme: String,
+}
+
+/// File-based backend list settings
+///
+/// The file holds either a list of backends or a mapping with a `backends`
+/// key, in the same shape as `proxy.backends`. Files ending in `.json` are
+/// parsed as JSON, anything else as YAML.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileDiscoveryConfig {
+    /// Path to the backend list
+    pub path: PathBuf,
+
+    /// Interval between checks for changes (in seconds)
+    #[serde(default = "default_file_refresh_interval")]
+    pub refresh_interval_secs: u64,
+}
+
+/// Configuration for a backend server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackendConfig {
+    /// Backend URL (e.g., "http://localhost:3000")
+    pub url: String,
+
+    /// Optional backend name for logging
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    /// Relative share of requests; 0 stops new requests (drain)
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+
+    /// Metadata such as version, zone, or tier (e.g., `canary: "true"`)
+    ///
+    /// Used by routing rules, added to backend metrics, and logged with
+    /// forwarded requests.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+
+    /// Zone or locality the backend runs in (e.g., "us-east-1a")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+
+    /// How active health checks probe this backend (defaults to HTTP GET)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_check: Option<BackendHealthCheck>,
+
+    /// Consecutive failures that mark this backend down, overriding the
+    /// proxy's `unhealthy_threshold`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unhealthy_threshold: Option<u32>,
+
+    /// Consecutive successes that bring this backend back, overriding the
+    /// proxy's `healthy_threshold`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub healthy_threshold: Option<u32>,
+
+    /// Most requests forwarded to this backend at once; further requests go
+    /// to other backends, or get a 503 if every backend is full
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_connections: Option<u32>,
+
+    /// Send no new requests to this backend, letting in-flight ones finish
+    #[serde(default)]
+    pub drain: bool,
+
+    /// Speak cleartext HTTP/2 to this `http://` backend, multiplexing
+    /// requests over one shared connection
+    #[serde(default)]
+    pub h2c: bool,
+}
+
+impl Default for BackendConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            name: None,
+            weight: default_weight(),
+            labels: BTreeMap::new(),
+            zone: None,
+            health_check: None,
+            unhealthy_threshold: None,
+            healthy_threshold: None,
+            max_connections: None,
+            drain: false,
+            h2c: false,
+        }
+    }
+}
+
+/// Fault injection configuration
+///
+/// Each request is matched against `rules` in order; the first rule whose
+/// `path_prefix` matches decides which faults may be injected.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ChaosConfig {
+    /// Master switch, so the section can stay in the file while disabled
+    #[serde(default = "default_false")]
+    pub enabled: bool,
+
+    /// Per-route fault rules
+    #[serde(default)]
+    pub rules: Vec<ChaosRule>,
+}
+
+/// Faults injected for requests under a path prefix
+///
+/// Probabilities are in the range `0.0..=1.0`. Faults are rolled
+/// independently: latency first, then abort, error, and truncation.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ChaosRule {
+    /// Path prefix this rule applies to (e.g., "/api")
+    pub path_prefix: String,
+
+    /// Added delay before the request is handled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency: Option<LatencyFault>,
+
+    /// Replace the response with an error status
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorFault>,
+
+    /// Probability of closing the connection without responding
+    #[serde(default)]
+    pub abort_probability: f64,
+
+    /// Probability of sending only part of the body, then closing
+    #[serde(default)]
+    pub truncate_probability: f64,
+}
+
+/// Latency fault settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LatencyFault {
+    /// Probability of delaying a request
+    pub probability: f64,
+
+    /// Minimum delay in milliseconds
+    pub min_ms: u64,
+
+    /// Maximum delay in milliseconds (defaults to `min_ms`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_ms: Option<u64>,
+}
+
+/// Error response fault settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorFault {
+    /// Probability of replacing the response
+    pub probability: f64,
+
+    /// Status code to return (default: 503)
+    #[serde(default = "default_chaos_status")]
+    pub status: u16,
+}
+
+impl ChaosConfig {
+    /// Validate probabilities, latency ranges, and status codes
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (idx, rule) in self.rules.iter().enumerate() {
+            let mut probabilities = vec![
+                ("abort_probability", rule.abort_probability),
+                ("truncate_probability", rule.truncate_probability),
+            ];
+            if let Some(latency) = &rule.latency {
+                probabilities.push(("latency.probability", latency.probability));
+                if latency.max_ms.is_some_and(|max| max < latency.min_ms) {
+                    anyhow::bail!("Chaos rule {} has latency.max_ms below min_ms", idx);
+                }
+            }
+            if let Some(error) = &rule.error {
+                probabilities.push(("error.probability", error.probability));
+                if StatusCode::from_u16(error.status).is_none() {
+                    anyhow::bail!(
+                        "Chaos rule {} has unsupported error status {}",
+                        idx,
+                        error.status
+                    );
+                }
+            }
+
+            for (name, p) in probabilities {
+                if !(0.0..=1.0).contains(&p) {
+                    anyhow::bail!("Chaos rule {} {} must be between 0 and 1: {}", idx, name, p);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Forward proxy configuration
+///
+/// Clients send `CONNECT host:port` and, if the destination matches an
+/// entry in `allow` (and the credentials match one of `users`, when any are
+/// configured), get a raw TCP tunnel to it.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ForwardProxyConfig {
+    /// Master switch, so the section can stay in the file while disabled
+    #[serde(default = "default_false")]
+    pub enabled: bool,
+
+    /// Allowed destinations as `host:port` patterns. The host may be `*` or
+    /// start with `*.` to match subdomains; the port may be `*`. Nothing is
+    /// allowed when empty.
+    #[serde(default)]
+    pub allow: Vec<String>,
+
+    /// Accepted `Proxy-Authorization: Basic` credentials (no auth if empty)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub users: Vec<ProxyUser>,
+
+    /// Timeout for connecting to the destination in milliseconds
+    #[serde(default = "default_connection_timeout")]
+    pub connect_timeout_ms: u64,
+}
+
+/// Security headers added to responses
+///
+/// An empty section sends the preset: HSTS for a year including
+/// subdomains (on HTTPS requests only), `X-Content-Type-Options: nosniff`,
+/// `X-Frame-Options: DENY`, and `Referrer-Policy:
+/// strict-origin-when-cross-origin`. Set a header to `null` to leave it
+/// out. Headers the response already has are kept unless `replace` is set.
+///
+/// # Example
+///
+/// ```yaml
+/// security_headers:
+///   hsts: { max_age_secs: 63072000, preload: true }
+///   frame_options: sameorigin
+///   content_security_policy: "default-src 'self'"
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecurityHeadersConfig {
+    /// `Strict-Transport-Security`, sent on HTTPS requests
+    #[serde(default = "default_hsts")]
+    pub hsts: Option<HstsConfig>,
+
+    /// Send `X-Content-Type-Options: nosniff`
+    #[serde(default = "default_true")]
+    pub content_type_options: bool,
+
+    /// `X-Frame-Options`
+    #[serde(default = "default_frame_options")]
+    pub frame_options: Option<FrameOptions>,
+
+    /// `Referrer-Policy`
+    #[serde(default = "default_referrer_policy")]
+    pub referrer_policy: Option<String>,
+
+    /// `Content-Security-Policy`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_security_policy: Option<String>,
+
+    /// Overwrite the headers when the response already has them
+    #[serde(default)]
+    pub replace: bool,
+}
+
+impl Default for SecurityHeadersConfig {
+    fn default() -> Self {
+        Self {
+            hsts: default_hsts(),
+            content_type_options: true,
+            frame_options: default_frame_options(),
+            referrer_policy: default_referrer_policy(),
+            content_security_policy: None,
+            replace: false,
+        }
+    }
+}
+
+impl SecurityHeadersConfig {
+    /// Check the HSTS preload requirements, referrer policy, and CSP
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let Some(hsts) = &self.hsts
+            && hsts.preload
+            && (!hsts.include_subdomains || hsts.max_age_secs < 31_536_000)
+        {
+            anyhow::bail!("HSTS preload requires include_subdomains and a max_age_secs of a year");
+        }
+        if let Some(policy) = &self.referrer_policy {
+            const POLICIES: [&str; 8] = [
+                "no-referrer",
+                "no-referrer-when-downgrade",
+                "origin",
+                "origin-when-cross-origin",
+                "same-origin",
+                "strict-origin",
+                "strict-origin-when-cross-origin",
+                "unsafe-url",
+            ];
+            if let Some(unknown) = policy
+                .split(',')
+                .map(str::trim)
+                .find(|p| !POLICIES.contains(p))
+            {
+                anyhow::bail!("Unknown referrer policy: {:?}", unknown);
+            }
+        }
+        if let Some(csp) = &self.content_security_policy
+            && (csp.trim().is_empty() || csp.chars().any(|c| c.is_ascii_control()))
+        {
+            anyhow::bail!("Content security policy must be non-empty and on one line");
+        }
+        Ok(())
+    }
+}
+
+/// `Strict-Transport-Security` settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HstsConfig {
+    /// How long browsers remember to use HTTPS only, in seconds
+    #[serde(default = "default_hsts_max_age")]
+    pub max_age_secs: u64,
+
+    /// Apply to subdomains too
+    #[serde(default = "default_true")]
+    pub include_subdomains: bool,
+
+    /// Ask to be included in browsers' preload lists
+    #[serde(default)]
+    pub preload: bool,
+}
+
+impl HstsConfig {
+    /// The header value, e.g. `max-age=31536000; includeSubDomains`
+    pub fn header_value(&self) -> String {
+        let mut value = format!("max-age={}", self.max_age_secs);
+        if self.include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        if self.preload {
+            value.push_str("; preload");
+        }
+        value
+    }
+}
+
+/// `X-Frame-Options` value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FrameOptions {
+    /// Never render the page in a frame
+    Deny,
+    /// Only in frames of the same origin
+    SameOrigin,
+}
+
+impl FrameOptions {
+    /// The header value
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FrameOptions::Deny => "DENY",
+            FrameOptions::SameOrigin => "SAMEORIGIN",
+        }
+    }
+}
+
+/// Redirects that normalize request URLs
+///
+/// `rules` are checked first, in order, and the first matching rule
+/// answers with its own status and `Location`. Otherwise the canonical host
+/// and trailing-slash policies apply: GET and HEAD requests get a 301;
+/// other methods get a 308 so the method and body survive the redirect.
+///
+/// # Example
+///
+/// ```yaml
+/// redirects:
+///   canonical_host: apex
+///   rules:
+///     - scheme: http
+///       location: "https://$host$path$query"
+///       status: 308
+///     - host: "*.old-brand.com"
+///       path_regex: "^/blog/(.*)"
+///       location: "https://example.com/articles/$1"
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedirectConfig {
+    /// Redirect rules matched on host, scheme, and path
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<RedirectRule>,
+
+    /// Redirect to the `www.` or the bare (apex) form of the requested host
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canonical_host: Option<CanonicalHost>,
+
+    /// Add or remove the trailing slash on paths. Paths whose last segment
+    /// has an extension (`/app.js`) are left alone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trailing_slash: Option<TrailingSlash>,
+
+    /// Path prefixes the trailing-slash policy does not apply to
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trailing_slash_exclude: Vec<String>,
+
+    /// Scheme for host redirects (e.g. `https` behind a TLS terminator)
+    #[serde(default = "default_redirect_scheme")]
+    pub scheme: String,
+}
+
+impl Default for RedirectConfig {
+    fn default() -> Self {
+        Self {
+            rules: Vec::new(),
+            canonical_host: None,
+            trailing_slash: None,
+            trailing_slash_exclude: Vec::new(),
+            scheme: default_redirect_scheme(),
+        }
+    }
+}
+
+impl RedirectConfig {
+    /// Validate the scheme, exclusions, and rules
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.scheme != "http" && self.scheme != "https" {
+            anyhow::bail!("Redirect scheme must be http or https: {}", self.scheme);
+        }
+        for (idx, rule) in self.rules.iter().enumerate() {
+            rule.validate()
+                .with_context(|| format!("Invalid redirect rule {}", idx))?;
+        }
+        if let Some(prefix) = self
+            .trailing_slash_exclude
+            .iter()
+            .find(|p| !p.starts_with('/'))
+        {
+            anyhow::bail!("Trailing-slash exclusion must start with '/': {}", prefix);
+        }
+        Ok(())
+    }
+}
+
+/// A redirect for requests matching a host, scheme, and path
+///
+/// Every condition that is set must match. `location` may use `$scheme`,
+/// `$host` (with any port), `$path`, `$query` (including the `?`, or empty),
+/// and `$1` to `$9` for capture groups of `path_regex`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedirectRule {
+    /// Host the request addresses (port ignored, case-insensitive);
+    /// `*.example.com` matches any subdomain
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+
+    /// Scheme the request was made with (`http` or `https`), from
+    /// `X-Forwarded-Proto` from a trusted proxy or else the listener
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scheme: Option<String>,
+
+    /// Regular expression the path (without query) must match
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_regex: Option<String>,
+
+    /// Template for the `Location` header
+    pub location: String,
+
+    /// Redirect status: 301, 302, 303, 307, or 308
+    #[serde(default = "default_redirect_status")]
+    pub status: u16,
+}
+
+impl RedirectRule {
+    /// The compiled `path_regex`, if the rule has one
+    pub fn regex(&self) -> anyhow::Result<Option<regex::Regex>> {
+        self.path_regex
+            .as_deref()
+            .map(|pattern| {
+                regex::Regex::new(pattern)
+                    .with_context(|| format!("Invalid redirect path_regex: {}", pattern))
+            })
+            .transpose()
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if !matches!(self.status, 301 | 302 | 303 | 307 | 308) {
+            anyhow::bail!("Redirect status must be 301, 302, 303, 307, or 308");
+        }
+        if self.location.is_empty() {
+            anyhow::bail!("Redirect location must not be empty");
+        }
+        if let Some(scheme) = self
+            .scheme
+            .as_deref()
+            .filter(|s| *s != "http" && *s != "https")
+        {
+            anyhow::bail!("Redirect rule scheme must be http or https: {}", scheme);
+        }
+        if self.host.as_deref().is_some_and(str::is_empty) {
+            anyhow::bail!("Redirect rule host must not be empty");
+        }
+        self.regex()?;
+        Ok(())
+    }
+}
+
+/// Preferred form of the host name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CanonicalHost {
+    /// `example.com` redirects to `www.example.com`
+    Www,
+    /// `www.example.com` redirects to `example.com`
+    Apex,
+}
+
+/// Trailing-slash policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrailingSlash {
+    /// `/docs` redirects to `/docs/`
+    Add,
+    /// `/docs/` redirects to `/docs`
+    Remove,
+}
+
+/// Service level objective for the requests under a path prefix
+///
+/// Responses with a 5xx status count against availability; responses slower
+/// than the latency threshold count against the latency objective. Success
+/// rates and error-budget burn rates are reported over each window.
+///
+/// # Example
+///
+/// ```yaml
+/// slos:
+///   - name: checkout
+///     path_prefix: /checkout
+///     availability: 99.9
+///     latency: { threshold_ms: 300, target: 99 }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SloConfig {
+    /// Name used as the `slo` metric label
+    pub name: String,
+
+    /// Path prefix of the requests this objective covers
+    #[serde(default = "default_path_prefix")]
+    pub path_prefix: String,
+
+    /// Percentage of requests that must not fail with a 5xx status
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub availability: Option<f64>,
+
+    /// Percentage of requests that must finish within a threshold
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency: Option<LatencyObjective>,
+
+    /// Rolling windows to report over (in seconds)
+    #[serde(default = "default_slo_windows")]
+    pub windows_secs: Vec<u64>,
+}
+
+/// Latency half of an [`SloConfig`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LatencyObjective {
+    /// Slowest acceptable response (in milliseconds)
+    pub threshold_ms: u64,
+
+    /// Percentage of requests that must be at least this fast
+    pub target: f64,
+}
+
+impl SloConfig {
+    /// Check the objectives and windows
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.name.is_empty() {
+            anyhow::bail!("SLO requires a name");
+        }
+        if !self.path_prefix.starts_with('/') {
+            anyhow::bail!(
+                "SLO {} path_prefix must start with '/': {}",
+                self.name,
+                self.path_prefix
+            );
+        }
+        if self.availability.is_none() && self.latency.is_none() {
+            anyhow::bail!(
+                "SLO {} needs an availability or latency objective",
+                self.name
+            );
+        }
+        let targets = self
+            .availability
+            .iter()
+            .chain(self.latency.as_ref().map(|l| &l.target));
+        for target in targets {
+            if !(*target > 0.0 && *target < 100.0) {
+                anyhow::bail!(
+                    "SLO {} target must be between 0 and 100 (exclusive), got {}",
+                    self.name,
+                    target
+                );
+            }
+        }
+        if self.latency.as_ref().is_some_and(|l| l.threshold_ms == 0) {
+            anyhow::bail!(
+                "SLO {} latency threshold_ms must be greater than 0",
+                self.name
+            );
+        }
+        if self.windows_secs.is_empty() {
+            anyhow::bail!("SLO {} requires at least one window", self.name);
+        }
+        if let Some(window) = self.windows_secs.iter().find(|w| **w < SLO_BUCKET_SECS) {
+            anyhow::bail!(
+                "SLO {} window must be at least {} seconds, got {}",
+                self.name,
+                SLO_BUCKET_SECS,
+                window
+            );
+        }
+        if let Some(window) = self.windows_secs.iter().find(|w| **w > MAX_SLO_WINDOW_SECS) {
+            anyhow::bail!(
+                "SLO {} window must be at most {} seconds, got {}",
+                self.name,
+                MAX_SLO_WINDOW_SECS,
+                window
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Idempotency-Key request deduplication
+///
+/// On opted-in routes the first request carrying a key is forwarded and its
+/// response kept for `ttl_secs`. Later requests with the same key, including
+/// ones that arrive while the first is still in flight, get that response
+/// instead of reaching the backend. 5xx responses are not kept, so a failed
+/// attempt can be retried.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct IdempotencyConfig {
+    /// Master switch, so the section can stay in the file while disabled
+    #[serde(default = "default_false")]
+    pub enabled: bool,
+
+    /// Path prefixes that opt in to deduplication
+    #[serde(default)]
+    pub routes: Vec<String>,
+
+    /// Request header carrying the key
+    #[serde(default = "default_idempotency_header")]
+    pub header: String,
+
+    /// How long a response is replayed for (in seconds)
+    #[serde(default = "default_idempotency_ttl")]
+    pub ttl_secs: u64,
+
+    /// Most keys remembered at once; requests with new keys are passed
+    /// through undeduplicated while the cache is full
+    #[serde(default = "default_idempotency_max_entries")]
+    pub max_entries: usize,
+}
+
+impl IdempotencyConfig {
+    /// Validate routes and limits
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let Some(route) = self.routes.iter().find(|r| !r.starts_with('/')) {
+            anyhow::bail!("Idempotency route must start with '/': {}", route);
+        }
+        if self.header.is_empty() {
+            anyhow::bail!("Idempotency requires a header name");
+        }
+        if self.ttl_secs == 0 || self.max_entries == 0 {
+            anyhow::bail!("Idempotency ttl_secs and max_entries must be greater than 0");
+        }
+        Ok(())
+    }
+}
+
+/// Rules on TLS client fingerprints, for turning away known bots
+///
+/// Entries are JA3 hashes or JA4 fingerprints. A client matching `deny` is
+/// refused; when `allow` is non-empty, so is any TLS client not in it.
+/// Plain HTTP requests carry no fingerprint and are never refused.
+///
+/// # Example
+///
+/// ```yaml
+/// fingerprints:
+///   deny:
+///     - e7d705a3286e19ea42f587b344ee6865
+///     - t13d1516h2_8daaf6152771_e5627efa2ab1
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct FingerprintRulesConfig {
+    /// Fingerprints allowed to connect (all when empty)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+
+    /// Fingerprints refused, even if also allowed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+}
+
+impl FingerprintRulesConfig {
+    /// Check every entry looks like a JA3 hash or JA4 fingerprint
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for fingerprint in self.allow.iter().chain(&self.deny) {
+            let ja3 = fingerprint.len() == 32 && fingerprint.chars().all(|c| c.is_ascii_hexdigit());
+            let ja4 = fingerprint.len() == 36
+                && fingerprint.as_bytes()[10] == b'_'
+                && fingerprint.as_bytes()[23] == b'_'
+                && fingerprint.is_ascii();
+            if !ja3 && !ja4 {
+                anyhow::bail!("Not a JA3 or JA4 fingerprint: {}", fingerprint);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Bot detection rules
+///
+/// Each request is matched against `rules` in order and the first match
+/// decides what happens to it; requests matching no rule pass through.
+///
+/// # Example
+///
+/// ```yaml
+/// bots:
+///   rules:
+///     - name: search-engines
+///       user_agents: ["Googlebot", "bingbot"]
+///       action: allow
+///     - name: scripts
+///       user_agents: ["python-requests", "curl/"]
+///       action: block
+///     - name: headless
+///       missing_headers: ["Accept-Language"]
+///       action: tarpit
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct BotConfig {
+    #[serde(default)]
+    pub rules: Vec<BotRule>,
+}
+
+/// Signals identifying a kind of bot, and what to do with it
+///
+/// Every signal that is set must match for the rule to apply.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BotRule {
+    /// Rule name, used in logs and metrics
+    pub name: String,
+
+    /// Matches if the User-Agent contains any of these (case-insensitive)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub user_agents: Vec<String>,
+
+    /// Matches if any of these headers is missing
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub missing_headers: Vec<String>,
+
+    /// Matches once a client IP exceeds this request rate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate: Option<BotRate>,
+
+    /// What to do with matching requests
+    pub action: BotAction,
+
+    /// Delay before a tarpitted request is handled, in milliseconds
+    #[serde(default = "default_tarpit_ms")]
+    pub tarpit_ms: u64,
+
+    /// Backends serving requests routed by this rule
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub backends: Vec<BackendConfig>,
+}
+
+/// Request rate threshold for one client IP
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct BotRate {
+    /// Requests allowed per window
+    pub requests: u32,
+    /// Window length in seconds
+    pub per_secs: u64,
+}
+
+/// Handling of requests matching a bot rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BotAction {
+    /// Serve normally, skipping later rules
+    Allow,
+    /// Refuse with 403
+    Block,
+    /// Serve after a delay, slowing the client down
+    Tarpit,
+    /// Serve from the rule's own backends
+    Route,
+}
+
+impl BotAction {
+    /// Lowercase name, as in configuration
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BotAction::Allow => "allow",
+            BotAction::Block => "block",
+            BotAction::Tarpit => "tarpit",
+            BotAction::Route => "route",
+        }
+    }
+}
+
+impl BotConfig {
+    /// Validate names, signals, and per-action settings
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut names = std::collections::HashSet::new();
+        for rule in &self.rules {
+            if !is_token(&rule.name) || !names.insert(rule.name.as_str()) {
                 anyhow::bail!(
-                    "Backend {} URL must start with http:// or https://: {}",
-                    idx,
-                    backend.url
+                    "Bot rule names must be unique and use only letters, digits, '-' and '_': {:?}",
+                    rule.name
                 );
             }
-
-            // Check URL can be parsed
-            if let Err(e) = url::Url::parse(&backend.url) {
-                anyhow::bail!("Backend {} has invalid URL '{}': {}", idx, backend.url, e);
+            if rule.user_agents.is_empty() && rule.missing_headers.is_empty() && rule.rate.is_none()
+            {
+                anyhow::bail!("Bot rule {} has no signals", rule.name);
+            }
+            if rule.user_agents.iter().any(|ua| ua.is_empty()) {
+                anyhow::bail!("Bot rule {} has an empty user_agents pattern", rule.name);
+            }
+            if let Some(rate) = rule.rate
+                && (rate.requests == 0 || rate.per_secs == 0)
+            {
+                anyhow::bail!(
+                    "Bot rule {} rate requests and per_secs must be greater than 0",
+                    rule.name
+                );
+            }
+            match rule.action {
+                BotAction::Route if rule.backends.is_empty() => {
+                    anyhow::bail!(
+                        "Bot rule {} routes to a pool but has no backends",
+                        rule.name
+                    )
+                }
+                BotAction::Route => validate_backends(&rule.backends)?,
+                _ if !rule.backends.is_empty() => {
+                    anyhow::bail!(
+                        "Bot rule {} has backends but its action is {}",
+                        rule.name,
+                        rule.action.as_str()
+                    )
+                }
+                _ => {}
             }
         }
-
         Ok(())
     }
 }
 
-/// Main configuration for the Sentinel server
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Config {
-    /// Server listening configuration
-    pub server: ServerConfig,
+/// Authorization by an external policy engine
+///
+/// Exactly one of `opa` or `cedar` must be set. Every request is described
+/// to the engine (method, path, query, headers, client address and TLS
+/// fingerprint) and refused with 403 unless the policy allows it.
+///
+/// # Example
+///
+/// ```yaml
+/// policy:
+///   opa:
+///     url: "http://127.0.0.1:8181/v1/data/sentinel/authz"
+///   fail_open: false
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct PolicyConfig {
+    /// Open Policy Agent, queried over its data API
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub opa: Option<OpaConfig>,
 
-    /// Static file serving configuration
-    pub static_files: StaticFilesConfig,
+    /// Cedar policies evaluated in-process (requires the `cedar` feature)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cedar: Option<CedarConfig>,
 
-    /// Reverse proxy configuration
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub proxy: Option<ProxyConfig>,
+    /// Serve requests when the engine cannot be reached or fails, instead
+    /// of answering 503
+    #[serde(default)]
+    pub fail_open: bool,
 }
 
-/// Server listening settings
+/// Open Policy Agent settings
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ServerConfig {
-    /// Address to bind to (e.g., "127.0.0.1:8080")
-    pub listen_addr: String,
+pub struct OpaConfig {
+    /// Data API URL of the decision, e.g. `http://opa:8181/v1/data/http/authz`
+    ///
+    /// The document may be a boolean, or an object with `allow` and an
+    /// optional `reason`.
+    pub url: String,
+
+    /// Time allowed for one decision, in milliseconds
+    #[serde(default = "default_policy_timeout_ms")]
+    pub timeout_ms: u64,
 }
 
-/// Configuration for serving static files
+/// Cedar policy settings
+///
+/// Requests are evaluated with principal `Client::"<ip>"`, action
+/// `Action::"<METHOD>"` and resource `Path::"<path>"`; the remaining request
+/// attributes are in the context.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct StaticFilesConfig {
-    /// Root directory for static files
-    pub root: PathBuf,
-
-    /// Default file to serve for directory requests
-    pub index: String,
-
-    /// Custom error pages
-    #[serde(default)]
-    pub error_pages: ErrorPages,
+pub struct CedarConfig {
+    /// File of Cedar policies
+    pub policies_file: PathBuf,
 
-    /// Enable or disable directory listings (for future implementation)
-    #[serde(default = "default_false")]
-    pub directory_listing: bool,
+    /// Optional entities JSON file, for group membership and attributes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entities_file: Option<PathBuf>,
 }
 
-/// Custom error page configuration
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct ErrorPages {
-    /// Custom 404 Not Found page (relative to static root)
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub not_found: Option<String>,
-
-    /// Custom 400 Bad Request page (relative to static root)
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub bad_request: Option<String>,
+impl PolicyConfig {
+    /// Check exactly one engine is configured and usable
+    pub fn validate(&self) -> anyhow::Result<()> {
+        match (&self.opa, &self.cedar) {
+            (Some(opa), None) => {
+                let url = match url::Url::parse(&opa.url) {
+                    Ok(url) => url,
+                    Err(e) => anyhow::bail!("Invalid OPA URL '{}': {}", opa.url, e),
+                };
+                if url.scheme() != "http" {
+                    anyhow::bail!("OPA URL must use http: {}", opa.url);
+                }
+                if opa.timeout_ms == 0 {
+                    anyhow::bail!("OPA timeout_ms must be greater than 0");
+                }
+                Ok(())
+            }
+            (None, Some(_)) if !cfg!(feature = "cedar") => {
+                anyhow::bail!("Cedar policies require building with the cedar feature")
+            }
+            (None, Some(_)) => Ok(()),
+            _ => anyhow::bail!("Policy must configure exactly one of opa or cedar"),
+        }
+    }
 }
 
-/// Reverse proxy configuration
+/// Basic auth credentials (forward proxy and WebDAV users)
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ProxyConfig {
-    /// List of backend servers
-    pub backends: Vec<BackendConfig>,
+pub struct ProxyUser {
+    pub username: String,
+    pub password: String,
+}
 
-    /// Connection timeout for backend servers (in milliseconds)
-    #[serde(default = "default_connection_timeout")]
-    pub connection_timeout_ms: u64,
+impl ForwardProxyConfig {
+    /// Validate allow-list patterns and credentials
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for pattern in &self.allow {
+            let Some((host, port)) = pattern.rsplit_once(':') else {
+                anyhow::bail!("Forward proxy allow entry must be host:port: {}", pattern);
+            };
+            let name = host.strip_prefix("*.").unwrap_or(host);
+            if host != "*" && (name.is_empty() || name.contains('*')) {
+                anyhow::bail!("Forward proxy allow entry has an invalid host: {}", pattern);
+            }
+            if port != "*" && port.parse::<u16>().is_err() {
+                anyhow::bail!("Forward proxy allow entry has an invalid port: {}", pattern);
+            }
+        }
 
-    /// Request timeout for backend servers (in milliseconds)
-    #[serde(default = "default_request_timeout")]
-    pub request_timeout_ms: u64,
-}
+        for user in &self.users {
+            if user.username.is_empty() || user.username.contains(':') {
+                anyhow::bail!(
+                    "Forward proxy username must be non-empty and not contain ':': {:?}",
+                    user.username
+                );
+            }
+        }
 
-/// Configuration for a backend server
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct BackendConfig {
-    /// Backend URL (e.g., "http://localhost:3000")
-    pub url: String,
+        if self.connect_timeout_ms == 0 {
+            anyhow::bail!("Forward proxy connect_timeout_ms must be greater than 0");
+        }
 
-    /// Optional backend name for logging
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub name: Option<String>,
+        Ok(())
+    }
 }
 
 fn default_false() -> bool {
     false
 }
 
+fn default_true() -> bool {
+    true
+}
+
 fn default_connection_timeout() -> u64 {
     5000 // 5 seconds
 }
@@ -117,6 +4253,340 @@ fn default_request_timeout() -> u64 {
     30000 // 30 seconds
 }
 
+fn default_mirror_percent() -> u8 {
+    100
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+fn default_health_interval() -> u64 {
+    10
+}
+
+fn default_health_timeout() -> u64 {
+    2000
+}
+
+fn default_health_path() -> String {
+    "/health".to_string()
+}
+
+fn default_unhealthy_threshold() -> u32 {
+    3
+}
+
+fn default_healthy_threshold() -> u32 {
+    1
+}
+
+fn default_circuit_cooldown() -> u64 {
+    10_000
+}
+
+fn default_half_open_requests() -> u32 {
+    1
+}
+
+fn default_dns_ttl() -> u64 {
+    30_000
+}
+
+fn default_happy_eyeballs_delay() -> u64 {
+    250
+}
+
+fn default_metrics_path() -> String {
+    "/metrics".to_string()
+}
+
+fn default_tls_session_cache_size() -> usize {
+    DEFAULT_SESSION_CACHE_SIZE
+}
+
+fn default_keepalive_max_idle() -> usize {
+    8
+}
+
+fn default_keepalive_idle_timeout() -> u64 {
+    30_000
+}
+
+fn default_tls_reload_interval() -> u64 {
+    30
+}
+
+fn default_tls_min_version() -> TlsVersion {
+    TlsVersion::Tls12
+}
+
+fn default_tls_max_version() -> TlsVersion {
+    TlsVersion::Tls13
+}
+
+fn default_alpn() -> Vec<String> {
+    vec!["http/1.1".to_string()]
+}
+
+fn default_ocsp_refresh() -> u64 {
+    3600
+}
+
+fn default_ocsp_retry() -> u64 {
+    60
+}
+
+fn default_ocsp_timeout() -> u64 {
+    5000
+}
+
+fn default_load_header() -> String {
+    "endpoint-load-metrics".to_string()
+}
+
+fn default_load_metric() -> String {
+    "cpu_utilization".to_string()
+}
+
+fn default_load_smoothing() -> f64 {
+    0.3
+}
+
+fn default_min_weight_percent() -> u8 {
+    10
+}
+
+fn default_path_prefix() -> String {
+    "/".to_string()
+}
+
+fn default_intercept_min_status() -> u16 {
+    500
+}
+
+fn default_retry_after_secs() -> u64 {
+    5
+}
+
+fn default_retry_methods() -> Vec<String> {
+    ["GET", "HEAD", "OPTIONS", "PUT", "DELETE", "PROPFIND"]
+        .map(String::from)
+        .to_vec()
+}
+
+fn default_retry_budget_percent() -> u32 {
+    20
+}
+
+fn default_retry_budget_burst() -> u32 {
+    10
+}
+
+fn default_backoff_base() -> u64 {
+    50
+}
+
+fn default_backoff_multiplier() -> f64 {
+    2.0
+}
+
+fn default_backoff_jitter() -> f64 {
+    0.5
+}
+
+fn default_backoff_max() -> u64 {
+    1000
+}
+
+fn default_slo_windows() -> Vec<u64> {
+    vec![300, 3600, 21600]
+}
+
+fn default_static_response_status() -> u16 {
+    200
+}
+
+fn default_capture_max_bytes() -> usize {
+    4096
+}
+
+fn default_capture_per_minute() -> u32 {
+    60
+}
+
+fn default_traffic_sample_percent() -> u8 {
+    1
+}
+
+fn default_traffic_entries_per_file() -> usize {
+    1000
+}
+
+fn default_traffic_flush_secs() -> u64 {
+    60
+}
+
+fn default_body_memory_limit_bytes() -> usize {
+    1024 * 1024
+}
+
+fn default_body_max_bytes() -> u64 {
+    1024 * 1024 * 1024
+}
+
+fn default_max_header_bytes() -> usize {
+    DEFAULT_MAX_HEADER_BYTES
+}
+
+fn default_max_headers() -> usize {
+    DEFAULT_MAX_HEADERS
+}
+
+fn default_max_uri_length() -> usize {
+    DEFAULT_MAX_URI_LENGTH
+}
+
+fn default_server_header() -> String {
+    format!("sentinel/{}", env!("CARGO_PKG_VERSION"))
+}
+
+fn default_traffic_max_body_bytes() -> usize {
+    64 * 1024
+}
+
+fn default_image_max_dimension() -> u32 {
+    4096
+}
+
+fn default_image_quality() -> u8 {
+    80
+}
+
+fn default_image_max_source_bytes() -> usize {
+    20 * 1024 * 1024
+}
+
+fn default_image_cache_entries() -> usize {
+    256
+}
+
+fn default_capture_redact_headers() -> Vec<String> {
+    [
+        "Authorization",
+        "Proxy-Authorization",
+        "Cookie",
+        "Set-Cookie",
+        "X-Api-Key",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+fn default_preview_header() -> String {
+    "X-Sentinel-Preview".to_string()
+}
+
+fn default_variant_header() -> String {
+    "X-Sentinel-Variant".to_string()
+}
+
+fn default_experiment_max_age() -> u64 {
+    30 * 24 * 60 * 60 // 30 days
+}
+
+fn default_chaos_status() -> u16 {
+    503
+}
+
+fn default_redirect_status() -> u16 {
+    301
+}
+
+fn default_cors_methods() -> Vec<String> {
+    ["GET", "HEAD", "POST"].map(String::from).to_vec()
+}
+
+fn default_cors_max_age() -> u64 {
+    600
+}
+
+fn default_hsts() -> Option<HstsConfig> {
+    Some(HstsConfig {
+        max_age_secs: default_hsts_max_age(),
+        include_subdomains: true,
+        preload: false,
+    })
+}
+
+fn default_hsts_max_age() -> u64 {
+    31_536_000
+}
+
+fn default_frame_options() -> Option<FrameOptions> {
+    Some(FrameOptions::Deny)
+}
+
+fn default_referrer_policy() -> Option<String> {
+    Some("strict-origin-when-cross-origin".to_string())
+}
+
+fn default_via() -> String {
+    "sentinel".to_string()
+}
+
+fn default_redirect_scheme() -> String {
+    "http".to_string()
+}
+
+fn default_tarpit_ms() -> u64 {
+    10_000
+}
+
+fn default_policy_timeout_ms() -> u64 {
+    500
+}
+
+fn default_idempotency_header() -> String {
+    "Idempotency-Key".to_string()
+}
+
+fn default_idempotency_ttl() -> u64 {
+    86_400 // 24 hours
+}
+
+fn default_idempotency_max_entries() -> usize {
+    10_000
+}
+
+fn default_consul_address() -> String {
+    "http://127.0.0.1:8500".to_string()
+}
+
+fn default_refresh_interval() -> u64 {
+    10
+}
+
+fn default_backend_scheme() -> String {
+    "http".to_string()
+}
+
+fn default_docker_endpoint() -> String {
+    "unix:///var/run/docker.sock".to_string()
+}
+
+fn default_label_prefix() -> String {
+    "sentinel".to_string()
+}
+
+fn default_docker_refresh_interval() -> u64 {
+    5
+}
+
+fn default_file_refresh_interval() -> u64 {
+    2
+}
+
 impl Config {
     /// Load configuration from a YAML file
     ///
@@ -149,14 +4619,46 @@ impl Config {
         let listen_addr = std::env::var("LISTEN").unwrap_or_else(|_| "127.0.0.1:8080".to_string());
 
         Self {
-            server: ServerConfig { listen_addr },
+            server: ServerConfig {
+                listen_addr,
+                request_timeout_ms: None,
+                max_requests_per_connection: None,
+                malformed_capture: None,
+                daemon: None,
+                tls: None,
+                request_body: None,
+                max_header_bytes: default_max_header_bytes(),
+                max_headers: default_max_headers(),
+                max_uri_length: default_max_uri_length(),
+                lenient_parsing: false,
+                server_header: default_server_header(),
+                backend_server_header: BackendServerHeader::Keep,
+                scrub_response_headers: Vec::new(),
+                trusted_proxies: Vec::new(),
+            },
             static_files: StaticFilesConfig {
                 root: PathBuf::from("public"),
                 index: "index.html".to_string(),
                 error_pages: ErrorPages::default(),
                 directory_listing: false,
+                images: None,
             },
             proxy: None,
+            chaos: None,
+            forward_proxy: None,
+            redirects: None,
+            security_headers: None,
+            idempotency: None,
+            fingerprints: None,
+            bots: None,
+            policy: None,
+            traffic_capture: None,
+            admin: None,
+            metrics: None,
+            static_responses: Vec::new(),
+            webdav: Vec::new(),
+            slos: Vec::new(),
+            routes: Vec::new(),
         }
     }
 }
@@ -0,0 +1,224 @@
+//! Consul service discovery
+//!
+//! Watches `/v1/health/service/<service>` with blocking queries and keeps a
+//! backend pool in sync with the service's instances. Instances with a
+//! critical check are always excluded; instances with a warning are
+//! excluded unless `only_passing` is turned off.
+//!
+//! # Example
+//!
+//! ```yaml
+//! proxy:
+//!   backends: []
+//!   discovery:
+//!     consul:
+//!       address: "http://127.0.0.1:8500"
+//!       service: "web"
+//!       datacenter: "dc1"
+//!       tags: ["production"]
+//!       refresh_interval_secs: 10
+//! ```
+
+use crate::config::{BackendConfig, ConsulConfig};
+use crate::discovery::http;
+use crate::proxy::BackendPool;
+use anyhow::Context;
+use serde::Deserialize;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Extra time allowed on top of the blocking-query wait before timing out
+const REQUEST_GRACE: Duration = Duration::from_secs(10);
+
+/// Keeps a backend pool in sync with a Consul service
+pub struct ConsulWatcher {
+    config: ConsulConfig,
+    pool: BackendPool,
+    /// `X-Consul-Index` of the last response, for blocking queries
+    index: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct HealthEntry {
+    node: NodeInfo,
+    service: ServiceInfo,
+    #[serde(default)]
+    checks: Vec<CheckInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct NodeInfo {
+    address: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ServiceInfo {
+    #[serde(rename = "ID")]
+    id: String,
+    #[serde(default)]
+    address: String,
+    port: u16,
+    #[serde(default)]
+    tags: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct CheckInfo {
+    status: String,
+}
+
+impl ConsulWatcher {
+    /// Create a watcher that updates `pool` from the configured service
+    pub fn new(config: ConsulConfig, pool: BackendPool) -> Self {
+        Self {
+            config,
+            pool,
+            index: None,
+        }
+    }
+
+    /// Fetch the service's instances once and apply them to the pool
+    ///
+    /// After the first call this is a blocking query: Consul holds the
+    /// request until the service changes or the refresh interval passes.
+    /// Returns the number of backends now in the pool.
+    pub async fn poll(&mut self) -> anyhow::Result<usize> {
+        let wait = Duration::from_secs(self.config.refresh_interval_secs);
+        let url = self.request_url(wait)?;
+
+        let mut headers = Vec::new();
+        if let Some(token) = &self.config.token {
+            headers.push(("X-Consul-Token", token.as_str()));
+        }
+
+        let response = http::get_url(&url, &headers, wait + REQUEST_GRACE).await?;
+        if response.status != 200 {
+            anyhow::bail!(
+                "Consul returned {}: {}",
+                response.status,
+                String::from_utf8_lossy(&response.body)
+            );
+        }
+
+        let entries: Vec<HealthEntry> =
+            serde_json::from_slice(&response.body).context("Invalid Consul health response")?;
+
+        // Reset on a missing or decreasing index, as Consul recommends
+        let index = response
+            .header("X-Consul-Index")
+            .and_then(|v| v.parse::<u64>().ok());
+        self.index = match (self.index, index) {
+            (Some(old), Some(new)) if new < old => None,
+            (_, new) => new,
+        };
+
+        let backends = self.backends_from(entries);
+        let count = backends.len();
+        self.pool.replace_backends(backends).await;
+
+        tracing::debug!(service = %self.config.service, backends = count, "Consul refresh applied");
+        Ok(count)
+    }
+
+    /// Poll until `cancel` fires, backing off for one refresh interval on errors
+    pub async fn run(mut self, cancel: CancellationToken) {
+        let interval = Duration::from_secs(self.config.refresh_interval_secs);
+        tracing::info!(
+            service = %self.config.service,
+            address = %self.config.address,
+            "Starting Consul discovery"
+        );
+
+        loop {
+            let result = tokio::select! {
+                result = self.poll() => result,
+                _ = cancel.cancelled() => return,
+            };
+
+            let pause = match result {
+                // Blocking queries do their own waiting
+                Ok(_) if self.index.is_some() => None,
+                Ok(_) => Some(interval),
+                Err(e) => {
+                    tracing::warn!(
+                        service = %self.config.service,
+                        error = %e,
+                        "Consul refresh failed, keeping current backends"
+                    );
+                    self.index = None;
+                    Some(interval)
+                }
+            };
+
+            if let Some(pause) = pause {
+                tokio::select! {
+                    _ = tokio::time::sleep(pause) => {}
+                    _ = cancel.cancelled() => return,
+                }
+            }
+        }
+    }
+
+    fn request_url(&self, wait: Duration) -> anyhow::Result<url::Url> {
+        let base = url::Url::parse(&self.config.address).context("Invalid Consul address")?;
+        let mut url = base.join(&format!("/v1/health/service/{}", self.config.service))?;
+
+        {
+            let mut query = url.query_pairs_mut();
+            if let Some(dc) = &self.config.datacenter {
+                query.append_pair("dc", dc);
+            }
+            if let Some(index) = self.index {
+                query.append_pair("index", &index.to_string());
+                query.append_pair("wait", &format!("{}s", wait.as_secs()));
+            }
+        }
+
+        Ok(url)
+    }
+
+    /// Turn health entries into backend configs, applying health and tag filters
+    fn backends_from(&self, entries: Vec<HealthEntry>) -> Vec<BackendConfig> {
+        let mut backends: Vec<BackendConfig> = entries
+            .into_iter()
+            .filter(|entry| self.is_healthy(entry))
+            .filter(|entry| {
+                let tags = entry.service.tags.as_deref().unwrap_or_default();
+                self.config.tags.iter().all(|t| tags.contains(t))
+            })
+            .map(|entry| {
+                let address = if entry.service.address.is_empty() {
+                    entry.node.address
+                } else {
+                    entry.service.address
+                };
+                BackendConfig {
+                    url: format!(
+                        "{}://{}:{}",
+                        self.config.scheme, address, entry.service.port
+                    ),
+                    name: Some(format!("{}/{}", self.config.service, entry.service.id)),
+                    ..Default::default()
+                }
+            })
+            .collect();
+
+        backends.sort_by(|a, b| a.url.cmp(&b.url));
+        backends
+    }
+
+    fn is_healthy(&self, entry: &HealthEntry) -> bool {
+        entry
+            .checks
+            .iter()
+            .all(|check| match check.status.as_str() {
+                "passing" => true,
+                "warning" => !self.config.only_passing,
+                _ => false,
+            })
+    }
+}
@@ -0,0 +1,283 @@
+//! Docker container discovery
+//!
+//! Polls the Docker Engine API for running containers labelled
+//! `sentinel.enable=true` (with the configured label prefix) and registers
+//! them as backends, in the style of Traefik's Docker provider:
+//!
+//! - `sentinel.port`: container port to proxy to (defaults to the only
+//!   exposed TCP port)
+//! - `sentinel.route`: path prefix served by this container; containers
+//!   sharing a prefix are load balanced together. Containers without a
+//!   route join the main backend pool.
+//!
+//! Intended for single-host deployments where Sentinel can reach container
+//! IPs directly (e.g. it runs on the same Docker network).
+//!
+//! # Example
+//!
+//! ```yaml
+//! proxy:
+//!   backends: []
+//!   discovery:
+//!     docker:
+//!       endpoint: "unix:///var/run/docker.sock"
+//!       network: "web"
+//! ```
+
+use crate::config::{BackendConfig, DockerConfig};
+use crate::discovery::http::{self, HttpResponse};
+use crate::proxy::{BackendPool, DynamicRoutes};
+use anyhow::Context;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Timeout for a single Docker API request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Keeps a backend pool and dynamic routes in sync with labelled containers
+pub struct DockerWatcher {
+    config: DockerConfig,
+    pool: BackendPool,
+    routes: Option<DynamicRoutes>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ContainerSummary {
+    id: String,
+    #[serde(default)]
+    names: Vec<String>,
+    #[serde(default)]
+    labels: HashMap<String, String>,
+    #[serde(default)]
+    ports: Vec<PortInfo>,
+    #[serde(default)]
+    network_settings: Option<NetworkSettings>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PortInfo {
+    private_port: u16,
+    #[serde(rename = "Type", default)]
+    kind: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct NetworkSettings {
+    #[serde(default)]
+    networks: BTreeMap<String, NetworkInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NetworkInfo {
+    #[serde(rename = "IPAddress", default)]
+    ip_address: String,
+}
+
+impl DockerWatcher {
+    /// Create a watcher that updates `pool` from labelled containers
+    ///
+    /// Containers with a route label are ignored unless routes are attached
+    /// with [`DockerWatcher::with_routes`].
+    pub fn new(config: DockerConfig, pool: BackendPool) -> Self {
+        Self {
+            config,
+            pool,
+            routes: None,
+        }
+    }
+
+    /// Register containers with a route label under their own path prefix
+    pub fn with_routes(mut self, routes: DynamicRoutes) -> Self {
+        self.routes = Some(routes);
+        self
+    }
+
+    /// List containers once and apply them to the pool and routes
+    ///
+    /// Returns the number of containers registered.
+    pub async fn poll(&self) -> anyhow::Result<usize> {
+        let response = self.list_containers().await?;
+        if response.status != 200 {
+            anyhow::bail!(
+                "Docker returned {}: {}",
+                response.status,
+                String::from_utf8_lossy(&response.body)
+            );
+        }
+
+        let containers: Vec<ContainerSummary> =
+            serde_json::from_slice(&response.body).context("Invalid Docker container list")?;
+
+        let mut main = Vec::new();
+        let mut routed: BTreeMap<String, Vec<BackendConfig>> = BTreeMap::new();
+        for container in &containers {
+            let Some(backend) = self.backend_from(container) else {
+                continue;
+            };
+            match container.labels.get(&self.label("route")) {
+                Some(route) if self.routes.is_some() => {
+                    routed.entry(route.clone()).or_default().push(backend)
+                }
+                Some(route) => tracing::warn!(
+                    container = %backend.name.as_deref().unwrap_or_default(),
+                    route = %route,
+                    "Ignoring routed container, dynamic routes are not enabled"
+                ),
+                None => main.push(backend),
+            }
+        }
+
+        let count = main.len() + routed.values().map(Vec::len).sum::<usize>();
+
+        main.sort_by(|a, b| a.url.cmp(&b.url));
+        self.pool.replace_backends(main).await;
+
+        if let Some(routes) = &self.routes {
+            let prefixes: Vec<String> = routed.keys().cloned().collect();
+            for (prefix, mut backends) in routed {
+                backends.sort_by(|a, b| a.url.cmp(&b.url));
+                routes.set_backends(&prefix, backends).await;
+            }
+            routes.retain(&prefixes);
+        }
+
+        tracing::debug!(containers = count, "Docker refresh applied");
+        Ok(count)
+    }
+
+    /// Poll until `cancel` fires
+    pub async fn run(self, cancel: CancellationToken) {
+        let interval = Duration::from_secs(self.config.refresh_interval_secs);
+        tracing::info!(endpoint = %self.config.endpoint, "Starting Docker discovery");
+
+        loop {
+            let result = tokio::select! {
+                result = self.poll() => result,
+                _ = cancel.cancelled() => return,
+            };
+
+            if let Err(e) = result {
+                tracing::warn!(
+                    endpoint = %self.config.endpoint,
+                    error = %e,
+                    "Docker refresh failed, keeping current backends"
+                );
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = cancel.cancelled() => return,
+            }
+        }
+    }
+
+    fn label(&self, name: &str) -> String {
+        format!("{}.{}", self.config.label_prefix, name)
+    }
+
+    fn list_path(&self) -> String {
+        let filters = serde_json::json!({ "label": [format!("{}=true", self.label("enable"))] });
+        let encoded: String =
+            url::form_urlencoded::byte_serialize(filters.to_string().as_bytes()).collect();
+        format!("/containers/json?filters={}", encoded)
+    }
+
+    async fn list_containers(&self) -> anyhow::Result<HttpResponse> {
+        let path = self.list_path();
+
+        if let Some(socket) = self.config.endpoint.strip_prefix("unix://") {
+            return list_over_unix(socket, &path).await;
+        }
+
+        let base = url::Url::parse(&self.config.endpoint).context("Invalid Docker endpoint")?;
+        let url = base.join(&path)?;
+        http::get_url(&url, &[], REQUEST_TIMEOUT).await
+    }
+
+    /// Build a backend for a container, or `None` if it has no usable address
+    fn backend_from(&self, container: &ContainerSummary) -> Option<BackendConfig> {
+        let name = container
+            .names
+            .first()
+            .map(|n| n.trim_start_matches('/').to_string())
+            .unwrap_or_else(|| container.id.chars().take(12).collect());
+
+        let port = match container.labels.get(&self.label("port")) {
+            Some(port) => match port.parse::<u16>() {
+                Ok(port) => port,
+                Err(_) => {
+                    tracing::warn!(container = %name, port = %port, "Invalid port label, skipping container");
+                    return None;
+                }
+            },
+            None => {
+                let mut tcp: Vec<u16> = container
+                    .ports
+                    .iter()
+                    .filter(|p| p.kind.is_empty() || p.kind == "tcp")
+                    .map(|p| p.private_port)
+                    .collect();
+                tcp.sort_unstable();
+                tcp.dedup();
+                match tcp.as_slice() {
+                    [port] => *port,
+                    _ => {
+                        tracing::warn!(
+                            container = %name,
+                            "Cannot pick a port, set the {} label",
+                            self.label("port")
+                        );
+                        return None;
+                    }
+                }
+            }
+        };
+
+        let networks = container
+            .network_settings
+            .as_ref()
+            .map(|settings| &settings.networks);
+        let ip = networks.and_then(|networks| match &self.config.network {
+            Some(network) => networks.get(network).map(|n| n.ip_address.as_str()),
+            None => networks
+                .values()
+                .map(|n| n.ip_address.as_str())
+                .find(|ip| !ip.is_empty()),
+        });
+        let Some(ip) = ip.filter(|ip| !ip.is_empty()) else {
+            tracing::warn!(container = %name, "Container has no IP address on the configured network");
+            return None;
+        };
+
+        Some(BackendConfig {
+            url: format!("{}://{}:{}", self.config.scheme, ip, port),
+            name: Some(name),
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(unix)]
+async fn list_over_unix(socket: &str, path: &str) -> anyhow::Result<HttpResponse> {
+    tokio::time::timeout(REQUEST_TIMEOUT, async {
+        let stream = tokio::net::UnixStream::connect(socket)
+            .await
+            .with_context(|| format!("Failed to connect to {}", socket))?;
+        http::get(stream, "localhost", path, &[]).await
+    })
+    .await
+    .context("Docker request timeout")?
+}
+
+#[cfg(not(unix))]
+async fn list_over_unix(socket: &str, _path: &str) -> anyhow::Result<HttpResponse> {
+    anyhow::bail!(
+        "Unix socket endpoints are not supported on this platform: {}",
+        socket
+    )
+}
@@ -0,0 +1,126 @@
+//! File-based backend lists
+//!
+//! Reads the pool's backends from a separate JSON or YAML file and re-reads
+//! it whenever its contents change, so orchestration tooling can update the
+//! pool by rewriting one file. A file that fails to parse or validate is
+//! ignored until the next change; the pool keeps its current backends.
+//!
+//! # Example
+//!
+//! ```yaml
+//! # backends.yaml
+//! backends:
+//!   - url: "http://10.0.0.1:3000"
+//!     name: "web-1"
+//!   - url: "http://10.0.0.2:3000"
+//! ```
+//!
+//! Writing the new list to a temporary file and renaming it over the old
+//! one avoids reading a half-written file.
+
+use crate::config::{BackendConfig, FileDiscoveryConfig, validate_backends};
+use crate::proxy::BackendPool;
+use anyhow::Context;
+use serde::Deserialize;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Keeps a backend pool in sync with a backend list file
+pub struct FileWatcher {
+    config: FileDiscoveryConfig,
+    pool: BackendPool,
+    /// Contents of the file as last applied
+    last: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum BackendFile {
+    List(Vec<BackendConfig>),
+    Document { backends: Vec<BackendConfig> },
+}
+
+impl FileWatcher {
+    /// Create a watcher that updates `pool` from the configured file
+    pub fn new(config: FileDiscoveryConfig, pool: BackendPool) -> Self {
+        Self {
+            config,
+            pool,
+            last: None,
+        }
+    }
+
+    /// Re-read the file and apply it to the pool if its contents changed
+    ///
+    /// Returns the number of backends applied, or `None` if the file is
+    /// unchanged since the last successful load.
+    pub async fn poll(&mut self) -> anyhow::Result<Option<usize>> {
+        let contents = tokio::fs::read(&self.config.path)
+            .await
+            .with_context(|| format!("Failed to read {}", self.config.path.display()))?;
+
+        if self.last.as_deref() == Some(contents.as_slice()) {
+            return Ok(None);
+        }
+
+        let backends = self.parse(&contents)?;
+        validate_backends(&backends)?;
+
+        let count = backends.len();
+        self.pool.replace_backends(backends).await;
+        self.last = Some(contents);
+
+        tracing::info!(
+            path = %self.config.path.display(),
+            backends = count,
+            "Backend file loaded"
+        );
+        Ok(Some(count))
+    }
+
+    /// Check the file for changes until `cancel` fires
+    pub async fn run(mut self, cancel: CancellationToken) {
+        let interval = Duration::from_secs(self.config.refresh_interval_secs);
+        tracing::info!(path = %self.config.path.display(), "Watching backend file");
+
+        // Warn once per failure streak rather than every interval
+        let mut failing = false;
+        loop {
+            match self.poll().await {
+                Ok(_) => failing = false,
+                Err(e) if !failing => {
+                    tracing::warn!(
+                        path = %self.config.path.display(),
+                        error = %e,
+                        "Backend file reload failed, keeping current backends"
+                    );
+                    failing = true;
+                }
+                Err(_) => {}
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = cancel.cancelled() => return,
+            }
+        }
+    }
+
+    fn parse(&self, contents: &[u8]) -> anyhow::Result<Vec<BackendConfig>> {
+        let is_json = self
+            .config
+            .path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+
+        let file: BackendFile = if is_json {
+            serde_json::from_slice(contents).context("Invalid JSON backend file")?
+        } else {
+            serde_yaml::from_slice(contents).context("Invalid YAML backend file")?
+        };
+
+        Ok(match file {
+            BackendFile::List(backends) | BackendFile::Document { backends } => backends,
+        })
+    }
+}
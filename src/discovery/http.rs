@@ -0,0 +1,207 @@
+//! Minimal HTTP/1.1 client for discovery APIs
+//!
+//! Discovery sources (Consul, the Docker socket), health checks, and OCSP
+//! fetches only need one-shot requests with `Connection: close`, so this
+//! reads the whole response and decodes chunked bodies without pulling in a
+//! full client.
+
+use anyhow::Context;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// A buffered response from a discovery API
+#[derive(Debug)]
+pub(crate) struct HttpResponse {
+    pub status: u16,
+    /// Header names are lowercased
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+}
+
+/// GET an `http://` URL over TCP, giving up after `timeout`
+pub(crate) async fn get_url(
+    url: &url::Url,
+    headers: &[(&str, &str)],
+    timeout: Duration,
+) -> anyhow::Result<HttpResponse> {
+    let target = Target::parse(url)?;
+    tokio::time::timeout(timeout, async {
+        let stream = target.connect().await?;
+        get(stream, &target.authority, &target.path, headers).await
+    })
+    .await
+    .context("Discovery request timeout")?
+}
+
+/// POST `body` to an `http://` URL over TCP, giving up after `timeout`
+pub(crate) async fn post_url(
+    url: &url::Url,
+    content_type: &str,
+    body: &[u8],
+    timeout: Duration,
+) -> anyhow::Result<HttpResponse> {
+    let target = Target::parse(url)?;
+    tokio::time::timeout(timeout, async {
+        let mut stream = target.connect().await?;
+        let head = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            target.path,
+            target.authority,
+            content_type,
+            body.len()
+        );
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(body).await?;
+        stream.flush().await?;
+
+        let mut raw = Vec::new();
+        stream.read_to_end(&mut raw).await?;
+        parse_response(&raw)
+    })
+    .await
+    .context("HTTP request timeout")?
+}
+
+/// Where an `http://` URL points
+struct Target {
+    host: String,
+    port: u16,
+    /// `host[:port]` for the Host header
+    authority: String,
+    /// Path and query
+    path: String,
+}
+
+impl Target {
+    fn parse(url: &url::Url) -> anyhow::Result<Self> {
+        if url.scheme() != "http" {
+            anyhow::bail!("Unsupported scheme for discovery request: {}", url.scheme());
+        }
+        let host = url.host_str().context("URL missing host")?;
+
+        let mut path = url.path().to_string();
+        if let Some(query) = url.query() {
+            path.push('?');
+            path.push_str(query);
+        }
+        Ok(Self {
+            host: host.to_string(),
+            port: url.port().unwrap_or(80),
+            authority: match url.port() {
+                Some(port) => format!("{}:{}", host, port),
+                None => host.to_string(),
+            },
+            path,
+        })
+    }
+
+    async fn connect(&self) -> anyhow::Result<TcpStream> {
+        TcpStream::connect((self.host.as_str(), self.port))
+            .await
+            .with_context(|| format!("Failed to connect to {}", self.authority))
+    }
+}
+
+/// Send a GET request over an established stream and read the full response
+pub(crate) async fn get<S>(
+    mut stream: S,
+    host: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+) -> anyhow::Result<HttpResponse>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nAccept: application/json\r\nConnection: close\r\n",
+        path, host
+    );
+    for (key, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", key, value));
+    }
+    request.push_str("\r\n");
+
+    stream.write_all(request.as_bytes()).await?;
+    stream.flush().await?;
+
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).await?;
+
+    parse_response(&raw)
+}
+
+fn parse_response(raw: &[u8]) -> anyhow::Result<HttpResponse> {
+    let end = raw
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .context("Incomplete HTTP response")?;
+    let head = std::str::from_utf8(&raw[..end]).context("Invalid UTF-8 in response headers")?;
+    let mut lines = head.split("\r\n");
+
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok())
+        .context("Invalid status line")?;
+
+    let headers: HashMap<String, String> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(k, v)| (k.trim().to_ascii_lowercase(), v.trim().to_string()))
+        .collect();
+
+    let rest = &raw[end + 4..];
+    let body = if headers
+        .get("transfer-encoding")
+        .is_some_and(|v| v.eq_ignore_ascii_case("chunked"))
+    {
+        decode_chunked(rest)?
+    } else if let Some(len) = headers
+        .get("content-length")
+        .and_then(|v| v.parse::<usize>().ok())
+    {
+        rest[..len.min(rest.len())].to_vec()
+    } else {
+        rest.to_vec()
+    };
+
+    Ok(HttpResponse {
+        status,
+        headers,
+        body,
+    })
+}
+
+fn decode_chunked(mut data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut body = Vec::new();
+
+    loop {
+        let line_end = data
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .context("Incomplete chunk size")?;
+        let size_line = std::str::from_utf8(&data[..line_end])?;
+        let size_hex = size_line.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size_hex, 16).context("Invalid chunk size")?;
+
+        data = &data[line_end + 2..];
+        if size == 0 {
+            return Ok(body);
+        }
+        if data.len() < size + 2 {
+            anyhow::bail!("Truncated chunk");
+        }
+
+        body.extend_from_slice(&data[..size]);
+        data = &data[size + 2..];
+    }
+}
@@ -0,0 +1,57 @@
+//! Dynamic backend discovery
+//!
+//! Watchers keep a [`BackendPool`] in sync with an external source of truth
+//! by periodically replacing its members (see
+//! [`BackendPool::replace_backends`]). Backends that stay in the source keep
+//! their health state across refreshes.
+//!
+//! - `consul`: Healthy instances of a Consul service
+//! - `docker`: Labelled containers on the local Docker engine
+//! - `file`: A JSON/YAML backend list, reloaded when it changes
+
+pub mod consul;
+pub mod docker;
+pub mod file;
+pub(crate) mod http;
+
+pub use consul::ConsulWatcher;
+pub use docker::DockerWatcher;
+pub use file::FileWatcher;
+
+use crate::config::DiscoveryConfig;
+use crate::proxy::{BackendPool, DynamicRoutes};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+/// Start every watcher enabled in `config`, updating `pool` until `cancel` fires
+///
+/// Sources that can register per-prefix routes (Docker) add them to `routes`
+/// when given.
+pub fn spawn_watchers(
+    config: &DiscoveryConfig,
+    pool: &BackendPool,
+    routes: Option<&DynamicRoutes>,
+    cancel: CancellationToken,
+) -> Vec<JoinHandle<()>> {
+    let mut handles = Vec::new();
+
+    if let Some(consul) = &config.consul {
+        let watcher = ConsulWatcher::new(consul.clone(), pool.clone());
+        handles.push(tokio::spawn(watcher.run(cancel.clone())));
+    }
+
+    if let Some(docker) = &config.docker {
+        let mut watcher = DockerWatcher::new(docker.clone(), pool.clone());
+        if let Some(routes) = routes {
+            watcher = watcher.with_routes(routes.clone());
+        }
+        handles.push(tokio::spawn(watcher.run(cancel.clone())));
+    }
+
+    if let Some(file) = &config.file {
+        let watcher = FileWatcher::new(file.clone(), pool.clone());
+        handles.push(tokio::spawn(watcher.run(cancel.clone())));
+    }
+
+    handles
+}
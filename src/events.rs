@@ -0,0 +1,150 @@
+//! Lifecycle events
+//!
+//! Sentinel emits typed [`Event`]s at key points (connections, requests,
+//! backend selection and health, configuration reloads). Embedders can
+//! observe them either synchronously through an [`EventSubscriber`] or
+//! asynchronously through a broadcast channel, e.g. for custom logging,
+//! billing, or alerting.
+//!
+//! # Example
+//!
+//! ```
+//! use sentinel::events::{Event, EventSubscriber, Events};
+//! use std::sync::Arc;
+//!
+//! struct Audit;
+//!
+//! impl EventSubscriber for Audit {
+//!     fn on_event(&self, event: &Event) {
+//!         if let Event::BackendStateChanged { backend, to, .. } = event {
+//!             eprintln!("{} is now {:?}", backend, to);
+//!         }
+//!     }
+//! }
+//!
+//! let events = Events::new();
+//! events.add_subscriber(Arc::new(Audit));
+//! ```
+
+use crate::http::request::Method;
+use crate::proxy::backend::BackendState;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// Capacity of the broadcast channel; slow receivers see `Lagged` errors
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// A lifecycle event
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// A client connection was accepted
+    ConnectionAccepted { peer: SocketAddr },
+
+    /// A client connection was closed
+    ConnectionClosed { peer: SocketAddr },
+
+    /// A request was parsed and is about to be handled
+    RequestStarted { method: Method, path: String },
+
+    /// A response was produced for a request
+    RequestFinished {
+        method: Method,
+        path: String,
+        status: u16,
+        duration: Duration,
+    },
+
+    /// A backend was selected to serve a request
+    BackendSelected { backend: String },
+
+    /// A backend changed state (e.g. Up → Down)
+    BackendStateChanged {
+        backend: String,
+        from: BackendState,
+        to: BackendState,
+    },
+
+    /// A backend joined the pool (e.g. through service discovery)
+    BackendAdded { backend: String },
+
+    /// A backend left the pool
+    BackendRemoved { backend: String },
+
+    /// Configuration (or part of it) was reloaded
+    ConfigReloaded { source: String },
+}
+
+/// Receives events synchronously as they are emitted
+///
+/// Implementations should return quickly; slow work belongs on a task fed by
+/// [`Events::channel`].
+pub trait EventSubscriber: Send + Sync {
+    /// Called for every emitted event
+    fn on_event(&self, event: &Event);
+}
+
+/// Event bus shared by the server, connections, and backend pools
+///
+/// Cloning is cheap; all clones publish to the same subscribers.
+#[derive(Clone)]
+pub struct Events {
+    inner: Arc<EventsInner>,
+}
+
+struct EventsInner {
+    subscribers: RwLock<Vec<Arc<dyn EventSubscriber>>>,
+    sender: broadcast::Sender<Event>,
+}
+
+impl Events {
+    /// Create an event bus with no subscribers
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+
+        Self {
+            inner: Arc::new(EventsInner {
+                subscribers: RwLock::new(Vec::new()),
+                sender,
+            }),
+        }
+    }
+
+    /// Register a synchronous subscriber
+    pub fn add_subscriber(&self, subscriber: Arc<dyn EventSubscriber>) {
+        self.inner.subscribers.write().unwrap().push(subscriber);
+    }
+
+    /// Get a broadcast receiver for all events emitted from now on
+    pub fn channel(&self) -> broadcast::Receiver<Event> {
+        self.inner.sender.subscribe()
+    }
+
+    /// Publish an event to all subscribers and channel receivers
+    pub fn emit(&self, event: Event) {
+        for subscriber in self.inner.subscribers.read().unwrap().iter() {
+            subscriber.on_event(&event);
+        }
+
+        if self.inner.sender.receiver_count() > 0 {
+            // Only fails when all receivers were dropped in the meantime
+            let _ = self.inner.sender.send(event);
+        }
+    }
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for Events {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Events")
+            .field("subscribers", &self.inner.subscribers.read().unwrap().len())
+            .field("receivers", &self.inner.sender.receiver_count())
+            .finish()
+    }
+}
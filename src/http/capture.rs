@@ -0,0 +1,134 @@
+//! Malformed request capture
+//!
+//! When the parser rejects a request, a [`MalformedCapture`] appends the raw
+//! bytes to a capture file so operators can tell attacks from broken clients
+//! without running tcpdump. Captures are size-capped, rate-limited, and have
+//! secret header values redacted.
+//!
+//! Each capture is one JSON line:
+//!
+//! ```text
+//! {"bytes":52,"error":"InvalidHeader","peer":"10.0.0.7:51812","raw":"GET / HTTP/1.1\r\nAuthorization: [REDACTED]\r\nbad header\r\n\r\n","suppressed":0,"truncated":false,"unix_time":1760601600}
+//! ```
+
+use crate::config::MalformedCaptureConfig;
+use crate::http::parser::ParseError;
+use anyhow::Context;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const WINDOW: Duration = Duration::from_secs(60);
+const REDACTED: &[u8] = b" [REDACTED]";
+
+/// Appends rejected requests to a capture file
+pub struct MalformedCapture {
+    max_bytes: usize,
+    per_minute: u32,
+    redact_headers: Vec<String>,
+    state: Mutex<CaptureState>,
+}
+
+struct CaptureState {
+    file: File,
+    window_start: Instant,
+    written: u32,
+    suppressed: u64,
+}
+
+impl MalformedCapture {
+    /// Open (or create) the capture file
+    pub fn new(config: &MalformedCaptureConfig) -> anyhow::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.path)
+            .with_context(|| format!("Failed to open capture file {}", config.path.display()))?;
+        Ok(Self {
+            max_bytes: config.max_bytes,
+            per_minute: config.per_minute,
+            redact_headers: config.redact_headers.clone(),
+            state: Mutex::new(CaptureState {
+                file,
+                window_start: Instant::now(),
+                written: 0,
+                suppressed: 0,
+            }),
+        })
+    }
+
+    /// Record a rejected request, unless this minute's budget is spent
+    ///
+    /// Returns whether the request was written.
+    pub fn record(&self, peer: Option<SocketAddr>, error: &ParseError, raw: &[u8]) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.window_start.elapsed() >= WINDOW {
+            state.window_start = Instant::now();
+            state.written = 0;
+        }
+        if state.written >= self.per_minute {
+            state.suppressed += 1;
+            return false;
+        }
+
+        let mut captured = self.redact(raw);
+        let truncated = captured.len() > self.max_bytes;
+        captured.truncate(self.max_bytes);
+
+        let line = serde_json::json!({
+            "unix_time": SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            "peer": peer.map(|p| p.to_string()),
+            "error": format!("{:?}", error),
+            "bytes": raw.len(),
+            "truncated": truncated,
+            "suppressed": state.suppressed,
+            "raw": String::from_utf8_lossy(&captured),
+        });
+        if let Err(e) = writeln!(state.file, "{}", line) {
+            tracing::warn!(error = %e, "Failed to write malformed request capture");
+            return false;
+        }
+        state.written += 1;
+        state.suppressed = 0;
+        true
+    }
+
+    /// Copy of `raw` with the values of redacted headers replaced
+    fn redact(&self, raw: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(raw.len());
+        let mut in_headers = true;
+        for line in raw.split_inclusive(|&b| b == b'\n') {
+            let content = line.strip_suffix(b"\n").unwrap_or(line);
+            let content = content.strip_suffix(b"\r").unwrap_or(content);
+            if content.is_empty() {
+                in_headers = false;
+            }
+
+            let name = content
+                .iter()
+                .position(|&b| b == b':')
+                .map(|colon| &content[..colon]);
+            let secret = in_headers
+                && name.is_some_and(|name| {
+                    self.redact_headers
+                        .iter()
+                        .any(|h| h.as_bytes().eq_ignore_ascii_case(name.trim_ascii()))
+                });
+            if secret {
+                let name = name.unwrap_or_default();
+                out.extend_from_slice(name);
+                out.push(b':');
+                out.extend_from_slice(REDACTED);
+                out.extend_from_slice(&line[content.len()..]);
+            } else {
+                out.extend_from_slice(line);
+            }
+        }
+        out
+    }
+}
@@ -1,19 +1,26 @@
-use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpStream;
 
-use crate::http::parser::{ParseError, parse_http_request};
-use crate::http::request::Request;
-use crate::http::writer::ResponseWriter;
+use crate::http::parser::{HeadLimits, ParseError, ParseMode, RequestParser};
+use crate::http::request::{Method, Request};
+use crate::http::writer::{ResponseWriter, ServerIdentity, write_body_stream};
 
-use std::path::PathBuf;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::fs;
 
 use crate::config::StaticFilesConfig;
-use crate::http::mime::content_type;
-use crate::http::response::{Response, ResponseBuilder, StatusCode};
-use crate::proxy::ProxyHandler;
-use std::time::Instant;
+use crate::events::{Event, Events};
+use crate::http::capture::MalformedCapture;
+use crate::http::context::RequestContext;
+use crate::http::error_pages::{ErrorPageHandler, ErrorPageRenderer};
+use crate::http::handler::{Handler, handle_isolated};
+use crate::http::response::{Disposition, Response, StatusCode};
+use crate::http::spool::BodySpool;
+use crate::http::static_files::StaticFileHandler;
+use crate::metrics::Metrics;
+use crate::tls::{ClientCertificate, TlsFingerprint};
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
 
 /// Handles a single HTTP client connection with support for keep-alive and pipelining.
 ///
@@ -33,6 +40,9 @@ use std::time::Instant;
 /// The machine allows keep-alive connections to cycle back from Writing to Reading
 /// for multiple requests on the same connection.
 ///
+/// The stream is usually a `TcpStream`, but any `AsyncRead + AsyncWrite`
+/// transport works (TLS streams, or `tokio::io::duplex` in tests).
+///
 /// # Example
 ///
 /// ```ignore
@@ -46,19 +56,33 @@ use std::time::Instant;
 ///     loop {
 ///         let (socket, _) = listener.accept().await?;
 ///         tokio::spawn(async move {
-///             let mut conn = Connection::new(socket);
+///             let mut conn = Connection::new(socket, static_config.clone());
 ///             let _ = conn.run().await;
 ///         });
 ///     }
 /// }
 /// ```
-pub struct Connection {
-    stream: TcpStream,
+pub struct Connection<S = TcpStream> {
+    stream: S,
     buffer: Vec<u8>,
     state: ConnectionState,
     request_start: Option<Instant>,
-    static_config: StaticFilesConfig,
-    proxy_handler: Option<Arc<ProxyHandler>>,
+    handler: Arc<dyn Handler>,
+    events: Events,
+    metrics: Metrics,
+    cancel: CancellationToken,
+    drain: CancellationToken,
+    request_timeout: Option<Duration>,
+    peer_closed: bool,
+    peer: Option<SocketAddr>,
+    capture: Option<Arc<MalformedCapture>>,
+    fingerprint: Option<Arc<TlsFingerprint>>,
+    client_cert: Option<Arc<ClientCertificate>>,
+    spool: Option<Arc<BodySpool>>,
+    max_requests: Option<u64>,
+    requests_served: u64,
+    parser: RequestParser,
+    identity: Arc<ServerIdentity>,
 }
 
 /// Represents the state of an HTTP connection in its processing lifecycle.
@@ -79,12 +103,15 @@ pub enum ConnectionState {
     Closed,
 }
 
-impl Connection {
-    /// Creates a new HTTP connection handler for the given TCP stream.
+impl<S> Connection<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Creates a new HTTP connection handler for the given stream.
     ///
     /// # Arguments
     ///
-    /// * `stream` - The TCP stream connected to the client
+    /// * `stream` - The stream connected to the client
     /// * `static_config` - Configuration for serving static files
     ///
     /// # Returns
@@ -98,43 +125,150 @@ impl Connection {
     /// let mut conn = Connection::new(socket, static_config);
     /// conn.run().await?;
     /// ```
-    pub fn new(stream: TcpStream, static_config: StaticFilesConfig) -> Self {
-        Self {
+    pub fn new(stream: S, static_config: StaticFilesConfig) -> Self {
+        let renderer = ErrorPageRenderer::load(&static_config.root, &static_config.error_pages);
+        Self::with_handler(
             stream,
-            buffer: Vec::with_capacity(4096),
-            state: ConnectionState::Reading,
-            request_start: None,
-            static_config,
-            proxy_handler: None,
-        }
+            Arc::new(ErrorPageHandler::new(
+                StaticFileHandler::new(static_config),
+                Arc::new(renderer),
+            )),
+        )
     }
 
-    /// Creates a new HTTP connection handler with proxy support.
+    /// Creates a new HTTP connection handler that dispatches every request to `handler`.
     ///
     /// # Arguments
     ///
-    /// * `stream` - The TCP stream connected to the client
-    /// * `static_config` - Configuration for serving static files
-    /// * `proxy_handler` - Optional proxy handler for backend forwarding
+    /// * `stream` - The stream connected to the client
+    /// * `handler` - The root handler (usually a [`Router`](crate::http::router::Router))
     ///
     /// # Returns
     ///
-    /// A new `Connection` initialized with the provided stream and proxy handler.
-    pub fn with_proxy(
-        stream: TcpStream,
-        static_config: StaticFilesConfig,
-        proxy_handler: Arc<ProxyHandler>,
-    ) -> Self {
+    /// A new `Connection` initialized with the provided stream and handler.
+    pub fn with_handler(stream: S, handler: Arc<dyn Handler>) -> Self {
         Self {
             stream,
             buffer: Vec::with_capacity(4096),
             state: ConnectionState::Reading,
             request_start: None,
-            static_config,
-            proxy_handler: Some(proxy_handler),
+            handler,
+            events: Events::new(),
+            metrics: Metrics::default(),
+            cancel: CancellationToken::new(),
+            drain: CancellationToken::new(),
+            request_timeout: None,
+            peer_closed: false,
+            peer: None,
+            capture: None,
+            fingerprint: None,
+            client_cert: None,
+            spool: None,
+            max_requests: None,
+            requests_served: 0,
+            parser: RequestParser::new(ParseMode::Strict, HeadLimits::default()),
+            identity: Arc::default(),
         }
     }
 
+    /// Publishes request lifecycle events to the given event bus.
+    pub fn with_events(mut self, events: Events) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Records request counts and durations to the given metrics recorder.
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Cancels in-flight requests when `token` is cancelled (e.g. on server shutdown).
+    ///
+    /// Each request receives a child of this token in its
+    /// [`RequestContext`], which is also cancelled if the client disconnects
+    /// or the request deadline passes.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// Closes the connection once it is idle after `token` is cancelled.
+    ///
+    /// A request already being read or handled is answered, with
+    /// `Connection: close`, rather than cancelled.
+    pub fn with_drain(mut self, token: CancellationToken) -> Self {
+        self.drain = token;
+        self
+    }
+
+    /// Limits how long a handler may take to produce a response.
+    ///
+    /// When the deadline passes the request is cancelled and the client
+    /// receives `504 Gateway Timeout`.
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Records the client's address, shown in logs and captures and passed to handlers.
+    pub fn with_peer(mut self, peer: SocketAddr) -> Self {
+        self.peer = Some(peer);
+        self
+    }
+
+    /// Attaches the client's TLS fingerprint to every request and access log line.
+    pub fn with_tls_fingerprint(mut self, fingerprint: Arc<TlsFingerprint>) -> Self {
+        self.fingerprint = Some(fingerprint);
+        self
+    }
+
+    /// Attaches the client's verified certificate to every request.
+    pub fn with_client_cert(mut self, cert: Arc<ClientCertificate>) -> Self {
+        self.client_cert = Some(cert);
+        self
+    }
+
+    /// Writes requests the parser rejects to `capture`.
+    pub fn with_malformed_capture(mut self, capture: Arc<MalformedCapture>) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+
+    /// Streams request bodies over the spool's memory limit to disk, and
+    /// refuses bodies over its size limit with `413 Payload Too Large`.
+    pub fn with_body_spool(mut self, spool: Arc<BodySpool>) -> Self {
+        self.spool = Some(spool);
+        self
+    }
+
+    /// Closes the connection after `max` requests, answering the last one
+    /// with `Connection: close`.
+    pub fn with_max_requests(mut self, max: u64) -> Self {
+        self.max_requests = Some(max);
+        self
+    }
+
+    /// Refuses request heads over these limits with `414 URI Too Long` or
+    /// `431 Request Header Fields Too Large` (defaults apply otherwise).
+    pub fn with_head_limits(mut self, limits: HeadLimits) -> Self {
+        self.parser = RequestParser::new(self.parser.mode(), limits);
+        self
+    }
+
+    /// Sets the `Server` header and scrubs headers on every response written.
+    pub fn with_server_identity(mut self, identity: Arc<ServerIdentity>) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    /// Parses request heads in `mode`, e.g. to accept LF-only line endings
+    /// from legacy clients.
+    pub fn with_parse_mode(mut self, mode: ParseMode) -> Self {
+        self.parser = RequestParser::new(mode, self.parser.limits());
+        self
+    }
+
     /// Runs the connection state machine until the connection closes.
     ///
     /// This function implements the HTTP protocol handling loop, cycling through states:
@@ -171,6 +305,10 @@ impl Connection {
                                 path = %req.path,
                                 "Received HTTP request"
                             );
+                            self.events.emit(Event::RequestStarted {
+                                method: req.method.clone(),
+                                path: req.path.clone(),
+                            });
                             self.state = ConnectionState::Processing(req);
                         }
                         None => {
@@ -180,33 +318,99 @@ impl Connection {
                     }
                 }
 
-                ConnectionState::Processing(req) => {
+                ConnectionState::Processing(mut req) => {
                     tracing::debug!("Connection state: Processing");
-                    // TEMP handler (real routing comes later)
-                    let (response, keep_alive) = self.handle_request(&req).await;
+                    let method = req.method.clone();
+                    let path = req.path.clone();
+                    let keep_alive = req.keep_alive();
+
+                    req.context = RequestContext::new(self.cancel.child_token());
+                    req.context.deadline = self.request_timeout.map(|t| Instant::now() + t);
+                    req.context.peer = self.peer;
+                    req.context.tls_fingerprint = self.fingerprint.clone();
+                    req.context.client_cert = self.client_cert.clone();
+
+                    let mut response = self.dispatch(req).await;
                     let status = response.status.as_u16();
 
+                    self.requests_served += 1;
+                    let limit_reached = self
+                        .max_requests
+                        .is_some_and(|max| self.requests_served >= max);
+                    if (limit_reached || self.drain.is_cancelled())
+                        && response.disposition == Disposition::Send
+                        && response.tunnel.is_none()
+                    {
+                        tracing::debug!(
+                            requests = self.requests_served,
+                            draining = self.drain.is_cancelled(),
+                            "Request limit reached or draining, closing connection"
+                        );
+                        response.disposition = Disposition::SendAndClose;
+                        response
+                            .headers
+                            .retain(|key, _| !key.eq_ignore_ascii_case("connection"));
+                        response
+                            .headers
+                            .insert("Connection".to_string(), "close".to_string());
+                    }
+
                     if let Some(start) = self.request_start.take() {
-                        let duration = start.elapsed();
-                        tracing::info!(
-                            method = ?req.method,
-                            path = %req.path,
-                            status = status,
-                            duration_ms = duration.as_millis(),
-                            "HTTP request completed"
+                        record_request(
+                            &self.events,
+                            &self.metrics,
+                            method,
+                            path,
+                            status,
+                            start.elapsed(),
+                            self.fingerprint.as_deref(),
                         );
                     }
 
                     self.state = ConnectionState::Writing(response, keep_alive);
                 }
 
-                ConnectionState::Writing(response, keep_alive) => {
+                ConnectionState::Writing(mut response, keep_alive) => {
                     tracing::debug!("Connection state: Writing");
+                    if response.disposition == Disposition::Abort {
+                        tracing::debug!("Response aborted, closing connection");
+                        self.state = ConnectionState::Closed;
+                        continue;
+                    }
+                    if self.peer_closed {
+                        tracing::debug!("Client disconnected, discarding response");
+                        self.state = ConnectionState::Closed;
+                        continue;
+                    }
+
+                    self.identity.apply(&mut response);
                     let mut writer = ResponseWriter::new(&response);
                     writer.write_to_stream(&mut self.stream).await?;
                     tracing::debug!("Response written, keep_alive: {}", keep_alive);
 
-                    if keep_alive {
+                    if let Some(mut upstream) = response.tunnel.take() {
+                        // Bytes the client sent after its CONNECT request
+                        if !self.buffer.is_empty() {
+                            upstream.write_all(&self.buffer).await?;
+                            self.buffer.clear();
+                        }
+                        run_tunnel(&mut self.stream, upstream, &self.cancel).await;
+                        self.state = ConnectionState::Closed;
+                        continue;
+                    }
+
+                    if let Some(mut body) = response.stream.take() {
+                        tokio::select! {
+                            result = write_body_stream(&mut self.stream, &mut body) => result?,
+                            _ = self.cancel.cancelled() => {
+                                tracing::debug!("Streamed response cancelled");
+                                self.state = ConnectionState::Closed;
+                                continue;
+                            }
+                        }
+                    }
+
+                    if keep_alive && response.disposition == Disposition::Send {
                         self.state = ConnectionState::Reading; // go back for next request
                     } else {
                         self.state = ConnectionState::Closed;
@@ -215,6 +419,9 @@ impl Connection {
 
                 ConnectionState::Closed => {
                     tracing::debug!("Connection state: Closed");
+                    // Lets TLS streams send close_notify; errors mean the
+                    // client is already gone
+                    let _ = self.stream.shutdown().await;
                     break;
                 }
             }
@@ -223,6 +430,59 @@ impl Connection {
         Ok(())
     }
 
+    /// Runs the handler while watching for client disconnects and the deadline.
+    ///
+    /// Bytes that arrive while the handler runs (pipelined requests) are
+    /// buffered for the next read, up to the head size limit; past that the
+    /// client is left to wait until the response is written. End-of-stream or a read error means the
+    /// client went away, so the request's token is cancelled; the handler is
+    /// still awaited so it can clean up. A passed deadline cancels the token
+    /// and answers `504 Gateway Timeout` without waiting further.
+    async fn dispatch(&mut self, req: Request) -> Response {
+        let token = req.context.cancel.clone();
+        let deadline = req.context.deadline;
+
+        let handler = self.handler.clone();
+        let metrics = self.metrics.clone();
+        let handle = handle_isolated(handler.as_ref(), req, &metrics);
+        tokio::pin!(handle);
+
+        let expired = async {
+            match deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::pin!(expired);
+
+        let max_buffered = self.parser.limits().max_header_bytes;
+        let mut temp = [0u8; 1024];
+        loop {
+            tokio::select! {
+                response = &mut handle => return response,
+
+                _ = &mut expired => {
+                    tracing::warn!("Request deadline exceeded, cancelling");
+                    token.cancel();
+                    return Response::new(StatusCode::GatewayTimeout)
+                        .header("Content-Type", "text/plain")
+                        .body(b"504 Gateway Timeout".to_vec())
+                        .build();
+                }
+
+                read = self.stream.read(&mut temp),
+                    if !self.peer_closed && self.buffer.len() < max_buffered => match read {
+                    Ok(0) | Err(_) => {
+                        tracing::debug!("Client disconnected during request, cancelling");
+                        self.peer_closed = true;
+                        token.cancel();
+                    }
+                    Ok(n) => self.buffer.extend_from_slice(&temp[..n]),
+                },
+            }
+        }
+    }
+
     /// Reads and parses a complete HTTP request from the client.
     ///
     /// This function implements non-blocking request reading with buffering. It continues
@@ -247,8 +507,23 @@ impl Connection {
     /// ```
     pub async fn read_request(&mut self) -> anyhow::Result<Option<Request>> {
         loop {
-            // Try parsing whatever we already have
-            match parse_http_request(&self.buffer) {
+            // Large bodies are refused or spooled once the head is in
+            if let Some(spool) = self.spool.clone()
+                && let Ok(request) = self.parser.head(&self.buffer)
+            {
+                let length = request.content_length() as u64;
+                if spool.too_large(length) || spool.spools(length) {
+                    let (request, head_len) = self.parser.take_head().expect("head was parsed");
+                    if spool.too_large(length) {
+                        return self.refuse_body(&request, length).await;
+                    }
+                    self.buffer.drain(..head_len);
+                    return self.spool_body(&spool, request, length).await.map(Some);
+                }
+            }
+
+            // Parse what has arrived since the last read
+            match self.parser.parse(&self.buffer) {
                 Ok((request, consumed)) => {
                     // Remove consumed bytes
                     self.buffer.drain(..consumed);
@@ -256,18 +531,45 @@ impl Connection {
                 }
 
                 Err(ParseError::Incomplete) => {
+                    // Chunked bodies are decoded in memory, within the
+                    // spool's size limit
+                    let decoded = self.parser.decoded_len() as u64;
+                    if let Some(spool) = &self.spool
+                        && spool.too_large(decoded)
+                    {
+                        let (request, _) = self.parser.take_head().expect("head was parsed");
+                        return self.refuse_body(&request, decoded).await;
+                    }
                     // Need more data → fall through to read
                 }
 
+                Err(
+                    e @ (ParseError::UriTooLong
+                    | ParseError::HeadersTooLarge
+                    | ParseError::InvalidHost
+                    | ParseError::InvalidContentLength
+                    | ParseError::ConflictingLength
+                    | ParseError::UnsupportedTransferEncoding),
+                ) => return self.refuse_head(e).await,
+
                 Err(e) => {
                     // Malformed request → protocol error
+                    if let Some(capture) = &self.capture {
+                        capture.record(self.peer, &e, &self.buffer);
+                    }
                     return Err(anyhow::anyhow!("HTTP parse error: {:?}", e));
                 }
             }
 
-            // Read more data
+            // Read more data, unless idle between requests while draining
             let mut temp = [0u8; 1024];
-            let n = self.stream.read(&mut temp).await?;
+            let n = tokio::select! {
+                n = self.stream.read(&mut temp) => n?,
+                _ = self.drain.cancelled(), if self.buffer.is_empty() => {
+                    tracing::debug!("Draining, closing idle connection");
+                    return Ok(None);
+                }
+            };
 
             if n == 0 {
                 // Client closed connection
@@ -278,117 +580,141 @@ impl Connection {
         }
     }
 
-    /// Handles an HTTP request and generates an appropriate response.
-    ///
-    /// If a proxy handler is configured, requests are forwarded to backend servers.
-    /// Otherwise, serves files from the static files directory.
-    ///
-    /// Supports all HTTP methods (GET, POST, PUT, DELETE, etc.) and will forward them
-    /// appropriately to configured backend servers.
-    ///
-    /// # Arguments
-    ///
-    /// * `req` - The parsed HTTP request to handle
-    ///
-    /// # Returns
-    ///
-    /// A tuple of `(Response, bool)` where:
-    /// - `Response` is the generated HTTP response
-    /// - `bool` indicates whether keep-alive is enabled for this connection
-    ///
-    /// # Example
-    ///
-    /// ```ignore
-    /// let (response, keep_alive) = Connection::handle_request(&request).await;
-    /// if keep_alive {
-    ///     // Can reuse connection for next request
-    /// }
-    /// ```
-    async fn handle_request(&self, req: &Request) -> (Response, bool) {
-        let keep_alive = req.keep_alive();
-
-        // If proxy handler is configured, forward to backend
-        if let Some(ref proxy) = self.proxy_handler {
-            match proxy.forward_request(req).await {
-                Ok(response) => {
-                    tracing::debug!(
-                        status = response.status.as_u16(),
-                        "Proxy response received"
-                    );
-                    return (response, keep_alive);
-                }
-                Err(e) => {
-                    tracing::error!(error = %e, "Proxy error");
-                    // Error responses are already handled in ProxyHandler
-                    // This should not normally be reached
-                    return (Response::internal_error(), keep_alive);
-                }
-            }
+    /// Answers a request whose head is over the limits, lacks a valid
+    /// Host, or frames its body ambiguously or in a way Sentinel can't
+    /// read, then closes
+    async fn refuse_head(&mut self, error: ParseError) -> anyhow::Result<Option<Request>> {
+        tracing::warn!(error = ?error, "Refusing request head");
+        if let Some(capture) = &self.capture {
+            capture.record(self.peer, &error, &self.buffer);
         }
-
-        // Otherwise, serve static files
-        self.serve_static_file(req, keep_alive).await
+        self.metrics
+            .increment("sentinel_request_heads_rejected_total", &[]);
+        let status = match error {
+            ParseError::UriTooLong => StatusCode::UriTooLong,
+            ParseError::HeadersTooLarge => StatusCode::RequestHeaderFieldsTooLarge,
+            ParseError::UnsupportedTransferEncoding => StatusCode::NotImplemented,
+            _ => StatusCode::BadRequest,
+        };
+        let mut response = Response::error(status, "");
+        self.identity.apply(&mut response);
+        ResponseWriter::new(&response)
+            .write_to_stream(&mut self.stream)
+            .await?;
+        Ok(None)
     }
 
-    /// Serves a static file from the configured static files directory
-    async fn serve_static_file(&self, req: &Request, keep_alive: bool) -> (Response, bool) {
-        // Normalize path
-        let mut path = req.path.clone();
-        if path == "/" {
-            path = format!("/{}", self.static_config.index);
-        }
+    /// Answers a request whose body is over the limit, then closes
+    async fn refuse_body(
+        &mut self,
+        request: &Request,
+        length: u64,
+    ) -> anyhow::Result<Option<Request>> {
+        tracing::warn!(
+            method = ?request.method,
+            path = %request.path,
+            length,
+            "Refusing request body over the size limit"
+        );
+        self.metrics
+            .increment("sentinel_request_bodies_rejected_total", &[]);
+        let mut response = Response::error(StatusCode::PayloadTooLarge, "");
+        self.identity.apply(&mut response);
+        ResponseWriter::new(&response)
+            .write_to_stream(&mut self.stream)
+            .await?;
+        Ok(None)
+    }
 
-        // Prevent path traversal
-        if path.contains("..") {
-            let error_body =
-                if let Some(ref error_page) = self.static_config.error_pages.bad_request {
-                    let error_path = self.static_config.root.join(error_page);
-                    fs::read(&error_path)
-                        .await
-                        .unwrap_or_else(|_| b"400 Bad Request".to_vec())
-                } else {
-                    b"400 Bad Request".to_vec()
-                };
-
-            return (
-                ResponseBuilder::new(StatusCode::BadRequest)
-                    .body(error_body)
-                    .build(),
-                keep_alive,
-            );
+    /// Streams the body of `request` into a spool file
+    ///
+    /// The head has been drained from the buffer; body bytes already
+    /// buffered are written first, and anything after the body stays
+    /// buffered for the next request.
+    async fn spool_body(
+        &mut self,
+        spool: &BodySpool,
+        mut request: Request,
+        length: u64,
+    ) -> anyhow::Result<Request> {
+        let mut file = spool.create().await?;
+        let buffered = self.buffer.len().min(length as usize);
+        file.write(&self.buffer[..buffered]).await?;
+        self.buffer.drain(..buffered);
+
+        let mut chunk = vec![0u8; 64 * 1024];
+        while file.len() < length {
+            let want = chunk.len().min((length - file.len()) as usize);
+            let n = self.stream.read(&mut chunk[..want]).await?;
+            if n == 0 {
+                anyhow::bail!("Client closed connection during request body");
+            }
+            file.write(&chunk[..n]).await?;
         }
 
-        let full_path: PathBuf = self.static_config.root.join(&path[1..]);
-
-        match fs::read(&full_path).await {
-            Ok(contents) => {
-                let mime = content_type(&path);
-                let response = ResponseBuilder::new(StatusCode::Ok)
-                    .header("Content-Type", mime)
-                    .body(contents)
-                    .build();
-
-                (response, keep_alive)
-            }
+        tracing::debug!(path = %request.path, length, "Spooled request body to disk");
+        self.metrics
+            .increment("sentinel_request_bodies_spooled_total", &[]);
+        request.spooled = Some(Arc::new(file.finish().await?));
+        Ok(request)
+    }
+}
 
-            Err(_) => {
-                let error_body =
-                    if let Some(ref error_page) = self.static_config.error_pages.not_found {
-                        let error_path = self.static_config.root.join(error_page);
-                        fs::read(&error_path)
-                            .await
-                            .unwrap_or_else(|_| b"404 Not Found".to_vec())
-                    } else {
-                        b"404 Not Found".to_vec()
-                    };
-
-                (
-                    ResponseBuilder::new(StatusCode::NotFound)
-                        .body(error_body)
-                        .build(),
-                    keep_alive,
-                )
-            }
-        }
+/// Copies bytes between a client and a `CONNECT` upstream until either side
+/// closes or `cancel` fires.
+///
+/// Shared by every connection engine once the tunnel response is written.
+pub(crate) async fn run_tunnel<C>(
+    client: &mut C,
+    mut upstream: TcpStream,
+    cancel: &CancellationToken,
+) where
+    C: AsyncRead + AsyncWrite + Unpin + ?Sized,
+{
+    tokio::select! {
+        result = tokio::io::copy_bidirectional(client, &mut upstream) => match result {
+            Ok((sent, received)) => tracing::debug!(sent, received, "Tunnel closed"),
+            Err(e) => tracing::debug!(error = %e, "Tunnel closed with error"),
+        },
+        _ = cancel.cancelled() => tracing::debug!("Tunnel cancelled"),
     }
 }
+
+/// Logs a completed request and publishes its metrics and `RequestFinished` event.
+///
+/// Shared by every connection engine so they report identically.
+pub(crate) fn record_request(
+    events: &Events,
+    metrics: &Metrics,
+    method: Method,
+    path: String,
+    status: u16,
+    duration: Duration,
+    fingerprint: Option<&TlsFingerprint>,
+) {
+    tracing::info!(
+        method = ?method,
+        path = %path,
+        status = status,
+        duration_ms = duration.as_millis(),
+        ja3 = fingerprint.map(|f| f.ja3.as_str()),
+        ja4 = fingerprint.map(|f| f.ja4.as_str()),
+        "HTTP request completed"
+    );
+    let method_label = format!("{:?}", method);
+    metrics.increment(
+        "sentinel_requests_total",
+        &[("method", &method_label), ("status", &status.to_string())],
+    );
+    metrics.histogram(
+        "sentinel_request_duration_seconds",
+        &[("method", &method_label)],
+        duration.as_secs_f64(),
+    );
+    events.emit(Event::RequestFinished {
+        method,
+        path,
+        status,
+        duration,
+    });
+}
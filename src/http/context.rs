@@ -0,0 +1,81 @@
+//! Per-request context
+//!
+//! State that travels with a [`Request`](crate::http::request::Request)
+//! through middleware and handlers but is not part of the HTTP message.
+
+use crate::tls::{ClientCertificate, TlsFingerprint};
+pub use ::http::Extensions;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+/// Out-of-band state attached to every request
+///
+/// # Cancellation
+///
+/// The token is a child of the connection's token, which is in turn a child
+/// of the server's shutdown token. It is cancelled when:
+///
+/// - the server shuts down
+/// - the client disconnects while the request is being handled
+/// - the request deadline (if any) passes
+///
+/// Handlers that spawn work or wait on I/O should select on
+/// [`RequestContext::cancelled`] and stop promptly.
+///
+/// # Extensions
+///
+/// Middleware attaches what it learns about a request (an authenticated
+/// identity, the matched route, a rate-limit decision) to `extensions`,
+/// keyed by type, for later stages and logging to read:
+///
+/// ```
+/// # use sentinel::http::context::RequestContext;
+/// #[derive(Clone)]
+/// struct Tenant(String);
+///
+/// let mut context = RequestContext::default();
+/// context.extensions.insert(Tenant("acme".to_string()));
+/// assert_eq!(context.extensions.get::<Tenant>().unwrap().0, "acme");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RequestContext {
+    /// Cancelled when the request should be abandoned
+    pub cancel: CancellationToken,
+    /// Time by which the response must be produced, if limited
+    pub deadline: Option<Instant>,
+    /// Address of the client connection, if known
+    pub peer: Option<SocketAddr>,
+    /// JA3/JA4 fingerprint of the client, on TLS connections
+    pub tls_fingerprint: Option<Arc<TlsFingerprint>>,
+    /// Certificate the client presented and the listener verified, with
+    /// mutual TLS
+    pub client_cert: Option<Arc<ClientCertificate>>,
+    /// Values attached by middleware, one per type
+    pub extensions: Extensions,
+}
+
+impl RequestContext {
+    /// Creates a context with the given cancellation token and no deadline.
+    pub fn new(cancel: CancellationToken) -> Self {
+        Self {
+            cancel,
+            deadline: None,
+            peer: None,
+            tls_fingerprint: None,
+            client_cert: None,
+            extensions: Extensions::new(),
+        }
+    }
+
+    /// Returns `true` once the request has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    /// Completes when the request is cancelled.
+    pub async fn cancelled(&self) {
+        self.cancel.cancelled().await
+    }
+}
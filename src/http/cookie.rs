@@ -0,0 +1,209 @@
+//! Cookie parsing and `Set-Cookie` values
+//!
+//! [`parse_cookies`] reads a request's `Cookie` header (RFC 6265, section
+//! 5.4) and [`Cookie`] renders a `Set-Cookie` value with its attributes.
+//!
+//! Response headers hold one value per name, so a response setting several
+//! cookies keeps them in one `Set-Cookie` entry separated by newlines (see
+//! [`ResponseBuilder::cookie`](crate::http::response::ResponseBuilder::cookie)).
+//! Both engines write each of them as its own header line.
+
+use std::fmt;
+use std::time::Duration;
+
+/// Separates cookies sharing a response's `Set-Cookie` header entry
+pub const SET_COOKIE_SEPARATOR: char = '\n';
+
+/// `SameSite` attribute of a cookie
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    /// Sent only with same-site requests
+    Strict,
+    /// Also sent when following a link from another site
+    Lax,
+    /// Sent with cross-site requests too (browsers require `Secure`)
+    None,
+}
+
+impl SameSite {
+    fn as_str(self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+/// A cookie to set on the client, rendered as a `Set-Cookie` value
+///
+/// # Example
+///
+/// ```
+/// # use sentinel::http::cookie::{Cookie, SameSite};
+/// # use std::time::Duration;
+/// let cookie = Cookie::new("session", "abc123")
+///     .path("/")
+///     .max_age(Duration::from_secs(3600))
+///     .http_only()
+///     .same_site(SameSite::Lax);
+/// assert_eq!(
+///     cookie.to_string(),
+///     "session=abc123; Path=/; Max-Age=3600; HttpOnly; SameSite=Lax"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<Duration>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    /// Create a session cookie without attributes
+    ///
+    /// Characters a cookie name or value may not contain (whitespace,
+    /// control characters, `;`, `,`, `"`, `\`, and `=` in the name) are
+    /// dropped rather than let split the header.
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+            path: None,
+            domain: None,
+            max_age: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    /// A cookie telling the client to delete `name` (empty, `Max-Age=0`)
+    ///
+    /// The path must match the one the cookie was set with.
+    pub fn removal(name: impl Into<String>) -> Self {
+        Self::new(name, "").max_age(Duration::ZERO)
+    }
+
+    /// Only send the cookie for paths under `path`
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Also send the cookie to subdomains of `domain`
+    pub fn domain(mut self, domain: impl Into<String>) -> Self {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    /// Expire the cookie after `max_age` (whole seconds); session cookies
+    /// last until the browser closes
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Only send the cookie over HTTPS
+    pub fn secure(mut self) -> Self {
+        self.secure = true;
+        self
+    }
+
+    /// Hide the cookie from scripts
+    pub fn http_only(mut self) -> Self {
+        self.http_only = true;
+        self
+    }
+
+    /// Limit when the cookie is sent with cross-site requests
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    /// The cookie's name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The cookie's value
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name: String = self
+            .name
+            .chars()
+            .filter(|&c| cookie_octet(c) && c != '=')
+            .collect();
+        let value: String = self.value.chars().filter(|&c| cookie_octet(c)).collect();
+        write!(f, "{}={}", name, value)?;
+
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", attribute(path))?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", attribute(domain))?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if self.secure {
+            f.write_str("; Secure")?;
+        }
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+/// Name and value pairs of a `Cookie` header, in the order sent
+///
+/// Pairs without `=` are skipped and a value's surrounding double quotes
+/// are removed.
+///
+/// # Example
+///
+/// ```
+/// # use sentinel::http::cookie::parse_cookies;
+/// let cookies: Vec<_> = parse_cookies("session=abc; theme=\"dark\"; flag").collect();
+/// assert_eq!(cookies, [("session", "abc"), ("theme", "dark")]);
+/// ```
+pub fn parse_cookies(header: &str) -> impl Iterator<Item = (&str, &str)> {
+    header.split(';').filter_map(|pair| {
+        let (name, value) = pair.split_once('=')?;
+        let name = name.trim();
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(value);
+        (!name.is_empty()).then_some((name, value))
+    })
+}
+
+/// Whether `c` may appear in a cookie value (RFC 6265, section 4.1.1)
+fn cookie_octet(c: char) -> bool {
+    c.is_ascii_graphic() && !matches!(c, '"' | ',' | ';' | '\\')
+}
+
+/// An attribute value with characters that would end it removed
+fn attribute(value: &str) -> String {
+    value
+        .chars()
+        .filter(|&c| !c.is_ascii_control() && c != ';')
+        .collect()
+}
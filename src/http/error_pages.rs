@@ -0,0 +1,374 @@
+//! Error responses
+//!
+//! Errors Sentinel generates itself (missing static files, unreachable
+//! backends, timeouts) are rendered here before they reach the client. An
+//! [`ErrorPageRenderer`] holds an optional template per status code; errors
+//! without one get a body in the format the client's `Accept` header
+//! prefers: JSON for API clients, HTML for browsers, plain text otherwise.
+//! Every format carries the same fields: status, error code, message, and
+//! request ID. Responses relayed from a backend are left untouched unless
+//! their route intercepts backend errors.
+//!
+//! Templates may reference these variables:
+//!
+//! - `$status`: the numeric status code
+//! - `$reason`: the reason phrase
+//! - `$request_id`: the request's `X-Request-Id`, or a generated one
+//! - `$path`: the request path, HTML-escaped
+//!
+//! # Example
+//!
+//! ```yaml
+//! static_files:
+//!   error_pages:
+//!     pages:
+//!       404: errors/404.html
+//!       502: errors/502.html
+//! ```
+//!
+//! An API client asking for JSON gets:
+//!
+//! ```text
+//! {"error":"bad_gateway","message":"Failed to connect to backend server.","request_id":"5f0c...","status":502}
+//! ```
+
+use crate::config::{ErrorPages, InterceptErrors};
+use crate::http::handler::Handler;
+use crate::http::mime::content_type;
+use crate::http::request::Request;
+use crate::http::response::Response;
+use async_trait::async_trait;
+use rand::Rng;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Header carrying the request ID shown on error pages
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Headers describing a backend body, dropped when the body is replaced
+const BODY_HEADERS: &[&str] = &[
+    "Content-Type",
+    "Content-Length",
+    "Content-Encoding",
+    "Content-Range",
+    "Transfer-Encoding",
+    "ETag",
+    "Last-Modified",
+];
+
+/// Body format for an error without a template
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// `application/json`
+    Json,
+    /// `text/html`
+    Html,
+    /// `text/plain`
+    Text,
+}
+
+impl ErrorFormat {
+    /// The format an `Accept` header prefers, plain text if it names none
+    /// of them
+    ///
+    /// Media ranges are weighed by their `q` parameter; on a tie the one
+    /// listed first wins. `*/*` alone selects plain text.
+    pub fn negotiate(accept: Option<&str>) -> Self {
+        let mut best = (ErrorFormat::Text, 0.0);
+        for range in accept.unwrap_or_default().split(',') {
+            let mut params = range.split(';');
+            let media = params
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase();
+            let format = if media == "application/json" || media.ends_with("+json") {
+                ErrorFormat::Json
+            } else if media == "text/html" || media == "application/xhtml+xml" {
+                ErrorFormat::Html
+            } else if media == "text/plain" {
+                ErrorFormat::Text
+            } else {
+                continue;
+            };
+            let q = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if q > best.1 {
+                best = (format, q);
+            }
+        }
+        best.0
+    }
+}
+
+/// What error pages need to know about the request that failed
+#[derive(Debug, Clone)]
+pub struct ErrorContext {
+    /// Request path
+    pub path: String,
+    /// The client's `X-Request-Id`, or a generated one
+    pub request_id: String,
+    /// Preferred body format
+    pub format: ErrorFormat,
+}
+
+impl ErrorContext {
+    /// Context for `req`, generating a request ID if it has none
+    pub fn from_request(req: &Request) -> Self {
+        let header = |name: &str| {
+            req.headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.trim())
+        };
+        Self {
+            path: req.path.clone(),
+            request_id: header(REQUEST_ID_HEADER)
+                .filter(|id| !id.is_empty())
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("{:016x}", rand::rng().random::<u64>())),
+            format: ErrorFormat::negotiate(header("Accept")),
+        }
+    }
+}
+
+struct Template {
+    content_type: String,
+    body: String,
+}
+
+/// Error page templates, keyed by status code
+#[derive(Default)]
+pub struct ErrorPageRenderer {
+    templates: HashMap<u16, Template>,
+}
+
+impl ErrorPageRenderer {
+    /// Create a renderer with no templates
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the configured templates from `root`
+    ///
+    /// A template that cannot be read is logged and skipped, leaving the
+    /// negotiated body for that status.
+    pub fn load(root: &Path, pages: &ErrorPages) -> Self {
+        let mut renderer = Self::new();
+        for (status, page) in pages.templates() {
+            let path = root.join(&page);
+            match std::fs::read_to_string(&path) {
+                Ok(body) => renderer = renderer.with_template(status, content_type(&page), body),
+                Err(e) => tracing::warn!(
+                    status,
+                    path = %path.display(),
+                    error = %e,
+                    "Failed to load error page"
+                ),
+            }
+        }
+        renderer
+    }
+
+    /// Render `status` with `template`, served as `content_type`
+    pub fn with_template(
+        mut self,
+        status: u16,
+        content_type: impl Into<String>,
+        template: impl Into<String>,
+    ) -> Self {
+        self.templates.insert(
+            status,
+            Template {
+                content_type: content_type.into(),
+                body: template.into(),
+            },
+        );
+        self
+    }
+
+    /// Render the body of a generated error response
+    ///
+    /// The status's template is used if there is one, otherwise a body in
+    /// the client's preferred format. Returns false, leaving the response
+    /// alone, if it was relayed from a backend or is not an error.
+    pub fn apply(&self, ctx: &ErrorContext, response: &mut Response) -> bool {
+        let status = response.status.as_u16();
+        if !response.generated || status < 400 {
+            return false;
+        }
+
+        let (content_type, body) = match self.templates.get(&status) {
+            Some(template) => (
+                template.content_type.clone(),
+                template
+                    .body
+                    .replace("$status", &status.to_string())
+                    .replace("$reason", response.status.reason_phrase())
+                    .replace("$request_id", &ctx.request_id)
+                    .replace("$path", &escape_html(&ctx.path)),
+            ),
+            None => negotiated_body(ctx, response),
+        };
+
+        response
+            .headers
+            .insert("Content-Type".to_string(), content_type);
+        response
+            .headers
+            .insert("Content-Length".to_string(), body.len().to_string());
+        response
+            .headers
+            .insert(REQUEST_ID_HEADER.to_string(), ctx.request_id.clone());
+        response.body = body.into_bytes();
+        true
+    }
+
+    /// Replace the body of a backend error response with its error page
+    ///
+    /// The status and headers unrelated to the body are kept; the backend
+    /// body never reaches the client.
+    pub fn intercept(&self, ctx: &ErrorContext, response: &mut Response) {
+        response
+            .headers
+            .retain(|name, _| !BODY_HEADERS.iter().any(|h| name.eq_ignore_ascii_case(h)));
+        response.generated = true;
+        response.detail.clear();
+        self.apply(ctx, response);
+    }
+}
+
+/// Handler decorator that renders the body of generated errors
+pub struct ErrorPageHandler {
+    inner: Arc<dyn Handler>,
+    renderer: Arc<ErrorPageRenderer>,
+    intercepts: Vec<InterceptErrors>,
+}
+
+impl ErrorPageHandler {
+    /// Wrap `inner`, rendering its generated errors with `renderer`
+    pub fn new(inner: impl Handler, renderer: Arc<ErrorPageRenderer>) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            renderer,
+            intercepts: Vec::new(),
+        }
+    }
+
+    /// Also replace the body of backend errors on these routes
+    ///
+    /// When several rules match a request, the longest path prefix wins.
+    pub fn with_intercepts(mut self, intercepts: Vec<InterceptErrors>) -> Self {
+        self.intercepts = intercepts;
+        self
+    }
+
+    /// Lowest backend status intercepted for `path`, if any
+    fn intercept_threshold(&self, path: &str) -> Option<u16> {
+        self.intercepts
+            .iter()
+            .filter(|rule| path.starts_with(&rule.path_prefix))
+            .max_by_key(|rule| rule.path_prefix.len())
+            .map(|rule| rule.min_status)
+    }
+}
+
+#[async_trait]
+impl Handler for ErrorPageHandler {
+    async fn handle(&self, req: Request) -> Response {
+        let threshold = self.intercept_threshold(&req.path);
+        let ctx = ErrorContext::from_request(&req);
+        let mut response = self.inner.handle(req).await;
+        let status = response.status.as_u16();
+        if !response.generated
+            && response.tunnel.is_none()
+            && threshold.is_some_and(|min| status >= min)
+        {
+            tracing::debug!(status, path = %ctx.path, "Intercepted backend error");
+            self.renderer.intercept(&ctx, &mut response);
+        } else {
+            self.renderer.apply(&ctx, &mut response);
+        }
+        response
+    }
+}
+
+/// Content type and body for an error in the client's preferred format
+fn negotiated_body(ctx: &ErrorContext, response: &Response) -> (String, String) {
+    let status = response.status.as_u16();
+    let reason = response.status.reason_phrase();
+    let message = if response.detail.is_empty() {
+        reason
+    } else {
+        response.detail.as_str()
+    };
+
+    match ctx.format {
+        ErrorFormat::Json => {
+            let mut body = serde_json::json!({
+                "status": status,
+                "error": error_code(reason),
+                "message": message,
+                "request_id": ctx.request_id,
+            });
+            let retry_after = response
+                .headers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case("Retry-After"))
+                .and_then(|(_, value)| value.trim().parse::<u64>().ok());
+            if let Some(secs) = retry_after {
+                body["retry_after"] = secs.into();
+            }
+            ("application/json".to_string(), body.to_string())
+        }
+        ErrorFormat::Html => (
+            "text/html; charset=utf-8".to_string(),
+            format!(
+                "<!DOCTYPE html>\n<html>\n<head><title>{status} {reason}</title></head>\n\
+                 <body>\n<h1>{status} {reason}</h1>\n<p>{}</p>\n<p>Request ID: {}</p>\n</body>\n</html>\n",
+                escape_html(message),
+                escape_html(&ctx.request_id),
+            ),
+        ),
+        ErrorFormat::Text => (
+            "text/plain; charset=utf-8".to_string(),
+            format!(
+                "{status} {reason}\r\n\r\n{message}\r\n\r\nRequest ID: {}",
+                ctx.request_id
+            ),
+        ),
+    }
+}
+
+/// Machine-readable error code for a reason phrase (`Bad Gateway` is
+/// `bad_gateway`)
+fn error_code(reason: &str) -> String {
+    reason
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,93 @@
+//! Trusted proxies and the scheme a request was made with
+//!
+//! `X-Forwarded-Proto` is only believed on connections from an address in
+//! `server.trusted_proxies`. Anyone else could use it to claim a plain
+//! request was made over HTTPS (skipping an `http` to `https` redirect) or
+//! that a TLS request was not (suppressing HSTS), so their requests get the
+//! listener's own scheme.
+//!
+//! # Example
+//!
+//! ```yaml
+//! server:
+//!   listen_addr: "0.0.0.0:8080"
+//!   trusted_proxies: ["10.0.0.0/8", "127.0.0.1"]
+//! ```
+
+use crate::http::request::Request;
+use anyhow::Context;
+use std::net::IpAddr;
+
+/// Addresses and CIDR ranges whose forwarding headers are believed
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    ranges: Vec<(IpAddr, u8)>,
+}
+
+impl TrustedProxies {
+    /// Parse addresses (`10.0.0.1`) and CIDR ranges (`10.0.0.0/8`,
+    /// `fd00::/8`)
+    pub fn new(entries: &[String]) -> anyhow::Result<Self> {
+        let ranges = entries
+            .iter()
+            .map(|entry| {
+                parse_range(entry).with_context(|| format!("Invalid trusted proxy {:?}", entry))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self { ranges })
+    }
+
+    /// Whether requests from `addr` may set forwarding headers
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        let addr = addr.to_canonical();
+        self.ranges
+            .iter()
+            .any(|&(network, prefix)| in_range(addr, network, prefix))
+    }
+
+    /// The scheme `req` was made with: `X-Forwarded-Proto` from a trusted
+    /// proxy, otherwise `https` on a `tls` listener and `http` elsewhere
+    pub fn scheme<'a>(&self, req: &'a Request, tls: bool) -> &'a str {
+        let trusted = req
+            .context
+            .peer
+            .is_some_and(|peer| self.contains(peer.ip()));
+        match req.header("X-Forwarded-Proto") {
+            Some(proto) if trusted => proto.trim(),
+            _ if tls => "https",
+            _ => "http",
+        }
+    }
+}
+
+fn parse_range(entry: &str) -> anyhow::Result<(IpAddr, u8)> {
+    let (addr, prefix) = match entry.trim().split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (entry.trim(), None),
+    };
+    let addr: IpAddr = addr.parse().context("not an IP address")?;
+    let addr = addr.to_canonical();
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(prefix) => prefix.parse().context("bad prefix length")?,
+        None => max,
+    };
+    if prefix > max {
+        anyhow::bail!("prefix length {} is over {}", prefix, max);
+    }
+    Ok((addr, prefix))
+}
+
+fn in_range(addr: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    match (addr, network) {
+        (IpAddr::V4(addr), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+            u32::from(addr) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(addr), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+            u128::from(addr) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,140 @@
+//! Request handlers
+//!
+//! A [`Handler`] turns a [`Request`] into a [`Response`]. Static file serving,
+//! reverse proxying, and routing are all handlers, so library users can plug
+//! their own endpoints into the same pipeline.
+
+use crate::http::request::Request;
+use crate::http::response::{Response, StatusCode};
+use crate::metrics::Metrics;
+use async_trait::async_trait;
+use std::any::Any;
+use std::future::Future;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// An asynchronous request handler.
+///
+/// # Example
+///
+/// ```ignore
+/// use sentinel::http::handler::Handler;
+///
+/// struct FeatureFlags;
+///
+/// #[async_trait::async_trait]
+/// impl Handler for FeatureFlags {
+///     async fn handle(&self, _req: Request) -> Response {
+///         Response::ok(br#"{"new_ui": true}"#.to_vec())
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait Handler: Send + Sync + 'static {
+    /// Handles a request and produces the response to send to the client.
+    async fn handle(&self, req: Request) -> Response;
+}
+
+#[async_trait]
+impl<H: Handler + ?Sized> Handler for Arc<H> {
+    async fn handle(&self, req: Request) -> Response {
+        (**self).handle(req).await
+    }
+}
+
+#[async_trait]
+impl<H: Handler + ?Sized> Handler for Box<H> {
+    async fn handle(&self, req: Request) -> Response {
+        (**self).handle(req).await
+    }
+}
+
+/// A handler backed by an async closure. Created with [`handler_fn`].
+pub struct HandlerFn<F> {
+    f: F,
+}
+
+/// Wraps an async closure as a [`Handler`].
+///
+/// # Example
+///
+/// ```
+/// # use sentinel::http::handler::handler_fn;
+/// # use sentinel::http::response::Response;
+/// let ping = handler_fn(|_req| async { Response::ok(b"pong".to_vec()) });
+/// ```
+pub fn handler_fn<F, Fut>(f: F) -> HandlerFn<F>
+where
+    F: Fn(Request) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Response> + Send + 'static,
+{
+    HandlerFn { f }
+}
+
+#[async_trait]
+impl<F, Fut> Handler for HandlerFn<F>
+where
+    F: Fn(Request) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Response> + Send + 'static,
+{
+    async fn handle(&self, req: Request) -> Response {
+        (self.f)(req).await
+    }
+}
+
+/// Runs `handler`, answering `500 Internal Server Error` if it panics.
+///
+/// The panic is logged with the request's method and path and counted in
+/// `sentinel_panics_total`, and the connection goes on serving requests.
+pub async fn handle_isolated(handler: &dyn Handler, req: Request, metrics: &Metrics) -> Response {
+    let method = req.method.clone();
+    let path = req.path.clone();
+    let _in_flight = metrics.in_flight("sentinel_requests_in_flight", &[]);
+
+    match (CatchPanic {
+        inner: handler.handle(req),
+    })
+    .await
+    {
+        Ok(response) => response,
+        Err(payload) => {
+            tracing::error!(
+                method = ?method,
+                path = %path,
+                panic = panic_message(payload.as_ref()),
+                "Handler panicked"
+            );
+            metrics.increment("sentinel_panics_total", &[]);
+            Response::error(StatusCode::InternalServerError, "")
+        }
+    }
+}
+
+/// Future that turns a panic while polling `inner` into an error
+struct CatchPanic<F> {
+    inner: F,
+}
+
+impl<F: Future + Unpin> Future for CatchPanic<F> {
+    type Output = Result<F::Output, Box<dyn Any + Send>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match catch_unwind(AssertUnwindSafe(|| Pin::new(&mut self.inner).poll(cx))) {
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(payload) => Poll::Ready(Err(payload)),
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "non-string panic payload"
+    }
+}
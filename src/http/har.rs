@@ -0,0 +1,552 @@
+//! HAR (HTTP Archive) traffic capture
+//!
+//! [`HarRecorder`] turns sampled requests and their responses into HAR 1.2
+//! entries and writes them to files that browsers, proxies and load
+//! testing tools can open. Secrets never reach the files: configured
+//! headers are redacted, and so are configured fields of query strings and
+//! JSON or form bodies.
+//!
+//! Entries are buffered in memory and written as a new file of up to
+//! `entries_per_file` entries, named `sentinel-<unix_ms>-<seq>.har`. Files
+//! appear atomically (written then renamed), so a collector can pick up
+//! every `*.har` in the directory.
+//!
+//! The model deserializes leniently, so HAR files exported by browsers can
+//! be read back too (see [`crate::proxy::replay`]).
+
+use crate::config::TrafficCaptureConfig;
+use crate::http::request::Request;
+use crate::http::response::Response;
+use crate::http::util::civil_from_days;
+use anyhow::Context;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio_util::sync::CancellationToken;
+
+/// Replaces the values of redacted headers and fields
+pub const REDACTED: &str = "[REDACTED]";
+
+/// A HAR document
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Har {
+    pub log: HarLog,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HarLog {
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub creator: HarCreator,
+    #[serde(default)]
+    pub entries: Vec<HarEntry>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HarCreator {
+    pub name: String,
+    pub version: String,
+}
+
+/// One request and its response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarEntry {
+    /// ISO 8601 time the request started
+    pub started_date_time: String,
+    /// Total time taken, in milliseconds
+    pub time: f64,
+    pub request: HarRequest,
+    pub response: HarResponse,
+    #[serde(default)]
+    pub cache: Value,
+    #[serde(default)]
+    pub timings: HarTimings,
+    /// Address of the client that sent the request (Sentinel extension)
+    #[serde(
+        rename = "_clientAddress",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub client_address: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarRequest {
+    pub method: String,
+    pub url: String,
+    #[serde(default)]
+    pub http_version: String,
+    #[serde(default)]
+    pub cookies: Vec<Value>,
+    #[serde(default)]
+    pub headers: Vec<HarHeader>,
+    #[serde(default)]
+    pub query_string: Vec<HarHeader>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_data: Option<HarPostData>,
+    #[serde(default = "unknown_size")]
+    pub headers_size: i64,
+    #[serde(default = "unknown_size")]
+    pub body_size: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarResponse {
+    pub status: u16,
+    #[serde(default)]
+    pub status_text: String,
+    #[serde(default)]
+    pub http_version: String,
+    #[serde(default)]
+    pub cookies: Vec<Value>,
+    #[serde(default)]
+    pub headers: Vec<HarHeader>,
+    #[serde(default)]
+    pub content: HarContent,
+    #[serde(rename = "redirectURL", default)]
+    pub redirect_url: String,
+    #[serde(default = "unknown_size")]
+    pub headers_size: i64,
+    #[serde(default = "unknown_size")]
+    pub body_size: i64,
+}
+
+/// A header, query parameter or cookie
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HarHeader {
+    pub name: String,
+    pub value: String,
+}
+
+/// A request body
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarPostData {
+    #[serde(default)]
+    pub mime_type: String,
+    #[serde(default)]
+    pub text: String,
+    /// `base64` for binary bodies (Sentinel extension; HAR 1.2 only
+    /// defines this on response content)
+    #[serde(rename = "_encoding", default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+impl HarPostData {
+    /// The body bytes, decoding base64 if needed
+    pub fn bytes(&self) -> anyhow::Result<Vec<u8>> {
+        match self.encoding.as_deref() {
+            Some("base64") => STANDARD
+                .decode(&self.text)
+                .context("Invalid base64 request body"),
+            _ => Ok(self.text.as_bytes().to_vec()),
+        }
+    }
+}
+
+/// A response body
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarContent {
+    pub size: i64,
+    #[serde(default)]
+    pub mime_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HarTimings {
+    pub send: f64,
+    pub wait: f64,
+    pub receive: f64,
+}
+
+fn unknown_size() -> i64 {
+    -1
+}
+
+impl Har {
+    /// Wrap `entries` in a HAR log created by Sentinel
+    pub fn new(entries: Vec<HarEntry>) -> Self {
+        Self {
+            log: HarLog {
+                version: "1.2".to_string(),
+                creator: HarCreator {
+                    name: "sentinel".to_string(),
+                    version: env!("CARGO_PKG_VERSION").to_string(),
+                },
+                entries,
+            },
+        }
+    }
+
+    /// Read a HAR file
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let data =
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_slice(&data)
+            .with_context(|| format!("Invalid HAR file {}", path.display()))
+    }
+}
+
+/// Records sampled traffic into HAR files
+pub struct HarRecorder {
+    config: TrafficCaptureConfig,
+    entries: Mutex<Vec<HarEntry>>,
+    /// Distinguishes files written in the same millisecond
+    sequence: AtomicU64,
+}
+
+impl HarRecorder {
+    /// Create the capture directory if needed
+    pub fn new(config: &TrafficCaptureConfig) -> anyhow::Result<Self> {
+        config.validate()?;
+        std::fs::create_dir_all(&config.dir).with_context(|| {
+            format!(
+                "Failed to create capture directory {}",
+                config.dir.display()
+            )
+        })?;
+        Ok(Self {
+            config: config.clone(),
+            entries: Mutex::new(Vec::new()),
+            sequence: AtomicU64::new(0),
+        })
+    }
+
+    /// Whether to record the next request
+    pub fn sampled(&self) -> bool {
+        let percent = self.config.sample_percent;
+        percent >= 100 || rand::rng().random_range(0..100) < percent
+    }
+
+    /// Describe `req` as it arrived
+    ///
+    /// Call before handing the request on; `scheme` is that of the listener.
+    pub fn request(&self, req: &Request, scheme: &str) -> HarRequest {
+        let host = req.header("Host").unwrap_or("localhost");
+        let (path, query) = match req.path.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (req.path.as_str(), None),
+        };
+        let query_string: Vec<HarHeader> = query
+            .map(|query| {
+                form_pairs(query)
+                    .map(|(name, value)| HarHeader {
+                        value: if self.redacts_field(&name) {
+                            REDACTED.to_string()
+                        } else {
+                            value
+                        },
+                        name,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let url = match query {
+            // Rebuilt only when needed, to keep the client's encoding
+            Some(_) if query_string.iter().any(|p| p.value == REDACTED) => format!(
+                "{}://{}{}?{}",
+                scheme,
+                host,
+                path,
+                query_string
+                    .iter()
+                    .map(|p| format!("{}={}", encode(&p.name), encode(&p.value)))
+                    .collect::<Vec<_>>()
+                    .join("&")
+            ),
+            _ => format!("{}://{}{}", scheme, host, req.path),
+        };
+
+        let mime_type = req.header("Content-Type").unwrap_or_default().to_string();
+        let post_data = match &req.spooled {
+            // Spooled bodies are not read back, so replay skips them
+            Some(spooled) if self.config.bodies => Some(HarPostData {
+                mime_type: mime_type.clone(),
+                text: String::new(),
+                encoding: None,
+                comment: Some(format!("truncated from {} bytes", spooled.len())),
+            }),
+            _ => (self.config.bodies && !req.body.is_empty()).then(|| {
+                let (text, encoding, comment) = self.body(&req.body, &mime_type);
+                HarPostData {
+                    mime_type: mime_type.clone(),
+                    text,
+                    encoding,
+                    comment,
+                }
+            }),
+        };
+        HarRequest {
+            method: format!("{:?}", req.method),
+            url,
+            http_version: req.version.clone(),
+            cookies: Vec::new(),
+            headers: self.headers(req.headers.iter()),
+            query_string,
+            post_data,
+            headers_size: -1,
+            body_size: req.body_len() as i64,
+        }
+    }
+
+    /// Describe `response` as it is about to be sent
+    pub fn response(&self, response: &Response) -> HarResponse {
+        let mime_type = response
+            .headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Content-Type"))
+            .map(|(_, value)| value.clone())
+            .unwrap_or_default();
+        let mut content = HarContent {
+            size: response.body.len() as i64,
+            mime_type,
+            ..HarContent::default()
+        };
+        if self.config.bodies && !response.body.is_empty() {
+            let (text, encoding, comment) = self.body(&response.body, &content.mime_type);
+            content.text = Some(text);
+            content.encoding = encoding;
+            content.comment = comment;
+        }
+        HarResponse {
+            status: response.status.as_u16(),
+            status_text: response.status.reason_phrase().to_string(),
+            http_version: "HTTP/1.1".to_string(),
+            cookies: Vec::new(),
+            headers: self.headers(response.headers.iter()),
+            redirect_url: response
+                .headers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case("Location"))
+                .map(|(_, value)| value.clone())
+                .unwrap_or_default(),
+            content,
+            headers_size: -1,
+            body_size: response.body.len() as i64,
+        }
+    }
+
+    /// Buffer an exchange, writing a file once enough are buffered
+    pub fn record(
+        &self,
+        started: SystemTime,
+        elapsed: Duration,
+        client: Option<String>,
+        request: HarRequest,
+        response: HarResponse,
+    ) {
+        let time = elapsed.as_secs_f64() * 1000.0;
+        let entry = HarEntry {
+            started_date_time: iso8601(started),
+            time,
+            request,
+            response,
+            cache: Value::Object(Default::default()),
+            timings: HarTimings {
+                send: 0.0,
+                wait: time,
+                receive: 0.0,
+            },
+            client_address: client,
+        };
+
+        let full = {
+            let mut entries = self.entries.lock().unwrap();
+            entries.push(entry);
+            (entries.len() >= self.config.entries_per_file).then(|| std::mem::take(&mut *entries))
+        };
+        if let Some(entries) = full
+            && let Err(e) = self.write(entries)
+        {
+            tracing::warn!(error = %e, "Failed to write traffic capture");
+        }
+    }
+
+    /// Write buffered entries to a new file, if there are any
+    pub fn flush(&self) -> anyhow::Result<Option<PathBuf>> {
+        let entries = std::mem::take(&mut *self.entries.lock().unwrap());
+        if entries.is_empty() {
+            return Ok(None);
+        }
+        self.write(entries).map(Some)
+    }
+
+    /// Flush every `flush_secs` until `cancel`, then flush once more
+    pub async fn run(self: Arc<Self>, cancel: CancellationToken) {
+        let mut interval = tokio::time::interval(Duration::from_secs(self.config.flush_secs));
+        interval.tick().await;
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = cancel.cancelled() => break,
+            }
+            if let Err(e) = self.flush() {
+                tracing::warn!(error = %e, "Failed to write traffic capture");
+            }
+        }
+        if let Err(e) = self.flush() {
+            tracing::warn!(error = %e, "Failed to write traffic capture");
+        }
+    }
+
+    fn write(&self, entries: Vec<HarEntry>) -> anyhow::Result<PathBuf> {
+        let count = entries.len();
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        let path = self
+            .config
+            .dir
+            .join(format!("sentinel-{}-{}.har", millis, sequence));
+        let partial = path.with_extension("har.tmp");
+
+        let data = serde_json::to_vec(&Har::new(entries))?;
+        std::fs::write(&partial, data)
+            .with_context(|| format!("Failed to write {}", partial.display()))?;
+        std::fs::rename(&partial, &path)
+            .with_context(|| format!("Failed to rename {}", partial.display()))?;
+        tracing::debug!(path = %path.display(), entries = count, "Wrote traffic capture");
+        Ok(path)
+    }
+
+    fn headers<'a>(
+        &self,
+        headers: impl Iterator<Item = (&'a String, &'a String)>,
+    ) -> Vec<HarHeader> {
+        let mut headers: Vec<HarHeader> = headers
+            .map(|(name, value)| HarHeader {
+                name: name.clone(),
+                value: if self
+                    .config
+                    .redact_headers
+                    .iter()
+                    .any(|h| h.eq_ignore_ascii_case(name))
+                {
+                    REDACTED.to_string()
+                } else {
+                    value.clone()
+                },
+            })
+            .collect();
+        headers.sort_by(|a, b| a.name.cmp(&b.name));
+        headers
+    }
+
+    fn redacts_field(&self, name: &str) -> bool {
+        self.config
+            .redact_fields
+            .iter()
+            .any(|f| f.eq_ignore_ascii_case(name))
+    }
+
+    /// Body text, encoding and truncation note, after redaction
+    fn body(&self, body: &[u8], mime_type: &str) -> (String, Option<String>, Option<String>) {
+        let mut body = self.redact_body(body, mime_type);
+        let comment = (body.len() > self.config.max_body_bytes)
+            .then(|| format!("truncated from {} bytes", body.len()));
+        body.truncate(self.config.max_body_bytes);
+        match String::from_utf8(body) {
+            Ok(text) => (text, None, comment),
+            Err(e) => (
+                STANDARD.encode(e.as_bytes()),
+                Some("base64".to_string()),
+                comment,
+            ),
+        }
+    }
+
+    fn redact_body(&self, body: &[u8], mime_type: &str) -> Vec<u8> {
+        if self.config.redact_fields.is_empty() {
+            return body.to_vec();
+        }
+        let mime_type = mime_type.to_ascii_lowercase();
+        if mime_type.contains("json")
+            && let Ok(mut value) = serde_json::from_slice::<Value>(body)
+        {
+            self.redact_json(&mut value);
+            return serde_json::to_vec(&value).unwrap_or_default();
+        }
+        if mime_type.starts_with("application/x-www-form-urlencoded")
+            && let Ok(text) = std::str::from_utf8(body)
+        {
+            return form_pairs(text)
+                .map(|(name, value)| {
+                    let value = if self.redacts_field(&name) {
+                        REDACTED.to_string()
+                    } else {
+                        value
+                    };
+                    format!("{}={}", encode(&name), encode(&value))
+                })
+                .collect::<Vec<_>>()
+                .join("&")
+                .into_bytes();
+        }
+        body.to_vec()
+    }
+
+    fn redact_json(&self, value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    if self.redacts_field(key) {
+                        *value = Value::from(REDACTED);
+                    } else {
+                        self.redact_json(value);
+                    }
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(|v| self.redact_json(v)),
+            _ => {}
+        }
+    }
+}
+
+/// Decoded `name=value` pairs of a query string or form body
+fn form_pairs(query: &str) -> impl Iterator<Item = (String, String)> + '_ {
+    url::form_urlencoded::parse(query.as_bytes()).map(|(k, v)| (k.into_owned(), v.into_owned()))
+}
+
+fn encode(value: &str) -> String {
+    url::form_urlencoded::byte_serialize(value.as_bytes()).collect()
+}
+
+/// Format `time` as an ISO 8601 UTC timestamp with milliseconds
+pub fn iso8601(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days(secs / 86_400);
+    let secs_of_day = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
@@ -0,0 +1,635 @@
+//! hyper-based connection engine
+//!
+//! Enabled with the `hyper-engine` cargo feature. Replaces the built-in
+//! parser, [`Connection`](crate::http::connection::Connection) state machine,
+//! and writer with hyper's HTTP/1.1 and HTTP/2 server, while requests still
+//! flow through the same [`Handler`] tree (router, middleware, proxy).
+//!
+//! HTTP/1.1 and HTTP/2 (prior knowledge) are detected per connection.
+
+use crate::events::{Event, Events};
+use crate::http::connection::{record_request, run_tunnel};
+use crate::http::context::RequestContext;
+use crate::http::cookie::SET_COOKIE_SEPARATOR;
+use crate::http::handler::{Handler, handle_isolated};
+use crate::http::parser::{HeadLimits, check_host};
+use crate::http::request::{Method, Request};
+use crate::http::response::{BodyStream, Disposition, Response, StatusCode};
+use crate::http::spool::{BodySpool, SpoolFile, SpooledBody};
+use crate::http::writer::ServerIdentity;
+use crate::metrics::Metrics;
+use crate::tls::{ClientCertificate, TlsFingerprint};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Frame, Incoming, SizeHint};
+use hyper::service::service_fn;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// Headers that describe the connection rather than the message; hyper
+/// manages these itself and HTTP/2 forbids them.
+const CONNECTION_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// A client connection served by hyper.
+///
+/// Mirrors [`Connection`](crate::http::connection::Connection): the same
+/// handler, events, metrics, cancellation, and request timeout settings.
+///
+/// # Example
+///
+/// ```ignore
+/// let (socket, _) = listener.accept().await?;
+/// HyperConnection::new(socket, handler)
+///     .with_cancellation(shutdown.child_token())
+///     .run()
+///     .await?;
+/// ```
+pub struct HyperConnection<S> {
+    stream: S,
+    state: Arc<EngineState>,
+    cancel: CancellationToken,
+}
+
+/// Settings shared by every request on a connection
+struct EngineState {
+    handler: Arc<dyn Handler>,
+    events: Events,
+    metrics: Metrics,
+    request_timeout: Option<Duration>,
+    peer: Option<SocketAddr>,
+    fingerprint: Option<Arc<TlsFingerprint>>,
+    client_cert: Option<Arc<ClientCertificate>>,
+    spool: Option<Arc<BodySpool>>,
+    max_requests: Option<u64>,
+    requests_served: AtomicU64,
+    limits: HeadLimits,
+    identity: Arc<ServerIdentity>,
+    /// Cancelled once `max_requests` is reached, or when the server drains,
+    /// to shut down gracefully
+    drain: CancellationToken,
+}
+
+impl<S> HyperConnection<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    /// Creates a connection that dispatches every request to `handler`.
+    pub fn new(stream: S, handler: Arc<dyn Handler>) -> Self {
+        Self {
+            stream,
+            state: Arc::new(EngineState {
+                handler,
+                events: Events::new(),
+                metrics: Metrics::default(),
+                request_timeout: None,
+                peer: None,
+                fingerprint: None,
+                client_cert: None,
+                spool: None,
+                max_requests: None,
+                requests_served: AtomicU64::new(0),
+                limits: HeadLimits::default(),
+                identity: Arc::default(),
+                drain: CancellationToken::new(),
+            }),
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    /// Publishes request lifecycle events to the given event bus.
+    pub fn with_events(mut self, events: Events) -> Self {
+        self.state_mut().events = events;
+        self
+    }
+
+    /// Records request counts and durations to the given metrics recorder.
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.state_mut().metrics = metrics;
+        self
+    }
+
+    /// Cancels in-flight requests and shuts the connection down gracefully
+    /// when `token` is cancelled.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// Shuts the connection down gracefully when `token` is cancelled:
+    /// requests in flight are answered, then the connection closes.
+    pub fn with_drain(mut self, token: CancellationToken) -> Self {
+        self.state_mut().drain = token.child_token();
+        self
+    }
+
+    /// Limits how long a handler may take to produce a response.
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.state_mut().request_timeout = Some(timeout);
+        self
+    }
+
+    /// Records the client's address, passed to handlers.
+    pub fn with_peer(mut self, peer: SocketAddr) -> Self {
+        self.state_mut().peer = Some(peer);
+        self
+    }
+
+    /// Attaches the client's TLS fingerprint to every request and access log line.
+    pub fn with_tls_fingerprint(mut self, fingerprint: Arc<TlsFingerprint>) -> Self {
+        self.state_mut().fingerprint = Some(fingerprint);
+        self
+    }
+
+    /// Attaches the client's verified certificate to every request.
+    pub fn with_client_cert(mut self, cert: Arc<ClientCertificate>) -> Self {
+        self.state_mut().client_cert = Some(cert);
+        self
+    }
+
+    /// Streams request bodies over the spool's memory limit to disk, and
+    /// refuses bodies over its size limit with `413 Payload Too Large`.
+    pub fn with_body_spool(mut self, spool: Arc<BodySpool>) -> Self {
+        self.state_mut().spool = Some(spool);
+        self
+    }
+
+    /// Closes the connection after `max` requests, answering the last one
+    /// with `Connection: close` (HTTP/1) or `GOAWAY` (HTTP/2).
+    pub fn with_max_requests(mut self, max: u64) -> Self {
+        self.state_mut().max_requests = Some(max);
+        self
+    }
+
+    /// Refuses request heads over these limits with `414 URI Too Long` or
+    /// `431 Request Header Fields Too Large` (defaults apply otherwise).
+    pub fn with_head_limits(mut self, limits: HeadLimits) -> Self {
+        self.state_mut().limits = limits;
+        self
+    }
+
+    /// Sets the `Server` header and scrubs headers on every response.
+    pub fn with_server_identity(mut self, identity: Arc<ServerIdentity>) -> Self {
+        self.state_mut().identity = identity;
+        self
+    }
+
+    fn state_mut(&mut self) -> &mut EngineState {
+        Arc::get_mut(&mut self.state).expect("engine state is not shared before run")
+    }
+
+    /// Serves requests until the client closes the connection.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection fails at the protocol or I/O
+    /// level, or if a handler aborted it (see [`Disposition::Abort`]).
+    pub async fn run(self) -> anyhow::Result<()> {
+        let state = self.state;
+        let cancel = self.cancel;
+        let drain = state.drain.clone();
+        let limits = state.limits;
+
+        let service_cancel = cancel.clone();
+        let service = service_fn(move |req: hyper::Request<Incoming>| {
+            let state = state.clone();
+            let token = service_cancel.child_token();
+            let cancel = service_cancel.clone();
+            async move { handle(req, state, token, cancel).await }
+        });
+
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        // hyper answers heads over these with 431 itself; its read buffer
+        // cannot be smaller than 8 KiB
+        builder
+            .http1()
+            .max_headers(limits.max_headers)
+            .max_buf_size(limits.max_header_bytes.max(8192));
+        builder
+            .http2()
+            .max_header_list_size(u32::try_from(limits.max_header_bytes).unwrap_or(u32::MAX));
+        let conn = builder.serve_connection_with_upgrades(TokioIo::new(self.stream), service);
+        tokio::pin!(conn);
+
+        let result = tokio::select! {
+            result = conn.as_mut() => result,
+            _ = cancel.cancelled() => {
+                conn.as_mut().graceful_shutdown();
+                conn.await
+            }
+            _ = drain.cancelled() => {
+                tracing::debug!("Request limit reached or draining, closing connection");
+                conn.as_mut().graceful_shutdown();
+                conn.await
+            }
+        };
+
+        result.map_err(|e| anyhow::anyhow!("hyper connection error: {}", e))
+    }
+}
+
+/// Convert, dispatch, and convert back a single request
+///
+/// `token` belongs to this request; `cancel` is the connection's token,
+/// which also ends any tunnel opened by a `CONNECT` request.
+async fn handle(
+    mut req: hyper::Request<Incoming>,
+    state: Arc<EngineState>,
+    token: CancellationToken,
+    cancel: CancellationToken,
+) -> Result<hyper::Response<ResponseBody>, std::io::Error> {
+    // hyper drops this future when the client disconnects
+    let _guard = token.clone().drop_guard();
+    let started = Instant::now();
+
+    let http1 = req.version() < hyper::Version::HTTP_2;
+    let target = req.uri().path_and_query().map_or(0, |p| p.as_str().len());
+    if target > state.limits.max_uri_length {
+        tracing::warn!(
+            length = target,
+            "Refusing request target over the length limit"
+        );
+        let response = Response::error(StatusCode::UriTooLong, "");
+        return Ok(from_response(response, http1, &state.identity));
+    }
+    let upgrade = (req.method() == hyper::Method::CONNECT).then(|| hyper::upgrade::on(&mut req));
+    let mut req = match into_request(req, state.spool.as_deref()).await {
+        Ok(req) => req,
+        Err(response) => return Ok(from_response(response, http1, &state.identity)),
+    };
+    req.context = RequestContext::new(token.clone());
+    req.context.deadline = state.request_timeout.map(|t| started + t);
+    req.context.peer = state.peer;
+    req.context.tls_fingerprint = state.fingerprint.clone();
+    req.context.client_cert = state.client_cert.clone();
+
+    let method = req.method.clone();
+    let path = req.path.clone();
+
+    tracing::info!(method = ?method, path = %path, "Received HTTP request");
+    state.events.emit(Event::RequestStarted {
+        method: method.clone(),
+        path: path.clone(),
+    });
+
+    let mut response = match state.request_timeout {
+        Some(limit) => match tokio::time::timeout(
+            limit,
+            handle_isolated(state.handler.as_ref(), req, &state.metrics),
+        )
+        .await
+        {
+            Ok(response) => response,
+            Err(_) => {
+                tracing::warn!("Request deadline exceeded, cancelling");
+                token.cancel();
+                Response::new(StatusCode::GatewayTimeout)
+                    .header("Content-Type", "text/plain")
+                    .body(b"504 Gateway Timeout".to_vec())
+                    .build()
+            }
+        },
+        None => handle_isolated(state.handler.as_ref(), req, &state.metrics).await,
+    };
+
+    record_request(
+        &state.events,
+        &state.metrics,
+        method,
+        path,
+        response.status.as_u16(),
+        started.elapsed(),
+        state.fingerprint.as_deref(),
+    );
+
+    let served = state.requests_served.fetch_add(1, Ordering::Relaxed) + 1;
+    if state.max_requests.is_some_and(|max| served >= max)
+        && response.disposition == Disposition::Send
+        && response.tunnel.is_none()
+    {
+        response.disposition = Disposition::SendAndClose;
+        state.drain.cancel();
+    }
+
+    if response.disposition == Disposition::Abort {
+        // Failing the service makes hyper drop the connection
+        return Err(std::io::Error::other("response aborted by handler"));
+    }
+
+    if let Some(upstream) = response.tunnel.take() {
+        let Some(upgrade) = upgrade else {
+            tracing::error!("Handler returned a tunnel for a non-CONNECT request");
+            return Ok(from_response(
+                Response::internal_error(),
+                http1,
+                &state.identity,
+            ));
+        };
+        let cancel = cancel.clone();
+        tokio::spawn(async move {
+            match upgrade.await {
+                Ok(upgraded) => run_tunnel(&mut TokioIo::new(upgraded), upstream, &cancel).await,
+                Err(e) => tracing::debug!(error = %e, "CONNECT upgrade failed"),
+            }
+        });
+    }
+
+    // hyper waits for bodies to finish before shutting down, so a stream
+    // that never ends must stop when the connection is cancelled
+    if let Some(body) = response.stream.take() {
+        let prefix = std::mem::take(&mut response.body);
+        response.stream = Some(until_cancelled(prefix, body, cancel));
+    }
+
+    Ok(from_response(response, http1, &state.identity))
+}
+
+/// Response body handed to hyper: buffered, or streamed as it arrives
+enum ResponseBody {
+    Full(Full<Bytes>),
+    Stream(BodyStream),
+}
+
+impl hyper::body::Body for ResponseBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Infallible>>> {
+        match self.get_mut() {
+            ResponseBody::Full(full) => Pin::new(full).poll_frame(cx),
+            ResponseBody::Stream(stream) => stream
+                .poll_recv(cx)
+                .map(|data| data.map(|data| Ok(Frame::data(data)))),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match self {
+            ResponseBody::Full(full) => full.is_end_stream(),
+            ResponseBody::Stream(_) => false,
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match self {
+            ResponseBody::Full(full) => full.size_hint(),
+            ResponseBody::Stream(_) => SizeHint::default(),
+        }
+    }
+}
+
+/// Forward `prefix` and then `body` until `cancel` fires
+fn until_cancelled(prefix: Vec<u8>, mut body: BodyStream, cancel: CancellationToken) -> BodyStream {
+    let (tx, rx) = mpsc::channel(1);
+    tokio::spawn(async move {
+        if !prefix.is_empty() && tx.send(Bytes::from(prefix)).await.is_err() {
+            return;
+        }
+        loop {
+            let data = tokio::select! {
+                data = body.recv() => data,
+                _ = cancel.cancelled() => None,
+            };
+            let Some(data) = data else { return };
+            if tx.send(data).await.is_err() {
+                return;
+            }
+        }
+    });
+    rx
+}
+
+/// Build a Sentinel request from a hyper request, buffering the body (or
+/// spooling it to disk when `spool` is set and the body is large)
+async fn into_request(
+    req: hyper::Request<Incoming>,
+    spool: Option<&BodySpool>,
+) -> Result<Request, Response> {
+    let (parts, body) = req.into_parts();
+
+    let Some(method) = Method::from_str(parts.method.as_str()) else {
+        return Err(Response::new(StatusCode::MethodNotAllowed)
+            .body(b"405 Method Not Allowed".to_vec())
+            .build());
+    };
+
+    if parts.headers.get_all(hyper::header::HOST).iter().count() > 1 {
+        tracing::warn!("Refusing request with a repeated Host");
+        return Err(Response::error(StatusCode::BadRequest, ""));
+    }
+    let mut headers = HashMap::new();
+    for (name, value) in &parts.headers {
+        if let Ok(value) = value.to_str() {
+            headers.insert(canonical_header_name(name.as_str()), value.to_string());
+        }
+    }
+
+    // HTTP/2 carries the host in the :authority pseudo-header
+    if !headers.contains_key("Host")
+        && let Some(authority) = parts.uri.authority()
+    {
+        headers.insert("Host".to_string(), authority.to_string());
+    }
+
+    // CONNECT targets are in authority form (host:port)
+    let target = if method == Method::CONNECT {
+        parts.uri.authority().map(|a| a.as_str())
+    } else {
+        parts.uri.path_and_query().map(|pq| pq.as_str())
+    };
+    let path = target.unwrap_or("/").to_string();
+
+    let mut request = Request {
+        method,
+        path,
+        version: format!("{:?}", parts.version),
+        headers,
+        body: Vec::new(),
+        spooled: None,
+        context: RequestContext::default(),
+    };
+    if check_host(&request).is_err() {
+        tracing::warn!(host = ?request.host(), "Refusing request without a valid Host");
+        return Err(Response::error(StatusCode::BadRequest, ""));
+    }
+
+    (request.body, request.spooled) = match spool {
+        Some(spool) => {
+            let (body, spooled) = read_body(body, spool).await?;
+            (body, spooled.map(Arc::new))
+        }
+        None => match body.collect().await {
+            Ok(collected) => (collected.to_bytes().to_vec(), None),
+            Err(e) => {
+                tracing::warn!(error = %e, "Failed to read request body");
+                return Err(Response::new(StatusCode::BadRequest)
+                    .body(b"400 Bad Request".to_vec())
+                    .build());
+            }
+        },
+    };
+
+    // hyper has already removed any chunked framing, and HTTP/2 bodies
+    // need not declare a length, so the body is described by its length as
+    // the built-in parser does
+    let len = match &request.spooled {
+        Some(spooled) => spooled.len(),
+        None => request.body.len() as u64,
+    };
+    let chunked = request.headers.remove("Transfer-Encoding").is_some();
+    if chunked || (len > 0 && !request.headers.contains_key("Content-Length")) {
+        request
+            .headers
+            .insert("Content-Length".to_string(), len.to_string());
+    }
+
+    Ok(request)
+}
+
+/// Read a body into memory, moving it to a spool file once it outgrows
+/// the memory limit
+///
+/// Chunked bodies have no declared length, so the size limit is enforced
+/// as data arrives.
+async fn read_body(
+    mut body: Incoming,
+    spool: &BodySpool,
+) -> Result<(Vec<u8>, Option<SpooledBody>), Response> {
+    let failed = |e: &dyn std::fmt::Display| {
+        tracing::warn!(error = %e, "Failed to read request body");
+        Response::new(StatusCode::BadRequest)
+            .body(b"400 Bad Request".to_vec())
+            .build()
+    };
+    let mut memory = Vec::new();
+    let mut file: Option<SpoolFile> = None;
+    let mut total = 0u64;
+    while let Some(frame) = body.frame().await {
+        let frame = frame.map_err(|e| failed(&e))?;
+        // Trailers carry no body data
+        let Ok(data) = frame.into_data() else {
+            continue;
+        };
+        total += data.len() as u64;
+        if spool.too_large(total) {
+            tracing::warn!(length = total, "Refusing request body over the size limit");
+            return Err(Response::error(StatusCode::PayloadTooLarge, ""));
+        }
+        let written = if let Some(file) = file.as_mut() {
+            file.write(&data).await
+        } else if spool.spools(total) {
+            spill(spool, &memory, &data).await.map(|spilled| {
+                memory = Vec::new();
+                file = Some(spilled);
+            })
+        } else {
+            memory.extend_from_slice(&data);
+            Ok(())
+        };
+        if let Err(e) = written {
+            tracing::error!(error = %e, "Failed to spool request body");
+            return Err(Response::internal_error());
+        }
+    }
+    match file {
+        Some(file) => match file.finish().await {
+            Ok(spooled) => Ok((Vec::new(), Some(spooled))),
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to spool request body");
+                Err(Response::internal_error())
+            }
+        },
+        None => Ok((memory, None)),
+    }
+}
+
+/// Move a body that outgrew memory into a new spool file
+async fn spill(spool: &BodySpool, memory: &[u8], data: &[u8]) -> std::io::Result<SpoolFile> {
+    let mut file = spool.create().await?;
+    file.write(memory).await?;
+    file.write(data).await?;
+    Ok(file)
+}
+
+/// Build a hyper response from a Sentinel response
+fn from_response(
+    mut response: Response,
+    http1: bool,
+    identity: &ServerIdentity,
+) -> hyper::Response<ResponseBody> {
+    identity.apply(&mut response);
+    let mut builder = hyper::Response::builder().status(response.status.as_u16());
+
+    if http1 && response.disposition == Disposition::SendAndClose {
+        builder = builder.header("Connection", "close");
+    }
+    if http1
+        && let Some(reason) = response.reason.as_deref()
+        && let Ok(reason) = hyper::ext::ReasonPhrase::try_from(reason.as_bytes())
+    {
+        builder = builder.extension(reason);
+    }
+
+    for (key, value) in &response.headers {
+        if CONNECTION_HEADERS
+            .iter()
+            .any(|h| key.eq_ignore_ascii_case(h))
+        {
+            continue;
+        }
+        if key.eq_ignore_ascii_case("Set-Cookie") {
+            for cookie in value.split(SET_COOKIE_SEPARATOR) {
+                builder = builder.header(key.as_str(), cookie);
+            }
+            continue;
+        }
+        builder = builder.header(key.as_str(), value.as_str());
+    }
+
+    let body = match response.stream {
+        Some(stream) => ResponseBody::Stream(stream),
+        None => ResponseBody::Full(Full::new(Bytes::from(response.body))),
+    };
+    builder.body(body).unwrap_or_else(|e| {
+        tracing::error!(error = %e, "Handler produced an invalid response");
+        let mut fallback = hyper::Response::new(ResponseBody::Full(Full::new(Bytes::from_static(
+            b"500 Internal Server Error",
+        ))));
+        *fallback.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+        fallback
+    })
+}
+
+/// hyper lowercases header names; restore the conventional `Title-Case`
+/// spelling, since handlers look headers up by exact key (e.g. "Host").
+fn canonical_header_name(name: &str) -> String {
+    name.split('-')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
@@ -0,0 +1,282 @@
+//! On-the-fly image transformation
+//!
+//! Resizes and converts static images according to query parameters (see
+//! [`ImageConfig`]), so sites can serve responsive images without a
+//! separate image service:
+//!
+//! ```text
+//! GET /photos/cat.jpg?w=640&format=auto
+//! ```
+//!
+//! Decoding and encoding run on the blocking pool, and results are cached
+//! in memory keyed by a hash of the source file, so an edited image is
+//! transformed afresh. Encoders come from the `image` crate and are only
+//! built with the `images` feature.
+
+use crate::config::ImageConfig;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// Largest source width or height decoded, bounding decode memory
+#[cfg(feature = "images")]
+const MAX_SOURCE_DIMENSION: u32 = 16_384;
+
+/// Speed of the AVIF encoder, 1 (slowest, smallest) to 10
+#[cfg(feature = "images")]
+const AVIF_SPEED: u8 = 8;
+
+/// Output formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ImageFormat {
+    Webp,
+    Avif,
+    Png,
+    Jpeg,
+}
+
+impl ImageFormat {
+    /// Format named in a `format` query parameter
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "webp" => Some(Self::Webp),
+            "avif" => Some(Self::Avif),
+            "png" => Some(Self::Png),
+            "jpeg" | "jpg" => Some(Self::Jpeg),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Webp => "image/webp",
+            Self::Avif => "image/avif",
+            Self::Png => "image/png",
+            Self::Jpeg => "image/jpeg",
+        }
+    }
+}
+
+/// A requested transformation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ImageTransform {
+    /// Largest width of the result
+    pub width: Option<u32>,
+    /// Largest height of the result
+    pub height: Option<u32>,
+    /// Output format (the source's if unset)
+    pub format: Option<ImageFormat>,
+    /// Whether the format was chosen from the Accept header
+    pub negotiated: bool,
+}
+
+impl ImageTransform {
+    /// Parse `w`, `h` and `format` from a query string
+    ///
+    /// Returns `Ok(None)` when none are present (other parameters, such as
+    /// cache busters, are ignored) and a message for invalid values.
+    pub fn from_query(
+        query: &str,
+        accept: Option<&str>,
+        config: &ImageConfig,
+    ) -> Result<Option<Self>, String> {
+        let mut transform = Self {
+            width: None,
+            height: None,
+            format: None,
+            negotiated: false,
+        };
+        let mut requested = false;
+        for (name, value) in url::form_urlencoded::parse(query.as_bytes()) {
+            match name.as_ref() {
+                "w" => transform.width = Some(dimension("w", &value, config)?),
+                "h" => transform.height = Some(dimension("h", &value, config)?),
+                "format" if value == "auto" => {
+                    let accept = accept.unwrap_or_default();
+                    transform.format = if accept.contains("image/avif") {
+                        Some(ImageFormat::Avif)
+                    } else if accept.contains("image/webp") {
+                        Some(ImageFormat::Webp)
+                    } else {
+                        None
+                    };
+                    transform.negotiated = true;
+                }
+                "format" => {
+                    transform.format = Some(
+                        ImageFormat::from_name(&value)
+                            .ok_or_else(|| format!("Unsupported image format '{}'", value))?,
+                    )
+                }
+                _ => continue,
+            }
+            requested = true;
+        }
+        if let Some(width) = transform.width
+            && !config.widths.is_empty()
+            && !config.widths.contains(&width)
+        {
+            return Err(format!(
+                "Image width {} is not one of {:?}",
+                width, config.widths
+            ));
+        }
+        Ok(requested.then_some(transform))
+    }
+}
+
+fn dimension(name: &str, value: &str, config: &ImageConfig) -> Result<u32, String> {
+    match value.parse::<u32>() {
+        Ok(n) if (1..=config.max_dimension).contains(&n) => Ok(n),
+        _ => Err(format!(
+            "Image {} must be 1-{}, got '{}'",
+            name, config.max_dimension, value
+        )),
+    }
+}
+
+/// A transformed image
+#[derive(Debug, Clone)]
+pub struct TransformedImage {
+    pub body: Arc<Vec<u8>>,
+    pub content_type: &'static str,
+}
+
+type CacheKey = ([u8; 32], ImageTransform);
+
+/// Transforms images, caching the results
+pub struct ImageTransformer {
+    config: ImageConfig,
+    cache: Mutex<ImageCache>,
+}
+
+#[derive(Default)]
+struct ImageCache {
+    images: HashMap<CacheKey, TransformedImage>,
+    /// Keys oldest first, for eviction
+    order: VecDeque<CacheKey>,
+}
+
+impl ImageTransformer {
+    pub fn new(config: ImageConfig) -> Self {
+        Self {
+            config,
+            cache: Mutex::new(ImageCache::default()),
+        }
+    }
+
+    pub fn config(&self) -> &ImageConfig {
+        &self.config
+    }
+
+    /// Transformed images currently cached
+    pub fn cached(&self) -> usize {
+        self.cache.lock().unwrap().images.len()
+    }
+
+    /// Apply `transform` to the image `source`
+    pub async fn transform(
+        &self,
+        source: Vec<u8>,
+        transform: ImageTransform,
+    ) -> anyhow::Result<TransformedImage> {
+        if source.len() > self.config.max_source_bytes {
+            anyhow::bail!(
+                "Image of {} bytes is larger than max_source_bytes ({})",
+                source.len(),
+                self.config.max_source_bytes
+            );
+        }
+        let key = (Sha256::digest(&source).into(), transform);
+        if let Some(image) = self.cache.lock().unwrap().images.get(&key) {
+            return Ok(image.clone());
+        }
+
+        let quality = self.config.quality;
+        let (body, format) =
+            tokio::task::spawn_blocking(move || render(&source, &transform, quality)).await??;
+        let image = TransformedImage {
+            body: Arc::new(body),
+            content_type: format.content_type(),
+        };
+
+        let mut cache = self.cache.lock().unwrap();
+        if self.config.cache_entries > 0 && !cache.images.contains_key(&key) {
+            while cache.order.len() >= self.config.cache_entries {
+                if let Some(oldest) = cache.order.pop_front() {
+                    cache.images.remove(&oldest);
+                }
+            }
+            cache.order.push_back(key);
+            cache.images.insert(key, image.clone());
+        }
+        Ok(image)
+    }
+}
+
+/// Decode, resize and encode an image
+#[cfg(feature = "images")]
+fn render(
+    source: &[u8],
+    transform: &ImageTransform,
+    quality: u8,
+) -> anyhow::Result<(Vec<u8>, ImageFormat)> {
+    use anyhow::Context;
+    use image::codecs::avif::AvifEncoder;
+    use image::codecs::jpeg::JpegEncoder;
+    use image::codecs::png::PngEncoder;
+    use image::codecs::webp::WebPEncoder;
+    use image::imageops::FilterType;
+    use image::{DynamicImage, ImageReader, Limits};
+
+    let mut reader = ImageReader::new(std::io::Cursor::new(source)).with_guessed_format()?;
+    let source_format = reader.format().context("Unrecognized image format")?;
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_SOURCE_DIMENSION);
+    limits.max_image_height = Some(MAX_SOURCE_DIMENSION);
+    reader.limits(limits);
+    let mut image = reader.decode().context("Failed to decode image")?;
+
+    // Fit within the requested box, never enlarging
+    let width = transform.width.unwrap_or(u32::MAX).min(image.width());
+    let height = transform.height.unwrap_or(u32::MAX).min(image.height());
+    if width < image.width() || height < image.height() {
+        image = image.resize(width, height, FilterType::Lanczos3);
+    }
+
+    let format = transform.format.unwrap_or(match source_format {
+        image::ImageFormat::Jpeg => ImageFormat::Jpeg,
+        image::ImageFormat::WebP => ImageFormat::Webp,
+        image::ImageFormat::Avif => ImageFormat::Avif,
+        _ => ImageFormat::Png,
+    });
+    // Encoders take 8-bit images; JPEG has no alpha channel
+    let image = match format {
+        ImageFormat::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8()),
+        _ if image.color().has_alpha() => DynamicImage::ImageRgba8(image.to_rgba8()),
+        _ => DynamicImage::ImageRgb8(image.to_rgb8()),
+    };
+
+    let mut out = Vec::new();
+    match format {
+        ImageFormat::Webp => image.write_with_encoder(WebPEncoder::new_lossless(&mut out)),
+        ImageFormat::Avif => image.write_with_encoder(AvifEncoder::new_with_speed_quality(
+            &mut out, AVIF_SPEED, quality,
+        )),
+        ImageFormat::Png => image.write_with_encoder(PngEncoder::new(&mut out)),
+        ImageFormat::Jpeg => {
+            image.write_with_encoder(JpegEncoder::new_with_quality(&mut out, quality))
+        }
+    }
+    .context("Failed to encode image")?;
+    Ok((out, format))
+}
+
+#[cfg(not(feature = "images"))]
+fn render(
+    _source: &[u8],
+    _transform: &ImageTransform,
+    _quality: u8,
+) -> anyhow::Result<(Vec<u8>, ImageFormat)> {
+    anyhow::bail!("Image transformation requires building with the images feature")
+}
@@ -11,6 +11,12 @@ pub fn content_type(path: &str) -> &'static str {
         "image/png"
     } else if path.ends_with(".jpg") || path.ends_with(".jpeg") {
         "image/jpeg"
+    } else if path.ends_with(".webp") {
+        "image/webp"
+    } else if path.ends_with(".avif") {
+        "image/avif"
+    } else if path.ends_with(".gif") {
+        "image/gif"
     } else if path.ends_with(".txt") {
         "text/plain"
     } else {
@@ -6,12 +6,30 @@
 //!
 //! The HTTP layer is organized into several submodules:
 //!
+//! - **`capture`**: Writes requests the parser rejects to a capture file
 //! - **`connection`**: The main connection handler implementing the request-response state machine
+//! - **`context`**: Per-request cancellation token and deadline
+//! - **`cookie`**: `Cookie` header parsing and `Set-Cookie` values
+//! - **`forwarded`**: Trusted proxies and the scheme a request was made with
+//! - **`error_pages`**: Renders errors Sentinel generates itself (templates or JSON/HTML/text)
+//! - **`har`**: HAR model and sampled traffic recording
+//! - **`handler`**: The `Handler` trait implemented by everything that produces responses
+//! - **`images`**: Resizes and converts static images on request (encoders behind feature `images`)
+//! - **`multipart`**: Streaming `multipart/form-data` parser for inspecting uploads
+//! - **`router`**: Dispatches requests to handlers by path
+//! - **`sandbox`**: Opens files without letting paths escape the static root
+//! - **`service`**: Adapters between handlers and `tower::Service`/`Layer`
+//! - **`spool`**: Streams large request bodies to temporary files
+//! - **`static_files`**: Serves files from the static root
+//! - **`static_response`**: Fixed responses for routes defined in config
+//! - **`util`**: Basic credentials, HTTP dates, and percent-decoding shared across the crate
 //! - **`parser`**: Parses incoming HTTP requests from byte buffers
 //! - **`request`**: HTTP request representation and parsing utilities
 //! - **`response`**: HTTP response representation with builder pattern
+//! - **`webdav`**: Shares a directory over WebDAV (PROPFIND, PUT, MKCOL, COPY, MOVE, ...)
 //! - **`writer`**: Serializes and writes HTTP responses to the client
 //! - **`mime`**: MIME type detection based on file extensions
+//! - **`hyper_engine`**: Alternative hyper-based engine (feature `hyper-engine`)
 //!
 //! # Connection State Machine
 //!
@@ -49,7 +67,7 @@
 //!     loop {
 //!         let (socket, _addr) = listener.accept().await?;
 //!         tokio::spawn(async move {
-//!             let mut conn = Connection::new(socket);
+//!             let mut conn = Connection::new(socket, static_config.clone());
 //!             if let Err(e) = conn.run().await {
 //!                 eprintln!("Connection error: {}", e);
 //!             }
@@ -58,9 +76,28 @@
 //! }
 //! ```
 
+pub mod capture;
 pub mod connection;
+pub mod context;
+pub mod cookie;
+pub mod error_pages;
+pub mod forwarded;
+pub mod handler;
+pub mod har;
+#[cfg(feature = "hyper-engine")]
+pub mod hyper_engine;
+pub mod images;
 pub mod mime;
+pub mod multipart;
 pub mod parser;
 pub mod request;
 pub mod response;
+pub mod router;
+pub mod sandbox;
+pub mod service;
+pub mod spool;
+pub mod static_files;
+pub mod static_response;
+pub(crate) mod util;
+pub mod webdav;
 pub mod writer;
@@ -0,0 +1,352 @@
+//! Streaming `multipart/form-data` parsing
+//!
+//! [`Multipart`] reads a body from any [`AsyncRead`] and yields its parts
+//! one at a time: [`Multipart::next_part`] returns the part's headers and
+//! [`Multipart::next_chunk`] its content in pieces, so an upload can be
+//! inspected (for example to check file types) without holding it in
+//! memory. At most one read chunk plus the boundary, or
+//! `max_header_bytes` while reading part headers, is buffered.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use sentinel::http::multipart::Multipart;
+//!
+//! let Some(mut multipart) = Multipart::from_request(&req).await? else {
+//!     return Ok(()); // not multipart/form-data
+//! };
+//! while let Some(part) = multipart.next_part().await? {
+//!     if let Some(filename) = part.filename() {
+//!         let first = multipart.next_chunk().await?.unwrap_or_default();
+//!         tracing::info!(filename, magic = ?&first[..first.len().min(4)], "Upload");
+//!     }
+//! }
+//! ```
+
+use crate::http::request::Request;
+use std::collections::HashMap;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Bytes requested from the reader at a time
+const READ_CHUNK: usize = 16 * 1024;
+
+/// Default limit on the headers of one part
+const DEFAULT_MAX_HEADER_BYTES: usize = 8 * 1024;
+
+/// Default limit on the number of parts
+const DEFAULT_MAX_PARTS: usize = 256;
+
+/// Headers of one part of a multipart body
+#[derive(Debug, Clone, Default)]
+pub struct Part {
+    pub headers: HashMap<String, String>,
+}
+
+impl Part {
+    /// Value of a header, matched case-insensitively
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Form field name from `Content-Disposition`
+    pub fn name(&self) -> Option<String> {
+        self.header("Content-Disposition")
+            .and_then(|value| param(value, "name"))
+    }
+
+    /// Uploaded file name from `Content-Disposition`, if the part is a file
+    pub fn filename(&self) -> Option<String> {
+        self.header("Content-Disposition")
+            .and_then(|value| param(value, "filename"))
+    }
+
+    /// Declared content type (`text/plain` if absent, per RFC 7578)
+    pub fn content_type(&self) -> &str {
+        self.header("Content-Type").unwrap_or("text/plain")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    /// Reading a part's content (or the preamble before the first part)
+    Body,
+    /// The current part's content is exhausted; headers of the next follow
+    Headers,
+    /// The closing boundary has been read
+    Done,
+}
+
+/// Streaming parser over a `multipart/form-data` body
+pub struct Multipart<R> {
+    reader: R,
+    /// `\r\n--boundary`
+    delimiter: Vec<u8>,
+    buffer: Vec<u8>,
+    state: State,
+    /// Whether the current body is the preamble rather than a part
+    preamble: bool,
+    eof: bool,
+    parts: usize,
+    max_header_bytes: usize,
+    max_parts: usize,
+}
+
+impl<R: AsyncRead + Unpin> Multipart<R> {
+    /// Parse `reader` with the given boundary
+    pub fn new(reader: R, boundary: &str) -> Self {
+        Self {
+            reader,
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            // The first boundary has no preceding line break
+            buffer: b"\r\n".to_vec(),
+            state: State::Body,
+            preamble: true,
+            eof: false,
+            parts: 0,
+            max_header_bytes: DEFAULT_MAX_HEADER_BYTES,
+            max_parts: DEFAULT_MAX_PARTS,
+        }
+    }
+
+    /// Limit the size of one part's headers
+    pub fn with_max_header_bytes(mut self, max: usize) -> Self {
+        self.max_header_bytes = max;
+        self
+    }
+
+    /// Limit the number of parts
+    pub fn with_max_parts(mut self, max: usize) -> Self {
+        self.max_parts = max;
+        self
+    }
+
+    /// Advance to the next part, skipping what is left of the current one
+    ///
+    /// Returns `None` after the closing boundary.
+    pub async fn next_part(&mut self) -> anyhow::Result<Option<Part>> {
+        while self.state == State::Body {
+            self.next_chunk_any().await?;
+        }
+        if self.state == State::Done {
+            return Ok(None);
+        }
+
+        let end = loop {
+            // Headers may be empty, leaving only the blank line
+            if self.buffer.starts_with(b"\r\n") {
+                break 0;
+            }
+            if let Some(end) = find(&self.buffer, b"\r\n\r\n") {
+                break end;
+            }
+            if self.buffer.len() > self.max_header_bytes {
+                anyhow::bail!(
+                    "Multipart part headers exceed {} bytes",
+                    self.max_header_bytes
+                );
+            }
+            self.fill().await?;
+        };
+        if end > self.max_header_bytes {
+            anyhow::bail!(
+                "Multipart part headers exceed {} bytes",
+                self.max_header_bytes
+            );
+        }
+
+        self.parts += 1;
+        if self.parts > self.max_parts {
+            anyhow::bail!("Multipart body has more than {} parts", self.max_parts);
+        }
+
+        let text = std::str::from_utf8(&self.buffer[..end])
+            .map_err(|_| anyhow::anyhow!("Multipart part headers are not UTF-8"))?;
+        let mut headers = HashMap::new();
+        for line in text.split("\r\n").filter(|line| !line.is_empty()) {
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| anyhow::anyhow!("Malformed multipart header: {}", line))?;
+            headers.insert(name.trim().to_string(), value.trim().to_string());
+        }
+        let consumed = if end == 0 { 2 } else { end + 4 };
+        self.buffer.drain(..consumed);
+        self.state = State::Body;
+        Ok(Some(Part { headers }))
+    }
+
+    /// Next piece of the current part's content, or `None` at its end
+    pub async fn next_chunk(&mut self) -> anyhow::Result<Option<Vec<u8>>> {
+        if self.preamble {
+            return Ok(None);
+        }
+        self.next_chunk_any().await
+    }
+
+    /// Read the rest of the current part, failing if it exceeds `limit`
+    pub async fn read_to_end(&mut self, limit: usize) -> anyhow::Result<Vec<u8>> {
+        let mut content = Vec::new();
+        while let Some(chunk) = self.next_chunk().await? {
+            if content.len() + chunk.len() > limit {
+                anyhow::bail!("Multipart part exceeds {} bytes", limit);
+            }
+            content.extend_from_slice(&chunk);
+        }
+        Ok(content)
+    }
+
+    /// Next piece of content, preamble included
+    async fn next_chunk_any(&mut self) -> anyhow::Result<Option<Vec<u8>>> {
+        if self.state != State::Body {
+            return Ok(None);
+        }
+        loop {
+            if let Some(at) = find(&self.buffer, &self.delimiter) {
+                if at > 0 {
+                    return Ok(Some(self.buffer.drain(..at).collect()));
+                }
+                if !self.finish_boundary().await? {
+                    continue;
+                }
+                return Ok(None);
+            }
+
+            // Keep enough of the tail to hold a partial delimiter
+            let safe = self.buffer.len().saturating_sub(self.delimiter.len() - 1);
+            if safe > 0 {
+                return Ok(Some(self.buffer.drain(..safe).collect()));
+            }
+            self.fill().await?;
+        }
+    }
+
+    /// Consume the boundary at the start of the buffer
+    ///
+    /// Returns `false` if more input is needed to tell a closing boundary
+    /// from one that starts a new part.
+    async fn finish_boundary(&mut self) -> anyhow::Result<bool> {
+        let rest = &self.buffer[self.delimiter.len()..];
+        if rest.starts_with(b"--") {
+            self.buffer.clear();
+            self.state = State::Done;
+            self.preamble = false;
+            return Ok(true);
+        }
+        // Transport padding may follow the boundary before its line break
+        let padding = rest
+            .iter()
+            .take_while(|b| matches!(b, b' ' | b'\t'))
+            .count();
+        let rest = &rest[padding..];
+        if rest.len() < 2 {
+            self.fill().await?;
+            return Ok(false);
+        }
+        if !rest.starts_with(b"\r\n") {
+            anyhow::bail!("Malformed multipart boundary");
+        }
+        self.buffer.drain(..self.delimiter.len() + padding + 2);
+        self.state = State::Headers;
+        self.preamble = false;
+        Ok(true)
+    }
+
+    /// Read more input, failing at end of stream
+    async fn fill(&mut self) -> anyhow::Result<()> {
+        if self.eof {
+            anyhow::bail!("Multipart body ended before the closing boundary");
+        }
+        let start = self.buffer.len();
+        self.buffer.resize(start + READ_CHUNK, 0);
+        let n = self.reader.read(&mut self.buffer[start..]).await?;
+        self.buffer.truncate(start + n);
+        if n == 0 {
+            self.eof = true;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Multipart<Box<dyn AsyncRead + Send + Unpin + 'a>> {
+    /// Parse the body of a `multipart/form-data` request, in memory or
+    /// [spooled](crate::http::spool) to disk
+    ///
+    /// Returns `None` if the request is not `multipart/form-data`.
+    pub async fn from_request(req: &'a Request) -> anyhow::Result<Option<Self>> {
+        let Some(boundary) = req
+            .headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Content-Type"))
+            .and_then(|(_, value)| boundary(value))
+        else {
+            return Ok(None);
+        };
+        let reader: Box<dyn AsyncRead + Send + Unpin + 'a> = match &req.spooled {
+            Some(spooled) => Box::new(spooled.open().await?),
+            None => Box::new(req.body.as_slice()),
+        };
+        Ok(Some(Self::new(reader, &boundary)))
+    }
+}
+
+/// Boundary of a `multipart/form-data` content type
+pub fn boundary(content_type: &str) -> Option<String> {
+    let (mime, _) = content_type.split_once(';')?;
+    if !mime.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    param(content_type, "boundary").filter(|b| !b.is_empty() && b.len() <= 70)
+}
+
+/// Value of parameter `key` in a header such as `form-data; name="a"`
+fn param(value: &str, key: &str) -> Option<String> {
+    let mut rest = value.split_once(';')?.1;
+    loop {
+        rest = rest.trim_start_matches([' ', '\t', ';']);
+        if rest.is_empty() {
+            return None;
+        }
+        let eq = rest.find('=')?;
+        let name = rest[..eq].trim();
+        rest = &rest[eq + 1..];
+
+        let parsed = if let Some(quoted) = rest.strip_prefix('"') {
+            let mut out = String::new();
+            let mut chars = quoted.char_indices();
+            let mut end = quoted.len();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\\' => {
+                        if let Some((_, escaped)) = chars.next() {
+                            out.push(escaped);
+                        }
+                    }
+                    '"' => {
+                        end = i + 1;
+                        break;
+                    }
+                    _ => out.push(c),
+                }
+            }
+            rest = &quoted[end.min(quoted.len())..];
+            out
+        } else {
+            let end = rest.find(';').unwrap_or(rest.len());
+            let token = rest[..end].trim().to_string();
+            rest = &rest[end..];
+            token
+        };
+
+        if name.eq_ignore_ascii_case(key) {
+            return Some(parsed);
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
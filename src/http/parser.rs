@@ -1,8 +1,9 @@
+use crate::http::context::RequestContext;
 use crate::http::request::{Method, Request};
 use std::collections::HashMap;
 
 /// Errors that can occur during HTTP request parsing.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ParseError {
     /// The request line or headers are malformed
     InvalidRequest,
@@ -10,17 +11,328 @@ pub enum ParseError {
     InvalidMethod,
     /// A header line is malformed
     InvalidHeader,
-    /// Content-Length header value is not a valid number
+    /// Content-Length header value is not a valid number, or the header
+    /// appears more than once
     InvalidContentLength,
+    /// Both Transfer-Encoding and Content-Length are present, so the body
+    /// could be framed two different ways
+    ConflictingLength,
+    /// Transfer-Encoding is something other than `chunked`, so the body
+    /// cannot be framed
+    UnsupportedTransferEncoding,
+    /// An HTTP/1.1 request has no Host header, or Host is repeated or not
+    /// a valid host and port
+    InvalidHost,
+    /// The request target is longer than allowed
+    UriTooLong,
+    /// The request line and headers are larger, or the headers more
+    /// numerous, than allowed
+    HeadersTooLarge,
     /// The request is incomplete and more data is needed
     Incomplete,
 }
 
+/// How strictly request heads are parsed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Lines must end in CRLF and folded header lines are rejected
+    #[default]
+    Strict,
+    /// Also accept LF-only line endings and obsolete line folding
+    /// (continuation lines starting with a space or tab), as sent by some
+    /// legacy clients. Folded values are joined with a single space.
+    Lenient,
+}
+
+/// Default for [`HeadLimits::max_header_bytes`]
+pub const DEFAULT_MAX_HEADER_BYTES: usize = 64 * 1024;
+
+/// Default for [`HeadLimits::max_headers`]
+pub const DEFAULT_MAX_HEADERS: usize = 100;
+
+/// Default for [`HeadLimits::max_uri_length`]
+pub const DEFAULT_MAX_URI_LENGTH: usize = 8 * 1024;
+
+/// Size limits on a request head, checked while it is still arriving so
+/// the connection buffer cannot grow without bound
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeadLimits {
+    /// Largest request line plus headers, in bytes
+    pub max_header_bytes: usize,
+    /// Most header lines
+    pub max_headers: usize,
+    /// Longest request target
+    pub max_uri_length: usize,
+}
+
+impl Default for HeadLimits {
+    fn default() -> Self {
+        Self {
+            max_header_bytes: DEFAULT_MAX_HEADER_BYTES,
+            max_headers: DEFAULT_MAX_HEADERS,
+            max_uri_length: DEFAULT_MAX_URI_LENGTH,
+        }
+    }
+}
+
+impl HeadLimits {
+    /// No limits, for parsing buffers that are already complete
+    const NONE: HeadLimits = HeadLimits {
+        max_header_bytes: usize::MAX,
+        max_headers: usize::MAX,
+        max_uri_length: usize::MAX,
+    };
+
+    /// Check the head at the start of `buf`, complete or not
+    ///
+    /// Returns [`ParseError::UriTooLong`] or [`ParseError::HeadersTooLarge`]
+    /// as soon as what has arrived breaks a limit. Lines may end in CRLF or
+    /// a bare LF, so the limits hold in either [`ParseMode`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use sentinel::http::parser::{HeadLimits, ParseError};
+    /// let limits = HeadLimits { max_uri_length: 4, ..HeadLimits::default() };
+    /// assert!(matches!(limits.check(b"GET /too-long HTTP/1.1\r\n"), Err(ParseError::UriTooLong)));
+    /// assert!(limits.check(b"GET /a HTTP/1.1\r\nHost: a\r\n\r\n").is_ok());
+    /// ```
+    pub fn check(&self, buf: &[u8]) -> Result<(), ParseError> {
+        RequestParser::new(ParseMode::Strict, *self)
+            .scan(buf)
+            .map(|_| ())
+    }
+}
+
+/// Header slots parsed without allocating; heads with more lines get a
+/// slot per line
+const INLINE_HEADERS: usize = 32;
+
+/// Parses requests out of a connection's read buffer as data arrives
+///
+/// Each call only looks at the bytes added since the previous one, and the
+/// head is parsed once, when its blank line has arrived, then kept while
+/// the body arrives. A client trickling a request in byte by byte therefore
+/// costs linear rather than quadratic time. The limits are enforced while
+/// the head is still arriving.
+///
+/// Chunked bodies are decoded as their chunks arrive. The request is
+/// returned with the decoded body, a `Content-Length` for it, and no
+/// `Transfer-Encoding`, so handlers see one kind of body either way.
+///
+/// Once a request is returned the parser starts over; the caller drains
+/// the consumed bytes from its buffer.
+///
+/// # Example
+///
+/// ```
+/// # use sentinel::http::parser::{HeadLimits, ParseError, ParseMode, RequestParser};
+/// let mut parser = RequestParser::new(ParseMode::Strict, HeadLimits::default());
+/// let mut buf = b"POST /upload HTTP/1.1\r\nHost: a\r\n".to_vec();
+/// assert!(matches!(parser.parse(&buf), Err(ParseError::Incomplete)));
+///
+/// buf.extend_from_slice(b"Content-Length: 2\r\n\r\nhi");
+/// let (request, consumed) = parser.parse(&buf).unwrap();
+/// assert_eq!(request.body, b"hi");
+/// assert_eq!(consumed, buf.len());
+/// ```
+#[derive(Debug)]
+pub struct RequestParser {
+    mode: ParseMode,
+    limits: HeadLimits,
+    /// Bytes of the buffer looked at so far
+    scanned: usize,
+    /// Start of the line being scanned
+    line_start: usize,
+    /// Line feeds seen in the head
+    lines: usize,
+    /// Spaces seen in the request line
+    spaces: usize,
+    /// Length of the request target so far
+    target_len: usize,
+    /// The parsed head and its length, once complete
+    head: Option<(Request, usize)>,
+    /// Why the head was rejected, returned again by later calls
+    failed: Option<ParseError>,
+    /// Progress through a chunked body
+    chunks: ChunkedBody,
+}
+
+impl RequestParser {
+    /// Create a parser for requests in `mode`, enforcing `limits`
+    pub fn new(mode: ParseMode, limits: HeadLimits) -> Self {
+        Self {
+            mode,
+            limits,
+            scanned: 0,
+            line_start: 0,
+            lines: 0,
+            spaces: 0,
+            target_len: 0,
+            head: None,
+            failed: None,
+            chunks: ChunkedBody::default(),
+        }
+    }
+
+    /// The mode requests are parsed in
+    pub fn mode(&self) -> ParseMode {
+        self.mode
+    }
+
+    /// The limits enforced on request heads
+    pub fn limits(&self) -> HeadLimits {
+        self.limits
+    }
+
+    /// Parse the head at the start of `buf`, once all of it has arrived
+    ///
+    /// Returns [`ParseError::Incomplete`] until then. The body is left
+    /// empty; see [`parse`](Self::parse) and [`take_head`](Self::take_head).
+    pub fn head(&mut self, buf: &[u8]) -> Result<&Request, ParseError> {
+        if let Some(error) = self.failed {
+            return Err(error);
+        }
+        if self.head.is_none() {
+            let parsed = self.scan(buf).and_then(|end| {
+                let (end, head_len) = end.ok_or(ParseError::Incomplete)?;
+                let request = match self.mode {
+                    ParseMode::Strict => parse_head(&buf[..head_len], self.lines)?,
+                    ParseMode::Lenient => parse_head(&unfold(&buf[..end])?, self.lines)?,
+                };
+                Ok((request, head_len))
+            });
+            match parsed {
+                Ok(head) => self.head = Some(head),
+                Err(ParseError::Incomplete) => return Err(ParseError::Incomplete),
+                Err(error) => {
+                    self.failed = Some(error);
+                    return Err(error);
+                }
+            }
+        }
+        match &self.head {
+            Some((request, _)) => Ok(request),
+            None => Err(ParseError::Incomplete),
+        }
+    }
+
+    /// Parse a whole request at the start of `buf`
+    ///
+    /// Returns the request and the number of bytes it took up, or
+    /// [`ParseError::Incomplete`] until its body (framed by
+    /// Content-Length or chunked) has arrived.
+    pub fn parse(&mut self, buf: &[u8]) -> Result<(Request, usize), ParseError> {
+        let head = self.head(buf)?;
+        let chunked = head.headers.contains_key("Transfer-Encoding");
+        let content_length = head.content_length();
+        let head_len = self.head.as_ref().map_or(0, |(_, len)| *len);
+
+        let body_bytes = &buf[head_len..];
+        if chunked {
+            let body_len = self
+                .chunks
+                .decode(body_bytes, self.limits)?
+                .ok_or(ParseError::Incomplete)?;
+            let body = std::mem::take(&mut self.chunks.data);
+            let (mut request, _) = self.take_head().ok_or(ParseError::Incomplete)?;
+            request.headers.remove("Transfer-Encoding");
+            request
+                .headers
+                .insert("Content-Length".to_string(), body.len().to_string());
+            request.body = body;
+            return Ok((request, head_len + body_len));
+        }
+        if body_bytes.len() < content_length {
+            return Err(ParseError::Incomplete);
+        }
+        let (mut request, _) = self.take_head().ok_or(ParseError::Incomplete)?;
+        request.body = body_bytes[..content_length].to_vec();
+
+        Ok((request, head_len + content_length))
+    }
+
+    /// Take the head parsed by [`head`](Self::head), with its length, and
+    /// start over, e.g. to read a large body separately
+    pub fn take_head(&mut self) -> Option<(Request, usize)> {
+        let head = self.head.take();
+        self.scanned = 0;
+        self.line_start = 0;
+        self.lines = 0;
+        self.spaces = 0;
+        self.target_len = 0;
+        self.failed = None;
+        self.chunks = ChunkedBody::default();
+        head
+    }
+
+    /// Bytes of a chunked body decoded so far, while it is still arriving
+    pub fn decoded_len(&self) -> usize {
+        self.chunks.data.len()
+    }
+
+    /// Look at the bytes added to `buf` since the last call, checking the
+    /// limits, until the blank line ending the head arrives
+    ///
+    /// Lines may end in CRLF or a bare LF here; strict parsing rejects the
+    /// latter afterwards. Returns where the last header line ends and the
+    /// length of the head including the blank line.
+    fn scan(&mut self, buf: &[u8]) -> Result<Option<(usize, usize)>, ParseError> {
+        let mut end = None;
+        while self.scanned < buf.len() {
+            let i = self.scanned;
+            self.scanned += 1;
+            match buf[i] {
+                b'\n' => {}
+                // The target is the second word of the request line
+                b' ' if self.lines == 0 => {
+                    self.spaces += 1;
+                    continue;
+                }
+                b'\r' => continue,
+                _ => {
+                    if self.lines == 0 && self.spaces == 1 {
+                        self.target_len += 1;
+                        if self.target_len > self.limits.max_uri_length {
+                            return Err(ParseError::UriTooLong);
+                        }
+                    }
+                    continue;
+                }
+            }
+
+            self.lines += 1;
+            let line = &buf[self.line_start..i];
+            if (line.is_empty() || line == b"\r") && self.line_start > 0 {
+                // Drop the previous line's terminator from the head
+                let last = if buf[..self.line_start].ends_with(b"\r\n") {
+                    self.line_start - 2
+                } else {
+                    self.line_start - 1
+                };
+                end = Some((last, i + 1));
+                self.lines -= 1;
+                break;
+            }
+            self.line_start = i + 1;
+        }
+
+        let head_len = end.map_or(self.scanned, |(last, _)| last);
+        // Every line after the request line is a header
+        let headers = self.lines.saturating_sub(1);
+        if head_len > self.limits.max_header_bytes || headers > self.limits.max_headers {
+            return Err(ParseError::HeadersTooLarge);
+        }
+        Ok(end)
+    }
+}
+
 /// Parses an HTTP request from a byte buffer.
 ///
 /// This function attempts to parse a complete HTTP request from the given buffer.
 /// It expects the buffer to contain a request line, headers, a blank line separator,
-/// and optionally a body.
+/// and optionally a body. Connections reading a request as it arrives use a
+/// [`RequestParser`] instead, which does not start over on every read.
 ///
 /// # Arguments
 ///
@@ -43,66 +355,365 @@ pub enum ParseError {
 /// }
 /// ```
 pub fn parse_http_request(buf: &[u8]) -> Result<(Request, usize), ParseError> {
-    // Look for header/body separator
-    let headers_end = find_headers_end(buf).ok_or(ParseError::Incomplete)?;
-    let header_bytes = &buf[..headers_end];
-    let body_bytes = &buf[headers_end + 4..];
-
-    let headers_str = std::str::from_utf8(header_bytes).map_err(|_| ParseError::InvalidRequest)?;
+    parse_http_request_with_mode(buf, ParseMode::Strict)
+}
 
-    let mut lines = headers_str.split("\r\n");
+/// Parses an HTTP request like [`parse_http_request`], in the given mode.
+pub fn parse_http_request_with_mode(
+    buf: &[u8],
+    mode: ParseMode,
+) -> Result<(Request, usize), ParseError> {
+    RequestParser::new(mode, HeadLimits::NONE).parse(buf)
+}
 
-    // Request line
-    let request_line = lines.next().ok_or(ParseError::InvalidRequest);
-    let mut parts = request_line?.split_whitespace();
+/// Parses the request line and headers, leaving the body empty.
+///
+/// Returns the request and the length of the head (including the blank
+/// line), so callers can read a large body separately, e.g. into a
+/// [`spool`](crate::http::spool) file.
+///
+/// Anything that would let Sentinel and a backend disagree about where the
+/// request ends is rejected (RFC 9112): control characters such as a bare
+/// CR, header names that are not tokens (including whitespace before the
+/// colon or folded lines), a Content-Length that is not a plain number or
+/// is repeated, Transfer-Encoding alongside Content-Length, and any
+/// Transfer-Encoding other than a single `chunked` on an HTTP/1.1 request.
+/// Content-Length, Transfer-Encoding, and Host are stored under those
+/// spellings whatever case the client used, and Host is checked with
+/// [`check_host`].
+pub fn parse_request_head(buf: &[u8]) -> Result<(Request, usize), ParseError> {
+    parse_request_head_with_mode(buf, ParseMode::Strict)
+}
 
-    let method_str = parts.next().ok_or(ParseError::InvalidRequest)?;
-    let path = parts.next().ok_or(ParseError::InvalidRequest)?;
-    let version = parts.next().ok_or(ParseError::InvalidRequest)?;
+/// Parses the request line and headers like [`parse_request_head`], in the
+/// given mode.
+///
+/// In [`ParseMode::Lenient`] the head is first rewritten with CRLF line
+/// endings and folded lines joined, then checked as strictly as ever; a
+/// bare CR is still rejected.
+///
+/// # Example
+///
+/// ```
+/// # use sentinel::http::parser::{ParseMode, parse_request_head_with_mode};
+/// let head = b"GET / HTTP/1.0\nX-Note: one\n two\n\n";
+/// let (request, len) = parse_request_head_with_mode(head, ParseMode::Lenient).unwrap();
+/// assert_eq!(request.headers["X-Note"], "one two");
+/// assert_eq!(len, head.len());
+/// assert!(parse_request_head_with_mode(head, ParseMode::Strict).is_err());
+/// ```
+pub fn parse_request_head_with_mode(
+    buf: &[u8],
+    mode: ParseMode,
+) -> Result<(Request, usize), ParseError> {
+    let mut parser = RequestParser::new(mode, HeadLimits::NONE);
+    parser.head(buf)?;
+    parser.take_head().ok_or(ParseError::Incomplete)
+}
 
-    let method = Method::from_str(method_str).ok_or(ParseError::InvalidMethod)?;
+/// Parse a complete head, ending in a blank line with CRLF line endings
+///
+/// httparse checks the request line, header names, and header values;
+/// `lines` bounds the number of headers.
+fn parse_head(head: &[u8], lines: usize) -> Result<Request, ParseError> {
+    // httparse also accepts bare LF line endings
+    let bare_lf = head
+        .iter()
+        .enumerate()
+        .any(|(i, &b)| b == b'\n' && (i == 0 || head[i - 1] != b'\r'));
+    if bare_lf {
+        return Err(ParseError::InvalidRequest);
+    }
 
-    // Headers
-    let mut headers = HashMap::new();
+    let mut inline = [httparse::EMPTY_HEADER; INLINE_HEADERS];
+    let mut spilled;
+    let slots: &mut [httparse::Header] = if lines <= INLINE_HEADERS {
+        &mut inline
+    } else {
+        spilled = vec![httparse::EMPTY_HEADER; lines];
+        &mut spilled
+    };
 
-    for line in lines {
-        if line.is_empty() {
-            continue;
+    let mut parsed = httparse::Request::new(slots);
+    match parsed.parse(head) {
+        Ok(httparse::Status::Complete(_)) => {}
+        Ok(httparse::Status::Partial) => return Err(ParseError::Incomplete),
+        Err(httparse::Error::HeaderName | httparse::Error::HeaderValue) => {
+            return Err(ParseError::InvalidHeader);
         }
+        Err(httparse::Error::TooManyHeaders) => return Err(ParseError::HeadersTooLarge),
+        Err(_) => return Err(ParseError::InvalidRequest),
+    }
+
+    let method = parsed.method.ok_or(ParseError::InvalidRequest)?;
+    let method = Method::from_str(method).ok_or(ParseError::InvalidMethod)?;
+    let path = parsed.path.ok_or(ParseError::InvalidRequest)?;
+    let version = match parsed.version {
+        Some(0) => "HTTP/1.0",
+        Some(1) => "HTTP/1.1",
+        _ => return Err(ParseError::InvalidRequest),
+    };
 
-        let (key, value) = line.split_once(':').ok_or(ParseError::InvalidHeader)?;
+    let mut headers = HashMap::with_capacity(parsed.headers.len());
+    let mut content_length = false;
+    let mut transfer_encoding = false;
+    let mut host = false;
 
-        headers.insert(key.trim().to_string(), value.trim().to_string());
-    }
+    for header in parsed.headers.iter() {
+        let value = std::str::from_utf8(header.value).map_err(|_| ParseError::InvalidHeader)?;
+        let value = value.trim_matches([' ', '\t']);
 
-    // Body
-    let content_length = headers
-        .get("Content-Length")
-        .map(|v| {
-            v.parse::<usize>()
-                .map_err(|_| ParseError::InvalidContentLength)
-        })
-        .transpose()?
-        .unwrap_or(0);
+        let key = header.name;
+        let key = if key.eq_ignore_ascii_case("Content-Length") {
+            if content_length || value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(ParseError::InvalidContentLength);
+            }
+            value
+                .parse::<usize>()
+                .map_err(|_| ParseError::InvalidContentLength)?;
+            content_length = true;
+            "Content-Length"
+        } else if key.eq_ignore_ascii_case("Transfer-Encoding") {
+            if transfer_encoding {
+                return Err(ParseError::UnsupportedTransferEncoding);
+            }
+            transfer_encoding = true;
+            "Transfer-Encoding"
+        } else if key.eq_ignore_ascii_case("Host") {
+            if host {
+                return Err(ParseError::InvalidHost);
+            }
+            host = true;
+            "Host"
+        } else {
+            key
+        };
 
-    if body_bytes.len() < content_length {
-        return Err(ParseError::Incomplete);
+        headers.insert(key.to_string(), value.to_string());
     }
 
-    let body = body_bytes[..content_length].to_vec();
+    if transfer_encoding {
+        if content_length {
+            return Err(ParseError::ConflictingLength);
+        }
+        // HTTP/1.0 has no chunked framing, and no other coding can be
+        // framed at all
+        let chunked = headers["Transfer-Encoding"].eq_ignore_ascii_case("chunked");
+        if !chunked || version != "HTTP/1.1" {
+            return Err(ParseError::UnsupportedTransferEncoding);
+        }
+    }
 
     let request = Request {
         method,
         path: path.to_string(),
         version: version.to_string(),
         headers,
-        body,
+        body: Vec::new(),
+        spooled: None,
+        context: RequestContext::default(),
+    };
+    check_host(&request)?;
+
+    Ok(request)
+}
+
+/// Check the Host header of a parsed request (RFC 9112, section 3.2)
+///
+/// HTTP/1.1 requests must have one; HTTP/1.0 and HTTP/2 requests may omit
+/// it. When present it must be a host (a name, IPv4 address, or bracketed
+/// IPv6 address) with an optional port, or empty.
+///
+/// # Example
+///
+/// ```
+/// # use sentinel::http::parser::check_host;
+/// # use sentinel::http::request::{Method, RequestBuilder};
+/// let request = |host: &str| {
+///     RequestBuilder::new().method(Method::GET).path("/").header("Host", host).build().unwrap()
+/// };
+/// assert!(check_host(&request("example.com:8080")).is_ok());
+/// assert!(check_host(&request("[::1]:8080")).is_ok());
+/// assert!(check_host(&request("evil.com/path")).is_err());
+/// assert!(check_host(&request("a b")).is_err());
+/// ```
+pub fn check_host(request: &Request) -> Result<(), ParseError> {
+    let Some(host) = request
+        .headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case("Host"))
+        .map(|(_, v)| v.as_str())
+    else {
+        return if request.version == "HTTP/1.1" {
+            Err(ParseError::InvalidHost)
+        } else {
+            Ok(())
+        };
+    };
+
+    let (valid_name, port) = match host.strip_prefix('[') {
+        Some(rest) => {
+            let (ip, rest) = rest.split_once(']').ok_or(ParseError::InvalidHost)?;
+            let port = match rest {
+                "" => "",
+                rest => rest.strip_prefix(':').ok_or(ParseError::InvalidHost)?,
+            };
+            (ip.parse::<std::net::Ipv6Addr>().is_ok(), port)
+        }
+        None => {
+            let (name, port) = host.split_once(':').unwrap_or((host, ""));
+            (name.bytes().all(is_reg_name_char), port)
+        }
     };
 
-    let total_consumed = headers_end + 4 + content_length;
-    Ok((request, total_consumed))
+    if valid_name && port.bytes().all(|b| b.is_ascii_digit()) {
+        Ok(())
+    } else {
+        Err(ParseError::InvalidHost)
+    }
+}
+
+/// Whether `b` may appear in a host name (RFC 3986 reg-name)
+fn is_reg_name_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric()
+        || matches!(
+            b,
+            b'-' | b'.'
+                | b'_'
+                | b'~'
+                | b'%'
+                | b'!'
+                | b'$'
+                | b'&'
+                | b'\''
+                | b'('
+                | b')'
+                | b'*'
+                | b'+'
+                | b','
+                | b';'
+                | b'='
+        )
+}
+
+/// Longest chunk size line, extensions included
+const MAX_CHUNK_LINE: usize = 1024;
+
+/// Progress through a chunked request body (RFC 9112, section 7.1)
+#[derive(Debug, Default)]
+struct ChunkedBody {
+    /// Offset in the body of the first chunk not yet decoded
+    next: usize,
+    /// Data of the chunks decoded so far
+    data: Vec<u8>,
+}
+
+impl ChunkedBody {
+    /// Decode the chunks of `body` that have arrived since the last call
+    ///
+    /// Returns the length of the chunked body, through its last chunk and
+    /// trailers, once all of it has arrived. Trailers are checked against
+    /// the head size limit and dropped; lines must end in CRLF.
+    fn decode(&mut self, body: &[u8], limits: HeadLimits) -> Result<Option<usize>, ParseError> {
+        loop {
+            let Some(line) = chunk_line(&body[self.next..], MAX_CHUNK_LINE)? else {
+                return Ok(None);
+            };
+            let size = chunk_size(&body[self.next..self.next + line])?;
+            let start = self.next + line + 2;
+
+            if size == 0 {
+                // Trailers end with an empty line
+                let mut pos = start;
+                loop {
+                    let max = limits.max_header_bytes.saturating_sub(pos - start);
+                    let Some(line) = chunk_line(&body[pos..], max)? else {
+                        return Ok(None);
+                    };
+                    pos += line + 2;
+                    if line == 0 {
+                        return Ok(Some(pos));
+                    }
+                }
+            }
+
+            let end = start.checked_add(size).ok_or(ParseError::InvalidRequest)?;
+            if body.len() < end.saturating_add(2) {
+                return Ok(None);
+            }
+            if &body[end..end + 2] != b"\r\n" {
+                return Err(ParseError::InvalidRequest);
+            }
+            self.data.extend_from_slice(&body[start..end]);
+            self.next = end + 2;
+        }
+    }
+}
+
+/// Length of the CRLF-terminated line at the start of `data`, once it has
+/// arrived
+///
+/// A bare CR or LF is rejected, as is a line longer than `max` (with
+/// [`ParseError::HeadersTooLarge`]).
+fn chunk_line(data: &[u8], max: usize) -> Result<Option<usize>, ParseError> {
+    for (i, &b) in data.iter().enumerate() {
+        match b {
+            b'\r' => {
+                return match data.get(i + 1) {
+                    Some(b'\n') => Ok(Some(i)),
+                    Some(_) => Err(ParseError::InvalidRequest),
+                    None => Ok(None),
+                };
+            }
+            b'\n' => return Err(ParseError::InvalidRequest),
+            _ if i >= max => return Err(ParseError::HeadersTooLarge),
+            _ => {}
+        }
+    }
+    Ok(None)
 }
 
-fn find_headers_end(buf: &[u8]) -> Option<usize> {
-    buf.windows(4).position(|w| w == b"\r\n\r\n")
+/// The size of a chunk from its size line, ignoring extensions
+///
+/// Only hex digits are accepted, unlike `from_str_radix`, which also takes
+/// a sign.
+fn chunk_size(line: &[u8]) -> Result<usize, ParseError> {
+    let size = match line.iter().position(|&b| b == b';') {
+        Some(ext) => line[..ext].trim_ascii_end(),
+        None => line,
+    };
+    if size.is_empty() || size.len() > 15 || !size.iter().all(u8::is_ascii_hexdigit) {
+        return Err(ParseError::InvalidRequest);
+    }
+    let size = std::str::from_utf8(size).map_err(|_| ParseError::InvalidRequest)?;
+    usize::from_str_radix(size, 16).map_err(|_| ParseError::InvalidRequest)
+}
+
+/// Rewrite a head with CRLF line endings, joining folded lines onto the
+/// header they continue (RFC 9112, section 5.2)
+fn unfold(head: &[u8]) -> Result<Vec<u8>, ParseError> {
+    let mut out: Vec<u8> = Vec::with_capacity(head.len() + 4);
+    for (i, line) in head.split(|&b| b == b'\n').enumerate() {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if matches!(line.first(), Some(b' ' | b'\t')) {
+            // A fold cannot continue the request line
+            if i < 2 {
+                return Err(ParseError::InvalidHeader);
+            }
+            let rest = line.trim_ascii_start();
+            out.truncate(out.len() - 2);
+            while out.last().is_some_and(|&b| b == b' ' || b == b'\t') {
+                out.pop();
+            }
+            if !rest.is_empty() {
+                out.push(b' ');
+                out.extend_from_slice(rest);
+            }
+        } else {
+            out.extend_from_slice(line);
+        }
+        out.extend_from_slice(b"\r\n");
+    }
+    out.extend_from_slice(b"\r\n");
+    Ok(out)
 }
@@ -1,4 +1,8 @@
+use crate::http::context::RequestContext;
+use crate::http::cookie::parse_cookies;
+use crate::http::spool::SpooledBody;
 use std::collections::HashMap;
+use std::sync::Arc;
 
 /// HTTP request methods.
 ///
@@ -20,6 +24,16 @@ pub enum Method {
     OPTIONS,
     /// PATCH - Partial modification of a resource
     PATCH,
+    /// CONNECT - Open a tunnel to the host:port given as the path
+    CONNECT,
+    /// PROPFIND - Retrieve resource properties (WebDAV)
+    PROPFIND,
+    /// MKCOL - Create a collection (WebDAV)
+    MKCOL,
+    /// COPY - Copy a resource to the `Destination` URL (WebDAV)
+    COPY,
+    /// MOVE - Move a resource to the `Destination` URL (WebDAV)
+    MOVE,
 }
 
 /// Represents a parsed HTTP request from a client.
@@ -38,6 +52,11 @@ pub struct Request {
     pub headers: HashMap<String, String>,
     /// Request body for POST/PUT requests
     pub body: Vec<u8>,
+    /// Body streamed to disk instead of `body` when it exceeded the memory
+    /// limit (see [`spool`](crate::http::spool))
+    pub spooled: Option<Arc<SpooledBody>>,
+    /// Cancellation and deadline state (not part of the HTTP message)
+    pub context: RequestContext,
 }
 
 /// Builder for constructing Request objects.
@@ -67,6 +86,7 @@ impl Method {
     /// assert_eq!(Method::from_str("GET"), Some(Method::GET));
     /// assert_eq!(Method::from_str("get"), None);
     /// ```
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Option<Self> {
         match s {
             "GET" => Some(Method::GET),
@@ -76,11 +96,22 @@ impl Method {
             "HEAD" => Some(Method::HEAD),
             "OPTIONS" => Some(Method::OPTIONS),
             "PATCH" => Some(Method::PATCH),
+            "CONNECT" => Some(Method::CONNECT),
+            "PROPFIND" => Some(Method::PROPFIND),
+            "MKCOL" => Some(Method::MKCOL),
+            "COPY" => Some(Method::COPY),
+            "MOVE" => Some(Method::MOVE),
             _ => None,
         }
     }
 }
 
+impl Default for RequestBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl RequestBuilder {
     pub fn new() -> Self {
         Self {
@@ -124,6 +155,8 @@ impl RequestBuilder {
             version: self.version.unwrap_or_else(|| "HTTP/1.1".to_string()),
             headers: self.headers,
             body: self.body,
+            spooled: None,
+            context: RequestContext::default(),
         })
     }
 }
@@ -142,6 +175,62 @@ impl Request {
         self.headers.get(key).map(|v| v.as_str())
     }
 
+    /// The host the client addressed, from the Host header (including any
+    /// port).
+    ///
+    /// `None` if the header is missing or empty, as HTTP/1.0 clients may
+    /// send; such requests belong to the default virtual host.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use sentinel::http::request::{Method, RequestBuilder};
+    /// let req = RequestBuilder::new()
+    ///     .method(Method::GET)
+    ///     .path("/")
+    ///     .header("host", "example.com:8080")
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(req.host(), Some("example.com:8080"));
+    /// ```
+    pub fn host(&self) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("Host"))
+            .map(|(_, v)| v.trim())
+            .filter(|host| !host.is_empty())
+    }
+
+    /// Cookies sent with the request, by name.
+    ///
+    /// If a name is sent more than once, the first value wins (browsers
+    /// send the cookie with the most specific path first).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use sentinel::http::request::{Method, RequestBuilder};
+    /// let req = RequestBuilder::new()
+    ///     .method(Method::GET)
+    ///     .path("/")
+    ///     .header("Cookie", "session=abc; theme=dark")
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(req.cookies().get("session"), Some(&"abc"));
+    /// ```
+    pub fn cookies(&self) -> HashMap<&str, &str> {
+        let mut cookies = HashMap::new();
+        for (name, value) in self
+            .headers
+            .iter()
+            .filter(|(k, _)| k.eq_ignore_ascii_case("Cookie"))
+            .flat_map(|(_, value)| parse_cookies(value))
+        {
+            cookies.entry(name).or_insert(value);
+        }
+        cookies
+    }
+
     /// Retrieves the Content-Length header value and parses it as a usize.
     ///
     /// Returns 0 if the header is missing or not a valid number.
@@ -151,6 +240,14 @@ impl Request {
             .unwrap_or(0)
     }
 
+    /// Length of the body, in memory or spooled to disk
+    pub fn body_len(&self) -> u64 {
+        match &self.spooled {
+            Some(spooled) => spooled.len(),
+            None => self.body.len() as u64,
+        }
+    }
+
     /// Determines whether the connection should remain open after the response.
     ///
     /// Checks the Connection header. For HTTP/1.1, the default is `true` (keep-alive).
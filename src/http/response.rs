@@ -1,42 +1,121 @@
+use crate::http::cookie::{Cookie, SET_COOKIE_SEPARATOR};
+use bytes::Bytes;
 use std::collections::HashMap;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
 
-/// HTTP status codes supported by the server.
+/// Body data still arriving after a response head has been sent
 ///
-/// Common HTTP status codes used in responses:
+/// Each piece is passed on to the client as soon as it is received. The
+/// body ends when the sender is dropped.
+pub type BodyStream = mpsc::Receiver<Bytes>;
+
+/// HTTP status codes.
+///
+/// Common HTTP status codes have a variant of their own:
 /// - `Ok` (200): Request successful
 /// - `Created` (201): Resource created successfully
 /// - `NoContent` (204): Successful request with no content
+/// - `MovedPermanently` (301): Resource moved; clients may switch to GET
+/// - `NotModified` (304): Cached copy is still fresh
+/// - `PermanentRedirect` (308): Resource moved; method and body are kept
 /// - `BadRequest` (400): Malformed request
+/// - `Unauthorized` (401): Credentials missing or wrong
+/// - `Forbidden` (403): Request understood but refused
 /// - `NotFound` (404): Resource not found
 /// - `MethodNotAllowed` (405): HTTP method not supported
+/// - `ProxyAuthenticationRequired` (407): Proxy credentials missing or wrong
+/// - `UriTooLong` (414): Request target longer than the server accepts
+/// - `UnprocessableEntity` (422): Well-formed request that cannot be processed
+/// - `TooManyRequests` (429): Client is being rate limited
+/// - `RequestHeaderFieldsTooLarge` (431): Request headers over the limits
 /// - `InternalServerError` (500): Server error
 /// - `BadGateway` (502): Bad response from upstream server
 /// - `ServiceUnavailable` (503): Service temporarily unavailable
 /// - `GatewayTimeout` (504): Upstream server timeout
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+///
+/// Any other code is carried as `Other`. Codes compare by number, so
+/// `Other(404)` equals `NotFound`.
+#[derive(Debug, Clone, Copy)]
 pub enum StatusCode {
     /// 200 OK
     Ok,
     /// 201 Created
     Created,
+    /// 202 Accepted
+    Accepted,
     /// 204 No Content
     NoContent,
+    /// 206 Partial Content
+    PartialContent,
+    /// 207 Multi-Status (WebDAV)
+    MultiStatus,
+    /// 301 Moved Permanently
+    MovedPermanently,
+    /// 302 Found
+    Found,
+    /// 303 See Other
+    SeeOther,
+    /// 304 Not Modified
+    NotModified,
+    /// 307 Temporary Redirect
+    TemporaryRedirect,
+    /// 308 Permanent Redirect
+    PermanentRedirect,
     /// 400 Bad Request
     BadRequest,
+    /// 401 Unauthorized
+    Unauthorized,
+    /// 403 Forbidden
+    Forbidden,
     /// 404 Not Found
     NotFound,
     /// 405 Method Not Allowed
     MethodNotAllowed,
+    /// 407 Proxy Authentication Required
+    ProxyAuthenticationRequired,
+    /// 408 Request Timeout
+    RequestTimeout,
+    /// 409 Conflict
+    Conflict,
+    /// 410 Gone
+    Gone,
+    /// 412 Precondition Failed
+    PreconditionFailed,
+    /// 413 Payload Too Large
+    PayloadTooLarge,
+    /// 414 URI Too Long
+    UriTooLong,
+    /// 415 Unsupported Media Type
+    UnsupportedMediaType,
+    /// 422 Unprocessable Entity
+    UnprocessableEntity,
+    /// 429 Too Many Requests
+    TooManyRequests,
+    /// 431 Request Header Fields Too Large
+    RequestHeaderFieldsTooLarge,
     /// 500 Internal Server Error
     InternalServerError,
+    /// 501 Not Implemented
+    NotImplemented,
     /// 502 Bad Gateway
     BadGateway,
     /// 503 Service Unavailable
     ServiceUnavailable,
     /// 504 Gateway Timeout
     GatewayTimeout,
+    /// Any other code from 100 to 599
+    Other(u16),
+}
+
+impl PartialEq for StatusCode {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_u16() == other.as_u16()
+    }
 }
 
+impl Eq for StatusCode {}
+
 impl StatusCode {
     /// Returns the numeric HTTP status code.
     ///
@@ -46,47 +125,199 @@ impl StatusCode {
     /// # use sentinel::http::response::StatusCode;
     /// assert_eq!(StatusCode::Ok.as_u16(), 200);
     /// assert_eq!(StatusCode::NotFound.as_u16(), 404);
+    /// assert_eq!(StatusCode::Other(418).as_u16(), 418);
     /// ```
     pub fn as_u16(&self) -> u16 {
         match self {
             StatusCode::Ok => 200,
             StatusCode::Created => 201,
+            StatusCode::Accepted => 202,
             StatusCode::NoContent => 204,
+            StatusCode::PartialContent => 206,
+            StatusCode::MultiStatus => 207,
+            StatusCode::MovedPermanently => 301,
+            StatusCode::Found => 302,
+            StatusCode::SeeOther => 303,
+            StatusCode::NotModified => 304,
+            StatusCode::TemporaryRedirect => 307,
+            StatusCode::PermanentRedirect => 308,
             StatusCode::BadRequest => 400,
+            StatusCode::Unauthorized => 401,
+            StatusCode::Forbidden => 403,
             StatusCode::NotFound => 404,
             StatusCode::MethodNotAllowed => 405,
+            StatusCode::ProxyAuthenticationRequired => 407,
+            StatusCode::RequestTimeout => 408,
+            StatusCode::Conflict => 409,
+            StatusCode::Gone => 410,
+            StatusCode::PreconditionFailed => 412,
+            StatusCode::PayloadTooLarge => 413,
+            StatusCode::UriTooLong => 414,
+            StatusCode::UnsupportedMediaType => 415,
+            StatusCode::UnprocessableEntity => 422,
+            StatusCode::TooManyRequests => 429,
+            StatusCode::RequestHeaderFieldsTooLarge => 431,
             StatusCode::InternalServerError => 500,
+            StatusCode::NotImplemented => 501,
             StatusCode::BadGateway => 502,
             StatusCode::ServiceUnavailable => 503,
             StatusCode::GatewayTimeout => 504,
+            StatusCode::Other(code) => *code,
         }
     }
 
+    /// Looks up a status code by its numeric value.
+    ///
+    /// Codes with a variant of their own get it; any other code from 100 to
+    /// 599 becomes `Other`. Values outside that range are not status codes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use sentinel::http::response::StatusCode;
+    /// assert_eq!(StatusCode::from_u16(503), Some(StatusCode::ServiceUnavailable));
+    /// assert_eq!(StatusCode::from_u16(299), Some(StatusCode::Other(299)));
+    /// assert_eq!(StatusCode::from_u16(600), None);
+    /// ```
+    pub fn from_u16(code: u16) -> Option<Self> {
+        let status = match code {
+            200 => StatusCode::Ok,
+            201 => StatusCode::Created,
+            202 => StatusCode::Accepted,
+            204 => StatusCode::NoContent,
+            206 => StatusCode::PartialContent,
+            207 => StatusCode::MultiStatus,
+            301 => StatusCode::MovedPermanently,
+            302 => StatusCode::Found,
+            303 => StatusCode::SeeOther,
+            304 => StatusCode::NotModified,
+            307 => StatusCode::TemporaryRedirect,
+            308 => StatusCode::PermanentRedirect,
+            400 => StatusCode::BadRequest,
+            401 => StatusCode::Unauthorized,
+            403 => StatusCode::Forbidden,
+            404 => StatusCode::NotFound,
+            405 => StatusCode::MethodNotAllowed,
+            407 => StatusCode::ProxyAuthenticationRequired,
+            408 => StatusCode::RequestTimeout,
+            409 => StatusCode::Conflict,
+            410 => StatusCode::Gone,
+            412 => StatusCode::PreconditionFailed,
+            413 => StatusCode::PayloadTooLarge,
+            414 => StatusCode::UriTooLong,
+            415 => StatusCode::UnsupportedMediaType,
+            422 => StatusCode::UnprocessableEntity,
+            429 => StatusCode::TooManyRequests,
+            431 => StatusCode::RequestHeaderFieldsTooLarge,
+            500 => StatusCode::InternalServerError,
+            501 => StatusCode::NotImplemented,
+            502 => StatusCode::BadGateway,
+            503 => StatusCode::ServiceUnavailable,
+            504 => StatusCode::GatewayTimeout,
+            100..=599 => StatusCode::Other(code),
+            _ => return None,
+        };
+        Some(status)
+    }
+
     /// Returns the standard HTTP reason phrase for this status code.
     ///
+    /// Codes missing from the IANA registry have an empty reason phrase.
+    ///
     /// # Example
     ///
     /// ```
     /// # use sentinel::http::response::StatusCode;
     /// assert_eq!(StatusCode::Ok.reason_phrase(), "OK");
     /// assert_eq!(StatusCode::NotFound.reason_phrase(), "Not Found");
+    /// assert_eq!(StatusCode::Other(418).reason_phrase(), "I'm a teapot");
+    /// assert_eq!(StatusCode::Other(299).reason_phrase(), "");
     /// ```
     pub fn reason_phrase(&self) -> &'static str {
-        match self {
-            StatusCode::Ok => "OK",
-            StatusCode::Created => "Created",
-            StatusCode::NoContent => "No Content",
-            StatusCode::BadRequest => "Bad Request",
-            StatusCode::BadGateway => "Bad Gateway",
-            StatusCode::ServiceUnavailable => "Service Unavailable",
-            StatusCode::GatewayTimeout => "Gateway Timeout",
-            StatusCode::NotFound => "Not Found",
-            StatusCode::MethodNotAllowed => "Method Not Allowed",
-            StatusCode::InternalServerError => "Internal Server Error",
+        match self.as_u16() {
+            100 => "Continue",
+            101 => "Switching Protocols",
+            102 => "Processing",
+            103 => "Early Hints",
+            200 => "OK",
+            201 => "Created",
+            202 => "Accepted",
+            203 => "Non-Authoritative Information",
+            204 => "No Content",
+            205 => "Reset Content",
+            206 => "Partial Content",
+            207 => "Multi-Status",
+            208 => "Already Reported",
+            226 => "IM Used",
+            300 => "Multiple Choices",
+            301 => "Moved Permanently",
+            302 => "Found",
+            303 => "See Other",
+            304 => "Not Modified",
+            305 => "Use Proxy",
+            307 => "Temporary Redirect",
+            308 => "Permanent Redirect",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            402 => "Payment Required",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            406 => "Not Acceptable",
+            407 => "Proxy Authentication Required",
+            408 => "Request Timeout",
+            409 => "Conflict",
+            410 => "Gone",
+            411 => "Length Required",
+            412 => "Precondition Failed",
+            413 => "Payload Too Large",
+            414 => "URI Too Long",
+            415 => "Unsupported Media Type",
+            416 => "Range Not Satisfiable",
+            417 => "Expectation Failed",
+            418 => "I'm a teapot",
+            421 => "Misdirected Request",
+            422 => "Unprocessable Entity",
+            423 => "Locked",
+            424 => "Failed Dependency",
+            425 => "Too Early",
+            426 => "Upgrade Required",
+            428 => "Precondition Required",
+            429 => "Too Many Requests",
+            431 => "Request Header Fields Too Large",
+            451 => "Unavailable For Legal Reasons",
+            500 => "Internal Server Error",
+            501 => "Not Implemented",
+            502 => "Bad Gateway",
+            503 => "Service Unavailable",
+            504 => "Gateway Timeout",
+            505 => "HTTP Version Not Supported",
+            506 => "Variant Also Negotiates",
+            507 => "Insufficient Storage",
+            508 => "Loop Detected",
+            510 => "Not Extended",
+            511 => "Network Authentication Required",
+            _ => "",
         }
     }
 }
 
+/// How the connection delivers a response to the client.
+///
+/// - `Send`: Write the response and honor keep-alive (the default)
+/// - `SendAndClose`: Write the response, then close the connection
+/// - `Abort`: Close the connection without writing anything
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Disposition {
+    /// Write the response normally
+    #[default]
+    Send,
+    /// Write the response, then close the connection
+    SendAndClose,
+    /// Drop the connection without writing a response
+    Abort,
+}
+
 /// Represents a complete HTTP response ready to be sent to a client.
 ///
 /// Contains the HTTP status code, headers, and response body.
@@ -94,10 +325,27 @@ impl StatusCode {
 pub struct Response {
     /// The HTTP status code
     pub status: StatusCode,
+    /// Reason phrase sent instead of the standard one for `status`, such as
+    /// the one a backend sent
+    pub reason: Option<String>,
     /// HTTP headers as key-value pairs
     pub headers: HashMap<String, String>,
     /// Response body as bytes
     pub body: Vec<u8>,
+    /// How the connection should deliver this response
+    pub disposition: Disposition,
+    /// Upstream connection to splice the client onto once this response
+    /// is written (a successful `CONNECT`)
+    pub tunnel: Option<TcpStream>,
+    /// Rest of the body, sent after `body` without buffering (event
+    /// streams and long polls); the response has no `Content-Length`
+    pub stream: Option<BodyStream>,
+    /// Set on errors Sentinel produced itself rather than relayed from a
+    /// backend; configured error pages replace the body of these
+    pub generated: bool,
+    /// Explanation of a generated error, kept so the body can be rendered
+    /// again in the format the client prefers
+    pub detail: String,
 }
 
 /// Builder for constructing HTTP responses in a fluent style.
@@ -112,6 +360,7 @@ pub struct Response {
 /// ```
 pub struct ResponseBuilder {
     status: StatusCode,
+    reason: Option<String>,
     headers: HashMap<String, String>,
     body: Vec<u8>,
 }
@@ -121,11 +370,26 @@ impl ResponseBuilder {
     pub fn new(status: StatusCode) -> Self {
         Self {
             status,
+            reason: None,
             headers: HashMap::new(),
             body: Vec::new(),
         }
     }
 
+    /// Sends `reason` instead of the standard reason phrase.
+    ///
+    /// Characters not allowed in a status line are dropped.
+    pub fn reason(mut self, reason: impl Into<String>) -> Self {
+        let reason: String = reason.into();
+        self.reason = Some(
+            reason
+                .chars()
+                .filter(|c| *c == '\t' || (!c.is_control() && c.is_ascii()))
+                .collect(),
+        );
+        self
+    }
+
     /// Adds or replaces a header.
     ///
     /// # Arguments
@@ -149,6 +413,27 @@ impl ResponseBuilder {
         self.header(key, value)
     }
 
+    /// Adds a `Set-Cookie` header, keeping cookies already set.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use sentinel::http::cookie::{Cookie, SameSite};
+    /// # use sentinel::http::response::{Response, StatusCode};
+    /// let response = Response::new(StatusCode::Ok)
+    ///     .cookie(Cookie::new("session", "abc").http_only())
+    ///     .cookie(Cookie::new("theme", "dark").same_site(SameSite::Lax))
+    ///     .build();
+    /// assert_eq!(
+    ///     response.headers["Set-Cookie"],
+    ///     "session=abc; HttpOnly\ntheme=dark; SameSite=Lax"
+    /// );
+    /// ```
+    pub fn cookie(mut self, cookie: Cookie) -> Self {
+        add_cookie(&mut self.headers, &cookie);
+        self
+    }
+
     /// Sets multiple headers at once.
     pub fn with_headers(mut self, headers: HashMap<String, String>) -> Self {
         self.headers = headers;
@@ -177,18 +462,48 @@ impl ResponseBuilder {
 
         Response {
             status: self.status,
+            reason: self.reason,
             headers: self.headers,
             body: self.body,
+            disposition: Disposition::Send,
+            tunnel: None,
+            stream: None,
+            generated: false,
+            detail: String::new(),
+        }
+    }
+}
+
+/// Append `cookie` to the `Set-Cookie` entry in `headers`, whatever its case
+fn add_cookie(headers: &mut HashMap<String, String>, cookie: &Cookie) {
+    let existing = headers
+        .keys()
+        .find(|k| k.eq_ignore_ascii_case("Set-Cookie"))
+        .cloned();
+    match existing.and_then(|k| headers.get_mut(&k)) {
+        Some(value) => {
+            value.push(SET_COOKIE_SEPARATOR);
+            value.push_str(&cookie.to_string());
+        }
+        None => {
+            headers.insert("Set-Cookie".to_string(), cookie.to_string());
         }
     }
 }
 
 impl Response {
     /// Creates a new response builder with the specified status code.
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(status: StatusCode) -> ResponseBuilder {
         ResponseBuilder::new(status)
     }
 
+    /// Adds a `Set-Cookie` header, keeping cookies already set (e.g. by a
+    /// backend). See [`ResponseBuilder::cookie`].
+    pub fn add_cookie(&mut self, cookie: Cookie) {
+        add_cookie(&mut self.headers, &cookie);
+    }
+
     /// Creates a simple 200 OK response with the given body.
     pub fn ok(body: impl Into<Vec<u8>>) -> Self {
         ResponseBuilder::new(StatusCode::Ok)
@@ -198,15 +513,68 @@ impl Response {
 
     /// Creates a 404 Not Found response.
     pub fn not_found() -> Self {
-        ResponseBuilder::new(StatusCode::NotFound)
-            .body(b"404 Not Found".to_vec())
-            .build()
+        Self::error(StatusCode::NotFound, "")
+    }
+
+    /// Creates an error response generated by Sentinel.
+    ///
+    /// The plain-text body is the status line, followed by `detail` if it
+    /// is not empty. The response is marked [`generated`](Response::generated)
+    /// so configured error pages can replace the body.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use sentinel::http::response::{Response, StatusCode};
+    /// let response = Response::error(StatusCode::BadGateway, "");
+    /// assert_eq!(response.body, b"502 Bad Gateway".to_vec());
+    /// assert!(response.generated);
+    /// ```
+    pub fn error(status: StatusCode, detail: &str) -> Self {
+        let mut body = format!("{} {}", status.as_u16(), status.reason_phrase())
+            .trim_end()
+            .to_string();
+        if !detail.is_empty() {
+            body.push_str("\r\n\r\n");
+            body.push_str(detail);
+        }
+        let mut response = ResponseBuilder::new(status)
+            .header("Content-Type", "text/plain")
+            .body(body.into_bytes())
+            .build();
+        response.generated = true;
+        response.detail = detail.to_string();
+        response
+    }
+
+    /// Creates a response that closes the connection without being written.
+    pub fn abort() -> Self {
+        let mut response = ResponseBuilder::new(StatusCode::InternalServerError).build();
+        response.disposition = Disposition::Abort;
+        response
+    }
+
+    /// Creates a `200 OK` response to a `CONNECT` request that hands the
+    /// client connection over to `upstream`.
+    ///
+    /// The response has no body and no `Content-Length`; after it is
+    /// written, bytes are copied in both directions until either side closes.
+    pub fn tunnel(upstream: TcpStream) -> Self {
+        Response {
+            status: StatusCode::Ok,
+            reason: None,
+            headers: HashMap::new(),
+            body: Vec::new(),
+            disposition: Disposition::SendAndClose,
+            tunnel: Some(upstream),
+            stream: None,
+            generated: false,
+            detail: String::new(),
+        }
     }
 
     /// Creates a 500 Internal Server Error response.
     pub fn internal_error() -> Self {
-        ResponseBuilder::new(StatusCode::InternalServerError)
-            .body(b"500 Internal Server Error".to_vec())
-            .build()
+        Self::error(StatusCode::InternalServerError, "")
     }
 }
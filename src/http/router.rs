@@ -0,0 +1,236 @@
+//! Request routing
+//!
+//! The [`Router`] dispatches requests to handlers registered for exact paths,
+//! regular expressions, or path prefixes, falling back to a default handler
+//! (typically static files or the reverse proxy) when nothing matches. A
+//! route may also require a [`Predicate`] on the method or headers.
+
+use crate::http::handler::Handler;
+use crate::http::request::{Method, Request};
+use crate::http::response::Response;
+use async_trait::async_trait;
+use regex::Regex;
+use std::sync::Arc;
+
+/// A condition on the request, besides its path, for a route to apply
+#[derive(Debug, Clone)]
+pub enum Predicate {
+    /// The method is one of these
+    Method(Vec<Method>),
+    /// The header (name compared case-insensitively) is present with a
+    /// matching value
+    Header(String, ValueMatch),
+    /// Every predicate matches (true if there are none)
+    All(Vec<Predicate>),
+    /// At least one predicate matches (false if there are none)
+    Any(Vec<Predicate>),
+}
+
+/// How a header value is matched
+#[derive(Debug, Clone)]
+pub enum ValueMatch {
+    /// Any value
+    Present,
+    /// Exactly this value
+    Exact(String),
+    /// A value the expression matches (anchor it with `^...$` to match the
+    /// whole value)
+    Regex(Regex),
+}
+
+impl Predicate {
+    /// Whether `req` meets the condition
+    pub fn matches(&self, req: &Request) -> bool {
+        match self {
+            Predicate::Method(methods) => methods.contains(&req.method),
+            Predicate::Header(name, value) => req
+                .headers
+                .iter()
+                .filter(|(k, _)| k.eq_ignore_ascii_case(name))
+                .any(|(_, v)| match value {
+                    ValueMatch::Present => true,
+                    ValueMatch::Exact(expected) => v == expected,
+                    ValueMatch::Regex(regex) => regex.is_match(v),
+                }),
+            Predicate::All(predicates) => predicates.iter().all(|p| p.matches(req)),
+            Predicate::Any(predicates) => predicates.iter().any(|p| p.matches(req)),
+        }
+    }
+}
+
+/// How a route matches the request path
+#[derive(Debug, Clone)]
+enum PathMatch {
+    /// Path must be equal (query string ignored)
+    Exact(String),
+    /// Path must start with the prefix
+    Prefix(String),
+    /// Path must match the expression
+    Regex(Regex),
+}
+
+struct Route {
+    matcher: PathMatch,
+    predicate: Option<Predicate>,
+    handler: Arc<dyn Handler>,
+}
+
+/// Routes requests to handlers by path.
+///
+/// Exact routes take precedence over regex routes, which are tried in the
+/// order registered, and both over prefix routes, among which the longest
+/// matching prefix wins. For the same path, a route whose
+/// predicate matches wins over one without a predicate, and otherwise the
+/// route registered first. Requests that match no route go to the fallback
+/// handler, or get a 404 if none is set.
+///
+/// # Example
+///
+/// ```
+/// # use sentinel::http::handler::handler_fn;
+/// # use sentinel::http::response::Response;
+/// # use sentinel::http::router::Router;
+/// let router = Router::new()
+///     .route("/internal/feature-flags", handler_fn(|_req| async {
+///         Response::ok(br#"{"new_ui": true}"#.to_vec())
+///     }))
+///     .route_prefix("/internal/", handler_fn(|_req| async { Response::not_found() }));
+/// ```
+///
+/// Sending beta testers to another handler:
+///
+/// ```
+/// # use sentinel::http::handler::handler_fn;
+/// # use sentinel::http::response::Response;
+/// # use sentinel::http::router::{Predicate, Router, ValueMatch};
+/// let beta = Predicate::Header("X-Beta".to_string(), ValueMatch::Exact("true".to_string()));
+/// let router = Router::new()
+///     .route_prefix_when("/api/", beta, handler_fn(|_req| async { Response::ok(b"beta".to_vec()) }))
+///     .route_prefix("/api/", handler_fn(|_req| async { Response::ok(b"stable".to_vec()) }));
+/// ```
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    fallback: Option<Arc<dyn Handler>>,
+}
+
+impl Router {
+    /// Create an empty router with no fallback
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a handler for an exact path
+    pub fn route(mut self, path: impl Into<String>, handler: impl Handler) -> Self {
+        self.routes.push(Route {
+            matcher: PathMatch::Exact(path.into()),
+            predicate: None,
+            handler: Arc::new(handler),
+        });
+        self
+    }
+
+    /// Register a handler for every path starting with `prefix`
+    pub fn route_prefix(mut self, prefix: impl Into<String>, handler: impl Handler) -> Self {
+        self.routes.push(Route {
+            matcher: PathMatch::Prefix(prefix.into()),
+            predicate: None,
+            handler: Arc::new(handler),
+        });
+        self
+    }
+
+    /// Register a handler for paths starting with `prefix` on requests
+    /// meeting `predicate`
+    pub fn route_prefix_when(
+        mut self,
+        prefix: impl Into<String>,
+        predicate: Predicate,
+        handler: impl Handler,
+    ) -> Self {
+        self.routes.push(Route {
+            matcher: PathMatch::Prefix(prefix.into()),
+            predicate: Some(predicate),
+            handler: Arc::new(handler),
+        });
+        self
+    }
+
+    /// Register a handler for every path `regex` matches
+    ///
+    /// The expression is matched against the path without the query
+    /// string; anchor it with `^` to match from the start.
+    pub fn route_regex(mut self, regex: Regex, handler: impl Handler) -> Self {
+        self.routes.push(Route {
+            matcher: PathMatch::Regex(regex),
+            predicate: None,
+            handler: Arc::new(handler),
+        });
+        self
+    }
+
+    /// Register a handler for paths `regex` matches on requests meeting
+    /// `predicate`
+    pub fn route_regex_when(
+        mut self,
+        regex: Regex,
+        predicate: Predicate,
+        handler: impl Handler,
+    ) -> Self {
+        self.routes.push(Route {
+            matcher: PathMatch::Regex(regex),
+            predicate: Some(predicate),
+            handler: Arc::new(handler),
+        });
+        self
+    }
+
+    /// Set the handler used when no route matches
+    pub fn fallback(mut self, handler: impl Handler) -> Self {
+        self.fallback = Some(Arc::new(handler));
+        self
+    }
+
+    /// Whether a fallback handler has been set
+    pub fn has_fallback(&self) -> bool {
+        self.fallback.is_some()
+    }
+
+    /// Find the handler for a request
+    ///
+    /// Returns `None` if no route matches and there is no fallback.
+    pub fn find(&self, req: &Request) -> Option<&Arc<dyn Handler>> {
+        let path = req.path.split('?').next().unwrap_or(&req.path);
+
+        let route = self
+            .routes
+            .iter()
+            .filter(|route| route.predicate.as_ref().is_none_or(|p| p.matches(req)))
+            .filter_map(|route| {
+                let (rank, len) = match &route.matcher {
+                    PathMatch::Exact(p) if p == path => (2, p.len()),
+                    PathMatch::Regex(regex) if regex.is_match(path) => (1, 0),
+                    PathMatch::Prefix(p) if path.starts_with(p.as_str()) => (0, p.len()),
+                    _ => return None,
+                };
+                Some(((rank, len, route.predicate.is_some()), route))
+            })
+            // `max_by_key` keeps the last of equal keys, so walk backwards
+            // for the first registered
+            .rev()
+            .max_by_key(|(key, _)| *key)
+            .map(|(_, route)| route);
+
+        route.map(|r| &r.handler).or(self.fallback.as_ref())
+    }
+}
+
+#[async_trait]
+impl Handler for Router {
+    async fn handle(&self, req: Request) -> Response {
+        match self.find(&req) {
+            Some(handler) => handler.handle(req).await,
+            None => Response::not_found(),
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! Confined file access
+//!
+//! Static files are opened through [`read_beneath`], which refuses any path
+//! that resolves outside the static root, whether through `..`, an absolute
+//! path, or a symlink pointing elsewhere. A bug in request path handling
+//! therefore cannot expose files beyond the web root.
+//!
+//! On Linux 5.6+ the kernel enforces this with `openat2(RESOLVE_BENEATH)`,
+//! which resolves the path relative to the root directory and fails if any
+//! component escapes it. Elsewhere, or where `openat2` is unavailable (old
+//! kernels, seccomp filters), the path is canonicalized and checked to lie
+//! under the canonical root before opening. Landlock is not used: it
+//! confines the whole process, which would also cut off config reloads,
+//! capture files, and backend sockets.
+//!
+//! Paths that are written rather than read (WebDAV uploads, moves and
+//! deletes) are checked with [`resolve_beneath`] instead.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Read the file at `relative` beneath `root`
+///
+/// Fails with [`io::ErrorKind::PermissionDenied`] if the path resolves
+/// outside `root`.
+pub fn read_beneath(root: &Path, relative: &Path) -> io::Result<Vec<u8>> {
+    let mut file = open_beneath(root, relative)?;
+    let mut contents = Vec::new();
+    file.read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+/// Open the file at `relative` beneath `root` for reading
+pub fn open_beneath(root: &Path, relative: &Path) -> io::Result<File> {
+    #[cfg(target_os = "linux")]
+    if let Some(file) = openat2_beneath(root, relative)? {
+        return Ok(file);
+    }
+    open_canonical(root, relative)
+}
+
+/// Open with `openat2(RESOLVE_BENEATH)`, or `None` if the kernel does not
+/// support it
+#[cfg(target_os = "linux")]
+fn openat2_beneath(root: &Path, relative: &Path) -> io::Result<Option<File>> {
+    use std::ffi::CString;
+    use std::os::fd::{AsRawFd, FromRawFd};
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = File::open(root)?;
+    let path = CString::new(relative.as_os_str().as_bytes())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains NUL"))?;
+
+    // SAFETY: open_how is a plain C struct for which all zeroes is valid
+    let mut how: libc::open_how = unsafe { std::mem::zeroed() };
+    how.flags = (libc::O_RDONLY | libc::O_CLOEXEC) as u64;
+    how.resolve = libc::RESOLVE_BENEATH | libc::RESOLVE_NO_MAGICLINKS;
+
+    // SAFETY: the path and open_how outlive the call, and the size matches
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_openat2,
+            dir.as_raw_fd(),
+            path.as_ptr(),
+            &how as *const libc::open_how,
+            std::mem::size_of::<libc::open_how>(),
+        )
+    };
+    if fd >= 0 {
+        // SAFETY: the kernel returned a new descriptor that nothing else owns
+        return Ok(Some(unsafe { File::from_raw_fd(fd as i32) }));
+    }
+
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::ENOSYS) | Some(libc::EPERM) => Ok(None),
+        Some(libc::EXDEV) => Err(escaped(root, relative)),
+        _ => Err(err),
+    }
+}
+
+/// Open after checking that the canonical path lies under the canonical
+/// root
+///
+/// A symlink swapped in between the check and the open can still escape;
+/// `openat2` closes that window where it is available.
+fn open_canonical(root: &Path, relative: &Path) -> io::Result<File> {
+    if relative.is_absolute() {
+        return Err(escaped(root, relative));
+    }
+    let root = root.canonicalize()?;
+    let path = root.join(relative).canonicalize()?;
+    if !path.starts_with(&root) {
+        return Err(escaped(&root, relative));
+    }
+    File::open(path)
+}
+
+/// Resolve `relative` beneath `root` for modification
+///
+/// The parent directory must exist and lie under the canonical root, and
+/// the final component must not be a symlink, so writes never land outside
+/// `root`. The returned path need not exist. As with the canonical fallback
+/// of [`open_beneath`], a symlink swapped in after the check can still
+/// escape.
+pub fn resolve_beneath(root: &Path, relative: &Path) -> io::Result<PathBuf> {
+    if relative.is_absolute() {
+        return Err(escaped(root, relative));
+    }
+    let root = root.canonicalize()?;
+    let Some(name) = relative.file_name() else {
+        return if relative.as_os_str().is_empty() {
+            Ok(root)
+        } else {
+            Err(escaped(&root, relative))
+        };
+    };
+    let parent = relative.parent().unwrap_or(Path::new(""));
+    let parent = root.join(parent).canonicalize()?;
+    if !parent.starts_with(&root) {
+        return Err(escaped(&root, relative));
+    }
+    let path = parent.join(name);
+    match path.symlink_metadata() {
+        Ok(meta) if meta.file_type().is_symlink() => Err(escaped(&root, relative)),
+        _ => Ok(path),
+    }
+}
+
+fn escaped(root: &Path, relative: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("{} resolves outside {}", relative.display(), root.display()),
+    )
+}
@@ -0,0 +1,125 @@
+//! Tower compatibility
+//!
+//! Adapters between Sentinel's [`Handler`] and [`tower::Service`], so the
+//! request pipeline can be composed with tower layers (timeout, buffer,
+//! concurrency limits, retries, ...).
+//!
+//! # Example
+//!
+//! ```ignore
+//! use sentinel::http::service::HandlerExt;
+//! use tower::timeout::TimeoutLayer;
+//!
+//! let handler = StaticFileHandler::new(static_config)
+//!     .layer(TimeoutLayer::new(Duration::from_secs(2)));
+//! ```
+
+use crate::http::handler::Handler;
+use crate::http::request::Request;
+use crate::http::response::{Response, ResponseBuilder, StatusCode};
+use async_trait::async_trait;
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tower::{BoxError, Layer, Service, ServiceExt};
+
+/// A [`Handler`] exposed as a [`tower::Service`].
+///
+/// Handlers never fail, so the error type is [`Infallible`].
+#[derive(Clone)]
+pub struct HandlerService {
+    handler: Arc<dyn Handler>,
+}
+
+impl HandlerService {
+    /// Wrap a handler as a service
+    pub fn new(handler: Arc<dyn Handler>) -> Self {
+        Self { handler }
+    }
+}
+
+impl Service<Request> for HandlerService {
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let handler = self.handler.clone();
+        Box::pin(async move { Ok(handler.handle(req).await) })
+    }
+}
+
+/// A [`tower::Service`] exposed as a [`Handler`].
+///
+/// The service is cloned for every request, following the usual tower
+/// convention. Service errors become `500 Internal Server Error`, except
+/// timeouts from `tower::timeout`, which become `504 Gateway Timeout`.
+pub struct ServiceHandler<S> {
+    service: Mutex<S>,
+}
+
+impl<S> ServiceHandler<S> {
+    /// Wrap a service as a handler
+    pub fn new(service: S) -> Self {
+        Self {
+            service: Mutex::new(service),
+        }
+    }
+}
+
+#[async_trait]
+impl<S> Handler for ServiceHandler<S>
+where
+    S: Service<Request, Response = Response> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send,
+{
+    async fn handle(&self, req: Request) -> Response {
+        let service = self.service.lock().unwrap().clone();
+
+        match service.oneshot(req).await {
+            Ok(response) => response,
+            Err(e) => error_response(e.into()),
+        }
+    }
+}
+
+/// Map a service error to an HTTP response
+fn error_response(error: BoxError) -> Response {
+    let status = if error.is::<tower::timeout::error::Elapsed>() {
+        StatusCode::GatewayTimeout
+    } else {
+        StatusCode::InternalServerError
+    };
+
+    tracing::error!(error = %error, status = status.as_u16(), "Service error");
+
+    ResponseBuilder::new(status)
+        .header("Content-Type", "text/plain")
+        .body(format!("{} {}", status.as_u16(), status.reason_phrase()).into_bytes())
+        .build()
+}
+
+/// Extension methods for converting handlers to and from tower services
+pub trait HandlerExt: Handler + Sized {
+    /// Convert this handler into a tower service
+    fn into_service(self) -> HandlerService {
+        HandlerService::new(Arc::new(self))
+    }
+
+    /// Wrap this handler with a tower layer, producing a new handler
+    fn layer<L>(self, layer: L) -> ServiceHandler<L::Service>
+    where
+        L: Layer<HandlerService>,
+    {
+        ServiceHandler::new(layer.layer(self.into_service()))
+    }
+}
+
+impl<H: Handler> HandlerExt for H {}
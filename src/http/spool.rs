@@ -0,0 +1,181 @@
+//! Spooling of large request bodies to disk
+//!
+//! Bodies larger than the configured memory limit (see
+//! [`RequestBodyConfig`]) are streamed into a temporary file instead of
+//! being held in memory. The request then carries a [`SpooledBody`] rather
+//! than the bytes, and the reverse proxy streams it from disk to the
+//! backend.
+//!
+//! Spool files are created with owner-only permissions and removed when
+//! the last clone of the request is dropped. Files left behind by a process
+//! that crashed are removed at startup.
+
+use crate::config::RequestBodyConfig;
+use rand::Rng;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+
+/// Prefix of spool file names, followed by the owning process id
+const FILE_PREFIX: &str = "sentinel-body-";
+
+/// Creates spool files for large request bodies
+#[derive(Debug)]
+pub struct BodySpool {
+    dir: PathBuf,
+    memory_limit: usize,
+    max_bytes: u64,
+}
+
+impl BodySpool {
+    /// Prepare the spool directory, removing stale spool files
+    pub fn new(config: &RequestBodyConfig) -> anyhow::Result<Self> {
+        let dir = config.spool_dir.clone().unwrap_or_else(std::env::temp_dir);
+        std::fs::create_dir_all(&dir)?;
+        remove_stale(&dir);
+        Ok(Self {
+            dir,
+            memory_limit: config.memory_limit_bytes,
+            max_bytes: config.max_bytes,
+        })
+    }
+
+    /// Whether a body of `len` bytes is kept on disk
+    pub fn spools(&self, len: u64) -> bool {
+        len > self.memory_limit as u64
+    }
+
+    /// Whether a body of `len` bytes exceeds the limit and is refused
+    pub fn too_large(&self, len: u64) -> bool {
+        len > self.max_bytes
+    }
+
+    /// Create an empty spool file
+    pub async fn create(&self) -> io::Result<SpoolFile> {
+        let name = format!(
+            "{}{}-{:016x}",
+            FILE_PREFIX,
+            std::process::id(),
+            rand::rng().random::<u64>()
+        );
+        let path = self.dir.join(name);
+        let mut options = tokio::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let file = options.open(&path).await?;
+        Ok(SpoolFile {
+            file,
+            body: SpooledBody { path, len: 0 },
+        })
+    }
+}
+
+/// A spool file being written
+pub struct SpoolFile {
+    file: tokio::fs::File,
+    body: SpooledBody,
+}
+
+impl SpoolFile {
+    /// Append a chunk of the body
+    pub async fn write(&mut self, chunk: &[u8]) -> io::Result<()> {
+        self.file.write_all(chunk).await?;
+        self.body.len += chunk.len() as u64;
+        Ok(())
+    }
+
+    /// Bytes written so far
+    pub fn len(&self) -> u64 {
+        self.body.len
+    }
+
+    /// Whether nothing has been written yet
+    pub fn is_empty(&self) -> bool {
+        self.body.len == 0
+    }
+
+    /// Flush the file and hand over the body
+    pub async fn finish(mut self) -> io::Result<SpooledBody> {
+        self.file.flush().await?;
+        Ok(self.body)
+    }
+}
+
+/// A request body stored on disk, deleted when dropped
+#[derive(Debug)]
+pub struct SpooledBody {
+    path: PathBuf,
+    len: u64,
+}
+
+impl SpooledBody {
+    /// Location of the spool file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Length of the body in bytes
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Whether the body is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Open the body for reading from the start
+    pub async fn open(&self) -> io::Result<tokio::fs::File> {
+        tokio::fs::File::open(&self.path).await
+    }
+}
+
+impl Drop for SpooledBody {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path)
+            && e.kind() != io::ErrorKind::NotFound
+        {
+            tracing::warn!(path = %self.path.display(), error = %e, "Failed to remove spooled body");
+        }
+    }
+}
+
+/// Remove spool files whose process is gone
+fn remove_stale(dir: &Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let Some(pid) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(FILE_PREFIX))
+            .and_then(|rest| rest.split('-').next())
+            .and_then(|pid| pid.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        if pid != std::process::id() && !process_alive(pid) {
+            tracing::info!(file = ?name, "Removing stale spooled body");
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+}
+
+#[cfg(unix)]
+fn process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: signal 0 only checks that the process exists
+    unsafe {
+        libc::kill(pid, 0) == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+}
+
+/// Without a portable liveness check, assume other processes still run
+#[cfg(not(unix))]
+fn process_alive(_pid: u32) -> bool {
+    true
+}
@@ -0,0 +1,139 @@
+//! Static file serving
+//!
+//! Serves files from the configured static root. Files are opened through
+//! [`sandbox`](crate::http::sandbox), so no request path can reach outside
+//! the root. Custom error pages for
+//! bad requests and missing files are rendered by
+//! [`ErrorPageHandler`](crate::http::error_pages::ErrorPageHandler).
+//!
+//! With `images` configured, images requested with transformation query
+//! parameters are resized and converted by an
+//! [`ImageTransformer`](crate::http::images::ImageTransformer).
+
+use crate::config::StaticFilesConfig;
+use crate::http::handler::Handler;
+use crate::http::images::{ImageTransform, ImageTransformer};
+use crate::http::mime::content_type;
+use crate::http::request::Request;
+use crate::http::response::{Response, ResponseBuilder, StatusCode};
+use crate::http::sandbox::read_beneath;
+use async_trait::async_trait;
+use std::io;
+use std::path::PathBuf;
+
+/// Handler that serves files from a static root directory
+pub struct StaticFileHandler {
+    config: StaticFilesConfig,
+    images: Option<ImageTransformer>,
+}
+
+impl StaticFileHandler {
+    /// Create a new static file handler
+    pub fn new(config: StaticFilesConfig) -> Self {
+        let images = config.images.clone().map(ImageTransformer::new);
+        Self { config, images }
+    }
+
+    /// The image transformer, when `images` is configured
+    pub fn images(&self) -> Option<&ImageTransformer> {
+        self.images.as_ref()
+    }
+
+    /// Serves a static file from the configured static files directory
+    async fn serve(&self, req: &Request) -> Response {
+        // Normalize path
+        let (path, query) = match req.path.split_once('?') {
+            Some((path, query)) => (path, query),
+            None => (req.path.as_str(), ""),
+        };
+        let mut path = path.to_string();
+        if path == "/" {
+            path = format!("/{}", self.config.index);
+        }
+
+        // Prevent path traversal
+        if path.contains("..") {
+            return Response::error(StatusCode::BadRequest, "");
+        }
+
+        let root = self.config.root.clone();
+        let relative = PathBuf::from(&path[1..]);
+        let read = tokio::task::spawn_blocking(move || read_beneath(&root, &relative)).await;
+
+        match read {
+            Ok(Ok(contents)) => {
+                let mime = content_type(&path);
+                if let Some(images) = &self.images
+                    && mime.starts_with("image/")
+                {
+                    let accept = req
+                        .headers
+                        .iter()
+                        .find(|(key, _)| key.eq_ignore_ascii_case("Accept"))
+                        .map(|(_, value)| value.as_str());
+                    match ImageTransform::from_query(query, accept, images.config()) {
+                        Ok(Some(transform)) => {
+                            return Self::transform(images, req, mime, contents, transform).await;
+                        }
+                        Ok(None) => {}
+                        Err(message) => {
+                            return Response::error(StatusCode::BadRequest, &message);
+                        }
+                    }
+                }
+                ResponseBuilder::new(StatusCode::Ok)
+                    .header("Content-Type", mime)
+                    .body(contents)
+                    .build()
+            }
+
+            Ok(Err(e)) if e.kind() == io::ErrorKind::PermissionDenied => {
+                tracing::warn!(path = %req.path, error = %e, "Refused static file outside root");
+                Response::not_found()
+            }
+
+            _ => Response::not_found(),
+        }
+    }
+
+    async fn transform(
+        images: &ImageTransformer,
+        req: &Request,
+        mime: &str,
+        contents: Vec<u8>,
+        transform: ImageTransform,
+    ) -> Response {
+        // Too large to transform: serve the original instead
+        if contents.len() > images.config().max_source_bytes {
+            return ResponseBuilder::new(StatusCode::Ok)
+                .header("Content-Type", mime)
+                .body(contents)
+                .build();
+        }
+        match images.transform(contents, transform).await {
+            Ok(image) => {
+                let mut response = ResponseBuilder::new(StatusCode::Ok)
+                    .header("Content-Type", image.content_type)
+                    .body(image.body.as_ref().clone());
+                if transform.negotiated {
+                    response = response.header("Vary", "Accept");
+                }
+                response.build()
+            }
+            Err(e) => {
+                tracing::warn!(path = %req.path, "Image transformation failed: {:#}", e);
+                Response::error(
+                    StatusCode::UnprocessableEntity,
+                    "Image could not be transformed",
+                )
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Handler for StaticFileHandler {
+    async fn handle(&self, req: Request) -> Response {
+        self.serve(&req).await
+    }
+}
@@ -0,0 +1,91 @@
+//! Fixed responses defined in configuration
+//!
+//! A [`StaticResponse`] answers every request with the same status, headers,
+//! and body, for endpoints like `/ping` or `/robots.txt` that should never
+//! reach the disk or a backend. File bodies are read once, when the handler
+//! is built.
+
+use crate::config::StaticResponseConfig;
+use crate::http::handler::Handler;
+use crate::http::mime::content_type;
+use crate::http::request::Request;
+use crate::http::response::{Response, StatusCode};
+use anyhow::Context;
+use async_trait::async_trait;
+use std::path::Path;
+
+/// Handler that answers with a fixed response
+pub struct StaticResponse {
+    status: StatusCode,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl StaticResponse {
+    /// Create a handler answering `status` with `body` and no extra headers
+    pub fn new(status: StatusCode, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: body.into(),
+        }
+    }
+
+    /// Build the handler for a configured route, reading a file body from
+    /// `root`
+    ///
+    /// Without a `Content-Type` header, file bodies are typed by extension
+    /// and inline bodies are sent as plain text.
+    pub fn from_config(config: &StaticResponseConfig, root: &Path) -> anyhow::Result<Self> {
+        config.validate()?;
+        let status = StatusCode::from_u16(config.status).expect("status checked by validate");
+
+        let (body, default_type) = match &config.file {
+            Some(file) => {
+                let path = root.join(file);
+                let body = std::fs::read(&path).with_context(|| {
+                    format!(
+                        "Failed to read static response file {} for {}",
+                        path.display(),
+                        config.path
+                    )
+                })?;
+                (body, content_type(&file.to_string_lossy()))
+            }
+            None => (
+                config.body.clone().unwrap_or_default().into_bytes(),
+                "text/plain",
+            ),
+        };
+
+        let mut handler = Self::new(status, body);
+        for (name, value) in &config.headers {
+            handler = handler.with_header(name.clone(), value.clone());
+        }
+        if !config
+            .headers
+            .keys()
+            .any(|name| name.eq_ignore_ascii_case("Content-Type"))
+        {
+            handler = handler.with_header("Content-Type", default_type);
+        }
+        Ok(handler)
+    }
+
+    /// Add a header to every response
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+}
+
+#[async_trait]
+impl Handler for StaticResponse {
+    async fn handle(&self, _req: Request) -> Response {
+        let mut builder = Response::new(self.status);
+        for (name, value) in &self.headers {
+            builder = builder.header(name.clone(), value.clone());
+        }
+        builder.body(self.body.clone()).build()
+    }
+}
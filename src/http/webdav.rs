@@ -0,0 +1,456 @@
+//! WebDAV file sharing
+//!
+//! Serves a directory under a path prefix as a WebDAV (RFC 4918, class 1)
+//! share: `PROPFIND` lists, `GET` reads, `PUT` uploads, `MKCOL` creates
+//! directories, and `DELETE`, `COPY` and `MOVE` manage files. Every request
+//! needs the `Authorization: Basic` credentials of a configured user.
+//!
+//! Reads go through [`open_beneath`] like static files; paths that are
+//! modified are checked with [`resolve_beneath`], so neither can reach
+//! outside the mount root. Symlinks are neither listed nor modified.
+//!
+//! Locking is not supported, so clients that insist on `LOCK` (such as
+//! macOS Finder) mount shares read-only. `PROPFIND` always answers with all
+//! properties, and `Depth: infinity` is answered as `Depth: 1`.
+
+use crate::config::WebDavConfig;
+use crate::http::handler::Handler;
+use crate::http::mime::content_type;
+use crate::http::request::{Method, Request};
+use crate::http::response::{Response, ResponseBuilder, StatusCode};
+use crate::http::sandbox::{open_beneath, resolve_beneath};
+use crate::http::spool::SpooledBody;
+use crate::http::util::{basic_credentials, http_date, percent_decode};
+use async_trait::async_trait;
+use rand::Rng;
+use std::fs::{self, Metadata};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use subtle::{Choice, ConstantTimeEq};
+
+/// Methods a mount answers
+const ALLOW: &str = "OPTIONS, GET, HEAD, PUT, DELETE, MKCOL, COPY, MOVE, PROPFIND";
+
+/// Handler serving one WebDAV mount
+pub struct WebDavHandler {
+    config: WebDavConfig,
+}
+
+impl WebDavHandler {
+    /// Serve the mount described by `config`
+    pub fn new(config: WebDavConfig) -> Self {
+        Self { config }
+    }
+
+    /// Whether the request carries credentials for a configured user
+    fn is_authorized(&self, req: &Request) -> bool {
+        let Some((username, password)) = header(req, "Authorization").and_then(basic_credentials)
+        else {
+            return false;
+        };
+        // Constant-time, and without stopping at the first match
+        self.config
+            .users
+            .iter()
+            .fold(Choice::from(0), |found, user| {
+                found
+                    | (user.username.as_bytes().ct_eq(username.as_bytes())
+                        & user.password.as_bytes().ct_eq(password.as_bytes()))
+            })
+            .into()
+    }
+
+    /// The path beneath the mount root named by a request path, or `None`
+    /// if it is outside the mount or not a plain path
+    fn relative(&self, path: &str) -> Option<PathBuf> {
+        let path = path.split('?').next().unwrap_or(path);
+        let rest = path.strip_prefix(&self.config.prefix)?;
+        if !rest.is_empty() && !rest.starts_with('/') {
+            return None;
+        }
+        let mut relative = PathBuf::new();
+        for segment in rest.split('/').filter(|s| !s.is_empty()) {
+            let segment = percent_decode(segment)?;
+            if segment == "." || segment == ".." || segment.contains(['/', '\\', '\0']) {
+                return None;
+            }
+            relative.push(segment);
+        }
+        Some(relative)
+    }
+
+    /// The path named by the `Destination` header of COPY and MOVE
+    fn destination(&self, req: &Request) -> Result<PathBuf, (StatusCode, String)> {
+        let Some(destination) = header(req, "Destination") else {
+            return Err((StatusCode::BadRequest, "Missing Destination header".into()));
+        };
+        let path = if destination.starts_with('/') {
+            destination.to_string()
+        } else {
+            match url::Url::parse(destination) {
+                Ok(url) => url.path().to_string(),
+                Err(_) => {
+                    return Err((StatusCode::BadRequest, "Invalid Destination header".into()));
+                }
+            }
+        };
+        self.relative(&path).ok_or_else(|| {
+            (
+                StatusCode::Forbidden,
+                format!("Destination must be beneath {}", self.config.prefix),
+            )
+        })
+    }
+}
+
+#[async_trait]
+impl Handler for WebDavHandler {
+    async fn handle(&self, req: Request) -> Response {
+        if !self.is_authorized(&req) {
+            let mut response = Response::error(StatusCode::Unauthorized, "");
+            response.headers.insert(
+                "WWW-Authenticate".to_string(),
+                "Basic realm=\"sentinel\"".to_string(),
+            );
+            return response;
+        }
+        let Some(relative) = self.relative(&req.path) else {
+            return Response::error(StatusCode::BadRequest, "Invalid path");
+        };
+
+        let method = req.method.clone();
+        let modifies = matches!(
+            method,
+            Method::PUT | Method::DELETE | Method::MKCOL | Method::COPY | Method::MOVE
+        );
+        if modifies && self.config.read_only {
+            return Response::error(StatusCode::Forbidden, "This share is read-only");
+        }
+        let destination = match method {
+            Method::COPY | Method::MOVE => match self.destination(&req) {
+                Ok(destination) => destination,
+                Err((status, detail)) => return Response::error(status, &detail),
+            },
+            _ => PathBuf::new(),
+        };
+        let overwrite = header(&req, "Overwrite") != Some("F");
+        let shallow = header(&req, "Depth") == Some("0");
+        if modifies {
+            tracing::info!(method = ?method, path = %req.path, "WebDAV request");
+        }
+
+        let root = self.config.root.clone();
+        let prefix = self.config.prefix.clone();
+        let body = req.body;
+        let spooled = req.spooled;
+        let response = tokio::task::spawn_blocking(move || match method {
+            Method::OPTIONS => ResponseBuilder::new(StatusCode::Ok)
+                .header("DAV", "1")
+                .header("Allow", ALLOW)
+                .header("MS-Author-Via", "DAV")
+                .build(),
+            Method::GET | Method::HEAD => get(&root, &relative),
+            Method::PROPFIND => propfind(&root, &prefix, &relative, shallow),
+            Method::PUT => put(&root, &relative, &body, spooled.as_deref()),
+            Method::DELETE => delete(&root, &relative),
+            Method::MKCOL => mkcol(&root, &relative, &body),
+            Method::COPY => transfer(&root, &relative, &destination, overwrite, false),
+            Method::MOVE => transfer(&root, &relative, &destination, overwrite, true),
+            _ => {
+                let mut response = Response::error(StatusCode::MethodNotAllowed, "");
+                response
+                    .headers
+                    .insert("Allow".to_string(), ALLOW.to_string());
+                response
+            }
+        })
+        .await;
+        response.unwrap_or_else(|_| Response::error(StatusCode::InternalServerError, ""))
+    }
+}
+
+/// Look up a header case-insensitively
+fn header<'a>(req: &'a Request, name: &str) -> Option<&'a str> {
+    req.headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim())
+}
+
+/// Response for a failed filesystem operation
+fn io_error(e: io::Error, relative: &Path) -> Response {
+    match e.kind() {
+        io::ErrorKind::NotFound => Response::not_found(),
+        io::ErrorKind::PermissionDenied => {
+            tracing::warn!(path = %relative.display(), error = %e, "Refused WebDAV path");
+            Response::error(StatusCode::Forbidden, "")
+        }
+        _ => {
+            tracing::warn!(path = %relative.display(), error = %e, "WebDAV operation failed");
+            Response::error(StatusCode::InternalServerError, "")
+        }
+    }
+}
+
+/// Response for a path to modify that could not be resolved; a missing
+/// parent directory is a conflict
+fn unresolved(e: io::Error, relative: &Path) -> Response {
+    match e.kind() {
+        io::ErrorKind::NotFound => {
+            Response::error(StatusCode::Conflict, "Parent collection does not exist")
+        }
+        _ => io_error(e, relative),
+    }
+}
+
+fn get(root: &Path, relative: &Path) -> Response {
+    let read = open_beneath(root, relative).and_then(|mut file| {
+        if file.metadata()?.is_dir() {
+            return Ok(None);
+        }
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        Ok(Some(contents))
+    });
+    match read {
+        Ok(Some(contents)) => ResponseBuilder::new(StatusCode::Ok)
+            .header("Content-Type", content_type(&relative.to_string_lossy()))
+            .body(contents)
+            .build(),
+        Ok(None) => Response::error(
+            StatusCode::Forbidden,
+            "Collections are listed with PROPFIND",
+        ),
+        Err(e) => io_error(e, relative),
+    }
+}
+
+fn put(root: &Path, relative: &Path, body: &[u8], spooled: Option<&SpooledBody>) -> Response {
+    let path = match resolve_beneath(root, relative) {
+        Ok(path) => path,
+        Err(e) => return unresolved(e, relative),
+    };
+    if relative.as_os_str().is_empty() || path.is_dir() {
+        return Response::error(StatusCode::MethodNotAllowed, "Cannot PUT a collection");
+    }
+    let existed = path.exists();
+
+    // Write beside the target and rename, so readers never see a partial file
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let upload = path.with_file_name(format!(
+        ".{}.upload-{:016x}",
+        name,
+        rand::rng().random::<u64>()
+    ));
+    let written = match spooled {
+        Some(spooled) => fs::copy(spooled.path(), &upload).map(|_| ()),
+        None => fs::write(&upload, body),
+    };
+    if let Err(e) = written.and_then(|()| fs::rename(&upload, &path)) {
+        let _ = fs::remove_file(&upload);
+        return io_error(e, relative);
+    }
+    Response::new(if existed {
+        StatusCode::NoContent
+    } else {
+        StatusCode::Created
+    })
+    .build()
+}
+
+fn delete(root: &Path, relative: &Path) -> Response {
+    if relative.as_os_str().is_empty() {
+        return Response::error(StatusCode::Forbidden, "Cannot delete the share");
+    }
+    let path = match resolve_beneath(root, relative) {
+        Ok(path) => path,
+        Err(e) => return io_error(e, relative),
+    };
+    match remove(&path) {
+        Ok(()) => Response::new(StatusCode::NoContent).build(),
+        Err(e) => io_error(e, relative),
+    }
+}
+
+fn mkcol(root: &Path, relative: &Path, body: &[u8]) -> Response {
+    if !body.is_empty() {
+        return Response::error(StatusCode::UnsupportedMediaType, "MKCOL takes no body");
+    }
+    let path = match resolve_beneath(root, relative) {
+        Ok(path) => path,
+        Err(e) => return unresolved(e, relative),
+    };
+    if path.symlink_metadata().is_ok() {
+        return Response::error(StatusCode::MethodNotAllowed, "Resource already exists");
+    }
+    match fs::create_dir(&path) {
+        Ok(()) => Response::new(StatusCode::Created).build(),
+        Err(e) => io_error(e, relative),
+    }
+}
+
+/// COPY or MOVE `from` to `to`
+fn transfer(root: &Path, from: &Path, to: &Path, overwrite: bool, moving: bool) -> Response {
+    if from.as_os_str().is_empty() || to.as_os_str().is_empty() {
+        return Response::error(StatusCode::Forbidden, "Cannot copy or move the share");
+    }
+    let source = match resolve_beneath(root, from) {
+        Ok(source) if source.exists() => source,
+        Ok(_) => return Response::not_found(),
+        Err(e) => return io_error(e, from),
+    };
+    let target = match resolve_beneath(root, to) {
+        Ok(target) => target,
+        Err(e) => return unresolved(e, to),
+    };
+    if target.starts_with(&source) {
+        return Response::error(
+            StatusCode::Forbidden,
+            "Destination is the source or inside it",
+        );
+    }
+
+    let existed = target.symlink_metadata().is_ok();
+    if existed {
+        if !overwrite {
+            return Response::error(StatusCode::PreconditionFailed, "Destination exists");
+        }
+        if let Err(e) = remove(&target) {
+            return io_error(e, to);
+        }
+    }
+    let result = if moving {
+        fs::rename(&source, &target)
+    } else {
+        copy_recursive(&source, &target)
+    };
+    match result {
+        Ok(()) => Response::new(if existed {
+            StatusCode::NoContent
+        } else {
+            StatusCode::Created
+        })
+        .build(),
+        Err(e) => io_error(e, from),
+    }
+}
+
+/// Remove a file or a directory tree
+fn remove(path: &Path) -> io::Result<()> {
+    if path.symlink_metadata()?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Copy a file or a directory tree, skipping symlinks
+fn copy_recursive(from: &Path, to: &Path) -> io::Result<()> {
+    if !from.is_dir() {
+        return fs::copy(from, to).map(|_| ());
+    }
+    fs::create_dir(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        if entry.file_type()?.is_symlink() {
+            continue;
+        }
+        copy_recursive(&entry.path(), &to.join(entry.file_name()))?;
+    }
+    Ok(())
+}
+
+fn propfind(root: &Path, prefix: &str, relative: &Path, shallow: bool) -> Response {
+    let listed = resolve_beneath(root, relative).and_then(|path| {
+        let meta = fs::metadata(&path)?;
+        let mut resources = vec![(relative.to_path_buf(), meta)];
+        if resources[0].1.is_dir() && !shallow {
+            let mut children = Vec::new();
+            for entry in fs::read_dir(&path)? {
+                let entry = entry?;
+                if entry.file_type()?.is_symlink() {
+                    continue;
+                }
+                children.push((relative.join(entry.file_name()), entry.metadata()?));
+            }
+            children.sort_by(|a, b| a.0.cmp(&b.0));
+            resources.extend(children);
+        }
+        Ok(resources)
+    });
+    let resources = match listed {
+        Ok(resources) => resources,
+        Err(e) => return io_error(e, relative),
+    };
+
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n",
+    );
+    for (path, meta) in &resources {
+        xml.push_str(&describe(prefix, path, meta));
+    }
+    xml.push_str("</D:multistatus>\n");
+    ResponseBuilder::new(StatusCode::MultiStatus)
+        .header("Content-Type", "application/xml; charset=utf-8")
+        .body(xml.into_bytes())
+        .build()
+}
+
+/// The `<D:response>` element for one resource
+fn describe(prefix: &str, relative: &Path, meta: &Metadata) -> String {
+    let mut href = prefix.to_string();
+    for segment in relative.iter() {
+        href.push('/');
+        href.push_str(&percent_encode(&segment.to_string_lossy()));
+    }
+    if meta.is_dir() {
+        href.push('/');
+    }
+    let name = relative
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let mut props = format!("<D:displayname>{}</D:displayname>", xml_escape(&name));
+    if meta.is_dir() {
+        props.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+    } else {
+        props.push_str(&format!(
+            "<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength>\
+             <D:getcontenttype>{}</D:getcontenttype>",
+            meta.len(),
+            content_type(&name)
+        ));
+    }
+    if let Ok(modified) = meta.modified() {
+        props.push_str(&format!(
+            "<D:getlastmodified>{}</D:getlastmodified>",
+            http_date(modified)
+        ));
+    }
+    format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>{}</D:prop>\
+         <D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+        xml_escape(&href),
+        props
+    )
+}
+
+fn percent_encode(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -1,9 +1,10 @@
-use tokio::io::AsyncWriteExt;
-use tokio::net::TcpStream;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 use tokio::time::{Duration, timeout};
 const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
 
-use crate::http::response::Response;
+use crate::config::BackendServerHeader;
+use crate::http::cookie::SET_COOKIE_SEPARATOR;
+use crate::http::response::{BodyStream, Response};
 
 const HTTP_VERSION: &str = "HTTP/1.1";
 
@@ -24,7 +25,9 @@ fn serialize_response(resp: &Response) -> Vec<u8> {
         "{} {} {}\r\n",
         HTTP_VERSION,
         resp.status.as_u16(),
-        resp.status.reason_phrase()
+        resp.reason
+            .as_deref()
+            .unwrap_or(resp.status.reason_phrase())
     );
     buf.extend_from_slice(status_line.as_bytes());
 
@@ -39,13 +42,30 @@ fn serialize_response(resp: &Response) -> Vec<u8> {
             has_conn = true;
         }
 
-        buf.extend_from_slice(k.as_bytes());
-        buf.extend_from_slice(b": ");
-        buf.extend_from_slice(v.as_bytes());
-        buf.extend_from_slice(b"\r\n");
+        // Each cookie gets its own line
+        let values = if k.eq_ignore_ascii_case("set-cookie") {
+            v.split(SET_COOKIE_SEPARATOR).collect()
+        } else {
+            vec![v.as_str()]
+        };
+        for v in values {
+            buf.extend_from_slice(k.as_bytes());
+            buf.extend_from_slice(b": ");
+            buf.extend_from_slice(v.as_bytes());
+            buf.extend_from_slice(b"\r\n");
+        }
     }
 
-    // REQUIRED headers
+    // REQUIRED headers (a tunnel response has no message body at all)
+    if resp.tunnel.is_some() {
+        has_len = true;
+        has_conn = true;
+    }
+    // A streamed body's length is unknown until it ends
+    if resp.stream.is_some() {
+        has_len = true;
+        buf.extend_from_slice(b"Transfer-Encoding: chunked\r\n");
+    }
     if !has_len {
         buf.extend_from_slice(format!("Content-Length: {}\r\n", resp.body.len()).as_bytes());
     }
@@ -58,17 +78,127 @@ fn serialize_response(resp: &Response) -> Vec<u8> {
     buf.extend_from_slice(b"\r\n");
 
     // Body
-    buf.extend_from_slice(&resp.body);
+    if resp.stream.is_some() {
+        if !resp.body.is_empty() {
+            buf.extend_from_slice(&chunk(&resp.body));
+        }
+    } else {
+        buf.extend_from_slice(&resp.body);
+    }
+
+    buf
+}
+
+/// How responses identify the server, applied just before they are written
+///
+/// Built from the server config by
+/// [`ServerConfig::server_identity`](crate::config::ServerConfig::server_identity);
+/// the default leaves responses as they are.
+///
+/// # Example
+///
+/// ```
+/// # use sentinel::http::response::{Response, StatusCode};
+/// # use sentinel::http::writer::ServerIdentity;
+/// let identity = ServerIdentity::default().with_server("sentinel/1.0");
+/// let mut response = Response::new(StatusCode::Ok).build();
+/// identity.apply(&mut response);
+/// assert_eq!(response.headers["Server"], "sentinel/1.0");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ServerIdentity {
+    pub(crate) server: Option<String>,
+    pub(crate) backend: BackendServerHeader,
+    pub(crate) scrub: Vec<String>,
+}
+
+impl ServerIdentity {
+    /// Send `Server: <server>` on responses without one
+    pub fn with_server(mut self, server: impl Into<String>) -> Self {
+        self.server = Some(server.into());
+        self
+    }
+
+    /// Keep, replace, or strip a `Server` header responses already have
+    pub fn with_backend(mut self, backend: BackendServerHeader) -> Self {
+        self.backend = backend;
+        self
+    }
 
+    /// Remove header `name` from every response
+    pub fn with_scrubbed(mut self, name: impl Into<String>) -> Self {
+        self.scrub.push(name.into());
+        self
+    }
+
+    /// Scrub headers and set `Server` on `response`
+    pub fn apply(&self, response: &mut Response) {
+        let scrub = |name: &String| {
+            self.scrub.iter().any(|s| s.eq_ignore_ascii_case(name))
+                || (name.eq_ignore_ascii_case("Server")
+                    && self.backend != BackendServerHeader::Keep)
+        };
+        response.headers.retain(|name, _| !scrub(name));
+
+        let has_server = response
+            .headers
+            .keys()
+            .any(|name| name.eq_ignore_ascii_case("Server"));
+        if let Some(server) = &self.server
+            && !has_server
+            && self.backend != BackendServerHeader::Strip
+        {
+            response.headers.insert("Server".into(), server.clone());
+        }
+    }
+}
+
+/// Encode `data` as one chunk of a chunked body
+fn chunk(data: &[u8]) -> Vec<u8> {
+    let mut buf = format!("{:x}\r\n", data.len()).into_bytes();
+    buf.extend_from_slice(data);
+    buf.extend_from_slice(b"\r\n");
     buf
 }
 
-/// Handles writing HTTP responses to a TCP stream.
+/// Writes a streamed body as chunks, flushing each as soon as it arrives,
+/// then the final empty chunk.
+///
+/// Follows a head written by [`ResponseWriter`] for a response with a
+/// [`stream`](Response::stream).
+pub async fn write_body_stream<W>(stream: &mut W, body: &mut BodyStream) -> anyhow::Result<()>
+where
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    while let Some(data) = body.recv().await {
+        if data.is_empty() {
+            continue;
+        }
+        timeout(WRITE_TIMEOUT, async {
+            stream.write_all(&chunk(&data)).await?;
+            stream.flush().await
+        })
+        .await
+        .map_err(|_| anyhow::anyhow!("Write timeout"))??;
+    }
+    timeout(WRITE_TIMEOUT, async {
+        stream.write_all(b"0\r\n\r\n").await?;
+        stream.flush().await
+    })
+    .await
+    .map_err(|_| anyhow::anyhow!("Write timeout"))??;
+    Ok(())
+}
+
+/// Handles writing HTTP responses to an async byte stream.
 ///
 /// This struct manages the serialization and transmission of an HTTP response
 /// to a client. It handles partial writes by tracking how many bytes have been
 /// sent and applying a 5-second timeout to write operations.
 ///
+/// Any `AsyncWrite` sink can be used: a `TcpStream`, a TLS stream, or an
+/// in-memory sink such as `Vec<u8>` or `tokio::io::duplex` in tests.
+///
 /// # Example
 ///
 /// ```ignore
@@ -98,7 +228,22 @@ impl ResponseWriter {
         }
     }
 
-    /// Writes the complete response to the TCP stream.
+    /// Returns the serialized response in HTTP wire format.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use sentinel::http::response::{ResponseBuilder, StatusCode};
+    /// # use sentinel::http::writer::ResponseWriter;
+    /// let response = ResponseBuilder::new(StatusCode::Ok).build();
+    /// let writer = ResponseWriter::new(&response);
+    /// assert!(writer.serialize().starts_with(b"HTTP/1.1 200 OK\r\n"));
+    /// ```
+    pub fn serialize(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Writes the complete response to the stream and flushes it.
     ///
     /// Handles partial writes by tracking progress. If the underlying socket
     /// cannot accept all data at once, this function will resume writing on
@@ -106,13 +251,16 @@ impl ResponseWriter {
     ///
     /// # Arguments
     ///
-    /// * `stream` - The TCP stream to write to
+    /// * `stream` - Any async writer (TCP stream, TLS stream, in-memory sink)
     ///
     /// # Returns
     ///
     /// `Ok(())` when all bytes have been successfully written, or an error
     /// if I/O fails or the write times out.
-    pub async fn write_to_stream(&mut self, stream: &mut TcpStream) -> anyhow::Result<()> {
+    pub async fn write_to_stream<W>(&mut self, stream: &mut W) -> anyhow::Result<()>
+    where
+        W: AsyncWrite + Unpin + ?Sized,
+    {
         while self.written < self.buffer.len() {
             let write_fut = stream.write(&self.buffer[self.written..]);
 
@@ -131,6 +279,12 @@ impl ResponseWriter {
             self.written += n;
         }
 
+        // Buffered writers (e.g. TLS) only hit the wire once flushed
+        match timeout(WRITE_TIMEOUT, stream.flush()).await {
+            Ok(res) => res?,
+            Err(_) => return Err(anyhow::anyhow!("flush timed out")),
+        }
+
         Ok(())
     }
 }
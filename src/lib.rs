@@ -2,7 +2,14 @@
 //!
 //! Core library for HTTP and proxy functionality.
 
+pub mod admin;
 pub mod config;
+pub mod discovery;
+pub mod events;
 pub mod http;
+pub mod metrics;
+pub mod middleware;
 pub mod proxy;
 pub mod server;
+pub mod testing;
+pub mod tls;
@@ -1,29 +1,207 @@
-mod config;
-mod http;
-mod proxy;
-mod server;
+use sentinel::config::Config;
+use sentinel::server::Server;
+use sentinel::server::daemon::LogFile;
+use tokio_util::sync::CancellationToken;
+use tracing_subscriber::fmt::MakeWriter;
 
-use config::Config;
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("service") {
+        return service(&args[1..]);
+    }
+    if args.first().map(String::as_str) == Some("replay") {
+        tracing::subscriber::set_global_default(subscriber(std::io::stdout, true))?;
+        return tokio::runtime::Runtime::new()?.block_on(replay(&args[1..]));
+    }
+
+    // Logs emitted while loading the config always reach the terminal
+    let cfg = tracing::subscriber::with_default(subscriber(std::io::stdout, true), Config::load);
+
+    // Forking has to happen before the runtime starts any threads
+    #[cfg(unix)]
+    let daemon = match &cfg.server.daemon {
+        Some(config) => Some(sentinel::server::daemon::daemonize(config)?),
+        None => None,
+    };
+    #[cfg(unix)]
+    let log = daemon.as_ref().and_then(|d| d.log_file().cloned());
+    #[cfg(not(unix))]
+    let log: Option<LogFile> = match cfg.server.daemon {
+        Some(_) => anyhow::bail!("Daemon mode is only supported on Unix"),
+        None => None,
+    };
+
+    match &log {
+        Some(log) => tracing::subscriber::set_global_default(subscriber(log.clone(), false))?,
+        None => tracing::subscriber::set_global_default(subscriber(std::io::stdout, true))?,
+    }
+
+    tokio::runtime::Runtime::new()?.block_on(run(cfg, log))
+}
+
+/// `sentinel service <install|uninstall|run>`
+#[cfg(windows)]
+fn service(args: &[String]) -> anyhow::Result<()> {
+    use anyhow::Context;
+    use sentinel::server::windows_service;
+
+    match args.first().map(String::as_str) {
+        Some("install") => {
+            tracing::subscriber::set_global_default(subscriber(std::io::stdout, true))?;
+            windows_service::install(&std::env::current_dir()?)
+        }
+        Some("uninstall") => {
+            tracing::subscriber::set_global_default(subscriber(std::io::stdout, true))?;
+            windows_service::uninstall()
+        }
+        Some("run") => {
+            // Services start in System32; run from the install directory
+            if let Some(dir) = args.get(1) {
+                std::env::set_current_dir(dir)
+                    .with_context(|| format!("Failed to change directory to {}", dir))?;
+            }
+            let log = LogFile::open(std::path::Path::new("sentinel.log"))
+                .context("Failed to open sentinel.log")?;
+            tracing::subscriber::set_global_default(subscriber(log, false))?;
+            windows_service::run(|shutdown| {
+                let cfg = Config::load();
+                let server = Server::new(cfg)
+                    .shutdown(shutdown)
+                    .drain_timeout(windows_service::DRAIN_TIMEOUT);
+                tokio::runtime::Runtime::new()?.block_on(server.run())
+            })
+        }
+        _ => anyhow::bail!("Usage: sentinel service <install|uninstall|run>"),
+    }
+}
+
+#[cfg(not(windows))]
+fn service(_args: &[String]) -> anyhow::Result<()> {
+    anyhow::bail!("Service commands are only available on Windows")
+}
+
+/// `sentinel replay <capture.har>... --target <url> [--concurrency <n>]`
+async fn replay(args: &[String]) -> anyhow::Result<()> {
+    use sentinel::http::har::Har;
+    use sentinel::proxy::Replayer;
+
+    const USAGE: &str =
+        "Usage: sentinel replay <capture.har>... --target <url> [--concurrency <n>]";
+    let mut files = Vec::new();
+    let mut target = None;
+    let mut concurrency = 1;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--target" => target = args.next().cloned(),
+            "--concurrency" => {
+                concurrency = match args.next().map(|n| n.parse()) {
+                    Some(Ok(n)) => n,
+                    _ => anyhow::bail!(USAGE),
+                }
+            }
+            file if !file.starts_with("--") => files.push(std::path::PathBuf::from(file)),
+            _ => anyhow::bail!(USAGE),
+        }
+    }
+    let Some(target) = target else {
+        anyhow::bail!(USAGE);
+    };
+    if files.is_empty() {
+        anyhow::bail!(USAGE);
+    }
+
+    let replayer = Replayer::new(&target)?.with_concurrency(concurrency);
+    let mut failed = 0;
+    for file in &files {
+        let har = Har::load(file)?;
+        let report = replayer.replay(&har.log.entries).await;
+        tracing::info!(
+            file = %file.display(),
+            sent = report.sent(),
+            matched = report.matched,
+            mismatched = report.mismatched,
+            failed = report.failed,
+            skipped = report.skipped,
+            "Replayed capture"
+        );
+        failed += report.failed;
+    }
+    if failed > 0 {
+        anyhow::bail!("{} replayed requests could not reach {}", failed, target);
+    }
+    Ok(())
+}
+
+async fn run(cfg: Config, log: Option<LogFile>) -> anyhow::Result<()> {
+    let shutdown = CancellationToken::new();
+
+    let signal = shutdown.clone();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        tracing::info!("Shutdown signal received");
+        signal.cancel();
+    });
+
+    #[cfg(unix)]
+    if let Some(log) = log {
+        tokio::spawn(reopen_on_hangup(log));
+    }
+    #[cfg(not(unix))]
+    let _ = log;
+
+    Server::new(cfg).shutdown(shutdown).run().await
+}
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
+fn subscriber<W>(writer: W, ansi: bool) -> impl tracing::Subscriber + Send + Sync
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
     tracing_subscriber::fmt()
         .with_target(false)
         .with_level(true)
         .with_max_level(tracing::Level::DEBUG)
-        .init();
-
-    let cfg = Config::load();
+        .with_ansi(ansi)
+        .with_writer(writer)
+        .finish()
+}
 
-    tokio::select! {
-        res = server::listener::run(&cfg) => {
-            res?;
+/// Ctrl-C, or SIGTERM from an init script
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = term.recv() => {}
+                }
+                return;
+            }
+            Err(e) => tracing::warn!(error = %e, "Failed to listen for SIGTERM"),
         }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
 
-        _ = tokio::signal::ctrl_c() => {
-            tracing::info!("Shutdown signal received");
+/// Reopen the log file on every SIGHUP, for log rotation
+#[cfg(unix)]
+async fn reopen_on_hangup(log: LogFile) {
+    use tokio::signal::unix::{SignalKind, signal};
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to listen for SIGHUP, log file will not be reopened");
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        match log.reopen() {
+            Ok(()) => tracing::info!(path = %log.path().display(), "Reopened log file"),
+            Err(e) => {
+                tracing::warn!(path = %log.path().display(), error = %e, "Failed to reopen log file")
+            }
         }
     }
-
-    Ok(())
 }
@@ -0,0 +1,43 @@
+//! Prometheus scrape endpoint
+//!
+//! Serves a [`PrometheusRecorder`] in the text exposition format, at the
+//! configured `metrics.path` on the main listener or at `/metrics` on the
+//! admin listener.
+
+use crate::http::handler::Handler;
+use crate::http::request::{Method, Request};
+use crate::http::response::{Response, StatusCode};
+use crate::metrics::PrometheusRecorder;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// Content type of the Prometheus text exposition format
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Handler answering `GET` (and `HEAD`) with every recorded metric
+pub struct MetricsEndpoint {
+    recorder: Arc<PrometheusRecorder>,
+}
+
+impl MetricsEndpoint {
+    /// Serve what `recorder` has aggregated
+    pub fn new(recorder: Arc<PrometheusRecorder>) -> Self {
+        Self { recorder }
+    }
+}
+
+#[async_trait]
+impl Handler for MetricsEndpoint {
+    async fn handle(&self, req: Request) -> Response {
+        match req.method {
+            Method::GET | Method::HEAD => Response::new(StatusCode::Ok)
+                .header("Content-Type", CONTENT_TYPE)
+                .header("Cache-Control", "no-store")
+                .body(self.recorder.render().into_bytes())
+                .build(),
+            _ => Response::new(StatusCode::MethodNotAllowed)
+                .header("Allow", "GET, HEAD")
+                .build(),
+        }
+    }
+}
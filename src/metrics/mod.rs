@@ -0,0 +1,196 @@
+//! Metrics recording
+//!
+//! All modules emit measurements through the [`MetricsRecorder`] trait, so
+//! embedders can bridge Sentinel to their own telemetry stack. Built-in
+//! recorders:
+//!
+//! - [`NoopRecorder`]: discards everything (the default)
+//! - [`PrometheusRecorder`]: aggregates in memory and renders the Prometheus text format
+//! - [`StatsdRecorder`]: sends each measurement over UDP in (Dog)StatsD format
+//!
+//! With a top-level `metrics` section, the server records to a
+//! [`PrometheusRecorder`] (besides any recorder it was given) and serves it
+//! through a [`MetricsEndpoint`] on the main or the admin listener.
+//!
+//! # Metric names
+//!
+//! | Name | Type | Labels |
+//! |------|------|--------|
+//! | `sentinel_requests_total` | counter | `method`, `status` |
+//! | `sentinel_request_duration_seconds` | histogram | `method` |
+//! | `sentinel_requests_in_flight` | gauge | |
+//! | `sentinel_route_requests_total` | counter | `route`, `status` |
+//! | `sentinel_route_duration_seconds` | histogram | `route` |
+//! | `sentinel_route_requests_in_flight` | gauge | `route` |
+//! | `sentinel_upstream_requests_total` | counter | `backend`, `outcome` |
+//! | `sentinel_upstream_responses_total` | counter | `backend`, `status` |
+//! | `sentinel_upstream_duration_seconds` | histogram | `backend` |
+//! | `sentinel_upstream_requests_in_flight` | gauge | `backend` |
+//! | `sentinel_backend_selections_total` | counter | `backend` |
+//! | `sentinel_backend_up` | gauge | `backend` |
+//!
+//! Backend series also carry the backend's labels. Route series cover the
+//! routes under the top-level `routes` section, by name.
+
+pub mod endpoint;
+pub mod prometheus;
+pub mod route;
+pub mod statsd;
+
+pub use endpoint::MetricsEndpoint;
+pub use prometheus::PrometheusRecorder;
+pub use route::RouteMetrics;
+pub use statsd::StatsdRecorder;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Destination for counters, gauges, and histogram observations
+///
+/// Labels are passed as `(name, value)` pairs. Implementations must be cheap
+/// to call from the request path.
+pub trait MetricsRecorder: Send + Sync {
+    /// Add `value` to a monotonically increasing counter
+    fn increment_counter(&self, name: &str, labels: &[(&str, &str)], value: u64);
+
+    /// Set a gauge to an absolute value
+    fn set_gauge(&self, name: &str, labels: &[(&str, &str)], value: f64);
+
+    /// Record a single observation (e.g. a duration in seconds)
+    fn record_histogram(&self, name: &str, labels: &[(&str, &str)], value: f64);
+}
+
+/// Recorder that discards all measurements
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopRecorder;
+
+impl MetricsRecorder for NoopRecorder {
+    fn increment_counter(&self, _name: &str, _labels: &[(&str, &str)], _value: u64) {}
+
+    fn set_gauge(&self, _name: &str, _labels: &[(&str, &str)], _value: f64) {}
+
+    fn record_histogram(&self, _name: &str, _labels: &[(&str, &str)], _value: f64) {}
+}
+
+/// Recorder passing every measurement on to two others
+struct Tee(Arc<dyn MetricsRecorder>, Arc<dyn MetricsRecorder>);
+
+impl MetricsRecorder for Tee {
+    fn increment_counter(&self, name: &str, labels: &[(&str, &str)], value: u64) {
+        self.0.increment_counter(name, labels, value);
+        self.1.increment_counter(name, labels, value);
+    }
+
+    fn set_gauge(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.0.set_gauge(name, labels, value);
+        self.1.set_gauge(name, labels, value);
+    }
+
+    fn record_histogram(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.0.record_histogram(name, labels, value);
+        self.1.record_histogram(name, labels, value);
+    }
+}
+
+/// Things in progress, by gauge name and labels
+type InFlightCounts = Mutex<HashMap<(String, Vec<(String, String)>), i64>>;
+
+/// Shared handle to the active recorder
+///
+/// Cloning is cheap. Defaults to [`NoopRecorder`].
+#[derive(Clone)]
+pub struct Metrics {
+    recorder: Arc<dyn MetricsRecorder>,
+    in_flight: Arc<InFlightCounts>,
+}
+
+impl Metrics {
+    /// Create a handle for the given recorder
+    pub fn new(recorder: Arc<dyn MetricsRecorder>) -> Self {
+        Self {
+            recorder,
+            in_flight: Arc::default(),
+        }
+    }
+
+    /// Also send every measurement to `recorder`
+    pub fn with_recorder(self, recorder: Arc<dyn MetricsRecorder>) -> Self {
+        Self {
+            recorder: Arc::new(Tee(self.recorder, recorder)),
+            in_flight: self.in_flight,
+        }
+    }
+
+    /// Count something as in progress in gauge `name` until the returned
+    /// guard is dropped
+    pub fn in_flight(&self, name: &str, labels: &[(&str, &str)]) -> InFlight {
+        let key = (
+            name.to_string(),
+            labels
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        );
+        self.add_in_flight(&key, 1);
+        InFlight {
+            metrics: self.clone(),
+            key,
+        }
+    }
+
+    fn add_in_flight(&self, key: &(String, Vec<(String, String)>), delta: i64) {
+        let mut counts = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+        let count = counts.entry(key.clone()).or_default();
+        *count += delta;
+        let value = *count as f64;
+        if *count == 0 {
+            counts.remove(key);
+        }
+        // Set under the lock so concurrent updates land in order
+        let labels: Vec<(&str, &str)> = key
+            .1
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        self.recorder.set_gauge(&key.0, &labels, value);
+    }
+
+    /// Increment a counter by one
+    pub fn increment(&self, name: &str, labels: &[(&str, &str)]) {
+        self.recorder.increment_counter(name, labels, 1);
+    }
+
+    /// Set a gauge
+    pub fn gauge(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.recorder.set_gauge(name, labels, value);
+    }
+
+    /// Record a histogram observation
+    pub fn histogram(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.recorder.record_histogram(name, labels, value);
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new(Arc::new(NoopRecorder))
+    }
+}
+
+/// Guard counting one thing in progress (see [`Metrics::in_flight`])
+pub struct InFlight {
+    metrics: Metrics,
+    key: (String, Vec<(String, String)>),
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.metrics.add_in_flight(&self.key, -1);
+    }
+}
+
+impl std::fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Metrics").finish_non_exhaustive()
+    }
+}
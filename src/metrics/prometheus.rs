@@ -0,0 +1,196 @@
+//! In-memory Prometheus recorder
+//!
+//! Aggregates counters, gauges, and histograms and renders them in the
+//! Prometheus text exposition format.
+
+use crate::metrics::MetricsRecorder;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+/// Default histogram buckets (seconds), matching the Prometheus client defaults
+pub const DEFAULT_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Series key: sorted label pairs
+type LabelSet = Vec<(String, String)>;
+
+#[derive(Debug, Clone)]
+struct Histogram {
+    /// Cumulative count per bucket upper bound
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    counters: BTreeMap<String, BTreeMap<LabelSet, u64>>,
+    gauges: BTreeMap<String, BTreeMap<LabelSet, f64>>,
+    histograms: BTreeMap<String, BTreeMap<LabelSet, Histogram>>,
+}
+
+/// Recorder that keeps metrics in memory for Prometheus scraping
+#[derive(Debug)]
+pub struct PrometheusRecorder {
+    registry: Mutex<Registry>,
+    buckets: Vec<f64>,
+}
+
+impl PrometheusRecorder {
+    /// Create a recorder using [`DEFAULT_BUCKETS`] for histograms
+    pub fn new() -> Self {
+        Self::with_buckets(DEFAULT_BUCKETS.to_vec())
+    }
+
+    /// Create a recorder with custom histogram bucket upper bounds
+    pub fn with_buckets(mut buckets: Vec<f64>) -> Self {
+        buckets.sort_by(|a, b| a.total_cmp(b));
+
+        Self {
+            registry: Mutex::new(Registry::default()),
+            buckets,
+        }
+    }
+
+    /// Render all metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let registry = self.registry.lock().unwrap();
+        let mut out = String::new();
+
+        for (name, series) in &registry.counters {
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            for (labels, value) in series {
+                let _ = writeln!(out, "{}{} {}", name, format_labels(labels, None), value);
+            }
+        }
+
+        for (name, series) in &registry.gauges {
+            let _ = writeln!(out, "# TYPE {} gauge", name);
+            for (labels, value) in series {
+                let _ = writeln!(out, "{}{} {}", name, format_labels(labels, None), value);
+            }
+        }
+
+        for (name, series) in &registry.histograms {
+            let _ = writeln!(out, "# TYPE {} histogram", name);
+            for (labels, histogram) in series {
+                for (bound, count) in self.buckets.iter().zip(&histogram.buckets) {
+                    let le = bound.to_string();
+                    let _ = writeln!(
+                        out,
+                        "{}_bucket{} {}",
+                        name,
+                        format_labels(labels, Some(&le)),
+                        count
+                    );
+                }
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{} {}",
+                    name,
+                    format_labels(labels, Some("+Inf")),
+                    histogram.count
+                );
+                let _ = writeln!(
+                    out,
+                    "{}_sum{} {}",
+                    name,
+                    format_labels(labels, None),
+                    histogram.sum
+                );
+                let _ = writeln!(
+                    out,
+                    "{}_count{} {}",
+                    name,
+                    format_labels(labels, None),
+                    histogram.count
+                );
+            }
+        }
+
+        out
+    }
+}
+
+impl Default for PrometheusRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MetricsRecorder for PrometheusRecorder {
+    fn increment_counter(&self, name: &str, labels: &[(&str, &str)], value: u64) {
+        let mut registry = self.registry.lock().unwrap();
+        *registry
+            .counters
+            .entry(name.to_string())
+            .or_default()
+            .entry(label_set(labels))
+            .or_default() += value;
+    }
+
+    fn set_gauge(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+        let mut registry = self.registry.lock().unwrap();
+        registry
+            .gauges
+            .entry(name.to_string())
+            .or_default()
+            .insert(label_set(labels), value);
+    }
+
+    fn record_histogram(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+        let mut registry = self.registry.lock().unwrap();
+        let histogram = registry
+            .histograms
+            .entry(name.to_string())
+            .or_default()
+            .entry(label_set(labels))
+            .or_insert_with(|| Histogram {
+                buckets: vec![0; self.buckets.len()],
+                sum: 0.0,
+                count: 0,
+            });
+
+        for (bound, count) in self.buckets.iter().zip(histogram.buckets.iter_mut()) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        histogram.sum += value;
+        histogram.count += 1;
+    }
+}
+
+fn label_set(labels: &[(&str, &str)]) -> LabelSet {
+    let mut set: LabelSet = labels
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    set.sort();
+    set
+}
+
+fn format_labels(labels: &LabelSet, le: Option<&str>) -> String {
+    if labels.is_empty() && le.is_none() {
+        return String::new();
+    }
+
+    let mut parts: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, escape_label_value(v)))
+        .collect();
+    if let Some(le) = le {
+        parts.push(format!("le=\"{}\"", le));
+    }
+
+    format!("{{{}}}", parts.join(","))
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
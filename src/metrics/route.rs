@@ -0,0 +1,56 @@
+//! Per-route request metrics
+//!
+//! Wraps the handler of a configured route so its requests are counted by
+//! status, timed, and gauged while in flight under the route's name.
+
+use crate::http::handler::Handler;
+use crate::http::request::Request;
+use crate::http::response::Response;
+use crate::metrics::Metrics;
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Handler decorator recording `sentinel_route_*` metrics for one route
+pub struct RouteMetrics {
+    inner: Arc<dyn Handler>,
+    route: String,
+    metrics: Metrics,
+}
+
+impl RouteMetrics {
+    /// Wrap `inner`, the handler of the route named `route`
+    pub fn new(inner: impl Handler, route: impl Into<String>, metrics: Metrics) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            route: route.into(),
+            metrics,
+        }
+    }
+}
+
+#[async_trait]
+impl Handler for RouteMetrics {
+    async fn handle(&self, req: Request) -> Response {
+        let labels = [("route", self.route.as_str())];
+        let started = Instant::now();
+        let response = {
+            let _in_flight = self
+                .metrics
+                .in_flight("sentinel_route_requests_in_flight", &labels);
+            self.inner.handle(req).await
+        };
+
+        let status = response.status.as_u16().to_string();
+        self.metrics.increment(
+            "sentinel_route_requests_total",
+            &[("route", self.route.as_str()), ("status", &status)],
+        );
+        self.metrics.histogram(
+            "sentinel_route_duration_seconds",
+            &labels,
+            started.elapsed().as_secs_f64(),
+        );
+        response
+    }
+}
@@ -0,0 +1,65 @@
+//! StatsD recorder
+//!
+//! Sends every measurement as a UDP datagram using the DogStatsD line
+//! format (`name:value|type|#tag:value,...`). Sends are non-blocking and
+//! failures are dropped, so a missing agent never slows down requests.
+
+use crate::metrics::MetricsRecorder;
+use std::io;
+use std::net::{ToSocketAddrs, UdpSocket};
+
+/// Recorder that forwards measurements to a StatsD agent
+#[derive(Debug)]
+pub struct StatsdRecorder {
+    socket: UdpSocket,
+    prefix: String,
+}
+
+impl StatsdRecorder {
+    /// Create a recorder sending to `addr` (e.g. "127.0.0.1:8125")
+    ///
+    /// `prefix` is prepended to every metric name with a dot separator,
+    /// unless empty.
+    pub fn new(addr: impl ToSocketAddrs, prefix: impl Into<String>) -> io::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(addr)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(Self {
+            socket,
+            prefix: prefix.into(),
+        })
+    }
+
+    fn send(&self, name: &str, value: &str, kind: &str, labels: &[(&str, &str)]) {
+        let mut line = if self.prefix.is_empty() {
+            format!("{}:{}|{}", name, value, kind)
+        } else {
+            format!("{}.{}:{}|{}", self.prefix, name, value, kind)
+        };
+
+        if !labels.is_empty() {
+            let tags: Vec<String> = labels.iter().map(|(k, v)| format!("{}:{}", k, v)).collect();
+            line.push_str("|#");
+            line.push_str(&tags.join(","));
+        }
+
+        if let Err(e) = self.socket.send(line.as_bytes()) {
+            tracing::trace!(error = %e, "Failed to send StatsD metric");
+        }
+    }
+}
+
+impl MetricsRecorder for StatsdRecorder {
+    fn increment_counter(&self, name: &str, labels: &[(&str, &str)], value: u64) {
+        self.send(name, &value.to_string(), "c", labels);
+    }
+
+    fn set_gauge(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.send(name, &value.to_string(), "g", labels);
+    }
+
+    fn record_histogram(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.send(name, &value.to_string(), "h", labels);
+    }
+}
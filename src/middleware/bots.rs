@@ -0,0 +1,206 @@
+//! Rule-based bot detection
+//!
+//! Every request is checked against the configured [`BotRule`]s in order.
+//! A rule combines signals (User-Agent substrings, missing headers that
+//! real browsers always send, and a per-IP request rate), all of which
+//! must match, and the first matching rule's action decides the outcome:
+//!
+//! - `allow`: serve normally (e.g. known crawlers ahead of broader rules)
+//! - `block`: answer 403
+//! - `tarpit`: wait `tarpit_ms`, then serve, so aggressive clients slow down
+//! - `route`: serve from the rule's own backends, keeping bots off the main
+//!   pool
+//!
+//! Every match counts towards `sentinel_bot_verdicts_total{rule, action}`.
+//!
+//! # Example
+//!
+//! ```yaml
+//! bots:
+//!   rules:
+//!     - name: scrapers
+//!       user_agents: ["Scrapy", "python-requests"]
+//!       action: block
+//!     - name: hammering
+//!       rate: { requests: 300, per_secs: 60 }
+//!       action: route
+//!       backends:
+//!         - url: "http://127.0.0.1:3100"
+//! ```
+
+use crate::config::{BotAction, BotConfig, BotRule};
+use crate::http::handler::Handler;
+use crate::http::request::Request;
+use crate::http::response::{Response, StatusCode};
+use crate::metrics::Metrics;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Clients tracked per rate rule before expired windows are swept
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Outcome of matching a request against the rules
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BotVerdict<'a> {
+    /// Name of the matching rule
+    pub rule: &'a str,
+    pub action: BotAction,
+}
+
+struct CompiledRule {
+    rule: BotRule,
+    /// Lowercased User-Agent patterns
+    user_agents: Vec<String>,
+    /// Requests per client IP in the current window
+    rates: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+    pool: Option<Arc<dyn Handler>>,
+}
+
+/// Handler decorator that applies bot rules
+pub struct BotHandler {
+    inner: Arc<dyn Handler>,
+    rules: Vec<CompiledRule>,
+    metrics: Metrics,
+}
+
+impl BotHandler {
+    /// Wrap `inner`, classifying requests according to `config`
+    pub fn new(inner: impl Handler, config: BotConfig) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            rules: config
+                .rules
+                .into_iter()
+                .map(|rule| CompiledRule {
+                    user_agents: rule.user_agents.iter().map(|p| p.to_lowercase()).collect(),
+                    rule,
+                    rates: Mutex::new(HashMap::new()),
+                    pool: None,
+                })
+                .collect(),
+            metrics: Metrics::default(),
+        }
+    }
+
+    /// Count verdicts through the given recorder
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Serve requests routed by the rule named `rule` with `handler`
+    pub fn with_pool(mut self, rule: &str, handler: impl Handler) -> Self {
+        if let Some(compiled) = self.rules.iter_mut().find(|r| r.rule.name == rule) {
+            compiled.pool = Some(Arc::new(handler));
+        }
+        self
+    }
+
+    /// The first rule matching `req`, if any
+    ///
+    /// Each call counts towards the client's request rate.
+    pub fn classify(&self, req: &Request) -> Option<BotVerdict<'_>> {
+        let user_agent = req.header("User-Agent").map(str::to_lowercase);
+        let client = req.context.peer.map(|peer| peer.ip());
+
+        let mut verdict = None;
+        for compiled in &self.rules {
+            // Rates are counted for every request, so a client cannot reset
+            // its count by being caught by an earlier rule
+            let over_rate = compiled.rule.rate.map(|rate| match client {
+                Some(ip) => compiled.count(ip, rate.requests, rate.per_secs),
+                None => false,
+            });
+            if verdict.is_some() {
+                continue;
+            }
+
+            let rule = &compiled.rule;
+            let agent_matches = compiled.user_agents.is_empty()
+                || user_agent
+                    .as_deref()
+                    .is_some_and(|ua| compiled.user_agents.iter().any(|p| ua.contains(p)));
+            let header_missing = rule.missing_headers.is_empty()
+                || rule
+                    .missing_headers
+                    .iter()
+                    .any(|name| req.header(name).is_none());
+            if agent_matches && header_missing && over_rate.unwrap_or(true) {
+                verdict = Some(BotVerdict {
+                    rule: &rule.name,
+                    action: rule.action,
+                });
+            }
+        }
+        verdict
+    }
+}
+
+impl CompiledRule {
+    /// Count a request from `ip`; true once it exceeds `limit` in the window
+    fn count(&self, ip: IpAddr, limit: u32, per_secs: u64) -> bool {
+        let window = Duration::from_secs(per_secs);
+        let now = Instant::now();
+        let mut rates = self.rates.lock().unwrap();
+        if rates.len() >= MAX_TRACKED_CLIENTS {
+            rates.retain(|_, (start, _)| now.duration_since(*start) < window);
+        }
+
+        let (start, count) = rates.entry(ip).or_insert((now, 0));
+        if now.duration_since(*start) >= window {
+            *start = now;
+            *count = 0;
+        }
+        *count = count.saturating_add(1);
+        *count > limit
+    }
+}
+
+#[async_trait]
+impl Handler for BotHandler {
+    async fn handle(&self, req: Request) -> Response {
+        let Some(verdict) = self.classify(&req) else {
+            return self.inner.handle(req).await;
+        };
+        self.metrics.increment(
+            "sentinel_bot_verdicts_total",
+            &[("rule", verdict.rule), ("action", verdict.action.as_str())],
+        );
+        tracing::debug!(
+            rule = verdict.rule,
+            action = verdict.action.as_str(),
+            path = %req.path,
+            "Bot rule matched"
+        );
+
+        let compiled = self
+            .rules
+            .iter()
+            .find(|r| r.rule.name == verdict.rule)
+            .expect("verdict names a configured rule");
+        match verdict.action {
+            BotAction::Allow => self.inner.handle(req).await,
+            BotAction::Block => Response::new(StatusCode::Forbidden)
+                .header("Content-Type", "text/plain")
+                .body(b"403 Forbidden".to_vec())
+                .build(),
+            BotAction::Tarpit => {
+                let delay = Duration::from_millis(compiled.rule.tarpit_ms);
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => self.inner.handle(req).await,
+                    _ = req.context.cancelled() => Response::new(StatusCode::ServiceUnavailable)
+                        .header("Content-Type", "text/plain")
+                        .body(b"503 Service Unavailable".to_vec())
+                        .build(),
+                }
+            }
+            BotAction::Route => match &compiled.pool {
+                Some(pool) => pool.handle(req).await,
+                None => self.inner.handle(req).await,
+            },
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! Composable request and response hooks
+//!
+//! A [`Middleware`] looks at each request before the handler runs and at
+//! each response on its way back, without wrapping the handler itself. A
+//! [`MiddlewareChain`] runs its middlewares in order around a handler:
+//! `before_request` hooks first to last, `after_response` hooks last to
+//! first, like nested decorators.
+//!
+//! # Example
+//!
+//! ```
+//! # use sentinel::http::handler::handler_fn;
+//! # use sentinel::http::request::Request;
+//! # use sentinel::http::response::Response;
+//! # use sentinel::middleware::{Middleware, MiddlewareChain};
+//! struct PoweredBy;
+//!
+//! #[async_trait::async_trait]
+//! impl Middleware for PoweredBy {
+//!     async fn after_response(&self, _req: &Request, response: &mut Response) {
+//!         response.headers.remove("X-Powered-By");
+//!     }
+//! }
+//!
+//! let chain = MiddlewareChain::new(handler_fn(|_req| async { Response::not_found() }))
+//!     .with(PoweredBy);
+//! ```
+
+use crate::http::handler::Handler;
+use crate::http::request::Request;
+use crate::http::response::Response;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// Hooks run before a request is handled and after its response is made
+///
+/// Both hooks do nothing by default.
+#[async_trait]
+pub trait Middleware: Send + Sync + 'static {
+    /// Inspect or change the request, or answer it with a response of its
+    /// own, which skips the rest of the chain and the handler
+    async fn before_request(&self, _req: &mut Request) -> Option<Response> {
+        None
+    }
+
+    /// Inspect or change the response; `req` is the request as this
+    /// middleware passed it on, with its context and extensions but without
+    /// its body
+    async fn after_response(&self, _req: &Request, _response: &mut Response) {}
+}
+
+#[async_trait]
+impl<M: Middleware + ?Sized> Middleware for Arc<M> {
+    async fn before_request(&self, req: &mut Request) -> Option<Response> {
+        (**self).before_request(req).await
+    }
+
+    async fn after_response(&self, req: &Request, response: &mut Response) {
+        (**self).after_response(req, response).await
+    }
+}
+
+/// Handler that runs middlewares in order around an inner handler
+pub struct MiddlewareChain {
+    inner: Arc<dyn Handler>,
+    middlewares: Vec<Arc<dyn Middleware>>,
+}
+
+impl MiddlewareChain {
+    /// Chain without middlewares around `inner`
+    pub fn new(inner: impl Handler) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            middlewares: Vec::new(),
+        }
+    }
+
+    /// Run `middleware` after those already added
+    pub fn with(mut self, middleware: impl Middleware) -> Self {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
+
+    /// Run each of `middlewares`, in order, after those already added
+    pub fn with_all(mut self, middlewares: impl IntoIterator<Item = Arc<dyn Middleware>>) -> Self {
+        self.middlewares.extend(middlewares);
+        self
+    }
+
+    /// Whether the chain has no middlewares
+    pub fn is_empty(&self) -> bool {
+        self.middlewares.is_empty()
+    }
+}
+
+#[async_trait]
+impl Handler for MiddlewareChain {
+    async fn handle(&self, mut req: Request) -> Response {
+        // Each middleware's view of the request, for its after_response hook
+        let mut passed = Vec::with_capacity(self.middlewares.len());
+        let mut answered = None;
+        for middleware in &self.middlewares {
+            if let Some(response) = middleware.before_request(&mut req).await {
+                answered = Some(response);
+                break;
+            }
+            passed.push(head(&req));
+        }
+
+        let mut response = match answered {
+            Some(response) => response,
+            None => self.inner.handle(req).await,
+        };
+        // Middlewares that answered or never ran see nothing come back
+        for (middleware, req) in self.middlewares.iter().zip(&passed).rev() {
+            middleware.after_response(req, &mut response).await;
+        }
+        response
+    }
+}
+
+/// `req` without its body
+fn head(req: &Request) -> Request {
+    Request {
+        method: req.method.clone(),
+        path: req.path.clone(),
+        version: req.version.clone(),
+        headers: req.headers.clone(),
+        body: Vec::new(),
+        spooled: None,
+        context: req.context.clone(),
+    }
+}
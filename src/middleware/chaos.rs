@@ -0,0 +1,123 @@
+//! Chaos / fault injection middleware
+//!
+//! Injects latency, error responses, connection aborts, and truncated bodies
+//! at configured probabilities per path prefix, so clients can be tested
+//! against a misbehaving upstream in staging. Off unless `chaos.enabled` is
+//! set in configuration.
+//!
+//! # Example
+//!
+//! ```yaml
+//! chaos:
+//!   enabled: true
+//!   rules:
+//!     - path_prefix: "/api"
+//!       latency: { probability: 0.2, min_ms: 100, max_ms: 800 }
+//!       error: { probability: 0.05, status: 503 }
+//!       abort_probability: 0.01
+//!       truncate_probability: 0.01
+//! ```
+
+use crate::config::{ChaosConfig, ChaosRule};
+use crate::http::handler::Handler;
+use crate::http::request::Request;
+use crate::http::response::{Disposition, Response, StatusCode};
+use async_trait::async_trait;
+use rand::Rng;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Header added to responses that were altered by fault injection
+pub const CHAOS_HEADER: &str = "X-Sentinel-Chaos";
+
+/// Handler decorator that injects faults into matching requests
+pub struct ChaosHandler {
+    inner: Arc<dyn Handler>,
+    rules: Vec<ChaosRule>,
+}
+
+impl ChaosHandler {
+    /// Wrap `inner`, injecting faults according to `config`
+    ///
+    /// A disabled config produces a pass-through handler.
+    pub fn new(inner: impl Handler, config: ChaosConfig) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            rules: if config.enabled {
+                config.rules
+            } else {
+                Vec::new()
+            },
+        }
+    }
+
+    /// First rule whose prefix matches the request path (query ignored)
+    fn rule_for(&self, path: &str) -> Option<&ChaosRule> {
+        let path = path.split('?').next().unwrap_or(path);
+        self.rules
+            .iter()
+            .find(|rule| path.starts_with(&rule.path_prefix))
+    }
+}
+
+#[async_trait]
+impl Handler for ChaosHandler {
+    async fn handle(&self, req: Request) -> Response {
+        let Some(rule) = self.rule_for(&req.path) else {
+            return self.inner.handle(req).await;
+        };
+
+        if let Some(latency) = &rule.latency
+            && roll(latency.probability)
+        {
+            let max = latency.max_ms.unwrap_or(latency.min_ms);
+            let delay = rand::rng().random_range(latency.min_ms..=max);
+            tracing::debug!(path = %req.path, delay_ms = delay, "Chaos: injecting latency");
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+        }
+
+        if roll(rule.abort_probability) {
+            tracing::debug!(path = %req.path, "Chaos: aborting connection");
+            return Response::abort();
+        }
+
+        if let Some(error) = &rule.error
+            && roll(error.probability)
+        {
+            let status =
+                StatusCode::from_u16(error.status).unwrap_or(StatusCode::ServiceUnavailable);
+            tracing::debug!(path = %req.path, status = status.as_u16(), "Chaos: injecting error");
+            return Response::new(status)
+                .header("Content-Type", "text/plain")
+                .header(CHAOS_HEADER, "error")
+                .body(format!("{} {}", status.as_u16(), status.reason_phrase()).into_bytes())
+                .build();
+        }
+
+        let mut response = self.inner.handle(req).await;
+
+        if roll(rule.truncate_probability) && !response.body.is_empty() {
+            // Keep the advertised length so the client sees a short read
+            let full_len = response.body.len();
+            response
+                .headers
+                .retain(|k, _| !k.eq_ignore_ascii_case("content-length"));
+            response
+                .headers
+                .insert("Content-Length".to_string(), full_len.to_string());
+            response
+                .headers
+                .insert(CHAOS_HEADER.to_string(), "truncate".to_string());
+            response.body.truncate(full_len / 2);
+            response.disposition = Disposition::SendAndClose;
+            tracing::debug!(full_len, sent = full_len / 2, "Chaos: truncating body");
+        }
+
+        response
+    }
+}
+
+/// Returns true with the given probability
+fn roll(probability: f64) -> bool {
+    probability > 0.0 && rand::rng().random::<f64>() < probability
+}
@@ -0,0 +1,88 @@
+//! Per-route client certificate requirements
+//!
+//! With mutual TLS on the listener, [`ClientCertPolicy`] tells backends
+//! who the client is and lets a route refuse requests from clients that
+//! presented no certificate. The details go in the headers in
+//! `client_auth.headers` (by default `X-Client-Cert-Subject`,
+//! `X-Client-Cert-Issuer`, `X-Client-Cert-Sha256`, and the URL-encoded
+//! `X-Client-Cert-PEM`). Clients can't set those headers themselves: any
+//! they send are removed first.
+//!
+//! # Example
+//!
+//! ```yaml
+//! server:
+//!   tls:
+//!     cert_file: /etc/sentinel/cert.pem
+//!     key_file: /etc/sentinel/key.pem
+//!     client_auth: { ca_file: /etc/sentinel/clients-ca.pem, mode: optional }
+//!
+//! routes:
+//!   - prefix: /internal/
+//!     pool: internal
+//!     client_cert: require
+//! ```
+
+use crate::config::{ClientCertHeaders, ClientCertRequirement};
+use crate::http::request::Request;
+use crate::http::response::{Response, StatusCode};
+use crate::middleware::Middleware;
+use async_trait::async_trait;
+
+/// Middleware that enforces a route's client certificate requirement
+pub struct ClientCertPolicy {
+    requirement: ClientCertRequirement,
+    headers: ClientCertHeaders,
+}
+
+impl ClientCertPolicy {
+    /// Policy for routes with `requirement`; `Off` refuses nothing but
+    /// still passes on a certificate the client presented
+    pub fn new(requirement: ClientCertRequirement) -> Self {
+        Self {
+            requirement,
+            headers: ClientCertHeaders::default(),
+        }
+    }
+
+    /// Pass the certificate's details under these header names instead of
+    /// the defaults
+    pub fn with_headers(mut self, headers: ClientCertHeaders) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    fn set(req: &mut Request, name: &str, value: String) {
+        if !name.is_empty() && !value.is_empty() {
+            req.headers.insert(name.to_string(), value);
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for ClientCertPolicy {
+    async fn before_request(&self, req: &mut Request) -> Option<Response> {
+        req.headers.retain(|name, _| {
+            !self
+                .headers
+                .names()
+                .any(|configured| name.eq_ignore_ascii_case(configured))
+        });
+        match (self.requirement, req.context.client_cert.clone()) {
+            (ClientCertRequirement::Require, None) => Some(Response::error(
+                StatusCode::Forbidden,
+                "Client certificate required",
+            )),
+            (_, None) => None,
+            (_, Some(cert)) => {
+                Self::set(req, &self.headers.subject, cert.subject.clone());
+                Self::set(req, &self.headers.issuer, cert.issuer.clone());
+                Self::set(req, &self.headers.sha256, cert.sha256.clone());
+                if !self.headers.pem.is_empty() {
+                    Self::set(req, &self.headers.pem, cert.escaped_pem());
+                }
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! Cross-origin resource sharing
+//!
+//! [`Cors`] applies a route's CORS policy: preflight `OPTIONS` requests
+//! are answered directly, without reaching the backend, and responses to
+//! allowed origins get their `Access-Control-*` headers. Requests without
+//! an `Origin` header pass through untouched.
+//!
+//! # Example
+//!
+//! ```yaml
+//! routes:
+//!   - prefix: /api/
+//!     pool: api
+//!     cors:
+//!       allow_origins: ["https://app.example.com"]
+//!       allow_methods: [GET, POST, DELETE]
+//!       allow_headers: [Content-Type, Authorization]
+//!       allow_credentials: true
+//! ```
+
+use crate::config::CorsConfig;
+use crate::http::request::{Method, Request};
+use crate::http::response::{Response, StatusCode};
+use crate::middleware::Middleware;
+use async_trait::async_trait;
+use std::collections::HashMap;
+
+/// Middleware that answers preflights and adds CORS response headers
+///
+/// Preflights from origins, or asking for methods or headers, the policy
+/// doesn't allow get a 403. Responses to cross-origin requests have any
+/// `Access-Control-*` headers from the backend replaced by the policy's,
+/// so a backend can't allow more than the route does.
+pub struct Cors {
+    config: CorsConfig,
+    methods: String,
+}
+
+impl Cors {
+    /// Middleware applying `config`
+    pub fn new(config: CorsConfig) -> Self {
+        let methods = config.allow_methods.join(", ");
+        Self { config, methods }
+    }
+
+    /// The response to a preflight from `origin`
+    fn preflight(&self, req: &Request, origin: &str, method: &str) -> Response {
+        if !self.config.allows_origin(origin) {
+            return Response::error(StatusCode::Forbidden, "Origin not allowed");
+        }
+        if !self.config.allow_methods.iter().any(|m| m == method) {
+            return Response::error(StatusCode::Forbidden, "Method not allowed by CORS");
+        }
+        let requested = req.header("Access-Control-Request-Headers").unwrap_or("");
+        let headers: Vec<&str> = requested
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .collect();
+        if !headers.iter().all(|name| self.config.allows_header(name)) {
+            return Response::error(StatusCode::Forbidden, "Header not allowed by CORS");
+        }
+
+        let mut response = Response::new(StatusCode::NoContent)
+            .header("Access-Control-Allow-Methods", self.methods.clone())
+            .build();
+        self.allow(&mut response.headers, origin);
+        if !headers.is_empty() {
+            response
+                .headers
+                .insert("Access-Control-Allow-Headers".into(), headers.join(", "));
+        }
+        if self.config.max_age_secs > 0 {
+            response.headers.insert(
+                "Access-Control-Max-Age".into(),
+                self.config.max_age_secs.to_string(),
+            );
+        }
+        response
+    }
+
+    /// Allow `origin` to read the response
+    fn allow(&self, headers: &mut HashMap<String, String>, origin: &str) {
+        let allowed = if self.config.allows_any_origin() {
+            "*".to_string()
+        } else {
+            vary_on_origin(headers);
+            origin.to_string()
+        };
+        headers.insert("Access-Control-Allow-Origin".into(), allowed);
+        if self.config.allow_credentials {
+            headers.insert("Access-Control-Allow-Credentials".into(), "true".into());
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for Cors {
+    async fn before_request(&self, req: &mut Request) -> Option<Response> {
+        if req.method != Method::OPTIONS {
+            return None;
+        }
+        let origin = req.header("Origin")?;
+        let method = req.header("Access-Control-Request-Method")?;
+        Some(self.preflight(req, origin.trim(), method.trim()))
+    }
+
+    async fn after_response(&self, req: &Request, response: &mut Response) {
+        let Some(origin) = req.header("Origin").map(str::trim) else {
+            return;
+        };
+        response
+            .headers
+            .retain(|name, _| !name.to_ascii_lowercase().starts_with("access-control-"));
+        if !self.config.allows_origin(origin) {
+            return;
+        }
+        self.allow(&mut response.headers, origin);
+        if !self.config.expose_headers.is_empty() {
+            response.headers.insert(
+                "Access-Control-Expose-Headers".into(),
+                self.config.expose_headers.join(", "),
+            );
+        }
+    }
+}
+
+/// Add `Origin` to the response's `Vary` header, so caches keep one copy
+/// per origin
+fn vary_on_origin(headers: &mut HashMap<String, String>) {
+    match headers
+        .iter_mut()
+        .find(|(name, _)| name.eq_ignore_ascii_case("Vary"))
+    {
+        Some((_, vary)) => {
+            let listed = vary
+                .split(',')
+                .any(|v| v.trim() == "*" || v.trim().eq_ignore_ascii_case("Origin"));
+            if !listed {
+                vary.push_str(", Origin");
+            }
+        }
+        None => {
+            headers.insert("Vary".into(), "Origin".into());
+        }
+    }
+}
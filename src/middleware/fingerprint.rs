@@ -0,0 +1,90 @@
+//! TLS fingerprint allow and deny rules
+//!
+//! Refuses requests from TLS clients whose JA3 hash or JA4 fingerprint
+//! (see [`crate::tls::fingerprint`]) is denied, or not allowed when an
+//! allow list is set. Scrapers and bots that fake a browser User-Agent
+//! usually still send their TLS library's ClientHello, so this catches
+//! clients that header rules miss.
+//!
+//! Refusals answer 403 and count towards
+//! `sentinel_fingerprint_rejections_total{reason}` (`denied` or
+//! `not_allowed`).
+//!
+//! # Example
+//!
+//! ```yaml
+//! fingerprints:
+//!   deny:
+//!     - t13d1516h2_8daaf6152771_e5627efa2ab1
+//! ```
+
+use crate::config::FingerprintRulesConfig;
+use crate::http::handler::Handler;
+use crate::http::request::Request;
+use crate::http::response::{Response, StatusCode};
+use crate::metrics::Metrics;
+use crate::tls::TlsFingerprint;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// Handler decorator that applies fingerprint rules
+pub struct FingerprintFilter {
+    inner: Arc<dyn Handler>,
+    config: FingerprintRulesConfig,
+    metrics: Metrics,
+}
+
+impl FingerprintFilter {
+    /// Wrap `inner`, refusing clients according to `config`
+    pub fn new(inner: impl Handler, config: FingerprintRulesConfig) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            config,
+            metrics: Metrics::default(),
+        }
+    }
+
+    /// Count rejections through the given recorder
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Why a client with `fingerprint` is refused, if it is
+    pub fn rejection(&self, fingerprint: &TlsFingerprint) -> Option<&'static str> {
+        if self.config.deny.iter().any(|f| fingerprint.matches(f)) {
+            Some("denied")
+        } else if !self.config.allow.is_empty()
+            && !self.config.allow.iter().any(|f| fingerprint.matches(f))
+        {
+            Some("not_allowed")
+        } else {
+            None
+        }
+    }
+}
+
+#[async_trait]
+impl Handler for FingerprintFilter {
+    async fn handle(&self, req: Request) -> Response {
+        if let Some(fingerprint) = req.context.tls_fingerprint.as_deref()
+            && let Some(reason) = self.rejection(fingerprint)
+        {
+            tracing::info!(
+                ja3 = %fingerprint.ja3,
+                ja4 = %fingerprint.ja4,
+                reason,
+                "Refused request by TLS fingerprint"
+            );
+            self.metrics.increment(
+                "sentinel_fingerprint_rejections_total",
+                &[("reason", reason)],
+            );
+            return Response::new(StatusCode::Forbidden)
+                .header("Content-Type", "text/plain")
+                .body(b"403 Forbidden".to_vec())
+                .build();
+        }
+        self.inner.handle(req).await
+    }
+}
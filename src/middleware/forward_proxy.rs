@@ -0,0 +1,192 @@
+//! Forward proxy (CONNECT tunnelling) middleware
+//!
+//! Lets Sentinel double as a controlled egress proxy: clients send
+//! `CONNECT host:port`, and if the destination is on the allow-list (and the
+//! client presents valid `Proxy-Authorization: Basic` credentials, when users
+//! are configured) the connection becomes a raw TCP tunnel to it. All other
+//! requests pass through to the wrapped handler unchanged.
+//!
+//! The allow-list matches the name the client asked for, before DNS
+//! resolution, so it should list names and addresses under your control.
+//!
+//! # Example
+//!
+//! ```yaml
+//! forward_proxy:
+//!   enabled: true
+//!   allow:
+//!     - "api.stripe.com:443"
+//!     - "*.internal.example.com:*"
+//!   users:
+//!     - username: "billing"
+//!       password: "s3cret"
+//! ```
+
+use crate::config::ForwardProxyConfig;
+use crate::http::handler::Handler;
+use crate::http::request::{Method, Request};
+use crate::http::response::{Response, StatusCode};
+use crate::http::util::basic_credentials;
+use crate::metrics::Metrics;
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::time::Duration;
+use subtle::{Choice, ConstantTimeEq};
+use tokio::net::TcpStream;
+
+/// Handler decorator that answers `CONNECT` requests with tunnels
+pub struct ForwardProxyHandler {
+    inner: Arc<dyn Handler>,
+    config: ForwardProxyConfig,
+    metrics: Metrics,
+}
+
+impl ForwardProxyHandler {
+    /// Wrap `inner`, tunnelling `CONNECT` requests according to `config`
+    ///
+    /// A disabled config produces a pass-through handler.
+    pub fn new(inner: impl Handler, config: ForwardProxyConfig) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            config,
+            metrics: Metrics::default(),
+        }
+    }
+
+    /// Record tunnel outcomes to the given metrics recorder
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Whether the destination matches an allow-list entry
+    pub fn is_allowed(&self, host: &str, port: u16) -> bool {
+        self.config
+            .allow
+            .iter()
+            .any(|pattern| pattern_matches(pattern, host, port))
+    }
+
+    /// Whether the request carries credentials for a configured user
+    fn is_authorized(&self, req: &Request) -> bool {
+        if self.config.users.is_empty() {
+            return true;
+        }
+
+        let Some((username, password)) = req
+            .headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Proxy-Authorization"))
+            .and_then(|(_, value)| basic_credentials(value))
+        else {
+            return false;
+        };
+
+        // Every user is compared in full, in constant time, so timings
+        // don't tell how much of a name or password was right
+        self.config
+            .users
+            .iter()
+            .fold(Choice::from(0), |found, user| {
+                found
+                    | (user.username.as_bytes().ct_eq(username.as_bytes())
+                        & user.password.as_bytes().ct_eq(password.as_bytes()))
+            })
+            .into()
+    }
+
+    async fn connect(&self, req: &Request) -> Response {
+        let Some((host, port)) = parse_target(&req.path) else {
+            return self.refuse(StatusCode::BadRequest, "invalid", &req.path);
+        };
+
+        if !self.is_authorized(req) {
+            let mut response = self.refuse(
+                StatusCode::ProxyAuthenticationRequired,
+                "unauthorized",
+                &req.path,
+            );
+            response.headers.insert(
+                "Proxy-Authenticate".to_string(),
+                "Basic realm=\"sentinel\"".to_string(),
+            );
+            return response;
+        }
+
+        if !self.is_allowed(host, port) {
+            return self.refuse(StatusCode::Forbidden, "denied", &req.path);
+        }
+
+        let timeout = Duration::from_millis(self.config.connect_timeout_ms);
+        let connect = TcpStream::connect((host.trim_matches(['[', ']']), port));
+        match tokio::time::timeout(timeout, connect).await {
+            Ok(Ok(upstream)) => {
+                tracing::info!(destination = %req.path, "Opened CONNECT tunnel");
+                self.record("opened");
+                Response::tunnel(upstream)
+            }
+            Ok(Err(e)) => {
+                tracing::warn!(destination = %req.path, error = %e, "CONNECT destination unreachable");
+                self.refuse(StatusCode::BadGateway, "failed", &req.path)
+            }
+            Err(_) => {
+                tracing::warn!(destination = %req.path, "CONNECT destination timed out");
+                self.refuse(StatusCode::GatewayTimeout, "failed", &req.path)
+            }
+        }
+    }
+
+    fn refuse(&self, status: StatusCode, outcome: &str, target: &str) -> Response {
+        tracing::debug!(destination = %target, outcome, "Refused CONNECT request");
+        self.record(outcome);
+        Response::new(status)
+            .header("Content-Type", "text/plain")
+            .body(format!("{} {}", status.as_u16(), status.reason_phrase()).into_bytes())
+            .build()
+    }
+
+    fn record(&self, outcome: &str) {
+        self.metrics
+            .increment("sentinel_tunnels_total", &[("outcome", outcome)]);
+    }
+}
+
+#[async_trait]
+impl Handler for ForwardProxyHandler {
+    async fn handle(&self, req: Request) -> Response {
+        if req.method != Method::CONNECT || !self.config.enabled {
+            return self.inner.handle(req).await;
+        }
+        self.connect(&req).await
+    }
+}
+
+/// Split a `host:port` CONNECT target (IPv6 hosts in brackets)
+fn parse_target(target: &str) -> Option<(&str, u16)> {
+    let (host, port) = target.rsplit_once(':')?;
+    let port = port.parse().ok()?;
+    if host.is_empty() || port == 0 {
+        return None;
+    }
+    Some((host, port))
+}
+
+/// Match a `host:port` allow-list pattern (see [`ForwardProxyConfig::allow`])
+fn pattern_matches(pattern: &str, host: &str, port: u16) -> bool {
+    let Some((host_pattern, port_pattern)) = pattern.rsplit_once(':') else {
+        return false;
+    };
+
+    let port_ok = port_pattern == "*" || port_pattern.parse() == Ok(port);
+    let host = host.to_ascii_lowercase();
+    let host_pattern = host_pattern.to_ascii_lowercase();
+    let host_ok = if host_pattern == "*" {
+        true
+    } else if let Some(suffix) = host_pattern.strip_prefix("*.") {
+        host.ends_with(&format!(".{}", suffix))
+    } else {
+        host == host_pattern
+    };
+
+    port_ok && host_ok
+}
@@ -0,0 +1,180 @@
+//! Request and response header rules
+//!
+//! [`HeaderRewrite`] adds, replaces, and removes request headers before the
+//! request is passed on, so backends can be given tenant or client details
+//! and kept from seeing internal headers without changing them, and
+//! response headers before the response is written, e.g. to hide
+//! `X-Powered-By` or override `Cache-Control`.
+//!
+//! # Example
+//!
+//! ```yaml
+//! routes:
+//!   - prefix: /api/
+//!     name: api
+//!     pool: api
+//!     request_headers:
+//!       set: { X-Tenant: acme, X-Client-Ip: "$client_ip" }
+//!       add: { X-Trace: "route=$route" }
+//!       remove: [X-Internal-Debug]
+//!     response_headers:
+//!       set: { Cache-Control: "no-store" }
+//!       remove: [X-Powered-By, Server]
+//! ```
+
+use crate::config::HeaderRules;
+use crate::http::cookie::SET_COOKIE_SEPARATOR;
+use crate::http::error_pages::REQUEST_ID_HEADER;
+use crate::http::request::Request;
+use crate::http::response::Response;
+use crate::middleware::Middleware;
+use async_trait::async_trait;
+use rand::Rng;
+use std::collections::HashMap;
+
+/// Values of the template variables for one request
+type Vars = [(&'static str, String); 3];
+
+/// Middleware that applies header rules to requests and responses
+///
+/// Response rules apply to every response, errors Sentinel generates
+/// included, and their variables describe the request as passed on.
+pub struct HeaderRewrite {
+    request: HeaderRules,
+    response: HeaderRules,
+    route: String,
+}
+
+impl HeaderRewrite {
+    /// Rewrite without rules; `route` is the value of `$route`
+    pub fn new(route: impl Into<String>) -> Self {
+        Self {
+            request: HeaderRules::default(),
+            response: HeaderRules::default(),
+            route: route.into(),
+        }
+    }
+
+    /// Change request headers by `rules`
+    pub fn with_request(mut self, rules: HeaderRules) -> Self {
+        self.request = rules;
+        self
+    }
+
+    /// Change response headers by `rules`
+    pub fn with_response(mut self, rules: HeaderRules) -> Self {
+        self.response = rules;
+        self
+    }
+
+    /// Apply the request rules to `req`
+    pub fn apply_request(&self, req: &mut Request) {
+        let vars = is_templated(&self.request).then(|| vars(req, &self.route));
+        apply(&mut req.headers, &self.request, vars.as_ref());
+    }
+
+    /// Apply the response rules to the response to `req`
+    pub fn apply_response(&self, req: &Request, response: &mut Response) {
+        let vars = is_templated(&self.response).then(|| vars(req, &self.route));
+        apply(&mut response.headers, &self.response, vars.as_ref());
+    }
+}
+
+#[async_trait]
+impl Middleware for HeaderRewrite {
+    async fn before_request(&self, req: &mut Request) -> Option<Response> {
+        self.apply_request(req);
+        None
+    }
+
+    async fn after_response(&self, req: &Request, response: &mut Response) {
+        self.apply_response(req, response);
+    }
+}
+
+/// Whether any value of `rules` uses a variable
+fn is_templated(rules: &HeaderRules) -> bool {
+    rules
+        .add
+        .values()
+        .chain(rules.set.values())
+        .any(|value| value.contains('$'))
+}
+
+fn vars(req: &Request, route: &str) -> Vars {
+    let request_id = req
+        .header(REQUEST_ID_HEADER)
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("{:016x}", rand::rng().random::<u64>()));
+    let client_ip = req
+        .context
+        .peer
+        .map(|peer| peer.ip().to_canonical().to_string())
+        .unwrap_or_default();
+    [
+        ("$client_ip", client_ip),
+        ("$request_id", request_id),
+        ("$route", route.to_string()),
+    ]
+}
+
+/// Remove, then set, then add headers by `rules`
+fn apply(headers: &mut HashMap<String, String>, rules: &HeaderRules, vars: Option<&Vars>) {
+    let expand = |value: &str| match vars {
+        Some(vars) => expand(value, vars),
+        None => value.to_string(),
+    };
+
+    for name in &rules.remove {
+        remove(headers, name);
+    }
+    for (name, value) in &rules.set {
+        remove(headers, name);
+        headers.insert(name.clone(), expand(value));
+    }
+    for (name, value) in &rules.add {
+        let value = expand(value);
+        let value = match remove(headers, name) {
+            // Cookies share one entry, each on its own line
+            Some(existing) if name.eq_ignore_ascii_case("Set-Cookie") => {
+                format!("{}{}{}", existing, SET_COOKIE_SEPARATOR, value)
+            }
+            Some(existing) if !existing.trim().is_empty() => format!("{}, {}", existing, value),
+            _ => value,
+        };
+        headers.insert(name.clone(), value);
+    }
+}
+
+/// Remove header `name` whatever its case, returning its value
+fn remove(headers: &mut HashMap<String, String>, name: &str) -> Option<String> {
+    let key = headers
+        .keys()
+        .find(|key| key.eq_ignore_ascii_case(name))
+        .cloned()?;
+    headers.remove(&key)
+}
+
+/// `template` with each variable replaced by its value
+fn expand(template: &str, vars: &Vars) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(pos) = rest.find('$') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        match vars.iter().find(|(name, _)| rest.starts_with(name)) {
+            Some((name, value)) => {
+                out.push_str(value);
+                rest = &rest[name.len()..];
+            }
+            None => {
+                out.push('$');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
@@ -0,0 +1,351 @@
+//! Idempotency-Key request deduplication
+//!
+//! Clients retrying a payment-style request send the same
+//! `Idempotency-Key` each time. On opted-in routes the first request with a
+//! key is forwarded; every other request with that key, concurrent or
+//! later, waits for and receives the same response (marked with
+//! [`REPLAYED_HEADER`]) until the entry expires. Reusing a key for a
+//! different body is rejected with 422.
+//!
+//! Keys are scoped to the client and the request's method and path: the
+//! same key sent by another client, or to another endpoint, is a different
+//! entry. A client is identified by its `Authorization` header, else its
+//! TLS client certificate, else its IP address, so clients can't replay
+//! each other's responses by reusing or guessing keys.
+//!
+//! Responses are kept in memory, so deduplication is per Sentinel instance.
+//! Streamed responses are passed through without being kept.
+//!
+//! # Example
+//!
+//! ```yaml
+//! idempotency:
+//!   enabled: true
+//!   routes: ["/payments"]
+//!   ttl_secs: 86400
+//! ```
+
+use crate::config::IdempotencyConfig;
+use crate::http::handler::Handler;
+use crate::http::request::Request;
+use crate::http::response::{Response, StatusCode};
+use crate::http::spool::SpooledBody;
+use crate::metrics::Metrics;
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncReadExt;
+use tokio::sync::OnceCell;
+
+/// Header added to responses replayed from the cache
+pub const REPLAYED_HEADER: &str = "Idempotent-Replayed";
+
+/// A response kept for replay
+#[derive(Debug, Clone)]
+struct StoredResponse {
+    status: StatusCode,
+    reason: Option<String>,
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+impl StoredResponse {
+    /// Whether all of `response` is in memory and can be replayed
+    fn can_keep(response: &Response) -> bool {
+        response.stream.is_none() && response.tunnel.is_none()
+    }
+
+    fn keep(response: Response) -> Self {
+        Self {
+            status: response.status,
+            reason: response.reason,
+            headers: response.headers,
+            body: response.body,
+        }
+    }
+
+    fn to_response(&self) -> Response {
+        let mut response = Response::new(self.status);
+        if let Some(reason) = &self.reason {
+            response = response.reason(reason.clone());
+        }
+        response
+            .with_headers(self.headers.clone())
+            .with_body(self.body.clone())
+            .build()
+    }
+}
+
+/// What became of the first request with a key: its response, or `None`
+/// if the response could not be kept
+type Slot = Arc<OnceCell<Option<StoredResponse>>>;
+
+struct Entry {
+    /// Hash of the body of the first request
+    fingerprint: [u8; 32],
+    created: Instant,
+    response: Slot,
+}
+
+/// Why a request with a key is not deduplicated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Skip {
+    /// The key was used for a request with a different body
+    Conflict,
+    /// The cache is full, or the body could not be read
+    Bypass,
+}
+
+impl Skip {
+    /// Value of the `outcome` metric label
+    fn as_str(self) -> &'static str {
+        match self {
+            Skip::Conflict => "conflict",
+            Skip::Bypass => "bypass",
+        }
+    }
+}
+
+/// Handler decorator that replays responses for repeated idempotency keys
+pub struct IdempotencyHandler {
+    inner: Arc<dyn Handler>,
+    config: IdempotencyConfig,
+    entries: Mutex<HashMap<String, Entry>>,
+    metrics: Metrics,
+}
+
+impl IdempotencyHandler {
+    /// Wrap `inner`, deduplicating requests according to `config`
+    ///
+    /// A disabled config produces a pass-through handler.
+    pub fn new(inner: impl Handler, mut config: IdempotencyConfig) -> Self {
+        if !config.enabled {
+            config.routes.clear();
+        }
+        Self {
+            inner: Arc::new(inner),
+            config,
+            entries: Mutex::new(HashMap::new()),
+            metrics: Metrics::default(),
+        }
+    }
+
+    /// Record deduplication outcomes through the given recorder
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Idempotency key sent with a request on an opted-in route
+    fn key<'a>(&self, req: &'a Request) -> Option<&'a str> {
+        let path = req.path.split('?').next().unwrap_or(&req.path);
+        if !self
+            .config
+            .routes
+            .iter()
+            .any(|r| path.starts_with(r.as_str()))
+        {
+            return None;
+        }
+        req.headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(&self.config.header))
+            .map(|(_, value)| value.trim())
+            .filter(|key| !key.is_empty())
+    }
+
+    /// Find or create the entry for `key`
+    ///
+    /// Returns why not when the request cannot be deduplicated.
+    fn entry(&self, key: &str, fingerprint: [u8; 32]) -> Result<Slot, Skip> {
+        let ttl = Duration::from_secs(self.config.ttl_secs);
+        let mut entries = self.entries.lock().unwrap();
+
+        if let Some(entry) = entries.get(key)
+            && entry.created.elapsed() < ttl
+        {
+            return if entry.fingerprint == fingerprint {
+                Ok(entry.response.clone())
+            } else {
+                Err(Skip::Conflict)
+            };
+        }
+
+        if entries.len() >= self.config.max_entries {
+            entries.retain(|_, entry| entry.created.elapsed() < ttl);
+            if entries.len() >= self.config.max_entries {
+                tracing::warn!(
+                    max_entries = self.config.max_entries,
+                    "Idempotency cache full, not deduplicating"
+                );
+                return Err(Skip::Bypass);
+            }
+        }
+
+        let response = Arc::new(OnceCell::new());
+        entries.insert(
+            key.to_string(),
+            Entry {
+                fingerprint,
+                created: Instant::now(),
+                response: response.clone(),
+            },
+        );
+        Ok(response)
+    }
+
+    /// Drop the entry for `key` if it still holds `response`
+    fn forget(&self, key: &str, response: &Slot) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries
+            .get(key)
+            .is_some_and(|entry| Arc::ptr_eq(&entry.response, response))
+        {
+            entries.remove(key);
+        }
+    }
+
+    fn record(&self, outcome: &str) {
+        self.metrics.increment(
+            "sentinel_idempotency_requests_total",
+            &[("outcome", outcome)],
+        );
+    }
+}
+
+#[async_trait]
+impl Handler for IdempotencyHandler {
+    async fn handle(&self, req: Request) -> Response {
+        let Some(key) = self.key(&req).map(|key| scoped_key(&req, key)) else {
+            return self.inner.handle(req).await;
+        };
+
+        let fingerprint = match fingerprint(&req).await {
+            Ok(fingerprint) => fingerprint,
+            Err(e) => {
+                tracing::warn!(error = %e, "Cannot read spooled body, not deduplicating");
+                self.record(Skip::Bypass.as_str());
+                return self.inner.handle(req).await;
+            }
+        };
+        let cell = match self.entry(&key, fingerprint) {
+            Ok(cell) => cell,
+            Err(Skip::Conflict) => {
+                self.record(Skip::Conflict.as_str());
+                tracing::debug!(key = %key, path = %req.path, "Idempotency key reused for a different request");
+                return Response::new(StatusCode::UnprocessableEntity)
+                    .with_header("Content-Type", "text/plain")
+                    .with_body(b"Idempotency key was already used for a different request".to_vec())
+                    .build();
+            }
+            Err(skip) => {
+                self.record(skip.as_str());
+                return self.inner.handle(req).await;
+            }
+        };
+
+        // Only the request that fills the cell reaches the backend; if it is
+        // cancelled, a waiting duplicate takes over
+        let mut forwarded = false;
+        let mut streamed = None;
+        let stored = cell
+            .get_or_init(|| async {
+                forwarded = true;
+                let response = self.inner.handle(req).await;
+                if StoredResponse::can_keep(&response) {
+                    Some(StoredResponse::keep(response))
+                } else {
+                    streamed = Some(response);
+                    None
+                }
+            })
+            .await;
+
+        let Some(stored) = stored else {
+            self.forget(&key, &cell);
+            if let Some(response) = streamed {
+                self.record("miss");
+                return response;
+            }
+            // The duplicate arrived while a streamed response was in flight
+            self.record(Skip::Conflict.as_str());
+            return Response::new(StatusCode::Conflict)
+                .with_header("Content-Type", "text/plain")
+                .with_body(b"A request with this idempotency key is still in progress".to_vec())
+                .build();
+        };
+        if stored.status.as_u16() >= 500 {
+            self.forget(&key, &cell);
+        }
+
+        let mut response = stored.to_response();
+        if forwarded {
+            self.record("miss");
+        } else {
+            self.record("replay");
+            response
+                .headers
+                .insert(REPLAYED_HEADER.to_string(), "true".to_string());
+        }
+        response
+    }
+}
+
+/// Cache key for `key` sent by the request's client to its method and path
+fn scoped_key(req: &Request, key: &str) -> String {
+    let authorization = req
+        .headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("Authorization"))
+        .map(|(_, value)| value.as_str());
+    let client = if let Some(authorization) = authorization {
+        format!("auth {}", authorization)
+    } else if let Some(cert) = &req.context.client_cert {
+        format!("cert {}", cert.sha256)
+    } else if let Some(peer) = req.context.peer {
+        format!("ip {}", peer.ip())
+    } else {
+        String::new()
+    };
+
+    // Hashed so credentials aren't kept in memory as they were sent
+    let mut hasher = Sha256::new();
+    for part in [
+        client.as_str(),
+        &format!("{:?}", req.method),
+        &req.path,
+        key,
+    ] {
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part.as_bytes());
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Hash of the body a key was first used with, read from disk if spooled
+async fn fingerprint(req: &Request) -> std::io::Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    hasher.update(&req.body);
+    if let Some(spooled) = &req.spooled {
+        hash_spooled(&mut hasher, spooled).await?;
+    }
+    Ok(hasher.finalize().into())
+}
+
+async fn hash_spooled(hasher: &mut Sha256, body: &SpooledBody) -> std::io::Result<()> {
+    let mut file = body.open().await?;
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        hasher.update(&buf[..n]);
+    }
+}
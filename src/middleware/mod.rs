@@ -0,0 +1,54 @@
+//! Request middleware
+//!
+//! Middleware wraps another [`Handler`](crate::http::handler::Handler) and
+//! can alter the request on the way in, the response on the way out, or
+//! short-circuit the inner handler entirely. A [`Middleware`] does the same
+//! through hooks, run in order by a [`MiddlewareChain`].
+//!
+//! - `bots`: Rule-based bot detection with allow, block, tarpit and route actions
+//! - `chain`: The `Middleware` trait and chains running middlewares in order
+//! - `client_cert`: Per-route client certificate requirements (mutual TLS)
+//! - `cors`: Cross-origin policies with preflight responses
+//! - `chaos`: Fault injection (latency, errors, aborts, truncated bodies)
+//! - `fingerprint`: Allow and deny rules on JA3/JA4 TLS client fingerprints
+//! - `forward_proxy`: CONNECT tunnelling to allow-listed destinations
+//! - `headers`: Request and response header add, set and remove rules
+//! - `idempotency`: Replay of responses for retried Idempotency-Key requests
+//! - `policy`: Authorization by OPA or Cedar policies
+//! - `redirect`: Canonical host and trailing-slash redirects
+//! - `rewrite`: Request path rewriting before routing to a backend
+//! - `security_headers`: HSTS, CSP and other security response headers
+//! - `slo`: Availability and latency objectives with burn-rate metrics
+//! - `traffic_capture`: Sampled request and response capture into HAR files
+
+pub mod bots;
+pub mod chain;
+pub mod chaos;
+pub mod client_cert;
+pub mod cors;
+pub mod fingerprint;
+pub mod forward_proxy;
+pub mod headers;
+pub mod idempotency;
+pub mod policy;
+pub mod redirect;
+pub mod rewrite;
+pub mod security_headers;
+pub mod slo;
+pub mod traffic_capture;
+
+pub use bots::BotHandler;
+pub use chain::{Middleware, MiddlewareChain};
+pub use chaos::ChaosHandler;
+pub use client_cert::ClientCertPolicy;
+pub use cors::Cors;
+pub use fingerprint::FingerprintFilter;
+pub use forward_proxy::ForwardProxyHandler;
+pub use headers::HeaderRewrite;
+pub use idempotency::IdempotencyHandler;
+pub use policy::PolicyHandler;
+pub use redirect::RedirectHandler;
+pub use rewrite::{PathRewrite, RewriteHandler};
+pub use security_headers::SecurityHeaders;
+pub use slo::{SloHandler, SloTracker};
+pub use traffic_capture::TrafficCaptureHandler;
@@ -0,0 +1,398 @@
+//! Authorization by an external policy engine
+//!
+//! Each request is described as a JSON document (see [`policy_input`]) and
+//! handed to a [`PolicyEngine`]. Denied requests answer 403, with the
+//! policy's reason logged; when the engine itself fails the request answers
+//! 503, unless `fail_open` lets it through.
+//!
+//! Two engines are built in:
+//!
+//! - [`OpaEngine`]: queries an Open Policy Agent over its data API
+//! - [`CedarEngine`]: evaluates Cedar policies in-process (`cedar` feature)
+//!
+//! Decisions count towards `sentinel_policy_decisions_total{engine,
+//! decision}` (`allow`, `deny` or `error`) and evaluation time towards
+//! `sentinel_policy_evaluation_seconds{engine}`.
+//!
+//! # Example
+//!
+//! ```yaml
+//! policy:
+//!   opa:
+//!     url: "http://127.0.0.1:8181/v1/data/sentinel/authz"
+//!     timeout_ms: 500
+//! ```
+//!
+//! with a Rego policy such as:
+//!
+//! ```text
+//! package sentinel.authz
+//!
+//! default allow := false
+//! allow if input.method == "GET"
+//! reason := "writes are disabled" if not allow
+//! ```
+
+use crate::config::{OpaConfig, PolicyConfig};
+use crate::discovery::http::post_url;
+use crate::http::handler::Handler;
+use crate::http::request::Request;
+use crate::http::response::{Response, StatusCode};
+use crate::metrics::Metrics;
+use anyhow::Context;
+use async_trait::async_trait;
+use serde_json::{Map, Value, json};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Outcome of a policy evaluation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyDecision {
+    pub allowed: bool,
+    /// Why the request was denied, if the policy says
+    pub reason: Option<String>,
+}
+
+/// Something that can decide whether a request is authorized
+#[async_trait]
+pub trait PolicyEngine: Send + Sync + 'static {
+    /// Name used in logs and metrics
+    fn name(&self) -> &'static str;
+
+    /// Decide on a request described by [`policy_input`]
+    async fn decide(&self, input: &Value) -> anyhow::Result<PolicyDecision>;
+}
+
+/// Describe `req` for policy evaluation
+///
+/// ```json
+/// {
+///   "method": "GET",
+///   "path": "/orders/7",
+///   "query": "expand=items",
+///   "headers": {"user-agent": "curl/8.0"},
+///   "client": {"ip": "10.0.0.7"},
+///   "tls": {"ja3": "...", "ja4": "..."}
+/// }
+/// ```
+///
+/// Header names are lowercased. `query`, `client` and `tls` are left out
+/// when unknown rather than set to null, which Cedar contexts cannot hold.
+pub fn policy_input(req: &Request) -> Value {
+    let (path, query) = match req.path.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (req.path.as_str(), None),
+    };
+    let headers: Map<String, Value> = req
+        .headers
+        .iter()
+        .map(|(name, value)| (name.to_ascii_lowercase(), Value::from(value.as_str())))
+        .collect();
+
+    let mut input = Map::new();
+    input.insert("method".into(), format!("{:?}", req.method).into());
+    input.insert("path".into(), path.into());
+    if let Some(query) = query {
+        input.insert("query".into(), query.into());
+    }
+    input.insert("headers".into(), Value::Object(headers));
+    if let Some(peer) = req.context.peer {
+        input.insert("client".into(), json!({ "ip": peer.ip().to_string() }));
+    }
+    if let Some(fingerprint) = &req.context.tls_fingerprint {
+        input.insert(
+            "tls".into(),
+            json!({ "ja3": fingerprint.ja3, "ja4": fingerprint.ja4 }),
+        );
+    }
+    Value::Object(input)
+}
+
+/// Open Policy Agent, queried over its data API
+///
+/// The input is POSTed as `{"input": ...}` to the configured document. A
+/// boolean result is the decision; an object result is read for `allow`
+/// and `reason`. An undefined document denies.
+pub struct OpaEngine {
+    url: url::Url,
+    timeout: Duration,
+}
+
+impl OpaEngine {
+    pub fn new(config: &OpaConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            url: url::Url::parse(&config.url)
+                .with_context(|| format!("Invalid OPA URL '{}'", config.url))?,
+            timeout: Duration::from_millis(config.timeout_ms),
+        })
+    }
+}
+
+#[async_trait]
+impl PolicyEngine for OpaEngine {
+    fn name(&self) -> &'static str {
+        "opa"
+    }
+
+    async fn decide(&self, input: &Value) -> anyhow::Result<PolicyDecision> {
+        let body = serde_json::to_vec(&json!({ "input": input }))?;
+        let response = post_url(&self.url, "application/json", &body, self.timeout).await?;
+        if response.status != 200 {
+            anyhow::bail!("OPA answered {}", response.status);
+        }
+        let document: Value =
+            serde_json::from_slice(&response.body).context("OPA response is not JSON")?;
+
+        match document.get("result") {
+            None => Ok(PolicyDecision {
+                allowed: false,
+                reason: Some("policy decision is undefined".to_string()),
+            }),
+            Some(Value::Bool(allowed)) => Ok(PolicyDecision {
+                allowed: *allowed,
+                reason: None,
+            }),
+            Some(Value::Object(result)) => Ok(PolicyDecision {
+                allowed: result
+                    .get("allow")
+                    .and_then(Value::as_bool)
+                    .unwrap_or(false),
+                reason: result
+                    .get("reason")
+                    .and_then(Value::as_str)
+                    .map(str::to_string),
+            }),
+            Some(other) => {
+                anyhow::bail!("OPA result is neither a boolean nor an object: {}", other)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "cedar")]
+pub use cedar::CedarEngine;
+
+#[cfg(feature = "cedar")]
+mod cedar {
+    use super::{PolicyDecision, PolicyEngine};
+    use crate::config::CedarConfig;
+    use anyhow::Context as _;
+    use async_trait::async_trait;
+    use cedar_policy::{
+        Authorizer, Context, Decision, Entities, EntityId, EntityTypeName, EntityUid, PolicySet,
+        Request,
+    };
+    use serde_json::Value;
+    use std::str::FromStr;
+
+    /// Cedar policies evaluated in-process
+    ///
+    /// Requests are `Client::"<ip>"` (or `Client::"unknown"`) performing
+    /// `Action::"<METHOD>"` on `Path::"<path>"`, with the whole
+    /// [`policy_input`](super::policy_input) as context. A denial's reason is
+    /// the `@reason` annotation of the deciding `forbid` policies, or their
+    /// ids.
+    pub struct CedarEngine {
+        policies: PolicySet,
+        entities: Entities,
+        authorizer: Authorizer,
+    }
+
+    impl CedarEngine {
+        pub fn load(config: &CedarConfig) -> anyhow::Result<Self> {
+            let source = std::fs::read_to_string(&config.policies_file)
+                .with_context(|| format!("Failed to read {}", config.policies_file.display()))?;
+            let policies = PolicySet::from_str(&source).map_err(|e| {
+                anyhow::anyhow!(
+                    "Invalid Cedar policies in {}: {}",
+                    config.policies_file.display(),
+                    e
+                )
+            })?;
+            let entities = match &config.entities_file {
+                Some(path) => {
+                    let file = std::fs::File::open(path)
+                        .with_context(|| format!("Failed to open {}", path.display()))?;
+                    Entities::from_json_file(file, None)
+                        .with_context(|| format!("Invalid Cedar entities in {}", path.display()))?
+                }
+                None => Entities::empty(),
+            };
+            Ok(Self {
+                policies,
+                entities,
+                authorizer: Authorizer::new(),
+            })
+        }
+    }
+
+    fn uid(kind: &str, id: &str) -> anyhow::Result<EntityUid> {
+        let kind = EntityTypeName::from_str(kind).map_err(|e| anyhow::anyhow!("{}", e))?;
+        let id = EntityId::from_str(id).map_err(|e| anyhow::anyhow!("{}", e))?;
+        Ok(EntityUid::from_type_name_and_id(kind, id))
+    }
+
+    #[async_trait]
+    impl PolicyEngine for CedarEngine {
+        fn name(&self) -> &'static str {
+            "cedar"
+        }
+
+        async fn decide(&self, input: &Value) -> anyhow::Result<PolicyDecision> {
+            let field = |pointer: &str| input.pointer(pointer).and_then(Value::as_str);
+            let request = Request::new(
+                Some(uid("Client", field("/client/ip").unwrap_or("unknown"))?),
+                Some(uid("Action", field("/method").unwrap_or_default())?),
+                Some(uid("Path", field("/path").unwrap_or_default())?),
+                Context::from_json_value(input.clone(), None)
+                    .context("Request cannot be a Cedar context")?,
+            );
+            let response = self
+                .authorizer
+                .is_authorized(&request, &self.policies, &self.entities);
+            for error in response.diagnostics().errors() {
+                tracing::warn!(error = %error, "Cedar policy evaluation error");
+            }
+
+            if response.decision() == Decision::Allow {
+                return Ok(PolicyDecision {
+                    allowed: true,
+                    reason: None,
+                });
+            }
+            let reasons: Vec<String> = response
+                .diagnostics()
+                .reason()
+                .map(|id| {
+                    self.policies
+                        .annotation(id, "reason")
+                        .map(str::to_string)
+                        .unwrap_or_else(|| id.to_string())
+                })
+                .collect();
+            Ok(PolicyDecision {
+                allowed: false,
+                reason: Some(if reasons.is_empty() {
+                    "no policy permits the request".to_string()
+                } else {
+                    reasons.join(", ")
+                }),
+            })
+        }
+    }
+}
+
+/// Handler decorator that enforces policy decisions
+pub struct PolicyHandler {
+    inner: Arc<dyn Handler>,
+    engine: Arc<dyn PolicyEngine>,
+    fail_open: bool,
+    metrics: Metrics,
+}
+
+impl PolicyHandler {
+    /// Wrap `inner`, authorizing every request with `engine`
+    pub fn new(inner: impl Handler, engine: impl PolicyEngine) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            engine: Arc::new(engine),
+            fail_open: false,
+            metrics: Metrics::default(),
+        }
+    }
+
+    /// Wrap `inner` with the engine described by `config`
+    pub fn from_config(inner: impl Handler, config: &PolicyConfig) -> anyhow::Result<Self> {
+        config.validate()?;
+        let handler = match (&config.opa, &config.cedar) {
+            (Some(opa), _) => Self::new(inner, OpaEngine::new(opa)?),
+            #[cfg(feature = "cedar")]
+            (None, Some(cedar)) => Self::new(inner, CedarEngine::load(cedar)?),
+            _ => unreachable!("validated above"),
+        };
+        Ok(handler.with_fail_open(config.fail_open))
+    }
+
+    /// Serve requests when the engine fails, instead of answering 503
+    pub fn with_fail_open(mut self, fail_open: bool) -> Self {
+        self.fail_open = fail_open;
+        self
+    }
+
+    /// Count decisions through the given recorder
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Name of the engine in use
+    pub fn engine(&self) -> &'static str {
+        self.engine.name()
+    }
+}
+
+#[async_trait]
+impl Handler for PolicyHandler {
+    async fn handle(&self, req: Request) -> Response {
+        let engine = self.engine.name();
+        let input = policy_input(&req);
+        let started = Instant::now();
+        let result = tokio::select! {
+            result = self.engine.decide(&input) => result,
+            _ = req.context.cancelled() => Err(anyhow::anyhow!("request cancelled")),
+        };
+        self.metrics.histogram(
+            "sentinel_policy_evaluation_seconds",
+            &[("engine", engine)],
+            started.elapsed().as_secs_f64(),
+        );
+
+        match result {
+            Ok(decision) if decision.allowed => {
+                self.metrics.increment(
+                    "sentinel_policy_decisions_total",
+                    &[("engine", engine), ("decision", "allow")],
+                );
+                self.inner.handle(req).await
+            }
+            Ok(decision) => {
+                self.metrics.increment(
+                    "sentinel_policy_decisions_total",
+                    &[("engine", engine), ("decision", "deny")],
+                );
+                tracing::info!(
+                    engine,
+                    method = ?req.method,
+                    path = %req.path,
+                    reason = decision.reason.as_deref().unwrap_or("denied by policy"),
+                    "Request denied by policy"
+                );
+                Response::new(StatusCode::Forbidden)
+                    .header("Content-Type", "text/plain")
+                    .body(b"403 Forbidden".to_vec())
+                    .build()
+            }
+            Err(e) => {
+                self.metrics.increment(
+                    "sentinel_policy_decisions_total",
+                    &[("engine", engine), ("decision", "error")],
+                );
+                tracing::warn!(
+                    engine,
+                    path = %req.path,
+                    fail_open = self.fail_open,
+                    "Policy evaluation failed: {:#}",
+                    e
+                );
+                if self.fail_open {
+                    self.inner.handle(req).await
+                } else {
+                    Response::new(StatusCode::ServiceUnavailable)
+                        .header("Content-Type", "text/plain")
+                        .body(b"503 Service Unavailable".to_vec())
+                        .build()
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,301 @@
+//! Redirect rules and canonical URL redirects
+//!
+//! Configured rules redirect requests by host, scheme, and path pattern
+//! (e.g. `http` to `https`, or an old domain's blog to a new one), with the
+//! `Location` built from a template. Requests no rule matches are
+//! redirected if they address the non-canonical host (`www.` or apex) or
+//! break the trailing-slash policy, so backends see one form of every URL.
+//! Both fixes are applied in a single redirect. Host names without a dot
+//! (`localhost`) and IP addresses are never rewritten.
+//!
+//! Rules match the listener's scheme, or `X-Forwarded-Proto` on requests
+//! from `server.trusted_proxies` (see [`TrustedProxies`]).
+//!
+//! # Example
+//!
+//! ```yaml
+//! redirects:
+//!   canonical_host: apex
+//!   trailing_slash: remove
+//!   trailing_slash_exclude: ["/api"]
+//!   scheme: https
+//!   rules:
+//!     - scheme: http
+//!       location: "https://$host$path$query"
+//! ```
+
+use crate::config::{CanonicalHost, RedirectConfig, RedirectRule, TrailingSlash};
+use crate::http::forwarded::TrustedProxies;
+use crate::http::handler::Handler;
+use crate::http::request::{Method, Request};
+use crate::http::response::{Response, StatusCode};
+use async_trait::async_trait;
+use regex::{Captures, Regex};
+use std::borrow::Cow;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+/// A redirect rule with its path expression compiled
+struct Rule {
+    config: RedirectRule,
+    regex: Option<Regex>,
+}
+
+/// Handler decorator that applies redirect rules and redirects to
+/// canonical URLs
+pub struct RedirectHandler {
+    inner: Arc<dyn Handler>,
+    config: RedirectConfig,
+    rules: Vec<Rule>,
+    tls: bool,
+    trusted: Arc<TrustedProxies>,
+}
+
+impl RedirectHandler {
+    /// Wrap `inner`, redirecting according to `config`
+    ///
+    /// Rules with an invalid `path_regex` are skipped (see
+    /// [`RedirectConfig::validate`]).
+    pub fn new(inner: impl Handler, config: RedirectConfig) -> Self {
+        let rules = config
+            .rules
+            .iter()
+            .filter_map(|rule| match rule.regex() {
+                Ok(regex) => Some(Rule {
+                    config: rule.clone(),
+                    regex,
+                }),
+                Err(e) => {
+                    tracing::warn!(error = %format!("{:#}", e), "Skipping redirect rule");
+                    None
+                }
+            })
+            .collect();
+        Self {
+            inner: Arc::new(inner),
+            config,
+            rules,
+            tls: false,
+            trusted: Arc::default(),
+        }
+    }
+
+    /// Treat requests as made over HTTPS, for listeners terminating TLS
+    pub fn with_tls(mut self, tls: bool) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    /// Take the scheme from `X-Forwarded-Proto` on requests from these
+    /// proxies
+    pub fn with_trusted_proxies(mut self, trusted: Arc<TrustedProxies>) -> Self {
+        self.trusted = trusted;
+        self
+    }
+
+    /// Status and location of the first redirect rule matching the request
+    pub fn rule_redirect(&self, req: &Request) -> Option<(StatusCode, String)> {
+        let (path, query) = match req.path.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (req.path.as_str(), None),
+        };
+        let host = req.host().unwrap_or_default();
+        let scheme = self.trusted.scheme(req, self.tls);
+
+        self.rules.iter().find_map(|rule| {
+            if let Some(expected) = &rule.config.scheme
+                && !expected.eq_ignore_ascii_case(scheme)
+            {
+                return None;
+            }
+            if let Some(pattern) = &rule.config.host
+                && !host_matches(pattern, split_port(host).0)
+            {
+                return None;
+            }
+            let captures = match &rule.regex {
+                Some(regex) => Some(regex.captures(path)?),
+                None => None,
+            };
+
+            let query = query.map(|q| format!("?{}", q)).unwrap_or_default();
+            let vars = [
+                ("scheme", scheme),
+                ("host", host),
+                ("path", path),
+                ("query", query.as_str()),
+            ];
+            let location = expand(&rule.config.location, &vars, captures.as_ref());
+            let status = StatusCode::from_u16(rule.config.status)?;
+            Some((status, location))
+        })
+    }
+
+    /// Location to redirect the request to, if it is not canonical
+    pub fn location(&self, req: &Request) -> Option<String> {
+        let (path, query) = match req.path.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (req.path.as_str(), None),
+        };
+        let host = req
+            .headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Host"))
+            .map(|(_, value)| value.trim());
+
+        let canonical_host = host.and_then(|host| self.canonical_host(host));
+        let canonical_path = self.canonical_path(path);
+        if canonical_host.is_none() && canonical_path.is_none() {
+            return None;
+        }
+
+        let mut location = match canonical_host {
+            Some(host) => format!("{}://{}", self.config.scheme, host),
+            None => String::new(),
+        };
+        location.push_str(&collapse_leading_slashes(
+            canonical_path.as_deref().unwrap_or(path),
+        ));
+        if let Some(query) = query {
+            location.push('?');
+            location.push_str(query);
+        }
+        Some(location)
+    }
+
+    fn canonical_host(&self, host: &str) -> Option<String> {
+        let policy = self.config.canonical_host?;
+        let (name, port) = split_port(host);
+        if !name.contains('.') || name.parse::<IpAddr>().is_ok() {
+            return None;
+        }
+
+        let www = name
+            .get(..4)
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case("www."));
+        let name = match policy {
+            CanonicalHost::Www if !www => format!("www.{}", name),
+            CanonicalHost::Apex if www => name[4..].to_string(),
+            _ => return None,
+        };
+        Some(format!("{}{}", name, port))
+    }
+
+    fn canonical_path(&self, path: &str) -> Option<String> {
+        let policy = self.config.trailing_slash?;
+        if path == "/"
+            || !path.starts_with('/')
+            || self
+                .config
+                .trailing_slash_exclude
+                .iter()
+                .any(|prefix| path.starts_with(prefix.as_str()))
+        {
+            return None;
+        }
+
+        match policy {
+            TrailingSlash::Add if !path.ends_with('/') => {
+                let last = path.rsplit('/').next().unwrap_or_default();
+                (!last.contains('.')).then(|| format!("{}/", path))
+            }
+            TrailingSlash::Remove if path.ends_with('/') => {
+                let trimmed = path.trim_end_matches('/');
+                Some(if trimmed.is_empty() { "/" } else { trimmed }.to_string())
+            }
+            _ => None,
+        }
+    }
+}
+
+#[async_trait]
+impl Handler for RedirectHandler {
+    async fn handle(&self, req: Request) -> Response {
+        if let Some((status, location)) = self.rule_redirect(&req) {
+            tracing::debug!(path = %req.path, location = %location, "Redirecting by rule");
+            return Response::new(status)
+                .with_header("Location", location)
+                .build();
+        }
+        let Some(location) = self.location(&req) else {
+            return self.inner.handle(req).await;
+        };
+
+        let status = match req.method {
+            Method::GET | Method::HEAD => StatusCode::MovedPermanently,
+            _ => StatusCode::PermanentRedirect,
+        };
+        tracing::debug!(path = %req.path, location = %location, "Redirecting to canonical URL");
+        Response::new(status)
+            .with_header("Location", location)
+            .build()
+    }
+}
+
+/// `path` with a run of leading slashes reduced to one
+///
+/// A relative `Location` of `//host/...` (or `/\host/...`, which browsers
+/// read the same way) would send the client to another site.
+fn collapse_leading_slashes(path: &str) -> Cow<'_, str> {
+    match path.strip_prefix('/') {
+        Some(rest) if rest.starts_with(['/', '\\']) => {
+            Cow::Owned(format!("/{}", rest.trim_start_matches(['/', '\\'])))
+        }
+        _ => Cow::Borrowed(path),
+    }
+}
+
+/// Whether `host` is `pattern`, or a subdomain of it for `*.` patterns
+fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host.len().checked_sub(domain.len() + 1).is_some_and(|dot| {
+            host.as_bytes()[dot] == b'.' && host[dot + 1..].eq_ignore_ascii_case(domain)
+        }),
+        None => host.eq_ignore_ascii_case(pattern),
+    }
+}
+
+/// Fill `$name` variables and `$1` to `$9` capture groups into `template`
+///
+/// Unknown variables and groups that did not participate are left empty.
+fn expand(template: &str, vars: &[(&str, &str)], captures: Option<&Captures>) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(idx) = rest.find('$') {
+        out.push_str(&rest[..idx]);
+        rest = &rest[idx + 1..];
+        let len = match rest.as_bytes().first() {
+            Some(b'1'..=b'9') => 1,
+            _ => rest
+                .find(|c: char| !c.is_ascii_alphabetic() && c != '_')
+                .unwrap_or(rest.len()),
+        };
+        let (name, after) = rest.split_at(len);
+        match name.parse::<usize>() {
+            Ok(group) => {
+                let value = captures.and_then(|c| c.get(group));
+                out.push_str(value.map_or("", |m| m.as_str()));
+            }
+            Err(_) if name.is_empty() => out.push('$'),
+            Err(_) => {
+                let value = vars.iter().find(|(var, _)| *var == name);
+                out.push_str(value.map_or("", |(_, value)| value));
+            }
+        }
+        rest = after;
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Split `host:port` into the name and the `:port` suffix (possibly empty)
+fn split_port(host: &str) -> (&str, &str) {
+    match host.rfind(':') {
+        Some(idx)
+            if !host.ends_with(']') && host[idx + 1..].bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            host.split_at(idx)
+        }
+        _ => (host, ""),
+    }
+}
@@ -0,0 +1,92 @@
+//! Request path rewriting
+//!
+//! Changes the path of a request before passing it on, so backends need
+//! not know the public URL layout. The query string is kept as sent.
+//!
+//! # Example
+//!
+//! ```yaml
+//! routes:
+//!   - prefix: /api/
+//!     strip_prefix: true
+//!     pool: api
+//!     backends:
+//!       - url: "http://127.0.0.1:3000"
+//!   - path_regex: '^/v1/users/(\d+)'
+//!     rewrite: "/users/$1"
+//!     pool: users
+//!     backends:
+//!       - url: "http://127.0.0.1:3001"
+//! ```
+
+use crate::http::handler::Handler;
+use crate::http::request::Request;
+use crate::http::response::Response;
+use async_trait::async_trait;
+use regex::Regex;
+use std::sync::Arc;
+
+/// How a request path is rewritten
+#[derive(Debug, Clone)]
+pub enum PathRewrite {
+    /// Replace the leading prefix (the first string) with the second
+    Prefix(String, String),
+    /// Replace the part of the path the expression matches with the
+    /// template, in which `$1` or `${name}` stand for capture groups
+    /// (`${1}` when followed by a letter, digit, or `_`)
+    Regex(Regex, String),
+}
+
+impl PathRewrite {
+    /// The rewritten form of `path` (without a query string)
+    ///
+    /// The result always starts with `/`.
+    pub fn apply(&self, path: &str) -> String {
+        let rewritten = match self {
+            PathRewrite::Prefix(prefix, replacement) => match path.strip_prefix(prefix.as_str()) {
+                Some(rest) => format!("{}{}", replacement, rest).into(),
+                None => path.into(),
+            },
+            PathRewrite::Regex(regex, template) => regex.replace(path, template.as_str()),
+        };
+        if rewritten.starts_with('/') {
+            rewritten.into_owned()
+        } else {
+            format!("/{}", rewritten)
+        }
+    }
+}
+
+/// Handler decorator that rewrites the request path
+pub struct RewriteHandler {
+    inner: Arc<dyn Handler>,
+    rewrite: PathRewrite,
+}
+
+impl RewriteHandler {
+    /// Wrap `inner`, passing it requests with paths rewritten by `rewrite`
+    pub fn new(inner: impl Handler, rewrite: PathRewrite) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            rewrite,
+        }
+    }
+}
+
+#[async_trait]
+impl Handler for RewriteHandler {
+    async fn handle(&self, mut req: Request) -> Response {
+        let (path, query) = match req.path.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (req.path.as_str(), None),
+        };
+        let mut rewritten = self.rewrite.apply(path);
+        if let Some(query) = query {
+            rewritten.push('?');
+            rewritten.push_str(query);
+        }
+        tracing::debug!(from = %req.path, to = %rewritten, "Rewrote request path");
+        req.path = rewritten;
+        self.inner.handle(req).await
+    }
+}
@@ -0,0 +1,119 @@
+//! Security response headers
+//!
+//! [`SecurityHeaders`] adds the headers browsers use to harden a site
+//! (`Strict-Transport-Security`, `X-Content-Type-Options`,
+//! `X-Frame-Options`, `Referrer-Policy` and `Content-Security-Policy`) to
+//! every response, so backends don't each have to send them. It runs for
+//! the whole server from the top-level `security_headers` section, or for
+//! one route from the route's own section, which then takes precedence.
+//!
+//! # Example
+//!
+//! ```yaml
+//! security_headers: {}
+//!
+//! routes:
+//!   - prefix: /embed/
+//!     pool: widgets
+//!     security_headers:
+//!       frame_options: sameorigin
+//!       content_security_policy: "frame-ancestors 'self' https://partner.example"
+//! ```
+
+use crate::config::SecurityHeadersConfig;
+use crate::http::forwarded::TrustedProxies;
+use crate::http::request::Request;
+use crate::http::response::Response;
+use crate::middleware::Middleware;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// Middleware that adds security headers to responses
+///
+/// Headers the response already has, from the backend or a route's own
+/// security headers, are kept unless the config sets `replace`.
+/// `Strict-Transport-Security` is only sent on HTTPS requests, since
+/// browsers ignore it over plain HTTP.
+pub struct SecurityHeaders {
+    headers: Vec<(&'static str, String)>,
+    hsts: Option<String>,
+    replace: bool,
+    tls: bool,
+    trusted: Arc<TrustedProxies>,
+}
+
+impl SecurityHeaders {
+    /// Middleware adding the headers of `config`
+    pub fn new(config: &SecurityHeadersConfig) -> Self {
+        let mut headers = Vec::new();
+        if config.content_type_options {
+            headers.push(("X-Content-Type-Options", "nosniff".to_string()));
+        }
+        if let Some(frame) = config.frame_options {
+            headers.push(("X-Frame-Options", frame.as_str().to_string()));
+        }
+        if let Some(policy) = &config.referrer_policy {
+            headers.push(("Referrer-Policy", policy.clone()));
+        }
+        if let Some(csp) = &config.content_security_policy {
+            headers.push(("Content-Security-Policy", csp.clone()));
+        }
+        Self {
+            headers,
+            hsts: config.hsts.as_ref().map(|hsts| hsts.header_value()),
+            replace: config.replace,
+            tls: false,
+            trusted: Arc::default(),
+        }
+    }
+
+    /// Treat requests as made over HTTPS, for listeners that terminate TLS
+    pub fn with_tls(mut self, tls: bool) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    /// Believe `X-Forwarded-Proto` on requests from these proxies, e.g. a
+    /// load balancer terminating TLS in front of a plain listener
+    pub fn with_trusted_proxies(mut self, trusted: Arc<TrustedProxies>) -> Self {
+        self.trusted = trusted;
+        self
+    }
+
+    fn is_https(&self, req: &Request) -> bool {
+        self.trusted
+            .scheme(req, self.tls)
+            .eq_ignore_ascii_case("https")
+    }
+
+    fn insert(&self, response: &mut Response, name: &str, value: &str) {
+        let existing = response
+            .headers
+            .keys()
+            .find(|key| key.eq_ignore_ascii_case(name))
+            .cloned();
+        match existing {
+            Some(_) if !self.replace => {}
+            Some(key) => {
+                response.headers.insert(key, value.to_string());
+            }
+            None => {
+                response.headers.insert(name.to_string(), value.to_string());
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for SecurityHeaders {
+    async fn after_response(&self, req: &Request, response: &mut Response) {
+        if let Some(hsts) = &self.hsts
+            && self.is_https(req)
+        {
+            self.insert(response, "Strict-Transport-Security", hsts);
+        }
+        for (name, value) in &self.headers {
+            self.insert(response, name, value);
+        }
+    }
+}
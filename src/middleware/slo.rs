@@ -0,0 +1,285 @@
+//! Service level objectives
+//!
+//! An [`SloHandler`] times every response and hands it to an [`SloTracker`],
+//! which counts requests, 5xx errors, and slow responses per objective in
+//! [`SLO_BUCKET_SECS`]-second buckets. Every bucket interval the tracker
+//! sums the buckets over each configured window and publishes:
+//!
+//! | Name | Type | Labels |
+//! |------|------|--------|
+//! | `sentinel_slo_requests_total` | counter | `slo`, `outcome` (`good`, `error`, `slow`) |
+//! | `sentinel_slo_availability_ratio` | gauge | `slo`, `window` |
+//! | `sentinel_slo_latency_ratio` | gauge | `slo`, `window` |
+//! | `sentinel_slo_burn_rate` | gauge | `slo`, `objective`, `window` |
+//!
+//! The burn rate is the share of bad requests divided by the error budget
+//! (`1 - target`): 1 spends the budget exactly over the SLO period, and a
+//! fast burn on a short window confirmed by a long one is the usual
+//! multi-window alert. Windows with no traffic report a ratio of 1 and a
+//! burn rate of 0.
+
+use crate::config::SloConfig;
+use crate::http::handler::Handler;
+use crate::http::request::Request;
+use crate::http::response::Response;
+use crate::metrics::Metrics;
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio_util::sync::CancellationToken;
+
+/// Granularity of the rolling windows, and how often gauges are updated
+pub const SLO_BUCKET_SECS: u64 = 10;
+
+/// Longest allowed window (three days)
+pub const MAX_SLO_WINDOW_SECS: u64 = 3 * 24 * 60 * 60;
+
+/// Requests counted for an objective over some period
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SloCounts {
+    /// Every request
+    pub total: u64,
+    /// Requests answered with a 5xx status
+    pub errors: u64,
+    /// Requests slower than the latency threshold
+    pub slow: u64,
+}
+
+impl SloCounts {
+    fn add(&mut self, other: &SloCounts) {
+        self.total += other.total;
+        self.errors += other.errors;
+        self.slow += other.slow;
+    }
+
+    /// Share of requests without a 5xx status (1 with no traffic)
+    pub fn availability(&self) -> f64 {
+        ratio(self.total - self.errors, self.total)
+    }
+
+    /// Share of requests within the latency threshold (1 with no traffic)
+    pub fn latency(&self) -> f64 {
+        ratio(self.total - self.slow, self.total)
+    }
+}
+
+struct Objective {
+    config: SloConfig,
+    threshold: Option<Duration>,
+    /// `(bucket index, counts)`, oldest first
+    buckets: Mutex<VecDeque<(u64, SloCounts)>>,
+}
+
+/// Rolling per-objective request counts
+pub struct SloTracker {
+    objectives: Vec<Objective>,
+    metrics: Metrics,
+}
+
+impl SloTracker {
+    /// Track the given objectives
+    pub fn new(configs: &[SloConfig]) -> Self {
+        Self {
+            objectives: configs
+                .iter()
+                .map(|config| Objective {
+                    config: config.clone(),
+                    threshold: config
+                        .latency
+                        .as_ref()
+                        .map(|l| Duration::from_millis(l.threshold_ms)),
+                    buckets: Mutex::new(VecDeque::new()),
+                })
+                .collect(),
+            metrics: Metrics::default(),
+        }
+    }
+
+    /// Publish counters and gauges through the given recorder
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Count a response to a request for `path`
+    ///
+    /// The objective with the longest matching path prefix gets it.
+    pub fn record(&self, path: &str, status: u16, elapsed: Duration, now: SystemTime) {
+        let path = path.split('?').next().unwrap_or(path);
+        let Some(objective) = self
+            .objectives
+            .iter()
+            .filter(|o| path.starts_with(o.config.path_prefix.as_str()))
+            .max_by_key(|o| o.config.path_prefix.len())
+        else {
+            return;
+        };
+
+        let counts = SloCounts {
+            total: 1,
+            errors: u64::from(status >= 500),
+            slow: u64::from(objective.threshold.is_some_and(|t| elapsed > t)),
+        };
+        let outcome = if counts.errors > 0 {
+            "error"
+        } else if counts.slow > 0 {
+            "slow"
+        } else {
+            "good"
+        };
+        self.metrics.increment(
+            "sentinel_slo_requests_total",
+            &[("slo", &objective.config.name), ("outcome", outcome)],
+        );
+
+        let index = bucket_index(now);
+        let mut buckets = objective.buckets.lock().unwrap();
+        match buckets.back_mut() {
+            Some((last, total)) if *last == index => total.add(&counts),
+            _ => buckets.push_back((index, counts)),
+        }
+    }
+
+    /// Counts for the objective named `name` over the last `window_secs`
+    pub fn counts(&self, name: &str, window_secs: u64, now: SystemTime) -> Option<SloCounts> {
+        let objective = self.objectives.iter().find(|o| o.config.name == name)?;
+        Some(objective.counts(window_secs, bucket_index(now)))
+    }
+
+    /// Drop expired buckets and update every gauge
+    pub fn report(&self, now: SystemTime) {
+        let index = bucket_index(now);
+        for objective in &self.objectives {
+            objective.prune(index);
+
+            let name = objective.config.name.as_str();
+            for &window_secs in &objective.config.windows_secs {
+                let counts = objective.counts(window_secs, index);
+                let window = window_label(window_secs);
+                let labels = [("slo", name), ("window", window.as_str())];
+
+                if let Some(target) = objective.config.availability {
+                    let availability = counts.availability();
+                    self.metrics
+                        .gauge("sentinel_slo_availability_ratio", &labels, availability);
+                    self.metrics.gauge(
+                        "sentinel_slo_burn_rate",
+                        &[labels[0], ("objective", "availability"), labels[1]],
+                        burn_rate(availability, target),
+                    );
+                }
+                if let Some(latency) = &objective.config.latency {
+                    let within = counts.latency();
+                    self.metrics
+                        .gauge("sentinel_slo_latency_ratio", &labels, within);
+                    self.metrics.gauge(
+                        "sentinel_slo_burn_rate",
+                        &[labels[0], ("objective", "latency"), labels[1]],
+                        burn_rate(within, latency.target),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Update gauges every bucket interval until cancelled
+    pub async fn run(self: Arc<Self>, cancel: CancellationToken) {
+        tracing::info!(objectives = self.objectives.len(), "Starting SLO tracker");
+
+        loop {
+            self.report(SystemTime::now());
+
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(SLO_BUCKET_SECS)) => {}
+                _ = cancel.cancelled() => return,
+            }
+        }
+    }
+}
+
+impl Objective {
+    fn counts(&self, window_secs: u64, index: u64) -> SloCounts {
+        let oldest = index.saturating_sub(window_secs / SLO_BUCKET_SECS);
+        let mut total = SloCounts::default();
+        for (_, counts) in self
+            .buckets
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(i, _)| *i > oldest && *i <= index)
+        {
+            total.add(counts);
+        }
+        total
+    }
+
+    fn prune(&self, index: u64) {
+        let horizon = self.config.windows_secs.iter().max().copied().unwrap_or(0);
+        let oldest = index.saturating_sub(horizon / SLO_BUCKET_SECS);
+        let mut buckets = self.buckets.lock().unwrap();
+        while buckets.front().is_some_and(|(i, _)| *i <= oldest) {
+            buckets.pop_front();
+        }
+    }
+}
+
+/// Handler decorator that feeds every response to an [`SloTracker`]
+pub struct SloHandler {
+    inner: Arc<dyn Handler>,
+    tracker: Arc<SloTracker>,
+}
+
+impl SloHandler {
+    /// Wrap `inner`, recording its responses in `tracker`
+    pub fn new(inner: impl Handler, tracker: Arc<SloTracker>) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            tracker,
+        }
+    }
+}
+
+#[async_trait]
+impl Handler for SloHandler {
+    async fn handle(&self, req: Request) -> Response {
+        let path = req.path.clone();
+        let started = Instant::now();
+        let response = self.inner.handle(req).await;
+        self.tracker.record(
+            &path,
+            response.status.as_u16(),
+            started.elapsed(),
+            SystemTime::now(),
+        );
+        response
+    }
+}
+
+fn bucket_index(now: SystemTime) -> u64 {
+    now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / SLO_BUCKET_SECS
+}
+
+fn ratio(good: u64, total: u64) -> f64 {
+    if total == 0 {
+        1.0
+    } else {
+        good as f64 / total as f64
+    }
+}
+
+/// How fast the error budget is being spent, given the share of good
+/// requests and the target percentage
+fn burn_rate(good_ratio: f64, target: f64) -> f64 {
+    (1.0 - good_ratio) / (1.0 - target / 100.0)
+}
+
+/// Short form of a window for metric labels (`300` is `5m`)
+fn window_label(secs: u64) -> String {
+    match secs {
+        s if s % 86_400 == 0 => format!("{}d", s / 86_400),
+        s if s % 3_600 == 0 => format!("{}h", s / 3_600),
+        s if s % 60 == 0 => format!("{}m", s / 60),
+        s => format!("{}s", s),
+    }
+}
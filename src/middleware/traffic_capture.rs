@@ -0,0 +1,84 @@
+//! Sampled traffic capture into HAR files
+//!
+//! Records a sample of requests, with the responses clients received and
+//! how long they took, through a [`HarRecorder`]. Wrapped around the whole
+//! handler chain, so captures show exactly what clients saw, error pages
+//! included.
+//!
+//! Captured entries count towards `sentinel_traffic_captured_total`.
+//!
+//! # Example
+//!
+//! ```yaml
+//! traffic_capture:
+//!   dir: "captures"
+//!   sample_percent: 5
+//! ```
+
+use crate::http::handler::Handler;
+use crate::http::har::HarRecorder;
+use crate::http::request::Request;
+use crate::http::response::Response;
+use crate::metrics::Metrics;
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+
+/// Handler decorator that records sampled exchanges
+pub struct TrafficCaptureHandler {
+    inner: Arc<dyn Handler>,
+    recorder: Arc<HarRecorder>,
+    scheme: &'static str,
+    metrics: Metrics,
+}
+
+impl TrafficCaptureHandler {
+    /// Wrap `inner`, recording sampled exchanges with `recorder`
+    pub fn new(inner: impl Handler, recorder: Arc<HarRecorder>) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            recorder,
+            scheme: "http",
+            metrics: Metrics::default(),
+        }
+    }
+
+    /// Record URLs as `https://` (when the listener terminates TLS)
+    pub fn with_tls(mut self, tls: bool) -> Self {
+        self.scheme = if tls { "https" } else { "http" };
+        self
+    }
+
+    /// Count captured exchanges through the given recorder
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+}
+
+#[async_trait]
+impl Handler for TrafficCaptureHandler {
+    async fn handle(&self, req: Request) -> Response {
+        if !self.recorder.sampled() {
+            return self.inner.handle(req).await;
+        }
+
+        let started = SystemTime::now();
+        let timer = Instant::now();
+        let request = self.recorder.request(&req, self.scheme);
+        let client = req.context.peer.map(|peer| peer.ip().to_string());
+        let response = self.inner.handle(req).await;
+        let elapsed = timer.elapsed();
+
+        self.recorder.record(
+            started,
+            elapsed,
+            client,
+            request,
+            self.recorder.response(&response),
+        );
+        self.metrics
+            .increment("sentinel_traffic_captured_total", &[]);
+        response
+    }
+}
@@ -2,11 +2,27 @@
 //!
 //! This module manages the pool of backend servers, tracking their state
 //! and selecting backends for incoming requests.
+//!
+//! Failing backends are circuit broken: once failures take a backend down
+//! its circuit is open, and after a cooldown a limited number of live
+//! requests are sent to it as probes (half-open) until it either recovers
+//! or the circuit opens again. See [`CircuitBreakerConfig`].
+//!
+//! A backend can also be drained for planned maintenance: it gets no new
+//! requests, but those already forwarded to it finish normally.
 
-use crate::config::BackendConfig;
-use std::sync::Arc;
-use std::time::Instant;
-use tokio::sync::RwLock;
+use crate::config::{
+    BackendConfig, BackendHealthCheck, CircuitBreakerConfig, LoadBalancing, LoadFeedbackConfig,
+    LocalityConfig,
+};
+use crate::events::{Event, Events};
+use crate::metrics::Metrics;
+use crate::proxy::balancer::{self, Affinity, Candidate, LATENCY_SMOOTHING, LoadBalancer};
+use arc_swap::ArcSwap;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicI64, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 /// Represents the current state of a backend server
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -15,6 +31,19 @@ pub enum BackendState {
     Up,
     /// Backend is down or unreachable
     Down,
+    /// Backend gets no new requests while in-flight ones finish
+    Draining,
+}
+
+impl BackendState {
+    /// Lowercase name, as used by the admin API
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BackendState::Up => "up",
+            BackendState::Down => "down",
+            BackendState::Draining => "draining",
+        }
+    }
 }
 
 /// Represents a backend server with its metadata
@@ -29,11 +58,57 @@ pub struct Backend {
     /// Current state of the backend
     pub state: BackendState,
     
-    /// Last time backend was checked
-    pub last_check: Option<Instant>,
-    
-    /// Number of consecutive failures
-    pub consecutive_failures: u32,
+    /// When failures last opened the backend's circuit, while it is down
+    pub opened_at: Option<Instant>,
+
+    /// Failures that mark this backend down, overriding the pool's threshold
+    pub unhealthy_threshold: Option<u32>,
+
+    /// Successes that bring this backend back, overriding the pool's threshold
+    pub healthy_threshold: Option<u32>,
+
+    /// Most requests forwarded to the backend at once
+    pub max_connections: Option<u32>,
+
+    /// Whether the configuration asks for the backend to be drained
+    pub drain: bool,
+
+    /// Whether requests are sent over cleartext HTTP/2
+    pub h2c: bool,
+
+    /// Relative share of requests; 0 means no new requests
+    pub weight: u32,
+
+    /// Metadata labels from configuration or discovery
+    pub labels: BTreeMap<String, String>,
+
+    /// Zone the backend runs in, for locality-aware selection
+    pub zone: Option<String>,
+
+    /// Active health probe settings
+    pub health_check: Option<BackendHealthCheck>,
+
+    /// When the backend was created, from which its warm-up is measured;
+    /// None for the members a pool starts with, which need none
+    pub added_at: Option<Instant>,
+
+    /// Requests currently forwarded to the backend, shared by every clone
+    active: Arc<AtomicUsize>,
+
+    /// Outcomes and moving averages of requests, shared by every clone
+    stats: Arc<BackendStats>,
+
+    /// Smooth weighted round-robin counter, shared by every clone
+    pub(crate) current_weight: Arc<AtomicI64>,
+
+    /// Probe requests in flight while half-open, shared by every clone
+    probes: Arc<AtomicU32>,
+
+    /// Held by a backend selected for a probe request
+    probe: Option<Arc<ProbePermit>>,
+
+    /// Held by a selected backend, counting the request as in flight
+    request: Option<Arc<ActiveRequest>>,
 }
 
 impl Backend {
@@ -42,9 +117,28 @@ impl Backend {
         Self {
             url: config.url,
             name: config.name,
-            state: BackendState::Up,
-            last_check: None,
-            consecutive_failures: 0,
+            state: if config.drain {
+                BackendState::Draining
+            } else {
+                BackendState::Up
+            },
+            opened_at: None,
+            unhealthy_threshold: config.unhealthy_threshold,
+            healthy_threshold: config.healthy_threshold,
+            max_connections: config.max_connections,
+            drain: config.drain,
+            h2c: config.h2c,
+            weight: config.weight,
+            labels: config.labels,
+            zone: config.zone,
+            health_check: config.health_check,
+            added_at: Some(Instant::now()),
+            active: Arc::new(AtomicUsize::new(0)),
+            stats: Arc::new(BackendStats::new()),
+            current_weight: Arc::new(AtomicI64::new(0)),
+            probes: Arc::new(AtomicU32::new(0)),
+            probe: None,
+            request: None,
         }
     }
 
@@ -53,123 +147,995 @@ impl Backend {
         self.name.as_deref().unwrap_or(&self.url)
     }
 
-    /// Mark backend as failed
+    /// Mark backend as failed, using the default thresholds
     pub fn mark_failed(&mut self) {
-        self.consecutive_failures += 1;
-        self.last_check = Some(Instant::now());
-        
-        // Mark as down after 3 consecutive failures
-        if self.consecutive_failures >= 3 {
+        self.mark_failed_with(HealthThresholds::default());
+    }
+
+    /// Mark backend as successful, using the default thresholds
+    pub fn mark_success(&mut self) {
+        self.mark_success_with(HealthThresholds::default());
+    }
+
+    /// Mark backend as failed, taking it down after `unhealthy` failures in
+    /// a row (or its own threshold, if configured)
+    ///
+    /// A failure while the circuit is half-open opens it again. Failures
+    /// never take a draining backend down.
+    pub fn mark_failed_with(&mut self, thresholds: HealthThresholds) {
+        self.stats.record_failure();
+        self.apply_failures(thresholds);
+    }
+
+    /// Mark backend as successful, bringing it back up after `healthy`
+    /// successes in a row (or its own threshold, if configured)
+    pub fn mark_success_with(&mut self, thresholds: HealthThresholds) {
+        self.stats.record_success(self.state == BackendState::Down);
+        self.apply_successes(thresholds);
+    }
+
+    /// Whether the failures recorded so far change the backend's state
+    fn failures_change_state(&self, thresholds: HealthThresholds) -> bool {
+        match self.state {
+            BackendState::Down => self.opened_at.is_some(),
+            BackendState::Up => {
+                self.consecutive_failures()
+                    >= self.unhealthy_threshold.unwrap_or(thresholds.unhealthy)
+            }
+            BackendState::Draining => false,
+        }
+    }
+
+    /// Whether the successes recorded so far bring the backend back up
+    fn successes_change_state(&self, thresholds: HealthThresholds) -> bool {
+        self.state == BackendState::Down
+            && self.consecutive_successes() >= self.healthy_threshold.unwrap_or(thresholds.healthy)
+    }
+
+    /// Open the circuit if the recorded failures call for it
+    fn apply_failures(&mut self, thresholds: HealthThresholds) {
+        if !self.failures_change_state(thresholds) {
+            return;
+        }
+        self.opened_at = Some(Instant::now());
+        if self.state == BackendState::Up {
             self.state = BackendState::Down;
             tracing::warn!(
                 backend = self.display_name(),
-                failures = self.consecutive_failures,
+                failures = self.consecutive_failures(),
                 "Backend marked as down"
             );
         }
     }
 
-    /// Mark backend as successful
-    pub fn mark_success(&mut self) {
-        self.consecutive_failures = 0;
-        self.last_check = Some(Instant::now());
-        
-        if self.state == BackendState::Down {
+    /// Bring the backend back up if the recorded successes call for it
+    fn apply_successes(&mut self, thresholds: HealthThresholds) {
+        if self.successes_change_state(thresholds) {
             self.state = BackendState::Up;
+            self.stats.successes.store(0, Ordering::Release);
+            self.opened_at = None;
             tracing::info!(backend = self.display_name(), "Backend recovered");
         }
     }
 
+    /// Last time a request outcome was recorded for the backend
+    pub fn last_check(&self) -> Option<Instant> {
+        self.stats.last_check()
+    }
+
+    /// Number of consecutive failures
+    pub fn consecutive_failures(&self) -> u32 {
+        self.stats.failures.load(Ordering::Acquire)
+    }
+
+    /// Number of consecutive successes while down
+    pub fn consecutive_successes(&self) -> u32 {
+        self.stats.successes.load(Ordering::Acquire)
+    }
+
+    /// Smoothed utilization (0-1) reported by the backend, if any
+    pub fn load(&self) -> Option<f64> {
+        self.stats.load.get()
+    }
+
+    /// Moving average of successful response times in milliseconds, once
+    /// measured
+    pub fn latency_ms(&self) -> Option<f64> {
+        self.stats.latency_ms.get()
+    }
+
     /// Check if backend is available for requests
     pub fn is_available(&self) -> bool {
         self.state == BackendState::Up
     }
+
+    /// Check if the backend carries every label in `selector`
+    pub fn matches_labels(&self, selector: &BTreeMap<String, String>) -> bool {
+        selector
+            .iter()
+            .all(|(key, value)| self.labels.get(key) == Some(value))
+    }
+
+    /// Metric labels identifying this backend: its name plus its labels
+    pub fn metric_labels(&self) -> Vec<(&str, &str)> {
+        let mut labels = vec![("backend", self.display_name())];
+        labels.extend(self.labels.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+        labels
+    }
+
+    /// Labels formatted for logs as `key=value,key=value`
+    pub fn labels_display(&self) -> String {
+        self.labels
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Whether this backend was selected as a probe of its half-open circuit
+    pub fn is_probe(&self) -> bool {
+        self.probe.is_some()
+    }
+
+    /// Number of requests currently forwarded to the backend
+    ///
+    /// A backend returned by [`BackendPool::select_backend`] counts as one
+    /// until it (and every clone of it) is dropped.
+    pub fn active_requests(&self) -> usize {
+        self.active.load(Ordering::Acquire)
+    }
+
+    /// Count a request as in flight until the returned guard is dropped
+    pub fn start_request(&self) -> ActiveRequest {
+        self.active.fetch_add(1, Ordering::AcqRel);
+        ActiveRequest {
+            active: self.active.clone(),
+        }
+    }
+
+    /// Like [`Self::start_request`], unless `max_connections` requests are
+    /// already in flight
+    pub fn try_start_request(&self) -> Option<ActiveRequest> {
+        let max = self.max_connections.map_or(usize::MAX, |max| max as usize);
+        self.active
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .ok()?;
+        Some(ActiveRequest {
+            active: self.active.clone(),
+        })
+    }
+
+    /// Whether another request may be forwarded without exceeding
+    /// `max_connections`
+    pub fn has_capacity(&self) -> bool {
+        self.max_connections
+            .is_none_or(|max| self.active_requests() < max as usize)
+    }
+
+    /// A copy of the backend holding `request` until dropped
+    fn selected(&self, request: ActiveRequest) -> Self {
+        let mut selected = self.clone();
+        selected.request = Some(Arc::new(request));
+        selected
+    }
+
+    /// Check if backend can be chosen for a new request
+    fn is_selectable(&self) -> bool {
+        self.is_available() && self.weight > 0 && self.has_capacity()
+    }
+}
+
+/// Request outcomes and moving averages of a backend
+///
+/// These change with every request, so they are atomics shared by every
+/// copy of the backend rather than fields of the pool's snapshot.
+#[derive(Debug)]
+struct BackendStats {
+    created: Instant,
+    /// Nanoseconds from `created` to the last recorded outcome, plus one;
+    /// 0 if there is none
+    last_check: AtomicU64,
+    failures: AtomicU32,
+    successes: AtomicU32,
+    load: Average,
+    latency_ms: Average,
+}
+
+impl BackendStats {
+    fn new() -> Self {
+        Self {
+            created: Instant::now(),
+            last_check: AtomicU64::new(0),
+            failures: AtomicU32::new(0),
+            successes: AtomicU32::new(0),
+            load: Average::new(),
+            latency_ms: Average::new(),
+        }
+    }
+
+    fn last_check(&self) -> Option<Instant> {
+        match self.last_check.load(Ordering::Acquire) {
+            0 => None,
+            nanos => Some(self.created + Duration::from_nanos(nanos - 1)),
+        }
+    }
+
+    fn checked(&self) {
+        let nanos = u64::try_from(self.created.elapsed().as_nanos()).unwrap_or(u64::MAX - 1);
+        self.last_check.store(nanos + 1, Ordering::Release);
+    }
+
+    fn record_failure(&self) {
+        self.failures.fetch_add(1, Ordering::AcqRel);
+        self.successes.store(0, Ordering::Release);
+        self.checked();
+    }
+
+    /// Record a success, counted towards recovery if the backend is `down`
+    fn record_success(&self, down: bool) {
+        self.failures.store(0, Ordering::Release);
+        if down {
+            self.successes.fetch_add(1, Ordering::AcqRel);
+        }
+        self.checked();
+    }
+
+    fn reset(&self) {
+        self.failures.store(0, Ordering::Release);
+        self.successes.store(0, Ordering::Release);
+        self.checked();
+    }
+}
+
+/// An exponentially weighted moving average, stored as `f64` bits
+#[derive(Debug)]
+struct Average(AtomicU64);
+
+impl Average {
+    /// Bits of an average without samples (a NaN no sample produces)
+    const EMPTY: u64 = u64::MAX;
+
+    fn new() -> Self {
+        Self(AtomicU64::new(Self::EMPTY))
+    }
+
+    fn get(&self) -> Option<f64> {
+        let bits = self.0.load(Ordering::Acquire);
+        (bits != Self::EMPTY).then(|| f64::from_bits(bits))
+    }
+
+    /// Fold `sample` in with the given smoothing and return the new average
+    fn update(&self, sample: f64, smoothing: f64) -> f64 {
+        let mut average = sample;
+        let _ = self
+            .0
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |bits| {
+                average = if bits == Self::EMPTY {
+                    sample
+                } else {
+                    let previous = f64::from_bits(bits);
+                    previous + smoothing * (sample - previous)
+                };
+                Some(average.to_bits())
+            });
+        average
+    }
+}
+
+/// Consecutive outcomes that change a backend's state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthThresholds {
+    /// Failures in a row that mark a backend down
+    pub unhealthy: u32,
+    /// Successes in a row that bring a down backend back
+    pub healthy: u32,
+}
+
+impl Default for HealthThresholds {
+    fn default() -> Self {
+        Self {
+            unhealthy: 3,
+            healthy: 1,
+        }
+    }
+}
+
+/// A probe request's claim on a half-open circuit, released when dropped
+#[derive(Debug)]
+struct ProbePermit {
+    probes: Arc<AtomicU32>,
+}
+
+impl Drop for ProbePermit {
+    fn drop(&mut self) {
+        self.probes.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// An in-flight request to a backend, counted by [`Backend::active_requests`]
+#[derive(Debug)]
+pub struct ActiveRequest {
+    active: Arc<AtomicUsize>,
+}
+
+impl Drop for ActiveRequest {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::AcqRel);
+    }
 }
 
 /// Pool of backend servers
-#[derive(Debug, Clone)]
+///
+/// Cheap to clone; clones share the same members. Membership, weights, and
+/// states can be changed at runtime while requests are being routed.
+///
+/// Selection takes no locks: it works on an immutable snapshot of the
+/// members, and changes are made to a copy that replaces the snapshot.
+/// Request outcomes (failure counts, latency and load averages) are
+/// atomics shared by every snapshot, so the snapshot is only replaced when
+/// membership, weights, or states change.
+#[derive(Clone)]
 pub struct BackendPool {
-    backends: Arc<RwLock<Vec<Backend>>>,
-    current_index: Arc<RwLock<usize>>,
+    backends: Arc<ArcSwap<Vec<Arc<Backend>>>>,
+    /// Serializes changes so concurrent updates are not lost
+    writer: Arc<Mutex<()>>,
+    events: Events,
+    metrics: Metrics,
+    locality: Option<Arc<LocalityConfig>>,
+    load_feedback: Option<Arc<LoadFeedbackConfig>>,
+    balancer: Arc<dyn LoadBalancer>,
+    thresholds: HealthThresholds,
+    breaker: CircuitBreakerConfig,
+    warmup: Duration,
+}
+
+impl std::fmt::Debug for BackendPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BackendPool")
+            .field("backends", &self.backends)
+            .field("locality", &self.locality)
+            .field("load_feedback", &self.load_feedback)
+            .finish_non_exhaustive()
+    }
 }
 
 impl BackendPool {
     /// Create a new backend pool from configuration
     pub fn new(configs: Vec<BackendConfig>) -> Self {
-        let backends = configs.into_iter().map(Backend::new).collect();
-        
+        let backends = configs
+            .into_iter()
+            .map(|config| {
+                Arc::new(Backend {
+                    added_at: None,
+                    ..Backend::new(config)
+                })
+            })
+            .collect();
+
         Self {
-            backends: Arc::new(RwLock::new(backends)),
-            current_index: Arc::new(RwLock::new(0)),
+            backends: Arc::new(ArcSwap::from_pointee(backends)),
+            writer: Arc::new(Mutex::new(())),
+            events: Events::new(),
+            metrics: Metrics::default(),
+            locality: None,
+            load_feedback: None,
+            balancer: balancer::balancer_for(LoadBalancing::default()),
+            thresholds: HealthThresholds::default(),
+            breaker: CircuitBreakerConfig::default(),
+            warmup: Duration::ZERO,
         }
     }
 
-    /// Select the next available backend using round-robin
+    /// Publish backend selection and state changes to the given event bus
+    pub fn with_events(mut self, events: Events) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Record selection counts and backend health to the given metrics recorder
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        for backend in self.backends.load().iter() {
+            self.report_up(backend);
+        }
+        self
+    }
+
+    /// Prefer backends in the local zone, spilling to other zones only when
+    /// too few local backends are healthy
+    pub fn with_locality(mut self, locality: LocalityConfig) -> Self {
+        self.locality = Some(Arc::new(locality));
+        self
+    }
+
+    /// Scale weights by the load backends report through [`BackendPool::report_load`]
+    pub fn with_load_feedback(mut self, config: LoadFeedbackConfig) -> Self {
+        self.load_feedback = Some(Arc::new(config));
+        self
+    }
+
+    /// Change how many failures or successes in a row change a backend's
+    /// state, for backends without their own thresholds
+    pub fn with_health_thresholds(mut self, thresholds: HealthThresholds) -> Self {
+        self.thresholds = thresholds;
+        self
+    }
+
+    /// Change how long open circuits wait and how many probes half-open
+    /// circuits take
+    pub fn with_circuit_breaker(mut self, config: CircuitBreakerConfig) -> Self {
+        self.breaker = config;
+        self
+    }
+
+    /// Ramp the share of requests sent to a new backend up to its full
+    /// weight over `warmup`, so cold caches and JITs are not flooded
+    ///
+    /// Backends the pool starts with are already warm.
+    pub fn with_warmup(mut self, warmup: Duration) -> Self {
+        self.warmup = warmup;
+        self
+    }
+
+    /// Pick backends with the given built-in strategy (round-robin by default)
+    pub fn with_balancing(self, strategy: LoadBalancing) -> Self {
+        self.with_balancer(balancer::balancer_for(strategy))
+    }
+
+    /// Pick backends with a custom [`LoadBalancer`]
+    pub fn with_balancer(mut self, balancer: Arc<dyn LoadBalancer>) -> Self {
+        self.balancer = balancer;
+        self
+    }
+
+    /// Select the next available backend using the pool's strategy
     ///
     /// Returns None if no backends are available
     pub async fn select_backend(&self) -> Option<Backend> {
-        let backends = self.backends.read().await;
-        
-        if backends.is_empty() {
+        self.select_where(|_| true, Affinity::default()).await
+    }
+
+    /// Select a backend for a request with the given affinity
+    ///
+    /// With [`LoadBalancing::IpHash`] the client's address picks the backend
+    /// through weighted rendezvous hashing; with
+    /// [`LoadBalancing::ConsistentHash`] the key is looked up on a ring of
+    /// virtual nodes. Either way the same client or key sticks to one
+    /// backend, and only those of a backend that leaves are moved. Other
+    /// strategies ignore the affinity.
+    pub async fn select_backend_for(&self, affinity: Affinity<'_>) -> Option<Backend> {
+        self.select_where(|_| true, affinity).await
+    }
+
+    /// Select the next available backend carrying every label in `selector`
+    ///
+    /// Returns None if no matching backend is available
+    pub async fn select_backend_matching(
+        &self,
+        selector: &BTreeMap<String, String>,
+    ) -> Option<Backend> {
+        self.select_where(|b| b.matches_labels(selector), Affinity::default())
+            .await
+    }
+
+    /// [`Self::select_backend_matching`] for a request with the given affinity
+    pub async fn select_backend_matching_for(
+        &self,
+        selector: &BTreeMap<String, String>,
+        affinity: Affinity<'_>,
+    ) -> Option<Backend> {
+        self.select_where(|b| b.matches_labels(selector), affinity)
+            .await
+    }
+
+    /// Select a backend lacking some label in `selector`, for the requests
+    /// a traffic split keeps away from the labelled backends
+    ///
+    /// Returns None if no such backend is available
+    pub async fn select_backend_excluding_for(
+        &self,
+        selector: &BTreeMap<String, String>,
+        affinity: Affinity<'_>,
+    ) -> Option<Backend> {
+        self.select_where(|b| !b.matches_labels(selector), affinity)
+            .await
+    }
+
+    async fn select_where(
+        &self,
+        filter: impl Fn(&Backend) -> bool,
+        affinity: Affinity<'_>,
+    ) -> Option<Backend> {
+        let backends = self.backends.load();
+        let backend = match self.half_open_probe(&backends, &filter) {
+            Some(probe) => probe,
+            None => self.balance(&backends, &filter, affinity)?,
+        };
+        drop(backends);
+
+        self.events.emit(Event::BackendSelected {
+            backend: backend.url.clone(),
+        });
+        self.metrics.increment(
+            "sentinel_backend_selections_total",
+            &backend.metric_labels(),
+        );
+
+        Some(backend)
+    }
+
+    /// Pick a backend with the pool's balancer
+    fn balance(
+        &self,
+        backends: &[Arc<Backend>],
+        filter: &impl Fn(&Backend) -> bool,
+        affinity: Affinity<'_>,
+    ) -> Option<Backend> {
+        let selectable = |b: &Backend| b.is_selectable() && filter(b);
+
+        let zone = self.local_zone(backends, filter);
+        let mut candidates: Vec<Candidate> = backends
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| {
+                selectable(b) && zone.is_none_or(|zone| b.zone.as_deref() == Some(zone))
+            })
+            .map(|(index, b)| Candidate {
+                index,
+                weight: self.effective_weight(b),
+            })
+            .collect();
+
+        if candidates.is_empty() {
+            if backends
+                .iter()
+                .any(|b| b.is_available() && b.weight > 0 && filter(b))
+            {
+                tracing::warn!("Every available backend is at max_connections");
+            } else if !backends.is_empty() {
+                tracing::error!("No available backends in pool");
+            }
             return None;
         }
 
-        // Find first available backend starting from current index
-        let mut index = *self.current_index.read().await;
-        let start_index = index;
-        
-        loop {
-            if backends[index].is_available() {
-                let backend = backends[index].clone();
-                
-                // Update index for next request
-                drop(backends);
-                let mut current_index = self.current_index.write().await;
-                *current_index = (index + 1) % self.backends.read().await.len();
-                
-                return Some(backend);
-            }
-            
-            index = (index + 1) % backends.len();
-            
-            // If we've checked all backends and none are available
-            if index == start_index {
-                tracing::error!("No available backends in pool");
-                return None;
+        // Another request may take the last slot of the chosen backend
+        // first; then choose again among the rest
+        while !candidates.is_empty() {
+            let index = self
+                .balancer
+                .select(backends, &candidates, affinity)
+                .filter(|index| candidates.iter().any(|c| c.index == *index))?;
+            let backend = &backends[index];
+            match backend.try_start_request() {
+                Some(request) => return Some(backend.selected(request)),
+                None => candidates.retain(|c| c.index != index),
             }
         }
+        None
+    }
+
+    /// A backend whose circuit is half-open and can take another probe
+    ///
+    /// The returned backend holds a probe slot until it is dropped.
+    fn half_open_probe(
+        &self,
+        backends: &[Arc<Backend>],
+        filter: &impl Fn(&Backend) -> bool,
+    ) -> Option<Backend> {
+        let max = self.breaker.half_open_requests;
+        let cooldown = Duration::from_millis(self.breaker.cooldown_ms);
+        if max == 0 {
+            return None;
+        }
+
+        backends
+            .iter()
+            .filter(|b| {
+                b.state == BackendState::Down
+                    && b.weight > 0
+                    && b.opened_at.is_some_and(|at| at.elapsed() >= cooldown)
+                    && filter(b)
+            })
+            .find_map(|b| {
+                b.probes
+                    .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                        (n < max).then_some(n + 1)
+                    })
+                    .ok()?;
+                tracing::debug!(
+                    backend = b.display_name(),
+                    "Sending probe request to half-open backend"
+                );
+                let mut probe = b.selected(b.start_request());
+                probe.probe = Some(Arc::new(ProbePermit {
+                    probes: b.probes.clone(),
+                }));
+                Some(probe)
+            })
+    }
+
+    /// Weight used for selection, in hundredths of the configured weight
+    ///
+    /// Without load feedback or a warm-up in progress this is just the
+    /// configured weight (scaled).
+    fn effective_weight(&self, backend: &Backend) -> i64 {
+        let base = i64::from(backend.weight) * 100;
+        let mut factor = 1.0;
+        if let (Some(feedback), Some(load)) = (&self.load_feedback, backend.load()) {
+            let floor = f64::from(feedback.min_weight_percent) / 100.0;
+            factor *= (1.0 - load).max(floor);
+        }
+        if let Some(added_at) = backend.added_at
+            && added_at.elapsed() < self.warmup
+        {
+            factor *= added_at.elapsed().as_secs_f64() / self.warmup.as_secs_f64();
+        }
+
+        if factor < 1.0 {
+            ((base as f64 * factor).round() as i64).max(1)
+        } else {
+            base
+        }
+    }
+
+    /// The local zone, if selection should be restricted to it
+    ///
+    /// Returns None (select from every zone) when no locality is configured
+    /// or the local zone's healthy share is below `min_healthy_percent`.
+    fn local_zone(
+        &self,
+        backends: &[Arc<Backend>],
+        filter: &impl Fn(&Backend) -> bool,
+    ) -> Option<&str> {
+        let locality = self.locality.as_deref()?;
+        let local: Vec<&Arc<Backend>> = backends
+            .iter()
+            .filter(|b| filter(b) && b.zone.as_deref() == Some(locality.zone.as_str()))
+            .collect();
+        let healthy = local.iter().filter(|b| b.is_selectable()).count();
+
+        let enough =
+            healthy > 0 && healthy * 100 >= local.len() * usize::from(locality.min_healthy_percent);
+        if !enough {
+            tracing::debug!(
+                zone = %locality.zone,
+                healthy,
+                total = local.len(),
+                "Not enough healthy local backends, spilling to other zones"
+            );
+            self.metrics.increment(
+                "sentinel_backend_zone_spills_total",
+                &[("zone", &locality.zone)],
+            );
+        }
+
+        enough.then_some(locality.zone.as_str())
     }
 
     /// Mark a backend as failed
     pub async fn mark_backend_failed(&self, backend_url: &str) {
-        let mut backends = self.backends.write().await;
-        
-        if let Some(backend) = backends.iter_mut().find(|b| b.url == backend_url) {
-            backend.mark_failed();
+        let thresholds = self.thresholds;
+        let Some(backend) = self.find(backend_url) else {
+            return;
+        };
+        backend.stats.record_failure();
+        if backend.failures_change_state(thresholds) {
+            self.update_backend(backend_url, |backend| backend.apply_failures(thresholds))
+                .await;
         }
     }
 
     /// Mark a backend as successful
     pub async fn mark_backend_success(&self, backend_url: &str) {
-        let mut backends = self.backends.write().await;
-        
-        if let Some(backend) = backends.iter_mut().find(|b| b.url == backend_url) {
-            backend.mark_success();
+        let thresholds = self.thresholds;
+        let Some(backend) = self.find(backend_url) else {
+            return;
+        };
+        backend
+            .stats
+            .record_success(backend.state == BackendState::Down);
+        if backend.successes_change_state(thresholds) {
+            self.update_backend(backend_url, |backend| backend.apply_successes(thresholds))
+                .await;
+        }
+    }
+
+    /// The current member with the given URL
+    fn find(&self, backend_url: &str) -> Option<Arc<Backend>> {
+        self.backends
+            .load()
+            .iter()
+            .find(|b| b.url == backend_url)
+            .cloned()
+    }
+
+    /// Apply an update to a backend, emitting an event if its state changed
+    ///
+    /// Returns false if no backend has the given URL.
+    async fn update_backend(&self, backend_url: &str, update: impl FnOnce(&mut Backend)) -> bool {
+        let change = self.modify(|backends| {
+            let backend = Arc::make_mut(backends.iter_mut().find(|b| b.url == backend_url)?);
+            let before = backend.state;
+            update(backend);
+            Some((backend.state != before).then(|| (before, backend.clone())))
+        });
+        let Some(change) = change else {
+            return false;
+        };
+
+        if let Some((from, backend)) = change {
+            self.state_changed(from, &backend);
+        }
+
+        true
+    }
+
+    /// Set the `sentinel_backend_up` gauge of `backend` from its state
+    fn report_up(&self, backend: &Backend) {
+        self.metrics.gauge(
+            "sentinel_backend_up",
+            &backend.metric_labels(),
+            if backend.state == BackendState::Up {
+                1.0
+            } else {
+                0.0
+            },
+        );
+    }
+
+    /// Report a change of `backend`'s state from `from`
+    fn state_changed(&self, from: BackendState, backend: &Backend) {
+        let to = backend.state;
+        self.report_up(backend);
+        self.events.emit(Event::BackendStateChanged {
+            backend: backend.url.clone(),
+            from,
+            to,
+        });
+    }
+
+    /// Add a backend to the pool
+    ///
+    /// Returns false (and leaves the pool unchanged) if a backend with the
+    /// same URL is already a member.
+    pub async fn add_backend(&self, config: BackendConfig) -> bool {
+        let url = config.url.clone();
+        let added = self.modify(|backends| {
+            if backends.iter().any(|b| b.url == url) {
+                return false;
+            }
+            let backend = Arc::new(Backend::new(config));
+            self.report_up(&backend);
+            backends.push(backend);
+            true
+        });
+        if !added {
+            return false;
+        }
+
+        tracing::info!(backend = %url, "Backend added to pool");
+        self.events.emit(Event::BackendAdded { backend: url });
+        true
+    }
+
+    /// Remove a backend from the pool
+    ///
+    /// Requests already forwarded to it are not affected. Returns false if
+    /// no backend has the given URL.
+    pub async fn remove_backend(&self, backend_url: &str) -> bool {
+        let removed = self.modify(|backends| {
+            let before = backends.len();
+            backends.retain(|b| b.url != backend_url);
+            backends.len() != before
+        });
+        if !removed {
+            return false;
+        }
+
+        tracing::info!(backend = %backend_url, "Backend removed from pool");
+        self.events.emit(Event::BackendRemoved {
+            backend: backend_url.to_string(),
+        });
+        true
+    }
+
+    /// Record a load report (utilization, 0-1) from a backend
+    ///
+    /// Reports are folded into a moving average using the configured
+    /// smoothing. Ignored unless load feedback is enabled.
+    pub async fn report_load(&self, backend_url: &str, utilization: f64) {
+        let Some(feedback) = &self.load_feedback else {
+            return;
+        };
+        let Some(backend) = self.find(backend_url) else {
+            return;
+        };
+        let load = backend
+            .stats
+            .load
+            .update(utilization.clamp(0.0, 1.0), feedback.smoothing);
+        self.metrics
+            .gauge("sentinel_backend_load", &backend.metric_labels(), load);
+    }
+
+    /// Fold the response time of a successful request into the backend's
+    /// moving average, used by [`LoadBalancing::LeastLatency`]
+    pub async fn record_latency(&self, backend_url: &str, elapsed: Duration) {
+        let Some(backend) = self.find(backend_url) else {
+            return;
+        };
+        let latency = backend
+            .stats
+            .latency_ms
+            .update(elapsed.as_secs_f64() * 1000.0, LATENCY_SMOOTHING);
+        self.metrics.gauge(
+            "sentinel_backend_latency_ewma_seconds",
+            &backend.metric_labels(),
+            latency / 1000.0,
+        );
+    }
+
+    /// Change a backend's weight; 0 drains it of new requests
+    ///
+    /// Returns false if no backend has the given URL.
+    pub async fn set_weight(&self, backend_url: &str, weight: u32) -> bool {
+        self.update_backend(backend_url, |backend| {
+            backend.weight = weight;
+            backend.current_weight.store(0, Ordering::Relaxed);
+        })
+        .await
+    }
+
+    /// Force a backend's state, e.g. [`BackendState::Draining`] to take it out
+    /// of rotation for maintenance
+    ///
+    /// Passive health tracking still applies: a backend forced `Up` is marked
+    /// down again after repeated failures. Returns false if no backend has the
+    /// given URL.
+    pub async fn set_state(&self, backend_url: &str, state: BackendState) -> bool {
+        self.update_backend(backend_url, |backend| {
+            backend.state = state;
+            backend.stats.reset();
+            backend.opened_at = None;
+        })
+        .await
+    }
+
+    /// Replace the pool's members with `configs`
+    ///
+    /// Backends whose URL is already in the pool keep their health state;
+    /// new URLs start `Up`. Used by service discovery to apply a fresh
+    /// snapshot. Emits `BackendAdded`/`BackendRemoved` for the difference.
+    ///
+    /// Setting or clearing `drain` on a member drains it or puts it back in
+    /// rotation; otherwise a state set through [`set_state`](Self::set_state)
+    /// is kept.
+    pub async fn replace_backends(&self, configs: Vec<BackendConfig>) {
+        let mut changed = Vec::new();
+        let (added, removed) = self.modify(|backends| {
+            let removed: Vec<String> = backends
+                .iter()
+                .filter(|b| !configs.iter().any(|c| c.url == b.url))
+                .map(|b| b.url.clone())
+                .collect();
+
+            let mut added = Vec::new();
+            let next: Vec<Arc<Backend>> = configs
+                .into_iter()
+                .map(
+                    |config| match backends.iter().find(|b| b.url == config.url) {
+                        Some(existing) => {
+                            let state = match (existing.drain, config.drain) {
+                                (false, true) => BackendState::Draining,
+                                (true, false) if existing.state == BackendState::Draining => {
+                                    BackendState::Up
+                                }
+                                _ => existing.state,
+                            };
+                            let backend = Backend {
+                                state,
+                                drain: config.drain,
+                                h2c: config.h2c,
+                                name: config.name,
+                                weight: config.weight,
+                                labels: config.labels,
+                                zone: config.zone,
+                                health_check: config.health_check,
+                                unhealthy_threshold: config.unhealthy_threshold,
+                                healthy_threshold: config.healthy_threshold,
+                                max_connections: config.max_connections,
+                                ..Backend::clone(existing)
+                            };
+                            if state != existing.state {
+                                changed.push((existing.state, backend.clone()));
+                            }
+                            Arc::new(backend)
+                        }
+                        None => {
+                            added.push(config.url.clone());
+                            Arc::new(Backend::new(config))
+                        }
+                    },
+                )
+                .collect();
+
+            *backends = next;
+            (added, removed)
+        });
+
+        if !added.is_empty() || !removed.is_empty() {
+            tracing::info!(
+                added = added.len(),
+                removed = removed.len(),
+                "Backend pool membership updated"
+            );
+        }
+        for backend in added {
+            self.events.emit(Event::BackendAdded { backend });
+        }
+        for backend in removed {
+            self.events.emit(Event::BackendRemoved { backend });
+        }
+        for (from, backend) in changed {
+            tracing::info!(
+                backend = backend.display_name(),
+                state = backend.state.as_str(),
+                "Backend drain setting changed"
+            );
+            self.state_changed(from, &backend);
         }
     }
 
     /// Get all backends (for monitoring/debugging)
     pub async fn get_backends(&self) -> Vec<Backend> {
-        self.backends.read().await.clone()
+        self.backends
+            .load()
+            .iter()
+            .map(|b| Backend::clone(b))
+            .collect()
     }
 
     /// Get count of available backends
     pub async fn available_count(&self) -> usize {
         self.backends
-            .read()
-            .await
+            .load()
             .iter()
             .filter(|b| b.is_available())
             .count()
     }
+
+    /// Apply a change to a copy of the members and publish it
+    fn modify<R>(&self, change: impl FnOnce(&mut Vec<Arc<Backend>>) -> R) -> R {
+        let _writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        let mut backends = Vec::clone(&self.backends.load());
+        let result = change(&mut backends);
+        self.backends.store(Arc::new(backends));
+        result
+    }
+}
+
+/// Every backend pool the server built, so the admin API can find a
+/// backend whichever route, upstream, or deployment it serves
+///
+/// Cheap to clone; clones share the same list.
+#[derive(Debug, Clone, Default)]
+pub struct BackendPools {
+    pools: Arc<Mutex<Vec<BackendPool>>>,
+}
+
+impl BackendPools {
+    /// Add a pool to the list
+    pub fn register(&self, pool: BackendPool) {
+        self.pools
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(pool);
+    }
+
+    /// The pools registered so far, in the order they were built
+    pub fn all(&self) -> Vec<BackendPool> {
+        self.pools.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
 }
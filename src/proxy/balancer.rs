@@ -0,0 +1,378 @@
+//! Backend selection strategies
+//!
+//! [`BackendPool`](crate::proxy::BackendPool) narrows its members to the
+//! candidates for a request (available, in the preferred zone, matching any
+//! label selector) and asks its [`LoadBalancer`] to pick one. The built-in
+//! strategies are chosen with `strategy:` in the proxy configuration (see
+//! [`LoadBalancing`]); embedders can supply their own with
+//! [`BackendPool::with_balancer`](crate::proxy::BackendPool::with_balancer).
+//!
+//! # Example
+//!
+//! ```ignore
+//! use sentinel::proxy::balancer::{Affinity, Candidate, LoadBalancer};
+//!
+//! /// Always the first candidate, e.g. for an active/standby pair
+//! struct Primary;
+//!
+//! impl LoadBalancer for Primary {
+//!     fn select(
+//!         &self,
+//!         _: &[Arc<Backend>],
+//!         candidates: &[Candidate],
+//!         _: Affinity<'_>,
+//!     ) -> Option<usize> {
+//!         candidates.first().map(|c| c.index)
+//!     }
+//! }
+//!
+//! let pool = BackendPool::new(backends).with_balancer(Arc::new(Primary));
+//! ```
+
+use crate::config::LoadBalancing;
+use crate::proxy::backend::Backend;
+use arc_swap::ArcSwap;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+
+/// What a request offers for sticky backend selection
+///
+/// [`LoadBalancing::IpHash`] uses the client address and
+/// [`LoadBalancing::ConsistentHash`] the key; without it, selection falls
+/// back to round-robin.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Affinity<'a> {
+    /// Address of the client
+    pub client: Option<IpAddr>,
+    /// Value of the configured hash key
+    pub key: Option<&'a str>,
+}
+
+/// A backend that may serve the current request
+#[derive(Debug, Clone, Copy)]
+pub struct Candidate {
+    /// Position of the backend in the pool
+    pub index: usize,
+    /// Selection weight in hundredths of the configured weight, after load
+    /// feedback; always positive
+    pub weight: i64,
+}
+
+/// Picks a backend for each request
+pub trait LoadBalancer: Send + Sync {
+    /// Choose one of `candidates`, returning its [`Candidate::index`]
+    ///
+    /// `backends` holds every member of the pool, including those that are
+    /// not candidates; `candidates` is never empty. Selection runs
+    /// concurrently for every request, so implementations should avoid
+    /// holding locks.
+    fn select(
+        &self,
+        backends: &[Arc<Backend>],
+        candidates: &[Candidate],
+        affinity: Affinity<'_>,
+    ) -> Option<usize>;
+}
+
+/// The built-in balancer for a configured strategy
+pub fn balancer_for(strategy: LoadBalancing) -> Arc<dyn LoadBalancer> {
+    match strategy {
+        LoadBalancing::RoundRobin => Arc::new(RoundRobin),
+        LoadBalancing::Random => Arc::new(Random::default()),
+        LoadBalancing::LeastConn => Arc::new(LeastConn),
+        LoadBalancing::IpHash => Arc::new(IpHash::default()),
+        LoadBalancing::ConsistentHash => Arc::new(ConsistentHash::default()),
+        LoadBalancing::LeastLatency => Arc::new(LeastLatency),
+    }
+}
+
+/// Smooth weighted round-robin
+///
+/// Every candidate gains its weight; the leader is picked and pays back the
+/// total, so picks are spread in proportion to weight and backends with
+/// equal weights take plain turns. The counters are atomics kept on the
+/// backends: concurrent picks may both see the same leader, which then pays
+/// back twice and sits out until it is even again.
+#[derive(Debug, Default)]
+pub struct RoundRobin;
+
+impl LoadBalancer for RoundRobin {
+    fn select(
+        &self,
+        backends: &[Arc<Backend>],
+        candidates: &[Candidate],
+        _: Affinity<'_>,
+    ) -> Option<usize> {
+        let total: i64 = candidates.iter().map(|c| c.weight).sum();
+        let mut best: Option<(usize, i64)> = None;
+        for candidate in candidates {
+            let score = backends[candidate.index]
+                .current_weight
+                .fetch_add(candidate.weight, Ordering::Relaxed)
+                + candidate.weight;
+            if best.is_none_or(|(_, leader)| score > leader) {
+                best = Some((candidate.index, score));
+            }
+        }
+        let (index, _) = best?;
+        backends[index]
+            .current_weight
+            .fetch_sub(total, Ordering::Relaxed);
+        Some(index)
+    }
+}
+
+/// Weighted random choice
+///
+/// Keeps no shared state, so it scales to very large pools. A seeded
+/// balancer repeats the same sequence of picks for the same candidates,
+/// which makes tests deterministic.
+#[derive(Debug, Default)]
+pub struct Random {
+    seeded: Option<Mutex<StdRng>>,
+}
+
+impl Random {
+    /// A balancer whose picks follow from `seed`
+    pub fn seeded(seed: u64) -> Self {
+        Self {
+            seeded: Some(Mutex::new(StdRng::seed_from_u64(seed))),
+        }
+    }
+}
+
+impl LoadBalancer for Random {
+    fn select(
+        &self,
+        _: &[Arc<Backend>],
+        candidates: &[Candidate],
+        _: Affinity<'_>,
+    ) -> Option<usize> {
+        let candidate = match &self.seeded {
+            Some(rng) => weighted_random(
+                candidates,
+                &mut *rng.lock().unwrap_or_else(|e| e.into_inner()),
+            ),
+            None => weighted_random(candidates, &mut rand::rng()),
+        };
+        Some(candidate?.index)
+    }
+}
+
+/// Fewest in-flight requests relative to weight, ties broken at random
+#[derive(Debug, Default)]
+pub struct LeastConn;
+
+impl LoadBalancer for LeastConn {
+    fn select(
+        &self,
+        backends: &[Arc<Backend>],
+        candidates: &[Candidate],
+        _: Affinity<'_>,
+    ) -> Option<usize> {
+        let load = |c: &Candidate| backends[c.index].active_requests() as f64 / c.weight as f64;
+        let least = candidates.iter().map(load).min_by(f64::total_cmp)?;
+        let tied: Vec<Candidate> = candidates
+            .iter()
+            .filter(|c| load(c) == least)
+            .copied()
+            .collect();
+        Some(tied[rand::rng().random_range(0..tied.len())].index)
+    }
+}
+
+/// Client IP affinity through weighted rendezvous hashing
+///
+/// The candidate with the highest weighted score for the client wins, so
+/// only the clients of a backend that leaves are moved. Requests without a
+/// client address are balanced round-robin.
+#[derive(Debug, Default)]
+pub struct IpHash {
+    fallback: RoundRobin,
+}
+
+impl LoadBalancer for IpHash {
+    fn select(
+        &self,
+        backends: &[Arc<Backend>],
+        candidates: &[Candidate],
+        affinity: Affinity<'_>,
+    ) -> Option<usize> {
+        let Some(client) = affinity.client else {
+            return self.fallback.select(backends, candidates, affinity);
+        };
+        candidates
+            .iter()
+            .map(|c| {
+                (
+                    c.index,
+                    rendezvous_score(client, &backends[c.index], c.weight),
+                )
+            })
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(index, _)| index)
+    }
+}
+
+/// Virtual nodes placed on the ring per unit of backend weight
+const VNODES_PER_WEIGHT: u32 = 160;
+
+/// Key affinity through a consistent hash ring
+///
+/// Every backend owns `weight * 160` points on the ring; a key belongs to
+/// the first candidate point at or after its hash. Backends that are not
+/// candidates stay on the ring and are skipped, so their keys move to the
+/// next backend and come back when they recover. The ring is rebuilt only
+/// when members or their configured weights change, and swapped in whole so
+/// lookups never wait. Requests without a key are balanced round-robin.
+#[derive(Debug, Default)]
+pub struct ConsistentHash {
+    ring: ArcSwap<HashRing>,
+    fallback: RoundRobin,
+}
+
+impl LoadBalancer for ConsistentHash {
+    fn select(
+        &self,
+        backends: &[Arc<Backend>],
+        candidates: &[Candidate],
+        affinity: Affinity<'_>,
+    ) -> Option<usize> {
+        let Some(key) = affinity.key else {
+            return self.fallback.select(backends, candidates, affinity);
+        };
+        let mut ring = self.ring.load_full();
+        if !ring.built_from(backends) {
+            ring = Arc::new(HashRing::new(backends));
+            self.ring.store(ring.clone());
+        }
+        ring.owner(hash64(key.as_bytes()), candidates)
+    }
+}
+
+#[derive(Debug, Default)]
+struct HashRing {
+    /// Backend URLs and weights the ring was built from
+    members: Vec<(String, u32)>,
+    /// Sorted points and the pool index owning each
+    points: Vec<(u64, usize)>,
+}
+
+impl HashRing {
+    /// Place the pool's members on a new ring
+    fn new(backends: &[Arc<Backend>]) -> Self {
+        let members: Vec<(String, u32)> =
+            backends.iter().map(|b| (b.url.clone(), b.weight)).collect();
+        let mut points = Vec::new();
+        for (index, (url, weight)) in members.iter().enumerate() {
+            for vnode in 0..weight.saturating_mul(VNODES_PER_WEIGHT) {
+                let point = hash64(format!("{}#{}", url, vnode).as_bytes());
+                points.push((point, index));
+            }
+        }
+        points.sort_unstable();
+        Self { members, points }
+    }
+
+    /// Whether the ring matches the pool's membership and weights
+    fn built_from(&self, backends: &[Arc<Backend>]) -> bool {
+        self.members.len() == backends.len()
+            && self
+                .members
+                .iter()
+                .zip(backends)
+                .all(|((url, weight), b)| *url == b.url && *weight == b.weight)
+    }
+
+    /// Pool index of the first candidate at or after `hash`
+    fn owner(&self, hash: u64, candidates: &[Candidate]) -> Option<usize> {
+        let start = self.points.partition_point(|(point, _)| *point < hash);
+        let (after, before) = self.points.split_at(start);
+        before
+            .iter()
+            .chain(after)
+            .map(|(_, index)| *index)
+            .find(|index| candidates.iter().any(|c| c.index == *index))
+    }
+}
+
+/// Weight of the newest sample in a backend's latency average
+pub(crate) const LATENCY_SMOOTHING: f64 = 0.3;
+
+/// Share of picks made at random, so backends that were slow get measured
+/// again
+const LATENCY_EXPLORATION: f64 = 0.05;
+
+/// The faster of two random candidates by moving average response time
+///
+/// Power of two choices keeps one fast backend from being sent everything
+/// at once. Unmeasured backends count as fastest, so they are tried first.
+#[derive(Debug, Default)]
+pub struct LeastLatency;
+
+impl LoadBalancer for LeastLatency {
+    fn select(
+        &self,
+        backends: &[Arc<Backend>],
+        candidates: &[Candidate],
+        _: Affinity<'_>,
+    ) -> Option<usize> {
+        let mut rng = rand::rng();
+        let first = candidates[rng.random_range(0..candidates.len())];
+        if candidates.len() == 1 || rng.random::<f64>() < LATENCY_EXPLORATION {
+            return Some(first.index);
+        }
+
+        let mut second = first;
+        while second.index == first.index {
+            second = candidates[rng.random_range(0..candidates.len())];
+        }
+        let cost = |c: Candidate| backends[c.index].latency_ms().unwrap_or(0.0) / c.weight as f64;
+        Some(if cost(second) < cost(first) {
+            second.index
+        } else {
+            first.index
+        })
+    }
+}
+
+/// A candidate chosen with probability proportional to its weight
+fn weighted_random<'a>(candidates: &'a [Candidate], rng: &mut impl Rng) -> Option<&'a Candidate> {
+    let total: i64 = candidates.iter().map(|c| c.weight).sum();
+    if total <= 0 {
+        return None;
+    }
+    let mut roll = rng.random_range(0..total);
+    candidates.iter().find(|c| {
+        roll -= c.weight;
+        roll < 0
+    })
+}
+
+/// First 8 bytes of the SHA-256 of `bytes`
+///
+/// Stable across builds and platforms, so every instance maps keys alike.
+fn hash64(bytes: &[u8]) -> u64 {
+    let digest = Sha256::digest(bytes);
+    u64::from_be_bytes(digest[..8].try_into().expect("digest has 8 bytes"))
+}
+
+/// Weighted rendezvous score of `backend` for `client`
+///
+/// The client and backend URL hash to a point in (0, 1); `weight / -ln(h)`
+/// gives each backend a share of clients proportional to its weight.
+fn rendezvous_score(client: IpAddr, backend: &Backend, weight: i64) -> f64 {
+    let mut input = match client.to_canonical() {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    input.extend_from_slice(backend.url.as_bytes());
+    let hash = hash64(&input);
+    // 53 bits map exactly onto the f64 mantissa; the half keeps it off 0 and 1
+    let point = ((hash >> 11) as f64 + 0.5) / (1u64 << 53) as f64;
+    weight as f64 / -point.ln()
+}
@@ -0,0 +1,135 @@
+//! Blue-green deployments
+//!
+//! A [`BlueGreen`] handler owns two proxies, one per pool, and sends traffic
+//! to whichever is active. The switch is a single atomic store, so in-flight
+//! requests finish on the pool they started on and every later request goes
+//! to the new one. Requests carrying the preview header are sent to the
+//! inactive pool, letting testers exercise a release before the flip.
+
+use crate::config::DeploymentColor;
+use crate::http::handler::Handler;
+use crate::http::request::Request;
+use crate::http::response::Response;
+use crate::metrics::Metrics;
+use crate::proxy::ProxyHandler;
+use async_trait::async_trait;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Response header naming the pool that served the request
+pub const DEPLOYMENT_HEADER: &str = "X-Sentinel-Deployment";
+
+/// Two backend pools behind one route, with a switchable active pool
+pub struct BlueGreen {
+    name: String,
+    blue: ProxyHandler,
+    green: ProxyHandler,
+    /// True while green is active
+    green_active: AtomicBool,
+    preview_header: String,
+    metrics: Metrics,
+}
+
+impl BlueGreen {
+    /// Create a deployment named `name` with `active` receiving traffic
+    pub fn new(
+        name: impl Into<String>,
+        blue: ProxyHandler,
+        green: ProxyHandler,
+        active: DeploymentColor,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            blue,
+            green,
+            green_active: AtomicBool::new(active == DeploymentColor::Green),
+            preview_header: "X-Sentinel-Preview".to_string(),
+            metrics: Metrics::default(),
+        }
+    }
+
+    /// Send requests carrying `header` to the inactive pool
+    pub fn with_preview_header(mut self, header: impl Into<String>) -> Self {
+        self.preview_header = header.into();
+        self
+    }
+
+    /// Report the active pool through the given metrics recorder
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self.record_active(self.active());
+        self
+    }
+
+    /// Deployment name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The pool currently receiving traffic
+    pub fn active(&self) -> DeploymentColor {
+        if self.green_active.load(Ordering::Acquire) {
+            DeploymentColor::Green
+        } else {
+            DeploymentColor::Blue
+        }
+    }
+
+    /// Make `color` the active pool, returning the previously active one
+    pub fn switch_to(&self, color: DeploymentColor) -> DeploymentColor {
+        let was_green = self
+            .green_active
+            .swap(color == DeploymentColor::Green, Ordering::AcqRel);
+        let previous = if was_green {
+            DeploymentColor::Green
+        } else {
+            DeploymentColor::Blue
+        };
+
+        if previous != color {
+            tracing::info!(
+                deployment = %self.name,
+                from = previous.as_str(),
+                to = color.as_str(),
+                "Switched blue-green deployment"
+            );
+        }
+        self.record_active(color);
+        previous
+    }
+
+    fn is_preview(&self, req: &Request) -> bool {
+        req.headers
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case(&self.preview_header))
+    }
+
+    fn record_active(&self, active: DeploymentColor) {
+        for color in [DeploymentColor::Blue, DeploymentColor::Green] {
+            self.metrics.gauge(
+                "sentinel_deployment_active",
+                &[("deployment", &self.name), ("color", color.as_str())],
+                if color == active { 1.0 } else { 0.0 },
+            );
+        }
+    }
+}
+
+#[async_trait]
+impl Handler for BlueGreen {
+    async fn handle(&self, req: Request) -> Response {
+        let mut color = self.active();
+        if self.is_preview(&req) {
+            color = color.other();
+        }
+
+        let proxy = match color {
+            DeploymentColor::Blue => &self.blue,
+            DeploymentColor::Green => &self.green,
+        };
+        let mut response = proxy.handle(req).await;
+        response
+            .headers
+            .insert(DEPLOYMENT_HEADER.to_string(), color.as_str().to_string());
+        response
+    }
+}
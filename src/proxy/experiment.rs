@@ -0,0 +1,176 @@
+//! A/B experiments
+//!
+//! An [`Experiment`] assigns each new client a variant at random, weighted
+//! by the configured percentages, and remembers it in a cookie signed with
+//! HMAC-SHA256 so clients cannot pick their own variant. Every request is
+//! forwarded with the variant in a header; variants with their own backends
+//! are served from them, the others by the default handler.
+
+use crate::config::ExperimentConfig;
+use crate::http::cookie::{Cookie, SameSite};
+use crate::http::handler::Handler;
+use crate::http::request::Request;
+use crate::http::response::Response;
+use crate::metrics::Metrics;
+use crate::proxy::ProxyHandler;
+use async_trait::async_trait;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha2::Sha256;
+use std::sync::Arc;
+use std::time::Duration;
+
+type HmacSha256 = Hmac<Sha256>;
+
+struct Variant {
+    name: String,
+    percent: u8,
+    handler: Option<ProxyHandler>,
+}
+
+/// Splits clients between variants and routes each to its handler
+pub struct Experiment {
+    name: String,
+    secret: Vec<u8>,
+    cookie: String,
+    cookie_path: String,
+    header: String,
+    max_age_secs: u64,
+    variants: Vec<Variant>,
+    default: Arc<dyn Handler>,
+    metrics: Metrics,
+}
+
+impl Experiment {
+    /// Create an experiment whose variants are all served by `default`
+    ///
+    /// Give variants their own backends with [`Experiment::with_variant_handler`].
+    pub fn new(config: &ExperimentConfig, default: Arc<dyn Handler>) -> Self {
+        Self {
+            name: config.name.clone(),
+            secret: config.secret.as_bytes().to_vec(),
+            cookie: config.cookie_name(),
+            cookie_path: config.path_prefix.clone(),
+            header: config.header.clone(),
+            max_age_secs: config.max_age_secs,
+            variants: config
+                .variants
+                .iter()
+                .map(|v| Variant {
+                    name: v.name.clone(),
+                    percent: v.percent,
+                    handler: None,
+                })
+                .collect(),
+            default,
+            metrics: Metrics::default(),
+        }
+    }
+
+    /// Serve `variant` with `handler` instead of the default handler
+    pub fn with_variant_handler(mut self, variant: &str, handler: ProxyHandler) -> Self {
+        if let Some(v) = self.variants.iter_mut().find(|v| v.name == variant) {
+            v.handler = Some(handler);
+        }
+        self
+    }
+
+    /// Record assignments and per-variant responses through the given recorder
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Experiment name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Signed cookie value assigning a client to `variant`
+    pub fn cookie_value(&self, variant: &str) -> String {
+        let signature = self.mac(variant).finalize().into_bytes();
+        format!("{}.{}", variant, URL_SAFE_NO_PAD.encode(signature))
+    }
+
+    fn mac(&self, variant: &str) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(self.name.as_bytes());
+        mac.update(b"=");
+        mac.update(variant.as_bytes());
+        mac
+    }
+
+    /// Variant from a valid assignment cookie on the request
+    fn assigned(&self, req: &Request) -> Option<usize> {
+        let cookies = req.cookies();
+        let value = cookies.get(self.cookie.as_str())?;
+
+        let (variant, signature) = value.rsplit_once('.')?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        self.mac(variant).verify_slice(&signature).ok()?;
+        self.variants.iter().position(|v| v.name == variant)
+    }
+
+    /// Pick a variant for a new client
+    fn assign(&self) -> usize {
+        let mut roll = rand::rng().random_range(0..100u32);
+        for (idx, variant) in self.variants.iter().enumerate() {
+            let percent = u32::from(variant.percent);
+            if roll < percent {
+                return idx;
+            }
+            roll -= percent;
+        }
+        self.variants.len() - 1
+    }
+}
+
+#[async_trait]
+impl Handler for Experiment {
+    async fn handle(&self, mut req: Request) -> Response {
+        let (idx, is_new) = match self.assigned(&req) {
+            Some(idx) => (idx, false),
+            None => (self.assign(), true),
+        };
+        let variant = &self.variants[idx];
+
+        req.headers
+            .retain(|name, _| !name.eq_ignore_ascii_case(&self.header));
+        req.headers
+            .insert(self.header.clone(), variant.name.clone());
+
+        let mut response = match &variant.handler {
+            Some(handler) => handler.handle(req).await,
+            None => self.default.handle(req).await,
+        };
+
+        let labels = [
+            ("experiment", self.name.as_str()),
+            ("variant", variant.name.as_str()),
+        ];
+        if is_new {
+            self.metrics
+                .increment("sentinel_experiment_assignments_total", &labels);
+
+            // Sent alongside any cookies the upstream set
+            response.add_cookie(
+                Cookie::new(&self.cookie, self.cookie_value(&variant.name))
+                    .path(&self.cookie_path)
+                    .max_age(Duration::from_secs(self.max_age_secs))
+                    .http_only()
+                    .same_site(SameSite::Lax),
+            );
+        }
+
+        let status = response.status.as_u16().to_string();
+        self.metrics.increment(
+            "sentinel_experiment_requests_total",
+            &[labels[0], labels[1], ("status", &status)],
+        );
+
+        response
+    }
+}
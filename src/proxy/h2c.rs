@@ -0,0 +1,268 @@
+//! Cleartext HTTP/2 (h2c) to backends
+//!
+//! Backends configured with `h2c: true` are spoken to over HTTP/2 with prior
+//! knowledge. Instead of a connection per request, every request to such a
+//! backend is a stream on one shared connection. A new connection is opened
+//! when the shared one fails or the backend closes it (e.g. with `GOAWAY`).
+
+use crate::http::cookie::SET_COOKIE_SEPARATOR;
+use crate::http::request::Request;
+use crate::http::response::{Response, StatusCode};
+use crate::proxy::resolver::Resolver;
+use crate::proxy::upstream::UpstreamTimeouts;
+use anyhow::Context;
+use bytes::Bytes;
+use h2::client::SendRequest;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+
+/// Request headers that do not apply to an HTTP/2 hop
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+    "host",
+    "te",
+];
+
+/// Size of the chunks a spooled request body is sent in
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Data frames of a streamed response read ahead of a slow client
+const STREAM_CHANNEL_SIZE: usize = 16;
+
+/// The connection to one backend, locked while it is being opened so
+/// concurrent requests wait for it instead of opening their own
+type Slot = Arc<tokio::sync::Mutex<Option<SendRequest<Bytes>>>>;
+
+/// Shared HTTP/2 connections to h2c backends, one per backend URL
+#[derive(Debug, Default)]
+pub struct H2cConnections {
+    connections: Mutex<HashMap<String, Slot>>,
+}
+
+impl H2cConnections {
+    /// Create an empty set of connections
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Send `request` to the backend at `url` and read the whole response
+    ///
+    /// Event streams, and with `flush` any body without a `Content-Length`,
+    /// are passed on as they arrive instead.
+    pub async fn send(
+        &self,
+        resolver: &Resolver,
+        url: &url::Url,
+        request: &Request,
+        timeouts: &UpstreamTimeouts,
+        flush: bool,
+    ) -> anyhow::Result<Response> {
+        let mut client = self.ready(resolver, url, timeouts.connect).await?;
+
+        let spooled = request.spooled.is_some();
+        let end_of_stream = request.body.is_empty() && !spooled;
+        let (response, mut body) = client
+            .send_request(build_request(request, url)?, end_of_stream)
+            .context("Failed to open HTTP/2 stream")?;
+
+        if !request.body.is_empty() {
+            body.send_data(Bytes::copy_from_slice(&request.body), !spooled)?;
+        }
+        if let Some(spooled) = &request.spooled {
+            let mut file = spooled
+                .open()
+                .await
+                .context("Failed to open spooled body")?;
+            let mut chunk = vec![0; CHUNK_SIZE];
+            loop {
+                let n = file.read(&mut chunk).await?;
+                if n == 0 {
+                    body.send_data(Bytes::new(), true)?;
+                    break;
+                }
+                // Wait for flow control rather than buffering the whole body
+                body.reserve_capacity(n);
+                while body.capacity() < n {
+                    std::future::poll_fn(|cx| body.poll_capacity(cx))
+                        .await
+                        .context("HTTP/2 stream closed while sending body")??;
+                }
+                body.send_data(Bytes::copy_from_slice(&chunk[..n]), false)?;
+            }
+        }
+
+        let response = read_within(timeouts.first_byte, "First byte timeout", response)
+            .await?
+            .context("HTTP/2 request failed")?;
+        let (parts, mut received) = response.into_parts();
+
+        let event_stream = parts
+            .headers
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("text/event-stream"));
+        let stream =
+            (flush || event_stream) && !parts.headers.contains_key(http::header::CONTENT_LENGTH);
+        let mut data = Vec::new();
+        let mut body_stream = None;
+        if stream {
+            let (tx, rx) = mpsc::channel(STREAM_CHANNEL_SIZE);
+            tokio::spawn(async move {
+                loop {
+                    let chunk = tokio::select! {
+                        chunk = received.data() => chunk,
+                        _ = tx.closed() => return,
+                    };
+                    let Some(Ok(chunk)) = chunk else { return };
+                    let _ = received.flow_control().release_capacity(chunk.len());
+                    if tx.send(chunk).await.is_err() {
+                        return;
+                    }
+                }
+            });
+            body_stream = Some(rx);
+        } else {
+            while let Some(chunk) =
+                read_within(timeouts.idle, "Idle timeout", received.data()).await?
+            {
+                let chunk = chunk.context("Failed to read HTTP/2 response body")?;
+                let _ = received.flow_control().release_capacity(chunk.len());
+                data.extend_from_slice(&chunk);
+            }
+        }
+
+        let code = parts.status.as_u16();
+        let status =
+            StatusCode::from_u16(code).with_context(|| format!("Invalid status code {}", code))?;
+        let mut headers: HashMap<String, String> = HashMap::new();
+        for (name, value) in &parts.headers {
+            if let Ok(value) = value.to_str() {
+                match headers.get_mut(&title_case(name.as_str())) {
+                    // Cookies share one entry, each on its own line
+                    Some(cookies) if name == http::header::SET_COOKIE => {
+                        cookies.push(SET_COOKIE_SEPARATOR);
+                        cookies.push_str(value);
+                    }
+                    _ => {
+                        headers.insert(title_case(name.as_str()), value.to_string());
+                    }
+                }
+            }
+        }
+
+        let mut response = Response::new(status)
+            .with_headers(headers)
+            .with_body(data)
+            .build();
+        if body_stream.is_some() {
+            response.headers.remove("Content-Length");
+            response.stream = body_stream;
+        }
+        Ok(response)
+    }
+
+    /// A connection to `url` ready for another stream, opening one if needed
+    async fn ready(
+        &self,
+        resolver: &Resolver,
+        url: &url::Url,
+        connect_timeout: Duration,
+    ) -> anyhow::Result<SendRequest<Bytes>> {
+        let slot = self
+            .connections
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(url.as_str().to_string())
+            .or_default()
+            .clone();
+        let mut slot = slot.lock().await;
+        if let Some(client) = slot.take() {
+            match client.clone().ready().await {
+                Ok(ready) => {
+                    *slot = Some(client);
+                    return Ok(ready);
+                }
+                Err(e) => {
+                    tracing::debug!(backend = %url, error = %e, "HTTP/2 connection closed, reconnecting");
+                }
+            }
+        }
+
+        let host = url.host_str().context("Backend URL missing host")?;
+        let port = url.port_or_known_default().unwrap_or(80);
+        let client = timeout(connect_timeout, async {
+            let stream = resolver.connect(host, port).await?;
+            let (client, connection) = h2::client::handshake(stream)
+                .await
+                .context("HTTP/2 handshake failed")?;
+            let backend = url.to_string();
+            tokio::spawn(async move {
+                if let Err(e) = connection.await {
+                    tracing::debug!(backend = %backend, error = %e, "HTTP/2 connection ended");
+                }
+            });
+            anyhow::Ok(client)
+        })
+        .await
+        .context("Connection timeout")??;
+
+        tracing::debug!(backend = %url, "Opened HTTP/2 connection");
+        *slot = Some(client.clone());
+        Ok(client.ready().await?)
+    }
+}
+
+fn build_request(request: &Request, url: &url::Url) -> anyhow::Result<http::Request<()>> {
+    let path = if request.path.is_empty() {
+        "/"
+    } else {
+        &request.path
+    };
+    let authority = match url.port() {
+        Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+        None => url.host_str().unwrap_or_default().to_string(),
+    };
+
+    let mut builder = http::Request::builder()
+        .method(format!("{:?}", request.method).as_str())
+        .uri(format!("http://{}{}", authority, path));
+    for (name, value) in &request.headers {
+        if !HOP_BY_HOP.iter().any(|h| name.eq_ignore_ascii_case(h)) {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+    }
+    builder.body(()).context("Invalid request for HTTP/2")
+}
+
+/// `content-type` as `Content-Type`, matching headers read over HTTP/1.1
+fn title_case(name: &str) -> String {
+    name.split('-')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+async fn read_within<T>(
+    limit: Option<Duration>,
+    what: &'static str,
+    read: impl Future<Output = T>,
+) -> anyhow::Result<T> {
+    match limit {
+        Some(limit) => timeout(limit, read).await.context(what),
+        None => Ok(read.await),
+    }
+}
@@ -0,0 +1,366 @@
+//! Active backend health checking
+//!
+//! A [`HealthChecker`] probes every backend in a pool on an interval and
+//! feeds the results into the same failure tracking used for proxied
+//! requests, so backends marked down are brought back once they recover.
+//!
+//! Backends are probed with an HTTP GET by default. Backends configured
+//! with `protocol: grpc` are probed with the standard
+//! `grpc.health.v1.Health/Check` RPC over cleartext HTTP/2, and those with
+//! `protocol: tcp` only need to accept a connection. Backends may set their
+//! own probe interval and timeout.
+
+use crate::config::{HealthCheckConfig, HealthCheckProtocol};
+use crate::discovery::http::get_url;
+use crate::metrics::Metrics;
+use crate::proxy::backend::{Backend, BackendPool};
+use anyhow::Context;
+use bytes::{Buf, Bytes, BytesMut};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+/// `grpc.health.v1.HealthCheckResponse.ServingStatus.SERVING`
+const GRPC_SERVING: u64 = 1;
+
+/// Periodically probes the backends of a pool
+pub struct HealthChecker {
+    pool: BackendPool,
+    config: HealthCheckConfig,
+    metrics: Metrics,
+    /// When each backend, by URL, is next due for a probe
+    next_probe: Mutex<HashMap<String, Instant>>,
+}
+
+impl HealthChecker {
+    /// Create a checker for `pool`
+    pub fn new(pool: BackendPool, config: HealthCheckConfig) -> Self {
+        Self {
+            pool,
+            config,
+            metrics: Metrics::default(),
+            next_probe: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record probe outcomes to the given metrics recorder
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Probe every backend once, concurrently, and apply the results
+    ///
+    /// Returns the number of healthy backends.
+    pub async fn check_all(&self) -> usize {
+        self.check_where(|_| true).await
+    }
+
+    /// Probe the backends whose interval has elapsed
+    ///
+    /// Returns the number of healthy backends among them.
+    pub async fn check_due(&self) -> usize {
+        let now = Instant::now();
+        let due: Vec<String> = {
+            let backends = self.pool.get_backends().await;
+            let mut next_probe = self.next_probe.lock().unwrap_or_else(|e| e.into_inner());
+            next_probe.retain(|url, _| backends.iter().any(|b| b.url == *url));
+            let mut due = Vec::new();
+            for backend in &backends {
+                if next_probe.get(&backend.url).is_none_or(|at| *at <= now) {
+                    next_probe.insert(backend.url.clone(), now + self.interval(backend));
+                    due.push(backend.url.clone());
+                }
+            }
+            due
+        };
+        self.check_where(|b| due.contains(&b.url)).await
+    }
+
+    /// Time until the next backend is due, at most one global interval
+    async fn until_next_due(&self) -> Duration {
+        let backends = self.pool.get_backends().await;
+        let next_probe = self.next_probe.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        backends
+            .iter()
+            .map(|b| match next_probe.get(&b.url) {
+                Some(at) => at.saturating_duration_since(now),
+                None => Duration::ZERO,
+            })
+            .min()
+            .unwrap_or(Duration::MAX)
+            .min(self.default_interval())
+    }
+
+    /// Interval between probes when a backend sets none
+    fn default_interval(&self) -> Duration {
+        match self.config.interval_ms {
+            Some(ms) => Duration::from_millis(ms),
+            None => Duration::from_secs(self.config.interval_secs),
+        }
+    }
+
+    /// Interval between probes of `backend`
+    fn interval(&self, backend: &Backend) -> Duration {
+        backend
+            .health_check
+            .as_ref()
+            .and_then(|probe| probe.interval_ms)
+            .map(Duration::from_millis)
+            .unwrap_or_else(|| self.default_interval())
+    }
+
+    async fn check_where(&self, filter: impl Fn(&Backend) -> bool) -> usize {
+        let mut probes = JoinSet::new();
+        for backend in self.pool.get_backends().await {
+            if !filter(&backend) {
+                continue;
+            }
+            let config = self.config.clone();
+            probes.spawn(async move {
+                let result = probe(&config, &backend).await;
+                (backend, result)
+            });
+        }
+
+        let mut healthy = 0;
+        while let Some(joined) = probes.join_next().await {
+            let Ok((backend, result)) = joined else {
+                continue;
+            };
+            let outcome = match result {
+                Ok(()) => {
+                    healthy += 1;
+                    self.pool.mark_backend_success(&backend.url).await;
+                    "healthy"
+                }
+                Err(e) => {
+                    tracing::debug!(
+                        backend = backend.display_name(),
+                        error = %e,
+                        "Health check failed"
+                    );
+                    self.pool.mark_backend_failed(&backend.url).await;
+                    "unhealthy"
+                }
+            };
+
+            let mut labels = backend.metric_labels();
+            labels.push(("outcome", outcome));
+            self.metrics
+                .increment("sentinel_health_checks_total", &labels);
+        }
+
+        healthy
+    }
+
+    /// Probe each backend on its interval until `cancel` fires
+    pub async fn run(self, cancel: CancellationToken) {
+        tracing::info!(
+            interval_ms = self.default_interval().as_millis() as u64,
+            "Starting active health checks"
+        );
+
+        loop {
+            tokio::select! {
+                _ = self.check_due() => {}
+                _ = cancel.cancelled() => return,
+            }
+
+            let wait = self.until_next_due().await;
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = cancel.cancelled() => return,
+            }
+        }
+    }
+
+    /// Probe a single backend without recording the result
+    pub async fn check(&self, backend: &Backend) -> anyhow::Result<()> {
+        probe(&self.config, backend).await
+    }
+}
+
+async fn probe(config: &HealthCheckConfig, backend: &Backend) -> anyhow::Result<()> {
+    let probe = backend.health_check.clone().unwrap_or_default();
+    let timeout = Duration::from_millis(probe.timeout_ms.unwrap_or(config.timeout_ms));
+    let url = url::Url::parse(&backend.url).context("Invalid backend URL")?;
+
+    match probe.protocol {
+        HealthCheckProtocol::Grpc => {
+            tokio::time::timeout(timeout, check_grpc(&url, &probe.service))
+                .await
+                .context("Health check timeout")?
+        }
+        HealthCheckProtocol::Http if url.scheme() == "http" => {
+            let path = probe.path.as_deref().unwrap_or(&config.path);
+            let response = get_url(&url.join(path)?, &[], timeout).await?;
+            if !(200..400).contains(&response.status) {
+                anyhow::bail!("Health check returned {}", response.status);
+            }
+            Ok(())
+        }
+        // HTTPS (probes do not speak TLS) and uwsgi backends only get a
+        // connect check, like TCP probes
+        HealthCheckProtocol::Tcp | HealthCheckProtocol::Http => {
+            tokio::time::timeout(timeout, connect(&url))
+                .await
+                .context("Health check timeout")?
+                .map(drop)
+        }
+    }
+}
+
+async fn connect(url: &url::Url) -> anyhow::Result<TcpStream> {
+    let host = url.host_str().context("Backend URL missing host")?;
+    let port = url.port_or_known_default().unwrap_or(80);
+    TcpStream::connect((host, port))
+        .await
+        .with_context(|| format!("Failed to connect to {}:{}", host, port))
+}
+
+/// Call `grpc.health.v1.Health/Check` and require `SERVING`
+async fn check_grpc(url: &url::Url, service: &str) -> anyhow::Result<()> {
+    let stream = connect(url).await?;
+    let (client, connection) = h2::client::handshake(stream)
+        .await
+        .context("HTTP/2 handshake failed")?;
+    let connection = tokio::spawn(async move {
+        let _ = connection.await;
+    });
+
+    let result = async {
+        let mut client = client.ready().await?;
+        let authority = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+        let request =
+            http::Request::post(format!("http://{}/grpc.health.v1.Health/Check", authority))
+                .header("content-type", "application/grpc")
+                .header("te", "trailers")
+                .body(())?;
+
+        let (response, mut send) = client.send_request(request, false)?;
+        send.send_data(grpc_frame(&encode_health_request(service)), true)?;
+
+        let response = response.await?;
+        if response.status() != http::StatusCode::OK {
+            anyhow::bail!("gRPC health check returned HTTP {}", response.status());
+        }
+
+        // Trailers-only responses carry grpc-status in the headers
+        let mut grpc_status = header_str(response.headers(), "grpc-status").map(str::to_string);
+        let mut body = response.into_body();
+        let mut data = BytesMut::new();
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk?;
+            let _ = body.flow_control().release_capacity(chunk.len());
+            data.extend_from_slice(&chunk);
+        }
+        if let Some(trailers) = body.trailers().await?
+            && let Some(status) = header_str(&trailers, "grpc-status")
+        {
+            grpc_status = Some(status.to_string());
+        }
+
+        match grpc_status.as_deref() {
+            Some("0") => {}
+            Some(code) => anyhow::bail!("gRPC health check failed with status {}", code),
+            None => anyhow::bail!("gRPC health check response missing grpc-status"),
+        }
+
+        let message = parse_grpc_frame(data.freeze())?;
+        match decode_health_status(&message) {
+            Some(GRPC_SERVING) => Ok(()),
+            status => anyhow::bail!("gRPC service not serving (status {:?})", status),
+        }
+    }
+    .await;
+
+    connection.abort();
+    result
+}
+
+fn header_str<'a>(headers: &'a http::HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+/// Encode `HealthCheckRequest { service }` (field 1, length-delimited)
+fn encode_health_request(service: &str) -> Vec<u8> {
+    if service.is_empty() {
+        return Vec::new();
+    }
+    let mut message = vec![0x0a];
+    encode_varint(service.len() as u64, &mut message);
+    message.extend_from_slice(service.as_bytes());
+    message
+}
+
+/// Decode `HealthCheckResponse.status` (field 1, varint)
+fn decode_health_status(mut message: &[u8]) -> Option<u64> {
+    let mut status = 0;
+    while !message.is_empty() {
+        let key = decode_varint(&mut message)?;
+        match (key >> 3, key & 0x7) {
+            (1, 0) => status = decode_varint(&mut message)?,
+            (_, 0) => {
+                decode_varint(&mut message)?;
+            }
+            (_, 2) => {
+                let len = decode_varint(&mut message)? as usize;
+                message = message.get(len..)?;
+            }
+            _ => return None,
+        }
+    }
+    Some(status)
+}
+
+/// Prefix a message with the gRPC frame header (uncompressed, 4-byte length)
+fn grpc_frame(message: &[u8]) -> Bytes {
+    let mut frame = BytesMut::with_capacity(5 + message.len());
+    frame.extend_from_slice(&[0]);
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(message);
+    frame.freeze()
+}
+
+fn parse_grpc_frame(mut data: Bytes) -> anyhow::Result<Bytes> {
+    if data.len() < 5 {
+        anyhow::bail!("gRPC response body too short");
+    }
+    if data.get_u8() != 0 {
+        anyhow::bail!("Compressed gRPC responses are not supported");
+    }
+    let len = data.get_u32() as usize;
+    if data.len() < len {
+        anyhow::bail!("Truncated gRPC response");
+    }
+    Ok(data.split_to(len))
+}
+
+fn encode_varint(mut value: u64, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn decode_varint(data: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = data.split_first()?;
+        *data = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
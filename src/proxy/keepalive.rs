@@ -0,0 +1,105 @@
+//! Reuse of HTTP/1.1 connections to backends
+//!
+//! With `proxy.keepalive` set, a connection whose response was read to its
+//! end is parked here instead of being closed, and the next request to the
+//! same backend takes it rather than connecting (and, for `https://`
+//! backends, handshaking) again. Connections idle for longer than
+//! `idle_timeout_ms`, or that the backend has closed meanwhile, are
+//! dropped when next looked at.
+
+use crate::config::UpstreamKeepaliveConfig;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::task::Poll;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// A connection to a backend, plain or TLS
+pub trait UpstreamStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> UpstreamStream for S {}
+
+/// Why a reused connection failed before the backend answered
+///
+/// The backend most likely closed it just as it was taken, so the request
+/// can be sent again on a new connection.
+#[derive(Debug)]
+pub(crate) struct Unanswered;
+
+impl std::fmt::Display for Unanswered {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Backend closed the connection without responding")
+    }
+}
+
+impl std::error::Error for Unanswered {}
+
+struct Idle {
+    since: Instant,
+    stream: Box<dyn UpstreamStream>,
+}
+
+/// Idle keep-alive connections, by backend origin
+pub struct IdleConnections {
+    max_per_backend: usize,
+    idle_timeout: Duration,
+    connections: Mutex<HashMap<String, Vec<Idle>>>,
+}
+
+impl IdleConnections {
+    /// Keep up to `config.max_idle_per_backend` connections per backend
+    pub fn new(config: &UpstreamKeepaliveConfig) -> Self {
+        Self {
+            max_per_backend: config.max_idle_per_backend,
+            idle_timeout: Duration::from_millis(config.idle_timeout_ms),
+            connections: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The most recently parked connection to `origin` that is still open
+    pub fn take(&self, origin: &str) -> Option<Box<dyn UpstreamStream>> {
+        let mut connections = self.connections.lock().unwrap();
+        let idle = connections.get_mut(origin)?;
+        while let Some(mut conn) = idle.pop() {
+            if conn.since.elapsed() < self.idle_timeout && is_open(&mut conn.stream) {
+                return Some(conn.stream);
+            }
+        }
+        None
+    }
+
+    /// Park a connection to `origin` whose last response was read fully
+    ///
+    /// The longest idle connection makes room if the backend already has
+    /// its share.
+    pub fn put(&self, origin: &str, stream: Box<dyn UpstreamStream>) {
+        if self.max_per_backend == 0 {
+            return;
+        }
+        let mut connections = self.connections.lock().unwrap();
+        let idle = connections.entry(origin.to_string()).or_default();
+        idle.retain(|conn| conn.since.elapsed() < self.idle_timeout);
+        if idle.len() >= self.max_per_backend {
+            idle.remove(0);
+        }
+        idle.push(Idle {
+            since: Instant::now(),
+            stream,
+        });
+    }
+}
+
+/// Whether an idle connection can still carry a request
+///
+/// An idle backend has nothing to say, so anything readable (data, end of
+/// stream, a TLS close notification, or an error) means it is done with
+/// the connection.
+fn is_open(stream: &mut Box<dyn UpstreamStream>) -> bool {
+    let mut byte = [0; 1];
+    let mut buf = ReadBuf::new(&mut byte);
+    let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+    matches!(
+        std::pin::Pin::new(stream).poll_read(&mut cx, &mut buf),
+        Poll::Pending
+    )
+}
@@ -0,0 +1,262 @@
+//! Scheduled backend maintenance
+//!
+//! A [`MaintenanceScheduler`] checks the configured windows every
+//! [`CHECK_INTERVAL`]. Backends in an open window are drained by setting
+//! their weight to 0, so in-flight requests finish while new ones go
+//! elsewhere; when the window closes their previous weight is restored.
+//!
+//! Schedules are five-field cron expressions evaluated in UTC. Each field
+//! accepts `*`, a number, a range (`1-5`), a list (`1,15`), and a step
+//! (`*/15`, `0-30/10`). As with cron, a day matches if either the
+//! day-of-month or the day-of-week field matches when both are restricted.
+
+use crate::config::MaintenanceWindow;
+use crate::http::util::civil_from_days;
+use crate::metrics::Metrics;
+use crate::proxy::backend::{Backend, BackendPool};
+use anyhow::Context;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio_util::sync::CancellationToken;
+
+/// How often open windows are re-evaluated
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Longest allowed window (one week)
+pub const MAX_MAINTENANCE_MINS: u64 = 7 * 24 * 60;
+
+/// A parsed five-field cron expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl Schedule {
+    /// Parse `minute hour day-of-month month day-of-week`
+    ///
+    /// Day-of-week runs from 0 (Sunday) to 6; 7 is also accepted for Sunday.
+    pub fn parse(expr: &str) -> anyhow::Result<Self> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields.as_slice() else {
+            anyhow::bail!("expected 5 fields, got {}", fields.len());
+        };
+
+        let mut weekdays = parse_field(weekday, 0, 7).context("day-of-week")?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+
+        Ok(Self {
+            minutes: parse_field(minute, 0, 59).context("minute")?,
+            hours: parse_field(hour, 0, 23).context("hour")?,
+            days: parse_field(day, 1, 31).context("day-of-month")?,
+            months: parse_field(month, 1, 12).context("month")?,
+            weekdays,
+            days_restricted: *day != "*",
+            weekdays_restricted: *weekday != "*",
+        })
+    }
+
+    /// Check whether the schedule fires at the minute containing `time`
+    pub fn matches(&self, time: SystemTime) -> bool {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        self.matches_minute(secs / 60)
+    }
+
+    /// Check whether a window opened by this schedule and lasting
+    /// `duration_mins` is open at `time`
+    pub fn is_open(&self, duration_mins: u64, time: SystemTime) -> bool {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let now = secs / 60;
+        (0..duration_mins.min(now + 1)).any(|ago| self.matches_minute(now - ago))
+    }
+
+    /// `minute` counts minutes since the Unix epoch
+    fn matches_minute(&self, minute: u64) -> bool {
+        let days_since_epoch = minute / (24 * 60);
+        let (_, month, day) = civil_from_days(days_since_epoch);
+        // 1970-01-01 was a Thursday
+        let weekday = (days_since_epoch + 4) % 7;
+
+        let day_matches = match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => has(self.days, day) || has(self.weekdays, weekday),
+            _ => has(self.days, day) && has(self.weekdays, weekday),
+        };
+
+        has(self.minutes, minute % 60)
+            && has(self.hours, minute / 60 % 24)
+            && has(self.months, month)
+            && day_matches
+    }
+}
+
+fn has(set: u64, value: u64) -> bool {
+    set & (1 << value) != 0
+}
+
+/// Parse one cron field into a bit set of the values it matches
+fn parse_field(field: &str, min: u64, max: u64) -> anyhow::Result<u64> {
+    let mut set = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u64 = step
+                    .parse()
+                    .with_context(|| format!("bad step '{}'", step))?;
+                if step == 0 {
+                    anyhow::bail!("step must be greater than 0");
+                }
+                (range, step)
+            }
+            None => (part, 1),
+        };
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (parse_value(start, min, max)?, parse_value(end, min, max)?)
+        } else {
+            let value = parse_value(range, min, max)?;
+            // `5/10` means every 10 starting at 5
+            (value, if step > 1 { max } else { value })
+        };
+        if start > end {
+            anyhow::bail!("range {}-{} is backwards", start, end);
+        }
+
+        for value in (start..=end).step_by(step as usize) {
+            set |= 1 << value;
+        }
+    }
+    Ok(set)
+}
+
+fn parse_value(value: &str, min: u64, max: u64) -> anyhow::Result<u64> {
+    let parsed: u64 = value
+        .parse()
+        .with_context(|| format!("bad value '{}'", value))?;
+    if !(min..=max).contains(&parsed) {
+        anyhow::bail!("{} is outside {}-{}", parsed, min, max);
+    }
+    Ok(parsed)
+}
+
+/// Drains and restores the backends of a pool on a schedule
+pub struct MaintenanceScheduler {
+    pool: BackendPool,
+    windows: Vec<(MaintenanceWindow, Schedule)>,
+    /// Weights of drained backends, by URL, to restore when their window closes
+    drained: HashMap<String, u32>,
+    metrics: Metrics,
+}
+
+impl MaintenanceScheduler {
+    /// Create a scheduler for `pool`
+    ///
+    /// Fails if a window's schedule cannot be parsed.
+    pub fn new(pool: BackendPool, windows: &[MaintenanceWindow]) -> anyhow::Result<Self> {
+        let windows = windows
+            .iter()
+            .map(|window| {
+                Schedule::parse(&window.schedule)
+                    .with_context(|| format!("Invalid maintenance schedule '{}'", window.schedule))
+                    .map(|schedule| (window.clone(), schedule))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            pool,
+            windows,
+            drained: HashMap::new(),
+            metrics: Metrics::default(),
+        })
+    }
+
+    /// Report which backends are in maintenance through the given recorder
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Drain backends whose window is open at `now` and restore the rest
+    ///
+    /// Returns the number of backends currently drained.
+    pub async fn apply(&mut self, now: SystemTime) -> usize {
+        for backend in self.pool.get_backends().await {
+            let in_window = self.windows.iter().any(|(window, schedule)| {
+                window.backends.iter().any(|b| is_backend(&backend, b))
+                    && schedule.is_open(window.duration_mins, now)
+            });
+
+            if in_window {
+                // Re-drain if something (e.g. discovery) restored the weight
+                if backend.weight > 0 {
+                    self.drained
+                        .entry(backend.url.clone())
+                        .or_insert(backend.weight);
+                    self.pool.set_weight(&backend.url, 0).await;
+                    tracing::info!(
+                        backend = backend.display_name(),
+                        "Backend entering scheduled maintenance"
+                    );
+                    self.metrics.gauge(
+                        "sentinel_backend_maintenance",
+                        &backend.metric_labels(),
+                        1.0,
+                    );
+                }
+            } else if let Some(weight) = self.drained.remove(&backend.url) {
+                self.pool.set_weight(&backend.url, weight).await;
+                tracing::info!(
+                    backend = backend.display_name(),
+                    weight,
+                    "Backend leaving scheduled maintenance"
+                );
+                self.metrics.gauge(
+                    "sentinel_backend_maintenance",
+                    &backend.metric_labels(),
+                    0.0,
+                );
+            }
+        }
+
+        // Forget backends that left the pool while drained
+        let members = self.pool.get_backends().await;
+        self.drained
+            .retain(|url, _| members.iter().any(|b| &b.url == url));
+        self.drained.len()
+    }
+
+    /// Apply the schedule every [`CHECK_INTERVAL`] until `cancel` fires
+    pub async fn run(mut self, cancel: CancellationToken) {
+        tracing::info!(
+            windows = self.windows.len(),
+            "Starting maintenance scheduler"
+        );
+
+        loop {
+            self.apply(SystemTime::now()).await;
+
+            tokio::select! {
+                _ = tokio::time::sleep(CHECK_INTERVAL) => {}
+                _ = cancel.cancelled() => return,
+            }
+        }
+    }
+}
+
+fn is_backend(backend: &Backend, name_or_url: &str) -> bool {
+    backend.url == name_or_url || backend.name.as_deref() == Some(name_or_url)
+}
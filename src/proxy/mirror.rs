@@ -0,0 +1,183 @@
+//! Traffic mirroring with response comparison
+//!
+//! A [`Mirror`] serves every request from its primary handler and sends a
+//! copy of a share of them to a shadow proxy in the background. The client
+//! only ever sees the primary response. When comparison is enabled, the
+//! shadow response is checked against the primary one so a rewrite can be
+//! validated on real traffic before cutover.
+
+use crate::config::MirrorCompareConfig;
+use crate::http::context::RequestContext;
+use crate::http::handler::Handler;
+use crate::http::request::Request;
+use crate::http::response::Response;
+use crate::metrics::Metrics;
+use crate::proxy::ProxyHandler;
+use async_trait::async_trait;
+use rand::Rng;
+use std::sync::Arc;
+
+/// Outcome of comparing a shadow response with the primary one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    /// The responses are equivalent
+    Match,
+    /// The status codes differ
+    StatusMismatch,
+    /// The status codes match but the bodies differ
+    BodyMismatch,
+}
+
+impl Comparison {
+    /// Metric label for the outcome
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Comparison::Match => "match",
+            Comparison::StatusMismatch => "status_mismatch",
+            Comparison::BodyMismatch => "body_mismatch",
+        }
+    }
+}
+
+/// Serves requests from a primary handler and mirrors them to a shadow
+pub struct Mirror {
+    primary: Arc<dyn Handler>,
+    shadow: Arc<ProxyHandler>,
+    percent: u8,
+    compare: Option<Arc<MirrorCompareConfig>>,
+    metrics: Metrics,
+}
+
+impl Mirror {
+    /// Mirror every request handled by `primary` to `shadow`
+    pub fn new(primary: Arc<dyn Handler>, shadow: ProxyHandler) -> Self {
+        Self {
+            primary,
+            shadow: Arc::new(shadow),
+            percent: 100,
+            compare: None,
+            metrics: Metrics::default(),
+        }
+    }
+
+    /// Mirror only this percentage of requests (clamped to 100)
+    pub fn with_percent(mut self, percent: u8) -> Self {
+        self.percent = percent.min(100);
+        self
+    }
+
+    /// Compare shadow responses with primary ones
+    pub fn with_comparison(mut self, compare: MirrorCompareConfig) -> Self {
+        self.compare = Some(Arc::new(compare));
+        self
+    }
+
+    /// Record mirrored requests and comparison outcomes through the given recorder
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    fn should_mirror(&self) -> bool {
+        self.percent >= 100 || rand::rng().random_range(0..100) < self.percent
+    }
+}
+
+#[async_trait]
+impl Handler for Mirror {
+    async fn handle(&self, req: Request) -> Response {
+        if !self.should_mirror() {
+            return self.primary.handle(req).await;
+        }
+
+        // The shadow outlives the client request, so it gets its own context
+        let mut copy = req.clone();
+        copy.context = RequestContext::default();
+
+        let response = self.primary.handle(req).await;
+
+        let primary = self
+            .compare
+            .as_ref()
+            .map(|_| (response.status.as_u16(), response.body.clone()));
+        let shadow = self.shadow.clone();
+        let compare = self.compare.clone();
+        let metrics = self.metrics.clone();
+        tokio::spawn(async move {
+            let (method, path) = (copy.method.clone(), copy.path.clone());
+            let shadow_response = shadow.handle(copy).await;
+            let status = shadow_response.status.as_u16().to_string();
+            metrics.increment("sentinel_mirror_requests_total", &[("status", &status)]);
+
+            let (Some(compare), Some((primary_status, primary_body))) = (compare, primary) else {
+                return;
+            };
+            let outcome = compare_responses(
+                &compare,
+                primary_status,
+                &primary_body,
+                shadow_response.status.as_u16(),
+                &shadow_response.body,
+            );
+            if outcome != Comparison::Match {
+                tracing::warn!(
+                    method = ?method,
+                    path = %path,
+                    primary_status,
+                    shadow_status = shadow_response.status.as_u16(),
+                    outcome = outcome.as_str(),
+                    "Mirrored response differs from primary"
+                );
+            }
+            metrics.increment(
+                "sentinel_mirror_comparisons_total",
+                &[("result", outcome.as_str())],
+            );
+        });
+
+        response
+    }
+}
+
+/// Compare a shadow response with the primary response
+pub fn compare_responses(
+    compare: &MirrorCompareConfig,
+    primary_status: u16,
+    primary_body: &[u8],
+    shadow_status: u16,
+    shadow_body: &[u8],
+) -> Comparison {
+    if primary_status != shadow_status {
+        return Comparison::StatusMismatch;
+    }
+    if !compare.bodies || bodies_equal(primary_body, shadow_body, &compare.ignore_fields) {
+        return Comparison::Match;
+    }
+    Comparison::BodyMismatch
+}
+
+fn bodies_equal(primary: &[u8], shadow: &[u8], ignore_fields: &[String]) -> bool {
+    match (
+        serde_json::from_slice::<serde_json::Value>(primary),
+        serde_json::from_slice::<serde_json::Value>(shadow),
+    ) {
+        (Ok(mut primary), Ok(mut shadow)) => {
+            strip_fields(&mut primary, ignore_fields);
+            strip_fields(&mut shadow, ignore_fields);
+            primary == shadow
+        }
+        _ => primary.trim_ascii() == shadow.trim_ascii(),
+    }
+}
+
+/// Remove `fields` from every object in `value`
+fn strip_fields(value: &mut serde_json::Value, fields: &[String]) {
+    match value {
+        serde_json::Value::Object(map) => {
+            map.retain(|key, _| !fields.contains(key));
+            map.values_mut().for_each(|v| strip_fields(v, fields));
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(|v| strip_fields(v, fields)),
+        _ => {}
+    }
+}
@@ -4,7 +4,32 @@
 //! management, load balancing, and request forwarding.
 
 pub mod backend;
+pub mod balancer;
+pub mod blue_green;
+pub mod experiment;
+pub mod h2c;
+pub mod health;
+pub mod keepalive;
+pub mod maintenance;
+pub mod mirror;
+pub mod replay;
+pub mod resolver;
+pub mod routes;
+pub mod split;
 pub mod upstream;
+pub mod uwsgi;
 
-pub use backend::{Backend, BackendPool, BackendState};
-pub use upstream::ProxyHandler;
+pub use backend::{Backend, BackendPool, BackendPools, BackendState, HealthThresholds};
+pub use balancer::LoadBalancer;
+pub use blue_green::BlueGreen;
+pub use experiment::Experiment;
+pub use h2c::H2cConnections;
+pub use health::HealthChecker;
+pub use keepalive::IdleConnections;
+pub use maintenance::MaintenanceScheduler;
+pub use mirror::Mirror;
+pub use replay::Replayer;
+pub use resolver::Resolver;
+pub use routes::DynamicRoutes;
+pub use split::TrafficSplit;
+pub use upstream::{ProxyHandler, UpstreamTimeouts};
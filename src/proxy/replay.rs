@@ -0,0 +1,211 @@
+//! Replay of captured traffic
+//!
+//! Re-sends the requests of HAR files (from traffic capture, or exported by
+//! a browser) against a target environment through a [`ProxyHandler`], and
+//! compares each response status with the recorded one. This is what
+//! `sentinel replay` runs:
+//!
+//! ```text
+//! sentinel replay captures/*.har --target http://staging:8080 --concurrency 4
+//! ```
+//!
+//! Requests keep their method, path, query, headers and body; the Host
+//! header becomes the target's. Headers and bodies that capture redacted or
+//! truncated cannot be reproduced: redacted headers are left out, redacted
+//! body fields are sent as captured, and requests with truncated bodies are
+//! skipped.
+
+use crate::config::BackendConfig;
+use crate::http::har::{HarEntry, HarHeader, REDACTED};
+use crate::http::request::{Method, Request, RequestBuilder};
+use crate::http::response::Response;
+use crate::proxy::{BackendPool, ProxyHandler};
+use anyhow::Context;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// Headers that describe the original connection rather than the request
+const SKIPPED_HEADERS: &[&str] = &[
+    "host",
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+    "content-length",
+];
+
+/// Tally of a replay
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplayReport {
+    /// Requests whose response status matched the recorded one
+    pub matched: usize,
+    /// Requests answered with a different status
+    pub mismatched: usize,
+    /// Requests the target could not be reached for
+    pub failed: usize,
+    /// Entries that could not be replayed faithfully
+    pub skipped: usize,
+}
+
+impl ReplayReport {
+    /// Requests sent to the target
+    pub fn sent(&self) -> usize {
+        self.matched + self.mismatched + self.failed
+    }
+}
+
+/// Sends captured requests to a target
+pub struct Replayer {
+    proxy: Arc<ProxyHandler>,
+    concurrency: usize,
+}
+
+impl Replayer {
+    /// Replay against `target`, e.g. `http://staging:8080`
+    pub fn new(target: &str) -> anyhow::Result<Self> {
+        let url =
+            url::Url::parse(target).with_context(|| format!("Invalid target '{}'", target))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            anyhow::bail!("Replay target must be http or https: {}", target);
+        }
+        let pool = BackendPool::new(vec![BackendConfig {
+            url: target.trim_end_matches('/').to_string(),
+            ..BackendConfig::default()
+        }]);
+        Ok(Self {
+            proxy: Arc::new(ProxyHandler::new(
+                pool,
+                Duration::from_secs(5),
+                Duration::from_secs(30),
+            )),
+            concurrency: 1,
+        })
+    }
+
+    /// Keep up to `concurrency` requests in flight (sequential by default)
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Replay `entries`, in order when sequential
+    pub async fn replay(&self, entries: &[HarEntry]) -> ReplayReport {
+        let mut report = ReplayReport::default();
+        let limit = Arc::new(Semaphore::new(self.concurrency));
+        let mut tasks = JoinSet::new();
+
+        for entry in entries {
+            let request = match request_for(entry) {
+                Ok(request) => request,
+                Err(e) => {
+                    tracing::warn!(url = %entry.request.url, "Skipping entry: {:#}", e);
+                    report.skipped += 1;
+                    continue;
+                }
+            };
+            let permit = limit
+                .clone()
+                .acquire_owned()
+                .await
+                .expect("semaphore is never closed");
+            let proxy = self.proxy.clone();
+            let recorded = entry.response.status;
+            tasks.spawn(async move {
+                let _permit = permit;
+                let response = proxy.forward_request(&request).await;
+                (request, recorded, response)
+            });
+            // Tally finished requests as we go, so memory stays bounded
+            while let Some(done) = tasks.try_join_next() {
+                tally(&mut report, done);
+            }
+        }
+        while let Some(done) = tasks.join_next().await {
+            tally(&mut report, done);
+        }
+        report
+    }
+}
+
+type Outcome = (Request, u16, anyhow::Result<Response>);
+
+fn tally(report: &mut ReplayReport, done: Result<Outcome, tokio::task::JoinError>) {
+    let Ok((request, recorded, response)) = done else {
+        report.failed += 1;
+        return;
+    };
+    match response {
+        // Errors Sentinel generated mean the target was not reached
+        Ok(response) if response.generated && response.status.as_u16() >= 500 => {
+            tracing::warn!(
+                method = ?request.method,
+                path = %request.path,
+                detail = %response.detail,
+                "Replayed request failed"
+            );
+            report.failed += 1;
+        }
+        Ok(response) if response.status.as_u16() == recorded => report.matched += 1,
+        Ok(response) => {
+            tracing::info!(
+                method = ?request.method,
+                path = %request.path,
+                recorded,
+                status = response.status.as_u16(),
+                "Replayed request answered with a different status"
+            );
+            report.mismatched += 1;
+        }
+        Err(e) => {
+            tracing::warn!(method = ?request.method, path = %request.path, "Replayed request failed: {:#}", e);
+            report.failed += 1;
+        }
+    }
+}
+
+/// Rebuild the request of a HAR entry
+pub fn request_for(entry: &HarEntry) -> anyhow::Result<Request> {
+    let har = &entry.request;
+    let method = Method::from_str(&har.method)
+        .with_context(|| format!("unsupported method {}", har.method))?;
+    let url = url::Url::parse(&har.url).with_context(|| format!("invalid URL {}", har.url))?;
+    let path = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    };
+
+    let body = match &har.post_data {
+        Some(post)
+            if post
+                .comment
+                .as_deref()
+                .is_some_and(|c| c.starts_with("truncated")) =>
+        {
+            anyhow::bail!("request body was truncated when captured")
+        }
+        Some(post) => post.bytes()?,
+        None => Vec::new(),
+    };
+
+    let mut builder = RequestBuilder::new().method(method).path(path);
+    for HarHeader { name, value } in &har.headers {
+        // HTTP/2 pseudo-headers from browser exports, and redacted values
+        if name.starts_with(':')
+            || value == REDACTED
+            || SKIPPED_HEADERS.iter().any(|h| name.eq_ignore_ascii_case(h))
+        {
+            continue;
+        }
+        builder = builder.header(name.clone(), value.clone());
+    }
+    if !body.is_empty() {
+        builder = builder.header("Content-Length", body.len().to_string());
+    }
+    builder
+        .body(body)
+        .build()
+        .map_err(|e| anyhow::anyhow!("{}", e))
+}
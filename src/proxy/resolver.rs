@@ -0,0 +1,221 @@
+//! Cached DNS resolution for backend hostnames
+//!
+//! A [`Resolver`] keeps each backend host's addresses for a TTL instead of
+//! resolving on every connect. Each lookup hands the addresses out starting
+//! from the next one in turn, so connections rotate across multiple A/AAAA
+//! records. Once an entry expires the cached addresses keep being used
+//! while a background task resolves the host again, so DNS changes are
+//! picked up without adding lookup latency to requests.
+//!
+//! Connections to hosts with both IPv6 and IPv4 addresses follow Happy
+//! Eyeballs (RFC 8305): the families are interleaved, IPv6 first, and each
+//! further attempt starts after a short delay or as soon as the previous
+//! one fails. The first to connect wins, so a broken IPv6 path costs the
+//! delay rather than the whole connect timeout.
+
+use crate::config::DnsConfig;
+use anyhow::Context;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+
+/// Resolves backend hosts, caching the results
+///
+/// Cheap to clone; clones share the cache.
+#[derive(Debug, Clone)]
+pub struct Resolver {
+    ttl: Duration,
+    happy_eyeballs_delay: Duration,
+    cache: Arc<Mutex<HashMap<(String, u16), Entry>>>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    addrs: Vec<SocketAddr>,
+    resolved_at: Instant,
+    /// Index of the address handed out first by the next lookup
+    next: usize,
+    refreshing: bool,
+}
+
+impl Entry {
+    fn rotated(&mut self) -> Vec<SocketAddr> {
+        let start = self.next % self.addrs.len();
+        self.next = self.next.wrapping_add(1);
+        let mut addrs = self.addrs.clone();
+        addrs.rotate_left(start);
+        addrs
+    }
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        let config = DnsConfig::default();
+        Self::new(Duration::from_millis(config.ttl_ms))
+            .with_happy_eyeballs_delay(Duration::from_millis(config.happy_eyeballs_delay_ms))
+    }
+}
+
+impl Resolver {
+    /// Create a resolver reusing addresses for `ttl`; zero disables caching
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            happy_eyeballs_delay: Duration::from_millis(
+                DnsConfig::default().happy_eyeballs_delay_ms,
+            ),
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Wait this long for a connection attempt before also trying the next
+    /// address
+    pub fn with_happy_eyeballs_delay(mut self, delay: Duration) -> Self {
+        self.happy_eyeballs_delay = delay;
+        self
+    }
+
+    /// Connect to `host`, racing its addresses with Happy Eyeballs
+    ///
+    /// Fails with the last connect error once every address has failed.
+    pub async fn connect(&self, host: &str, port: u16) -> anyhow::Result<TcpStream> {
+        let mut pending = interleave(self.resolve(host, port).await?).into_iter();
+        let mut attempts = JoinSet::new();
+        let mut last_error = None;
+
+        loop {
+            let more = match pending.next() {
+                Some(addr) => {
+                    attempts.spawn(
+                        async move { TcpStream::connect(addr).await.map_err(|e| (addr, e)) },
+                    );
+                    pending.len() > 0
+                }
+                None => false,
+            };
+            if attempts.is_empty() {
+                break;
+            }
+
+            // Wait for an attempt to finish, or for the next one to be due
+            let stagger = tokio::time::sleep(self.happy_eyeballs_delay);
+            tokio::select! {
+                joined = attempts.join_next() => match joined {
+                    Some(Ok(Ok(stream))) => return Ok(stream),
+                    Some(Ok(Err((addr, e)))) => {
+                        tracing::trace!(address = %addr, error = %e, "Backend address refused");
+                        last_error = Some(e);
+                    }
+                    Some(Err(_)) | None => {}
+                },
+                _ = stagger, if more => {}
+            }
+        }
+
+        Err(match last_error {
+            Some(e) => anyhow::Error::new(e).context(format!("Failed to connect to {}", host)),
+            None => anyhow::anyhow!("No addresses for {}", host),
+        })
+    }
+
+    /// Addresses for `host` and `port`, in the order to try them
+    ///
+    /// IP literals are returned as they are. Hosts are resolved on first use
+    /// and then served from the cache.
+    pub async fn resolve(&self, host: &str, port: u16) -> anyhow::Result<Vec<SocketAddr>> {
+        let host = host.trim_matches(['[', ']']);
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(vec![SocketAddr::new(ip, port)]);
+        }
+        if self.ttl.is_zero() {
+            return lookup(host, port).await;
+        }
+
+        let key = (host.to_string(), port);
+        if let Some(addrs) = self.cached(&key) {
+            return Ok(addrs);
+        }
+
+        let addrs = lookup(host, port).await?;
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = cache.entry(key).or_insert(Entry {
+            addrs,
+            resolved_at: Instant::now(),
+            next: 0,
+            refreshing: false,
+        });
+        Ok(entry.rotated())
+    }
+
+    /// Forget every cached address
+    pub fn clear(&self) {
+        self.cache.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+
+    /// Cached addresses for `key`, starting a refresh if they have expired
+    fn cached(&self, key: &(String, u16)) -> Option<Vec<SocketAddr>> {
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = cache.get_mut(key)?;
+        if entry.resolved_at.elapsed() >= self.ttl && !entry.refreshing {
+            entry.refreshing = true;
+            let resolver = self.clone();
+            let key = key.clone();
+            tokio::spawn(async move { resolver.refresh(key).await });
+        }
+        Some(entry.rotated())
+    }
+
+    /// Resolve `key` again, keeping the old addresses if that fails
+    async fn refresh(&self, key: (String, u16)) {
+        let result = lookup(&key.0, key.1).await;
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(entry) = cache.get_mut(&key) else {
+            return;
+        };
+        match result {
+            Ok(addrs) => {
+                if addrs != entry.addrs {
+                    tracing::debug!(host = %key.0, addresses = addrs.len(), "Backend host re-resolved");
+                }
+                entry.addrs = addrs;
+            }
+            Err(e) => {
+                tracing::warn!(
+                    host = %key.0,
+                    error = %e,
+                    "Re-resolving backend host failed, keeping cached addresses"
+                );
+            }
+        }
+        // A failed lookup is retried after another TTL rather than per request
+        entry.resolved_at = Instant::now();
+        entry.refreshing = false;
+    }
+}
+
+/// Alternate IPv6 and IPv4 addresses, IPv6 first, keeping each family's order
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let (v6, v4): (Vec<_>, Vec<_>) = addrs.into_iter().partition(SocketAddr::is_ipv6);
+    let mut interleaved = Vec::with_capacity(v6.len() + v4.len());
+    let (mut v6, mut v4) = (v6.into_iter(), v4.into_iter());
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => return interleaved,
+            (a, b) => interleaved.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+async fn lookup(host: &str, port: u16) -> anyhow::Result<Vec<SocketAddr>> {
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .with_context(|| format!("Failed to resolve {}", host))?
+        .collect();
+    if addrs.is_empty() {
+        anyhow::bail!("{} has no addresses", host);
+    }
+    Ok(addrs)
+}
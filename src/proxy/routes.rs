@@ -0,0 +1,193 @@
+//! Proxy routes populated at runtime
+//!
+//! Discovery sources such as Docker labels can ask for a path prefix to be
+//! proxied to its own set of backends. [`DynamicRoutes`] keeps one backend
+//! pool per prefix and dispatches requests by longest matching prefix,
+//! handing everything else to its fallback.
+
+use crate::config::{BackendConfig, RouteTimeouts};
+use crate::events::Events;
+use crate::http::handler::Handler;
+use crate::http::request::Request;
+use crate::http::response::Response;
+use crate::metrics::Metrics;
+use crate::proxy::{BackendPool, ProxyHandler, UpstreamTimeouts};
+use async_trait::async_trait;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+struct DynamicRoute {
+    prefix: String,
+    pool: BackendPool,
+    handler: Arc<ProxyHandler>,
+}
+
+/// Settings applied to every pool and proxy handler created for a route
+struct RouteTemplate {
+    timeouts: UpstreamTimeouts,
+    route_timeouts: Vec<RouteTimeouts>,
+    events: Events,
+    metrics: Metrics,
+}
+
+/// Path-prefix routes whose backend pools are managed at runtime
+///
+/// Clones share the same routes, so a discovery watcher can update a clone
+/// while the server dispatches through another.
+#[derive(Clone)]
+pub struct DynamicRoutes {
+    routes: Arc<RwLock<Vec<DynamicRoute>>>,
+    template: Arc<RouteTemplate>,
+    fallback: Option<Arc<dyn Handler>>,
+}
+
+impl DynamicRoutes {
+    /// Create an empty route table whose proxies use the given timeouts
+    pub fn new(connection_timeout: Duration, request_timeout: Duration) -> Self {
+        Self {
+            routes: Arc::new(RwLock::new(Vec::new())),
+            template: Arc::new(RouteTemplate {
+                timeouts: UpstreamTimeouts::new(connection_timeout, request_timeout),
+                route_timeouts: Vec::new(),
+                events: Events::new(),
+                metrics: Metrics::default(),
+            }),
+            fallback: None,
+        }
+    }
+
+    /// Publish events from route pools to the given event bus
+    ///
+    /// Must be called before the table is cloned.
+    pub fn with_events(mut self, events: Events) -> Self {
+        self.template_mut().events = events;
+        self
+    }
+
+    /// Record metrics from route pools and proxies through the given recorder
+    ///
+    /// Must be called before the table is cloned.
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.template_mut().metrics = metrics;
+        self
+    }
+
+    /// Use `timeouts` for every route's proxy
+    ///
+    /// Must be called before the table is cloned.
+    pub fn with_timeouts(mut self, timeouts: UpstreamTimeouts) -> Self {
+        self.template_mut().timeouts = timeouts;
+        self
+    }
+
+    /// Apply per-path-prefix timeout overrides in every route's proxy
+    ///
+    /// Must be called before the table is cloned.
+    pub fn with_route_timeouts(mut self, routes: Vec<RouteTimeouts>) -> Self {
+        self.template_mut().route_timeouts = routes;
+        self
+    }
+
+    /// Handle requests that match no route with `handler`
+    pub fn fallback(mut self, handler: impl Handler) -> Self {
+        self.fallback = Some(Arc::new(handler));
+        self
+    }
+
+    fn template_mut(&mut self) -> &mut RouteTemplate {
+        Arc::get_mut(&mut self.template).expect("route template is not shared yet")
+    }
+
+    /// Set the backends for `prefix`, creating the route if needed
+    ///
+    /// Existing backends keep their health state (see
+    /// [`BackendPool::replace_backends`]).
+    pub async fn set_backends(&self, prefix: &str, backends: Vec<BackendConfig>) {
+        let existing = self
+            .routes
+            .read()
+            .unwrap()
+            .iter()
+            .find(|r| r.prefix == prefix)
+            .map(|r| r.pool.clone());
+
+        match existing {
+            Some(pool) => pool.replace_backends(backends).await,
+            None => {
+                let t = &self.template;
+                let pool = BackendPool::new(backends)
+                    .with_events(t.events.clone())
+                    .with_metrics(t.metrics.clone());
+                let handler = ProxyHandler::new(pool.clone(), t.timeouts.connect, Duration::ZERO)
+                    .with_timeouts(t.timeouts)
+                    .with_route_timeouts(t.route_timeouts.clone())
+                    .with_metrics(t.metrics.clone());
+
+                tracing::info!(prefix, "Adding dynamic proxy route");
+                let mut routes = self.routes.write().unwrap();
+                routes.push(DynamicRoute {
+                    prefix: prefix.to_string(),
+                    pool,
+                    handler: Arc::new(handler),
+                });
+                // Longest prefix first so the first match wins
+                routes.sort_by_key(|r| std::cmp::Reverse(r.prefix.len()));
+            }
+        }
+    }
+
+    /// Drop every route whose prefix is not in `keep`
+    pub fn retain(&self, keep: &[String]) {
+        self.routes.write().unwrap().retain(|r| {
+            let kept = keep.contains(&r.prefix);
+            if !kept {
+                tracing::info!(prefix = %r.prefix, "Removing dynamic proxy route");
+            }
+            kept
+        });
+    }
+
+    /// Prefixes currently routed, longest first
+    pub fn prefixes(&self) -> Vec<String> {
+        self.routes
+            .read()
+            .unwrap()
+            .iter()
+            .map(|r| r.prefix.clone())
+            .collect()
+    }
+
+    /// Backend pool serving `prefix`, if the route exists
+    pub fn pool(&self, prefix: &str) -> Option<BackendPool> {
+        self.routes
+            .read()
+            .unwrap()
+            .iter()
+            .find(|r| r.prefix == prefix)
+            .map(|r| r.pool.clone())
+    }
+
+    fn find(&self, path: &str) -> Option<Arc<ProxyHandler>> {
+        let path = path.split('?').next().unwrap_or(path);
+        self.routes
+            .read()
+            .unwrap()
+            .iter()
+            .find(|r| path.starts_with(&r.prefix))
+            .map(|r| r.handler.clone())
+    }
+}
+
+#[async_trait]
+impl Handler for DynamicRoutes {
+    async fn handle(&self, req: Request) -> Response {
+        if let Some(handler) = self.find(&req.path) {
+            return handler.handle(req).await;
+        }
+
+        match &self.fallback {
+            Some(fallback) => fallback.handle(req).await,
+            None => Response::not_found(),
+        }
+    }
+}
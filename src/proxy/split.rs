@@ -0,0 +1,140 @@
+//! Weighted traffic splits between upstreams
+//!
+//! A [`TrafficSplit`] sends a route's requests to several targets in
+//! proportion to their weights, e.g. 95% to a stable pool and 5% to a
+//! canary. The target is chosen by hashing a request key (the client
+//! address unless configured otherwise), so a client keeps reaching the
+//! same side while the weights stay the same. Requests and their latency
+//! are recorded per target so canaries can be compared with the baseline.
+
+use crate::config::HashKey;
+use crate::http::handler::Handler;
+use crate::http::request::Request;
+use crate::http::response::{Response, StatusCode};
+use crate::metrics::Metrics;
+use crate::proxy::upstream::hash_key_value;
+use async_trait::async_trait;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::Instant;
+
+struct Target {
+    name: String,
+    weight: u64,
+    handler: Arc<dyn Handler>,
+}
+
+/// Splits a route's requests between weighted targets
+pub struct TrafficSplit {
+    route: String,
+    key: Option<HashKey>,
+    targets: Vec<Target>,
+    metrics: Metrics,
+}
+
+impl TrafficSplit {
+    /// Create a split for `route` without targets, keyed by the client
+    /// address
+    pub fn new(route: impl Into<String>) -> Self {
+        Self {
+            route: route.into(),
+            key: None,
+            targets: Vec::new(),
+            metrics: Metrics::default(),
+        }
+    }
+
+    /// Hash `key` instead of the client address
+    pub fn with_key(mut self, key: HashKey) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Send a `weight` share of requests to `handler`, labelled `name`
+    ///
+    /// Targets own consecutive ranges of the hash space in the order they
+    /// are added, so raising the weight of the last target only moves
+    /// clients onto it.
+    pub fn with_target(
+        mut self,
+        name: impl Into<String>,
+        weight: u32,
+        handler: Arc<dyn Handler>,
+    ) -> Self {
+        self.targets.push(Target {
+            name: name.into(),
+            weight: u64::from(weight),
+            handler,
+        });
+        self
+    }
+
+    /// Record per-target requests through the given recorder
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Name of the target serving `req`
+    ///
+    /// Requests without the key are assigned at random by weight.
+    pub fn target_for(&self, req: &Request) -> Option<&str> {
+        self.pick(req).map(|target| target.name.as_str())
+    }
+
+    fn pick(&self, req: &Request) -> Option<&Target> {
+        let total: u64 = self.targets.iter().map(|t| t.weight).sum();
+        if total == 0 {
+            return None;
+        }
+        let key = match &self.key {
+            Some(key) => hash_key_value(key, req).map(str::to_string),
+            None => req.context.peer.map(|peer| peer.ip().to_string()),
+        };
+        let mut point = match key {
+            Some(key) => hash64(key.as_bytes()) % total,
+            None => rand::rng().random_range(0..total),
+        };
+        self.targets.iter().find(|target| {
+            if point < target.weight {
+                return true;
+            }
+            point -= target.weight;
+            false
+        })
+    }
+}
+
+#[async_trait]
+impl Handler for TrafficSplit {
+    async fn handle(&self, req: Request) -> Response {
+        let Some(target) = self.pick(&req) else {
+            return Response::new(StatusCode::ServiceUnavailable).build();
+        };
+
+        let started = Instant::now();
+        let response = target.handler.handle(req).await;
+        let labels = [
+            ("route", self.route.as_str()),
+            ("target", target.name.as_str()),
+        ];
+        let status = response.status.as_u16().to_string();
+        self.metrics.increment(
+            "sentinel_split_requests_total",
+            &[labels[0], labels[1], ("status", &status)],
+        );
+        self.metrics.histogram(
+            "sentinel_split_request_duration_seconds",
+            &labels,
+            started.elapsed().as_secs_f64(),
+        );
+        response
+    }
+}
+
+/// First 8 bytes of the SHA-256 of `bytes`, the same on every instance
+fn hash64(bytes: &[u8]) -> u64 {
+    let digest = Sha256::digest(bytes);
+    u64::from_be_bytes(digest[..8].try_into().expect("digest has 8 bytes"))
+}
@@ -3,33 +3,155 @@
 //! This module handles connecting to backend servers and forwarding
 //! HTTP requests/responses.
 
-use crate::http::request::Request;
-use crate::http::response::{Response, StatusCode};
+use crate::config::{
+    HashKey, LoadFeedbackConfig, LocationRewrite, RetryAfterConfig, RetryBackoffConfig,
+    RetryPolicy, RouteTimeouts, RoutingRule, UpstreamKeepaliveConfig,
+};
+use crate::http::cookie::SET_COOKIE_SEPARATOR;
+use crate::http::handler::Handler;
+use crate::http::request::{Method, Request};
+use crate::http::response::{Response, ResponseBuilder, StatusCode};
+use crate::metrics::Metrics;
 use crate::proxy::backend::{Backend, BackendPool};
+use crate::proxy::balancer::Affinity;
+use crate::proxy::h2c::H2cConnections;
+use crate::proxy::keepalive::{IdleConnections, Unanswered, UpstreamStream};
+use crate::proxy::resolver::Resolver;
+use crate::proxy::uwsgi;
+use crate::tls;
 use anyhow::{Context, Result};
-use bytes::{Buf, BytesMut};
-use std::time::Duration;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
+use async_trait::async_trait;
+use bytes::{Buf, Bytes, BytesMut};
+use rand::Rng;
+use rustls::HandshakeKind;
+use rustls::pki_types::ServerName;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
 use tokio::time::timeout;
+use tokio_rustls::TlsConnector;
 
 /// Default buffer size for streaming
 const BUFFER_SIZE: usize = 8192;
 
+/// Pieces of a streamed response body read ahead of a slow client
+const STREAM_CHANNEL_SIZE: usize = 16;
+
+/// Limits applied to one backend exchange
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UpstreamTimeouts {
+    /// Time allowed to connect to the backend
+    pub connect: Duration,
+    /// Time allowed from sending the request to the end of the response
+    pub total: Option<Duration>,
+    /// Time allowed between sending the request and the first response byte
+    pub first_byte: Option<Duration>,
+    /// Longest pause allowed between response reads after the first byte
+    pub idle: Option<Duration>,
+}
+
+impl UpstreamTimeouts {
+    /// Timeouts with only connect and overall limits (a zero `total` means
+    /// no overall limit)
+    pub fn new(connect: Duration, total: Duration) -> Self {
+        Self {
+            connect,
+            total: (!total.is_zero()).then_some(total),
+            first_byte: None,
+            idle: None,
+        }
+    }
+
+    /// Apply the fields a route sets on top of these timeouts
+    fn with_overrides(mut self, route: &RouteTimeouts) -> Self {
+        if route.stream {
+            self.total = None;
+            self.idle = None;
+        }
+        if let Some(ms) = route.connection_timeout_ms {
+            self.connect = Duration::from_millis(ms);
+        }
+        if let Some(ms) = route.request_timeout_ms {
+            self.total = optional_millis(ms);
+        }
+        if let Some(ms) = route.first_byte_timeout_ms {
+            self.first_byte = optional_millis(ms);
+        }
+        if let Some(ms) = route.idle_timeout_ms {
+            self.idle = optional_millis(ms);
+        }
+        self
+    }
+}
+
 /// Handles proxying requests to backend servers
 pub struct ProxyHandler {
     /// Pool of backend servers
     backend_pool: BackendPool,
     
-    /// Connection timeout duration
-    connection_timeout: Duration,
-    
-    /// Request timeout duration
-    request_timeout: Duration,
+    /// Timeouts for requests no route override matches
+    timeouts: UpstreamTimeouts,
+
+    /// Per-path-prefix timeout overrides
+    route_timeouts: Vec<RouteTimeouts>,
+
+    /// Metrics recorder for upstream requests
+    metrics: Metrics,
+
+    /// Rules that steer requests to labelled backends
+    routing_rules: Vec<RoutingRule>,
+
+    /// Where backends report their load, if load feedback is enabled
+    load_feedback: Option<LoadFeedbackConfig>,
+
+    /// Rules for rewriting backend URLs in response headers
+    location_rewrites: Vec<LocationRewrite>,
+
+    /// Retry hint for 503 responses, if configured
+    retry_after: Option<RetryAfterConfig>,
+
+    /// Which failed requests are retried on another backend
+    retry: RetryPolicy,
+
+    /// Retries currently allowed by the policy's budget
+    retry_tokens: Mutex<f64>,
+
+    /// Wait between attempts on successive backends, if configured
+    retry_backoff: Option<RetryBackoffConfig>,
+
+    /// TLS settings for `https://` backends (the process default if unset)
+    tls: Option<Arc<rustls::ClientConfig>>,
+
+    /// TLS sessions remembered per backend for resumption
+    tls_session_cache: usize,
+
+    /// `tls` with a session cache of its own, by backend origin
+    tls_sessions: Mutex<HashMap<String, Arc<rustls::ClientConfig>>>,
+
+    /// Idle connections to reuse, if keep-alive is enabled
+    keepalive: Option<IdleConnections>,
+
+    /// Request attribute passed to the pool for consistent hashing
+    hash_key: HashKey,
+
+    /// Cached addresses of backend hosts
+    resolver: Resolver,
+
+    /// Shared HTTP/2 connections to h2c backends
+    h2c: Arc<H2cConnections>,
+
+    /// Name added to `Via` headers and looked for to detect loops
+    via: String,
 }
 
 impl ProxyHandler {
     /// Create a new proxy handler
+    ///
+    /// `request_timeout` bounds the whole exchange; a zero duration removes
+    /// the limit.
     pub fn new(
         backend_pool: BackendPool,
         connection_timeout: Duration,
@@ -37,8 +159,210 @@ impl ProxyHandler {
     ) -> Self {
         Self {
             backend_pool,
-            connection_timeout,
-            request_timeout,
+            timeouts: UpstreamTimeouts::new(connection_timeout, request_timeout),
+            route_timeouts: Vec::new(),
+            metrics: Metrics::default(),
+            routing_rules: Vec::new(),
+            load_feedback: None,
+            location_rewrites: Vec::new(),
+            retry_after: None,
+            retry_tokens: Mutex::new(f64::from(RetryPolicy::default().budget_burst)),
+            retry: RetryPolicy::default(),
+            retry_backoff: None,
+            tls: None,
+            tls_session_cache: tls::DEFAULT_SESSION_CACHE_SIZE,
+            tls_sessions: Mutex::new(HashMap::new()),
+            keepalive: None,
+            hash_key: HashKey::default(),
+            resolver: Resolver::default(),
+            h2c: Arc::new(H2cConnections::new()),
+            via: "sentinel".to_string(),
+        }
+    }
+
+    /// Name this proxy in `Via` headers (default: `sentinel`)
+    pub fn with_via(mut self, pseudonym: impl Into<String>) -> Self {
+        self.via = pseudonym.into();
+        self
+    }
+
+    /// Whether `request` has already passed through this proxy, by its
+    /// `Via` header
+    pub fn has_looped(&self, request: &Request) -> bool {
+        header(&request.headers, "Via").is_some_and(|via| {
+            via.split(',').any(|entry| {
+                entry
+                    .split_whitespace()
+                    .nth(1)
+                    .is_some_and(|name| name.eq_ignore_ascii_case(&self.via))
+            })
+        })
+    }
+
+    /// Record upstream request counts and latency to the given metrics recorder
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Replace every timeout at once
+    pub fn with_timeouts(mut self, timeouts: UpstreamTimeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Fail requests whose response does not start within `timeout`
+    pub fn with_first_byte_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.first_byte = Some(timeout);
+        self
+    }
+
+    /// Fail requests whose response stalls for longer than `timeout`
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.idle = Some(timeout);
+        self
+    }
+
+    /// Override timeouts for requests under the given path prefixes
+    pub fn with_route_timeouts(mut self, routes: Vec<RouteTimeouts>) -> Self {
+        self.route_timeouts = routes;
+        self
+    }
+
+    /// Timeouts for a request, after any matching route override
+    pub fn timeouts_for(&self, request: &Request) -> UpstreamTimeouts {
+        self.route_for(request)
+            .map_or(self.timeouts, |route| self.timeouts.with_overrides(route))
+    }
+
+    /// Whether responses to a request are passed through as they arrive
+    /// rather than buffered
+    ///
+    /// Event streams (`text/event-stream`) always are; routes may ask for it
+    /// for any response without a `Content-Length`.
+    pub fn flushes(&self, request: &Request) -> bool {
+        self.route_for(request).is_some_and(|route| route.stream)
+    }
+
+    /// The route override with the longest prefix matching a request
+    fn route_for(&self, request: &Request) -> Option<&RouteTimeouts> {
+        let path = request.path.split('?').next().unwrap_or(&request.path);
+        self.route_timeouts
+            .iter()
+            .filter(|route| path.starts_with(route.path_prefix.as_str()))
+            .max_by_key(|route| route.path_prefix.len())
+    }
+
+    /// Send requests matching a rule to backends carrying its labels
+    pub fn with_routing_rules(mut self, rules: Vec<RoutingRule>) -> Self {
+        self.routing_rules = rules;
+        self
+    }
+
+    /// Read load reports from backend responses and pass them to the pool
+    ///
+    /// The pool must be configured with the same settings (see
+    /// [`BackendPool::with_load_feedback`]).
+    pub fn with_load_feedback(mut self, config: LoadFeedbackConfig) -> Self {
+        self.load_feedback = Some(config);
+        self
+    }
+
+    /// Rewrite `Location` and `Content-Location` headers pointing at backends
+    pub fn with_location_rewrites(mut self, rewrites: Vec<LocationRewrite>) -> Self {
+        self.location_rewrites = rewrites;
+        self
+    }
+
+    /// Send `Retry-After` with 503 responses when no backend is available
+    pub fn with_retry_after(mut self, retry_after: RetryAfterConfig) -> Self {
+        self.retry_after = Some(retry_after);
+        self
+    }
+
+    /// Decide which failed requests are retried, and how often
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_tokens = Mutex::new(f64::from(policy.budget_burst));
+        self.retry = policy;
+        self
+    }
+
+    /// Back off exponentially between attempts on successive backends
+    pub fn with_retry_backoff(mut self, backoff: RetryBackoffConfig) -> Self {
+        self.retry_backoff = Some(backoff);
+        self
+    }
+
+    /// Connect to `https://` backends with this TLS configuration
+    pub fn with_tls(mut self, tls: Arc<rustls::ClientConfig>) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Remember up to `size` TLS sessions per backend so reconnects resume
+    /// them (256 by default; 0 disables resumption)
+    pub fn with_tls_session_cache(mut self, size: usize) -> Self {
+        self.tls_session_cache = size;
+        self
+    }
+
+    /// Keep HTTP/1.1 connections to backends open and reuse them
+    pub fn with_keepalive(mut self, config: &UpstreamKeepaliveConfig) -> Self {
+        self.keepalive = Some(IdleConnections::new(config));
+        self
+    }
+
+    /// Hash this request attribute when the pool balances by consistent
+    /// hashing (see [`LoadBalancing::ConsistentHash`](crate::config::LoadBalancing))
+    pub fn with_hash_key(mut self, key: HashKey) -> Self {
+        self.hash_key = key;
+        self
+    }
+
+    /// Resolve backend hostnames with this resolver, e.g. one with another
+    /// TTL or shared with other handlers
+    pub fn with_resolver(mut self, resolver: Resolver) -> Self {
+        self.resolver = resolver;
+        self
+    }
+
+    /// Select a backend, honouring the first routing rule that matches
+    async fn select_backend(&self, request: &Request) -> Option<Backend> {
+        let affinity = Affinity {
+            client: request.context.peer.map(|peer| peer.ip()),
+            key: hash_key_value(&self.hash_key, request),
+        };
+        let Some(rule) = self.routing_rules.iter().find(|r| rule_matches(r, request)) else {
+            return self.backend_pool.select_backend_for(affinity).await;
+        };
+
+        if let Some(percent) = rule.percent
+            && rand::rng().random_range(0..100) >= percent
+        {
+            return match self
+                .backend_pool
+                .select_backend_excluding_for(&rule.backend_labels, affinity)
+                .await
+            {
+                Some(backend) => Some(backend),
+                None => self.backend_pool.select_backend_for(affinity).await,
+            };
+        }
+
+        match self
+            .backend_pool
+            .select_backend_matching_for(&rule.backend_labels, affinity)
+            .await
+        {
+            Some(backend) => Some(backend),
+            None if rule.fallback => {
+                tracing::debug!(
+                    path = %request.path,
+                    "No labelled backend available, falling back to the whole pool"
+                );
+                self.backend_pool.select_backend_for(affinity).await
+            }
+            None => None,
         }
     }
 
@@ -49,20 +373,59 @@ impl ProxyHandler {
     /// 2. Connects to the backend
     /// 3. Forwards the request
     /// 4. Streams the response back
-    /// 5. Retries with other backends if one fails
+    /// 5. Retries with other backends if one fails and the retry policy
+    ///    allows, backing off between attempts if configured
     pub async fn forward_request(&self, request: &Request) -> Result<Response> {
-        let max_retries = self.backend_pool.available_count().await;
-        
-        if max_retries == 0 {
-            anyhow::bail!("No available backends");
+        // With every backend down, a half-open circuit may still take a probe
+        let mut max_retries = self.backend_pool.available_count().await.max(1);
+        if let Some(max_attempts) = self.retry.max_attempts {
+            max_retries = max_retries.min(max_attempts as usize);
+        }
+        if !self.retry.allows(&request.method) {
+            max_retries = 1;
         }
+        self.deposit_retry_tokens();
 
         let mut last_error = None;
-        
+        let mut last_response = None;
+        let first_attempt = Instant::now();
+
         // Try up to the number of available backends
         for attempt in 0..max_retries {
+            if request.context.is_cancelled() {
+                return Ok(cancelled_response());
+            }
+
+            if attempt > 0 && !self.withdraw_retry_token() {
+                tracing::warn!(
+                    method = ?request.method,
+                    path = %request.path,
+                    "Retry budget exhausted, not retrying"
+                );
+                break;
+            }
+
+            if attempt > 0
+                && let Some(backoff) = &self.retry_backoff
+            {
+                let delay = backoff.delay(attempt as u32);
+                if let Some(max_total) = backoff.max_total_ms.map(Duration::from_millis)
+                    && first_attempt.elapsed() + delay > max_total
+                {
+                    tracing::debug!(
+                        attempt = attempt + 1,
+                        "Retry time limit reached, giving up on other backends"
+                    );
+                    break;
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = request.context.cancelled() => return Ok(cancelled_response()),
+                }
+            }
+
             // Select a backend
-            let backend = match self.backend_pool.select_backend().await {
+            let backend = match self.select_backend(request).await {
                 Some(b) => b,
                 None => {
                     tracing::error!("No available backends in pool");
@@ -79,14 +442,56 @@ impl ProxyHandler {
                 "Forwarding request to backend"
             );
 
-            // Try to proxy the request
-            match self.proxy_to_backend(&backend, request).await {
-                Ok(response) => {
+            // Try to proxy the request, abandoning it if the request is cancelled
+            let started = Instant::now();
+            let in_flight = self.metrics.in_flight(
+                "sentinel_upstream_requests_in_flight",
+                &backend.metric_labels(),
+            );
+            let result = tokio::select! {
+                result = self.proxy_to_backend(&backend, request) => result,
+                _ = request.context.cancelled() => {
+                    tracing::debug!(
+                        backend = backend.display_name(),
+                        method = ?request.method,
+                        path = %request.path,
+                        "Request cancelled, abandoning upstream attempt"
+                    );
+                    return Ok(cancelled_response());
+                }
+            };
+            drop(in_flight);
+            self.record_upstream(&backend, started, &result);
+
+            match result {
+                Ok(response)
+                    if attempt + 1 < max_retries
+                        && self.retry.statuses.contains(&response.status.as_u16()) =>
+                {
+                    self.backend_pool.mark_backend_failed(&backend.url).await;
+                    tracing::warn!(
+                        backend = backend.display_name(),
+                        status = response.status.as_u16(),
+                        method = ?request.method,
+                        path = %request.path,
+                        attempt = attempt + 1,
+                        "Backend returned a retryable status, will retry with another"
+                    );
+                    last_error = None;
+                    last_response = Some((backend, response));
+                }
+                Ok(mut response) => {
                     // Mark backend as successful
                     self.backend_pool.mark_backend_success(&backend.url).await;
-                    
+                    self.backend_pool
+                        .record_latency(&backend.url, started.elapsed())
+                        .await;
+                    self.apply_load_report(&backend, &mut response).await;
+                    self.rewrite_locations(&backend, request, &mut response);
+
                     tracing::info!(
                         backend = backend.display_name(),
+                        labels = %backend.labels_display(),
                         status = response.status.as_u16(),
                         method = ?request.method,
                         path = %request.path,
@@ -110,10 +515,17 @@ impl ProxyHandler {
                     );
                     
                     last_error = Some(e);
+                    last_response = None;
                     // Continue to next backend
                 }
             }
         }
+
+        // A retryable status is passed on when no retry could replace it
+        if let Some((backend, mut response)) = last_response {
+            self.rewrite_locations(&backend, request, &mut response);
+            return Ok(response);
+        }
         
         // All backends failed
         tracing::error!(
@@ -130,6 +542,121 @@ impl ProxyHandler {
         }
     }
 
+    /// Add the share of a retry earned by each request to the budget
+    fn deposit_retry_tokens(&self) {
+        let mut tokens = self.retry_tokens.lock().unwrap_or_else(|e| e.into_inner());
+        let burst = f64::from(self.retry.budget_burst.max(1));
+        *tokens = (*tokens + f64::from(self.retry.budget_percent) / 100.0).min(burst);
+    }
+
+    /// Take one retry from the budget, if any is left
+    fn withdraw_retry_token(&self) -> bool {
+        let mut tokens = self.retry_tokens.lock().unwrap_or_else(|e| e.into_inner());
+        if *tokens < 1.0 {
+            return false;
+        }
+        *tokens -= 1.0;
+        true
+    }
+
+    /// Apply the matching location rewrite rule to `response`
+    fn rewrite_locations(&self, backend: &Backend, request: &Request, response: &mut Response) {
+        let path = request.path.split('?').next().unwrap_or(&request.path);
+        let Some(rule) = self
+            .location_rewrites
+            .iter()
+            .filter(|rule| path.starts_with(rule.path_prefix.as_str()))
+            .max_by_key(|rule| rule.path_prefix.len())
+        else {
+            return;
+        };
+
+        let Some(to) = rule.to.clone().or_else(|| external_origin(request)) else {
+            return;
+        };
+        let backend_url = url::Url::parse(&backend.url).ok();
+
+        for (name, value) in response.headers.iter_mut() {
+            if !name.eq_ignore_ascii_case("Location")
+                && !name.eq_ignore_ascii_case("Content-Location")
+            {
+                continue;
+            }
+            let rest = match &rule.from {
+                Some(from) => value.strip_prefix(from.as_str()).map(str::to_string),
+                None => backend_url
+                    .as_ref()
+                    .and_then(|backend_url| strip_origin(value, backend_url)),
+            };
+            if let Some(rest) = rest {
+                let rewritten = format!("{}{}", to, rest);
+                tracing::debug!(
+                    backend = backend.display_name(),
+                    from = %value,
+                    to = %rewritten,
+                    "Rewrote backend URL in response header"
+                );
+                *value = rewritten;
+            }
+        }
+    }
+
+    /// Pass a load report in `response` to the pool, stripping it if configured
+    async fn apply_load_report(&self, backend: &Backend, response: &mut Response) {
+        let Some(feedback) = &self.load_feedback else {
+            return;
+        };
+        let Some(key) = response
+            .headers
+            .keys()
+            .find(|k| k.eq_ignore_ascii_case(&feedback.header))
+            .cloned()
+        else {
+            return;
+        };
+
+        let value = if feedback.strip_header {
+            response.headers.remove(&key)
+        } else {
+            response.headers.get(&key).cloned()
+        };
+
+        match value
+            .as_deref()
+            .and_then(|v| parse_load_report(v, &feedback.metric))
+        {
+            Some(load) => self.backend_pool.report_load(&backend.url, load).await,
+            None => tracing::debug!(
+                backend = backend.display_name(),
+                header = %key,
+                "Ignoring unparseable load report"
+            ),
+        }
+    }
+
+    /// Record the outcome, status, and latency of one upstream attempt
+    fn record_upstream(&self, backend: &Backend, started: Instant, result: &Result<Response>) {
+        let labels = backend.metric_labels();
+        let outcome = if result.is_ok() { "success" } else { "failure" };
+
+        let mut with_outcome = labels.clone();
+        with_outcome.push(("outcome", outcome));
+        self.metrics
+            .increment("sentinel_upstream_requests_total", &with_outcome);
+        if let Ok(response) = result {
+            let status = response.status.as_u16().to_string();
+            let mut with_status = labels.clone();
+            with_status.push(("status", &status));
+            self.metrics
+                .increment("sentinel_upstream_responses_total", &with_status);
+        }
+        self.metrics.histogram(
+            "sentinel_upstream_duration_seconds",
+            &labels,
+            started.elapsed().as_secs_f64(),
+        );
+    }
+
     /// Proxy a request to a specific backend
     async fn proxy_to_backend(&self, backend: &Backend, request: &Request) -> Result<Response> {
         // Parse backend URL to get host and port
@@ -142,43 +669,166 @@ impl ProxyHandler {
             _ => 80,
         });
 
+        let timeouts = self.timeouts_for(request);
+
+        if backend.h2c && url.scheme() == "http" {
+            let flush = self.flushes(request);
+            let exchange = self
+                .h2c
+                .send(&self.resolver, &url, request, &timeouts, flush);
+            return match timeouts.total {
+                Some(total) => timeout(total, exchange).await.context("Request timeout")?,
+                None => exchange.await,
+            };
+        }
+
+        let origin = format!("{}://{}:{}", url.scheme(), host, port);
+        if let Some(stream) = self.idle_for(&url).and_then(|idle| idle.take(&origin)) {
+            tracing::trace!(
+                backend = backend.display_name(),
+                "Reusing connection to backend"
+            );
+            match self
+                .exchange(stream, request, &url, &origin, &timeouts)
+                .await
+            {
+                // The backend closed it meanwhile; a new one will do
+                Err(e) if e.is::<Unanswered>() && self.retry.allows(&request.method) => {
+                    tracing::debug!(
+                        backend = backend.display_name(),
+                        "Reused connection was closed by the backend, reconnecting"
+                    );
+                }
+                result => return result,
+            }
+        }
+
+        let stream = self
+            .connect(backend, &url, &origin, host, port, &timeouts)
+            .await?;
+        self.exchange(stream, request, &url, &origin, &timeouts)
+            .await
+    }
+
+    /// Open a connection to a backend, with a TLS handshake for `https://`
+    async fn connect(
+        &self,
+        backend: &Backend,
+        url: &url::Url,
+        origin: &str,
+        host: &str,
+        port: u16,
+        timeouts: &UpstreamTimeouts,
+    ) -> Result<Box<dyn UpstreamStream>> {
         // Connect to backend with timeout
-        let addr = format!("{}:{}", host, port);
-        let stream = timeout(
-            self.connection_timeout,
-            TcpStream::connect(&addr),
-        )
-        .await
-        .context("Connection timeout")?
-        .context("Failed to connect to backend")?;
+        let stream = timeout(timeouts.connect, self.resolver.connect(host, port))
+            .await
+            .context("Connection timeout")?
+            .context("Failed to connect to backend")?;
 
         tracing::trace!(backend = backend.display_name(), "Connected to backend");
 
-        // Forward request and get response with timeout
-        timeout(
-            self.request_timeout,
-            self.send_request_and_receive_response(stream, request, &url),
+        if url.scheme() != "https" {
+            return Ok(Box::new(stream));
+        }
+        let server_name =
+            ServerName::try_from(host.to_string()).context("Invalid backend TLS server name")?;
+        let stream = timeout(
+            timeouts.connect,
+            TlsConnector::from(self.tls_for(origin)).connect(server_name, stream),
         )
         .await
-        .context("Request timeout")?
+        .context("TLS handshake timeout")?
+        .context("TLS handshake with backend failed")?;
+        tracing::trace!(
+            backend = backend.display_name(),
+            resumed = stream.get_ref().1.handshake_kind() == Some(HandshakeKind::Resumed),
+            "TLS handshake with backend complete"
+        );
+        Ok(Box::new(stream))
+    }
+
+    /// TLS settings for the backend at `origin`, with its own session cache
+    fn tls_for(&self, origin: &str) -> Arc<rustls::ClientConfig> {
+        let mut sessions = self.tls_sessions.lock().unwrap_or_else(|e| e.into_inner());
+        sessions
+            .entry(origin.to_string())
+            .or_insert_with(|| {
+                let mut config =
+                    (*self.tls.clone().unwrap_or_else(tls::default_client_config)).clone();
+                config.resumption = tls::session_cache(self.tls_session_cache);
+                Arc::new(config)
+            })
+            .clone()
+    }
+
+    /// Idle connections to keep a backend's connections in, if they are
+    /// kept at all
+    ///
+    /// uWSGI backends close the connection after every response.
+    fn idle_for(&self, url: &url::Url) -> Option<&IdleConnections> {
+        self.keepalive.as_ref().filter(|_| url.scheme() != "uwsgi")
     }
 
-    /// Send request to backend and receive response
-    async fn send_request_and_receive_response(
+    /// Forward request and get response, bounded overall if configured
+    ///
+    /// With keep-alive, the connection is kept for the next request if the
+    /// response left it reusable.
+    async fn exchange(
         &self,
-        mut stream: TcpStream,
+        stream: Box<dyn UpstreamStream>,
         request: &Request,
-        backend_url: &url::Url,
+        url: &url::Url,
+        origin: &str,
+        timeouts: &UpstreamTimeouts,
     ) -> Result<Response> {
-        // Build and send HTTP request
-        let request_bytes = self.build_http_request(request, backend_url)?;
-        stream.write_all(&request_bytes).await?;
+        let exchange = self.send_request_and_receive_response(stream, request, url, timeouts);
+        let (response, reusable) = match timeouts.total {
+            Some(total) => timeout(total, exchange).await.context("Request timeout")?,
+            None => exchange.await,
+        }?;
+        if let Some(idle) = self.idle_for(url)
+            && let Some(stream) = reusable
+        {
+            idle.put(origin, stream);
+        }
+        Ok(response)
+    }
+
+    /// Send request to backend and receive response, along with the
+    /// connection if another request can follow on it
+    async fn send_request_and_receive_response<S>(
+        &self,
+        mut stream: S,
+        request: &Request,
+        backend_url: &url::Url,
+        timeouts: &UpstreamTimeouts,
+    ) -> Result<(Response, Option<S>)>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        // Build and send the request in the backend's protocol
+        let request_bytes = match backend_url.scheme() {
+            "uwsgi" => uwsgi::encode_request(request, backend_url)?,
+            _ => self.build_http_request(request, backend_url)?,
+        };
+        stream.write_all(&request_bytes).await.context(Unanswered)?;
+        // Bodies spooled to disk follow the head in either protocol
+        if let Some(spooled) = &request.spooled {
+            let mut body = spooled
+                .open()
+                .await
+                .context("Failed to open spooled body")?;
+            tokio::io::copy(&mut body, &mut stream).await?;
+        }
         stream.flush().await?;
 
         tracing::trace!("Request sent to backend");
 
         // Read and parse response
-        self.read_http_response(&mut stream).await
+        let head = request.method == Method::HEAD;
+        self.read_http_response(stream, timeouts, self.flushes(request), head)
+            .await
     }
 
     /// Build HTTP request bytes to send to backend
@@ -195,8 +845,10 @@ impl ProxyHandler {
             &request.path
         };
         
+        // Backends are spoken to in HTTP/1.1 whatever the client used, e.g.
+        // HTTP/2 through the hyper engine
         buffer.extend_from_slice(
-            format!("{} {} {}\r\n", method, path, request.version).as_bytes()
+            format!("{} {} HTTP/1.1\r\n", method, path).as_bytes()
         );
 
         // Headers - add/modify headers for backend
@@ -219,8 +871,13 @@ impl ProxyHandler {
         headers.remove("Transfer-Encoding");
         headers.remove("Upgrade");
 
-        // Add Connection: close for simplicity
-        headers.insert("Connection".to_string(), "close".to_string());
+        // Connections are closed after the response unless kept alive
+        let connection = if self.keepalive.is_some() {
+            "keep-alive"
+        } else {
+            "close"
+        };
+        headers.insert("Connection".to_string(), connection.to_string());
 
         // Write headers
         for (key, value) in &headers {
@@ -239,13 +896,43 @@ impl ProxyHandler {
     }
 
     /// Read HTTP response from backend
-    async fn read_http_response(&self, stream: &mut TcpStream) -> Result<Response> {
+    ///
+    /// The first read is bounded by the first-byte timeout and every later
+    /// one by the idle timeout. A body that is passed through (see
+    /// [`flushes`](Self::flushes)) is read by a background task instead,
+    /// with no timeout. Responses to `HEAD` requests have no body.
+    ///
+    /// The stream is handed back if the response was read to its end on an
+    /// HTTP/1.1 connection the backend keeps open.
+    async fn read_http_response<S>(
+        &self,
+        mut stream: S,
+        timeouts: &UpstreamTimeouts,
+        flush: bool,
+        head: bool,
+    ) -> Result<(Response, Option<S>)>
+    where
+        S: AsyncRead + Unpin + Send + 'static,
+    {
         let mut buffer = BytesMut::with_capacity(BUFFER_SIZE);
         
         // Read response headers
         loop {
-            let n = stream.read_buf(&mut buffer).await?;
-            
+            let limit = if buffer.is_empty() {
+                (timeouts.first_byte, "First byte timeout")
+            } else {
+                (timeouts.idle, "Idle timeout")
+            };
+            let n = match read_within(limit.0, limit.1, stream.read_buf(&mut buffer)).await {
+                Err(e) if buffer.is_empty() && e.is::<std::io::Error>() => {
+                    return Err(e.context(Unanswered));
+                }
+                n => n?,
+            };
+
+            if n == 0 && buffer.is_empty() {
+                return Err(Unanswered.into());
+            }
             if n == 0 {
                 anyhow::bail!("Connection closed before complete response received");
             }
@@ -256,18 +943,39 @@ impl ProxyHandler {
                 .position(|window| window == b"\r\n\r\n")
             {
                 let headers_bytes = buffer.split_to(headers_end + 4);
-                let (status, headers) = self.parse_response_headers(&headers_bytes)?;
-                
-                // Read body based on Content-Length
-                let body = self.read_response_body(stream, &mut buffer, &headers).await?;
-                
-                // Build final response with body
-                let response = Response::new(status)
-                    .with_headers(headers)
-                    .with_body(body)
-                    .build();
-                
-                return Ok(response);
+                let (status, reason, headers) = self.parse_response_headers(&headers_bytes)?;
+
+                let bodiless = head || matches!(status.as_u16(), 204 | 304);
+                if (flush || is_event_stream(&headers))
+                    && !bodiless
+                    && header(&headers, "Content-Length").is_none()
+                {
+                    let response = streamed_response(status, reason, headers, stream, buffer);
+                    return Ok((response, None));
+                }
+
+                let keep_alive = headers_bytes.starts_with(b"HTTP/1.1 ")
+                    && !header(&headers, "Connection")
+                        .is_some_and(|value| value.to_ascii_lowercase().contains("close"));
+
+                // Read body based on Content-Length or chunked framing
+                let (body, delimited) = if bodiless {
+                    (Vec::new(), true)
+                } else {
+                    self.read_response_body(&mut stream, &mut buffer, &headers, timeouts.idle)
+                        .await?
+                };
+
+                // Build final response with body, keeping the backend's reason phrase
+                let mut response = Response::new(status);
+                if let Some(reason) = reason {
+                    response = response.reason(reason);
+                }
+                let response = response.with_headers(headers).with_body(body).build();
+
+                // Anything after the response would confuse the next one
+                let reusable = keep_alive && delimited && buffer.is_empty();
+                return Ok((response, reusable.then_some(stream)));
             }
 
             // Prevent unbounded header growth
@@ -277,11 +985,18 @@ impl ProxyHandler {
         }
     }
 
-    /// Parse response headers
-    fn parse_response_headers(&self, headers_bytes: &[u8]) -> Result<(StatusCode, std::collections::HashMap<String, String>)> {
-        let headers_str = std::str::from_utf8(headers_bytes)
-            .context("Invalid UTF-8 in response headers")?;
-        
+    /// Parse the status line and headers of a response
+    fn parse_response_headers(
+        &self,
+        headers_bytes: &[u8],
+    ) -> Result<(
+        StatusCode,
+        Option<String>,
+        std::collections::HashMap<String, String>,
+    )> {
+        let headers_str =
+            std::str::from_utf8(headers_bytes).context("Invalid UTF-8 in response headers")?;
+
         let mut lines = headers_str.lines();
         
         // Parse status line
@@ -292,64 +1007,94 @@ impl ProxyHandler {
             anyhow::bail!("Invalid status line: {}", status_line);
         }
 
-        let status_code: u16 = parts[1].parse()
-            .context("Invalid status code")?;
-        
-        let status = match status_code {
-            200 => StatusCode::Ok,
-            201 => StatusCode::Created,
-            204 => StatusCode::NoContent,
-            400 => StatusCode::BadRequest,
-            404 => StatusCode::NotFound,
-            405 => StatusCode::MethodNotAllowed,
-            500..=599 => StatusCode::BadGateway, // Map all 5xx to BadGateway for now
-            _ => StatusCode::Ok, // Default fallback
-        };
+        let status_code: u16 = parts[1].parse().context("Invalid status code")?;
+        let status = StatusCode::from_u16(status_code)
+            .with_context(|| format!("Invalid status code {}", status_code))?;
+        let reason = parts
+            .get(2)
+            .map(|reason| reason.trim())
+            .filter(|reason| !reason.is_empty())
+            .map(str::to_string);
 
         // Parse headers
-        let mut headers = std::collections::HashMap::new();
+        let mut headers: HashMap<String, String> = HashMap::new();
         for line in lines {
             if line.is_empty() {
                 break;
             }
             
             if let Some((key, value)) = line.split_once(':') {
-                headers.insert(
-                    key.trim().to_string(),
-                    value.trim().to_string(),
-                );
+                let (key, value) = (key.trim(), value.trim());
+                // Cookies share one entry, each on its own line
+                if key.eq_ignore_ascii_case("Set-Cookie") {
+                    let existing = headers
+                        .iter_mut()
+                        .find(|(k, _)| k.eq_ignore_ascii_case(key));
+                    if let Some((_, cookies)) = existing {
+                        cookies.push(SET_COOKIE_SEPARATOR);
+                        cookies.push_str(value);
+                        continue;
+                    }
+                }
+                headers.insert(key.to_string(), value.to_string());
             }
         }
 
-        Ok((status, headers))
+        Ok((status, reason, headers))
     }
 
     /// Read response body based on Content-Length
-    async fn read_response_body(
+    ///
+    /// A chunked body is read, still chunked, through its last chunk; a
+    /// body with neither is read until the backend closes the connection.
+    /// Also returns whether the body's end was marked, so that the
+    /// connection could carry another response. Bytes read past the end
+    /// are left in `buffer`.
+    async fn read_response_body<S>(
         &self,
-        stream: &mut TcpStream,
+        stream: &mut S,
         buffer: &mut BytesMut,
         headers: &std::collections::HashMap<String, String>,
-    ) -> Result<Vec<u8>> {
+        idle: Option<Duration>,
+    ) -> Result<(Vec<u8>, bool)>
+    where
+        S: AsyncRead + Unpin,
+    {
         // Check Content-Length header
-        let content_length = if let Some(cl) = headers.get("Content-Length") {
+        let content_length = if let Some(cl) = header(headers, "Content-Length") {
             cl.parse::<usize>().unwrap_or(0)
+        } else if header(headers, "Transfer-Encoding")
+            .is_some_and(|value| value.to_ascii_lowercase().contains("chunked"))
+        {
+            let mut body = buffer.split().to_vec();
+            let mut chunks = ChunkedBody::default();
+            loop {
+                if let Some(end) = chunks.end(&body)? {
+                    buffer.extend_from_slice(&body[end..]);
+                    body.truncate(end);
+                    return Ok((body, true));
+                }
+                let n = read_within(idle, "Idle timeout", stream.read_buf(buffer)).await?;
+                if n == 0 {
+                    anyhow::bail!("Connection closed before the last chunk");
+                }
+                body.extend_from_slice(&buffer.split());
+            }
         } else {
             // No Content-Length, read until connection closes
-            let mut body = buffer.to_vec();
+            let mut body = buffer.split().to_vec();
             loop {
-                let n = stream.read_buf(buffer).await?;
+                let n = read_within(idle, "Idle timeout", stream.read_buf(buffer)).await?;
                 if n == 0 {
                     break;
                 }
-                body.extend_from_slice(&buffer[..n]);
-                buffer.clear();
+                body.extend_from_slice(&buffer.split());
             }
-            return Ok(body);
+            return Ok((body, false));
         };
 
         if content_length == 0 {
-            return Ok(Vec::new());
+            return Ok((Vec::new(), true));
         }
 
         let mut body = Vec::with_capacity(content_length);
@@ -365,16 +1110,17 @@ impl ProxyHandler {
             let to_read = remaining.min(BUFFER_SIZE);
             
             buffer.resize(to_read, 0);
-            let n = stream.read(&mut buffer[..to_read]).await?;
-            
+            let n = read_within(idle, "Idle timeout", stream.read(&mut buffer[..to_read])).await?;
+
             if n == 0 {
                 anyhow::bail!("Connection closed before complete body received");
             }
             
             body.extend_from_slice(&buffer[..n]);
+            buffer.clear();
         }
 
-        Ok(body)
+        Ok((body, true))
     }
 
     /// Handle proxy errors and return appropriate HTTP responses
@@ -382,27 +1128,360 @@ impl ProxyHandler {
         let error_str = error.to_string();
         
         // Determine appropriate status code based on error
-        let (status, body) = if error_str.contains("timeout") {
+        let (status, detail) = if error_str.contains("timeout") {
             (
                 StatusCode::GatewayTimeout,
-                b"504 Gateway Timeout\r\n\r\nThe backend server did not respond in time.".to_vec(),
+                "The backend server did not respond in time.",
             )
         } else if error_str.contains("No available backends") || error_str.contains("All available backends failed") {
-            (
-                StatusCode::ServiceUnavailable,
-                b"503 Service Unavailable\r\n\r\nNo backend servers are available.".to_vec(),
-            )
+            return Ok(self.unavailable_response());
         } else {
             (
                 StatusCode::BadGateway,
-                b"502 Bad Gateway\r\n\r\nFailed to connect to backend server.".to_vec(),
+                "Failed to connect to backend server.",
             )
         };
 
-        Ok(Response::new(status)
-            .with_header("Content-Type", "text/plain")
-            .with_header("Content-Length", &body.len().to_string())
-            .with_body(body)
-            .build())
+        Ok(Response::error(status, detail))
     }
+
+    /// 503 with a JSON body and, if configured, a `Retry-After` hint
+    fn unavailable_response(&self) -> Response {
+        let retry_after = self
+            .retry_after
+            .as_ref()
+            .map(|config| config.secs + rand::rng().random_range(0..=config.jitter_secs));
+        let detail = "No backend servers are available.";
+        let mut body = serde_json::json!({
+            "error": "service_unavailable",
+            "status": 503,
+            "message": detail,
+        });
+        if let Some(secs) = retry_after {
+            body["retry_after"] = secs.into();
+        }
+
+        let mut builder = ResponseBuilder::new(StatusCode::ServiceUnavailable)
+            .header("Content-Type", "application/json");
+        if let Some(secs) = retry_after {
+            builder = builder.header("Retry-After", secs.to_string());
+        }
+        let mut response = builder.body(body.to_string().into_bytes()).build();
+        response.generated = true;
+        response.detail = detail.to_string();
+        response
+    }
+}
+
+#[async_trait]
+impl Handler for ProxyHandler {
+    async fn handle(&self, mut req: Request) -> Response {
+        if self.has_looped(&req) {
+            tracing::warn!(path = %req.path, via = self.via, "Request looped back to the proxy");
+            self.metrics.increment("sentinel_proxy_loops_total", &[]);
+            return Response::error(
+                StatusCode::Other(508),
+                "The request has already passed through this proxy.",
+            );
+        }
+        let via = format!("{} {}", via_protocol(&req.version), self.via);
+        append_header(&mut req.headers, "Via", &via);
+
+        match self.forward_request(&req).await {
+            Ok(mut response) => {
+                tracing::debug!(status = response.status.as_u16(), "Proxy response received");
+                append_header(&mut response.headers, "Via", &via);
+                response
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "Proxy error");
+                // Error responses are already handled in forward_request
+                // This should not normally be reached
+                Response::internal_error()
+            }
+        }
+    }
+}
+
+/// Response for a request cancelled by shutdown, disconnect, or deadline
+///
+/// The client has usually gone away, so this mostly shows up in logs and
+/// metrics.
+fn cancelled_response() -> Response {
+    Response::error(StatusCode::ServiceUnavailable, "The request was cancelled.")
+}
+
+/// Scheme and host the client addressed, from `X-Forwarded-Proto` and `Host`
+fn external_origin(request: &Request) -> Option<String> {
+    let header = |name: &str| {
+        request
+            .headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.trim())
+    };
+    let host = request.host()?;
+    let scheme = header("X-Forwarded-Proto").unwrap_or("http");
+    Some(format!("{}://{}", scheme, host))
+}
+
+/// The path, query, and fragment of `value` if it is a URL on `backend_url`'s origin
+fn strip_origin(value: &str, backend_url: &url::Url) -> Option<String> {
+    let location = url::Url::parse(value).ok()?;
+    let same_origin = location.scheme() == backend_url.scheme()
+        && location.host_str() == backend_url.host_str()
+        && location.port_or_known_default() == backend_url.port_or_known_default();
+    same_origin.then(|| location[url::Position::BeforePath..].to_string())
+}
+
+/// Await a read, failing with `message` if it takes longer than `limit`
+async fn read_within(
+    limit: Option<Duration>,
+    message: &'static str,
+    read: impl Future<Output = std::io::Result<usize>>,
+) -> Result<usize> {
+    match limit {
+        Some(limit) => Ok(timeout(limit, read)
+            .await
+            .map_err(|_| anyhow::anyhow!(message))??),
+        None => Ok(read.await?),
+    }
+}
+
+/// Value of a response header, whatever the case of its name
+fn header<'a>(headers: &'a HashMap<String, String>, name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// Add `value` to the comma-separated list in header `name`, however the
+/// existing header is cased
+fn append_header(headers: &mut HashMap<String, String>, name: &str, value: &str) {
+    let existing = headers
+        .keys()
+        .find(|key| key.eq_ignore_ascii_case(name))
+        .cloned();
+    match existing.and_then(|key| headers.remove(&key)) {
+        Some(list) if !list.trim().is_empty() => {
+            headers.insert(name.to_string(), format!("{}, {}", list, value))
+        }
+        _ => headers.insert(name.to_string(), value.to_string()),
+    };
+}
+
+/// Protocol version as written in `Via`: `1.1` for `HTTP/1.1`, `2` for
+/// HTTP/2
+fn via_protocol(version: &str) -> &str {
+    match version.strip_prefix("HTTP/").unwrap_or(version) {
+        "2.0" => "2",
+        version => version,
+    }
+}
+
+/// Whether a response is a Server-Sent Events stream
+fn is_event_stream(headers: &HashMap<String, String>) -> bool {
+    header(headers, "Content-Type")
+        .is_some_and(|value| value.trim_start().starts_with("text/event-stream"))
+}
+
+/// A response whose body is forwarded to the client as it arrives
+///
+/// `buffer` holds body bytes already read with the head. A chunked body is
+/// decoded here; the client side re-frames it for its own connection.
+fn streamed_response<S>(
+    status: StatusCode,
+    reason: Option<String>,
+    mut headers: HashMap<String, String>,
+    stream: S,
+    buffer: BytesMut,
+) -> Response
+where
+    S: AsyncRead + Unpin + Send + 'static,
+{
+    let chunked = header(&headers, "Transfer-Encoding")
+        .is_some_and(|value| value.to_ascii_lowercase().contains("chunked"));
+    headers.retain(|key, _| {
+        !["Transfer-Encoding", "Content-Length", "Connection"]
+            .iter()
+            .any(|name| key.eq_ignore_ascii_case(name))
+    });
+
+    let (tx, rx) = mpsc::channel(STREAM_CHANNEL_SIZE);
+    tokio::spawn(async move {
+        if let Err(e) = forward_body(stream, buffer, chunked, tx).await {
+            tracing::debug!(error = %e, "Streamed response from backend ended early");
+        }
+    });
+
+    let mut response = Response::new(status);
+    if let Some(reason) = reason {
+        response = response.reason(reason);
+    }
+    let mut response = response.with_headers(headers).build();
+    response.headers.remove("Content-Length");
+    response.stream = Some(rx);
+    response
+}
+
+/// Send a response body to `tx` as it is read, until the backend finishes
+/// it or the client goes away
+async fn forward_body<S>(
+    mut stream: S,
+    mut buffer: BytesMut,
+    chunked: bool,
+    tx: mpsc::Sender<Bytes>,
+) -> Result<()>
+where
+    S: AsyncRead + Unpin,
+{
+    // Data bytes left in the current chunk, and whether its CRLF follows
+    let mut remaining = 0;
+    let mut chunk_end = false;
+
+    loop {
+        if !chunked {
+            if !buffer.is_empty() && tx.send(buffer.split().freeze()).await.is_err() {
+                return Ok(());
+            }
+        } else if remaining > 0 {
+            if !buffer.is_empty() {
+                let n = remaining.min(buffer.len());
+                remaining -= n;
+                chunk_end = remaining == 0;
+                if tx.send(buffer.split_to(n).freeze()).await.is_err() {
+                    return Ok(());
+                }
+                continue;
+            }
+        } else if chunk_end {
+            if buffer.len() >= 2 {
+                buffer.advance(2);
+                chunk_end = false;
+                continue;
+            }
+        } else if let Some(end) = buffer.windows(2).position(|w| w == b"\r\n") {
+            let line = std::str::from_utf8(&buffer[..end]).context("Invalid chunk size")?;
+            let size = line.split(';').next().unwrap_or_default().trim();
+            remaining = usize::from_str_radix(size, 16).context("Invalid chunk size")?;
+            buffer.advance(end + 2);
+            if remaining == 0 {
+                // Trailers, if any, are dropped
+                return Ok(());
+            }
+            continue;
+        }
+
+        let n = tokio::select! {
+            n = stream.read_buf(&mut buffer) => n?,
+            _ = tx.closed() => return Ok(()),
+        };
+        if n == 0 {
+            if chunked {
+                anyhow::bail!("Connection closed before the last chunk");
+            }
+            return Ok(());
+        }
+    }
+}
+
+/// Where a chunked body read so far ends
+#[derive(Debug, Default)]
+struct ChunkedBody {
+    /// Offset of the first chunk not yet seen in full
+    next: usize,
+}
+
+impl ChunkedBody {
+    /// Length of the body at the start of `data`, through its last chunk
+    /// and any trailers, once all of it has arrived
+    fn end(&mut self, data: &[u8]) -> Result<Option<usize>> {
+        loop {
+            let Some(line) = crlf(&data[self.next..]) else {
+                return Ok(None);
+            };
+            let size = std::str::from_utf8(&data[self.next..self.next + line])
+                .ok()
+                .and_then(|line| line.split(';').next())
+                .and_then(|size| usize::from_str_radix(size.trim(), 16).ok())
+                .context("Invalid chunk size")?;
+
+            let mut pos = self.next + line + 2;
+            if size == 0 {
+                // Trailers end with an empty line
+                while let Some(line) = crlf(&data[pos..]) {
+                    pos += line + 2;
+                    if line == 0 {
+                        return Ok(Some(pos));
+                    }
+                }
+                return Ok(None);
+            }
+            pos += size + 2;
+            if pos > data.len() {
+                return Ok(None);
+            }
+            self.next = pos;
+        }
+    }
+}
+
+/// Offset of the first CRLF in `data`
+fn crlf(data: &[u8]) -> Option<usize> {
+    data.windows(2).position(|w| w == b"\r\n")
+}
+
+/// A duration in milliseconds, with 0 meaning no limit
+fn optional_millis(ms: u64) -> Option<Duration> {
+    (ms > 0).then(|| Duration::from_millis(ms))
+}
+
+/// Check a request against a routing rule's path prefix and headers
+///
+/// Header names are compared case-insensitively, values exactly.
+fn rule_matches(rule: &RoutingRule, request: &Request) -> bool {
+    let path = request.path.split('?').next().unwrap_or(&request.path);
+    if let Some(prefix) = &rule.path_prefix
+        && !path.starts_with(prefix.as_str())
+    {
+        return false;
+    }
+
+    rule.headers.iter().all(|(name, value)| {
+        request
+            .headers
+            .iter()
+            .any(|(k, v)| k.eq_ignore_ascii_case(name) && v == value)
+    })
+}
+
+/// Value of the consistent hashing key on a request, if present
+pub(crate) fn hash_key_value<'a>(key: &HashKey, request: &'a Request) -> Option<&'a str> {
+    match key {
+        HashKey::Path => request.path.split('?').next(),
+        HashKey::Header(name) => request
+            .headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str()),
+        HashKey::Cookie(name) => request.cookies().get(name.as_str()).copied(),
+    }
+}
+
+/// Parse a load report: a bare number, or `metric` from an ORCA text report
+///
+/// ORCA text reports look like `TEXT cpu_utilization=0.3, mem_utilization=0.5`.
+fn parse_load_report(value: &str, metric: &str) -> Option<f64> {
+    let value = value.trim();
+    let load = match value.strip_prefix("TEXT ") {
+        Some(pairs) => pairs
+            .split(',')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| key.trim() == metric)
+            .and_then(|(_, v)| v.trim().parse::<f64>().ok())?,
+        None => value.parse::<f64>().ok()?,
+    };
+
+    load.is_finite().then_some(load)
 }
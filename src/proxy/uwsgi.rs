@@ -0,0 +1,98 @@
+//! uwsgi protocol encoding
+//!
+//! Backends configured as `uwsgi://host:port` (e.g. uWSGI serving a Django or
+//! Flask app with `socket = :3031`) receive requests as a uwsgi packet: a
+//! 4-byte header followed by CGI-style variables, then the request body.
+//! The app server replies with a regular HTTP response, which is read like
+//! any other backend response.
+
+use crate::http::request::Request;
+use crate::http::util::percent_decode;
+use anyhow::Context;
+
+/// Largest variable block a uwsgi packet header can describe
+const MAX_VARS_SIZE: usize = u16::MAX as usize;
+
+/// Encode `request` as a uwsgi packet (modifier 0: WSGI request)
+///
+/// A body spooled to disk is not included; the caller streams it after the
+/// packet.
+pub fn encode_request(request: &Request, backend_url: &url::Url) -> anyhow::Result<Vec<u8>> {
+    let mut vars = Vec::new();
+
+    let uri = if request.path.is_empty() {
+        "/"
+    } else {
+        &request.path
+    };
+    let (path, query) = uri.split_once('?').unwrap_or((uri, ""));
+    let path_info = percent_decode(path).unwrap_or_else(|| path.to_string());
+
+    let host = request
+        .header("Host")
+        .map(str::to_string)
+        .or_else(|| backend_url.host_str().map(str::to_string))
+        .unwrap_or_default();
+    let (server_name, server_port) = match host.rsplit_once(':') {
+        Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => {
+            (name.to_string(), port.to_string())
+        }
+        _ => (host.clone(), "80".to_string()),
+    };
+
+    push_var(
+        &mut vars,
+        "REQUEST_METHOD",
+        &format!("{:?}", request.method),
+    )?;
+    push_var(&mut vars, "REQUEST_URI", uri)?;
+    push_var(&mut vars, "PATH_INFO", &path_info)?;
+    push_var(&mut vars, "QUERY_STRING", query)?;
+    push_var(&mut vars, "SCRIPT_NAME", "")?;
+    push_var(&mut vars, "SERVER_PROTOCOL", &request.version)?;
+    push_var(&mut vars, "SERVER_NAME", &server_name)?;
+    push_var(&mut vars, "SERVER_PORT", &server_port)?;
+    push_var(&mut vars, "CONTENT_LENGTH", &request.body_len().to_string())?;
+
+    for (key, value) in &request.headers {
+        let name = key.to_ascii_uppercase().replace('-', "_");
+        match name.as_str() {
+            "CONTENT_TYPE" => push_var(&mut vars, "CONTENT_TYPE", value)?,
+            // Set from the actual body above
+            "CONTENT_LENGTH" => {}
+            // Hop-by-hop headers are not forwarded
+            "CONNECTION" | "KEEP_ALIVE" | "PROXY_CONNECTION" | "TRANSFER_ENCODING" | "UPGRADE" => {}
+            _ => push_var(&mut vars, &format!("HTTP_{}", name), value)?,
+        }
+    }
+
+    if vars.len() > MAX_VARS_SIZE {
+        anyhow::bail!(
+            "uwsgi variables too large ({} bytes, limit {})",
+            vars.len(),
+            MAX_VARS_SIZE
+        );
+    }
+
+    let mut packet = Vec::with_capacity(4 + vars.len() + request.body.len());
+    packet.push(0); // modifier1: WSGI request
+    packet.extend_from_slice(&(vars.len() as u16).to_le_bytes());
+    packet.push(0); // modifier2
+    packet.extend_from_slice(&vars);
+    packet.extend_from_slice(&request.body);
+
+    Ok(packet)
+}
+
+/// Append a length-prefixed key/value pair
+fn push_var(vars: &mut Vec<u8>, key: &str, value: &str) -> anyhow::Result<()> {
+    let key_len = u16::try_from(key.len()).context("uwsgi variable name too long")?;
+    let value_len =
+        u16::try_from(value.len()).with_context(|| format!("uwsgi variable {} too long", key))?;
+
+    vars.extend_from_slice(&key_len.to_le_bytes());
+    vars.extend_from_slice(key.as_bytes());
+    vars.extend_from_slice(&value_len.to_le_bytes());
+    vars.extend_from_slice(value.as_bytes());
+    Ok(())
+}
@@ -0,0 +1,245 @@
+//! Classic Unix daemon mode
+//!
+//! For init scripts that expect a server to background itself: with
+//! `server.daemon` set, Sentinel forks twice, detaches from its terminal,
+//! writes a pidfile, and logs to a [`LogFile`] that is reopened on `SIGHUP`
+//! so logrotate can move it aside. The launching process waits until the
+//! pidfile is written and exits with status 0, or 1 with the error if the
+//! daemon could not start.
+//!
+//! Under systemd or another supervisor, leave daemon mode off and run in
+//! the foreground.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::fmt::writer::MutexGuardWriter;
+
+#[cfg(unix)]
+use crate::config::DaemonConfig;
+#[cfg(unix)]
+use anyhow::Context;
+
+/// Append-only log file that can be reopened after rotation
+///
+/// Clones share the same file, so a reopen is seen by every writer.
+#[derive(Clone)]
+pub struct LogFile {
+    path: PathBuf,
+    file: Arc<Mutex<File>>,
+}
+
+impl LogFile {
+    /// Open (or create) the log file for appending
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Arc::new(Mutex::new(Self::append(path)?)),
+        })
+    }
+
+    /// Switch to a freshly opened file at the same path
+    ///
+    /// On failure the current file is kept.
+    pub fn reopen(&self) -> io::Result<()> {
+        let file = Self::append(&self.path)?;
+        *self.file.lock().unwrap() = file;
+        Ok(())
+    }
+
+    /// Path of the log file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn append(path: &Path) -> io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogFile {
+    type Writer = MutexGuardWriter<'a, File>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.file.make_writer()
+    }
+}
+
+/// Pidfile holding the current process ID, removed when dropped
+#[cfg(unix)]
+pub struct PidFile {
+    path: PathBuf,
+    pid: u32,
+}
+
+#[cfg(unix)]
+impl PidFile {
+    /// Write the current process ID to `path`
+    ///
+    /// Fails if the file names a process that is still running; a stale
+    /// file is replaced.
+    pub fn create(path: &Path) -> anyhow::Result<Self> {
+        if let Some(pid) = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| contents.trim().parse::<i32>().ok())
+            && pid > 0
+            && process_alive(pid)
+        {
+            anyhow::bail!(
+                "Sentinel is already running with PID {} ({})",
+                pid,
+                path.display()
+            );
+        }
+
+        let pid = std::process::id();
+        std::fs::write(path, format!("{}\n", pid))
+            .with_context(|| format!("Failed to write pidfile {}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            pid,
+        })
+    }
+
+    /// Path of the pidfile
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(unix)]
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // Leave the file alone if another instance has since claimed it
+        let ours = std::fs::read_to_string(&self.path)
+            .is_ok_and(|contents| contents.trim() == self.pid.to_string());
+        if ours && let Err(e) = std::fs::remove_file(&self.path) {
+            tracing::warn!(path = %self.path.display(), error = %e, "Failed to remove pidfile");
+        }
+    }
+}
+
+/// A running daemon's pidfile and log
+///
+/// Keep it alive until shutdown; dropping it removes the pidfile.
+#[cfg(unix)]
+pub struct Daemon {
+    pid_file: PidFile,
+    log_file: Option<LogFile>,
+}
+
+#[cfg(unix)]
+impl Daemon {
+    /// The daemon's pidfile
+    pub fn pid_file(&self) -> &PidFile {
+        &self.pid_file
+    }
+
+    /// The daemon's log file, if one is configured
+    pub fn log_file(&self) -> Option<&LogFile> {
+        self.log_file.as_ref()
+    }
+}
+
+/// Detach into the background
+///
+/// Must be called before any other thread is started, in particular before
+/// the tokio runtime, since only the calling thread survives `fork`. Returns
+/// only in the daemon; the launching process exits here.
+#[cfg(unix)]
+pub fn daemonize(config: &DaemonConfig) -> anyhow::Result<Daemon> {
+    use std::io::{Read, Write};
+    use std::os::fd::{AsRawFd, FromRawFd};
+
+    if config.pid_file.as_os_str().is_empty() {
+        anyhow::bail!("Daemon mode requires a pid_file");
+    }
+    // Opened up front so a bad path is reported on the terminal
+    let log_file = config
+        .log_file
+        .as_deref()
+        .map(|path| {
+            LogFile::open(path)
+                .with_context(|| format!("Failed to open log file {}", path.display()))
+        })
+        .transpose()?;
+
+    let mut fds = [0; 2];
+    // SAFETY: fds has room for the two descriptors pipe writes
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error()).context("Failed to create daemon pipe");
+    }
+    // SAFETY: pipe just returned these descriptors and nothing else owns them
+    let (mut ready_rx, mut ready_tx) =
+        unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+    // SAFETY: no other threads exist yet (see above)
+    match unsafe { libc::fork() } {
+        -1 => return Err(io::Error::last_os_error()).context("Failed to fork"),
+        0 => drop(ready_rx),
+        _ => {
+            // Wait for the daemon to report in, then hand back the shell
+            drop(ready_tx);
+            let mut status = String::new();
+            let _ = ready_rx.read_to_string(&mut status);
+            if status == "ok" {
+                std::process::exit(0);
+            }
+            if status.is_empty() {
+                status = "daemon exited during startup".to_string();
+            }
+            eprintln!("Error: {}", status);
+            std::process::exit(1);
+        }
+    }
+
+    // First child: leave the terminal's session, then fork again so the
+    // daemon can never reacquire a controlling terminal
+    // SAFETY: setsid has no memory-safety preconditions
+    unsafe { libc::setsid() };
+    // SAFETY: still single-threaded
+    match unsafe { libc::fork() } {
+        -1 => {
+            let _ = write!(ready_tx, "Failed to fork: {}", io::Error::last_os_error());
+            std::process::exit(1);
+        }
+        0 => {}
+        // SAFETY: _exit skips destructors that belong to the daemon
+        _ => unsafe { libc::_exit(0) },
+    }
+
+    let started = PidFile::create(&config.pid_file).and_then(|pid_file| {
+        let null = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/null")
+            .context("Failed to open /dev/null")?;
+        for fd in 0..=2 {
+            // SAFETY: both descriptors are open; dup2 replaces the standard stream
+            if unsafe { libc::dup2(null.as_raw_fd(), fd) } == -1 {
+                return Err(io::Error::last_os_error()).context("Failed to redirect stdio");
+            }
+        }
+        Ok(pid_file)
+    });
+
+    match started {
+        Ok(pid_file) => {
+            let _ = ready_tx.write_all(b"ok");
+            Ok(Daemon { pid_file, log_file })
+        }
+        Err(e) => {
+            let _ = write!(ready_tx, "{:#}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn process_alive(pid: i32) -> bool {
+    // SAFETY: signal 0 only checks that the process exists
+    let rc = unsafe { libc::kill(pid, 0) };
+    rc == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
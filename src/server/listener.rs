@@ -1,58 +1,1306 @@
-use crate::config::Config;
+use crate::admin::AdminApi;
+use crate::config::{
+    BackendConfig, BotAction, ClientCertRequirement, Config, LoadBalancing, MetricsListener,
+    ProxyConfig, RedirectConfig, RouteTarget, UpstreamConfig,
+};
+use crate::discovery;
+use crate::events::{Event, Events};
+#[cfg(not(feature = "hyper-engine"))]
+use crate::http::capture::MalformedCapture;
+#[cfg(not(feature = "hyper-engine"))]
 use crate::http::connection::Connection;
-use crate::proxy::{BackendPool, ProxyHandler};
+use crate::http::error_pages::{ErrorPageHandler, ErrorPageRenderer};
+use crate::http::handler::{Handler, handler_fn};
+use crate::http::har::HarRecorder;
+#[cfg(feature = "hyper-engine")]
+use crate::http::hyper_engine::HyperConnection;
+use crate::http::parser::HeadLimits;
+#[cfg(not(feature = "hyper-engine"))]
+use crate::http::parser::ParseMode;
+use crate::http::response::Response;
+use crate::http::router::Router;
+use crate::http::spool::BodySpool;
+use crate::http::static_files::StaticFileHandler;
+use crate::http::static_response::StaticResponse;
+use crate::http::webdav::WebDavHandler;
+use crate::http::writer::ServerIdentity;
+use crate::metrics::{Metrics, MetricsEndpoint, PrometheusRecorder, RouteMetrics};
+use crate::middleware::{
+    BotHandler, ChaosHandler, ClientCertPolicy, Cors, FingerprintFilter, ForwardProxyHandler,
+    HeaderRewrite, IdempotencyHandler, Middleware, MiddlewareChain, PolicyHandler, RedirectHandler,
+    RewriteHandler, SecurityHeaders, SloHandler, SloTracker, TrafficCaptureHandler,
+};
+use crate::proxy::balancer::Random;
+use crate::proxy::{
+    BackendPool, BackendPools, BlueGreen, DynamicRoutes, Experiment, HealthChecker,
+    HealthThresholds, MaintenanceScheduler, Mirror, ProxyHandler, Resolver, TrafficSplit,
+    UpstreamTimeouts,
+};
+use crate::tls::{self, ClientCertificate, ClientHelloRecorder, TlsFingerprint};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpListener;
-use tracing::info;
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::{info, warn};
 
+/// Run the server using only the routes derived from configuration
 pub async fn run(cfg: &Config) -> anyhow::Result<()> {
-    let listener = TcpListener::bind(&cfg.server.listen_addr).await?;
-    info!("Listening on {}", cfg.server.listen_addr);
+    Server::new(cfg.clone()).run().await
+}
+
+/// Run the server with additional custom routes
+///
+/// Routes registered on `router` take precedence. Unless the router already
+/// has a fallback, unmatched requests go to the reverse proxy (if configured)
+/// or to static file serving.
+pub async fn run_with_router(cfg: &Config, router: Router) -> anyhow::Result<()> {
+    Server::new(cfg.clone()).router(router).run().await
+}
+
+/// Embeddable Sentinel server
+///
+/// # Example
+///
+/// ```ignore
+/// let events = Events::new();
+/// events.add_subscriber(Arc::new(MyAuditLog));
+///
+/// Server::new(Config::load())
+///     .router(Router::new().route("/internal/feature-flags", flags))
+///     .events(events)
+///     .run()
+///     .await?;
+/// ```
+pub struct Server {
+    config: Config,
+    router: Router,
+    events: Events,
+    metrics: Metrics,
+    shutdown: CancellationToken,
+    drain_timeout: Duration,
+    middlewares: Vec<Arc<dyn Middleware>>,
+}
+
+impl Server {
+    /// Create a server from configuration
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            router: Router::new(),
+            events: Events::new(),
+            metrics: Metrics::default(),
+            shutdown: CancellationToken::new(),
+            drain_timeout: Duration::ZERO,
+            middlewares: Vec::new(),
+        }
+    }
+
+    /// Add custom routes, which take precedence over configured handling
+    pub fn router(mut self, router: Router) -> Self {
+        self.router = router;
+        self
+    }
+
+    /// Publish lifecycle events to the given event bus
+    pub fn events(mut self, events: Events) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Record metrics through the given recorder
+    pub fn metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Stop accepting connections and cancel in-flight requests when `token` is cancelled
+    pub fn shutdown(mut self, token: CancellationToken) -> Self {
+        self.shutdown = token;
+        self
+    }
+
+    /// On shutdown, let in-flight requests finish for up to `timeout`
+    /// before cancelling them
+    ///
+    /// Idle connections are closed at once, and busy ones after their
+    /// current response. `run` returns when all have closed or the timeout
+    /// passes. Zero (the default) cancels in-flight requests immediately.
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// Run `middleware` for every request, after those already added and
+    /// around the configured handling
+    pub fn middleware(mut self, middleware: impl Middleware) -> Self {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
+
+    /// Bind the listener and serve connections until shutdown or an error occurs
+    pub async fn run(mut self) -> anyhow::Result<()> {
+        let cfg = &self.config;
+        let trusted = Arc::new(cfg.server.trusted_proxies()?);
+        let listener = TcpListener::bind(&cfg.server.listen_addr).await?;
+        info!("Listening on {}", cfg.server.listen_addr);
+
+        // Recorded alongside any recorder the embedder set
+        let prometheus = match &cfg.metrics {
+            Some(config) => {
+                config.validate()?;
+                if config.listener == MetricsListener::Admin && cfg.admin.is_none() {
+                    anyhow::bail!(
+                        "Metrics are served on the admin listener, which is not configured"
+                    );
+                }
+                let recorder = Arc::new(if config.buckets.is_empty() {
+                    PrometheusRecorder::new()
+                } else {
+                    PrometheusRecorder::with_buckets(config.buckets.clone())
+                });
+                self.metrics = self.metrics.clone().with_recorder(recorder.clone());
+                match config.listener {
+                    MetricsListener::Main => {
+                        info!(path = %config.path, "Serving Prometheus metrics")
+                    }
+                    MetricsListener::Admin => {
+                        info!("Serving Prometheus metrics on the admin listener")
+                    }
+                }
+                Some((config.listener, recorder))
+            }
+            None => None,
+        };
+
+        // Fail startup on unreadable CA bundles rather than per request
+        if let Some(tls) = cfg.proxy.as_ref().and_then(|proxy| proxy.tls.as_ref()) {
+            tls::client_config(tls)?;
+        }
+        let pools = BackendPools::default();
+        let proxy_handler =
+            build_proxy_handler(cfg, &self.events, &self.metrics, &self.shutdown, &pools)?;
+        let deployments =
+            build_deployments(cfg, &self.events, &self.metrics, &self.shutdown, &pools)?;
+        let mut router = self.router;
+        if let (Some(config), Some((MetricsListener::Main, recorder))) = (&cfg.metrics, &prometheus)
+        {
+            router = router.route(config.path.clone(), MetricsEndpoint::new(recorder.clone()));
+        }
+        for route in &cfg.static_responses {
+            router = router.route(
+                route.path.clone(),
+                StaticResponse::from_config(route, &cfg.static_files.root)?,
+            );
+        }
+        for mount in &cfg.webdav {
+            mount.validate()?;
+            let share = Arc::new(WebDavHandler::new(mount.clone()));
+            router = router
+                .route(mount.prefix.clone(), share.clone())
+                .route_prefix(format!("{}/", mount.prefix), share);
+            info!(
+                "Sharing {} over WebDAV at {}",
+                mount.root.display(),
+                mount.prefix
+            );
+        }
+        for (prefix, deployment) in &deployments {
+            router = router.route_prefix(prefix.clone(), deployment.clone());
+        }
+        let mut router = add_routes(
+            router,
+            cfg,
+            &self.events,
+            &self.metrics,
+            &self.shutdown,
+            &pools,
+        )?;
+        if let Some(default) = &proxy_handler {
+            for (prefix, experiment) in build_experiments(
+                cfg,
+                default,
+                &self.events,
+                &self.metrics,
+                &self.shutdown,
+                &pools,
+            )? {
+                router = router.route_prefix(prefix, experiment);
+            }
+        }
+
+        let acceptor = match &cfg.server.tls {
+            Some(config) => {
+                info!(
+                    cert = %config.cert_file.display(),
+                    sni_certificates = config.certificates.len(),
+                    reload_interval_secs = config.reload_interval_secs,
+                    versions = %format!("{}-{}", config.min_version, config.max_version),
+                    alpn = %config.alpn.join(","),
+                    "Terminating TLS"
+                );
+                #[cfg(not(feature = "hyper-engine"))]
+                if config.alpn.iter().any(|protocol| protocol == "h2") {
+                    anyhow::bail!(
+                        "TLS alpn h2 needs the hyper-engine feature; the built-in engine only speaks HTTP/1.1"
+                    );
+                }
+                let resolver = Arc::new(tls::CertResolver::load(config)?);
+                let reloader = Arc::new(
+                    tls::CertReloader::new(config, resolver.clone())
+                        .with_events(self.events.clone())
+                        .with_metrics(self.metrics.clone()),
+                );
+                if config.ocsp.is_some() {
+                    reloader.start_ocsp(&self.shutdown)?;
+                }
+                tokio::spawn(reloader.clone().run(self.shutdown.child_token()));
+                let acceptor = TlsAcceptor::from(tls::server_config_with(config, resolver)?);
+                Some((acceptor, reloader))
+            }
+            None => None,
+        };
+
+        if let Some(admin) = &cfg.admin {
+            let admin_listener = TcpListener::bind(&admin.listen_addr).await?;
+            info!("Admin API listening on {}", admin.listen_addr);
+            let mut api = AdminApi::new();
+            if let Some(token) = &admin.token {
+                api = api.with_token(token.clone());
+            }
+            for (_, deployment) in &deployments {
+                api = api.deployment(deployment.clone());
+            }
+            api = api.pools(pools.clone());
+            if let Some((_, reloader)) = &acceptor {
+                api = api.tls(reloader.clone());
+            }
+            if let Some((MetricsListener::Admin, recorder)) = &prometheus {
+                api = api.metrics(MetricsEndpoint::new(recorder.clone()));
+            }
+            tokio::spawn(api.serve(admin_listener, self.shutdown.child_token()));
+        }
+
+        let router = if router.has_fallback() {
+            router
+        } else if let Some(proxy_handler) = proxy_handler {
+            router.fallback(proxy_handler)
+        } else {
+            if let Some(images) = &cfg.static_files.images {
+                images.validate()?;
+            }
+            router.fallback(StaticFileHandler::new(cfg.static_files.clone()))
+        };
+        let handler: Arc<dyn Handler> = match &cfg.redirects {
+            Some(redirects) => {
+                redirects.validate()?;
+                Arc::new(
+                    RedirectHandler::new(router, redirects.clone())
+                        .with_tls(cfg.server.tls.is_some())
+                        .with_trusted_proxies(trusted.clone()),
+                )
+            }
+            None => Arc::new(router),
+        };
+        let handler: Arc<dyn Handler> = match &cfg.idempotency {
+            Some(idempotency) if idempotency.enabled => {
+                idempotency.validate()?;
+                info!(
+                    routes = idempotency.routes.len(),
+                    ttl_secs = idempotency.ttl_secs,
+                    "Idempotency-Key deduplication is enabled"
+                );
+                Arc::new(
+                    IdempotencyHandler::new(handler, idempotency.clone())
+                        .with_metrics(self.metrics.clone()),
+                )
+            }
+            _ => handler,
+        };
+        let handler: Arc<dyn Handler> = match &cfg.forward_proxy {
+            Some(forward) if forward.enabled => {
+                forward.validate()?;
+                info!(
+                    allowed = forward.allow.len(),
+                    auth = !forward.users.is_empty(),
+                    "Forward proxy (CONNECT) is enabled"
+                );
+                Arc::new(
+                    ForwardProxyHandler::new(handler, forward.clone())
+                        .with_metrics(self.metrics.clone()),
+                )
+            }
+            _ => handler,
+        };
+        let handler: Arc<dyn Handler> = match &cfg.chaos {
+            Some(chaos) if chaos.enabled => {
+                chaos.validate()?;
+                warn!(
+                    rules = chaos.rules.len(),
+                    "Chaos fault injection is enabled"
+                );
+                Arc::new(ChaosHandler::new(handler, chaos.clone()))
+            }
+            _ => handler,
+        };
+        let handler: Arc<dyn Handler> = match &cfg.fingerprints {
+            Some(rules) => {
+                rules.validate()?;
+                if cfg.server.tls.is_none() {
+                    warn!(
+                        "Fingerprint rules only apply to TLS connections, and TLS is not enabled"
+                    );
+                }
+                info!(
+                    allow = rules.allow.len(),
+                    deny = rules.deny.len(),
+                    "TLS fingerprint rules are enabled"
+                );
+                Arc::new(
+                    FingerprintFilter::new(handler, rules.clone())
+                        .with_metrics(self.metrics.clone()),
+                )
+            }
+            None => handler,
+        };
+        let handler: Arc<dyn Handler> = match &cfg.policy {
+            Some(policy) => {
+                let policy_handler =
+                    PolicyHandler::from_config(handler, policy)?.with_metrics(self.metrics.clone());
+                info!(
+                    engine = policy_handler.engine(),
+                    fail_open = policy.fail_open,
+                    "Policy authorization is enabled"
+                );
+                Arc::new(policy_handler)
+            }
+            None => handler,
+        };
+        let handler: Arc<dyn Handler> = match &cfg.bots {
+            Some(bots) if !bots.rules.is_empty() => {
+                bots.validate()?;
+                let mut bot_handler =
+                    BotHandler::new(handler, bots.clone()).with_metrics(self.metrics.clone());
+                for rule in bots.rules.iter().filter(|r| r.action == BotAction::Route) {
+                    let Some(proxy_config) = &cfg.proxy else {
+                        anyhow::bail!(
+                            "Bot rule {} routes to backends but no proxy is configured",
+                            rule.name
+                        );
+                    };
+                    let pool = build_pool(
+                        proxy_config,
+                        rule.backends.clone(),
+                        &self.events,
+                        &self.metrics,
+                        &self.shutdown,
+                        &pools,
+                    )?;
+                    bot_handler = bot_handler
+                        .with_pool(&rule.name, build_proxy(proxy_config, pool, &self.metrics));
+                }
+                info!(rules = bots.rules.len(), "Bot rules are enabled");
+                Arc::new(bot_handler)
+            }
+            _ => handler,
+        };
+        cfg.static_files.error_pages.validate()?;
+        let intercepts = cfg
+            .proxy
+            .as_ref()
+            .map(|proxy| proxy.intercept_errors.clone())
+            .unwrap_or_default();
+        let handler: Arc<dyn Handler> = Arc::new(
+            ErrorPageHandler::new(
+                handler,
+                Arc::new(ErrorPageRenderer::load(
+                    &cfg.static_files.root,
+                    &cfg.static_files.error_pages,
+                )),
+            )
+            .with_intercepts(intercepts),
+        );
+        // Outside the error pages so generated errors get the headers too
+        let handler: Arc<dyn Handler> = match &cfg.security_headers {
+            Some(security) => {
+                security.validate()?;
+                Arc::new(
+                    MiddlewareChain::new(handler).with(
+                        SecurityHeaders::new(security)
+                            .with_tls(cfg.server.tls.is_some())
+                            .with_trusted_proxies(trusted.clone()),
+                    ),
+                )
+            }
+            None => handler,
+        };
+        let handler: Arc<dyn Handler> = if cfg.slos.is_empty() {
+            handler
+        } else {
+            for slo in &cfg.slos {
+                slo.validate()?;
+            }
+            let tracker = Arc::new(SloTracker::new(&cfg.slos).with_metrics(self.metrics.clone()));
+            tokio::spawn(tracker.clone().run(self.shutdown.child_token()));
+            Arc::new(SloHandler::new(handler, tracker))
+        };
+        let handler: Arc<dyn Handler> = if self.middlewares.is_empty() {
+            handler
+        } else {
+            info!(
+                middlewares = self.middlewares.len(),
+                "Running registered middlewares"
+            );
+            Arc::new(MiddlewareChain::new(handler).with_all(self.middlewares.iter().cloned()))
+        };
+        // Client certificate headers come from the handshake, never from
+        // the client: they are set before any other middleware runs, for
+        // every route
+        let handler: Arc<dyn Handler> =
+            match cfg.server.tls.as_ref().and_then(|t| t.client_auth.as_ref()) {
+                Some(client_auth) => {
+                    info!(
+                        ca = %client_auth.ca_file.display(),
+                        mode = ?client_auth.mode,
+                        "Verifying client certificates"
+                    );
+                    Arc::new(
+                        MiddlewareChain::new(handler).with(
+                            ClientCertPolicy::new(ClientCertRequirement::Off)
+                                .with_headers(client_auth.headers.clone()),
+                        ),
+                    )
+                }
+                None => handler,
+            };
+        let traffic = match &cfg.traffic_capture {
+            Some(capture) => {
+                let recorder = Arc::new(HarRecorder::new(capture)?);
+                info!(
+                    dir = %capture.dir.display(),
+                    sample_percent = capture.sample_percent,
+                    bodies = capture.bodies,
+                    "Capturing sampled traffic to HAR files"
+                );
+                tokio::spawn(recorder.clone().run(self.shutdown.child_token()));
+                Some(recorder)
+            }
+            None => None,
+        };
+        let handler: Arc<dyn Handler> = match &traffic {
+            Some(recorder) => Arc::new(
+                TrafficCaptureHandler::new(handler, recorder.clone())
+                    .with_tls(cfg.server.tls.is_some())
+                    .with_metrics(self.metrics.clone()),
+            ),
+            None => handler,
+        };
+
+        let request_timeout = cfg.server.request_timeout_ms.map(Duration::from_millis);
+        if cfg.server.max_requests_per_connection == Some(0) {
+            anyhow::bail!("server.max_requests_per_connection must be at least 1");
+        }
+        let limits = cfg.server.head_limits();
+        if limits.max_header_bytes == 0 || limits.max_headers == 0 || limits.max_uri_length == 0 {
+            anyhow::bail!(
+                "server.max_header_bytes, max_headers, and max_uri_length must be at least 1"
+            );
+        }
+        let spool = match &cfg.server.request_body {
+            Some(body) => {
+                body.validate()?;
+                let spool = BodySpool::new(body)?;
+                info!(
+                    memory_limit_bytes = body.memory_limit_bytes,
+                    max_bytes = body.max_bytes,
+                    "Spooling large request bodies to disk"
+                );
+                Some(Arc::new(spool))
+            }
+            None => None,
+        };
+        #[cfg(feature = "hyper-engine")]
+        if cfg.server.malformed_capture.is_some() {
+            warn!("Malformed request capture is not supported by the hyper engine");
+        }
+        #[cfg(feature = "hyper-engine")]
+        if cfg.server.lenient_parsing {
+            warn!("Lenient parsing is not supported by the hyper engine");
+        }
+        #[cfg(not(feature = "hyper-engine"))]
+        let capture = match &cfg.server.malformed_capture {
+            Some(capture) => {
+                info!(path = %capture.path.display(), "Capturing malformed requests");
+                Some(Arc::new(MalformedCapture::new(capture)?))
+            }
+            None => None,
+        };
+
+        let acceptor = acceptor.map(|(acceptor, _)| acceptor);
+
+        let serving = Serving {
+            handler,
+            events: self.events.clone(),
+            metrics: self.metrics.clone(),
+            request_timeout,
+            max_requests: cfg.server.max_requests_per_connection,
+            limits,
+            identity: Arc::new(cfg.server.server_identity()?),
+            #[cfg(not(feature = "hyper-engine"))]
+            parse_mode: cfg.server.parse_mode(),
+            spool,
+            #[cfg(not(feature = "hyper-engine"))]
+            capture,
+        };
+
+        // Connections are cancelled only once draining is over
+        let connections = TaskTracker::new();
+        let abort = CancellationToken::new();
+        loop {
+            let (socket, peer) = tokio::select! {
+                accepted = listener.accept() => accepted?,
+                _ = self.shutdown.cancelled() => break,
+            };
+            info!("Accepted connection from {}", peer);
+            self.events.emit(Event::ConnectionAccepted { peer });
+
+            let serving = serving.clone();
+            let acceptor = acceptor.clone();
+            let cancel = abort.child_token();
+            let drain = self.shutdown.clone();
+
+            connections.spawn(async move {
+                let events = serving.events.clone();
+                let result = match acceptor {
+                    Some(acceptor) => {
+                        match acceptor.accept(ClientHelloRecorder::new(socket)).await {
+                            Ok(mut stream) => {
+                                let fingerprint = stream.get_mut().0.take_fingerprint();
+                                if let Some(fingerprint) = &fingerprint {
+                                    tracing::debug!(
+                                        %peer,
+                                        ja3 = %fingerprint.ja3,
+                                        ja4 = %fingerprint.ja4,
+                                        "TLS client fingerprinted"
+                                    );
+                                }
+                                let client_cert = stream
+                                    .get_ref()
+                                    .1
+                                    .peer_certificates()
+                                    .and_then(|certs| certs.first())
+                                    .map(|cert| Arc::new(ClientCertificate::from_der(cert)));
+                                let tls = TlsInfo {
+                                    fingerprint: fingerprint.map(Arc::new),
+                                    client_cert,
+                                };
+                                serving.serve(stream, peer, tls, cancel, drain).await
+                            }
+                            Err(e) => {
+                                tracing::debug!(%peer, error = %e, "TLS handshake failed");
+                                Ok(())
+                            }
+                        }
+                    }
+                    None => {
+                        serving
+                            .serve(socket, peer, TlsInfo::default(), cancel, drain)
+                            .await
+                    }
+                };
+
+                if let Err(e) = result {
+                    tracing::error!("Connection error from {}: {}", peer, e);
+                }
+
+                events.emit(Event::ConnectionClosed { peer });
+            });
+        }
+
+        info!("Shutdown requested, no longer accepting connections");
+        drop(listener);
+        // Written here too, as the process may exit before the recorder's
+        // own task runs
+        if let Some(recorder) = &traffic
+            && let Err(e) = recorder.flush()
+        {
+            warn!(error = %e, "Failed to write traffic capture");
+        }
+
+        connections.close();
+        if !self.drain_timeout.is_zero() && !connections.is_empty() {
+            info!(
+                connections = connections.len(),
+                timeout_secs = self.drain_timeout.as_secs_f64(),
+                "Waiting for in-flight requests to finish"
+            );
+            if tokio::time::timeout(self.drain_timeout, connections.wait())
+                .await
+                .is_err()
+            {
+                warn!(
+                    connections = connections.len(),
+                    "Drain timed out, cancelling the remaining requests"
+                );
+            }
+        }
+        abort.cancel();
+        Ok(())
+    }
+}
+
+/// What every accepted connection is served with
+#[derive(Clone)]
+struct Serving {
+    handler: Arc<dyn Handler>,
+    events: Events,
+    metrics: Metrics,
+    request_timeout: Option<Duration>,
+    max_requests: Option<u64>,
+    limits: HeadLimits,
+    identity: Arc<ServerIdentity>,
+    #[cfg(not(feature = "hyper-engine"))]
+    parse_mode: ParseMode,
+    spool: Option<Arc<BodySpool>>,
+    #[cfg(not(feature = "hyper-engine"))]
+    capture: Option<Arc<MalformedCapture>>,
+}
+
+/// What the TLS handshake told us about the client
+#[derive(Default)]
+struct TlsInfo {
+    fingerprint: Option<Arc<TlsFingerprint>>,
+    client_cert: Option<Arc<ClientCertificate>>,
+}
+
+impl Serving {
+    /// Serve requests on `stream` (plain TCP or TLS) until it closes
+    async fn serve<S>(
+        self,
+        stream: S,
+        peer: SocketAddr,
+        tls: TlsInfo,
+        cancel: CancellationToken,
+        drain: CancellationToken,
+    ) -> anyhow::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        #[cfg(feature = "hyper-engine")]
+        {
+            let mut conn = HyperConnection::new(stream, self.handler)
+                .with_events(self.events)
+                .with_metrics(self.metrics)
+                .with_cancellation(cancel)
+                .with_drain(drain)
+                .with_peer(peer)
+                .with_head_limits(self.limits)
+                .with_server_identity(self.identity);
+            if let Some(timeout) = self.request_timeout {
+                conn = conn.with_request_timeout(timeout);
+            }
+            if let Some(max) = self.max_requests {
+                conn = conn.with_max_requests(max);
+            }
+            if let Some(fingerprint) = tls.fingerprint {
+                conn = conn.with_tls_fingerprint(fingerprint);
+            }
+            if let Some(cert) = tls.client_cert {
+                conn = conn.with_client_cert(cert);
+            }
+            if let Some(spool) = self.spool {
+                conn = conn.with_body_spool(spool);
+            }
+            conn.run().await
+        }
+
+        #[cfg(not(feature = "hyper-engine"))]
+        {
+            let mut conn = Connection::with_handler(stream, self.handler)
+                .with_events(self.events)
+                .with_metrics(self.metrics)
+                .with_cancellation(cancel)
+                .with_drain(drain)
+                .with_peer(peer)
+                .with_head_limits(self.limits)
+                .with_parse_mode(self.parse_mode)
+                .with_server_identity(self.identity);
+            if let Some(timeout) = self.request_timeout {
+                conn = conn.with_request_timeout(timeout);
+            }
+            if let Some(max) = self.max_requests {
+                conn = conn.with_max_requests(max);
+            }
+            if let Some(capture) = self.capture {
+                conn = conn.with_malformed_capture(capture);
+            }
+            if let Some(fingerprint) = tls.fingerprint {
+                conn = conn.with_tls_fingerprint(fingerprint);
+            }
+            if let Some(cert) = tls.client_cert {
+                conn = conn.with_client_cert(cert);
+            }
+            if let Some(spool) = self.spool {
+                conn = conn.with_body_spool(spool);
+            }
+            conn.run().await
+        }
+    }
+}
 
-    // Initialize proxy handler if configured
+/// Build the proxy handler from configuration, if a proxy section is present
+///
+/// Discovery watchers configured for the pool are started here and stop
+/// when `shutdown` is cancelled. With Docker discovery, the proxy sits
+/// behind [`DynamicRoutes`] so labelled containers can claim path prefixes.
+fn build_proxy_handler(
+    cfg: &Config,
+    events: &Events,
+    metrics: &Metrics,
+    shutdown: &CancellationToken,
+    pools: &BackendPools,
+) -> anyhow::Result<Option<Arc<dyn Handler>>> {
     let proxy_handler = if let Some(ref proxy_config) = cfg.proxy {
         // Validate backend configuration
         proxy_config.validate()?;
 
-        // Create backend pool
-        let pool = BackendPool::new(proxy_config.backends.clone());
-        
+        let pool = build_pool(
+            proxy_config,
+            proxy_config.backends.clone(),
+            events,
+            metrics,
+            shutdown,
+            pools,
+        )?;
+
         info!(
             backends = proxy_config.backends.len(),
             "Initialized backend pool"
         );
 
-        // Create proxy handler
-        let handler = ProxyHandler::new(
-            pool,
-            Duration::from_millis(proxy_config.connection_timeout_ms),
-            Duration::from_millis(proxy_config.request_timeout_ms),
-        );
+        let connection_timeout = Duration::from_millis(proxy_config.connection_timeout_ms);
+        let request_timeout = Duration::from_millis(proxy_config.request_timeout_ms);
+
+        let routes = proxy_config
+            .discovery
+            .as_ref()
+            .filter(|d| d.docker.is_some())
+            .map(|_| {
+                DynamicRoutes::new(connection_timeout, request_timeout)
+                    .with_timeouts(upstream_timeouts(proxy_config))
+                    .with_route_timeouts(proxy_config.route_timeouts.clone())
+                    .with_events(events.clone())
+                    .with_metrics(metrics.clone())
+            });
+
+        if let Some(discovery) = &proxy_config.discovery {
+            discovery::spawn_watchers(discovery, &pool, routes.as_ref(), shutdown.child_token());
+        }
+
+        let handler = build_proxy(proxy_config, pool.clone(), metrics);
 
-        Some(Arc::new(handler))
+        let mut handler: Arc<dyn Handler> = match routes {
+            Some(routes) => Arc::new(routes.fallback(handler)),
+            None => Arc::new(handler),
+        };
+
+        if let Some(mirror) = &proxy_config.mirror {
+            let pool = build_pool(
+                proxy_config,
+                mirror.backends.clone(),
+                events,
+                metrics,
+                shutdown,
+                pools,
+            )?;
+            let mut mirrored = Mirror::new(handler, build_proxy(proxy_config, pool, metrics))
+                .with_percent(mirror.percent)
+                .with_metrics(metrics.clone());
+            if let Some(compare) = &mirror.compare {
+                mirrored = mirrored.with_comparison(compare.clone());
+            }
+            info!(
+                backends = mirror.backends.len(),
+                percent = mirror.percent,
+                compare = mirror.compare.is_some(),
+                "Mirroring traffic to shadow backends"
+            );
+            handler = Arc::new(mirrored);
+        }
+        Some(handler)
     } else {
         info!("No proxy configuration found, serving static files only");
         None
     };
 
-    loop {
-        let (socket, peer) = listener.accept().await?;
-        info!("Accepted connection from {}", peer);
+    Ok(proxy_handler)
+}
+
+/// Build the blue-green deployments configured under `proxy`
+///
+/// Returns each deployment with the path prefix it serves.
+fn build_deployments(
+    cfg: &Config,
+    events: &Events,
+    metrics: &Metrics,
+    shutdown: &CancellationToken,
+    pools: &BackendPools,
+) -> anyhow::Result<Vec<(String, Arc<BlueGreen>)>> {
+    let Some(proxy_config) = &cfg.proxy else {
+        return Ok(Vec::new());
+    };
+    proxy_config.validate()?;
 
-        let static_config = cfg.static_files.clone();
-        let proxy = proxy_handler.clone();
+    let deployments = proxy_config
+        .blue_green
+        .iter()
+        .map(|deployment| {
+            let [blue, green] = [&deployment.blue, &deployment.green].map(|backends| {
+                let pool = build_pool(
+                    proxy_config,
+                    backends.clone(),
+                    events,
+                    metrics,
+                    shutdown,
+                    pools,
+                )?;
+                anyhow::Ok(build_proxy(proxy_config, pool, metrics))
+            });
+            let (blue, green) = (blue?, green?);
+            info!(
+                deployment = %deployment.name,
+                prefix = %deployment.path_prefix,
+                active = deployment.active.as_str(),
+                "Initialized blue-green deployment"
+            );
+            let handler = BlueGreen::new(deployment.name.clone(), blue, green, deployment.active)
+                .with_preview_header(deployment.preview_header.clone())
+                .with_metrics(metrics.clone());
+            Ok((deployment.path_prefix.clone(), Arc::new(handler)))
+        })
+        .collect::<anyhow::Result<_>>()?;
 
-        tokio::spawn(async move {
-            let mut conn = if let Some(proxy_handler) = proxy {
-                Connection::with_proxy(socket, static_config, proxy_handler)
-            } else {
-                Connection::new(socket, static_config)
+    Ok(deployments)
+}
+
+/// Register the configured `routes` on `router`
+///
+/// Routes naming the same upstream share its pool, which is built on first
+/// use; a route with its own backends gets a pool of its own.
+fn add_routes(
+    mut router: Router,
+    cfg: &Config,
+    events: &Events,
+    metrics: &Metrics,
+    shutdown: &CancellationToken,
+    pools: &BackendPools,
+) -> anyhow::Result<Router> {
+    let trusted = Arc::new(cfg.server.trusted_proxies()?);
+    let mut upstreams: HashMap<&str, BackendPool> = HashMap::new();
+    let mut prefixes = std::collections::HashSet::new();
+    let client_auth = cfg
+        .server
+        .tls
+        .as_ref()
+        .and_then(|tls| tls.client_auth.as_ref());
+    for route in &cfg.routes {
+        route.validate()?;
+        let predicate = route.predicate()?;
+        if let Some(prefix) = &route.prefix
+            && predicate.is_none()
+            && !prefixes.insert(prefix.as_str())
+        {
+            anyhow::bail!(
+                "Route prefix {} is configured more than once without conditions",
+                prefix
+            );
+        }
+
+        let handler: Arc<dyn Handler> = match route.target()? {
+            RouteTarget::Static(dir) => {
+                let mut static_files = cfg.static_files.clone();
+                if let Some(dir) = dir {
+                    static_files.root = dir.to_path_buf();
+                }
+                info!(
+                    route = route.pattern(),
+                    root = %static_files.root.display(),
+                    "Serving route from static files"
+                );
+                Arc::new(StaticFileHandler::new(static_files))
+            }
+            RouteTarget::Redirect(redirect) => {
+                info!(
+                    route = route.pattern(),
+                    location = %redirect.location,
+                    "Redirecting route"
+                );
+                let config = RedirectConfig {
+                    rules: route.redirect_rule().into_iter().collect(),
+                    ..Default::default()
+                };
+                Arc::new(
+                    RedirectHandler::new(
+                        handler_fn(|_req| async { Response::not_found() }),
+                        config,
+                    )
+                    .with_tls(cfg.server.tls.is_some())
+                    .with_trusted_proxies(trusted.clone()),
+                )
+            }
+            RouteTarget::Respond(respond) => {
+                info!(
+                    route = route.pattern(),
+                    status = respond.status,
+                    "Answering route with a fixed response"
+                );
+                let response = route.static_response().expect("respond target");
+                Arc::new(StaticResponse::from_config(
+                    &response,
+                    &cfg.static_files.root,
+                )?)
+            }
+            RouteTarget::Proxy(name) => {
+                let Some(proxy_config) = &cfg.proxy else {
+                    anyhow::bail!(
+                        "Route {} has a backend pool but no proxy is configured",
+                        route.pattern()
+                    );
+                };
+                let upstream = proxy_config.upstreams.get_key_value(name);
+                let pool = match upstream {
+                    Some(_) if !route.backends.is_empty() => anyhow::bail!(
+                        "Route {} lists backends for pool {}, which is an upstream",
+                        route.pattern(),
+                        name
+                    ),
+                    Some((name, upstream)) => match upstreams.entry(name.as_str()) {
+                        Entry::Occupied(entry) => entry.get().clone(),
+                        Entry::Vacant(entry) => entry
+                            .insert(build_upstream(
+                                proxy_config,
+                                upstream,
+                                events,
+                                metrics,
+                                shutdown,
+                                pools,
+                            )?)
+                            .clone(),
+                    },
+                    None if route.backends.is_empty() => {
+                        anyhow::bail!("Route {} uses unknown upstream {}", route.pattern(), name)
+                    }
+                    None => build_pool(
+                        proxy_config,
+                        route.backends.clone(),
+                        events,
+                        metrics,
+                        shutdown,
+                        pools,
+                    )?,
+                };
+                info!(
+                    route = route.pattern(),
+                    pool = name,
+                    upstream = upstream.is_some(),
+                    "Routing requests to backend pool"
+                );
+                Arc::new(build_proxy(proxy_config, pool, metrics))
+            }
+            RouteTarget::Split(targets) => {
+                let Some(proxy_config) = &cfg.proxy else {
+                    anyhow::bail!(
+                        "Route {} splits between upstreams but no proxy is configured",
+                        route.pattern()
+                    );
+                };
+                let mut split = TrafficSplit::new(route.pattern()).with_metrics(metrics.clone());
+                if let Some(key) = &route.split_key {
+                    split = split.with_key(key.clone());
+                }
+                for target in targets {
+                    let Some((name, upstream)) = proxy_config.upstreams.get_key_value(&target.pool)
+                    else {
+                        anyhow::bail!(
+                            "Route {} uses unknown upstream {}",
+                            route.pattern(),
+                            target.pool
+                        );
+                    };
+                    let pool = match upstreams.entry(name.as_str()) {
+                        Entry::Occupied(entry) => entry.get().clone(),
+                        Entry::Vacant(entry) => entry
+                            .insert(build_upstream(
+                                proxy_config,
+                                upstream,
+                                events,
+                                metrics,
+                                shutdown,
+                                pools,
+                            )?)
+                            .clone(),
+                    };
+                    split = split.with_target(
+                        name.as_str(),
+                        target.weight,
+                        Arc::new(build_proxy(proxy_config, pool, metrics)),
+                    );
+                }
+                info!(
+                    route = route.pattern(),
+                    targets = targets.len(),
+                    "Splitting route between upstreams"
+                );
+                Arc::new(split)
+            }
+        };
+        let handler: Arc<dyn Handler> = match route.path_rewrite()? {
+            Some(rewrite) => Arc::new(RewriteHandler::new(handler, rewrite)),
+            None => handler,
+        };
+        // Client certificates are checked first and CORS then answers
+        // preflights; header rules run after security headers on the way
+        // out, so they can still override or remove them
+        let mut middlewares: Vec<Arc<dyn Middleware>> = Vec::new();
+        if route.client_cert != ClientCertRequirement::Off {
+            let Some(client_auth) = client_auth else {
+                anyhow::bail!(
+                    "Route {} needs client certificates but server.tls.client_auth is not configured",
+                    route.pattern()
+                );
             };
+            middlewares.push(Arc::new(
+                ClientCertPolicy::new(route.client_cert).with_headers(client_auth.headers.clone()),
+            ));
+        }
+        if let Some(cors) = &route.cors {
+            middlewares.push(Arc::new(Cors::new(cors.clone())));
+        }
+        if route.request_headers.is_some() || route.response_headers.is_some() {
+            middlewares.push(Arc::new(
+                HeaderRewrite::new(route.display_name())
+                    .with_request(route.request_headers.clone().unwrap_or_default())
+                    .with_response(route.response_headers.clone().unwrap_or_default()),
+            ));
+        }
+        if let Some(security) = &route.security_headers {
+            middlewares.push(Arc::new(
+                SecurityHeaders::new(security)
+                    .with_tls(cfg.server.tls.is_some())
+                    .with_trusted_proxies(trusted.clone()),
+            ));
+        }
+        let handler: Arc<dyn Handler> = if middlewares.is_empty() {
+            handler
+        } else {
+            Arc::new(MiddlewareChain::new(handler).with_all(middlewares))
+        };
+        let handler = RouteMetrics::new(handler, route.display_name(), metrics.clone());
+        router = match (route.regex()?, predicate) {
+            (Some(regex), Some(predicate)) => router.route_regex_when(regex, predicate, handler),
+            (Some(regex), None) => router.route_regex(regex, handler),
+            (None, Some(predicate)) => {
+                router.route_prefix_when(route.pattern().to_string(), predicate, handler)
+            }
+            (None, None) => router.route_prefix(route.pattern().to_string(), handler),
+        };
+    }
+    Ok(router)
+}
 
-            if let Err(e) = conn.run().await {
-                tracing::error!("Connection error from {}: {}", peer, e);
+/// Build the pool of a named upstream, with its own strategy and health
+/// checks where set
+fn build_upstream(
+    proxy_config: &ProxyConfig,
+    upstream: &UpstreamConfig,
+    events: &Events,
+    metrics: &Metrics,
+    shutdown: &CancellationToken,
+    pools: &BackendPools,
+) -> anyhow::Result<BackendPool> {
+    let mut config = proxy_config.clone();
+    if let Some(strategy) = upstream.strategy {
+        config.strategy = strategy;
+    }
+    if let Some(health) = &upstream.health_check {
+        config.health_check = Some(health.clone());
+    }
+    build_pool(
+        &config,
+        upstream.backends.clone(),
+        events,
+        metrics,
+        shutdown,
+        pools,
+    )
+}
+
+/// Build the A/B experiments configured under `proxy`
+///
+/// Variants without their own backends are served by `default`. Returns
+/// each experiment with the path prefix it runs on.
+fn build_experiments(
+    cfg: &Config,
+    default: &Arc<dyn Handler>,
+    events: &Events,
+    metrics: &Metrics,
+    shutdown: &CancellationToken,
+    pools: &BackendPools,
+) -> anyhow::Result<Vec<(String, Experiment)>> {
+    let Some(proxy_config) = &cfg.proxy else {
+        return Ok(Vec::new());
+    };
+
+    proxy_config
+        .experiments
+        .iter()
+        .map(|config| {
+            let mut experiment =
+                Experiment::new(config, default.clone()).with_metrics(metrics.clone());
+            for variant in config.variants.iter().filter(|v| !v.backends.is_empty()) {
+                let pool = build_pool(
+                    proxy_config,
+                    variant.backends.clone(),
+                    events,
+                    metrics,
+                    shutdown,
+                    pools,
+                )?;
+                experiment = experiment
+                    .with_variant_handler(&variant.name, build_proxy(proxy_config, pool, metrics));
+            }
+            info!(
+                experiment = %config.name,
+                prefix = %config.path_prefix,
+                variants = config.variants.len(),
+                "Initialized experiment"
+            );
+            Ok((config.path_prefix.clone(), experiment))
+        })
+        .collect()
+}
+
+/// Create a backend pool with the proxy's selection settings, starting its
+/// health checker if one is configured
+fn build_pool(
+    proxy_config: &ProxyConfig,
+    backends: Vec<BackendConfig>,
+    events: &Events,
+    metrics: &Metrics,
+    shutdown: &CancellationToken,
+    pools: &BackendPools,
+) -> anyhow::Result<BackendPool> {
+    let mut pool = BackendPool::new(backends)
+        .with_events(events.clone())
+        .with_metrics(metrics.clone())
+        .with_balancing(proxy_config.strategy)
+        .with_health_thresholds(HealthThresholds {
+            unhealthy: proxy_config.unhealthy_threshold,
+            healthy: proxy_config.healthy_threshold,
+        })
+        .with_circuit_breaker(proxy_config.circuit_breaker);
+    if let (LoadBalancing::Random, Some(seed)) = (proxy_config.strategy, proxy_config.random_seed) {
+        pool = pool.with_balancer(Arc::new(Random::seeded(seed)));
+    }
+    if let Some(warmup_ms) = proxy_config.warmup_ms {
+        pool = pool.with_warmup(Duration::from_millis(warmup_ms));
+    }
+    if let Some(locality) = &proxy_config.locality {
+        pool = pool.with_locality(locality.clone());
+    }
+    if let Some(feedback) = &proxy_config.load_feedback {
+        pool = pool.with_load_feedback(feedback.clone());
+    }
+
+    // Checked before anything is spawned, so a bad window fails startup
+    let scheduler = if proxy_config.maintenance.is_empty() {
+        None
+    } else {
+        Some(MaintenanceScheduler::new(
+            pool.clone(),
+            &proxy_config.maintenance,
+        )?)
+    };
+
+    if let Some(health) = &proxy_config.health_check {
+        let checker =
+            HealthChecker::new(pool.clone(), health.clone()).with_metrics(metrics.clone());
+        tokio::spawn(checker.run(shutdown.child_token()));
+    }
+
+    if let Some(scheduler) = scheduler {
+        tokio::spawn(
+            scheduler
+                .with_metrics(metrics.clone())
+                .run(shutdown.child_token()),
+        );
+    }
+
+    pools.register(pool.clone());
+    Ok(pool)
+}
+
+/// Backend timeouts from the proxy configuration
+fn upstream_timeouts(proxy_config: &ProxyConfig) -> UpstreamTimeouts {
+    UpstreamTimeouts {
+        first_byte: proxy_config
+            .first_byte_timeout_ms
+            .filter(|ms| *ms > 0)
+            .map(Duration::from_millis),
+        idle: proxy_config
+            .idle_timeout_ms
+            .filter(|ms| *ms > 0)
+            .map(Duration::from_millis),
+        ..UpstreamTimeouts::new(
+            Duration::from_millis(proxy_config.connection_timeout_ms),
+            Duration::from_millis(proxy_config.request_timeout_ms),
+        )
+    }
+}
+
+/// Create a proxy handler for `pool` with the proxy's timeouts and rules
+fn build_proxy(proxy_config: &ProxyConfig, pool: BackendPool, metrics: &Metrics) -> ProxyHandler {
+    let timeouts = upstream_timeouts(proxy_config);
+    let mut handler = ProxyHandler::new(pool, timeouts.connect, Duration::ZERO)
+        .with_timeouts(timeouts)
+        .with_route_timeouts(proxy_config.route_timeouts.clone())
+        .with_metrics(metrics.clone())
+        .with_routing_rules(proxy_config.routing_rules.clone())
+        .with_location_rewrites(proxy_config.location_rewrites.clone())
+        .with_hash_key(proxy_config.hash_key.clone())
+        .with_via(proxy_config.via.clone())
+        .with_retry_policy(proxy_config.retry.clone())
+        .with_resolver(
+            Resolver::new(Duration::from_millis(proxy_config.dns.ttl_ms))
+                .with_happy_eyeballs_delay(Duration::from_millis(
+                    proxy_config.dns.happy_eyeballs_delay_ms,
+                )),
+        );
+    if let Some(retry_after) = &proxy_config.retry_after {
+        handler = handler.with_retry_after(retry_after.clone());
+    }
+    if let Some(backoff) = &proxy_config.retry_backoff {
+        handler = handler.with_retry_backoff(backoff.clone());
+    }
+    if let Some(feedback) = &proxy_config.load_feedback {
+        handler = handler.with_load_feedback(feedback.clone());
+    }
+    if let Some(keepalive) = &proxy_config.keepalive {
+        handler = handler.with_keepalive(keepalive);
+    }
+    if let Some(tls) = &proxy_config.tls {
+        handler = handler.with_tls_session_cache(tls.session_cache_size);
+        match tls::client_config(tls) {
+            Ok(tls) => handler = handler.with_tls(tls),
+            Err(e) => {
+                warn!(error = %format!("{:#}", e), "Invalid upstream TLS settings, using defaults")
             }
-        });
+        }
     }
+    handler
 }
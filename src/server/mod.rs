@@ -1 +1,6 @@
+pub mod daemon;
 pub mod listener;
+#[cfg(windows)]
+pub mod windows_service;
+
+pub use listener::Server;
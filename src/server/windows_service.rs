@@ -0,0 +1,336 @@
+//! Windows service integration
+//!
+//! Lets Sentinel run under the Service Control Manager (SCM):
+//!
+//! - `sentinel service install` registers an auto-start service running
+//!   `sentinel service run <dir>`, where `<dir>` is the directory the
+//!   install command ran in (and so where `config.yaml` is looked up)
+//! - `sentinel service uninstall` stops and removes the service
+//! - `sentinel service run [dir]` is what the SCM launches; it is not meant
+//!   to be run by hand
+//!
+//! Stop and system shutdown requests from the SCM cancel the server's
+//! shutdown token. The service reports `SERVICE_STOP_PENDING` while the
+//! server stops accepting connections and drains the ones it has: in-flight
+//! requests get [`DRAIN_TIMEOUT`] to finish, inside the stop wait hint given
+//! to the SCM, before they are cancelled.
+//!
+//! The SCM API is called directly from `advapi32`, so no extra crates are
+//! needed.
+
+use anyhow::Context;
+use std::ffi::c_void;
+use std::path::Path;
+use std::ptr;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Name the service is registered under
+pub const SERVICE_NAME: &str = "Sentinel";
+
+const DISPLAY_NAME: &str = "Sentinel Web Server";
+
+/// How long the SCM is told a stop may take
+const STOP_WAIT_HINT: Duration = Duration::from_secs(30);
+
+/// How long in-flight requests may run after a stop request, leaving the
+/// rest of [`STOP_WAIT_HINT`] for the server to wind down
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(25);
+
+/// Server body run by [`run`] once the SCM has started the service
+///
+/// It must return after the token is cancelled, within [`DRAIN_TIMEOUT`]
+/// for requests to finish.
+pub type ServiceMain = fn(CancellationToken) -> anyhow::Result<()>;
+
+/// Register the service to start `service run` from `working_dir` at boot
+pub fn install(working_dir: &Path) -> anyhow::Result<()> {
+    let exe = std::env::current_exe().context("Failed to locate the sentinel executable")?;
+    let command = format!(
+        "\"{}\" service run \"{}\"",
+        exe.display(),
+        working_dir.display()
+    );
+
+    let manager = Handle::manager(SC_MANAGER_CONNECT | SC_MANAGER_CREATE_SERVICE)?;
+    // SAFETY: every string is NUL-terminated and outlives the call
+    let service = unsafe {
+        CreateServiceW(
+            manager.0,
+            wide(SERVICE_NAME).as_ptr(),
+            wide(DISPLAY_NAME).as_ptr(),
+            SERVICE_ALL_ACCESS,
+            SERVICE_WIN32_OWN_PROCESS,
+            SERVICE_AUTO_START,
+            SERVICE_ERROR_NORMAL,
+            wide(&command).as_ptr(),
+            ptr::null(),
+            ptr::null_mut(),
+            ptr::null(),
+            ptr::null(),
+            ptr::null(),
+        )
+    };
+    let _service = Handle::checked(service).context("Failed to create the service")?;
+    tracing::info!(service = SERVICE_NAME, command = %command, "Installed Windows service");
+    Ok(())
+}
+
+/// Stop the service if it is running and remove it
+pub fn uninstall() -> anyhow::Result<()> {
+    let manager = Handle::manager(SC_MANAGER_CONNECT)?;
+    // SAFETY: the name is NUL-terminated and outlives the call
+    let service = unsafe {
+        OpenServiceW(
+            manager.0,
+            wide(SERVICE_NAME).as_ptr(),
+            SERVICE_STOP | SERVICE_QUERY_STATUS | DELETE,
+        )
+    };
+    let service = Handle::checked(service).context("Failed to open the service")?;
+
+    let mut status = ServiceStatus::default();
+    // SAFETY: status is a valid out-parameter; failure just means it was not running
+    unsafe { ControlService(service.0, SERVICE_CONTROL_STOP, &mut status) };
+    // SAFETY: the handle is open with DELETE access
+    if unsafe { DeleteService(service.0) } == 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to delete the service");
+    }
+    tracing::info!(service = SERVICE_NAME, "Uninstalled Windows service");
+    Ok(())
+}
+
+/// Hand the process to the SCM and run `main` as the service
+///
+/// Blocks until the service stops. Fails if the process was not started
+/// by the SCM.
+pub fn run(main: ServiceMain) -> anyhow::Result<()> {
+    STATE
+        .set(ServiceState {
+            main,
+            shutdown: CancellationToken::new(),
+            status: OnceLock::new(),
+        })
+        .map_err(|_| anyhow::anyhow!("Service is already running"))?;
+
+    let mut name = wide(SERVICE_NAME);
+    let table = [
+        ServiceTableEntry {
+            name: name.as_mut_ptr(),
+            proc_: Some(service_main),
+        },
+        ServiceTableEntry {
+            name: ptr::null_mut(),
+            proc_: None,
+        },
+    ];
+    // SAFETY: the table is NULL-terminated and outlives the dispatcher
+    if unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) } == 0 {
+        return Err(std::io::Error::last_os_error())
+            .context("Failed to connect to the Service Control Manager");
+    }
+    Ok(())
+}
+
+struct ServiceState {
+    main: ServiceMain,
+    shutdown: CancellationToken,
+    status: OnceLock<StatusHandle>,
+}
+
+// SAFETY: the status handle is an opaque token the SCM allows any thread to use
+unsafe impl Send for ServiceState {}
+unsafe impl Sync for ServiceState {}
+
+static STATE: OnceLock<ServiceState> = OnceLock::new();
+
+/// Entry point the SCM calls on its own thread
+unsafe extern "system" fn service_main(_argc: u32, _argv: *mut *mut u16) {
+    let Some(state) = STATE.get() else { return };
+
+    // SAFETY: the name is NUL-terminated and the handler is a valid callback
+    let handle = unsafe {
+        RegisterServiceCtrlHandlerExW(
+            wide(SERVICE_NAME).as_ptr(),
+            control_handler,
+            ptr::null_mut(),
+        )
+    };
+    if handle.is_null() {
+        tracing::error!(
+            error = %std::io::Error::last_os_error(),
+            "Failed to register service control handler"
+        );
+        return;
+    }
+    let _ = state.status.set(handle);
+
+    report(SERVICE_RUNNING, 0);
+    let exit_code = match (state.main)(state.shutdown.clone()) {
+        Ok(()) => 0,
+        Err(e) => {
+            tracing::error!(error = %format!("{:#}", e), "Service failed");
+            1
+        }
+    };
+    report(SERVICE_STOPPED, exit_code);
+}
+
+/// Control requests from the SCM
+unsafe extern "system" fn control_handler(
+    control: u32,
+    _event_type: u32,
+    _event_data: *mut c_void,
+    _context: *mut c_void,
+) -> u32 {
+    let Some(state) = STATE.get() else {
+        return ERROR_CALL_NOT_IMPLEMENTED;
+    };
+    match control {
+        SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN | SERVICE_CONTROL_PRESHUTDOWN => {
+            tracing::info!(control, "Stop requested by the Service Control Manager");
+            report(SERVICE_STOP_PENDING, 0);
+            state.shutdown.cancel();
+            NO_ERROR
+        }
+        SERVICE_CONTROL_INTERROGATE => NO_ERROR,
+        _ => ERROR_CALL_NOT_IMPLEMENTED,
+    }
+}
+
+/// Tell the SCM the service's state
+fn report(state: u32, exit_code: u32) {
+    let Some(handle) = STATE.get().and_then(|s| s.status.get()) else {
+        return;
+    };
+    let status = ServiceStatus {
+        service_type: SERVICE_WIN32_OWN_PROCESS,
+        current_state: state,
+        controls_accepted: if state == SERVICE_RUNNING {
+            SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN | SERVICE_ACCEPT_PRESHUTDOWN
+        } else {
+            0
+        },
+        win32_exit_code: if exit_code == 0 {
+            NO_ERROR
+        } else {
+            ERROR_SERVICE_SPECIFIC_ERROR
+        },
+        service_specific_exit_code: exit_code,
+        check_point: 0,
+        wait_hint: if state == SERVICE_STOP_PENDING {
+            STOP_WAIT_HINT.as_millis() as u32
+        } else {
+            0
+        },
+    };
+    // SAFETY: the handle came from RegisterServiceCtrlHandlerExW
+    unsafe { SetServiceStatus(*handle, &status) };
+}
+
+/// Service Control Manager handle, closed on drop
+struct Handle(ScHandle);
+
+impl Handle {
+    fn manager(access: u32) -> anyhow::Result<Self> {
+        // SAFETY: null machine and database select the local active database
+        let manager = unsafe { OpenSCManagerW(ptr::null(), ptr::null(), access) };
+        Self::checked(manager).context("Failed to open the Service Control Manager")
+    }
+
+    fn checked(handle: ScHandle) -> std::io::Result<Self> {
+        if handle.is_null() {
+            Err(std::io::Error::last_os_error())
+        } else {
+            Ok(Self(handle))
+        }
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        // SAFETY: the handle is open and owned by this value
+        unsafe { CloseServiceHandle(self.0) };
+    }
+}
+
+/// NUL-terminated UTF-16 copy of `s`
+fn wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+type ScHandle = *mut c_void;
+type StatusHandle = *mut c_void;
+
+#[repr(C)]
+#[derive(Default)]
+struct ServiceStatus {
+    service_type: u32,
+    current_state: u32,
+    controls_accepted: u32,
+    win32_exit_code: u32,
+    service_specific_exit_code: u32,
+    check_point: u32,
+    wait_hint: u32,
+}
+
+#[repr(C)]
+struct ServiceTableEntry {
+    name: *mut u16,
+    proc_: Option<unsafe extern "system" fn(u32, *mut *mut u16)>,
+}
+
+const SC_MANAGER_CONNECT: u32 = 0x0001;
+const SC_MANAGER_CREATE_SERVICE: u32 = 0x0002;
+const SERVICE_ALL_ACCESS: u32 = 0x000F_01FF;
+const SERVICE_STOP: u32 = 0x0020;
+const SERVICE_QUERY_STATUS: u32 = 0x0004;
+const DELETE: u32 = 0x0001_0000;
+const SERVICE_WIN32_OWN_PROCESS: u32 = 0x10;
+const SERVICE_AUTO_START: u32 = 2;
+const SERVICE_ERROR_NORMAL: u32 = 1;
+const SERVICE_CONTROL_STOP: u32 = 1;
+const SERVICE_CONTROL_INTERROGATE: u32 = 4;
+const SERVICE_CONTROL_SHUTDOWN: u32 = 5;
+const SERVICE_CONTROL_PRESHUTDOWN: u32 = 0x0F;
+const SERVICE_ACCEPT_STOP: u32 = 0x001;
+const SERVICE_ACCEPT_SHUTDOWN: u32 = 0x004;
+const SERVICE_ACCEPT_PRESHUTDOWN: u32 = 0x100;
+const SERVICE_STOPPED: u32 = 1;
+const SERVICE_STOP_PENDING: u32 = 3;
+const SERVICE_RUNNING: u32 = 4;
+const NO_ERROR: u32 = 0;
+const ERROR_CALL_NOT_IMPLEMENTED: u32 = 120;
+const ERROR_SERVICE_SPECIFIC_ERROR: u32 = 1066;
+
+#[link(name = "advapi32")]
+unsafe extern "system" {
+    fn StartServiceCtrlDispatcherW(table: *const ServiceTableEntry) -> i32;
+    fn RegisterServiceCtrlHandlerExW(
+        name: *const u16,
+        handler: unsafe extern "system" fn(u32, u32, *mut c_void, *mut c_void) -> u32,
+        context: *mut c_void,
+    ) -> StatusHandle;
+    fn SetServiceStatus(handle: StatusHandle, status: *const ServiceStatus) -> i32;
+    fn OpenSCManagerW(machine: *const u16, database: *const u16, access: u32) -> ScHandle;
+    fn CreateServiceW(
+        manager: ScHandle,
+        name: *const u16,
+        display_name: *const u16,
+        access: u32,
+        service_type: u32,
+        start_type: u32,
+        error_control: u32,
+        binary_path: *const u16,
+        load_order_group: *const u16,
+        tag_id: *mut u32,
+        dependencies: *const u16,
+        account: *const u16,
+        password: *const u16,
+    ) -> ScHandle;
+    fn OpenServiceW(manager: ScHandle, name: *const u16, access: u32) -> ScHandle;
+    fn ControlService(service: ScHandle, control: u32, status: *mut ServiceStatus) -> i32;
+    fn DeleteService(service: ScHandle) -> i32;
+    fn CloseServiceHandle(handle: ScHandle) -> i32;
+}
@@ -0,0 +1,364 @@
+//! Test utilities
+//!
+//! An in-process [`MockBackend`] with scriptable responses, delays, and
+//! connection resets, plus helpers to drive a [`Connection`] or
+//! [`ProxyHandler`] against it without fixed ports.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use sentinel::testing::{MockAction, MockBackend, MockResponse, proxy_handler};
+//!
+//! let backend = MockBackend::start().await;
+//! backend.push(MockAction::Reset);
+//! backend.push(MockAction::Respond(MockResponse::new(200).body("hello")));
+//!
+//! let proxy = proxy_handler(&[&backend]);
+//! ```
+
+use crate::config::BackendConfig;
+use crate::http::connection::Connection;
+use crate::http::handler::Handler;
+use crate::http::parser::{ParseError, parse_http_request};
+use crate::http::request::Request;
+use crate::proxy::{BackendPool, ProxyHandler};
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+/// Connect timeout used by [`proxy_handler`]
+pub const TEST_CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Request timeout used by [`proxy_handler`]
+pub const TEST_REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A scripted response from a [`MockBackend`]
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    status: u16,
+    reason: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    delay: Duration,
+}
+
+impl MockResponse {
+    /// Create a response with the given status code and an empty body
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            reason: "Mock".to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+            delay: Duration::ZERO,
+        }
+    }
+
+    /// Set the reason phrase on the status line
+    pub fn reason(mut self, reason: impl Into<String>) -> Self {
+        self.reason = reason.into();
+        self
+    }
+
+    /// Add a response header
+    pub fn header(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((key.into(), value.into()));
+        self
+    }
+
+    /// Set the response body
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Wait before sending the response
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = format!("HTTP/1.1 {} {}\r\n", self.status, self.reason).into_bytes();
+
+        for (k, v) in &self.headers {
+            buf.extend_from_slice(format!("{}: {}\r\n", k, v).as_bytes());
+        }
+        if !self
+            .headers
+            .iter()
+            .any(|(k, _)| k.eq_ignore_ascii_case("content-length"))
+        {
+            buf.extend_from_slice(format!("Content-Length: {}\r\n", self.body.len()).as_bytes());
+        }
+        buf.extend_from_slice(b"Connection: close\r\n\r\n");
+        buf.extend_from_slice(&self.body);
+
+        buf
+    }
+}
+
+/// What a [`MockBackend`] does with the next request
+#[derive(Debug, Clone)]
+pub enum MockAction {
+    /// Send a response and close the connection
+    Respond(MockResponse),
+    /// Reset the connection without responding
+    Reset,
+    /// Read the request and never respond
+    Hang,
+}
+
+#[derive(Debug)]
+struct MockState {
+    script: VecDeque<MockAction>,
+    default: MockAction,
+    requests: Vec<Request>,
+}
+
+/// An in-process HTTP backend bound to an ephemeral localhost port
+///
+/// Actions pushed with [`MockBackend::push`] are consumed in order, one per
+/// request; once the script is exhausted the default action (200 "ok"
+/// unless changed) is used. The server stops when the value is dropped.
+pub struct MockBackend {
+    addr: SocketAddr,
+    state: Arc<Mutex<MockState>>,
+    task: JoinHandle<()>,
+}
+
+impl MockBackend {
+    /// Start a mock backend that answers `200 ok` by default
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind mock backend");
+        let addr = listener.local_addr().expect("mock backend address");
+
+        let state = Arc::new(Mutex::new(MockState {
+            script: VecDeque::new(),
+            default: MockAction::Respond(MockResponse::new(200).reason("OK").body("ok")),
+            requests: Vec::new(),
+        }));
+
+        let task_state = state.clone();
+        let task = tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(serve_mock(socket, task_state.clone()));
+            }
+        });
+
+        Self { addr, state, task }
+    }
+
+    /// Address the backend is listening on
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Base URL suitable for a [`BackendConfig`]
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Queue an action for the next unscripted request
+    pub fn push(&self, action: MockAction) {
+        self.state.lock().unwrap().script.push_back(action);
+    }
+
+    /// Set the action used once the script is exhausted
+    pub fn set_default(&self, action: MockAction) {
+        self.state.lock().unwrap().default = action;
+    }
+
+    /// Requests received so far, in arrival order
+    pub fn requests(&self) -> Vec<Request> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Number of requests received so far
+    pub fn request_count(&self) -> usize {
+        self.state.lock().unwrap().requests.len()
+    }
+}
+
+impl Drop for MockBackend {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve_mock(mut socket: TcpStream, state: Arc<Mutex<MockState>>) {
+    let mut buffer = Vec::new();
+
+    let request = loop {
+        match parse_http_request(&buffer) {
+            Ok((request, _)) => break request,
+            Err(ParseError::Incomplete) => {}
+            Err(_) => return,
+        }
+
+        let mut temp = [0u8; 4096];
+        match socket.read(&mut temp).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => buffer.extend_from_slice(&temp[..n]),
+        }
+    };
+
+    let action = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(request);
+        state
+            .script
+            .pop_front()
+            .unwrap_or_else(|| state.default.clone())
+    };
+
+    match action {
+        MockAction::Respond(response) => {
+            if !response.delay.is_zero() {
+                tokio::time::sleep(response.delay).await;
+            }
+            let _ = socket.write_all(&response.to_bytes()).await;
+            let _ = socket.shutdown().await;
+        }
+        MockAction::Reset => {
+            // Zero linger makes close() send RST instead of FIN. The
+            // deprecation is about blocking on drop, which a zero timeout
+            // never does.
+            #[allow(deprecated)]
+            let _ = socket.set_linger(Some(Duration::ZERO));
+            drop(socket);
+        }
+        MockAction::Hang => {
+            // Hold the connection open until the client gives up
+            let mut temp = [0u8; 1024];
+            while let Ok(n) = socket.read(&mut temp).await {
+                if n == 0 {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Backend configuration pointing at a mock backend
+pub fn backend_config(backend: &MockBackend) -> BackendConfig {
+    BackendConfig {
+        url: backend.url(),
+        name: None,
+        ..Default::default()
+    }
+}
+
+/// A proxy handler in front of the given mock backends, with short timeouts
+pub fn proxy_handler(backends: &[&MockBackend]) -> ProxyHandler {
+    let pool = BackendPool::new(backends.iter().map(|b| backend_config(b)).collect());
+    ProxyHandler::new(pool, TEST_CONNECT_TIMEOUT, TEST_REQUEST_TIMEOUT)
+}
+
+/// A response as seen by a test client
+#[derive(Debug, Clone)]
+pub struct TestResponse {
+    /// Numeric status code
+    pub status: u16,
+    /// Response headers
+    pub headers: HashMap<String, String>,
+    /// Response body
+    pub body: Vec<u8>,
+}
+
+impl TestResponse {
+    /// Look up a header (case-insensitive)
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Body as UTF-8 text (lossy)
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// Send raw request bytes through a [`Connection`] over an in-memory stream
+///
+/// Reads until the server closes the connection, so the last request must
+/// carry `Connection: close` (or the handler must close it). The client keeps
+/// its write half open, since end-of-stream counts as a disconnect. Returns
+/// every response.
+pub async fn send_raw(handler: Arc<dyn Handler>, raw: &[u8]) -> Vec<TestResponse> {
+    let (mut client, server) = tokio::io::duplex(64 * 1024);
+
+    let conn = tokio::spawn(async move {
+        let mut conn = Connection::with_handler(server, handler);
+        let _ = conn.run().await;
+    });
+
+    client.write_all(raw).await.expect("write request");
+
+    let mut output = Vec::new();
+    client
+        .read_to_end(&mut output)
+        .await
+        .expect("read response");
+    let _ = conn.await;
+
+    parse_responses(&output)
+}
+
+/// Send a single request through a [`Connection`] and return its response
+///
+/// # Panics
+///
+/// Panics if the connection produced no response.
+pub async fn send_request(handler: Arc<dyn Handler>, raw: &[u8]) -> TestResponse {
+    send_raw(handler, raw)
+        .await
+        .into_iter()
+        .next()
+        .expect("connection produced no response")
+}
+
+/// Parse a sequence of HTTP/1.1 responses delimited by Content-Length
+fn parse_responses(mut data: &[u8]) -> Vec<TestResponse> {
+    let mut responses = Vec::new();
+
+    while let Some(end) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+        let head = String::from_utf8_lossy(&data[..end]).into_owned();
+        let mut lines = head.split("\r\n");
+        let status = lines
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|code| code.parse().ok())
+            .unwrap_or(0);
+
+        let headers: HashMap<String, String> = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+            .collect();
+
+        let rest = &data[end + 4..];
+        let len = headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("content-length"))
+            .and_then(|(_, v)| v.parse::<usize>().ok())
+            .unwrap_or(rest.len())
+            .min(rest.len());
+
+        responses.push(TestResponse {
+            status,
+            headers,
+            body: rest[..len].to_vec(),
+        });
+        data = &rest[len..];
+    }
+
+    responses
+}
@@ -0,0 +1,150 @@
+//! Verified client certificates (mutual TLS)
+//!
+//! When the listener verifies client certificates
+//! (`server.tls.client_auth`), the certificate a client presented is
+//! attached to each of its requests as a [`ClientCertificate`], so routes
+//! can require one and pass its identity on to backends.
+//!
+//! The header names below are the defaults; `client_auth.headers` can
+//! rename or drop each of them.
+
+use crate::tls::der::{self, Reader};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use sha2::{Digest, Sha256};
+
+/// Header carrying the hex SHA-256 of the client's certificate to backends
+pub const CLIENT_CERT_SHA256_HEADER: &str = "X-Client-Cert-Sha256";
+
+/// Header carrying the client certificate's subject to backends
+pub const CLIENT_CERT_SUBJECT_HEADER: &str = "X-Client-Cert-Subject";
+
+/// Header carrying the client certificate's issuer to backends
+pub const CLIENT_CERT_ISSUER_HEADER: &str = "X-Client-Cert-Issuer";
+
+/// Header carrying the client certificate itself, as URL-encoded PEM
+pub const CLIENT_CERT_PEM_HEADER: &str = "X-Client-Cert-PEM";
+
+/// Short names of the subject attributes shown, by the last byte of their
+/// `2.5.4.n` OID
+const ATTRIBUTES: [(u8, &str); 6] = [
+    (3, "CN"),
+    (6, "C"),
+    (7, "L"),
+    (8, "ST"),
+    (10, "O"),
+    (11, "OU"),
+];
+
+/// A certificate the client presented and the listener verified
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientCertificate {
+    /// Lowercase hex SHA-256 of the DER certificate
+    pub sha256: String,
+    /// Subject in RFC 4514 form (e.g. `CN=billing,O=Example`), empty if it
+    /// has none of the common attributes
+    pub subject: String,
+    /// Issuer in RFC 4514 form, empty if it has none of the common
+    /// attributes
+    pub issuer: String,
+    /// The DER certificate
+    pub der: Vec<u8>,
+}
+
+impl ClientCertificate {
+    /// Details of the DER certificate `cert`
+    pub fn from_der(cert: &[u8]) -> Self {
+        let sha256 = Sha256::digest(cert)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let (issuer, subject) = names(cert).unwrap_or_else(|e| {
+            tracing::debug!(error = %e, "Could not read the client certificate's names");
+            (String::new(), String::new())
+        });
+        Self {
+            sha256,
+            subject,
+            issuer,
+            der: cert.to_vec(),
+        }
+    }
+
+    /// The certificate in PEM form
+    pub fn pem(&self) -> String {
+        let encoded = STANDARD.encode(&self.der);
+        let mut pem = String::from("-----BEGIN CERTIFICATE-----\n");
+        for line in encoded.as_bytes().chunks(64) {
+            pem.push_str(std::str::from_utf8(line).unwrap_or_default());
+            pem.push('\n');
+        }
+        pem.push_str("-----END CERTIFICATE-----\n");
+        pem
+    }
+
+    /// The PEM certificate percent-encoded to fit in a header, as nginx's
+    /// `$ssl_client_escaped_cert`
+    pub fn escaped_pem(&self) -> String {
+        let mut escaped = String::new();
+        for byte in self.pem().bytes() {
+            if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+                escaped.push(byte as char);
+            } else {
+                escaped.push_str(&format!("%{:02X}", byte));
+            }
+        }
+        escaped
+    }
+}
+
+/// The issuer and subject of `cert`
+fn names(cert: &[u8]) -> anyhow::Result<(String, String)> {
+    let certificate = Reader::new(cert).expect(der::SEQUENCE)?;
+    let mut tbs = certificate.reader().expect(der::SEQUENCE)?.reader();
+    tbs.optional(der::explicit(0))?; // version
+    tbs.expect(der::INTEGER)?; // serialNumber
+    tbs.expect(der::SEQUENCE)?; // signature
+    let issuer = tbs.expect(der::SEQUENCE)?;
+    tbs.expect(der::SEQUENCE)?; // validity
+    let subject = tbs.expect(der::SEQUENCE)?;
+    Ok((
+        distinguished_name(issuer.reader())?,
+        distinguished_name(subject.reader())?,
+    ))
+}
+
+/// An X.501 `Name` in RFC 4514 form, most significant attribute last
+fn distinguished_name(mut names: Reader<'_>) -> anyhow::Result<String> {
+    let mut parts = Vec::new();
+    while !names.is_empty() {
+        let mut set = names.expect(der::SET)?.reader();
+        while !set.is_empty() {
+            let mut attribute = set.expect(der::SEQUENCE)?.reader();
+            let oid = attribute.expect(der::OID)?;
+            let value = attribute.next()?;
+            let name = match oid.value {
+                [0x55, 0x04, n] => ATTRIBUTES.iter().find(|(id, _)| id == n),
+                _ => None,
+            };
+            if let Some((_, name)) = name {
+                let value = String::from_utf8_lossy(value.value);
+                parts.push(format!("{}={}", name, escape(&value)));
+            }
+        }
+    }
+    parts.reverse();
+    Ok(parts.join(","))
+}
+
+/// `value` with RFC 4514 special characters escaped and control
+/// characters dropped, so it fits in a header
+fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars().filter(|c| !c.is_control()) {
+        if matches!(c, ',' | '+' | '"' | '\\' | '<' | '>' | ';') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
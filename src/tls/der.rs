@@ -0,0 +1,191 @@
+//! Just enough DER to build OCSP requests and read certificates and OCSP
+//! responses
+//!
+//! Only definite lengths are supported, which DER requires anyway.
+
+use anyhow::Context;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub(crate) const SEQUENCE: u8 = 0x30;
+pub(crate) const INTEGER: u8 = 0x02;
+pub(crate) const BIT_STRING: u8 = 0x03;
+pub(crate) const OCTET_STRING: u8 = 0x04;
+pub(crate) const NULL: u8 = 0x05;
+pub(crate) const OID: u8 = 0x06;
+pub(crate) const SET: u8 = 0x31;
+pub(crate) const ENUMERATED: u8 = 0x0A;
+pub(crate) const GENERALIZED_TIME: u8 = 0x18;
+
+/// Tag of `[n]` context-specific constructed (explicit) fields
+pub(crate) const fn explicit(n: u8) -> u8 {
+    0xA0 | n
+}
+
+/// Tag of `[n]` context-specific primitive (implicit) fields
+pub(crate) const fn implicit(n: u8) -> u8 {
+    0x80 | n
+}
+
+/// One tag-length-value element
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Element<'a> {
+    pub tag: u8,
+    /// Contents, without the tag and length
+    pub value: &'a [u8],
+    /// The whole encoding, tag and length included
+    pub raw: &'a [u8],
+}
+
+impl<'a> Element<'a> {
+    /// Reader over the contents of a constructed element
+    pub fn reader(&self) -> Reader<'a> {
+        Reader::new(self.value)
+    }
+}
+
+/// Reads consecutive elements from a buffer
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Tag of the next element, if any
+    pub fn peek_tag(&self) -> Option<u8> {
+        self.data.first().copied()
+    }
+
+    /// Read the next element, whatever its tag
+    pub fn next(&mut self) -> anyhow::Result<Element<'a>> {
+        let (&tag, rest) = self.data.split_first().context("Truncated DER")?;
+        let (&first, mut rest) = rest.split_first().context("Truncated DER")?;
+        let len = if first < 0x80 {
+            usize::from(first)
+        } else {
+            let count = usize::from(first & 0x7F);
+            if count == 0 || count > 4 || rest.len() < count {
+                anyhow::bail!("Unsupported DER length");
+            }
+            let (bytes, after) = rest.split_at(count);
+            rest = after;
+            bytes.iter().fold(0, |len, &b| (len << 8) | usize::from(b))
+        };
+        if rest.len() < len {
+            anyhow::bail!("Truncated DER");
+        }
+
+        let header = self.data.len() - rest.len();
+        let raw = &self.data[..header + len];
+        self.data = &self.data[header + len..];
+        Ok(Element {
+            tag,
+            value: &rest[..len],
+            raw,
+        })
+    }
+
+    /// Read the next element, failing unless it has `tag`
+    pub fn expect(&mut self, tag: u8) -> anyhow::Result<Element<'a>> {
+        let element = self.next()?;
+        if element.tag != tag {
+            anyhow::bail!(
+                "Unexpected DER tag {:#04x}, expected {:#04x}",
+                element.tag,
+                tag
+            );
+        }
+        Ok(element)
+    }
+
+    /// Read the next element if it has `tag`
+    pub fn optional(&mut self, tag: u8) -> anyhow::Result<Option<Element<'a>>> {
+        if self.peek_tag() == Some(tag) {
+            self.next().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Encode one element
+pub(crate) fn encode(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = value.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes = len.to_be_bytes();
+        let skip = bytes.iter().take_while(|&&b| b == 0).count();
+        out.push(0x80 | (bytes.len() - skip) as u8);
+        out.extend_from_slice(&bytes[skip..]);
+    }
+    out.extend_from_slice(value);
+    out
+}
+
+/// Encode a SEQUENCE of already-encoded elements
+pub(crate) fn sequence(elements: &[&[u8]]) -> Vec<u8> {
+    encode(SEQUENCE, &elements.concat())
+}
+
+/// Encode a dotted object identifier such as `1.3.14.3.2.26`
+pub(crate) fn oid(dotted: &str) -> Vec<u8> {
+    let arcs: Vec<u64> = dotted.split('.').map(|a| a.parse().unwrap()).collect();
+    let mut value = Vec::new();
+    for (i, &arc) in arcs.iter().enumerate().skip(1) {
+        let arc = if i == 1 { arcs[0] * 40 + arc } else { arc };
+        let mut bytes = vec![(arc & 0x7F) as u8];
+        let mut rest = arc >> 7;
+        while rest > 0 {
+            bytes.push(0x80 | (rest & 0x7F) as u8);
+            rest >>= 7;
+        }
+        bytes.reverse();
+        value.extend(bytes);
+    }
+    encode(OID, &value)
+}
+
+/// Parse a GeneralizedTime such as `20261016120000Z`, ignoring fractional
+/// seconds
+pub(crate) fn generalized_time(value: &[u8]) -> anyhow::Result<SystemTime> {
+    let text = std::str::from_utf8(value).context("Invalid GeneralizedTime")?;
+    let digits = text
+        .strip_suffix('Z')
+        .context("GeneralizedTime is not in UTC")?;
+    let digits = digits.split('.').next().unwrap_or(digits);
+    if digits.len() != 14 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        anyhow::bail!("Invalid GeneralizedTime {}", text);
+    }
+    let field = |range: std::ops::Range<usize>| digits[range].parse::<u64>().unwrap();
+
+    let days = days_from_civil(field(0..4), field(4..6), field(6..8))
+        .with_context(|| format!("GeneralizedTime {} is out of range", text))?;
+    let secs = days * 86_400 + field(8..10) * 3_600 + field(10..12) * 60 + field(12..14);
+    Ok(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Days since the Unix epoch of a (year, month, day) date, for dates after
+/// 1970
+///
+/// Howard Hinnant's `days_from_civil`.
+fn days_from_civil(year: u64, month: u64, day: u64) -> Option<u64> {
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let yoe = year - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    (era * 146_097 + doe).checked_sub(719_468)
+}
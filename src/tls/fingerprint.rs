@@ -0,0 +1,382 @@
+//! TLS client fingerprints (JA3 and JA4)
+//!
+//! Clients built on the same TLS stack send near-identical ClientHellos,
+//! so a hash of the offered versions, cipher suites, extensions, and groups
+//! identifies the client library regardless of the User-Agent it claims.
+//!
+//! A [`ClientHelloRecorder`] wraps the accepted socket and keeps a copy of
+//! the bytes read until the ClientHello is complete; after the handshake
+//! [`ClientHelloRecorder::take_fingerprint`] computes both fingerprints:
+//!
+//! - JA3: MD5 of `version,ciphers,extensions,groups,point_formats`, each
+//!   list as decimal values joined with `-`, in the order the client sent
+//!   them
+//! - JA4: `t13d1516h2_8daaf6152771_e5627efa2ab1` style, a readable prefix
+//!   (protocol, version, SNI, counts, ALPN) followed by truncated SHA-256
+//!   hashes of the sorted cipher suites and of the sorted extensions plus
+//!   signature algorithms, which makes it stable under extension shuffling
+//!
+//! GREASE values (RFC 8701) are ignored in both.
+
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Largest ClientHello recorded; anything longer is not fingerprinted
+pub const MAX_CLIENT_HELLO: usize = 16 * 1024;
+
+const CONTENT_HANDSHAKE: u8 = 22;
+const HANDSHAKE_CLIENT_HELLO: u8 = 1;
+
+const EXT_SERVER_NAME: u16 = 0x0000;
+const EXT_SUPPORTED_GROUPS: u16 = 0x000a;
+const EXT_EC_POINT_FORMATS: u16 = 0x000b;
+const EXT_SIGNATURE_ALGORITHMS: u16 = 0x000d;
+const EXT_ALPN: u16 = 0x0010;
+const EXT_SUPPORTED_VERSIONS: u16 = 0x002b;
+
+/// Fingerprints of one TLS client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsFingerprint {
+    /// MD5 of [`ja3_full`](Self::ja3_full), in hex
+    pub ja3: String,
+    /// The JA3 string before hashing
+    pub ja3_full: String,
+    /// JA4 fingerprint
+    pub ja4: String,
+}
+
+impl TlsFingerprint {
+    /// Fingerprint the ClientHello at the start of `records`, which holds
+    /// raw TLS records as read from the client
+    ///
+    /// Returns `None` unless the records start with a complete, well-formed
+    /// ClientHello.
+    pub fn from_records(records: &[u8]) -> Option<Self> {
+        let hello = ClientHello::parse(&client_hello_message(records)?)?;
+        Some(Self {
+            ja3: hex(&Md5::digest(hello.ja3().as_bytes())),
+            ja3_full: hello.ja3(),
+            ja4: hello.ja4(),
+        })
+    }
+
+    /// Whether `fingerprint` is this client's JA3 hash or JA4 fingerprint
+    pub fn matches(&self, fingerprint: &str) -> bool {
+        fingerprint.eq_ignore_ascii_case(&self.ja3) || fingerprint.eq_ignore_ascii_case(&self.ja4)
+    }
+}
+
+/// Stream wrapper that records the client's ClientHello as it is read
+///
+/// Once the ClientHello is complete (or turns out not to be one) reads are
+/// passed straight through.
+pub struct ClientHelloRecorder<S> {
+    inner: S,
+    recorded: Vec<u8>,
+    recording: bool,
+}
+
+impl<S> ClientHelloRecorder<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            recorded: Vec::new(),
+            recording: true,
+        }
+    }
+
+    /// The wrapped stream
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Fingerprint the recorded ClientHello and free the recording
+    pub fn take_fingerprint(&mut self) -> Option<TlsFingerprint> {
+        self.recording = false;
+        let records = std::mem::take(&mut self.recorded);
+        TlsFingerprint::from_records(&records)
+    }
+
+    fn record(&mut self, bytes: &[u8]) {
+        self.recorded.extend_from_slice(bytes);
+        if self.recorded.first() != Some(&CONTENT_HANDSHAKE)
+            || self.recorded.len() > MAX_CLIENT_HELLO
+            || client_hello_message(&self.recorded).is_some()
+        {
+            self.recording = false;
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ClientHelloRecorder<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if this.recording && matches!(poll, Poll::Ready(Ok(()))) {
+            this.record(&buf.filled()[before..]);
+        }
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ClientHelloRecorder<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Reassemble the ClientHello body from handshake records, if complete
+fn client_hello_message(mut records: &[u8]) -> Option<Vec<u8>> {
+    let mut message = Vec::new();
+    while records.len() >= 5 && records[0] == CONTENT_HANDSHAKE {
+        let len = usize::from(u16::from_be_bytes([records[3], records[4]]));
+        let fragment = records.get(5..5 + len)?;
+        message.extend_from_slice(fragment);
+        records = &records[5 + len..];
+
+        if message.len() >= 4 {
+            if message[0] != HANDSHAKE_CLIENT_HELLO {
+                return None;
+            }
+            let body_len = usize::from(message[1]) << 16
+                | usize::from(message[2]) << 8
+                | usize::from(message[3]);
+            if message.len() >= 4 + body_len {
+                message.truncate(4 + body_len);
+                return Some(message.split_off(4));
+            }
+        }
+    }
+    None
+}
+
+/// The parts of a ClientHello that fingerprints use
+#[derive(Default)]
+struct ClientHello {
+    version: u16,
+    ciphers: Vec<u16>,
+    /// Extension types in the order sent
+    extensions: Vec<u16>,
+    groups: Vec<u16>,
+    point_formats: Vec<u8>,
+    signature_algorithms: Vec<u16>,
+    supported_versions: Vec<u16>,
+    alpn: Option<Vec<u8>>,
+}
+
+impl ClientHello {
+    fn parse(body: &[u8]) -> Option<Self> {
+        let mut hello = Self::default();
+        let mut r = Bytes(body);
+        hello.version = r.u16()?;
+        r.take(32)?; // random
+        let session_id = r.u8()?;
+        r.take(usize::from(session_id))?;
+        hello.ciphers = r.vec16()?.u16s()?;
+        let compression = r.u8()?;
+        r.take(usize::from(compression))?;
+        if r.0.is_empty() {
+            return Some(hello);
+        }
+
+        let mut extensions = r.vec16()?;
+        while !extensions.0.is_empty() {
+            let kind = extensions.u16()?;
+            let mut data = extensions.vec16()?;
+            hello.extensions.push(kind);
+            match kind {
+                EXT_SUPPORTED_GROUPS => hello.groups = data.vec16()?.u16s()?,
+                EXT_EC_POINT_FORMATS => hello.point_formats = data.vec8()?.0.to_vec(),
+                EXT_SIGNATURE_ALGORITHMS => hello.signature_algorithms = data.vec16()?.u16s()?,
+                EXT_SUPPORTED_VERSIONS => hello.supported_versions = data.vec8()?.u16s()?,
+                EXT_ALPN => {
+                    let mut protocols = data.vec16()?;
+                    if !protocols.0.is_empty() {
+                        hello.alpn = Some(protocols.vec8()?.0.to_vec());
+                    }
+                }
+                _ => {}
+            }
+        }
+        Some(hello)
+    }
+
+    fn ja3(&self) -> String {
+        fn join<T: Copy + Into<u16>>(values: &[T]) -> String {
+            values
+                .iter()
+                .map(|&v| v.into())
+                .filter(|&v| !is_grease(v))
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join("-")
+        }
+        format!(
+            "{},{},{},{},{}",
+            self.version,
+            join(&self.ciphers),
+            join(&self.extensions),
+            join(&self.groups),
+            join(&self.point_formats)
+        )
+    }
+
+    fn ja4(&self) -> String {
+        let ciphers: Vec<u16> = without_grease(&self.ciphers);
+        let extensions: Vec<u16> = without_grease(&self.extensions);
+
+        let version = without_grease(&self.supported_versions)
+            .into_iter()
+            .max()
+            .unwrap_or(self.version);
+        let version = match version {
+            0x0304 => "13",
+            0x0303 => "12",
+            0x0302 => "11",
+            0x0301 => "10",
+            0x0300 => "s3",
+            _ => "00",
+        };
+        let sni = if extensions.contains(&EXT_SERVER_NAME) {
+            'd'
+        } else {
+            'i'
+        };
+        let alpn = match self.alpn.as_deref() {
+            Some(value) if !value.is_empty() => {
+                let (first, last) = (value[0], value[value.len() - 1]);
+                if first.is_ascii_alphanumeric() && last.is_ascii_alphanumeric() {
+                    format!("{}{}", first as char, last as char)
+                } else {
+                    let hex = hex(value);
+                    format!("{}{}", &hex[..1], &hex[hex.len() - 1..])
+                }
+            }
+            _ => "00".to_string(),
+        };
+
+        let mut sorted_ciphers = ciphers.clone();
+        sorted_ciphers.sort_unstable();
+        let mut sorted_extensions: Vec<u16> = extensions
+            .iter()
+            .copied()
+            .filter(|&e| e != EXT_SERVER_NAME && e != EXT_ALPN)
+            .collect();
+        sorted_extensions.sort_unstable();
+        let mut extension_input = hex_list(&sorted_extensions);
+        let signature_algorithms = without_grease(&self.signature_algorithms);
+        if !signature_algorithms.is_empty() {
+            extension_input.push('_');
+            extension_input.push_str(&hex_list(&signature_algorithms));
+        }
+
+        format!(
+            "t{}{}{:02}{:02}{}_{}_{}",
+            version,
+            sni,
+            ciphers.len().min(99),
+            extensions.len().min(99),
+            alpn,
+            truncated_hash(&hex_list(&sorted_ciphers), sorted_ciphers.is_empty()),
+            truncated_hash(&extension_input, sorted_extensions.is_empty())
+        )
+    }
+}
+
+/// Big-endian reader over a byte slice
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Bytes<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    /// A vector with a one-byte length prefix
+    fn vec8(&mut self) -> Option<Bytes<'a>> {
+        let len = self.u8()?;
+        self.take(usize::from(len)).map(Bytes)
+    }
+
+    /// A vector with a two-byte length prefix
+    fn vec16(&mut self) -> Option<Bytes<'a>> {
+        let len = self.u16()?;
+        self.take(usize::from(len)).map(Bytes)
+    }
+
+    fn u16s(mut self) -> Option<Vec<u16>> {
+        let mut values = Vec::with_capacity(self.0.len() / 2);
+        while !self.0.is_empty() {
+            values.push(self.u16()?);
+        }
+        Some(values)
+    }
+}
+
+/// GREASE values are `0x?a?a` with both bytes equal
+fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+fn without_grease(values: &[u16]) -> Vec<u16> {
+    values.iter().copied().filter(|&v| !is_grease(v)).collect()
+}
+
+/// Comma-separated four-digit hex values
+fn hex_list(values: &[u16]) -> String {
+    values
+        .iter()
+        .map(|v| format!("{:04x}", v))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// First 12 hex digits of the SHA-256 of `input`, or zeros if `empty`
+fn truncated_hash(input: &str, empty: bool) -> String {
+    if empty {
+        return "0".repeat(12);
+    }
+    hex(&Sha256::digest(input.as_bytes()))[..12].to_string()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(out, "{:02x}", byte);
+    }
+    out
+}
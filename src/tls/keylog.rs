@@ -0,0 +1,81 @@
+//! NSS key log output
+//!
+//! Writes TLS session secrets in the format Wireshark and curl understand
+//! (`SSLKEYLOGFILE`), one line per secret:
+//!
+//! ```text
+//! CLIENT_HANDSHAKE_TRAFFIC_SECRET <client random hex> <secret hex>
+//! ```
+//!
+//! Anyone holding this file can decrypt the captured traffic, so it is
+//! created owner-readable only and should never be enabled in production.
+
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Environment variable naming a key log file, as in browsers and curl
+pub const KEY_LOG_ENV: &str = "SSLKEYLOGFILE";
+
+/// Appends session secrets to a key log file
+pub struct KeyLogFile {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl KeyLogFile {
+    /// Open (or create) `path` for appending
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut options = OpenOptions::new();
+        options.create(true).append(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(options.open(path)?),
+        })
+    }
+
+    /// Path of the key log file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl rustls::KeyLog for KeyLogFile {
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        // One write per line, so concurrent handshakes never interleave
+        let mut line =
+            String::with_capacity(label.len() + 2 * (client_random.len() + secret.len()) + 3);
+        line.push_str(label);
+        line.push(' ');
+        push_hex(&mut line, client_random);
+        line.push(' ');
+        push_hex(&mut line, secret);
+        line.push('\n');
+
+        if let Err(e) = self.file.lock().unwrap().write_all(line.as_bytes()) {
+            tracing::warn!(path = %self.path.display(), error = %e, "Failed to write TLS key log");
+        }
+    }
+
+    fn will_log(&self, _label: &str) -> bool {
+        true
+    }
+}
+
+impl std::fmt::Debug for KeyLogFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyLogFile")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+fn push_hex(out: &mut String, bytes: &[u8]) {
+    for byte in bytes {
+        let _ = write!(out, "{:02x}", byte);
+    }
+}
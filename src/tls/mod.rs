@@ -0,0 +1,394 @@
+//! TLS for client connections and `https://` backends
+//!
+//! Built on rustls (with the `ring` provider):
+//!
+//! - [`server_config`]: certificates and keys for terminating client TLS,
+//!   served through a [`CertResolver`] that picks one by SNI, and lets the
+//!   certificates (and the OCSP staple, see [`ocsp`]) be swapped without
+//!   rebuilding the config; [`reload`] does so when the files change
+//! - [`client_config`]: trust roots for backends, the Mozilla root store
+//!   plus an optional CA bundle, and a cache of sessions to resume
+//! - [`client_cert`]: client certificates verified against
+//!   `client_auth.ca_file` (mutual TLS), attached to requests
+//!
+//! Either side can log session secrets through [`keylog`] so captures can
+//! be decrypted in Wireshark: set `key_log_file` in the matching config
+//! section, or the `SSLKEYLOGFILE` environment variable for both.
+
+pub mod client_cert;
+mod der;
+pub mod fingerprint;
+pub mod keylog;
+pub mod ocsp;
+pub mod reload;
+
+pub use client_cert::ClientCertificate;
+pub use fingerprint::{ClientHelloRecorder, TlsFingerprint};
+pub use keylog::{KEY_LOG_ENV, KeyLogFile};
+pub use ocsp::OcspStapler;
+pub use reload::CertReloader;
+
+use crate::config::{ClientAuthConfig, ClientAuthMode, TlsConfig, TlsVersion, UpstreamTlsConfig};
+use anyhow::Context;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+
+/// Sessions remembered per backend when `session_cache_size` is not set
+pub const DEFAULT_SESSION_CACHE_SIZE: usize = 256;
+
+/// Serves the certificate for each handshake's SNI name, or the current
+/// default certificate
+///
+/// The certificates can be replaced while connections are being accepted,
+/// e.g. to attach a fresh OCSP staple or serve renewed certificates.
+#[derive(Debug)]
+pub struct CertResolver {
+    certificates: RwLock<Certificates>,
+}
+
+/// Everything a [`CertResolver`] serves, swapped as a whole
+#[derive(Debug)]
+struct Certificates {
+    default: Arc<CertifiedKey>,
+    /// By lowercase hostname, wildcards as `*.example.com`
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl CertResolver {
+    /// Load the certificate chains and keys named by `config`
+    pub fn load(config: &TlsConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            certificates: RwLock::new(Certificates::load(config)?),
+        })
+    }
+
+    /// The default certificate handed to new handshakes
+    pub fn current(&self) -> Arc<CertifiedKey> {
+        self.certificates.read().unwrap().default.clone()
+    }
+
+    /// The certificate for clients asking for `server_name`: an exact
+    /// match, then a wildcard for its parent domain, then the default
+    pub fn for_name(&self, server_name: Option<&str>) -> Arc<CertifiedKey> {
+        let certificates = self.certificates.read().unwrap();
+        let Some(name) = server_name.map(|n| n.trim_end_matches('.').to_ascii_lowercase()) else {
+            return certificates.default.clone();
+        };
+        let wildcard = name
+            .split_once('.')
+            .map(|(_, parent)| format!("*.{}", parent));
+        certificates
+            .by_name
+            .get(&name)
+            .or_else(|| wildcard.and_then(|w| certificates.by_name.get(&w)))
+            .unwrap_or(&certificates.default)
+            .clone()
+    }
+
+    /// Serve `certified` as the default certificate from the next
+    /// handshake on
+    pub fn replace(&self, certified: CertifiedKey) {
+        self.certificates.write().unwrap().default = Arc::new(certified);
+    }
+
+    /// Staple `ocsp` to the default certificate, if its leaf is still
+    /// `leaf`
+    ///
+    /// Returns false, leaving the certificate alone, once it has been
+    /// reloaded.
+    pub fn set_ocsp(&self, leaf: &CertificateDer<'_>, ocsp: Option<Vec<u8>>) -> bool {
+        let mut certificates = self.certificates.write().unwrap();
+        if certificates.default.cert.first() != Some(leaf) {
+            return false;
+        }
+        let mut certified = (*certificates.default).clone();
+        certified.ocsp = ocsp;
+        certificates.default = Arc::new(certified);
+        true
+    }
+
+    /// Load the certificates named by `config` again and serve them from
+    /// the next handshake on
+    ///
+    /// Nothing changes if any of them fails to load. A default
+    /// certificate whose leaf is unchanged keeps its OCSP staple.
+    pub fn reload(&self, config: &TlsConfig) -> anyhow::Result<()> {
+        let mut loaded = Certificates::load(config)?;
+        let mut certificates = self.certificates.write().unwrap();
+        if loaded.default.cert.first() == certificates.default.cert.first() {
+            let mut certified = (*loaded.default).clone();
+            certified.ocsp = certificates.default.ocsp.clone();
+            loaded.default = Arc::new(certified);
+        }
+        *certificates = loaded;
+        Ok(())
+    }
+}
+
+impl Certificates {
+    fn load(config: &TlsConfig) -> anyhow::Result<Self> {
+        let certified = load_certified(&config.cert_file, &config.key_file)?;
+        let mut by_name = HashMap::new();
+        for (name, cert) in &config.certificates {
+            let name = name.trim_end_matches('.').to_ascii_lowercase();
+            let host = name.strip_prefix("*.").unwrap_or(&name);
+            if host.is_empty()
+                || !host
+                    .split('.')
+                    .all(|label| !label.is_empty() && label.chars().all(is_host_char))
+            {
+                anyhow::bail!("Invalid TLS certificate hostname: {:?}", name);
+            }
+            let certified = load_certified(&cert.cert_file, &cert.key_file)
+                .with_context(|| format!("Failed to load the certificate for {}", name))?;
+            if by_name.insert(name.clone(), Arc::new(certified)).is_some() {
+                anyhow::bail!("TLS certificate hostname {} is configured twice", name);
+            }
+        }
+        Ok(Self {
+            default: Arc::new(certified),
+            by_name,
+        })
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.for_name(client_hello.server_name()))
+    }
+}
+
+/// Build the server side of TLS termination
+pub fn server_config(config: &TlsConfig) -> anyhow::Result<Arc<rustls::ServerConfig>> {
+    server_config_with(config, Arc::new(CertResolver::load(config)?))
+}
+
+/// Build the server side of TLS termination around an existing resolver
+pub fn server_config_with(
+    config: &TlsConfig,
+    resolver: Arc<CertResolver>,
+) -> anyhow::Result<Arc<rustls::ServerConfig>> {
+    config.validate()?;
+    let provider = crypto_provider(config)?;
+    let mut versions = Vec::new();
+    for version in [TlsVersion::Tls12, TlsVersion::Tls13] {
+        if !(config.min_version..=config.max_version).contains(&version) {
+            continue;
+        }
+        let supported = protocol_version(version);
+        if !provider
+            .cipher_suites
+            .iter()
+            .any(|suite| suite.version() == supported)
+        {
+            anyhow::bail!(
+                "TLS cipher_suites has none for TLS {}; add one or narrow min_version/max_version",
+                version
+            );
+        }
+        versions.push(supported);
+    }
+
+    let builder = rustls::ServerConfig::builder_with_provider(Arc::new(provider))
+        .with_protocol_versions(&versions)
+        .context("Invalid TLS protocol versions")?;
+    let mut server = match &config.client_auth {
+        Some(client_auth) => builder.with_client_cert_verifier(client_verifier(client_auth)?),
+        None => builder.with_no_client_auth(),
+    }
+    .with_cert_resolver(resolver);
+    server.alpn_protocols = config
+        .alpn
+        .iter()
+        .map(|protocol| protocol.as_bytes().to_vec())
+        .collect();
+    if let Some(key_log) = key_log(config.key_log_file.as_deref())? {
+        server.key_log = key_log;
+    }
+    Ok(Arc::new(server))
+}
+
+/// The ring provider, limited to `config.cipher_suites` in their order if
+/// any are listed
+fn crypto_provider(config: &TlsConfig) -> anyhow::Result<rustls::crypto::CryptoProvider> {
+    let mut provider = rustls::crypto::ring::default_provider();
+    if config.cipher_suites.is_empty() {
+        return Ok(provider);
+    }
+    let mut suites = Vec::with_capacity(config.cipher_suites.len());
+    for name in &config.cipher_suites {
+        let suite = provider
+            .cipher_suites
+            .iter()
+            .find(|suite| {
+                suite
+                    .suite()
+                    .as_str()
+                    .is_some_and(|known| known.eq_ignore_ascii_case(name))
+            })
+            .with_context(|| {
+                let supported: Vec<&str> = provider
+                    .cipher_suites
+                    .iter()
+                    .filter_map(|suite| suite.suite().as_str())
+                    .collect();
+                format!(
+                    "Unsupported TLS cipher suite {}; supported: {}",
+                    name,
+                    supported.join(", ")
+                )
+            })?;
+        suites.push(*suite);
+    }
+    provider.cipher_suites = suites;
+    Ok(provider)
+}
+
+fn protocol_version(version: TlsVersion) -> &'static rustls::SupportedProtocolVersion {
+    match version {
+        TlsVersion::Tls12 => &rustls::version::TLS12,
+        TlsVersion::Tls13 => &rustls::version::TLS13,
+    }
+}
+
+/// Verifier for client certificates chaining to `config.ca_file`
+fn client_verifier(
+    config: &ClientAuthConfig,
+) -> anyhow::Result<Arc<dyn rustls::server::danger::ClientCertVerifier>> {
+    let mut roots = rustls::RootCertStore::empty();
+    for cert in load_certs(&config.ca_file)? {
+        roots.add(cert).with_context(|| {
+            format!(
+                "Invalid client CA certificate in {}",
+                config.ca_file.display()
+            )
+        })?;
+    }
+    let mut verifier = rustls::server::WebPkiClientVerifier::builder_with_provider(
+        Arc::new(roots),
+        Arc::new(rustls::crypto::ring::default_provider()),
+    );
+    if config.mode == ClientAuthMode::Optional {
+        verifier = verifier.allow_unauthenticated();
+    }
+    verifier
+        .build()
+        .context("Failed to build the client certificate verifier")
+}
+
+/// Build the client side used for `https://` backends
+pub fn client_config(config: &UpstreamTlsConfig) -> anyhow::Result<Arc<rustls::ClientConfig>> {
+    let mut roots =
+        rustls::RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    if let Some(ca_file) = &config.ca_file {
+        for cert in load_certs(ca_file)? {
+            roots
+                .add(cert)
+                .with_context(|| format!("Invalid CA certificate in {}", ca_file.display()))?;
+        }
+    }
+
+    let mut client = rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    if let Some(key_log) = key_log(config.key_log_file.as_deref())? {
+        client.key_log = key_log;
+    }
+    client.resumption = session_cache(config.session_cache_size);
+    Ok(Arc::new(client))
+}
+
+/// Client config for backends when `proxy.tls` is not configured
+///
+/// Built once per process.
+pub fn default_client_config() -> Arc<rustls::ClientConfig> {
+    static DEFAULT: OnceLock<Arc<rustls::ClientConfig>> = OnceLock::new();
+    DEFAULT
+        .get_or_init(|| {
+            client_config(&UpstreamTlsConfig::default()).unwrap_or_else(|e| {
+                tracing::warn!(error = %format!("{:#}", e), "TLS key logging disabled");
+                Arc::new(
+                    rustls::ClientConfig::builder()
+                        .with_root_certificates(rustls::RootCertStore::from_iter(
+                            webpki_roots::TLS_SERVER_ROOTS.iter().cloned(),
+                        ))
+                        .with_no_client_auth(),
+                )
+            })
+        })
+        .clone()
+}
+
+/// In-memory store of up to `size` sessions to resume, or none if `size`
+/// is 0
+pub fn session_cache(size: usize) -> rustls::client::Resumption {
+    match size {
+        0 => rustls::client::Resumption::disabled(),
+        size => rustls::client::Resumption::in_memory_sessions(size),
+    }
+}
+
+/// Key log for `configured`, else for `SSLKEYLOGFILE`, else none
+///
+/// Every config naming the same file shares one writer.
+fn key_log(configured: Option<&Path>) -> anyhow::Result<Option<Arc<dyn rustls::KeyLog>>> {
+    static OPEN: OnceLock<Mutex<HashMap<PathBuf, Arc<KeyLogFile>>>> = OnceLock::new();
+
+    let path = match configured {
+        Some(path) => path.to_path_buf(),
+        None => match std::env::var_os(KEY_LOG_ENV).filter(|v| !v.is_empty()) {
+            Some(path) => PathBuf::from(path),
+            None => return Ok(None),
+        },
+    };
+
+    let mut open = OPEN.get_or_init(Default::default).lock().unwrap();
+    if let Some(file) = open.get(&path) {
+        return Ok(Some(file.clone()));
+    }
+    let file = Arc::new(
+        KeyLogFile::open(&path)
+            .with_context(|| format!("Failed to open TLS key log {}", path.display()))?,
+    );
+    tracing::warn!(
+        path = %path.display(),
+        "Logging TLS session secrets; anyone with this file can decrypt traffic"
+    );
+    open.insert(path, file.clone());
+    Ok(Some(file))
+}
+
+fn load_certified(cert_file: &Path, key_file: &Path) -> anyhow::Result<CertifiedKey> {
+    let certs = load_certs(cert_file)?;
+    let key = load_key(key_file)?;
+    CertifiedKey::from_der(certs, key, &rustls::crypto::ring::default_provider())
+        .context("TLS certificate and key do not match")
+}
+
+fn is_host_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_'
+}
+
+fn load_certs(path: &Path) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let pem = std::fs::read(path)
+        .with_context(|| format!("Failed to read certificates from {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut pem.as_slice())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Invalid PEM in {}", path.display()))?;
+    if certs.is_empty() {
+        anyhow::bail!("No certificates found in {}", path.display());
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> anyhow::Result<PrivateKeyDer<'static>> {
+    let pem = std::fs::read(path)
+        .with_context(|| format!("Failed to read private key from {}", path.display()))?;
+    rustls_pemfile::private_key(&mut pem.as_slice())
+        .with_context(|| format!("Invalid PEM in {}", path.display()))?
+        .with_context(|| format!("No private key found in {}", path.display()))
+}
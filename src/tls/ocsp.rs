@@ -0,0 +1,454 @@
+//! OCSP stapling
+//!
+//! An [`OcspStapler`] asks the certificate's OCSP responder whether the
+//! certificate is still good and attaches the signed answer to the
+//! [`CertResolver`]'s certificate, so clients get revocation status in the
+//! handshake instead of querying the CA themselves. Responses are refetched
+//! in the background; one that reaches its `nextUpdate` without being
+//! replaced is dropped rather than stapled stale. Each stapler serves one
+//! certificate: once it is reloaded, responses are no longer stapled and a
+//! new stapler takes over (see [`CertReloader`](crate::tls::CertReloader)).
+//!
+//! Responses are checked to cover the certificate and report it as good,
+//! but their signatures are left to clients to verify.
+//!
+//! | Name | Type | Labels |
+//! |------|------|--------|
+//! | `sentinel_ocsp_fetches_total` | counter | `certificate`, `outcome` (`success`, `error`) |
+//! | `sentinel_ocsp_staple_valid` | gauge | `certificate` |
+//! | `sentinel_ocsp_staple_age_seconds` | gauge | `certificate` |
+//! | `sentinel_ocsp_staple_expiry_seconds` | gauge | `certificate` |
+//!
+//! The age is measured from the response's `thisUpdate` and the expiry
+//! counts down to its `nextUpdate`; both are only published while a staple
+//! is served.
+
+use crate::config::{OcspConfig, TlsConfig};
+use crate::discovery::http::post_url;
+use crate::metrics::Metrics;
+use crate::tls::CertResolver;
+use crate::tls::der::{self, Reader};
+use anyhow::Context;
+use rustls::pki_types::CertificateDer;
+use sha1::{Digest, Sha1};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio_util::sync::CancellationToken;
+
+/// How often staple gauges are updated between fetches
+pub const OCSP_REPORT_SECS: u64 = 30;
+
+const SHA1: &str = "1.3.14.3.2.26";
+const AUTHORITY_INFO_ACCESS: &str = "1.3.6.1.5.5.7.1.1";
+const ACCESS_METHOD_OCSP: &str = "1.3.6.1.5.5.7.48.1";
+const BASIC_RESPONSE: &str = "1.3.6.1.5.5.7.48.1.1";
+
+const STATUS_GOOD: u8 = der::implicit(0);
+const STATUS_REVOKED: u8 = der::explicit(1);
+/// `uniformResourceIdentifier` in a GeneralName
+const URI: u8 = der::implicit(6);
+
+/// Validity of a stapled OCSP response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Staple {
+    /// When the responder produced the status
+    pub this_update: SystemTime,
+    /// When the status stops being valid, if the responder said
+    pub next_update: Option<SystemTime>,
+}
+
+/// Keeps an OCSP response stapled to a certificate
+pub struct OcspStapler {
+    resolver: Arc<CertResolver>,
+    /// The certificate responses are stapled to
+    leaf: CertificateDer<'static>,
+    config: OcspConfig,
+    /// Metric label naming the certificate
+    certificate: String,
+    responder: url::Url,
+    /// DER `OCSPRequest` for the certificate
+    request: Vec<u8>,
+    /// Contents of the certificate's serial number
+    serial: Vec<u8>,
+    staple: Mutex<Option<Staple>>,
+    metrics: Metrics,
+}
+
+impl OcspStapler {
+    /// Staple responses for the certificate `resolver` serves
+    ///
+    /// Fails if the chain has no issuer certificate or no responder is
+    /// known for it.
+    pub fn new(resolver: Arc<CertResolver>, tls: &TlsConfig) -> anyhow::Result<Self> {
+        let config = tls.ocsp.clone().unwrap_or_default();
+        let certified = resolver.current();
+        let leaf = parse_leaf(&certified.cert[0]).context("Invalid TLS certificate")?;
+        let issuer = certified.cert.get(1).with_context(|| {
+            format!(
+                "OCSP stapling needs the issuer certificate after the leaf in {}",
+                tls.cert_file.display()
+            )
+        })?;
+        let issuer_key = parse_public_key(issuer).context("Invalid issuer certificate")?;
+
+        let responder = config
+            .responder_url
+            .clone()
+            .or(leaf.responder)
+            .with_context(|| {
+                format!(
+                    "{} has no OCSP responder; set responder_url",
+                    tls.cert_file.display()
+                )
+            })?;
+        let responder = url::Url::parse(&responder)
+            .with_context(|| format!("Invalid OCSP responder URL {}", responder))?;
+
+        let cert_id = der::sequence(&[
+            &der::sequence(&[&der::oid(SHA1), &der::encode(der::NULL, &[])]),
+            &der::encode(der::OCTET_STRING, &Sha1::digest(leaf.issuer)),
+            &der::encode(der::OCTET_STRING, &Sha1::digest(issuer_key)),
+            leaf.serial.raw,
+        ]);
+        // OCSPRequest { TBSRequest { requestList { Request { CertID } } } }
+        let request = der::sequence(&[&der::sequence(&[&der::sequence(&[&der::sequence(&[
+            &cert_id,
+        ])])])]);
+
+        let served = certified.cert[0].clone();
+        Ok(Self {
+            resolver,
+            leaf: served,
+            config,
+            certificate: tls.cert_file.display().to_string(),
+            responder,
+            request,
+            serial: leaf.serial.value.to_vec(),
+            staple: Mutex::new(None),
+            metrics: Metrics::default(),
+        })
+    }
+
+    /// Publish counters and gauges through the given recorder
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Responder that is queried
+    pub fn responder(&self) -> &url::Url {
+        &self.responder
+    }
+
+    /// The response currently stapled, if any
+    pub fn staple(&self) -> Option<Staple> {
+        *self.staple.lock().unwrap()
+    }
+
+    /// Fetch a fresh response and staple it
+    ///
+    /// A revoked certificate loses its staple; any other failure leaves the
+    /// current one in place.
+    pub async fn refresh(&self) -> anyhow::Result<Staple> {
+        let result = self.fetch().await;
+        let outcome = if result.is_ok() { "success" } else { "error" };
+        self.metrics.increment(
+            "sentinel_ocsp_fetches_total",
+            &[("certificate", &self.certificate), ("outcome", outcome)],
+        );
+
+        match result {
+            Ok((staple, response)) => {
+                if !self.resolver.set_ocsp(&self.leaf, Some(response)) {
+                    anyhow::bail!("{} has been reloaded", self.certificate);
+                }
+                *self.staple.lock().unwrap() = Some(staple);
+                Ok(staple)
+            }
+            Err(Fetch::Revoked) => {
+                self.unstaple();
+                anyhow::bail!("{} has been revoked", self.certificate)
+            }
+            Err(Fetch::Failed(e)) => Err(e),
+        }
+    }
+
+    /// Drop an expired staple and update the gauges
+    pub fn report(&self, now: SystemTime) {
+        let labels = [("certificate", self.certificate.as_str())];
+        let staple = self.staple();
+        if staple
+            .and_then(|s| s.next_update)
+            .is_some_and(|next| next <= now)
+        {
+            tracing::warn!(certificate = %self.certificate, "OCSP staple expired, no longer stapling");
+            self.unstaple();
+        }
+
+        match self.staple() {
+            Some(staple) => {
+                self.metrics
+                    .gauge("sentinel_ocsp_staple_valid", &labels, 1.0);
+                self.metrics.gauge(
+                    "sentinel_ocsp_staple_age_seconds",
+                    &labels,
+                    signed_secs(staple.this_update, now),
+                );
+                if let Some(next_update) = staple.next_update {
+                    self.metrics.gauge(
+                        "sentinel_ocsp_staple_expiry_seconds",
+                        &labels,
+                        signed_secs(now, next_update),
+                    );
+                }
+            }
+            None => self
+                .metrics
+                .gauge("sentinel_ocsp_staple_valid", &labels, 0.0),
+        }
+    }
+
+    /// Keep the staple fresh until cancelled
+    ///
+    /// Fetches every `refresh_secs` (or at half the remaining validity if
+    /// that is sooner), retrying every `retry_secs` after a failure.
+    pub async fn run(self: Arc<Self>, cancel: CancellationToken) {
+        tracing::info!(
+            certificate = %self.certificate,
+            responder = %self.responder,
+            "Starting OCSP stapling"
+        );
+
+        let mut next_fetch = Instant::now();
+        loop {
+            if Instant::now() >= next_fetch {
+                let wait = match self.refresh().await {
+                    Ok(staple) => {
+                        tracing::debug!(certificate = %self.certificate, "Stapled fresh OCSP response");
+                        self.refresh_after(&staple)
+                    }
+                    Err(e) => {
+                        tracing::warn!(
+                            certificate = %self.certificate,
+                            error = %format!("{:#}", e),
+                            "OCSP fetch failed"
+                        );
+                        Duration::from_secs(self.config.retry_secs)
+                    }
+                };
+                next_fetch = Instant::now() + wait;
+            }
+            self.report(SystemTime::now());
+
+            let until_fetch = next_fetch.saturating_duration_since(Instant::now());
+            tokio::select! {
+                _ = tokio::time::sleep(until_fetch.min(Duration::from_secs(OCSP_REPORT_SECS))) => {}
+                _ = cancel.cancelled() => return,
+            }
+        }
+    }
+
+    fn refresh_after(&self, staple: &Staple) -> Duration {
+        let refresh = Duration::from_secs(self.config.refresh_secs);
+        match staple
+            .next_update
+            .and_then(|next| next.duration_since(SystemTime::now()).ok())
+        {
+            Some(remaining) => {
+                refresh.min((remaining / 2).max(Duration::from_secs(self.config.retry_secs)))
+            }
+            None => refresh,
+        }
+    }
+
+    fn unstaple(&self) {
+        self.resolver.set_ocsp(&self.leaf, None);
+        *self.staple.lock().unwrap() = None;
+    }
+
+    async fn fetch(&self) -> Result<(Staple, Vec<u8>), Fetch> {
+        let response = post_url(
+            &self.responder,
+            "application/ocsp-request",
+            &self.request,
+            Duration::from_millis(self.config.timeout_ms),
+        )
+        .await
+        .with_context(|| format!("Failed to query OCSP responder {}", self.responder))?;
+        if response.status != 200 {
+            return Err(anyhow::anyhow!(
+                "OCSP responder {} returned {}",
+                self.responder,
+                response.status
+            )
+            .into());
+        }
+
+        let staple = self.parse_response(&response.body)?;
+        if staple
+            .next_update
+            .is_some_and(|next| next <= SystemTime::now())
+        {
+            return Err(anyhow::anyhow!("OCSP response has already expired").into());
+        }
+        Ok((staple, response.body))
+    }
+
+    /// Find this certificate's status in a DER `OCSPResponse`
+    fn parse_response(&self, body: &[u8]) -> Result<Staple, Fetch> {
+        let response = Reader::new(body).expect(der::SEQUENCE)?;
+        let mut response = response.reader();
+        let status = response.expect(der::ENUMERATED)?;
+        if status.value != [0] {
+            return Err(anyhow::anyhow!(
+                "OCSP responder refused the request (status {:?})",
+                status.value
+            )
+            .into());
+        }
+
+        let bytes = response
+            .expect(der::explicit(0))?
+            .reader()
+            .expect(der::SEQUENCE)?;
+        let mut bytes = bytes.reader();
+        if bytes.expect(der::OID)?.raw != der::oid(BASIC_RESPONSE) {
+            return Err(anyhow::anyhow!("Unsupported OCSP response type").into());
+        }
+        let basic = Reader::new(bytes.expect(der::OCTET_STRING)?.value).expect(der::SEQUENCE)?;
+        let data = basic.reader().expect(der::SEQUENCE)?;
+        let mut data = data.reader();
+        data.optional(der::explicit(0))?; // version
+        data.next()?; // responderID
+        data.expect(der::GENERALIZED_TIME)?; // producedAt
+
+        let mut responses = data.expect(der::SEQUENCE)?.reader();
+        while !responses.is_empty() {
+            let mut single = responses.expect(der::SEQUENCE)?.reader();
+            let mut cert_id = single.expect(der::SEQUENCE)?.reader();
+            cert_id.expect(der::SEQUENCE)?; // hashAlgorithm
+            cert_id.expect(der::OCTET_STRING)?; // issuerNameHash
+            cert_id.expect(der::OCTET_STRING)?; // issuerKeyHash
+            if cert_id.expect(der::INTEGER)?.value != self.serial.as_slice() {
+                continue;
+            }
+
+            let status = single.next()?;
+            let this_update = der::generalized_time(single.expect(der::GENERALIZED_TIME)?.value)?;
+            let next_update = match single.optional(der::explicit(0))? {
+                Some(next) => Some(der::generalized_time(
+                    next.reader().expect(der::GENERALIZED_TIME)?.value,
+                )?),
+                None => None,
+            };
+            return match status.tag {
+                STATUS_GOOD => Ok(Staple {
+                    this_update,
+                    next_update,
+                }),
+                STATUS_REVOKED => Err(Fetch::Revoked),
+                _ => Err(anyhow::anyhow!("OCSP responder does not know the certificate").into()),
+            };
+        }
+        Err(anyhow::anyhow!("OCSP response does not cover the certificate").into())
+    }
+}
+
+/// Why a fetch produced no staple
+enum Fetch {
+    Revoked,
+    Failed(anyhow::Error),
+}
+
+impl From<anyhow::Error> for Fetch {
+    fn from(e: anyhow::Error) -> Self {
+        Fetch::Failed(e)
+    }
+}
+
+/// Fields of a leaf certificate needed to ask about it
+struct Leaf<'a> {
+    serial: der::Element<'a>,
+    /// DER issuer Name
+    issuer: &'a [u8],
+    /// First OCSP URL in the Authority Information Access extension
+    responder: Option<String>,
+}
+
+fn parse_leaf(cert: &[u8]) -> anyhow::Result<Leaf<'_>> {
+    let mut tbs = tbs_certificate(cert)?;
+    let serial = tbs.expect(der::INTEGER)?;
+    tbs.expect(der::SEQUENCE)?; // signature
+    let issuer = tbs.expect(der::SEQUENCE)?.raw;
+    tbs.expect(der::SEQUENCE)?; // validity
+    tbs.expect(der::SEQUENCE)?; // subject
+    tbs.expect(der::SEQUENCE)?; // subjectPublicKeyInfo
+    tbs.optional(der::implicit(1))?; // issuerUniqueID
+    tbs.optional(der::implicit(2))?; // subjectUniqueID
+
+    let mut responder = None;
+    if let Some(extensions) = tbs.optional(der::explicit(3))? {
+        let mut extensions = extensions.reader().expect(der::SEQUENCE)?.reader();
+        while !extensions.is_empty() && responder.is_none() {
+            let mut extension = extensions.expect(der::SEQUENCE)?.reader();
+            let id = extension.expect(der::OID)?;
+            extension.optional(0x01)?; // critical
+            let value = extension.expect(der::OCTET_STRING)?;
+            if id.raw == der::oid(AUTHORITY_INFO_ACCESS) {
+                responder = ocsp_url(value.value)?;
+            }
+        }
+    }
+
+    Ok(Leaf {
+        serial,
+        issuer,
+        responder,
+    })
+}
+
+/// The OCSP URL in an `AuthorityInfoAccessSyntax`
+fn ocsp_url(aia: &[u8]) -> anyhow::Result<Option<String>> {
+    let mut descriptions = Reader::new(aia).expect(der::SEQUENCE)?.reader();
+    let ocsp = der::oid(ACCESS_METHOD_OCSP);
+    while !descriptions.is_empty() {
+        let mut description = descriptions.expect(der::SEQUENCE)?.reader();
+        let method = description.expect(der::OID)?;
+        let location = description.next()?;
+        if method.raw == ocsp && location.tag == URI {
+            return Ok(Some(String::from_utf8_lossy(location.value).into_owned()));
+        }
+    }
+    Ok(None)
+}
+
+/// The subject public key bits of a certificate
+fn parse_public_key(cert: &[u8]) -> anyhow::Result<&[u8]> {
+    let mut tbs = tbs_certificate(cert)?;
+    tbs.expect(der::INTEGER)?; // serialNumber
+    tbs.expect(der::SEQUENCE)?; // signature
+    tbs.expect(der::SEQUENCE)?; // issuer
+    tbs.expect(der::SEQUENCE)?; // validity
+    tbs.expect(der::SEQUENCE)?; // subject
+    let mut spki = tbs.expect(der::SEQUENCE)?.reader();
+    spki.expect(der::SEQUENCE)?; // algorithm
+    let key = spki.expect(der::BIT_STRING)?;
+    // Skip the unused-bits count
+    key.value.get(1..).context("Empty public key")
+}
+
+/// Reader positioned at a certificate's serial number
+fn tbs_certificate(cert: &[u8]) -> anyhow::Result<Reader<'_>> {
+    let certificate = Reader::new(cert).expect(der::SEQUENCE)?;
+    let mut tbs = certificate.reader().expect(der::SEQUENCE)?.reader();
+    tbs.optional(der::explicit(0))?; // version
+    Ok(tbs)
+}
+
+/// Seconds from `from` to `to`, negative if `to` is earlier
+fn signed_secs(from: SystemTime, to: SystemTime) -> f64 {
+    match to.duration_since(from) {
+        Ok(d) => d.as_secs_f64(),
+        Err(e) => -e.duration().as_secs_f64(),
+    }
+}
@@ -0,0 +1,228 @@
+//! Certificate hot reload
+//!
+//! A [`CertReloader`] keeps the listener's [`CertResolver`] in step with
+//! the certificate and key files named in [`TlsConfig`]. They are checked
+//! every `reload_interval_secs`, on `SIGHUP`, and when the admin API asks
+//! (`POST /tls/reload`); if any changed, all of them are loaded and swapped
+//! into the resolver at once. New handshakes get the new certificates
+//! while established connections keep the ones they started with, so
+//! rotating a certificate doesn't need a restart.
+//!
+//! Renewal tools usually write the certificate and the key one after the
+//! other. A pair that doesn't match yet fails to load, the current
+//! certificates stay in place, and the next check tries again.
+
+use crate::config::TlsConfig;
+use crate::events::{Event, Events};
+use crate::metrics::Metrics;
+use crate::tls::{CertResolver, OcspStapler};
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Reloads a [`CertResolver`] when its certificate or key files change
+pub struct CertReloader {
+    config: TlsConfig,
+    resolver: Arc<CertResolver>,
+    events: Events,
+    metrics: Metrics,
+    /// SHA-256 over the files' paths and contents as last loaded
+    loaded: Mutex<Vec<u8>>,
+    /// OCSP stapling of the default certificate, if started
+    stapling: Mutex<Option<Stapling>>,
+}
+
+struct Stapling {
+    /// Cancelled when the server shuts down
+    shutdown: CancellationToken,
+    /// Stops the stapler of the certificate being served
+    stop: CancellationToken,
+}
+
+impl CertReloader {
+    /// Reload `resolver`, which was loaded from `config`, as its files
+    /// change
+    pub fn new(config: &TlsConfig, resolver: Arc<CertResolver>) -> Self {
+        Self {
+            loaded: Mutex::new(digest(config).unwrap_or_default()),
+            config: config.clone(),
+            resolver,
+            events: Events::default(),
+            metrics: Metrics::default(),
+            stapling: Mutex::new(None),
+        }
+    }
+
+    /// Publish a `ConfigReloaded` event (source `tls`) on every reload
+    pub fn with_events(mut self, events: Events) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Publish the OCSP staplers' metrics through the given recorder
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Staple OCSP responses to the default certificate until `shutdown`,
+    /// starting over for each reloaded one
+    ///
+    /// Fails if the certificate can't be stapled (see [`OcspStapler::new`]).
+    pub fn start_ocsp(&self, shutdown: &CancellationToken) -> anyhow::Result<()> {
+        let stapler = OcspStapler::new(self.resolver.clone(), &self.config)?
+            .with_metrics(self.metrics.clone());
+        let stop = shutdown.child_token();
+        tokio::spawn(Arc::new(stapler).run(stop.clone()));
+        let previous = self.stapling.lock().unwrap().replace(Stapling {
+            shutdown: shutdown.clone(),
+            stop,
+        });
+        if let Some(previous) = previous {
+            previous.stop.cancel();
+        }
+        Ok(())
+    }
+
+    /// Load the certificates again if any of their files changed since
+    /// the last load
+    ///
+    /// Returns whether they were reloaded. On failure the current
+    /// certificates are kept.
+    pub fn reload(&self) -> anyhow::Result<bool> {
+        let digest = digest(&self.config)?;
+        let mut loaded = self.loaded.lock().unwrap();
+        if *loaded == digest {
+            return Ok(false);
+        }
+        let previous = self.resolver.current();
+        self.resolver
+            .reload(&self.config)
+            .context("Failed to reload TLS certificates")?;
+        *loaded = digest;
+        drop(loaded);
+
+        tracing::info!(
+            cert = %self.config.cert_file.display(),
+            sni_certificates = self.config.certificates.len(),
+            "Reloaded TLS certificates"
+        );
+        self.events.emit(Event::ConfigReloaded {
+            source: "tls".to_string(),
+        });
+
+        // A new default certificate needs its own OCSP responses
+        let shutdown = self
+            .stapling
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|stapling| stapling.shutdown.clone());
+        if let Some(shutdown) = shutdown
+            && self.resolver.current().cert.first() != previous.cert.first()
+            && let Err(e) = self.start_ocsp(&shutdown)
+        {
+            tracing::warn!(
+                error = %format!("{:#}", e),
+                "Cannot staple OCSP responses to the reloaded certificate"
+            );
+        }
+        Ok(true)
+    }
+
+    /// Reload on changes and on `SIGHUP` until `cancel` fires
+    pub async fn run(self: Arc<Self>, cancel: CancellationToken) {
+        let interval = Duration::from_secs(self.config.reload_interval_secs);
+        let mut hangup = listen_for_hangup();
+
+        // Warn once per failure streak when polling, every time on SIGHUP
+        let mut failing = false;
+        loop {
+            let signalled = tokio::select! {
+                _ = sleep(interval) => false,
+                _ = hangup_received(&mut hangup) => true,
+                _ = cancel.cancelled() => return,
+            };
+            match self.reload() {
+                Ok(reloaded) => {
+                    if signalled && !reloaded {
+                        tracing::info!("TLS certificates unchanged");
+                    }
+                    failing = false;
+                }
+                Err(e) if signalled || !failing => {
+                    tracing::warn!(
+                        error = %format!("{:#}", e),
+                        "Keeping the current TLS certificates"
+                    );
+                    failing = true;
+                }
+                Err(_) => {}
+            }
+        }
+    }
+}
+
+/// Hash of every certificate and key file with its path
+fn digest(config: &TlsConfig) -> anyhow::Result<Vec<u8>> {
+    let mut files = vec![&config.cert_file, &config.key_file];
+    for cert in config.certificates.values() {
+        files.push(&cert.cert_file);
+        files.push(&cert.key_file);
+    }
+
+    let mut hasher = Sha256::new();
+    for path in files {
+        let contents =
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        hasher.update(path.as_os_str().as_encoded_bytes());
+        hasher.update((contents.len() as u64).to_be_bytes());
+        hasher.update(&contents);
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+/// Sleep for `interval`, or forever if it is zero
+async fn sleep(interval: Duration) {
+    if interval.is_zero() {
+        std::future::pending::<()>().await;
+    }
+    tokio::time::sleep(interval).await;
+}
+
+#[cfg(unix)]
+type Hangup = Option<tokio::signal::unix::Signal>;
+
+#[cfg(not(unix))]
+type Hangup = ();
+
+#[cfg(unix)]
+fn listen_for_hangup() -> Hangup {
+    use tokio::signal::unix::{SignalKind, signal};
+    signal(SignalKind::hangup())
+        .inspect_err(|e| {
+            tracing::warn!(error = %e, "Failed to listen for SIGHUP, TLS certificates are only reloaded on change");
+        })
+        .ok()
+}
+
+#[cfg(not(unix))]
+fn listen_for_hangup() -> Hangup {}
+
+#[cfg(unix)]
+async fn hangup_received(hangup: &mut Hangup) {
+    if let Some(signal) = hangup
+        && signal.recv().await.is_some()
+    {
+        return;
+    }
+    *hangup = None;
+    std::future::pending::<()>().await
+}
+
+#[cfg(not(unix))]
+async fn hangup_received(_hangup: &mut Hangup) {
+    std::future::pending::<()>().await
+}
@@ -1,13 +1,19 @@
 //! Tests for backend pool management
 
-use sentinel::config::BackendConfig;
-use sentinel::proxy::backend::{Backend, BackendPool, BackendState};
+use sentinel::config::{
+    BackendConfig, CircuitBreakerConfig, HashKey, LoadBalancing, LoadFeedbackConfig, LocalityConfig,
+};
+use sentinel::proxy::backend::{Backend, BackendPool, BackendState, HealthThresholds};
+use sentinel::proxy::balancer::{Affinity, Candidate, LoadBalancer, Random};
+use std::sync::Arc;
+use std::time::Duration;
 
 #[test]
 fn test_backend_creation() {
     let config = BackendConfig {
         url: "http://localhost:3000".to_string(),
         name: Some("backend-1".to_string()),
+        ..Default::default()
     };
     
     let backend = Backend::new(config);
@@ -21,6 +27,7 @@ fn test_backend_creation_without_name() {
     let config = BackendConfig {
         url: "http://localhost:3001".to_string(),
         name: None,
+        ..Default::default()
     };
     
     let backend = Backend::new(config);
@@ -34,30 +41,31 @@ fn test_backend_failure_tracking() {
     let config = BackendConfig {
         url: "http://localhost:3000".to_string(),
         name: None,
+        ..Default::default()
     };
     
     let mut backend = Backend::new(config);
     
     // Initial state
-    assert_eq!(backend.consecutive_failures, 0);
+    assert_eq!(backend.consecutive_failures(), 0);
     assert_eq!(backend.state, BackendState::Up);
     assert!(backend.is_available());
     
     // First failure
     backend.mark_failed();
-    assert_eq!(backend.consecutive_failures, 1);
+    assert_eq!(backend.consecutive_failures(), 1);
     assert!(backend.is_available());
     assert_eq!(backend.state, BackendState::Up);
     
     // Second failure
     backend.mark_failed();
-    assert_eq!(backend.consecutive_failures, 2);
+    assert_eq!(backend.consecutive_failures(), 2);
     assert!(backend.is_available());
     assert_eq!(backend.state, BackendState::Up);
     
     // Third failure - should mark as down
     backend.mark_failed();
-    assert_eq!(backend.consecutive_failures, 3);
+    assert_eq!(backend.consecutive_failures(), 3);
     assert!(!backend.is_available());
     assert_eq!(backend.state, BackendState::Down);
 }
@@ -67,6 +75,7 @@ fn test_backend_recovery() {
     let config = BackendConfig {
         url: "http://localhost:3000".to_string(),
         name: None,
+        ..Default::default()
     };
     
     let mut backend = Backend::new(config);
@@ -81,27 +90,100 @@ fn test_backend_recovery() {
     // Successful request recovers backend
     backend.mark_success();
     assert!(backend.is_available());
-    assert_eq!(backend.consecutive_failures, 0);
+    assert_eq!(backend.consecutive_failures(), 0);
     assert_eq!(backend.state, BackendState::Up);
 }
 
+#[test]
+fn test_backend_custom_thresholds() {
+    let thresholds = HealthThresholds {
+        unhealthy: 2,
+        healthy: 2,
+    };
+    let mut first = Backend::new(backend("http://localhost:3000", 1));
+
+    first.mark_failed_with(thresholds);
+    assert!(first.is_available());
+    first.mark_failed_with(thresholds);
+    assert_eq!(first.state, BackendState::Down);
+
+    // Recovery needs two successes in a row
+    first.mark_success_with(thresholds);
+    assert_eq!(first.state, BackendState::Down);
+    first.mark_failed_with(thresholds);
+    first.mark_success_with(thresholds);
+    assert_eq!(first.state, BackendState::Down);
+    first.mark_success_with(thresholds);
+    assert_eq!(first.state, BackendState::Up);
+
+    // The backend's own threshold wins over the pool's
+    let mut second = Backend::new(BackendConfig {
+        unhealthy_threshold: Some(1),
+        ..backend("http://localhost:3001", 1)
+    });
+    second.mark_failed_with(thresholds);
+    assert_eq!(second.state, BackendState::Down);
+}
+
+#[tokio::test]
+async fn test_backend_pool_health_thresholds() {
+    let pool = BackendPool::new(vec![
+        backend("http://localhost:3000", 1),
+        BackendConfig {
+            unhealthy_threshold: Some(5),
+            ..backend("http://localhost:3001", 1)
+        },
+    ])
+    .with_health_thresholds(HealthThresholds {
+        unhealthy: 1,
+        healthy: 1,
+    });
+
+    pool.mark_backend_failed("http://localhost:3000").await;
+    pool.mark_backend_failed("http://localhost:3001").await;
+    let urls = select_urls(&pool, 2).await;
+    assert_eq!(urls, vec!["http://localhost:3001", "http://localhost:3001"]);
+}
+
+#[test]
+fn test_health_threshold_config() {
+    let config: sentinel::config::ProxyConfig = serde_yaml::from_str(
+        "backends: [{url: 'http://localhost:3000', healthy_threshold: 3}]\nunhealthy_threshold: 5",
+    )
+    .unwrap();
+    assert_eq!(config.unhealthy_threshold, 5);
+    assert_eq!(config.healthy_threshold, 1);
+    assert_eq!(config.backends[0].healthy_threshold, Some(3));
+    assert!(config.validate().is_ok());
+
+    let invalid: sentinel::config::ProxyConfig =
+        serde_yaml::from_str("backends: [{url: 'http://localhost:3000', unhealthy_threshold: 0}]")
+            .unwrap();
+    assert!(invalid.validate().is_err());
+    let invalid: sentinel::config::ProxyConfig =
+        serde_yaml::from_str("backends: [{url: 'http://localhost:3000'}]\nhealthy_threshold: 0")
+            .unwrap();
+    assert!(invalid.validate().is_err());
+}
+
 #[test]
 fn test_backend_partial_failure_recovery() {
     let config = BackendConfig {
         url: "http://localhost:3000".to_string(),
         name: None,
+        ..Default::default()
     };
     
     let mut backend = Backend::new(config);
     
     // Fail once
     backend.mark_failed();
-    assert_eq!(backend.consecutive_failures, 1);
+    assert_eq!(backend.consecutive_failures(), 1);
     assert!(backend.is_available());
     
     // Recover
     backend.mark_success();
-    assert_eq!(backend.consecutive_failures, 0);
+    assert_eq!(backend.consecutive_failures(), 0);
     assert!(backend.is_available());
 }
 
@@ -111,10 +193,12 @@ async fn test_backend_pool_creation() {
         BackendConfig {
             url: "http://localhost:3000".to_string(),
             name: Some("backend-1".to_string()),
+            ..Default::default()
         },
         BackendConfig {
             url: "http://localhost:3001".to_string(),
             name: Some("backend-2".to_string()),
+            ..Default::default()
         },
     ];
     
@@ -130,10 +214,12 @@ async fn test_backend_pool_round_robin_selection() {
         BackendConfig {
             url: "http://localhost:3000".to_string(),
             name: Some("backend-1".to_string()),
+            ..Default::default()
         },
         BackendConfig {
             url: "http://localhost:3001".to_string(),
             name: Some("backend-2".to_string()),
+            ..Default::default()
         },
     ];
     
@@ -155,14 +241,17 @@ async fn test_backend_pool_skips_unavailable() {
         BackendConfig {
             url: "http://localhost:3000".to_string(),
             name: Some("backend-1".to_string()),
+            ..Default::default()
         },
         BackendConfig {
             url: "http://localhost:3001".to_string(),
             name: Some("backend-2".to_string()),
+            ..Default::default()
         },
         BackendConfig {
             url: "http://localhost:3002".to_string(),
             name: Some("backend-3".to_string()),
+            ..Default::default()
         },
     ];
     
@@ -189,6 +278,7 @@ async fn test_backend_pool_no_available_backends() {
         BackendConfig {
             url: "http://localhost:3000".to_string(),
             name: Some("backend-1".to_string()),
+            ..Default::default()
         },
     ];
     
@@ -213,6 +303,7 @@ async fn test_backend_pool_mark_success() {
         BackendConfig {
             url: "http://localhost:3000".to_string(),
             name: Some("backend-1".to_string()),
+            ..Default::default()
         },
     ];
     
@@ -230,3 +321,936 @@ async fn test_backend_pool_mark_success() {
     
     assert_eq!(pool.available_count().await, 1);
 }
+
+#[tokio::test]
+async fn test_backend_pool_replace_keeps_existing_state() {
+    let configs = vec![
+        BackendConfig {
+            url: "http://localhost:3000".to_string(),
+            name: Some("backend-1".to_string()),
+            ..Default::default()
+        },
+        BackendConfig {
+            url: "http://localhost:3001".to_string(),
+            name: Some("backend-2".to_string()),
+            ..Default::default()
+        },
+    ];
+
+    let pool = BackendPool::new(configs);
+
+    // Take backend-1 down, then replace the pool keeping backend-1
+    pool.mark_backend_failed("http://localhost:3000").await;
+    pool.mark_backend_failed("http://localhost:3000").await;
+    pool.mark_backend_failed("http://localhost:3000").await;
+
+    pool.replace_backends(vec![
+        BackendConfig {
+            url: "http://localhost:3000".to_string(),
+            name: Some("backend-1".to_string()),
+            ..Default::default()
+        },
+        BackendConfig {
+            url: "http://localhost:3002".to_string(),
+            name: Some("backend-3".to_string()),
+            ..Default::default()
+        },
+    ])
+    .await;
+
+    let backends = pool.get_backends().await;
+    assert_eq!(backends.len(), 2);
+    assert!(!backends[0].is_available()); // still down
+    assert_eq!(backends[1].url, "http://localhost:3002");
+    assert_eq!(pool.available_count().await, 1);
+}
+
+#[tokio::test]
+async fn test_backend_pool_replace_with_smaller_pool() {
+    let configs = vec![
+        BackendConfig {
+            url: "http://localhost:3000".to_string(),
+            name: None,
+            ..Default::default()
+        },
+        BackendConfig {
+            url: "http://localhost:3001".to_string(),
+            name: None,
+            ..Default::default()
+        },
+    ];
+
+    let pool = BackendPool::new(configs);
+
+    // Advance the round-robin index past the end of the new pool
+    pool.select_backend().await.unwrap();
+
+    pool.replace_backends(vec![BackendConfig {
+        url: "http://localhost:3005".to_string(),
+        name: None,
+        ..Default::default()
+    }])
+    .await;
+
+    let backend = pool.select_backend().await.unwrap();
+    assert_eq!(backend.url, "http://localhost:3005");
+
+    pool.replace_backends(vec![]).await;
+    assert!(pool.select_backend().await.is_none());
+}
+
+fn backend(url: &str, weight: u32) -> BackendConfig {
+    BackendConfig {
+        url: url.to_string(),
+        weight,
+        ..Default::default()
+    }
+}
+
+async fn select_urls(pool: &BackendPool, n: usize) -> Vec<String> {
+    let mut urls = Vec::new();
+    for _ in 0..n {
+        urls.push(pool.select_backend().await.unwrap().url);
+    }
+    urls
+}
+
+#[tokio::test]
+async fn test_backend_pool_weighted_selection() {
+    let pool = BackendPool::new(vec![
+        backend("http://localhost:3000", 3),
+        backend("http://localhost:3001", 1),
+    ]);
+
+    let urls = select_urls(&pool, 8).await;
+    let heavy = urls
+        .iter()
+        .filter(|u| *u == "http://localhost:3000")
+        .count();
+    assert_eq!(heavy, 6);
+
+    // Smooth: the light backend is not starved for a whole cycle
+    assert!(urls[..4].contains(&"http://localhost:3001".to_string()));
+}
+
+#[tokio::test]
+async fn test_backend_pool_add_and_remove() {
+    let pool = BackendPool::new(vec![backend("http://localhost:3000", 1)]);
+
+    assert!(pool.add_backend(backend("http://localhost:3001", 1)).await);
+    assert!(!pool.add_backend(backend("http://localhost:3001", 1)).await);
+    assert_eq!(pool.available_count().await, 2);
+
+    assert!(pool.remove_backend("http://localhost:3000").await);
+    assert!(!pool.remove_backend("http://localhost:3000").await);
+
+    let urls = select_urls(&pool, 2).await;
+    assert_eq!(urls, vec!["http://localhost:3001", "http://localhost:3001"]);
+}
+
+#[tokio::test]
+async fn test_backend_pool_zero_weight_drains_backend() {
+    let pool = BackendPool::new(vec![
+        backend("http://localhost:3000", 1),
+        backend("http://localhost:3001", 1),
+    ]);
+
+    assert!(pool.set_weight("http://localhost:3000", 0).await);
+    assert!(!pool.set_weight("http://localhost:9999", 5).await);
+
+    let urls = select_urls(&pool, 3).await;
+    assert!(urls.iter().all(|u| u == "http://localhost:3001"));
+
+    // Still healthy, just not receiving new requests
+    assert_eq!(pool.available_count().await, 2);
+
+    pool.set_weight("http://localhost:3001", 0).await;
+    assert!(pool.select_backend().await.is_none());
+}
+
+#[tokio::test]
+async fn test_backend_pool_set_state() {
+    let pool = BackendPool::new(vec![
+        backend("http://localhost:3000", 1),
+        backend("http://localhost:3001", 1),
+    ]);
+
+    assert!(
+        pool.set_state("http://localhost:3000", BackendState::Down)
+            .await
+    );
+    let urls = select_urls(&pool, 2).await;
+    assert!(urls.iter().all(|u| u == "http://localhost:3001"));
+
+    assert!(
+        pool.set_state("http://localhost:3000", BackendState::Up)
+            .await
+    );
+    assert_eq!(pool.available_count().await, 2);
+}
+
+#[tokio::test]
+async fn test_backend_pool_concurrent_mutation_and_selection() {
+    let pool = BackendPool::new(vec![backend("http://localhost:3000", 1)]);
+
+    let selectors: Vec<_> = (0..4)
+        .map(|_| {
+            let pool = pool.clone();
+            tokio::spawn(async move {
+                for _ in 0..200 {
+                    assert!(pool.select_backend().await.is_some());
+                }
+            })
+        })
+        .collect();
+
+    for i in 0..200 {
+        let url = format!("http://localhost:{}", 4000 + i);
+        pool.add_backend(backend(&url, 1 + i % 3)).await;
+        pool.set_weight(&url, i % 2).await;
+        pool.remove_backend(&url).await;
+    }
+
+    for selector in selectors {
+        selector.await.unwrap();
+    }
+    assert_eq!(pool.get_backends().await.len(), 1);
+}
+
+#[tokio::test]
+async fn test_backend_pool_select_matching_labels() {
+    let mut canary = backend("http://localhost:3001", 1);
+    canary
+        .labels
+        .insert("canary".to_string(), "true".to_string());
+    canary.labels.insert("zone".to_string(), "a".to_string());
+    let pool = BackendPool::new(vec![backend("http://localhost:3000", 1), canary]);
+
+    let selector = [("canary".to_string(), "true".to_string())].into();
+    for _ in 0..3 {
+        let chosen = pool.select_backend_matching(&selector).await.unwrap();
+        assert_eq!(chosen.url, "http://localhost:3001");
+        assert_eq!(chosen.labels_display(), "canary=true,zone=a");
+    }
+
+    let missing = [("zone".to_string(), "b".to_string())].into();
+    assert!(pool.select_backend_matching(&missing).await.is_none());
+}
+
+fn zoned(url: &str, zone: &str) -> BackendConfig {
+    BackendConfig {
+        zone: Some(zone.to_string()),
+        ..backend(url, 1)
+    }
+}
+
+fn locality(min_healthy_percent: u8) -> LocalityConfig {
+    LocalityConfig {
+        zone: "a".to_string(),
+        min_healthy_percent,
+    }
+}
+
+async fn take_down(pool: &BackendPool, url: &str) {
+    pool.set_state(url, BackendState::Down).await;
+}
+
+#[tokio::test]
+async fn test_backend_pool_prefers_local_zone() {
+    let pool = BackendPool::new(vec![
+        zoned("http://localhost:3000", "b"),
+        zoned("http://localhost:3001", "a"),
+        zoned("http://localhost:3002", "a"),
+    ])
+    .with_locality(locality(0));
+
+    let urls = select_urls(&pool, 4).await;
+    assert!(!urls.contains(&"http://localhost:3000".to_string()));
+
+    // Spill once the local zone has nothing healthy
+    take_down(&pool, "http://localhost:3001").await;
+    take_down(&pool, "http://localhost:3002").await;
+    let urls = select_urls(&pool, 2).await;
+    assert!(urls.iter().all(|u| u == "http://localhost:3000"));
+}
+
+#[tokio::test]
+async fn test_backend_pool_spills_below_min_healthy_percent() {
+    let pool = BackendPool::new(vec![
+        zoned("http://localhost:3000", "b"),
+        zoned("http://localhost:3001", "a"),
+        zoned("http://localhost:3002", "a"),
+    ])
+    .with_locality(locality(75));
+
+    // 1 of 2 local backends healthy is below 75%, so every zone is used
+    take_down(&pool, "http://localhost:3001").await;
+    let urls = select_urls(&pool, 4).await;
+    assert!(urls.contains(&"http://localhost:3000".to_string()));
+    assert!(urls.contains(&"http://localhost:3002".to_string()));
+}
+
+#[tokio::test]
+async fn test_backend_pool_load_reports_scale_weights() {
+    let pool = BackendPool::new(vec![
+        backend("http://localhost:3000", 1),
+        backend("http://localhost:3001", 1),
+    ])
+    .with_load_feedback(LoadFeedbackConfig {
+        smoothing: 1.0,
+        ..Default::default()
+    });
+
+    // 80% busy leaves a fifth of the weight: 1 in 6 requests
+    pool.report_load("http://localhost:3000", 0.8).await;
+    let urls = select_urls(&pool, 12).await;
+    let busy = urls
+        .iter()
+        .filter(|u| *u == "http://localhost:3000")
+        .count();
+    assert_eq!(busy, 2);
+
+    // Fully loaded backends keep the minimum share instead of starving
+    pool.report_load("http://localhost:3000", 1.0).await;
+    let urls = select_urls(&pool, 11).await;
+    assert!(urls.contains(&"http://localhost:3000".to_string()));
+}
+
+#[tokio::test]
+async fn test_backend_pool_load_reports_are_smoothed() {
+    let pool = BackendPool::new(vec![backend("http://localhost:3000", 1)]).with_load_feedback(
+        LoadFeedbackConfig {
+            smoothing: 0.5,
+            ..Default::default()
+        },
+    );
+
+    pool.report_load("http://localhost:3000", 0.2).await;
+    pool.report_load("http://localhost:3000", 1.0).await;
+
+    let load = pool.get_backends().await[0].load().unwrap();
+    assert!((load - 0.6).abs() < 1e-9);
+}
+
+#[tokio::test]
+async fn test_backend_pool_ignores_load_without_feedback() {
+    let pool = BackendPool::new(vec![backend("http://localhost:3000", 1)]);
+    pool.report_load("http://localhost:3000", 0.9).await;
+    assert!(pool.get_backends().await[0].load().is_none());
+}
+
+fn by_client(client: std::net::IpAddr) -> Affinity<'static> {
+    Affinity {
+        client: Some(client),
+        key: None,
+    }
+}
+
+fn by_key(key: &str) -> Affinity<'_> {
+    Affinity {
+        client: None,
+        key: Some(key),
+    }
+}
+
+#[tokio::test]
+async fn test_backend_pool_ip_hash_is_sticky() {
+    let pool = BackendPool::new(vec![
+        backend("http://localhost:3000", 1),
+        backend("http://localhost:3001", 1),
+        backend("http://localhost:3002", 1),
+    ])
+    .with_balancing(LoadBalancing::IpHash);
+
+    let mut chosen = Vec::new();
+    for i in 0..60u8 {
+        let client = std::net::IpAddr::from([10, 0, 0, i]);
+        let first = pool
+            .select_backend_for(by_client(client))
+            .await
+            .unwrap()
+            .url;
+        for _ in 0..3 {
+            let again = pool
+                .select_backend_for(by_client(client))
+                .await
+                .unwrap()
+                .url;
+            assert_eq!(again, first);
+        }
+        chosen.push(first);
+    }
+    // Clients spread over every backend
+    for url in [
+        "http://localhost:3000",
+        "http://localhost:3001",
+        "http://localhost:3002",
+    ] {
+        assert!(chosen.iter().any(|u| u == url), "{} unused", url);
+    }
+
+    // Only the clients of a backend that goes down move
+    take_down(&pool, "http://localhost:3001").await;
+    for (i, before) in chosen.iter().enumerate() {
+        let client = std::net::IpAddr::from([10, 0, 0, i as u8]);
+        let after = pool
+            .select_backend_for(by_client(client))
+            .await
+            .unwrap()
+            .url;
+        if before != "http://localhost:3001" {
+            assert_eq!(&after, before);
+        } else {
+            assert_ne!(after, "http://localhost:3001");
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_backend_pool_ip_hash_without_client_round_robins() {
+    let pool = BackendPool::new(vec![
+        backend("http://localhost:3000", 1),
+        backend("http://localhost:3001", 1),
+    ])
+    .with_balancing(LoadBalancing::IpHash);
+
+    let first = pool
+        .select_backend_for(Affinity::default())
+        .await
+        .unwrap()
+        .url;
+    let second = pool
+        .select_backend_for(Affinity::default())
+        .await
+        .unwrap()
+        .url;
+    assert_ne!(first, second);
+}
+
+#[test]
+fn test_load_balancing_config() {
+    let config: sentinel::config::ProxyConfig =
+        serde_yaml::from_str("backends: []\nload_balancing: ip_hash").unwrap();
+    assert_eq!(config.strategy, LoadBalancing::IpHash);
+    let config: sentinel::config::ProxyConfig = serde_yaml::from_str("backends: []").unwrap();
+    assert_eq!(config.strategy, LoadBalancing::RoundRobin);
+}
+
+async fn owners(pool: &BackendPool, keys: &[String]) -> Vec<String> {
+    let mut urls = Vec::new();
+    for key in keys {
+        urls.push(pool.select_backend_for(by_key(key)).await.unwrap().url);
+    }
+    urls
+}
+
+#[tokio::test]
+async fn test_backend_pool_consistent_hash_moves_few_keys() {
+    let pool = BackendPool::new(vec![
+        backend("http://localhost:3000", 1),
+        backend("http://localhost:3001", 1),
+        backend("http://localhost:3002", 1),
+    ])
+    .with_balancing(LoadBalancing::ConsistentHash);
+    let keys: Vec<String> = (0..300).map(|i| format!("/item/{}", i)).collect();
+
+    let before = owners(&pool, &keys).await;
+    assert_eq!(owners(&pool, &keys).await, before);
+    for url in [
+        "http://localhost:3000",
+        "http://localhost:3001",
+        "http://localhost:3002",
+    ] {
+        let share = before.iter().filter(|u| *u == url).count();
+        assert!((50..150).contains(&share), "{} owns {} keys", url, share);
+    }
+
+    // A new backend takes keys only from the others, about a quarter
+    pool.add_backend(backend("http://localhost:3003", 1)).await;
+    let after = owners(&pool, &keys).await;
+    let moved = before.iter().zip(&after).filter(|(b, a)| b != a).count();
+    assert!(
+        after
+            .iter()
+            .zip(&before)
+            .all(|(a, b)| a == b || a == "http://localhost:3003")
+    );
+    assert!((30..120).contains(&moved), "{} keys moved", moved);
+
+    // Keys of a backend that goes down move and return when it recovers
+    take_down(&pool, "http://localhost:3001").await;
+    let down = owners(&pool, &keys).await;
+    for (a, d) in after.iter().zip(&down) {
+        if a == "http://localhost:3001" {
+            assert_ne!(d, a);
+        } else {
+            assert_eq!(d, a);
+        }
+    }
+    pool.set_state("http://localhost:3001", BackendState::Up)
+        .await;
+    assert_eq!(owners(&pool, &keys).await, after);
+}
+
+#[tokio::test]
+async fn test_backend_pool_consistent_hash_follows_weights() {
+    let pool = BackendPool::new(vec![
+        backend("http://localhost:3000", 3),
+        backend("http://localhost:3001", 1),
+    ])
+    .with_balancing(LoadBalancing::ConsistentHash);
+    let keys: Vec<String> = (0..400).map(|i| format!("tenant-{}", i)).collect();
+
+    let heavy = owners(&pool, &keys)
+        .await
+        .iter()
+        .filter(|u| *u == "http://localhost:3000")
+        .count();
+    assert!((250..350).contains(&heavy), "{} of 400", heavy);
+}
+
+#[test]
+fn test_hash_key_config() {
+    let config: sentinel::config::ProxyConfig = serde_yaml::from_str(
+        "backends: [{url: 'http://localhost:3000'}]\nload_balancing: consistent_hash\nhash_key:\n  cookie: session",
+    )
+    .unwrap();
+    assert_eq!(config.strategy, LoadBalancing::ConsistentHash);
+    assert_eq!(config.hash_key, HashKey::Cookie("session".to_string()));
+    assert!(config.validate().is_ok());
+
+    let config: sentinel::config::ProxyConfig =
+        serde_yaml::from_str("backends: []\nhash_key: path").unwrap();
+    assert_eq!(config.hash_key, HashKey::Path);
+
+    let config: sentinel::config::ProxyConfig =
+        serde_yaml::from_str("backends: [{url: 'http://localhost:3000'}]\nhash_key:\n  header: ''")
+            .unwrap();
+    assert!(config.validate().is_err());
+}
+
+#[tokio::test]
+async fn test_backend_pool_latency_is_smoothed() {
+    let pool = BackendPool::new(vec![backend("http://localhost:3000", 1)]);
+    pool.record_latency("http://localhost:3000", Duration::from_millis(100))
+        .await;
+    pool.record_latency("http://localhost:3000", Duration::from_millis(200))
+        .await;
+
+    let latency = pool.get_backends().await[0].latency_ms().unwrap();
+    assert!((latency - 130.0).abs() < 1e-6, "{}", latency);
+}
+
+#[tokio::test]
+async fn test_backend_pool_least_latency_prefers_fast_backends() {
+    let pool = BackendPool::new(vec![
+        backend("http://localhost:3000", 1),
+        backend("http://localhost:3001", 1),
+    ])
+    .with_balancing(LoadBalancing::LeastLatency);
+    pool.record_latency("http://localhost:3000", Duration::from_millis(10))
+        .await;
+    pool.record_latency("http://localhost:3001", Duration::from_millis(200))
+        .await;
+
+    let urls = select_urls(&pool, 1000).await;
+    let slow = urls
+        .iter()
+        .filter(|u| *u == "http://localhost:3001")
+        .count();
+    // Only exploration reaches the slow backend
+    assert!((1..100).contains(&slow), "{} of 1000", slow);
+
+    // Unmeasured backends are tried first
+    pool.add_backend(backend("http://localhost:3002", 1)).await;
+    let urls = select_urls(&pool, 30).await;
+    assert!(urls.contains(&"http://localhost:3002".to_string()));
+}
+
+#[tokio::test]
+async fn test_backend_pool_random_follows_weights() {
+    let pool = BackendPool::new(vec![
+        backend("http://localhost:3000", 3),
+        backend("http://localhost:3001", 1),
+    ])
+    .with_balancing(LoadBalancing::Random);
+
+    let urls = select_urls(&pool, 4000).await;
+    let heavy = urls
+        .iter()
+        .filter(|u| *u == "http://localhost:3000")
+        .count();
+    assert!((2700..3300).contains(&heavy), "{} of 4000", heavy);
+}
+
+#[tokio::test]
+async fn test_backend_pool_least_conn_prefers_idle_backends() {
+    let pool = BackendPool::new(vec![
+        backend("http://localhost:3000", 1),
+        backend("http://localhost:3001", 1),
+    ])
+    .with_balancing(LoadBalancing::LeastConn);
+
+    // A selected backend counts as busy until it is dropped
+    let busy = pool.select_backend().await.unwrap();
+    for _ in 0..10 {
+        let selected = pool.select_backend().await.unwrap();
+        assert_ne!(selected.url, busy.url);
+    }
+
+    // The count is shared with the pool's copy and released on drop
+    let member = pool
+        .get_backends()
+        .await
+        .into_iter()
+        .find(|b| b.url == busy.url)
+        .unwrap();
+    assert_eq!(member.active_requests(), 1);
+    let in_flight = member.start_request();
+    drop(busy);
+    assert_eq!(member.active_requests(), 1);
+    drop(in_flight);
+    assert_eq!(member.active_requests(), 0);
+}
+
+/// Always the last candidate
+struct Last;
+
+impl LoadBalancer for Last {
+    fn select(
+        &self,
+        _: &[Arc<Backend>],
+        candidates: &[Candidate],
+        _: Affinity<'_>,
+    ) -> Option<usize> {
+        candidates.last().map(|c| c.index)
+    }
+}
+
+#[tokio::test]
+async fn test_backend_pool_custom_balancer() {
+    let pool = BackendPool::new(vec![
+        backend("http://localhost:3000", 1),
+        backend("http://localhost:3001", 1),
+        backend("http://localhost:3002", 0),
+    ])
+    .with_balancer(Arc::new(Last));
+
+    let urls = select_urls(&pool, 3).await;
+    assert!(urls.iter().all(|u| u == "http://localhost:3001"));
+
+    // Backends that are not candidates are never offered
+    pool.set_state("http://localhost:3001", BackendState::Down)
+        .await;
+    assert_eq!(
+        pool.select_backend().await.unwrap().url,
+        "http://localhost:3000"
+    );
+}
+
+#[test]
+fn test_strategy_config() {
+    let config: sentinel::config::ProxyConfig =
+        serde_yaml::from_str("backends: []\nstrategy: least_conn").unwrap();
+    assert_eq!(config.strategy, LoadBalancing::LeastConn);
+    let config: sentinel::config::ProxyConfig =
+        serde_yaml::from_str("backends: []\nstrategy: random").unwrap();
+    assert_eq!(config.strategy, LoadBalancing::Random);
+}
+
+#[tokio::test]
+async fn test_backend_pool_seeded_random_is_reproducible() {
+    let seeded = |seed| {
+        BackendPool::new(vec![
+            backend("http://localhost:3000", 1),
+            backend("http://localhost:3001", 2),
+            backend("http://localhost:3002", 1),
+        ])
+        .with_balancer(Arc::new(Random::seeded(seed)))
+    };
+
+    let first = select_urls(&seeded(7), 50).await;
+    assert_eq!(first, select_urls(&seeded(7), 50).await);
+    assert_ne!(first, select_urls(&seeded(8), 50).await);
+
+    let config: sentinel::config::ProxyConfig =
+        serde_yaml::from_str("backends: []\nstrategy: random\nrandom_seed: 7").unwrap();
+    assert_eq!(config.random_seed, Some(7));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_backend_pool_concurrent_selection() {
+    let pool = BackendPool::new(vec![
+        backend("http://localhost:3000", 1),
+        backend("http://localhost:3001", 1),
+    ]);
+
+    let selectors: Vec<_> = (0..8)
+        .map(|_| {
+            let pool = pool.clone();
+            tokio::spawn(async move { select_urls(&pool, 500).await })
+        })
+        .collect();
+    // Changes made meanwhile do not disturb selection
+    for _ in 0..50 {
+        pool.mark_backend_success("http://localhost:3000").await;
+        pool.set_weight("http://localhost:3001", 1).await;
+    }
+
+    let mut first = 0;
+    for selector in selectors {
+        first += selector
+            .await
+            .unwrap()
+            .iter()
+            .filter(|u| *u == "http://localhost:3000")
+            .count();
+    }
+    assert!((1800..2200).contains(&first), "{} of 4000", first);
+}
+
+#[tokio::test]
+async fn test_backend_pool_circuit_breaker_half_open() {
+    let pool = BackendPool::new(vec![backend("http://localhost:3000", 1)]).with_circuit_breaker(
+        CircuitBreakerConfig {
+            cooldown_ms: 50,
+            half_open_requests: 1,
+        },
+    );
+    for _ in 0..3 {
+        pool.mark_backend_failed("http://localhost:3000").await;
+    }
+    assert!(pool.select_backend().await.is_none());
+
+    // After the cooldown one probe at a time is let through
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    let probe = pool.select_backend().await.unwrap();
+    assert!(probe.is_probe());
+    assert!(pool.select_backend().await.is_none());
+    drop(probe);
+    let probe = pool.select_backend().await.unwrap();
+
+    // A failed probe opens the circuit again
+    pool.mark_backend_failed(&probe.url).await;
+    drop(probe);
+    assert!(pool.select_backend().await.is_none());
+
+    // A successful one closes it
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    let probe = pool.select_backend().await.unwrap();
+    pool.mark_backend_success(&probe.url).await;
+    drop(probe);
+    let selected = pool.select_backend().await.unwrap();
+    assert!(!selected.is_probe());
+    assert_eq!(selected.state, BackendState::Up);
+}
+
+#[tokio::test]
+async fn test_backend_pool_forced_down_is_not_probed() {
+    let pool = BackendPool::new(vec![backend("http://localhost:3000", 1)]).with_circuit_breaker(
+        CircuitBreakerConfig {
+            cooldown_ms: 0,
+            half_open_requests: 1,
+        },
+    );
+    pool.set_state("http://localhost:3000", BackendState::Down)
+        .await;
+    assert!(pool.select_backend().await.is_none());
+
+    let config: sentinel::config::ProxyConfig =
+        serde_yaml::from_str("backends: []\ncircuit_breaker:\n  cooldown_ms: 500").unwrap();
+    assert_eq!(config.circuit_breaker.cooldown_ms, 500);
+    assert_eq!(config.circuit_breaker.half_open_requests, 1);
+}
+
+#[tokio::test]
+async fn test_backend_pool_max_connections_overflow() {
+    let pool = BackendPool::new(vec![
+        BackendConfig {
+            max_connections: Some(1),
+            ..backend("http://localhost:3000", 1)
+        },
+        BackendConfig {
+            max_connections: Some(2),
+            ..backend("http://localhost:3001", 1)
+        },
+    ]);
+
+    let held: Vec<Backend> = vec![
+        pool.select_backend().await.unwrap(),
+        pool.select_backend().await.unwrap(),
+        pool.select_backend().await.unwrap(),
+    ];
+    let first = held
+        .iter()
+        .filter(|b| b.url == "http://localhost:3000")
+        .count();
+    assert_eq!(first, 1);
+
+    // Every backend is full until a request finishes
+    assert!(pool.select_backend().await.is_none());
+    drop(held);
+    assert!(pool.select_backend().await.is_some());
+
+    let invalid: sentinel::config::ProxyConfig =
+        serde_yaml::from_str("backends: [{url: 'http://localhost:3000', max_connections: 0}]")
+            .unwrap();
+    assert!(invalid.validate().is_err());
+}
+
+#[tokio::test]
+async fn test_backend_pool_drain() {
+    let pool = BackendPool::new(vec![
+        backend("http://localhost:3000", 1),
+        backend("http://localhost:3001", 1),
+    ]);
+
+    // Requests in flight are unaffected by draining
+    let in_flight = loop {
+        let selected = pool.select_backend().await.unwrap();
+        if selected.url == "http://localhost:3000" {
+            break selected;
+        }
+    };
+    assert!(pool.set_state(&in_flight.url, BackendState::Draining).await);
+    assert_eq!(
+        select_urls(&pool, 10).await,
+        vec!["http://localhost:3001"; 10]
+    );
+    assert_eq!(in_flight.active_requests(), 1);
+    drop(in_flight);
+
+    // Health outcomes leave a draining backend alone
+    for _ in 0..5 {
+        pool.mark_backend_failed("http://localhost:3000").await;
+    }
+    pool.mark_backend_success("http://localhost:3000").await;
+    let states: Vec<_> = pool.get_backends().await.iter().map(|b| b.state).collect();
+    assert_eq!(states, vec![BackendState::Draining, BackendState::Up]);
+
+    assert!(
+        pool.set_state("http://localhost:3000", BackendState::Up)
+            .await
+    );
+    assert_eq!(pool.available_count().await, 2);
+}
+
+#[tokio::test]
+async fn test_backend_pool_drain_from_config() {
+    let drained = BackendConfig {
+        drain: true,
+        ..backend("http://localhost:3000", 1)
+    };
+    let pool = BackendPool::new(vec![drained.clone(), backend("http://localhost:3001", 1)]);
+    assert_eq!(pool.available_count().await, 1);
+
+    // Clearing the setting on reload puts the backend back in rotation
+    pool.replace_backends(vec![
+        backend("http://localhost:3000", 1),
+        backend("http://localhost:3001", 1),
+    ])
+    .await;
+    assert_eq!(pool.available_count().await, 2);
+
+    // A state set at runtime survives reloads that do not change the setting
+    pool.set_state("http://localhost:3001", BackendState::Draining)
+        .await;
+    pool.replace_backends(vec![drained, backend("http://localhost:3001", 1)])
+        .await;
+    let states: Vec<_> = pool.get_backends().await.iter().map(|b| b.state).collect();
+    assert_eq!(states, vec![BackendState::Draining, BackendState::Draining]);
+}
+
+#[tokio::test]
+async fn test_backend_pool_warmup() {
+    let pool = BackendPool::new(vec![backend("http://localhost:3000", 1)])
+        .with_warmup(Duration::from_millis(200));
+    pool.add_backend(backend("http://localhost:3001", 1)).await;
+
+    // A backend that just joined gets only a sliver of the requests
+    let urls = select_urls(&pool, 100).await;
+    let new = urls
+        .iter()
+        .filter(|u| *u == "http://localhost:3001")
+        .count();
+    assert!(new < 20, "warming backend got {} of 100 requests", new);
+
+    // Once warmed up it gets its full share
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    let urls = select_urls(&pool, 100).await;
+    let new = urls
+        .iter()
+        .filter(|u| *u == "http://localhost:3001")
+        .count();
+    assert_eq!(new, 50);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_backend_pool_concurrent_membership_changes() {
+    let stable = "http://localhost:3000";
+    let pool = BackendPool::new(vec![backend(stable, 1)]);
+    pool.mark_backend_failed(stable).await;
+    let held = pool.select_backend().await.unwrap();
+
+    // Selectors hold requests on backends that are removed while they are
+    // in flight
+    let selectors: Vec<_> = (0..8)
+        .map(|_| {
+            let pool = pool.clone();
+            tokio::spawn(async move {
+                let mut held = Vec::new();
+                for _ in 0..500 {
+                    held.push(pool.select_backend().await.expect("stable backend missing"));
+                    if held.len() > 4 {
+                        held.remove(0);
+                    }
+                }
+            })
+        })
+        .collect();
+    let writers: Vec<_> = (0..4)
+        .map(|writer| {
+            let pool = pool.clone();
+            tokio::spawn(async move {
+                let url = format!("http://localhost:{}", 3100 + writer);
+                for i in 0..100 {
+                    match i % 3 {
+                        0 => {
+                            pool.add_backend(backend(&url, 1)).await;
+                        }
+                        1 => {
+                            pool.remove_backend(&url).await;
+                        }
+                        _ => {
+                            let mut members: Vec<BackendConfig> = pool
+                                .get_backends()
+                                .await
+                                .iter()
+                                .map(|b| backend(&b.url, b.weight))
+                                .collect();
+                            members.push(backend(&url, 1));
+                            pool.replace_backends(members).await;
+                        }
+                    }
+                }
+            })
+        })
+        .collect();
+    for task in selectors.into_iter().chain(writers) {
+        task.await.unwrap();
+    }
+
+    // The unchanged backend kept its state and in-flight count throughout
+    let backends = pool.get_backends().await;
+    assert!(backends.len() <= 5);
+    let member = backends.iter().find(|b| b.url == stable).unwrap();
+    assert_eq!(member.consecutive_failures(), 1);
+    assert_eq!(member.active_requests(), 1);
+    assert!(
+        backends
+            .iter()
+            .all(|b| b.url == stable || b.active_requests() == 0)
+    );
+    drop(held);
+    assert_eq!(member.active_requests(), 0);
+}
@@ -0,0 +1,287 @@
+//! Tests for blue-green deployments and the admin API that controls them
+
+use sentinel::admin::AdminApi;
+use sentinel::config::{BackendConfig, DeploymentColor, ProxyConfig};
+use sentinel::proxy::blue_green::DEPLOYMENT_HEADER;
+use sentinel::proxy::{BackendPool, BackendState, BlueGreen};
+use sentinel::testing::{MockAction, MockBackend, MockResponse, proxy_handler, send_request};
+use serde_json::{Value, json};
+use std::sync::Arc;
+
+const GET: &[u8] = b"GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n";
+const GET_PREVIEW: &[u8] =
+    b"GET / HTTP/1.1\r\nHost: a\r\nX-Sentinel-Preview: 1\r\nConnection: close\r\n\r\n";
+
+async fn backend(body: &str) -> MockBackend {
+    let backend = MockBackend::start().await;
+    backend.set_default(MockAction::Respond(
+        MockResponse::new(200).reason("OK").body(body),
+    ));
+    backend
+}
+
+fn admin_request(method: &str, path: &str, body: &str, token: Option<&str>) -> Vec<u8> {
+    let auth = token
+        .map(|t| format!("Authorization: Bearer {}\r\n", t))
+        .unwrap_or_default();
+    format!(
+        "{} {} HTTP/1.1\r\nHost: a\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        auth,
+        body.len(),
+        body
+    )
+    .into_bytes()
+}
+
+#[tokio::test]
+async fn test_traffic_follows_active_pool() {
+    let blue = backend("blue").await;
+    let green = backend("green").await;
+    let deployment = Arc::new(BlueGreen::new(
+        "web",
+        proxy_handler(&[&blue]),
+        proxy_handler(&[&green]),
+        DeploymentColor::Blue,
+    ));
+
+    let response = send_request(deployment.clone(), GET).await;
+    assert_eq!(response.text(), "blue");
+    assert_eq!(response.header(DEPLOYMENT_HEADER), Some("blue"));
+
+    assert_eq!(
+        deployment.switch_to(DeploymentColor::Green),
+        DeploymentColor::Blue
+    );
+
+    let response = send_request(deployment.clone(), GET).await;
+    assert_eq!(response.text(), "green");
+    assert_eq!(response.header(DEPLOYMENT_HEADER), Some("green"));
+}
+
+#[tokio::test]
+async fn test_preview_header_hits_inactive_pool() {
+    let blue = backend("blue").await;
+    let green = backend("green").await;
+    let deployment = Arc::new(BlueGreen::new(
+        "web",
+        proxy_handler(&[&blue]),
+        proxy_handler(&[&green]),
+        DeploymentColor::Blue,
+    ));
+
+    assert_eq!(
+        send_request(deployment.clone(), GET_PREVIEW).await.text(),
+        "green"
+    );
+
+    deployment.switch_to(DeploymentColor::Green);
+    assert_eq!(
+        send_request(deployment.clone(), GET_PREVIEW).await.text(),
+        "blue"
+    );
+}
+
+#[tokio::test]
+async fn test_admin_api_switches_deployment() {
+    let blue = backend("blue").await;
+    let green = backend("green").await;
+    let deployment = Arc::new(BlueGreen::new(
+        "web",
+        proxy_handler(&[&blue]),
+        proxy_handler(&[&green]),
+        DeploymentColor::Blue,
+    ));
+    let admin = Arc::new(AdminApi::new().deployment(deployment.clone()));
+
+    let response = send_request(
+        admin.clone(),
+        &admin_request("GET", "/deployments", "", None),
+    )
+    .await;
+    assert_eq!(response.status, 200);
+    let deployments: Value = serde_json::from_str(&response.text()).unwrap();
+    assert_eq!(deployments, json!([{"name": "web", "active": "blue"}]));
+
+    // An empty body flips the active pool
+    let response = send_request(
+        admin.clone(),
+        &admin_request("POST", "/deployments/web/switch", "", None),
+    )
+    .await;
+    assert_eq!(response.status, 200);
+    assert_eq!(deployment.active(), DeploymentColor::Green);
+
+    let response = send_request(
+        admin.clone(),
+        &admin_request(
+            "POST",
+            "/deployments/web/switch",
+            r#"{"active":"green"}"#,
+            None,
+        ),
+    )
+    .await;
+    assert_eq!(response.status, 200);
+    assert_eq!(deployment.active(), DeploymentColor::Green);
+
+    let response = send_request(
+        admin.clone(),
+        &admin_request("POST", "/deployments/missing/switch", "", None),
+    )
+    .await;
+    assert_eq!(response.status, 404);
+
+    let response = send_request(
+        admin,
+        &admin_request(
+            "POST",
+            "/deployments/web/switch",
+            r#"{"active":"red"}"#,
+            None,
+        ),
+    )
+    .await;
+    assert_eq!(response.status, 400);
+    assert_eq!(deployment.active(), DeploymentColor::Green);
+}
+
+#[tokio::test]
+async fn test_admin_api_requires_token() {
+    let blue = backend("blue").await;
+    let deployment = Arc::new(BlueGreen::new(
+        "web",
+        proxy_handler(&[&blue]),
+        proxy_handler(&[&blue]),
+        DeploymentColor::Blue,
+    ));
+    let admin = Arc::new(
+        AdminApi::new()
+            .with_token("s3cret")
+            .deployment(deployment.clone()),
+    );
+
+    let response = send_request(
+        admin.clone(),
+        &admin_request("POST", "/deployments/web/switch", "", None),
+    )
+    .await;
+    assert_eq!(response.status, 401);
+
+    let response = send_request(
+        admin.clone(),
+        &admin_request("POST", "/deployments/web/switch", "", Some("wrong")),
+    )
+    .await;
+    assert_eq!(response.status, 401);
+    assert_eq!(deployment.active(), DeploymentColor::Blue);
+
+    let response = send_request(
+        admin,
+        &admin_request("POST", "/deployments/web/switch", "", Some("s3cret")),
+    )
+    .await;
+    assert_eq!(response.status, 200);
+    assert_eq!(deployment.active(), DeploymentColor::Green);
+}
+
+#[test]
+fn test_config_requires_unique_names_and_both_pools() {
+    let parse = |yaml: &str| serde_yaml::from_str::<ProxyConfig>(yaml).unwrap();
+
+    let valid = parse(
+        r#"
+backends: []
+blue_green:
+  - name: web
+    blue: [{ url: "http://10.0.0.1:8080" }]
+    green: [{ url: "http://10.0.0.2:8080" }]
+"#,
+    );
+    assert!(valid.validate().is_ok());
+    assert_eq!(valid.blue_green[0].path_prefix, "/");
+    assert_eq!(valid.blue_green[0].active, DeploymentColor::Blue);
+
+    let duplicate = parse(
+        r#"
+backends: []
+blue_green:
+  - name: web
+    blue: [{ url: "http://10.0.0.1:8080" }]
+    green: [{ url: "http://10.0.0.2:8080" }]
+  - name: web
+    path_prefix: /api
+    blue: [{ url: "http://10.0.0.1:8080" }]
+    green: [{ url: "http://10.0.0.2:8080" }]
+"#,
+    );
+    assert!(duplicate.validate().is_err());
+
+    let empty_green = parse(
+        r#"
+backends: []
+blue_green:
+  - name: web
+    blue: [{ url: "http://10.0.0.1:8080" }]
+    green: []
+"#,
+    );
+    assert!(empty_green.validate().is_err());
+}
+
+#[tokio::test]
+async fn test_admin_api_drains_backends() {
+    let pool = BackendPool::new(vec![
+        BackendConfig {
+            url: "http://10.0.0.1:3000".to_string(),
+            name: Some("api-1".to_string()),
+            ..Default::default()
+        },
+        BackendConfig {
+            url: "http://10.0.0.2".to_string(),
+            ..Default::default()
+        },
+    ]);
+    let admin = Arc::new(AdminApi::new().backends(pool.clone()));
+
+    let response = send_request(
+        admin.clone(),
+        &admin_request("POST", "/backends/api-1/drain", "", None),
+    )
+    .await;
+    assert_eq!(response.status, 200);
+    let body: serde_json::Value = serde_json::from_slice(&response.body).unwrap();
+    assert_eq!(body["state"], "draining");
+    assert_eq!(body["previous"], "up");
+
+    // Unnamed backends are addressed by host:port
+    let response = send_request(
+        admin.clone(),
+        &admin_request("POST", "/backends/10.0.0.2:80/drain", "", None),
+    )
+    .await;
+    assert_eq!(response.status, 200);
+    assert_eq!(pool.available_count().await, 0);
+
+    let response = send_request(admin.clone(), &admin_request("GET", "/backends", "", None)).await;
+    let body: serde_json::Value = serde_json::from_slice(&response.body).unwrap();
+    assert_eq!(body[0]["name"], "api-1");
+    assert_eq!(body[1]["state"], "draining");
+
+    let response = send_request(
+        admin.clone(),
+        &admin_request("POST", "/backends/api-1/resume", "", None),
+    )
+    .await;
+    assert_eq!(response.status, 200);
+    let states: Vec<_> = pool.get_backends().await.iter().map(|b| b.state).collect();
+    assert_eq!(states, vec![BackendState::Up, BackendState::Draining]);
+
+    let response = send_request(
+        admin,
+        &admin_request("POST", "/backends/missing/drain", "", None),
+    )
+    .await;
+    assert_eq!(response.status, 404);
+}
@@ -0,0 +1,259 @@
+//! Tests for rule-based bot detection
+
+use sentinel::config::{BotAction, BotConfig};
+use sentinel::http::handler::{Handler, handler_fn};
+use sentinel::http::request::{Method, Request, RequestBuilder};
+use sentinel::http::response::Response;
+use sentinel::metrics::{Metrics, PrometheusRecorder};
+use sentinel::middleware::BotHandler;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+fn config(yaml: &str) -> BotConfig {
+    let config: BotConfig = serde_yaml::from_str(yaml).unwrap();
+    config.validate().unwrap();
+    config
+}
+
+fn bots(yaml: &str) -> BotHandler {
+    BotHandler::new(
+        handler_fn(|_req| async { Response::ok(b"origin".to_vec()) }),
+        config(yaml),
+    )
+}
+
+fn request(headers: &[(&str, &str)], peer: Option<&str>) -> Request {
+    let mut builder = RequestBuilder::new().method(Method::GET).path("/");
+    for (name, value) in headers {
+        builder = builder.header(*name, *value);
+    }
+    let mut req = builder.build().unwrap();
+    req.context.peer = peer.map(|p| p.parse::<SocketAddr>().unwrap());
+    req
+}
+
+fn verdict(handler: &BotHandler, req: &Request) -> Option<(String, BotAction)> {
+    handler
+        .classify(req)
+        .map(|v| (v.rule.to_string(), v.action))
+}
+
+#[test]
+fn test_user_agent_and_missing_header_signals() {
+    let handler = bots(
+        r#"
+rules:
+  - name: crawlers
+    user_agents: ["Googlebot"]
+    action: allow
+  - name: scripts
+    user_agents: ["python-requests", "curl/"]
+    action: block
+  - name: headless
+    missing_headers: ["Accept-Language"]
+    action: tarpit
+"#,
+    );
+
+    let browser = request(
+        &[("User-Agent", "Mozilla/5.0"), ("Accept-Language", "en")],
+        None,
+    );
+    assert_eq!(verdict(&handler, &browser), None);
+
+    // Patterns match case-insensitively anywhere in the User-Agent
+    let script = request(
+        &[
+            ("User-Agent", "Python-Requests/2.31"),
+            ("Accept-Language", "en"),
+        ],
+        None,
+    );
+    assert_eq!(
+        verdict(&handler, &script),
+        Some(("scripts".to_string(), BotAction::Block))
+    );
+
+    // The first matching rule wins, even if later rules also match
+    let crawler = request(&[("User-Agent", "Googlebot/2.1")], None);
+    assert_eq!(
+        verdict(&handler, &crawler),
+        Some(("crawlers".to_string(), BotAction::Allow))
+    );
+
+    let headless = request(&[("User-Agent", "Mozilla/5.0")], None);
+    assert_eq!(
+        verdict(&handler, &headless),
+        Some(("headless".to_string(), BotAction::Tarpit))
+    );
+}
+
+#[test]
+fn test_all_signals_of_a_rule_must_match() {
+    let handler = bots(
+        r#"
+rules:
+  - name: fake-browsers
+    user_agents: ["Chrome"]
+    missing_headers: ["Accept-Language", "Accept-Encoding"]
+    action: block
+"#,
+    );
+
+    let partial = request(
+        &[("User-Agent", "Chrome/120"), ("Accept-Language", "en")],
+        None,
+    );
+    assert!(verdict(&handler, &partial).is_some());
+
+    let complete = request(
+        &[
+            ("User-Agent", "Chrome/120"),
+            ("Accept-Language", "en"),
+            ("Accept-Encoding", "gzip"),
+        ],
+        None,
+    );
+    assert_eq!(verdict(&handler, &complete), None);
+
+    let other = request(&[("User-Agent", "Firefox/120")], None);
+    assert_eq!(verdict(&handler, &other), None);
+}
+
+#[test]
+fn test_rate_signal_is_per_client() {
+    let handler = bots(
+        r#"
+rules:
+  - name: hammering
+    rate: { requests: 2, per_secs: 60 }
+    action: block
+"#,
+    );
+
+    let first = request(&[], Some("10.0.0.1:4000"));
+    assert_eq!(verdict(&handler, &first), None);
+    assert_eq!(verdict(&handler, &first), None);
+    assert_eq!(
+        verdict(&handler, &first),
+        Some(("hammering".to_string(), BotAction::Block))
+    );
+
+    // Counts are kept per IP, regardless of the client port
+    assert_eq!(
+        verdict(&handler, &request(&[], Some("10.0.0.2:4000"))),
+        None
+    );
+    assert!(verdict(&handler, &request(&[], Some("10.0.0.1:5000"))).is_some());
+
+    // Without a known peer the rate signal never matches
+    for _ in 0..5 {
+        assert_eq!(verdict(&handler, &request(&[], None)), None);
+    }
+}
+
+#[tokio::test]
+async fn test_actions_and_metrics() {
+    let recorder = Arc::new(PrometheusRecorder::new());
+    let handler = BotHandler::new(
+        handler_fn(|_req| async { Response::ok(b"origin".to_vec()) }),
+        config(
+            r#"
+rules:
+  - name: scripts
+    user_agents: ["curl/"]
+    action: block
+  - name: slow
+    user_agents: ["wget/"]
+    action: tarpit
+    tarpit_ms: 50
+  - name: pooled
+    user_agents: ["scrapy"]
+    action: route
+    backends:
+      - url: "http://127.0.0.1:1"
+"#,
+        ),
+    )
+    .with_metrics(Metrics::new(recorder.clone()))
+    .with_pool(
+        "pooled",
+        handler_fn(|_req| async { Response::ok(b"bot pool".to_vec()) }),
+    );
+
+    let response = handler
+        .handle(request(&[("User-Agent", "curl/8.0")], None))
+        .await;
+    assert_eq!(response.status.as_u16(), 403);
+
+    let started = Instant::now();
+    let response = handler
+        .handle(request(&[("User-Agent", "Wget/1.21")], None))
+        .await;
+    assert!(started.elapsed() >= Duration::from_millis(50));
+    assert_eq!(response.body, b"origin");
+
+    let response = handler
+        .handle(request(&[("User-Agent", "Scrapy/2.11")], None))
+        .await;
+    assert_eq!(response.body, b"bot pool");
+
+    let response = handler
+        .handle(request(&[("User-Agent", "Mozilla/5.0")], None))
+        .await;
+    assert_eq!(response.body, b"origin");
+
+    let output = recorder.render();
+    assert!(output.contains(r#"sentinel_bot_verdicts_total{action="block",rule="scripts"} 1"#));
+    assert!(output.contains(r#"sentinel_bot_verdicts_total{action="tarpit",rule="slow"} 1"#));
+    assert!(output.contains(r#"sentinel_bot_verdicts_total{action="route",rule="pooled"} 1"#));
+}
+
+#[tokio::test]
+async fn test_tarpit_gives_up_on_cancelled_requests() {
+    let handler = bots(
+        r#"
+rules:
+  - name: slow
+    user_agents: ["wget/"]
+    action: tarpit
+    tarpit_ms: 60000
+"#,
+    );
+    let req = request(&[("User-Agent", "Wget/1.21")], None);
+    let cancel = req.context.cancel.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        cancel.cancel();
+    });
+
+    let response = tokio::time::timeout(Duration::from_secs(5), handler.handle(req))
+        .await
+        .unwrap();
+    assert_eq!(response.status.as_u16(), 503);
+}
+
+#[test]
+fn test_bot_config_validation() {
+    let parse = |yaml: &str| serde_yaml::from_str::<BotConfig>(yaml).unwrap().validate();
+
+    assert!(parse("rules: [{name: a, user_agents: [x], action: block}]").is_ok());
+    assert!(parse("rules: [{name: a, action: block}]").is_err());
+    assert!(parse("rules: [{name: a, user_agents: [''], action: block}]").is_err());
+    assert!(parse("rules: [{name: 'a b', user_agents: [x], action: block}]").is_err());
+    assert!(
+        parse(
+            "rules: [{name: a, user_agents: [x], action: block}, {name: a, user_agents: [y], action: allow}]"
+        )
+        .is_err()
+    );
+    assert!(parse("rules: [{name: a, rate: {requests: 0, per_secs: 1}, action: block}]").is_err());
+    assert!(parse("rules: [{name: a, user_agents: [x], action: route}]").is_err());
+    assert!(
+        parse(
+            "rules: [{name: a, user_agents: [x], action: block, backends: [{url: 'http://127.0.0.1:1'}]}]"
+        )
+        .is_err()
+    );
+}
@@ -0,0 +1,264 @@
+//! Tests for request cancellation (deadlines, disconnects, shutdown)
+
+use sentinel::config::Config;
+use sentinel::http::connection::Connection;
+use sentinel::http::context::RequestContext;
+use sentinel::http::handler::Handler;
+use sentinel::http::handler::handler_fn;
+use sentinel::http::parser::HeadLimits;
+use sentinel::http::request::Method;
+use sentinel::http::request::RequestBuilder;
+use sentinel::http::response::{Response, StatusCode};
+use sentinel::http::router::Router;
+use sentinel::proxy::{BackendPool, ProxyHandler};
+use sentinel::server::Server;
+use sentinel::testing::{MockAction, MockBackend, backend_config};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_util::sync::CancellationToken;
+
+/// Handler that waits for cancellation and records that it saw it
+fn waits_for_cancel(seen: Arc<AtomicBool>) -> Arc<dyn Handler> {
+    Arc::new(handler_fn(move |req| {
+        let seen = seen.clone();
+        async move {
+            tokio::select! {
+                _ = req.context.cancelled() => seen.store(true, Ordering::SeqCst),
+                _ = tokio::time::sleep(Duration::from_secs(5)) => {}
+            }
+            Response::ok(b"done".to_vec())
+        }
+    }))
+}
+
+#[tokio::test]
+async fn test_deadline_returns_504() {
+    let (mut client, server) = tokio::io::duplex(4096);
+
+    let conn = tokio::spawn({
+        let handler = waits_for_cancel(Arc::new(AtomicBool::new(false)));
+        async move {
+            Connection::with_handler(server, handler)
+                .with_request_timeout(Duration::from_millis(50))
+                .run()
+                .await
+        }
+    });
+
+    client
+        .write_all(b"GET /slow HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut output = Vec::new();
+    client.read_to_end(&mut output).await.unwrap();
+    conn.await.unwrap().unwrap();
+
+    assert!(output.starts_with(b"HTTP/1.1 504 Gateway Timeout\r\n"));
+}
+
+#[tokio::test]
+async fn test_client_disconnect_cancels_request() {
+    let seen = Arc::new(AtomicBool::new(false));
+    let (mut client, server) = tokio::io::duplex(4096);
+
+    let conn = tokio::spawn({
+        let handler = waits_for_cancel(seen.clone());
+        async move { Connection::with_handler(server, handler).run().await }
+    });
+
+    client
+        .write_all(b"GET /slow HTTP/1.1\r\nHost: a\r\n\r\n")
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    drop(client);
+
+    let started = Instant::now();
+    conn.await.unwrap().unwrap();
+
+    assert!(seen.load(Ordering::SeqCst));
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn test_shutdown_token_cancels_request() {
+    let seen = Arc::new(AtomicBool::new(false));
+    let shutdown = CancellationToken::new();
+    let (mut client, server) = tokio::io::duplex(4096);
+
+    let conn = tokio::spawn({
+        let handler = waits_for_cancel(seen.clone());
+        let token = shutdown.child_token();
+        async move {
+            Connection::with_handler(server, handler)
+                .with_cancellation(token)
+                .run()
+                .await
+        }
+    });
+
+    client
+        .write_all(b"GET /slow HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    shutdown.cancel();
+
+    let mut output = Vec::new();
+    client.read_to_end(&mut output).await.unwrap();
+    conn.await.unwrap().unwrap();
+
+    assert!(seen.load(Ordering::SeqCst));
+    assert!(output.ends_with(b"done"));
+}
+
+#[tokio::test]
+async fn test_pipelined_bytes_are_bounded_while_handler_runs() {
+    let (mut client, server) = tokio::io::duplex(4096);
+
+    let conn = tokio::spawn({
+        let handler: Arc<dyn Handler> = Arc::new(handler_fn(|_req| async {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            Response::ok(b"done".to_vec())
+        }));
+        async move {
+            Connection::with_handler(server, handler)
+                .with_head_limits(HeadLimits {
+                    max_header_bytes: 1024,
+                    ..HeadLimits::default()
+                })
+                .run()
+                .await
+        }
+    });
+
+    client
+        .write_all(b"GET /slow HTTP/1.1\r\nHost: a\r\n\r\n")
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+
+    // The connection stops reading past the limit, so the flood stalls
+    let flood = vec![b'A'; 64 * 1024];
+    let sent = tokio::time::timeout(Duration::from_millis(200), client.write_all(&flood)).await;
+    assert!(sent.is_err());
+
+    // Writing the response to the dropped client fails
+    drop(client);
+    let _ = conn.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_proxy_abandons_cancelled_request() {
+    let backend = MockBackend::start().await;
+    backend.set_default(MockAction::Hang);
+    let pool = BackendPool::new(vec![backend_config(&backend)]);
+    let proxy = ProxyHandler::new(
+        pool.clone(),
+        Duration::from_millis(500),
+        Duration::from_secs(5),
+    );
+
+    let token = CancellationToken::new();
+    let mut req = RequestBuilder::new()
+        .method(Method::GET)
+        .path("/slow")
+        .build()
+        .unwrap();
+    req.context = RequestContext::new(token.clone());
+
+    let canceller = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        token.cancel();
+    });
+
+    let started = Instant::now();
+    let response = proxy.handle(req).await;
+    canceller.await.unwrap();
+
+    assert_eq!(response.status, StatusCode::ServiceUnavailable);
+    assert!(started.elapsed() < Duration::from_secs(1));
+    // A cancelled attempt says nothing about backend health
+    assert_eq!(pool.available_count().await, 1);
+}
+
+#[tokio::test]
+async fn test_server_drains_in_flight_requests_on_shutdown() {
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let cfg: Config = serde_yaml::from_str(&format!(
+        r#"
+server:
+  listen_addr: "127.0.0.1:{}"
+static_files:
+  root: "public"
+  index: "index.html"
+"#,
+        port
+    ))
+    .unwrap();
+    let router = Router::new().route(
+        "/slow",
+        handler_fn(|req| async move {
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_millis(300)) => {
+                    Response::ok(b"finished".to_vec())
+                }
+                _ = req.context.cancelled() => Response::error(StatusCode::ServiceUnavailable, ""),
+            }
+        }),
+    );
+    let shutdown = CancellationToken::new();
+    let server = tokio::spawn(
+        Server::new(cfg)
+            .router(router)
+            .shutdown(shutdown.clone())
+            .drain_timeout(Duration::from_secs(5))
+            .run(),
+    );
+
+    let connect = || async {
+        for _ in 0..50 {
+            if let Ok(stream) = TcpStream::connect(("127.0.0.1", port)).await {
+                return stream;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("server did not start");
+    };
+    let mut busy = connect().await;
+    busy.write_all(b"GET /slow HTTP/1.1\r\nHost: a\r\n\r\n")
+        .await
+        .unwrap();
+    let mut idle = connect().await;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let started = Instant::now();
+    shutdown.cancel();
+
+    // The idle connection is closed without waiting for the busy one
+    let mut output = Vec::new();
+    idle.read_to_end(&mut output).await.unwrap();
+    assert!(output.is_empty());
+    assert!(started.elapsed() < Duration::from_millis(200));
+
+    // The request in flight is answered, and its connection then closed
+    let mut output = String::new();
+    busy.read_to_string(&mut output).await.unwrap();
+    assert!(output.starts_with("HTTP/1.1 200"), "{}", output);
+    assert!(
+        output.to_ascii_lowercase().contains("connection: close"),
+        "{}",
+        output
+    );
+    assert!(output.ends_with("finished"), "{}", output);
+
+    server.await.unwrap().unwrap();
+    assert!(started.elapsed() < Duration::from_secs(2));
+    assert!(TcpStream::connect(("127.0.0.1", port)).await.is_err());
+}
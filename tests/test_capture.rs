@@ -0,0 +1,94 @@
+//! Tests for malformed request capture
+
+use sentinel::config::MalformedCaptureConfig;
+use sentinel::http::capture::MalformedCapture;
+use sentinel::http::connection::Connection;
+use sentinel::http::handler::handler_fn;
+use sentinel::http::parser::ParseError;
+use sentinel::http::response::Response;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+
+fn capture_config(name: &str) -> MalformedCaptureConfig {
+    let path = std::env::temp_dir().join(format!(
+        "sentinel-capture-{}-{}.jsonl",
+        std::process::id(),
+        name
+    ));
+    let _ = std::fs::remove_file(&path);
+    MalformedCaptureConfig {
+        path,
+        max_bytes: 4096,
+        per_minute: 60,
+        redact_headers: vec!["Authorization".to_string(), "Cookie".to_string()],
+    }
+}
+
+fn read_lines(path: &PathBuf) -> Vec<serde_json::Value> {
+    let lines = std::fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    std::fs::remove_file(path).unwrap();
+    lines
+}
+
+#[tokio::test]
+async fn test_connection_captures_rejected_request() {
+    let config = capture_config("connection");
+    let capture = Arc::new(MalformedCapture::new(&config).unwrap());
+    let handler = Arc::new(handler_fn(|_req| async { Response::ok(Vec::new()) }));
+
+    let (mut client, server) = tokio::io::duplex(4096);
+    let mut conn = Connection::with_handler(server, handler)
+        .with_peer("10.0.0.7:51812".parse().unwrap())
+        .with_malformed_capture(capture);
+    client
+        .write_all(
+            b"GET / HTTP/1.1\r\nHost: a\r\nauthorization: Bearer s3cret\r\nno colon here\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    assert!(conn.run().await.is_err());
+
+    let lines = read_lines(&config.path);
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0]["peer"], "10.0.0.7:51812");
+    assert_eq!(lines[0]["error"], "InvalidHeader");
+    assert_eq!(
+        lines[0]["raw"],
+        "GET / HTTP/1.1\r\nHost: a\r\nauthorization: [REDACTED]\r\nno colon here\r\n\r\n"
+    );
+    assert!(!lines[0]["raw"].as_str().unwrap().contains("s3cret"));
+}
+
+#[test]
+fn test_captures_are_truncated_and_rate_limited() {
+    let mut config = capture_config("limits");
+    config.max_bytes = 8;
+    config.per_minute = 2;
+    let capture = MalformedCapture::new(&config).unwrap();
+
+    let raw = b"BREW /pot HTTP/1.1\r\nHost: a\r\n\r\n";
+    assert!(capture.record(None, &ParseError::InvalidMethod, raw));
+    assert!(capture.record(None, &ParseError::InvalidMethod, raw));
+    assert!(!capture.record(None, &ParseError::InvalidMethod, raw));
+
+    let lines = read_lines(&config.path);
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["raw"], "BREW /po");
+    assert_eq!(lines[0]["truncated"], true);
+    assert_eq!(lines[0]["bytes"], raw.len());
+    assert_eq!(lines[0]["peer"], serde_json::Value::Null);
+}
+
+#[test]
+fn test_config_defaults() {
+    let config: MalformedCaptureConfig =
+        serde_yaml::from_str("path: /var/log/sentinel/malformed.jsonl").unwrap();
+    assert_eq!(config.max_bytes, 4096);
+    assert_eq!(config.per_minute, 60);
+    assert!(config.redact_headers.iter().any(|h| h == "Cookie"));
+}
@@ -0,0 +1,164 @@
+//! Tests for the chaos / fault injection middleware
+
+use sentinel::config::{ChaosConfig, ChaosRule, ErrorFault, LatencyFault};
+use sentinel::http::handler::handler_fn;
+use sentinel::http::response::Response;
+use sentinel::middleware::ChaosHandler;
+use sentinel::middleware::chaos::CHAOS_HEADER;
+use sentinel::testing::{send_raw, send_request};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const GET_API: &[u8] = b"GET /api/users HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n";
+
+fn chaos(rule: ChaosRule) -> Arc<ChaosHandler> {
+    let inner = handler_fn(|_req| async { Response::ok(b"0123456789".to_vec()) });
+    Arc::new(ChaosHandler::new(
+        inner,
+        ChaosConfig {
+            enabled: true,
+            rules: vec![rule],
+        },
+    ))
+}
+
+fn api_rule() -> ChaosRule {
+    ChaosRule {
+        path_prefix: "/api".to_string(),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_error_injection() {
+    let handler = chaos(ChaosRule {
+        error: Some(ErrorFault {
+            probability: 1.0,
+            status: 503,
+        }),
+        ..api_rule()
+    });
+
+    let response = send_request(handler, GET_API).await;
+
+    assert_eq!(response.status, 503);
+    assert_eq!(response.header(CHAOS_HEADER), Some("error"));
+}
+
+#[tokio::test]
+async fn test_rules_only_apply_to_matching_prefix() {
+    let handler = chaos(ChaosRule {
+        error: Some(ErrorFault {
+            probability: 1.0,
+            status: 500,
+        }),
+        ..api_rule()
+    });
+
+    let response = send_request(
+        handler,
+        b"GET /health HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "0123456789");
+}
+
+#[tokio::test]
+async fn test_latency_injection() {
+    let handler = chaos(ChaosRule {
+        latency: Some(LatencyFault {
+            probability: 1.0,
+            min_ms: 100,
+            max_ms: None,
+        }),
+        ..api_rule()
+    });
+
+    let started = Instant::now();
+    let response = send_request(handler, GET_API).await;
+
+    assert_eq!(response.status, 200);
+    assert!(started.elapsed() >= Duration::from_millis(100));
+}
+
+#[tokio::test]
+async fn test_abort_closes_without_response() {
+    let handler = chaos(ChaosRule {
+        abort_probability: 1.0,
+        ..api_rule()
+    });
+
+    let responses = send_raw(handler, GET_API).await;
+
+    assert!(responses.is_empty());
+}
+
+#[tokio::test]
+async fn test_truncate_sends_partial_body_and_closes() {
+    let handler = chaos(ChaosRule {
+        truncate_probability: 1.0,
+        ..api_rule()
+    });
+
+    // Keep-alive request followed by a second one: the connection must
+    // close after the truncated response instead of serving the next.
+    let responses = send_raw(
+        handler,
+        b"GET /api/a HTTP/1.1\r\nHost: a\r\n\r\nGET /api/b HTTP/1.1\r\nHost: a\r\n\r\n",
+    )
+    .await;
+
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0].header("Content-Length"), Some("10"));
+    assert_eq!(responses[0].body, b"01234");
+}
+
+#[tokio::test]
+async fn test_disabled_config_passes_through() {
+    let inner = handler_fn(|_req| async { Response::ok(b"ok".to_vec()) });
+    let handler = Arc::new(ChaosHandler::new(
+        inner,
+        ChaosConfig {
+            enabled: false,
+            rules: vec![ChaosRule {
+                abort_probability: 1.0,
+                ..api_rule()
+            }],
+        },
+    ));
+
+    let response = send_request(handler, GET_API).await;
+
+    assert_eq!(response.status, 200);
+}
+
+#[test]
+fn test_validate_rejects_bad_probability() {
+    let config = ChaosConfig {
+        enabled: true,
+        rules: vec![ChaosRule {
+            abort_probability: 1.5,
+            ..api_rule()
+        }],
+    };
+
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn test_chaos_config_from_yaml() {
+    let yaml = r#"
+enabled: true
+rules:
+  - path_prefix: "/api"
+    latency: { probability: 0.5, min_ms: 10, max_ms: 20 }
+    error: { probability: 0.1 }
+"#;
+    let config: ChaosConfig = serde_yaml::from_str(yaml).unwrap();
+
+    assert!(config.validate().is_ok());
+    assert_eq!(config.rules[0].error.as_ref().unwrap().status, 503);
+    assert_eq!(config.rules[0].abort_probability, 0.0);
+}
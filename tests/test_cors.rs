@@ -0,0 +1,201 @@
+//! Tests for per-route CORS policies
+
+use sentinel::config::{CorsConfig, RouteConfig};
+use sentinel::http::handler::{Handler, handler_fn};
+use sentinel::http::request::{Method, RequestBuilder};
+use sentinel::http::response::{Response, StatusCode};
+use sentinel::middleware::{Cors, MiddlewareChain};
+use sentinel::testing::send_request;
+use std::sync::Arc;
+
+fn cors(yaml: &str) -> CorsConfig {
+    serde_yaml::from_str(yaml).unwrap()
+}
+
+/// `config` around a backend that answers OPTIONS with 500, so preflights
+/// reaching it show up
+fn chain(config: &str) -> Arc<dyn Handler> {
+    Arc::new(
+        MiddlewareChain::new(handler_fn(|req| async move {
+            if req.method == Method::OPTIONS {
+                return Response::new(StatusCode::InternalServerError).build();
+            }
+            Response::new(StatusCode::Ok)
+                .header("Access-Control-Allow-Origin", "*")
+                .header("Vary", "Accept-Encoding")
+                .body(b"ok".to_vec())
+                .build()
+        }))
+        .with(Cors::new(cors(config))),
+    )
+}
+
+const POLICY: &str = r#"
+allow_origins: ["https://app.example.com", "https://*.example.org"]
+allow_methods: [GET, POST, DELETE]
+allow_headers: [Content-Type, Authorization]
+expose_headers: [X-Request-Id]
+allow_credentials: true
+max_age_secs: 3600
+"#;
+
+#[test]
+fn test_origins_are_matched_and_validated() {
+    let policy = cors(POLICY);
+    assert!(policy.validate().is_ok());
+    assert!(policy.allows_origin("https://app.example.com"));
+    assert!(policy.allows_origin("HTTPS://App.Example.com"));
+    assert!(policy.allows_origin("https://eu.shop.example.org"));
+    assert!(!policy.allows_origin("https://example.org"));
+    assert!(!policy.allows_origin("https://evilexample.org"));
+    assert!(!policy.allows_origin("http://app.example.com"));
+    assert!(!policy.allows_origin("null"));
+
+    let defaults = cors("allow_origins: [\"*\"]");
+    assert!(defaults.validate().is_ok());
+    assert_eq!(defaults.allow_methods, ["GET", "HEAD", "POST"]);
+    assert_eq!(defaults.max_age_secs, 600);
+    assert!(defaults.allows_origin("https://anywhere.test"));
+
+    assert!(cors("allow_origins: []").validate().is_err());
+    assert!(
+        cors("allow_origins: [\"*\"]\nallow_credentials: true")
+            .validate()
+            .is_err()
+    );
+    for origin in [
+        "example.com",
+        "https://example.com/",
+        "https://*",
+        "ftp://x",
+    ] {
+        let config = cors(&format!("allow_origins: [\"{}\"]", origin));
+        assert!(config.validate().is_err(), "{}", origin);
+    }
+    assert!(
+        cors("allow_origins: [\"*\"]\nallow_methods: [FETCH]")
+            .validate()
+            .is_err()
+    );
+    assert!(
+        cors("allow_origins: [\"*\"]\nallow_headers: [\"Bad Header\"]")
+            .validate()
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_preflight_is_answered_without_the_backend() {
+    let handler = chain(POLICY);
+    let response = send_request(
+        handler.clone(),
+        b"OPTIONS /api/items HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\
+          Origin: https://app.example.com\r\n\
+          Access-Control-Request-Method: DELETE\r\n\
+          Access-Control-Request-Headers: content-type, authorization\r\n\r\n",
+    )
+    .await;
+
+    assert_eq!(response.status, 204);
+    assert_eq!(
+        response.header("Access-Control-Allow-Origin"),
+        Some("https://app.example.com")
+    );
+    assert_eq!(
+        response.header("Access-Control-Allow-Methods"),
+        Some("GET, POST, DELETE")
+    );
+    assert_eq!(
+        response.header("Access-Control-Allow-Headers"),
+        Some("content-type, authorization")
+    );
+    assert_eq!(
+        response.header("Access-Control-Allow-Credentials"),
+        Some("true")
+    );
+    assert_eq!(response.header("Access-Control-Max-Age"), Some("3600"));
+    assert_eq!(response.header("Vary"), Some("Origin"));
+
+    for (origin, method, headers) in [
+        ("https://evil.test", "GET", ""),
+        ("https://app.example.com", "PUT", ""),
+        ("https://app.example.com", "GET", "X-Secret"),
+    ] {
+        let raw = format!(
+            "OPTIONS / HTTP/1.1\r\nHost: a\r\nConnection: close\r\nOrigin: {}\r\n\
+             Access-Control-Request-Method: {}\r\nAccess-Control-Request-Headers: {}\r\n\r\n",
+            origin, method, headers
+        );
+        let response = send_request(handler.clone(), raw.as_bytes()).await;
+        assert_eq!(response.status, 403, "{} {} {}", origin, method, headers);
+        assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+    }
+
+    // OPTIONS without a preflight's headers is passed on
+    let response = send_request(
+        handler,
+        b"OPTIONS / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert_eq!(response.status, 500);
+}
+
+#[tokio::test]
+async fn test_actual_responses_get_the_policy_headers() {
+    let handler = chain(POLICY);
+    let response = send_request(
+        handler.clone(),
+        b"GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\nOrigin: https://eu.example.org\r\n\r\n",
+    )
+    .await;
+    assert_eq!(response.status, 200);
+    assert_eq!(
+        response.header("Access-Control-Allow-Origin"),
+        Some("https://eu.example.org")
+    );
+    assert_eq!(
+        response.header("Access-Control-Expose-Headers"),
+        Some("X-Request-Id")
+    );
+    assert_eq!(response.header("Vary"), Some("Accept-Encoding, Origin"));
+
+    // The backend can't let in origins the policy refuses
+    let response = send_request(
+        handler,
+        b"GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\nOrigin: https://evil.test\r\n\r\n",
+    )
+    .await;
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+
+    let open = chain("allow_origins: [\"*\"]");
+    let response = send_request(
+        open,
+        b"GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\nOrigin: https://x.test\r\n\r\n",
+    )
+    .await;
+    assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+    assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+}
+
+#[test]
+fn test_method_conditions_let_preflights_through() {
+    let route: RouteConfig = serde_yaml::from_str(
+        r#"
+prefix: /api/
+static_files: true
+methods: [POST]
+cors:
+  allow_origins: ["https://app.example.com"]
+"#,
+    )
+    .unwrap();
+    assert!(route.validate().is_ok());
+    let predicate = route.predicate().unwrap().unwrap();
+    let preflight = RequestBuilder::new()
+        .method(Method::OPTIONS)
+        .path("/api/")
+        .build()
+        .unwrap();
+    assert!(predicate.matches(&preflight));
+}
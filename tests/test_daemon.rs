@@ -0,0 +1,110 @@
+//! Tests for daemon mode
+
+#![cfg(unix)]
+
+use sentinel::server::daemon::{LogFile, PidFile};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::Duration;
+use tracing_subscriber::fmt::MakeWriter;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("sentinel-daemon-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_pidfile_refuses_running_instance() {
+    let dir = temp_dir("pidfile");
+    let path = dir.join("sentinel.pid");
+
+    let pid_file = PidFile::create(&path).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        format!("{}\n", std::process::id())
+    );
+    // This process is alive, so a second instance must not start
+    assert!(PidFile::create(&path).is_err());
+    drop(pid_file);
+    assert!(!path.exists());
+
+    // A pidfile left behind by a dead process is replaced
+    std::fs::write(&path, "999999999\n").unwrap();
+    let pid_file = PidFile::create(&path).unwrap();
+    assert_eq!(pid_file.path(), path);
+    drop(pid_file);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_log_file_reopens_after_rotation() {
+    let dir = temp_dir("log");
+    let path = dir.join("sentinel.log");
+    let log = LogFile::open(&path).unwrap();
+
+    writeln!(log.make_writer(), "before").unwrap();
+    std::fs::rename(&path, dir.join("sentinel.log.1")).unwrap();
+    // Until reopened, writes follow the rotated file
+    writeln!(log.clone().make_writer(), "rotated").unwrap();
+    log.reopen().unwrap();
+    writeln!(log.make_writer(), "after").unwrap();
+
+    assert_eq!(
+        std::fs::read_to_string(dir.join("sentinel.log.1")).unwrap(),
+        "before\nrotated\n"
+    );
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "after\n");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_binary_daemonizes_and_stops_on_sigterm() {
+    let dir = temp_dir("binary");
+    std::fs::create_dir_all(dir.join("public")).unwrap();
+    std::fs::write(
+        dir.join("config.yaml"),
+        "server:\n  listen_addr: \"127.0.0.1:0\"\n  daemon:\n    pid_file: sentinel.pid\n    log_file: sentinel.log\nstatic_files:\n  root: public\n  index: index.html\n",
+    )
+    .unwrap();
+
+    // The launcher returns once the daemon has written its pidfile
+    let status = Command::new(env!("CARGO_BIN_EXE_sentinel"))
+        .current_dir(&dir)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let pid: i32 = std::fs::read_to_string(dir.join("sentinel.pid"))
+        .unwrap()
+        .trim()
+        .parse()
+        .unwrap();
+
+    // A second launch sees the running daemon and fails
+    let output = Command::new(env!("CARGO_BIN_EXE_sentinel"))
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already running"));
+
+    Command::new("kill")
+        .args(["-TERM", &pid.to_string()])
+        .status()
+        .unwrap();
+    for _ in 0..100 {
+        if !dir.join("sentinel.pid").exists() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    assert!(!dir.join("sentinel.pid").exists());
+    let log = std::fs::read_to_string(dir.join("sentinel.log")).unwrap();
+    assert!(log.contains("Shutdown signal received"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
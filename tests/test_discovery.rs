@@ -0,0 +1,369 @@
+//! Tests for dynamic backend discovery
+
+use sentinel::config::{ConsulConfig, DockerConfig, FileDiscoveryConfig};
+use sentinel::discovery::{ConsulWatcher, DockerWatcher, FileWatcher};
+use sentinel::http::handler::Handler;
+use sentinel::proxy::{BackendPool, DynamicRoutes};
+use sentinel::testing::{
+    MockAction, MockBackend, MockResponse, TEST_CONNECT_TIMEOUT, TEST_REQUEST_TIMEOUT, send_request,
+};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+const HEALTH: &str = r#"[
+  {
+    "Node": {"Address": "10.0.0.1"},
+    "Service": {"ID": "web-1", "Address": "", "Port": 8080, "Tags": ["production"]},
+    "Checks": [{"Status": "passing"}, {"Status": "passing"}]
+  },
+  {
+    "Node": {"Address": "10.0.0.2"},
+    "Service": {"ID": "web-2", "Address": "10.1.0.2", "Port": 8080, "Tags": ["production"]},
+    "Checks": [{"Status": "passing"}, {"Status": "warning"}]
+  },
+  {
+    "Node": {"Address": "10.0.0.3"},
+    "Service": {"ID": "web-3", "Address": "", "Port": 8080, "Tags": ["production"]},
+    "Checks": [{"Status": "critical"}]
+  },
+  {
+    "Node": {"Address": "10.0.0.4"},
+    "Service": {"ID": "web-4", "Address": "", "Port": 9090, "Tags": ["canary"]},
+    "Checks": []
+  }
+]"#;
+
+fn consul_config(consul: &MockBackend) -> ConsulConfig {
+    ConsulConfig {
+        address: consul.url(),
+        service: "web".to_string(),
+        datacenter: Some("dc1".to_string()),
+        tags: vec![],
+        only_passing: true,
+        refresh_interval_secs: 1,
+        token: None,
+        scheme: "http".to_string(),
+    }
+}
+
+fn health_response(index: u64) -> MockAction {
+    MockAction::Respond(
+        MockResponse::new(200)
+            .header("Content-Type", "application/json")
+            .header("X-Consul-Index", index.to_string())
+            .body(HEALTH),
+    )
+}
+
+#[tokio::test]
+async fn test_consul_populates_pool_with_passing_instances() {
+    let consul = MockBackend::start().await;
+    consul.push(health_response(7));
+
+    let pool = BackendPool::new(vec![]);
+    let mut watcher = ConsulWatcher::new(consul_config(&consul), pool.clone());
+    let count = watcher.poll().await.unwrap();
+
+    let urls: Vec<_> = pool
+        .get_backends()
+        .await
+        .into_iter()
+        .map(|b| b.url)
+        .collect();
+    assert_eq!(count, 2);
+    assert_eq!(urls, vec!["http://10.0.0.1:8080", "http://10.0.0.4:9090"]);
+
+    let request = &consul.requests()[0];
+    assert_eq!(request.path, "/v1/health/service/web?dc=dc1");
+}
+
+#[tokio::test]
+async fn test_consul_warning_allowed_when_not_only_passing() {
+    let consul = MockBackend::start().await;
+    consul.push(health_response(7));
+
+    let pool = BackendPool::new(vec![]);
+    let config = ConsulConfig {
+        only_passing: false,
+        ..consul_config(&consul)
+    };
+    ConsulWatcher::new(config, pool.clone())
+        .poll()
+        .await
+        .unwrap();
+
+    assert_eq!(pool.available_count().await, 3);
+}
+
+#[tokio::test]
+async fn test_consul_filters_by_tags() {
+    let consul = MockBackend::start().await;
+    consul.push(health_response(7));
+
+    let pool = BackendPool::new(vec![]);
+    let config = ConsulConfig {
+        tags: vec!["production".to_string()],
+        ..consul_config(&consul)
+    };
+    ConsulWatcher::new(config, pool.clone())
+        .poll()
+        .await
+        .unwrap();
+
+    let backends = pool.get_backends().await;
+    assert_eq!(backends.len(), 1);
+    assert_eq!(backends[0].name.as_deref(), Some("web/web-1"));
+}
+
+#[tokio::test]
+async fn test_consul_uses_blocking_queries_after_first_poll() {
+    let consul = MockBackend::start().await;
+    consul.push(health_response(7));
+    consul.push(health_response(9));
+
+    let pool = BackendPool::new(vec![]);
+    let mut watcher = ConsulWatcher::new(consul_config(&consul), pool);
+    watcher.poll().await.unwrap();
+    watcher.poll().await.unwrap();
+
+    let requests = consul.requests();
+    assert_eq!(
+        requests[1].path,
+        "/v1/health/service/web?dc=dc1&index=7&wait=1s"
+    );
+}
+
+#[tokio::test]
+async fn test_consul_error_keeps_current_backends() {
+    let consul = MockBackend::start().await;
+    consul.push(health_response(7));
+    consul.push(MockAction::Respond(MockResponse::new(500).body("boom")));
+
+    let pool = BackendPool::new(vec![]);
+    let mut watcher = ConsulWatcher::new(consul_config(&consul), pool.clone());
+    watcher.poll().await.unwrap();
+
+    assert!(watcher.poll().await.is_err());
+    assert_eq!(pool.available_count().await, 2);
+}
+
+fn docker_config(docker: &MockBackend) -> DockerConfig {
+    DockerConfig {
+        endpoint: docker.url(),
+        network: None,
+        label_prefix: "sentinel".to_string(),
+        refresh_interval_secs: 1,
+        scheme: "http".to_string(),
+    }
+}
+
+fn container(name: &str, labels: &str, ports: &str, networks: &str) -> String {
+    format!(
+        r#"{{"Id": "{name}0123456789abcdef", "Names": ["/{name}"], "Labels": {labels},
+            "Ports": {ports}, "NetworkSettings": {{"Networks": {networks}}}}}"#
+    )
+}
+
+fn containers_response(containers: &[String]) -> MockAction {
+    MockAction::Respond(
+        MockResponse::new(200)
+            .header("Content-Type", "application/json")
+            .body(format!("[{}]", containers.join(","))),
+    )
+}
+
+#[tokio::test]
+async fn test_docker_registers_labelled_containers() {
+    let docker = MockBackend::start().await;
+    docker.push(containers_response(&[
+        container(
+            "web",
+            r#"{"sentinel.enable": "true"}"#,
+            r#"[{"PrivatePort": 8080, "Type": "tcp"}]"#,
+            r#"{"bridge": {"IPAddress": "172.17.0.2"}}"#,
+        ),
+        container(
+            "api",
+            r#"{"sentinel.enable": "true", "sentinel.port": "9000"}"#,
+            r#"[{"PrivatePort": 8080, "Type": "tcp"}, {"PrivatePort": 9000, "Type": "tcp"}]"#,
+            r#"{"bridge": {"IPAddress": ""}, "web": {"IPAddress": "10.5.0.3"}}"#,
+        ),
+        // Two exposed ports and no port label
+        container(
+            "db",
+            r#"{"sentinel.enable": "true"}"#,
+            r#"[{"PrivatePort": 5432, "Type": "tcp"}, {"PrivatePort": 9187, "Type": "tcp"}]"#,
+            r#"{"bridge": {"IPAddress": "172.17.0.4"}}"#,
+        ),
+    ]));
+
+    let pool = BackendPool::new(vec![]);
+    let count = DockerWatcher::new(docker_config(&docker), pool.clone())
+        .poll()
+        .await
+        .unwrap();
+
+    let backends = pool.get_backends().await;
+    let urls: Vec<_> = backends.iter().map(|b| b.url.as_str()).collect();
+    assert_eq!(count, 2);
+    assert_eq!(urls, vec!["http://10.5.0.3:9000", "http://172.17.0.2:8080"]);
+    assert_eq!(backends[0].name.as_deref(), Some("api"));
+
+    let request = &docker.requests()[0];
+    assert!(request.path.starts_with("/containers/json?filters="));
+    assert!(request.path.contains("sentinel.enable%3Dtrue"));
+}
+
+#[tokio::test]
+async fn test_docker_uses_configured_network() {
+    let docker = MockBackend::start().await;
+    docker.push(containers_response(&[container(
+        "web",
+        r#"{"sentinel.enable": "true", "sentinel.port": "80"}"#,
+        "[]",
+        r#"{"bridge": {"IPAddress": "172.17.0.2"}, "frontend": {"IPAddress": "10.9.0.2"}}"#,
+    )]));
+
+    let pool = BackendPool::new(vec![]);
+    let config = DockerConfig {
+        network: Some("frontend".to_string()),
+        ..docker_config(&docker)
+    };
+    DockerWatcher::new(config, pool.clone())
+        .poll()
+        .await
+        .unwrap();
+
+    assert_eq!(pool.get_backends().await[0].url, "http://10.9.0.2:80");
+}
+
+#[tokio::test]
+async fn test_docker_route_label_creates_dynamic_route() {
+    let app = MockBackend::start().await;
+    app.set_default(MockAction::Respond(MockResponse::new(200).body("from app")));
+
+    let docker = MockBackend::start().await;
+    let routed = container(
+        "app",
+        &format!(
+            r#"{{"sentinel.enable": "true", "sentinel.route": "/app", "sentinel.port": "{}"}}"#,
+            app.addr().port()
+        ),
+        "[]",
+        r#"{"bridge": {"IPAddress": "127.0.0.1"}}"#,
+    );
+    docker.push(containers_response(std::slice::from_ref(&routed)));
+    docker.push(containers_response(&[]));
+
+    let pool = BackendPool::new(vec![]);
+    let routes = DynamicRoutes::new(TEST_CONNECT_TIMEOUT, TEST_REQUEST_TIMEOUT);
+    let watcher =
+        DockerWatcher::new(docker_config(&docker), pool.clone()).with_routes(routes.clone());
+    watcher.poll().await.unwrap();
+
+    assert_eq!(routes.prefixes(), vec!["/app"]);
+    assert_eq!(pool.get_backends().await.len(), 0);
+
+    let handler: Arc<dyn Handler> = Arc::new(routes.clone());
+    let response = send_request(
+        handler.clone(),
+        b"GET /app/index HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert_eq!(response.text(), "from app");
+
+    let response = send_request(
+        handler,
+        b"GET /other HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert_eq!(response.status, 404);
+
+    // The container went away
+    watcher.poll().await.unwrap();
+    assert!(routes.prefixes().is_empty());
+}
+
+/// A backend list file in the temp directory, removed on drop
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("sentinel-{}-{}", std::process::id(), name));
+        Self(path)
+    }
+
+    fn write(&self, contents: &str) {
+        std::fs::write(&self.0, contents).unwrap();
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+fn file_config(file: &TempFile) -> FileDiscoveryConfig {
+    FileDiscoveryConfig {
+        path: file.0.clone(),
+        refresh_interval_secs: 1,
+    }
+}
+
+#[tokio::test]
+async fn test_file_loads_yaml_and_reloads_on_change() {
+    let file = TempFile::new("backends.yaml");
+    file.write("backends:\n  - url: \"http://10.0.0.1:3000\"\n    name: web-1\n");
+
+    let pool = BackendPool::new(vec![]);
+    let mut watcher = FileWatcher::new(file_config(&file), pool.clone());
+
+    assert_eq!(watcher.poll().await.unwrap(), Some(1));
+    assert_eq!(watcher.poll().await.unwrap(), None);
+
+    file.write("- url: \"http://10.0.0.1:3000\"\n- url: \"http://10.0.0.2:3000\"\n");
+    assert_eq!(watcher.poll().await.unwrap(), Some(2));
+
+    let urls: Vec<_> = pool
+        .get_backends()
+        .await
+        .into_iter()
+        .map(|b| b.url)
+        .collect();
+    assert_eq!(urls, vec!["http://10.0.0.1:3000", "http://10.0.0.2:3000"]);
+}
+
+#[tokio::test]
+async fn test_file_loads_json() {
+    let file = TempFile::new("backends.json");
+    file.write(r#"[{"url": "http://10.0.0.1:3000"}, {"url": "http://10.0.0.2:3000"}]"#);
+
+    let pool = BackendPool::new(vec![]);
+    FileWatcher::new(file_config(&file), pool.clone())
+        .poll()
+        .await
+        .unwrap();
+
+    assert_eq!(pool.available_count().await, 2);
+}
+
+#[tokio::test]
+async fn test_file_invalid_contents_keep_current_backends() {
+    let file = TempFile::new("invalid.yaml");
+    file.write("- url: \"http://10.0.0.1:3000\"\n");
+
+    let pool = BackendPool::new(vec![]);
+    let mut watcher = FileWatcher::new(file_config(&file), pool.clone());
+    watcher.poll().await.unwrap();
+
+    file.write("- url: \"ftp://10.0.0.9\"\n");
+    assert!(watcher.poll().await.is_err());
+
+    file.write("backends: [unterminated");
+    assert!(watcher.poll().await.is_err());
+
+    let backends = pool.get_backends().await;
+    assert_eq!(backends.len(), 1);
+    assert_eq!(backends[0].url, "http://10.0.0.1:3000");
+}
@@ -0,0 +1,263 @@
+//! Tests for templated error pages
+
+use sentinel::config::{ErrorPages, InterceptErrors, StaticFilesConfig};
+use sentinel::http::error_pages::{ErrorContext, ErrorFormat, ErrorPageHandler, ErrorPageRenderer};
+use sentinel::http::handler::handler_fn;
+use sentinel::http::response::{Response, StatusCode};
+use sentinel::http::static_files::StaticFileHandler;
+use sentinel::testing::{MockAction, MockBackend, MockResponse, proxy_handler, send_request};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+fn get(path: &str, extra: &str) -> Vec<u8> {
+    format!(
+        "GET {} HTTP/1.1\r\nHost: a\r\n{}Connection: close\r\n\r\n",
+        path, extra
+    )
+    .into_bytes()
+}
+
+fn renderer() -> Arc<ErrorPageRenderer> {
+    Arc::new(
+        ErrorPageRenderer::new()
+            .with_template(
+                404,
+                "text/html",
+                "<p>$status $reason: $path ($request_id)</p>",
+            )
+            .with_template(502, "application/json", r#"{"status":$status}"#),
+    )
+}
+
+#[tokio::test]
+async fn test_generated_errors_use_templates() {
+    let static_files = StaticFilesConfig {
+        root: std::env::temp_dir().join("sentinel-missing-root"),
+        index: "index.html".to_string(),
+        error_pages: ErrorPages::default(),
+        directory_listing: false,
+        images: None,
+    };
+    let handler = Arc::new(ErrorPageHandler::new(
+        StaticFileHandler::new(static_files),
+        renderer(),
+    ));
+
+    let response = send_request(
+        handler.clone(),
+        &get("/<b>.html", "X-Request-Id: abc123\r\n"),
+    )
+    .await;
+    assert_eq!(response.status, 404);
+    assert_eq!(response.header("Content-Type"), Some("text/html"));
+    assert_eq!(response.header("X-Request-Id"), Some("abc123"));
+    assert_eq!(
+        response.text(),
+        "<p>404 Not Found: /&lt;b&gt;.html (abc123)</p>"
+    );
+
+    // Without a client ID one is generated and echoed
+    let response = send_request(handler, &get("/missing", "")).await;
+    let id = response.header("X-Request-Id").unwrap().to_string();
+    assert!(response.text().contains(&id));
+}
+
+#[tokio::test]
+async fn test_proxy_errors_are_rendered_but_backend_errors_are_not() {
+    let backend = MockBackend::start().await;
+    backend.set_default(MockAction::Respond(
+        MockResponse::new(404)
+            .reason("Not Found")
+            .body("backend 404"),
+    ));
+    let handler = Arc::new(ErrorPageHandler::new(
+        proxy_handler(&[&backend]),
+        renderer(),
+    ));
+
+    let response = send_request(handler.clone(), &get("/", "")).await;
+    assert_eq!(response.status, 404);
+    assert_eq!(response.text(), "backend 404");
+
+    backend.set_default(MockAction::Reset);
+    let response = send_request(handler, &get("/", "")).await;
+    assert_eq!(response.status, 502);
+    assert_eq!(response.header("Content-Type"), Some("application/json"));
+    assert_eq!(response.text(), r#"{"status":502}"#);
+}
+
+#[tokio::test]
+async fn test_statuses_without_template_are_negotiated() {
+    let inner = handler_fn(|_req| async {
+        let mut response = Response::error(
+            StatusCode::ServiceUnavailable,
+            "No backend servers are available.",
+        );
+        response
+            .headers
+            .insert("Retry-After".to_string(), "7".to_string());
+        response
+    });
+    let handler = Arc::new(ErrorPageHandler::new(inner, renderer()));
+
+    let response = send_request(
+        handler.clone(),
+        &get("/", "Accept: application/json\r\nX-Request-Id: r1\r\n"),
+    )
+    .await;
+    assert_eq!(response.status, 503);
+    assert_eq!(response.header("Content-Type"), Some("application/json"));
+    let body: serde_json::Value = serde_json::from_str(&response.text()).unwrap();
+    assert_eq!(
+        body,
+        serde_json::json!({
+            "status": 503,
+            "error": "service_unavailable",
+            "message": "No backend servers are available.",
+            "request_id": "r1",
+            "retry_after": 7,
+        })
+    );
+
+    let response = send_request(
+        handler.clone(),
+        &get(
+            "/",
+            "Accept: text/html,application/json;q=0.9\r\nX-Request-Id: r2\r\n",
+        ),
+    )
+    .await;
+    assert_eq!(
+        response.header("Content-Type"),
+        Some("text/html; charset=utf-8")
+    );
+    assert!(response.text().contains("<h1>503 Service Unavailable</h1>"));
+    assert!(response.text().contains("Request ID: r2"));
+
+    let response = send_request(handler, &get("/", "X-Request-Id: r3\r\n")).await;
+    assert_eq!(
+        response.text(),
+        "503 Service Unavailable\r\n\r\nNo backend servers are available.\r\n\r\nRequest ID: r3"
+    );
+}
+
+#[test]
+fn test_accept_negotiation() {
+    let negotiate = |accept| ErrorFormat::negotiate(accept);
+
+    assert_eq!(negotiate(None), ErrorFormat::Text);
+    assert_eq!(negotiate(Some("*/*")), ErrorFormat::Text);
+    assert_eq!(negotiate(Some("application/json")), ErrorFormat::Json);
+    assert_eq!(
+        negotiate(Some("application/problem+json")),
+        ErrorFormat::Json
+    );
+    assert_eq!(
+        negotiate(Some("text/html,application/xhtml+xml,*/*;q=0.8")),
+        ErrorFormat::Html
+    );
+    assert_eq!(
+        negotiate(Some("text/html;q=0.5, application/json")),
+        ErrorFormat::Json
+    );
+    assert_eq!(
+        negotiate(Some("application/json;q=0, text/plain;q=0.1")),
+        ErrorFormat::Text
+    );
+}
+
+#[test]
+fn test_pages_override_legacy_fields_and_load_from_root() {
+    let root = std::env::temp_dir().join(format!("sentinel-error-pages-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("404.html"), "legacy").unwrap();
+    std::fs::write(root.join("gone.html"), "$status gone").unwrap();
+
+    let mut pages = BTreeMap::new();
+    pages.insert(404, "gone.html".to_string());
+    pages.insert(503, "missing.html".to_string());
+    let config = ErrorPages {
+        not_found: Some("404.html".to_string()),
+        bad_request: None,
+        pages,
+    };
+    assert!(config.validate().is_ok());
+
+    let renderer = ErrorPageRenderer::load(&root, &config);
+    let ctx = ErrorContext {
+        path: "/x".to_string(),
+        request_id: "id".to_string(),
+        format: ErrorFormat::Text,
+    };
+    let mut response = Response::not_found();
+    assert!(renderer.apply(&ctx, &mut response));
+    assert_eq!(response.body, b"404 gone".to_vec());
+
+    // Unreadable templates are skipped
+    let mut response = Response::error(StatusCode::ServiceUnavailable, "");
+    assert!(renderer.apply(&ctx, &mut response));
+    assert_eq!(
+        response.body,
+        b"503 Service Unavailable\r\n\r\nService Unavailable\r\n\r\nRequest ID: id".to_vec()
+    );
+    std::fs::remove_dir_all(&root).unwrap();
+
+    let mut invalid = ErrorPages::default();
+    invalid.pages.insert(302, "found.html".to_string());
+    assert!(invalid.validate().is_err());
+}
+
+#[tokio::test]
+async fn test_intercepted_backend_errors_keep_status() {
+    let backend = MockBackend::start().await;
+    backend.set_default(MockAction::Respond(
+        MockResponse::new(502)
+            .reason("Bad Gateway")
+            .header("Content-Type", "text/html")
+            .header("Retry-After", "5")
+            .body("<pre>stack trace</pre>"),
+    ));
+    let intercept = |path_prefix: &str, min_status| InterceptErrors {
+        path_prefix: path_prefix.to_string(),
+        min_status,
+    };
+    let handler = Arc::new(
+        ErrorPageHandler::new(proxy_handler(&[&backend]), renderer())
+            .with_intercepts(vec![intercept("/", 500), intercept("/raw", 599)]),
+    );
+
+    let response = send_request(handler.clone(), &get("/app", "")).await;
+    assert_eq!(response.status, 502);
+    assert_eq!(response.header("Content-Type"), Some("application/json"));
+    assert_eq!(response.header("Retry-After"), Some("5"));
+    assert_eq!(response.text(), r#"{"status":502}"#);
+
+    // The longest prefix wins and its threshold is not reached
+    let response = send_request(handler, &get("/raw/app", "")).await;
+    assert_eq!(response.text(), "<pre>stack trace</pre>");
+}
+
+#[tokio::test]
+async fn test_intercepted_errors_without_template_are_negotiated() {
+    let backend = MockBackend::start().await;
+    backend.set_default(MockAction::Respond(
+        MockResponse::new(400)
+            .reason("Bad Request")
+            .body("Traceback (most recent call last)"),
+    ));
+    let handler = Arc::new(
+        ErrorPageHandler::new(proxy_handler(&[&backend]), renderer()).with_intercepts(vec![
+            InterceptErrors {
+                path_prefix: "/".to_string(),
+                min_status: 400,
+            },
+        ]),
+    );
+
+    let response = send_request(handler, &get("/", "X-Request-Id: abc\r\n")).await;
+    assert_eq!(response.status, 400);
+    assert_eq!(
+        response.text(),
+        "400 Bad Request\r\n\r\nBad Request\r\n\r\nRequest ID: abc"
+    );
+}
@@ -0,0 +1,139 @@
+//! Tests for lifecycle events
+
+use sentinel::config::BackendConfig;
+use sentinel::events::{Event, EventSubscriber, Events};
+use sentinel::http::connection::Connection;
+use sentinel::http::handler::handler_fn;
+use sentinel::http::response::Response;
+use sentinel::proxy::backend::{BackendPool, BackendState};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+#[derive(Default)]
+struct Recorder {
+    events: Mutex<Vec<Event>>,
+}
+
+impl EventSubscriber for Recorder {
+    fn on_event(&self, event: &Event) {
+        self.events.lock().unwrap().push(event.clone());
+    }
+}
+
+fn pool(urls: &[&str]) -> BackendPool {
+    BackendPool::new(
+        urls.iter()
+            .map(|url| BackendConfig {
+                url: url.to_string(),
+                name: None,
+                ..Default::default()
+            })
+            .collect(),
+    )
+}
+
+#[test]
+fn test_subscriber_receives_emitted_events() {
+    let events = Events::new();
+    let recorder = Arc::new(Recorder::default());
+    events.add_subscriber(recorder.clone());
+
+    events.emit(Event::ConfigReloaded {
+        source: "config.yaml".to_string(),
+    });
+
+    let seen = recorder.events.lock().unwrap();
+    assert_eq!(seen.len(), 1);
+    assert!(matches!(&seen[0], Event::ConfigReloaded { source } if source == "config.yaml"));
+}
+
+#[tokio::test]
+async fn test_channel_receives_emitted_events() {
+    let events = Events::new();
+    let mut rx = events.channel();
+
+    events.emit(Event::BackendSelected {
+        backend: "http://localhost:3000".to_string(),
+    });
+
+    let event = rx.recv().await.unwrap();
+    assert!(
+        matches!(event, Event::BackendSelected { backend } if backend == "http://localhost:3000")
+    );
+}
+
+#[tokio::test]
+async fn test_pool_emits_backend_selected() {
+    let events = Events::new();
+    let recorder = Arc::new(Recorder::default());
+    events.add_subscriber(recorder.clone());
+
+    let pool = pool(&["http://localhost:3000"]).with_events(events);
+    pool.select_backend().await.unwrap();
+
+    let seen = recorder.events.lock().unwrap();
+    assert!(matches!(&seen[..], [Event::BackendSelected { .. }]));
+}
+
+#[tokio::test]
+async fn test_pool_emits_state_changes_only_on_transition() {
+    let events = Events::new();
+    let recorder = Arc::new(Recorder::default());
+    events.add_subscriber(recorder.clone());
+
+    let pool = pool(&["http://localhost:3000"]).with_events(events);
+
+    for _ in 0..4 {
+        pool.mark_backend_failed("http://localhost:3000").await;
+    }
+    pool.mark_backend_success("http://localhost:3000").await;
+    pool.mark_backend_success("http://localhost:3000").await;
+
+    let seen = recorder.events.lock().unwrap();
+    let transitions: Vec<_> = seen
+        .iter()
+        .filter_map(|e| match e {
+            Event::BackendStateChanged { from, to, .. } => Some((*from, *to)),
+            _ => None,
+        })
+        .collect();
+
+    assert_eq!(
+        transitions,
+        vec![
+            (BackendState::Up, BackendState::Down),
+            (BackendState::Down, BackendState::Up)
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_connection_emits_request_events() {
+    let events = Events::new();
+    let recorder = Arc::new(Recorder::default());
+    events.add_subscriber(recorder.clone());
+
+    let (mut client, server) = tokio::io::duplex(4096);
+
+    let server_events = events.clone();
+    let server = tokio::spawn(async move {
+        let handler = Arc::new(handler_fn(|_req| async { Response::ok(b"ok".to_vec()) }));
+        let mut conn = Connection::with_handler(server, handler).with_events(server_events);
+        conn.run().await.unwrap();
+    });
+
+    client
+        .write_all(b"GET /hello HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).await.unwrap();
+    server.await.unwrap();
+
+    let seen = recorder.events.lock().unwrap();
+    assert!(matches!(&seen[0], Event::RequestStarted { path, .. } if path == "/hello"));
+    assert!(matches!(
+        &seen[1],
+        Event::RequestFinished { status: 200, .. }
+    ));
+}
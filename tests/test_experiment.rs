@@ -0,0 +1,176 @@
+//! Tests for A/B experiment assignment and routing
+
+use sentinel::config::{ExperimentConfig, ProxyConfig, VariantConfig};
+use sentinel::metrics::{Metrics, PrometheusRecorder};
+use sentinel::proxy::Experiment;
+use sentinel::testing::{MockAction, MockBackend, MockResponse, proxy_handler, send_request};
+use std::sync::Arc;
+
+async fn backend(body: &str) -> MockBackend {
+    let backend = MockBackend::start().await;
+    backend.set_default(MockAction::Respond(
+        MockResponse::new(200).reason("OK").body(body),
+    ));
+    backend
+}
+
+fn config(variants: &[(&str, u8)]) -> ExperimentConfig {
+    ExperimentConfig {
+        name: "checkout".to_string(),
+        path_prefix: "/checkout".to_string(),
+        secret: "s3cret".to_string(),
+        cookie: None,
+        header: "X-Sentinel-Variant".to_string(),
+        max_age_secs: 2_592_000,
+        variants: variants
+            .iter()
+            .map(|(name, percent)| VariantConfig {
+                name: name.to_string(),
+                percent: *percent,
+                backends: Vec::new(),
+            })
+            .collect(),
+    }
+}
+
+fn get(cookie: Option<&str>) -> Vec<u8> {
+    let cookie = cookie
+        .map(|c| format!("Cookie: theme=dark; {}\r\n", c))
+        .unwrap_or_default();
+    format!(
+        "GET /checkout HTTP/1.1\r\nHost: a\r\n{}Connection: close\r\n\r\n",
+        cookie
+    )
+    .into_bytes()
+}
+
+#[tokio::test]
+async fn test_new_client_gets_signed_cookie_and_variant_header() {
+    let main = backend("main").await;
+    let experiment = Arc::new(Experiment::new(
+        &config(&[("control", 0), ("new-flow", 100)]),
+        Arc::new(proxy_handler(&[&main])),
+    ));
+
+    let response = send_request(experiment.clone(), &get(None)).await;
+
+    let expected = format!(
+        "sentinel_exp_checkout={}; Path=/checkout; Max-Age=2592000; HttpOnly; SameSite=Lax",
+        experiment.cookie_value("new-flow")
+    );
+    assert_eq!(response.header("Set-Cookie"), Some(expected.as_str()));
+    assert_eq!(
+        main.requests()[0].header("X-Sentinel-Variant"),
+        Some("new-flow")
+    );
+}
+
+#[tokio::test]
+async fn test_assigned_variant_is_sticky_and_uses_its_pool() {
+    let main = backend("main").await;
+    let variant = backend("variant").await;
+    let experiment = Arc::new(
+        Experiment::new(
+            &config(&[("control", 100), ("new-flow", 0)]),
+            Arc::new(proxy_handler(&[&main])),
+        )
+        .with_variant_handler("new-flow", proxy_handler(&[&variant])),
+    );
+
+    let cookie = format!(
+        "sentinel_exp_checkout={}",
+        experiment.cookie_value("new-flow")
+    );
+    let response = send_request(experiment.clone(), &get(Some(&cookie))).await;
+
+    // Kept in new-flow despite it getting 0% of new clients
+    assert_eq!(response.text(), "variant");
+    assert_eq!(response.header("Set-Cookie"), None);
+    assert_eq!(main.request_count(), 0);
+}
+
+#[tokio::test]
+async fn test_tampered_cookie_is_reassigned() {
+    let main = backend("main").await;
+    let variant = backend("variant").await;
+    let experiment = Arc::new(
+        Experiment::new(
+            &config(&[("control", 100), ("new-flow", 0)]),
+            Arc::new(proxy_handler(&[&main])),
+        )
+        .with_variant_handler("new-flow", proxy_handler(&[&variant])),
+    );
+
+    let forged = experiment
+        .cookie_value("control")
+        .replacen("control", "new-flow", 1);
+    let response = send_request(
+        experiment.clone(),
+        &get(Some(&format!("sentinel_exp_checkout={}", forged))),
+    )
+    .await;
+
+    assert_eq!(response.text(), "main");
+    assert!(response.header("Set-Cookie").is_some());
+    assert_eq!(variant.request_count(), 0);
+}
+
+#[tokio::test]
+async fn test_records_per_variant_metrics() {
+    let main = backend("main").await;
+    let recorder = Arc::new(PrometheusRecorder::new());
+    let experiment = Arc::new(
+        Experiment::new(
+            &config(&[("control", 100)]),
+            Arc::new(proxy_handler(&[&main])),
+        )
+        .with_metrics(Metrics::new(recorder.clone())),
+    );
+
+    send_request(experiment.clone(), &get(None)).await;
+    let cookie = format!(
+        "sentinel_exp_checkout={}",
+        experiment.cookie_value("control")
+    );
+    send_request(experiment.clone(), &get(Some(&cookie))).await;
+
+    let output = recorder.render();
+    assert!(output.contains(
+        "sentinel_experiment_assignments_total{experiment=\"checkout\",variant=\"control\"} 1"
+    ));
+    assert!(output.contains(
+        "sentinel_experiment_requests_total{experiment=\"checkout\",status=\"200\",variant=\"control\"} 2"
+    ));
+}
+
+#[test]
+fn test_config_requires_percentages_to_add_up() {
+    let parse = |variants: &str| {
+        serde_yaml::from_str::<ProxyConfig>(&format!(
+            "backends: [{{ url: \"http://10.0.0.1:8080\" }}]\nexperiments:\n  - name: checkout\n    secret: s3cret\n    variants: {}\n",
+            variants
+        ))
+        .unwrap()
+    };
+
+    assert!(
+        parse("[{ name: a, percent: 50 }, { name: b, percent: 50 }]")
+            .validate()
+            .is_ok()
+    );
+    assert!(
+        parse("[{ name: a, percent: 50 }, { name: b, percent: 40 }]")
+            .validate()
+            .is_err()
+    );
+    assert!(
+        parse("[{ name: a, percent: 50 }, { name: a, percent: 50 }]")
+            .validate()
+            .is_err()
+    );
+    assert!(
+        parse("[{ name: \"a b\", percent: 100 }]")
+            .validate()
+            .is_err()
+    );
+}
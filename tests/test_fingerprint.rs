@@ -0,0 +1,278 @@
+//! Tests for JA3/JA4 TLS client fingerprinting and fingerprint rules
+
+use sentinel::config::{FingerprintRulesConfig, TlsConfig, TlsVersion, UpstreamTlsConfig};
+use sentinel::http::connection::Connection;
+use sentinel::http::handler::{Handler, handler_fn};
+use sentinel::http::request::{Method, Request, RequestBuilder};
+use sentinel::http::response::Response;
+use sentinel::metrics::{Metrics, PrometheusRecorder};
+use sentinel::middleware::FingerprintFilter;
+use sentinel::tls::{self, ClientHelloRecorder, TlsFingerprint};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+const JA3_FULL: &str = "771,4865-4866-49195,0-16-10-11-13-43-23,29-23,0";
+const JA3: &str = "7e7ee967119be84adc2cf97e57d64928";
+const JA4: &str = "t13d0307h2_5559582ccdc4_1fdf4de06b7e";
+
+fn u16s(values: &[u16]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_be_bytes()).collect()
+}
+
+fn vec16(data: &[u8]) -> Vec<u8> {
+    let mut out = (data.len() as u16).to_be_bytes().to_vec();
+    out.extend_from_slice(data);
+    out
+}
+
+fn vec8(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![data.len() as u8];
+    out.extend_from_slice(data);
+    out
+}
+
+fn extension(kind: u16, data: &[u8]) -> Vec<u8> {
+    let mut out = kind.to_be_bytes().to_vec();
+    out.extend(vec16(data));
+    out
+}
+
+/// ClientHello body (without the handshake header), optionally with GREASE
+fn client_hello(grease: bool) -> Vec<u8> {
+    let g = |value: u16, values: &[u16]| -> Vec<u16> {
+        let mut out = if grease { vec![value] } else { Vec::new() };
+        out.extend_from_slice(values);
+        out
+    };
+
+    let mut sni = vec![0];
+    sni.extend(vec16(b"localhost"));
+    let mut alpn = vec8(b"h2");
+    alpn.extend(vec8(b"http/1.1"));
+
+    let mut extensions = Vec::new();
+    if grease {
+        extensions.extend(extension(0x1a1a, &[]));
+    }
+    extensions.extend(extension(0x0000, &vec16(&sni)));
+    extensions.extend(extension(0x0010, &vec16(&alpn)));
+    extensions.extend(extension(
+        0x000a,
+        &vec16(&u16s(&g(0x2a2a, &[0x001d, 0x0017]))),
+    ));
+    extensions.extend(extension(0x000b, &vec8(&[0])));
+    extensions.extend(extension(0x000d, &vec16(&u16s(&[0x0403, 0x0804, 0x0401]))));
+    extensions.extend(extension(
+        0x002b,
+        &vec8(&u16s(&g(0x3a3a, &[0x0304, 0x0303]))),
+    ));
+    extensions.extend(extension(0x0017, &[]));
+
+    let mut body = vec![0x03, 0x03];
+    body.extend([0; 32]);
+    body.push(0);
+    body.extend(vec16(&u16s(&g(0x0a0a, &[0x1301, 0x1302, 0xc02b]))));
+    body.extend(vec8(&[0]));
+    body.extend(vec16(&extensions));
+    body
+}
+
+/// Handshake message split into records carrying at most `split` bytes
+fn records(body: &[u8], split: usize) -> Vec<u8> {
+    let mut message = vec![1];
+    message.extend(&(body.len() as u32).to_be_bytes()[1..]);
+    message.extend_from_slice(body);
+
+    let mut out = Vec::new();
+    for fragment in message.chunks(split) {
+        out.extend([22, 3, 1]);
+        out.extend(vec16(fragment));
+    }
+    out
+}
+
+#[test]
+fn test_ja3_and_ja4_of_client_hello() {
+    let fingerprint = TlsFingerprint::from_records(&records(&client_hello(true), 4096)).unwrap();
+    assert_eq!(fingerprint.ja3_full, JA3_FULL);
+    assert_eq!(fingerprint.ja3, JA3);
+    assert_eq!(fingerprint.ja4, JA4);
+}
+
+#[test]
+fn test_grease_and_record_splits_do_not_change_fingerprint() {
+    let plain = TlsFingerprint::from_records(&records(&client_hello(false), 4096)).unwrap();
+    let split = TlsFingerprint::from_records(&records(&client_hello(true), 40)).unwrap();
+    assert_eq!(plain, split);
+}
+
+#[test]
+fn test_incomplete_or_foreign_records_are_not_fingerprinted() {
+    let full = records(&client_hello(true), 4096);
+    assert!(TlsFingerprint::from_records(&full[..full.len() - 1]).is_none());
+    assert!(TlsFingerprint::from_records(b"GET / HTTP/1.1\r\n\r\n").is_none());
+    assert!(TlsFingerprint::from_records(&[]).is_none());
+}
+
+#[test]
+fn test_fingerprint_rules() {
+    let fingerprint = TlsFingerprint::from_records(&records(&client_hello(true), 4096)).unwrap();
+    let filter = |allow: &[&str], deny: &[&str]| {
+        FingerprintFilter::new(
+            handler_fn(|_req| async { Response::ok(b"ok".to_vec()) }),
+            FingerprintRulesConfig {
+                allow: allow.iter().map(|s| s.to_string()).collect(),
+                deny: deny.iter().map(|s| s.to_string()).collect(),
+            },
+        )
+    };
+
+    assert_eq!(filter(&[], &[]).rejection(&fingerprint), None);
+    assert_eq!(filter(&[], &[JA3]).rejection(&fingerprint), Some("denied"));
+    assert_eq!(filter(&[], &[JA4]).rejection(&fingerprint), Some("denied"));
+    assert_eq!(
+        filter(&[JA4], &[JA3.to_uppercase().as_str()]).rejection(&fingerprint),
+        Some("denied")
+    );
+    assert_eq!(
+        filter(&["00000000000000000000000000000000"], &[]).rejection(&fingerprint),
+        Some("not_allowed")
+    );
+    assert_eq!(filter(&[JA3], &[]).rejection(&fingerprint), None);
+}
+
+#[tokio::test]
+async fn test_requests_without_fingerprint_pass_rules() {
+    let filter = FingerprintFilter::new(
+        handler_fn(|_req| async { Response::ok(b"ok".to_vec()) }),
+        FingerprintRulesConfig {
+            allow: vec![JA4.to_string()],
+            deny: Vec::new(),
+        },
+    );
+    let req = RequestBuilder::new()
+        .method(Method::GET)
+        .path("/")
+        .build()
+        .unwrap();
+    let response = filter.handle(req).await;
+    assert_eq!(response.status.as_u16(), 200);
+}
+
+#[test]
+fn test_fingerprint_rules_validation() {
+    let rules = |entry: &str| FingerprintRulesConfig {
+        allow: Vec::new(),
+        deny: vec![entry.to_string()],
+    };
+    assert!(rules(JA3).validate().is_ok());
+    assert!(rules(JA4).validate().is_ok());
+    assert!(rules("curl").validate().is_err());
+    assert!(
+        rules("t13d0307h2-5559582ccdc4-1fdf4de06b7e")
+            .validate()
+            .is_err()
+    );
+}
+
+/// Serve one TLS connection through `filter`-style rules, answering with
+/// the client's JA4, and return the raw response
+async fn fetch(cert: &rcgen::CertifiedKey, dir: &std::path::Path, deny: Vec<String>) -> String {
+    let cert_file = dir.join("cert.pem");
+    let key_file = dir.join("key.pem");
+    std::fs::write(&cert_file, cert.cert.pem()).unwrap();
+    std::fs::write(&key_file, cert.key_pair.serialize_pem()).unwrap();
+    let config = TlsConfig {
+        cert_file: cert_file.clone(),
+        key_file,
+        certificates: Default::default(),
+        client_auth: None,
+        reload_interval_secs: 0,
+        min_version: TlsVersion::Tls12,
+        max_version: TlsVersion::Tls13,
+        cipher_suites: Vec::new(),
+        alpn: vec!["http/1.1".to_string()],
+        key_log_file: None,
+        ocsp: None,
+    };
+    let acceptor = TlsAcceptor::from(tls::server_config(&config).unwrap());
+
+    let recorder = Arc::new(PrometheusRecorder::new());
+    let metrics = Metrics::new(recorder.clone());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut stream = acceptor
+            .accept(ClientHelloRecorder::new(socket))
+            .await
+            .unwrap();
+        let fingerprint = stream.get_mut().0.take_fingerprint().unwrap();
+        let handler = FingerprintFilter::new(
+            handler_fn(|req: Request| async move {
+                let fingerprint = req.context.tls_fingerprint.unwrap();
+                Response::ok(fingerprint.ja4.as_bytes().to_vec())
+            }),
+            FingerprintRulesConfig {
+                allow: Vec::new(),
+                deny,
+            },
+        )
+        .with_metrics(metrics);
+        let _ = Connection::with_handler(stream, Arc::new(handler))
+            .with_tls_fingerprint(Arc::new(fingerprint))
+            .run()
+            .await;
+    });
+
+    let client = tls::client_config(&UpstreamTlsConfig {
+        ca_file: Some(cert_file),
+        key_log_file: None,
+        session_cache_size: 256,
+    })
+    .unwrap();
+    let socket = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let mut stream = TlsConnector::from(client)
+        .connect("localhost".try_into().unwrap(), socket)
+        .await
+        .unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    let response = String::from_utf8_lossy(&response).to_string();
+    if response.starts_with("HTTP/1.1 403") {
+        assert!(
+            recorder
+                .render()
+                .contains(r#"sentinel_fingerprint_rejections_total{reason="denied"} 1"#)
+        );
+    }
+    response
+}
+
+#[tokio::test]
+async fn test_tls_client_is_fingerprinted_and_denied() {
+    let dir: PathBuf =
+        std::env::temp_dir().join(format!("sentinel-fingerprint-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+
+    // rustls offers TLS 1.3 and SNI; no ALPN is configured
+    let response = fetch(&cert, &dir, Vec::new()).await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    let ja4 = response.rsplit("\r\n\r\n").next().unwrap().to_string();
+    assert!(ja4.starts_with("t13d"), "{}", ja4);
+    assert_eq!(&ja4[8..10], "00", "{}", ja4);
+
+    // The same client is turned away once its fingerprint is denied
+    let response = fetch(&cert, &dir, vec![ja4]).await;
+    assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
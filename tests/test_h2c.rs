@@ -0,0 +1,150 @@
+//! Tests for cleartext HTTP/2 backends
+
+use bytes::Bytes;
+use sentinel::config::{BackendConfig, validate_backends};
+use sentinel::http::handler::Handler;
+use sentinel::http::request::{Method, RequestBuilder};
+use sentinel::http::writer::ResponseWriter;
+use sentinel::proxy::{BackendPool, ProxyHandler};
+use sentinel::testing::{TEST_CONNECT_TIMEOUT, TEST_REQUEST_TIMEOUT, send_request};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::net::TcpListener;
+
+/// Start an h2c server answering each request with its method, path, and
+/// body, counting the connections it accepts
+async fn h2c_server() -> (SocketAddr, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let connections = Arc::new(AtomicUsize::new(0));
+
+    let accepted = connections.clone();
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            accepted.fetch_add(1, Ordering::SeqCst);
+            tokio::spawn(async move {
+                let mut conn = h2::server::handshake(socket).await.unwrap();
+                while let Some(Ok((request, mut respond))) = conn.accept().await {
+                    tokio::spawn(async move {
+                        let method = request.method().to_string();
+                        let path = request.uri().path().to_string();
+                        let mut body = request.into_body();
+                        let mut received = Vec::new();
+                        while let Some(Ok(chunk)) = body.data().await {
+                            let _ = body.flow_control().release_capacity(chunk.len());
+                            received.extend_from_slice(&chunk);
+                        }
+
+                        let text =
+                            format!("{} {} {}", method, path, String::from_utf8_lossy(&received));
+                        let response = http::Response::builder()
+                            .header("content-type", "text/plain")
+                            .header("x-backend", "h2")
+                            .header("set-cookie", "session=abc; Path=/")
+                            .header("set-cookie", "theme=dark")
+                            .body(())
+                            .unwrap();
+                        let mut send = respond.send_response(response, false).unwrap();
+                        send.send_data(Bytes::from(text), true).unwrap();
+                    });
+                }
+            });
+        }
+    });
+
+    (addr, connections)
+}
+
+fn h2c_handler(addr: SocketAddr) -> Arc<dyn Handler> {
+    let pool = BackendPool::new(vec![BackendConfig {
+        url: format!("http://{}", addr),
+        h2c: true,
+        ..Default::default()
+    }]);
+    Arc::new(ProxyHandler::new(
+        pool,
+        TEST_CONNECT_TIMEOUT,
+        TEST_REQUEST_TIMEOUT,
+    ))
+}
+
+#[tokio::test]
+async fn test_h2c_multiplexes_requests_over_one_connection() {
+    let (addr, connections) = h2c_server().await;
+    let handler = h2c_handler(addr);
+
+    let requests = (0..8).map(|i| {
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            let raw = format!(
+                "GET /item/{} HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+                i
+            );
+            send_request(handler, raw.as_bytes()).await
+        })
+    });
+    for (i, request) in requests.collect::<Vec<_>>().into_iter().enumerate() {
+        let response = request.await.unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.text(), format!("GET /item/{} ", i));
+        assert_eq!(
+            response.headers.get("X-Backend").map(String::as_str),
+            Some("h2")
+        );
+    }
+
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_h2c_forwards_request_body() {
+    let (addr, _) = h2c_server().await;
+    let handler = h2c_handler(addr);
+
+    let response = send_request(
+        handler,
+        b"POST /submit HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello",
+    )
+    .await;
+
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "POST /submit hello");
+}
+
+#[tokio::test]
+async fn test_h2c_passes_on_every_cookie() {
+    let (addr, _) = h2c_server().await;
+    let handler = h2c_handler(addr);
+
+    let request = RequestBuilder::new()
+        .method(Method::GET)
+        .path("/")
+        .version("HTTP/1.1")
+        .build()
+        .unwrap();
+    let response = handler.handle(request).await;
+
+    assert_eq!(response.status.as_u16(), 200);
+    let written = String::from_utf8_lossy(ResponseWriter::new(&response).serialize()).to_string();
+    assert!(
+        written.contains("Set-Cookie: session=abc; Path=/\r\n"),
+        "{}",
+        written
+    );
+    assert!(
+        written.contains("Set-Cookie: theme=dark\r\n"),
+        "{}",
+        written
+    );
+}
+
+#[test]
+fn test_h2c_requires_http_url() {
+    let backend = BackendConfig {
+        url: "https://127.0.0.1:8443".to_string(),
+        h2c: true,
+        ..Default::default()
+    };
+    assert!(validate_backends(&[backend]).is_err());
+}
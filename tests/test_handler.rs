@@ -0,0 +1,188 @@
+//! Tests for custom handlers and routing
+
+use sentinel::http::handler::{Handler, handle_isolated, handler_fn};
+use sentinel::http::request::{Method, Request, RequestBuilder};
+use sentinel::http::response::{Response, StatusCode};
+use sentinel::http::router::{Predicate, Router, ValueMatch};
+use sentinel::metrics::{Metrics, PrometheusRecorder};
+use sentinel::testing::send_request;
+use std::sync::Arc;
+
+fn get(path: &str) -> Request {
+    RequestBuilder::new()
+        .method(Method::GET)
+        .path(path)
+        .build()
+        .unwrap()
+}
+
+fn text(body: &'static str) -> impl Handler {
+    handler_fn(move |_req| async move { Response::ok(body.as_bytes().to_vec()) })
+}
+
+#[tokio::test]
+async fn test_handler_fn_receives_request() {
+    let echo = handler_fn(|req: Request| async move { Response::ok(req.path.into_bytes()) });
+
+    let response = echo.handle(get("/echo")).await;
+
+    assert_eq!(response.status, StatusCode::Ok);
+    assert_eq!(response.body, b"/echo".to_vec());
+}
+
+#[tokio::test]
+async fn test_router_exact_route() {
+    let router = Router::new().route("/internal/feature-flags", text("flags"));
+
+    let response = router.handle(get("/internal/feature-flags")).await;
+    assert_eq!(response.body, b"flags".to_vec());
+
+    // Exact routes do not match sub-paths
+    let response = router.handle(get("/internal/feature-flags/x")).await;
+    assert_eq!(response.status, StatusCode::NotFound);
+}
+
+#[tokio::test]
+async fn test_router_exact_route_ignores_query_string() {
+    let router = Router::new().route("/status", text("ok"));
+
+    let response = router.handle(get("/status?verbose=1")).await;
+    assert_eq!(response.body, b"ok".to_vec());
+}
+
+#[tokio::test]
+async fn test_router_longest_prefix_wins() {
+    let router = Router::new()
+        .route_prefix("/api/", text("api"))
+        .route_prefix("/api/v2/", text("api-v2"));
+
+    assert_eq!(router.handle(get("/api/users")).await.body, b"api".to_vec());
+    assert_eq!(
+        router.handle(get("/api/v2/users")).await.body,
+        b"api-v2".to_vec()
+    );
+}
+
+#[tokio::test]
+async fn test_router_exact_beats_prefix() {
+    let router = Router::new()
+        .route_prefix("/internal/", text("prefix"))
+        .route("/internal/health", text("exact"));
+
+    assert_eq!(
+        router.handle(get("/internal/health")).await.body,
+        b"exact".to_vec()
+    );
+}
+
+#[tokio::test]
+async fn test_router_fallback() {
+    let router = Router::new()
+        .route("/ping", text("pong"))
+        .fallback(text("fallback"));
+
+    assert!(router.has_fallback());
+    assert_eq!(
+        router.handle(get("/anything")).await.body,
+        b"fallback".to_vec()
+    );
+}
+
+#[tokio::test]
+async fn test_router_without_fallback_returns_404() {
+    let router = Router::new().route("/ping", text("pong"));
+
+    assert!(!router.has_fallback());
+    let response = router.handle(get("/missing")).await;
+    assert_eq!(response.status, StatusCode::NotFound);
+}
+
+#[tokio::test]
+async fn test_router_predicates_pick_among_routes_for_a_prefix() {
+    let beta = Predicate::Header("x-beta".to_string(), ValueMatch::Exact("true".to_string()));
+    let writes = Predicate::Method(vec![Method::POST, Method::PUT]);
+    let router = Router::new()
+        .route_prefix("/api/", text("stable"))
+        .route_prefix_when("/api/", beta, text("beta"))
+        .route_prefix_when("/api/v2/", writes, text("writes"));
+
+    let mut request = get("/api/users");
+    assert_eq!(
+        router.handle(request.clone()).await.body,
+        b"stable".to_vec()
+    );
+    request
+        .headers
+        .insert("X-Beta".to_string(), "true".to_string());
+    assert_eq!(router.handle(request.clone()).await.body, b"beta".to_vec());
+    request
+        .headers
+        .insert("X-Beta".to_string(), "false".to_string());
+    assert_eq!(router.handle(request).await.body, b"stable".to_vec());
+
+    // A route whose predicate fails leaves the request to shorter prefixes
+    assert_eq!(
+        router.handle(get("/api/v2/x")).await.body,
+        b"stable".to_vec()
+    );
+    let mut post = get("/api/v2/x");
+    post.method = Method::POST;
+    assert_eq!(router.handle(post).await.body, b"writes".to_vec());
+}
+
+#[test]
+fn test_predicates_compose() {
+    let mobile = Predicate::Header(
+        "User-Agent".to_string(),
+        ValueMatch::Regex(regex::Regex::new("(?i)mobile").unwrap()),
+    );
+    let tagged = Predicate::Header("X-Tag".to_string(), ValueMatch::Present);
+    let any = Predicate::Any(vec![mobile.clone(), tagged.clone()]);
+    let all = Predicate::All(vec![mobile, tagged]);
+
+    let mut request = get("/");
+    assert!(!any.matches(&request));
+    request
+        .headers
+        .insert("User-Agent".to_string(), "Foo Mobile/1.0".to_string());
+    assert!(any.matches(&request));
+    assert!(!all.matches(&request));
+    request.headers.insert("X-Tag".to_string(), String::new());
+    assert!(all.matches(&request));
+
+    assert!(Predicate::All(Vec::new()).matches(&request));
+    assert!(!Predicate::Any(Vec::new()).matches(&request));
+}
+
+fn panics_on_boom() -> impl Handler {
+    handler_fn(|req: Request| async move {
+        if req.path == "/boom" {
+            panic!("handler bug");
+        }
+        Response::ok(b"fine".to_vec())
+    })
+}
+
+#[tokio::test]
+async fn test_panicking_handler_answers_500() {
+    let recorder = Arc::new(PrometheusRecorder::new());
+    let metrics = Metrics::new(recorder.clone());
+    let panics = panics_on_boom();
+
+    let response = handle_isolated(&panics, get("/boom"), &metrics).await;
+    assert_eq!(response.status, StatusCode::InternalServerError);
+    let response = handle_isolated(&panics, get("/ok"), &metrics).await;
+    assert_eq!(response.body, b"fine".to_vec());
+
+    assert!(recorder.render().contains("sentinel_panics_total 1"));
+}
+
+#[tokio::test]
+async fn test_connection_survives_handler_panic() {
+    let response = send_request(
+        Arc::new(panics_on_boom()),
+        b"GET /boom HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert_eq!(response.status, 500);
+}
@@ -0,0 +1,148 @@
+//! Tests for per-route request and response header rules
+
+use sentinel::config::{HeaderRules, RouteConfig};
+use sentinel::http::handler::{Handler, handler_fn};
+use sentinel::http::request::{Method, RequestBuilder};
+use sentinel::http::response::{Response, StatusCode};
+use sentinel::http::router::Router;
+use sentinel::http::writer::ResponseWriter;
+use sentinel::middleware::{HeaderRewrite, MiddlewareChain};
+use sentinel::testing::{MockAction, MockBackend, MockResponse, proxy_handler, send_request};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+fn rules(yaml: &str) -> HeaderRules {
+    serde_yaml::from_str(yaml).unwrap()
+}
+
+#[test]
+fn test_rules_remove_then_set_then_add() {
+    let rewrite = HeaderRewrite::new("api").with_request(rules(
+        r#"
+remove: [x-internal-debug, X-Tenant]
+set: { X-Tenant: acme, X-Client-Ip: "$client_ip" }
+add: { X-Trace: "route=$route id=$request_id", Accept: "text/html" }
+"#,
+    ));
+    let mut req = RequestBuilder::new()
+        .method(Method::GET)
+        .path("/api/")
+        .header("X-Internal-Debug", "1")
+        .header("x-tenant", "evil")
+        .header("X-Request-Id", "req-7")
+        .header("accept", "application/json")
+        .build()
+        .unwrap();
+    req.context.peer = Some("[::ffff:10.0.0.9]:4000".parse::<SocketAddr>().unwrap());
+    rewrite.apply_request(&mut req);
+
+    assert_eq!(req.header("X-Internal-Debug"), None);
+    assert_eq!(req.header("X-Tenant"), Some("acme"));
+    assert_eq!(req.headers.len(), 5);
+    assert_eq!(req.header("X-Client-Ip"), Some("10.0.0.9"));
+    assert_eq!(req.header("X-Trace"), Some("route=api id=req-7"));
+    assert_eq!(req.header("Accept"), Some("application/json, text/html"));
+}
+
+#[test]
+fn test_rules_are_validated() {
+    assert!(rules("set: { X-Ok: \"$route\" }").validate().is_ok());
+    assert!(rules("remove: [\"Bad Header\"]").validate().is_err());
+    assert!(
+        rules("set: { X-Split: \"a\\r\\nInjected: 1\" }")
+            .validate()
+            .is_err()
+    );
+
+    let route: RouteConfig = serde_yaml::from_str(
+        r#"
+prefix: /api/
+static_files: true
+request_headers:
+  add: { "": "x" }
+"#,
+    )
+    .unwrap();
+    assert!(route.validate().is_err());
+}
+
+#[tokio::test]
+async fn test_route_headers_reach_backend() {
+    let backend = MockBackend::start().await;
+    backend.set_default(MockAction::Respond(
+        MockResponse::new(200).reason("OK").body("ok"),
+    ));
+    let route: RouteConfig = serde_yaml::from_str(
+        r#"
+prefix: /api/
+pool: api
+request_headers:
+  set: { X-Tenant: acme, X-Route: "$route" }
+  remove: [Cookie]
+"#,
+    )
+    .unwrap();
+    let handler = MiddlewareChain::new(proxy_handler(&[&backend])).with(
+        HeaderRewrite::new(route.display_name())
+            .with_request(route.request_headers.clone().unwrap()),
+    );
+    let router: Arc<Router> = Arc::new(Router::new().route_prefix("/api/", handler));
+
+    let response = send_request(
+        router,
+        b"GET /api/x HTTP/1.1\r\nHost: a\r\nCookie: s=1\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert_eq!(response.text(), "ok");
+    let forwarded = &backend.requests()[0];
+    assert_eq!(forwarded.header("X-Tenant"), Some("acme"));
+    assert_eq!(forwarded.header("X-Route"), Some("/api/"));
+    assert_eq!(forwarded.header("Cookie"), None);
+}
+
+#[tokio::test]
+async fn test_response_headers_are_rewritten() {
+    let origin = handler_fn(|_req| async {
+        Response::new(StatusCode::Ok)
+            .with_header("X-Powered-By", "PHP/5.6")
+            .with_header("cache-control", "max-age=60")
+            .with_header("Set-Cookie", "a=1")
+            .body(b"ok".to_vec())
+            .build()
+    });
+    let route: RouteConfig = serde_yaml::from_str(
+        r#"
+prefix: /
+name: site
+static_files: true
+response_headers:
+  remove: [x-powered-by]
+  set: { Cache-Control: "no-store", X-Route: "$route" }
+  add: { Set-Cookie: "b=2", X-Client: "$client_ip" }
+"#,
+    )
+    .unwrap();
+    assert!(route.validate().is_ok());
+    let handler = MiddlewareChain::new(origin).with(
+        HeaderRewrite::new(route.display_name())
+            .with_response(route.response_headers.clone().unwrap()),
+    );
+
+    let mut req = RequestBuilder::new()
+        .method(Method::GET)
+        .path("/")
+        .build()
+        .unwrap();
+    req.context.peer = Some("10.0.0.9:4000".parse::<SocketAddr>().unwrap());
+    let response = handler.handle(req).await;
+    assert_eq!(response.body, b"ok");
+    assert_eq!(response.headers.len(), 5, "{:?}", response.headers);
+    assert_eq!(response.headers["Cache-Control"], "no-store");
+    assert_eq!(response.headers["X-Route"], "site");
+    assert_eq!(response.headers["X-Client"], "10.0.0.9");
+    // Cookies stay separate lines rather than one comma-joined value
+    assert_eq!(response.headers["Set-Cookie"], "a=1\nb=2");
+    let written = String::from_utf8_lossy(ResponseWriter::new(&response).serialize()).into_owned();
+    assert!(written.contains("Set-Cookie: a=1\r\n"), "{}", written);
+    assert!(written.contains("Set-Cookie: b=2\r\n"), "{}", written);
+}
@@ -0,0 +1,195 @@
+//! Tests for active backend health checks
+
+use bytes::Bytes;
+use sentinel::config::{BackendConfig, BackendHealthCheck, HealthCheckConfig, HealthCheckProtocol};
+use sentinel::proxy::{BackendPool, HealthChecker};
+use sentinel::testing::{MockAction, MockBackend, MockResponse, backend_config};
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+
+fn checker(pool: &BackendPool) -> HealthChecker {
+    HealthChecker::new(
+        pool.clone(),
+        HealthCheckConfig {
+            timeout_ms: 500,
+            ..Default::default()
+        },
+    )
+}
+
+#[tokio::test]
+async fn test_http_probe_marks_down_and_recovers() {
+    let backend = MockBackend::start().await;
+    backend.set_default(MockAction::Respond(MockResponse::new(503)));
+
+    let pool = BackendPool::new(vec![backend_config(&backend)]);
+    let checker = checker(&pool);
+
+    for _ in 0..3 {
+        assert_eq!(checker.check_all().await, 0);
+    }
+    assert_eq!(pool.available_count().await, 0);
+
+    backend.set_default(MockAction::Respond(MockResponse::new(200)));
+    assert_eq!(checker.check_all().await, 1);
+    assert_eq!(pool.available_count().await, 1);
+
+    assert_eq!(backend.requests()[0].path, "/health");
+}
+
+#[tokio::test]
+async fn test_http_probe_uses_backend_path() {
+    let backend = MockBackend::start().await;
+    let pool = BackendPool::new(vec![BackendConfig {
+        health_check: Some(BackendHealthCheck {
+            path: Some("/ready".to_string()),
+            ..Default::default()
+        }),
+        ..backend_config(&backend)
+    }]);
+
+    assert_eq!(checker(&pool).check_all().await, 1);
+    assert_eq!(backend.requests()[0].path, "/ready");
+}
+
+#[tokio::test]
+async fn test_unreachable_backend_fails_probe() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+
+    let pool = BackendPool::new(vec![BackendConfig {
+        url: format!("http://{}", addr),
+        ..Default::default()
+    }]);
+    let backend = &pool.get_backends().await[0];
+
+    assert!(checker(&pool).check(backend).await.is_err());
+}
+
+/// Start a gRPC health server answering every check with `status`
+async fn grpc_health_server(status: u8) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut conn = h2::server::handshake(socket).await.unwrap();
+                while let Some(Ok((request, mut respond))) = conn.accept().await {
+                    assert_eq!(request.uri().path(), "/grpc.health.v1.Health/Check");
+
+                    let response = http::Response::builder()
+                        .header("content-type", "application/grpc")
+                        .body(())
+                        .unwrap();
+                    let mut send = respond.send_response(response, false).unwrap();
+                    // HealthCheckResponse { status } framed as a gRPC message
+                    send.send_data(Bytes::from(vec![0, 0, 0, 0, 2, 0x08, status]), false)
+                        .unwrap();
+                    let mut trailers = http::HeaderMap::new();
+                    trailers.insert("grpc-status", "0".parse().unwrap());
+                    send.send_trailers(trailers).unwrap();
+                }
+            });
+        }
+    });
+
+    addr
+}
+
+fn grpc_backend(addr: SocketAddr) -> BackendConfig {
+    BackendConfig {
+        url: format!("http://{}", addr),
+        health_check: Some(BackendHealthCheck {
+            protocol: HealthCheckProtocol::Grpc,
+            service: "helloworld.Greeter".to_string(),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_grpc_probe_serving() {
+    let addr = grpc_health_server(1).await;
+    let pool = BackendPool::new(vec![grpc_backend(addr)]);
+    let backend = &pool.get_backends().await[0];
+
+    checker(&pool).check(backend).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_grpc_probe_not_serving() {
+    let addr = grpc_health_server(2).await;
+    let pool = BackendPool::new(vec![grpc_backend(addr)]);
+    let backend = &pool.get_backends().await[0];
+
+    let err = checker(&pool).check(backend).await.unwrap_err();
+    assert!(err.to_string().contains("not serving"));
+}
+
+#[tokio::test]
+async fn test_tcp_probe_only_connects() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let tcp_backend = BackendConfig {
+        url: format!("http://{}", addr),
+        health_check: Some(BackendHealthCheck {
+            protocol: HealthCheckProtocol::Tcp,
+            timeout_ms: Some(200),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let pool = BackendPool::new(vec![tcp_backend.clone()]);
+    let checker = checker(&pool);
+
+    // Accepted by the kernel without the listener answering anything
+    assert_eq!(checker.check_all().await, 1);
+
+    drop(listener);
+    for _ in 0..3 {
+        assert_eq!(checker.check_all().await, 0);
+    }
+    assert_eq!(pool.available_count().await, 0);
+
+    let listener = TcpListener::bind(addr).await.unwrap();
+    assert_eq!(checker.check_all().await, 1);
+    assert_eq!(pool.available_count().await, 1);
+    drop(listener);
+
+    let config: BackendConfig = serde_yaml::from_str(
+        "url: http://localhost:3000\nhealth_check:\n  protocol: tcp\n  timeout_ms: 250",
+    )
+    .unwrap();
+    let probe = config.health_check.unwrap();
+    assert_eq!(probe.protocol, HealthCheckProtocol::Tcp);
+    assert_eq!(probe.timeout_ms, Some(250));
+}
+
+#[tokio::test]
+async fn test_backend_probe_intervals() {
+    let fast = MockBackend::start().await;
+    let slow = MockBackend::start().await;
+    let pool = BackendPool::new(vec![
+        BackendConfig {
+            health_check: Some(BackendHealthCheck {
+                interval_ms: Some(50),
+                ..Default::default()
+            }),
+            ..backend_config(&fast)
+        },
+        backend_config(&slow),
+    ]);
+    let checker = checker(&pool);
+
+    // Everything is due at first, then only backends whose interval passed
+    assert_eq!(checker.check_due().await, 2);
+    assert_eq!(checker.check_due().await, 0);
+    tokio::time::sleep(std::time::Duration::from_millis(80)).await;
+    assert_eq!(checker.check_due().await, 1);
+
+    assert_eq!(fast.requests().len(), 2);
+    assert_eq!(slow.requests().len(), 1);
+}
@@ -0,0 +1,333 @@
+//! Tests for the hyper-based connection engine
+#![cfg(feature = "hyper-engine")]
+
+use bytes::Bytes;
+use sentinel::config::BackendServerHeader;
+use sentinel::events::Events;
+use sentinel::http::cookie::Cookie;
+use sentinel::http::handler::{Handler, handler_fn};
+use sentinel::http::hyper_engine::HyperConnection;
+use sentinel::http::request::Method;
+use sentinel::http::response::{Response, StatusCode};
+use sentinel::http::writer::ServerIdentity;
+use sentinel::metrics::{Metrics, PrometheusRecorder};
+use sentinel::testing::{MockAction, MockBackend, MockResponse, proxy_handler};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+async fn exchange(handler: Arc<dyn Handler>, metrics: Metrics, raw: &[u8]) -> String {
+    let (mut client, server) = tokio::io::duplex(64 * 1024);
+    let conn = tokio::spawn(
+        HyperConnection::new(server, handler)
+            .with_events(Events::new())
+            .with_metrics(metrics)
+            .run(),
+    );
+
+    client.write_all(raw).await.unwrap();
+    let mut output = Vec::new();
+    client.read_to_end(&mut output).await.unwrap();
+    let _ = conn.await;
+
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+#[tokio::test]
+async fn test_serves_http1_request() {
+    let handler = Arc::new(handler_fn(|req| async move {
+        let host = req.header("Host").unwrap_or("-").to_string();
+        Response::ok(format!("{} {}", req.path, host).into_bytes())
+    }));
+
+    let output = exchange(
+        handler,
+        Metrics::default(),
+        b"GET /hello?x=1 HTTP/1.1\r\nhost: example.com\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+
+    assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(output.ends_with("/hello?x=1 example.com"));
+}
+
+#[tokio::test]
+async fn test_passes_request_body() {
+    let handler = Arc::new(handler_fn(|req| async move { Response::ok(req.body) }));
+
+    let output = exchange(
+        handler,
+        Metrics::default(),
+        b"POST /echo HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello",
+    )
+    .await;
+
+    assert!(output.ends_with("\r\n\r\nhello"));
+}
+
+#[tokio::test]
+async fn test_streams_body() {
+    let handler = Arc::new(handler_fn(|_req| async {
+        let (tx, rx) = tokio::sync::mpsc::channel(2);
+        tokio::spawn(async move {
+            for event in ["data: one\n\n", "data: two\n\n"] {
+                tx.send(Bytes::from_static(event.as_bytes())).await.unwrap();
+            }
+        });
+        let mut response = Response::ok(Vec::new());
+        response.headers.remove("Content-Length");
+        response.stream = Some(rx);
+        response
+    }));
+
+    let output = exchange(
+        handler,
+        Metrics::default(),
+        b"GET /events HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+
+    assert!(output.contains("transfer-encoding: chunked\r\n"));
+    assert!(output.ends_with("B\r\ndata: one\n\n\r\nB\r\ndata: two\n\n\r\n0\r\n\r\n"));
+}
+
+#[tokio::test]
+async fn test_rejects_http11_request_without_host() {
+    let handler = Arc::new(handler_fn(|_req| async { Response::ok(Vec::new()) }));
+
+    let output = exchange(
+        handler,
+        Metrics::default(),
+        b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+
+    assert!(output.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+}
+
+#[tokio::test]
+async fn test_writes_each_cookie_as_a_header() {
+    let handler = Arc::new(handler_fn(|_req| async {
+        let mut response = Response::ok(Vec::new());
+        response.add_cookie(Cookie::new("a", "1"));
+        response.add_cookie(Cookie::new("b", "2"));
+        response
+    }));
+
+    let output = exchange(
+        handler,
+        Metrics::default(),
+        b"GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+
+    assert!(output.contains("set-cookie: a=1\r\n"));
+    assert!(output.contains("set-cookie: b=2\r\n"));
+}
+
+#[tokio::test]
+async fn test_records_request_metrics() {
+    let recorder = Arc::new(PrometheusRecorder::new());
+    let handler = Arc::new(handler_fn(|_req| async { Response::not_found() }));
+
+    exchange(
+        handler,
+        Metrics::new(recorder.clone()),
+        b"GET /missing HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+
+    assert!(
+        recorder
+            .render()
+            .contains("sentinel_requests_total{method=\"GET\",status=\"404\"} 1")
+    );
+}
+
+#[tokio::test]
+async fn test_abort_drops_connection() {
+    let handler = Arc::new(handler_fn(|_req| async { Response::abort() }));
+
+    let output = exchange(
+        handler,
+        Metrics::default(),
+        b"GET / HTTP/1.1\r\nHost: a\r\n\r\n",
+    )
+    .await;
+
+    assert!(output.is_empty());
+}
+
+#[tokio::test]
+async fn test_connect_tunnel() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let (mut reader, mut writer) = socket.split();
+        let _ = tokio::io::copy(&mut reader, &mut writer).await;
+    });
+
+    let handler = Arc::new(handler_fn(|req| async move {
+        assert_eq!(req.method, Method::CONNECT);
+        let upstream = tokio::net::TcpStream::connect(&req.path).await.unwrap();
+        Response::tunnel(upstream)
+    }));
+
+    let (mut client, server) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(HyperConnection::new(server, handler).run());
+
+    client
+        .write_all(format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n\r\n", target, target).as_bytes())
+        .await
+        .unwrap();
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        head.push(client.read_u8().await.unwrap());
+    }
+    assert!(head.starts_with(b"HTTP/1.1 200 OK\r\n"));
+
+    client.write_all(b"ping").await.unwrap();
+    let mut echoed = [0u8; 4];
+    client.read_exact(&mut echoed).await.unwrap();
+    assert_eq!(&echoed, b"ping");
+}
+
+#[tokio::test]
+async fn test_spools_chunked_body() {
+    use sentinel::config::RequestBodyConfig;
+    use sentinel::http::spool::BodySpool;
+
+    let dir = std::env::temp_dir().join(format!("sentinel-hyper-spool-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let spool = BodySpool::new(&RequestBodyConfig {
+        memory_limit_bytes: 4,
+        max_bytes: 64,
+        spool_dir: Some(dir.clone()),
+    })
+    .unwrap();
+    let handler = Arc::new(handler_fn(|req| async move {
+        // The body is passed on with a length, not chunked framing
+        assert_eq!(req.header("Transfer-Encoding"), None);
+        assert_eq!(req.header("Content-Length"), Some("11"));
+        let spooled = req.spooled.as_ref().expect("body spooled");
+        Response::ok(std::fs::read(spooled.path()).unwrap())
+    }));
+
+    let (mut client, server) = tokio::io::duplex(64 * 1024);
+    let conn = tokio::spawn(
+        HyperConnection::new(server, handler)
+            .with_body_spool(Arc::new(spool))
+            .run(),
+    );
+    client
+        .write_all(
+            b"POST /upload HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n\
+              6\r\nhello \r\n5\r\nworld\r\n0\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let mut output = Vec::new();
+    client.read_to_end(&mut output).await.unwrap();
+    let _ = conn.await;
+
+    assert!(String::from_utf8_lossy(&output).ends_with("\r\n\r\nhello world"));
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_proxies_h2c_requests_as_http1() {
+    let backend = MockBackend::start().await;
+    backend.set_default(MockAction::Respond(MockResponse::new(200).body("proxied")));
+    let handler: Arc<dyn Handler> = Arc::new(proxy_handler(&[&backend]));
+
+    let (client, server) = tokio::io::duplex(64 * 1024);
+    let conn = tokio::spawn(HyperConnection::new(server, handler).run());
+    let (mut h2, connection) = h2::client::handshake(client).await.unwrap();
+    tokio::spawn(connection);
+
+    // No content-length: the body's length is only known from its frames
+    let request = http::Request::builder()
+        .method("POST")
+        .uri("http://a/submit")
+        .body(())
+        .unwrap();
+    let (response, mut body) = h2.send_request(request, false).unwrap();
+    body.send_data(Bytes::from_static(b"hello"), true).unwrap();
+    let response = response.await.unwrap();
+    assert_eq!(response.status(), 200);
+    let mut received = response.into_body();
+    let mut text = Vec::new();
+    while let Some(data) = received.data().await {
+        text.extend_from_slice(&data.unwrap());
+    }
+    assert_eq!(text, b"proxied");
+
+    let requests = backend.requests();
+    assert_eq!(requests[0].version, "HTTP/1.1");
+    assert_eq!(requests[0].header("Content-Length"), Some("5"));
+    assert_eq!(requests[0].body, b"hello");
+    drop(h2);
+    conn.abort();
+}
+
+#[tokio::test]
+async fn test_closes_after_max_requests() {
+    let handler = Arc::new(handler_fn(|req| async move {
+        Response::ok(req.path.into_bytes())
+    }));
+
+    let (mut client, server) = tokio::io::duplex(64 * 1024);
+    let conn = tokio::spawn(
+        HyperConnection::new(server, handler)
+            .with_max_requests(2)
+            .run(),
+    );
+    client
+        .write_all(
+            b"GET /a HTTP/1.1\r\nHost: a\r\n\r\nGET /b HTTP/1.1\r\nHost: a\r\n\r\nGET /c HTTP/1.1\r\nHost: a\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let mut output = Vec::new();
+    client.read_to_end(&mut output).await.unwrap();
+    let _ = conn.await;
+
+    let output = String::from_utf8_lossy(&output).to_lowercase();
+    assert_eq!(output.matches("http/1.1 200").count(), 2);
+    assert!(output.contains("connection: close"));
+    assert!(output.ends_with("/b"));
+}
+
+#[tokio::test]
+async fn test_sets_server_header_and_scrubs_backend_headers() {
+    let handler = Arc::new(handler_fn(|_req| async move {
+        Response::new(StatusCode::Ok)
+            .header("Server", "nginx")
+            .header("X-Powered-By", "Express")
+            .build()
+    }));
+    let identity = ServerIdentity::default()
+        .with_server("sentinel/1.0")
+        .with_backend(BackendServerHeader::Replace)
+        .with_scrubbed("X-Powered-By");
+
+    let (mut client, server) = tokio::io::duplex(64 * 1024);
+    let conn = tokio::spawn(
+        HyperConnection::new(server, handler)
+            .with_server_identity(Arc::new(identity))
+            .run(),
+    );
+    client
+        .write_all(b"GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut output = Vec::new();
+    client.read_to_end(&mut output).await.unwrap();
+    let _ = conn.await;
+
+    let output = String::from_utf8_lossy(&output).to_lowercase();
+    assert!(output.contains("server: sentinel/1.0\r\n"));
+    assert!(!output.contains("nginx"));
+    assert!(!output.contains("x-powered-by"));
+}
@@ -0,0 +1,248 @@
+//! Tests for Idempotency-Key request deduplication
+
+use bytes::Bytes;
+use sentinel::config::{IdempotencyConfig, RequestBodyConfig};
+use sentinel::http::handler::{Handler, handler_fn};
+use sentinel::http::request::{Method, Request, RequestBuilder};
+use sentinel::http::response::{Response, StatusCode};
+use sentinel::http::spool::BodySpool;
+use sentinel::middleware::IdempotencyHandler;
+use sentinel::middleware::idempotency::REPLAYED_HEADER;
+use sentinel::testing::send_request;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+fn config() -> IdempotencyConfig {
+    IdempotencyConfig {
+        enabled: true,
+        routes: vec!["/payments".to_string()],
+        header: "Idempotency-Key".to_string(),
+        ttl_secs: 60,
+        max_entries: 100,
+    }
+}
+
+/// Counts calls and answers with the call number after `delay`
+fn counting(calls: Arc<AtomicUsize>, status: StatusCode, delay: Duration) -> IdempotencyHandler {
+    let inner = handler_fn(move |_req| {
+        let calls = calls.clone();
+        async move {
+            let n = calls.fetch_add(1, Ordering::SeqCst) + 1;
+            tokio::time::sleep(delay).await;
+            Response::new(status)
+                .with_body(format!("charge {}", n).into_bytes())
+                .build()
+        }
+    });
+    IdempotencyHandler::new(inner, config())
+}
+
+fn post(path: &str, key: Option<&str>, body: &str) -> Vec<u8> {
+    let key = key
+        .map(|k| format!("idempotency-key: {}\r\n", k))
+        .unwrap_or_default();
+    format!(
+        "POST {} HTTP/1.1\r\nHost: a\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        key,
+        body.len(),
+        body
+    )
+    .into_bytes()
+}
+
+#[tokio::test]
+async fn test_duplicate_key_replays_response() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let handler = Arc::new(counting(calls.clone(), StatusCode::Created, Duration::ZERO));
+
+    let first = send_request(handler.clone(), &post("/payments", Some("abc"), "{}")).await;
+    let second = send_request(handler.clone(), &post("/payments", Some("abc"), "{}")).await;
+
+    assert_eq!(first.status, 201);
+    assert_eq!(first.header(REPLAYED_HEADER), None);
+    assert_eq!(second.status, 201);
+    assert_eq!(second.text(), "charge 1");
+    assert_eq!(second.header(REPLAYED_HEADER), Some("true"));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    // Other keys, missing keys, and other routes are not deduplicated
+    send_request(handler.clone(), &post("/payments", Some("def"), "{}")).await;
+    send_request(handler.clone(), &post("/payments", None, "{}")).await;
+    send_request(handler.clone(), &post("/refunds", Some("abc"), "{}")).await;
+    assert_eq!(calls.load(Ordering::SeqCst), 4);
+}
+
+#[tokio::test]
+async fn test_concurrent_duplicates_share_one_backend_call() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let handler = Arc::new(counting(
+        calls.clone(),
+        StatusCode::Ok,
+        Duration::from_millis(200),
+    ));
+
+    let tasks: Vec<_> = (0..3)
+        .map(|_| {
+            let handler = handler.clone();
+            tokio::spawn(async move {
+                send_request(handler, &post("/payments", Some("abc"), "{}")).await
+            })
+        })
+        .collect();
+    let mut responses = Vec::new();
+    for task in tasks {
+        responses.push(task.await.unwrap());
+    }
+
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    assert!(responses.iter().all(|r| r.text() == "charge 1"));
+    assert_eq!(
+        responses
+            .iter()
+            .filter(|r| r.header(REPLAYED_HEADER).is_some())
+            .count(),
+        2
+    );
+}
+
+#[tokio::test]
+async fn test_key_reused_for_different_request_is_rejected() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let handler = Arc::new(counting(calls.clone(), StatusCode::Ok, Duration::ZERO));
+
+    send_request(
+        handler.clone(),
+        &post("/payments", Some("abc"), "{\"amount\":1}"),
+    )
+    .await;
+    let response = send_request(
+        handler.clone(),
+        &post("/payments", Some("abc"), "{\"amount\":2}"),
+    )
+    .await;
+
+    assert_eq!(response.status, 422);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_server_errors_are_not_replayed() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let handler = Arc::new(counting(
+        calls.clone(),
+        StatusCode::BadGateway,
+        Duration::ZERO,
+    ));
+
+    send_request(handler.clone(), &post("/payments", Some("abc"), "{}")).await;
+    let retry = send_request(handler.clone(), &post("/payments", Some("abc"), "{}")).await;
+
+    assert_eq!(retry.text(), "charge 2");
+    assert_eq!(retry.header(REPLAYED_HEADER), None);
+}
+
+#[tokio::test]
+async fn test_keys_are_scoped_to_client_and_endpoint() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let handler = Arc::new(counting(calls.clone(), StatusCode::Ok, Duration::ZERO));
+    let post_as = |user: &str, path: &str| {
+        format!(
+            "POST {} HTTP/1.1\r\nHost: a\r\nAuthorization: Bearer {}\r\nIdempotency-Key: abc\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{{}}",
+            path, user
+        )
+        .into_bytes()
+    };
+
+    let alice = send_request(handler.clone(), &post_as("alice", "/payments")).await;
+    let mallory = send_request(handler.clone(), &post_as("mallory", "/payments")).await;
+    let other_path = send_request(handler.clone(), &post_as("alice", "/payments/2")).await;
+    let retry = send_request(handler.clone(), &post_as("alice", "/payments")).await;
+
+    assert_eq!(alice.text(), "charge 1");
+    assert_eq!(mallory.text(), "charge 2");
+    assert_eq!(mallory.header(REPLAYED_HEADER), None);
+    assert_eq!(other_path.text(), "charge 3");
+    assert_eq!(retry.text(), "charge 1");
+    assert_eq!(retry.header(REPLAYED_HEADER), Some("true"));
+}
+
+#[tokio::test]
+async fn test_spooled_bodies_are_fingerprinted() {
+    let dir = std::env::temp_dir().join(format!("sentinel-idempotency-{}", std::process::id()));
+    let spool = BodySpool::new(&RequestBodyConfig {
+        spool_dir: Some(dir.clone()),
+        ..RequestBodyConfig::default()
+    })
+    .unwrap();
+    let upload = async |body: &[u8]| -> Request {
+        let mut file = spool.create().await.unwrap();
+        file.write(body).await.unwrap();
+        let mut req = RequestBuilder::new()
+            .method(Method::POST)
+            .path("/payments")
+            .header("Idempotency-Key", "abc")
+            .build()
+            .unwrap();
+        req.spooled = Some(Arc::new(file.finish().await.unwrap()));
+        req
+    };
+    let calls = Arc::new(AtomicUsize::new(0));
+    let handler = counting(calls.clone(), StatusCode::Ok, Duration::ZERO);
+
+    handler.handle(upload(b"first upload").await).await;
+    let same = handler.handle(upload(b"first upload").await).await;
+    let different = handler.handle(upload(b"second upload").await).await;
+
+    assert_eq!(
+        same.headers.get(REPLAYED_HEADER).map(String::as_str),
+        Some("true")
+    );
+    assert_eq!(different.status.as_u16(), 422);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn test_streamed_responses_are_not_kept() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    let handler = IdempotencyHandler::new(
+        handler_fn(move |req: Request| {
+            let n = counter.fetch_add(1, Ordering::SeqCst);
+            async move {
+                let mut response = Response::new(StatusCode::Accepted).reason("Queued").build();
+                if req.path == "/payments/stream" {
+                    let (tx, rx) = tokio::sync::mpsc::channel(1);
+                    tx.send(Bytes::from(format!("event {}", n))).await.unwrap();
+                    response.stream = Some(rx);
+                }
+                response
+            }
+        }),
+        config(),
+    );
+    let post = |path: &str| {
+        RequestBuilder::new()
+            .method(Method::POST)
+            .path(path)
+            .header("Idempotency-Key", "abc")
+            .build()
+            .unwrap()
+    };
+
+    let first = handler.handle(post("/payments/stream")).await;
+    let second = handler.handle(post("/payments/stream")).await;
+    assert!(first.stream.is_some());
+    assert!(second.stream.is_some());
+    assert!(!second.headers.contains_key(REPLAYED_HEADER));
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+    // Buffered responses are replayed with their reason phrase
+    handler.handle(post("/payments/plain")).await;
+    let replayed = handler.handle(post("/payments/plain")).await;
+    assert_eq!(replayed.reason.as_deref(), Some("Queued"));
+    assert!(replayed.headers.contains_key(REPLAYED_HEADER));
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+}
@@ -0,0 +1,195 @@
+//! Tests for on-the-fly image transformation
+
+use sentinel::config::ImageConfig;
+use sentinel::http::images::{ImageFormat, ImageTransform};
+
+fn image_config() -> ImageConfig {
+    ImageConfig {
+        max_dimension: 2000,
+        widths: Vec::new(),
+        quality: 80,
+        max_source_bytes: 1024 * 1024,
+        cache_entries: 4,
+    }
+}
+
+#[test]
+fn test_transform_query_parsing() {
+    let config = image_config();
+    let parse =
+        |query: &str, accept: Option<&str>| ImageTransform::from_query(query, accept, &config);
+
+    assert_eq!(parse("", None), Ok(None));
+    // Unrelated parameters, such as cache busters, are not transformations
+    assert_eq!(parse("v=3", None), Ok(None));
+
+    let transform = parse("w=640&h=480&format=webp&v=3", None).unwrap().unwrap();
+    assert_eq!(transform.width, Some(640));
+    assert_eq!(transform.height, Some(480));
+    assert_eq!(transform.format, Some(ImageFormat::Webp));
+    assert!(!transform.negotiated);
+    assert_eq!(
+        parse("format=JPG", None).unwrap().unwrap().format,
+        Some(ImageFormat::Jpeg)
+    );
+
+    for bad in ["w=0", "w=2001", "h=abc", "format=bmp"] {
+        assert!(parse(bad, None).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn test_auto_format_negotiation() {
+    let config = image_config();
+    let auto = |accept: Option<&str>| {
+        ImageTransform::from_query("format=auto", accept, &config)
+            .unwrap()
+            .unwrap()
+    };
+
+    let transform = auto(Some("image/avif,image/webp,*/*"));
+    assert_eq!(transform.format, Some(ImageFormat::Avif));
+    assert!(transform.negotiated);
+    assert_eq!(auto(Some("image/webp,*/*")).format, Some(ImageFormat::Webp));
+    // Clients that accept neither keep the source format
+    assert_eq!(auto(Some("*/*")).format, None);
+    assert_eq!(auto(None).format, None);
+    assert_eq!(ImageFormat::Avif.content_type(), "image/avif");
+}
+
+#[test]
+fn test_allowed_widths() {
+    let mut config = image_config();
+    config.widths = vec![320, 640];
+    assert!(ImageTransform::from_query("w=640", None, &config).is_ok());
+    assert!(ImageTransform::from_query("w=641", None, &config).is_err());
+    // Heights are bounded by max_dimension only
+    assert!(ImageTransform::from_query("h=777", None, &config).is_ok());
+}
+
+#[test]
+fn test_image_config_validation() {
+    let mut config = image_config();
+    assert_eq!(config.validate().is_ok(), cfg!(feature = "images"));
+    if cfg!(feature = "images") {
+        config.quality = 0;
+        assert!(config.validate().is_err());
+        config.quality = 80;
+        config.widths = vec![320, 4000];
+        assert!(config.validate().is_err());
+    }
+}
+
+#[cfg(feature = "images")]
+mod transform {
+    use super::image_config;
+    use sentinel::config::{ErrorPages, StaticFilesConfig};
+    use sentinel::http::handler::Handler;
+    use sentinel::http::request::{Method, RequestBuilder};
+    use sentinel::http::response::Response;
+    use sentinel::http::static_files::StaticFileHandler;
+    use std::path::PathBuf;
+
+    /// A static root holding a 400x200 PNG
+    fn setup(name: &str) -> (PathBuf, StaticFileHandler) {
+        let root =
+            std::env::temp_dir().join(format!("sentinel-images-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&root).unwrap();
+        image::RgbImage::from_fn(400, 200, |x, y| image::Rgb([x as u8, y as u8, 128]))
+            .save(root.join("photo.png"))
+            .unwrap();
+        std::fs::write(root.join("broken.png"), b"not an image").unwrap();
+        let handler = StaticFileHandler::new(StaticFilesConfig {
+            root: root.clone(),
+            index: "index.html".to_string(),
+            error_pages: ErrorPages::default(),
+            directory_listing: false,
+            images: Some(image_config()),
+        });
+        (root, handler)
+    }
+
+    async fn get(handler: &StaticFileHandler, path: &str, accept: Option<&str>) -> Response {
+        let mut builder = RequestBuilder::new().method(Method::GET).path(path);
+        if let Some(accept) = accept {
+            builder = builder.header("Accept", accept);
+        }
+        handler.handle(builder.build().unwrap()).await
+    }
+
+    fn content_type(response: &Response) -> Option<&str> {
+        response.headers.get("Content-Type").map(String::as_str)
+    }
+
+    #[tokio::test]
+    async fn test_resizes_and_converts() {
+        let (root, handler) = setup("resize");
+        let original = std::fs::read(root.join("photo.png")).unwrap();
+
+        let response = get(&handler, "/photo.png", None).await;
+        assert_eq!(response.body, original);
+        let response = get(&handler, "/photo.png?v=2", None).await;
+        assert_eq!(response.body, original);
+
+        let response = get(&handler, "/photo.png?w=100", None).await;
+        assert_eq!(response.status.as_u16(), 200);
+        assert_eq!(content_type(&response), Some("image/png"));
+        let resized = image::load_from_memory(&response.body).unwrap();
+        assert_eq!((resized.width(), resized.height()), (100, 50));
+
+        let response = get(&handler, "/photo.png?w=100&format=jpeg", None).await;
+        assert_eq!(content_type(&response), Some("image/jpeg"));
+        let converted = image::load_from_memory(&response.body).unwrap();
+        assert_eq!((converted.width(), converted.height()), (100, 50));
+
+        // Images are never enlarged
+        let response = get(&handler, "/photo.png?w=1000&format=webp", None).await;
+        assert_eq!(content_type(&response), Some("image/webp"));
+        let converted = image::load_from_memory(&response.body).unwrap();
+        assert_eq!((converted.width(), converted.height()), (400, 200));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_negotiates_and_caches() {
+        let (root, handler) = setup("negotiate");
+
+        let response = get(&handler, "/photo.png?h=20&format=auto", Some("image/avif")).await;
+        assert_eq!(content_type(&response), Some("image/avif"));
+        assert_eq!(
+            response.headers.get("Vary").map(String::as_str),
+            Some("Accept")
+        );
+        let response = get(&handler, "/photo.png?h=20&format=auto", Some("image/webp")).await;
+        assert_eq!(content_type(&response), Some("image/webp"));
+        assert_eq!(handler.images().unwrap().cached(), 2);
+
+        let first = get(&handler, "/photo.png?w=50", None).await;
+        let second = get(&handler, "/photo.png?w=50", None).await;
+        assert_eq!(first.body, second.body);
+        assert_eq!(handler.images().unwrap().cached(), 3);
+
+        // The cache is bounded by cache_entries
+        for width in [60, 70, 80] {
+            get(&handler, &format!("/photo.png?w={}", width), None).await;
+        }
+        assert_eq!(handler.images().unwrap().cached(), 4);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rejects_bad_requests() {
+        let (root, handler) = setup("errors");
+
+        let response = get(&handler, "/photo.png?w=5000", None).await;
+        assert_eq!(response.status.as_u16(), 400);
+        let response = get(&handler, "/broken.png?w=10", None).await;
+        assert_eq!(response.status.as_u16(), 422);
+        let response = get(&handler, "/missing.png?w=10", None).await;
+        assert_eq!(response.status.as_u16(), 404);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,154 @@
+//! Tests for scheduled maintenance windows
+
+use sentinel::config::{BackendConfig, Config, MaintenanceWindow, ProxyConfig};
+use sentinel::proxy::BackendPool;
+use sentinel::proxy::maintenance::{MaintenanceScheduler, Schedule};
+use sentinel::server::Server;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 2024-03-13 (a Wednesday) at `hour:minute` UTC
+fn wednesday(hour: u64, minute: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(1_710_288_000 + hour * 3600 + minute * 60)
+}
+
+fn backend(name: &str, url: &str, weight: u32) -> BackendConfig {
+    BackendConfig {
+        url: url.to_string(),
+        name: Some(name.to_string()),
+        weight,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_schedule_matches_cron_fields() {
+    let nightly = Schedule::parse("0 3 * * *").unwrap();
+    assert!(nightly.matches(wednesday(3, 0)));
+    assert!(!nightly.matches(wednesday(3, 1)));
+    assert!(!nightly.matches(wednesday(4, 0)));
+
+    let weekdays = Schedule::parse("*/15 22-23 * * 1-5").unwrap();
+    assert!(weekdays.matches(wednesday(22, 45)));
+    assert!(!weekdays.matches(wednesday(22, 50)));
+    assert!(!weekdays.matches(wednesday(22, 45) + Duration::from_secs(4 * 86_400)));
+
+    // Day-of-month and day-of-week are alternatives when both are set
+    let either = Schedule::parse("0 0 1 * 3").unwrap();
+    assert!(either.matches(wednesday(0, 0)));
+    assert!(either.matches(UNIX_EPOCH + Duration::from_secs(1_711_929_600))); // 2024-04-01
+
+    // Sunday is 0 or 7
+    let sunday = wednesday(12, 0) + Duration::from_secs(4 * 86_400);
+    assert!(Schedule::parse("0 12 * * 7").unwrap().matches(sunday));
+    assert!(Schedule::parse("0 12 * * 0").unwrap().matches(sunday));
+
+    for invalid in [
+        "0 3 * *",
+        "60 * * * *",
+        "0 3 * * 8",
+        "5-1 * * * *",
+        "*/0 * * * *",
+    ] {
+        assert!(Schedule::parse(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn test_window_stays_open_for_its_duration() {
+    let nightly = Schedule::parse("30 2 * * *").unwrap();
+    assert!(!nightly.is_open(30, wednesday(2, 29)));
+    assert!(nightly.is_open(30, wednesday(2, 30)));
+    assert!(nightly.is_open(30, wednesday(2, 59)));
+    assert!(!nightly.is_open(30, wednesday(3, 0)));
+}
+
+#[tokio::test]
+async fn test_scheduler_drains_and_restores_backends() {
+    let pool = BackendPool::new(vec![
+        backend("web-1", "http://127.0.0.1:9001", 3),
+        backend("web-2", "http://127.0.0.1:9002", 1),
+    ]);
+    let windows = vec![MaintenanceWindow {
+        backends: vec!["web-1".to_string()],
+        schedule: "0 3 * * *".to_string(),
+        duration_mins: 30,
+    }];
+    let mut scheduler = MaintenanceScheduler::new(pool.clone(), &windows).unwrap();
+    let weights = || async {
+        pool.get_backends()
+            .await
+            .iter()
+            .map(|b| b.weight)
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(scheduler.apply(wednesday(2, 59)).await, 0);
+    assert_eq!(weights().await, vec![3, 1]);
+
+    assert_eq!(scheduler.apply(wednesday(3, 0)).await, 1);
+    assert_eq!(weights().await, vec![0, 1]);
+
+    // Restored weight is the one from before the window
+    assert_eq!(scheduler.apply(wednesday(3, 15)).await, 1);
+    assert_eq!(scheduler.apply(wednesday(3, 30)).await, 0);
+    assert_eq!(weights().await, vec![3, 1]);
+}
+
+#[test]
+fn test_config_validates_maintenance_windows() {
+    let parse = |window: &str| {
+        serde_yaml::from_str::<ProxyConfig>(&format!(
+            "backends: [{{ url: \"http://10.0.0.1:8080\" }}]\nmaintenance:\n  - {}\n",
+            window
+        ))
+        .unwrap()
+    };
+
+    assert!(
+        parse("{ backends: [web-1], schedule: \"0 3 * * *\", duration_mins: 30 }")
+            .validate()
+            .is_ok()
+    );
+    assert!(
+        parse("{ backends: [web-1], schedule: \"0 25 * * *\", duration_mins: 30 }")
+            .validate()
+            .is_err()
+    );
+    assert!(
+        parse("{ backends: [web-1], schedule: \"0 3 * * *\", duration_mins: 0 }")
+            .validate()
+            .is_err()
+    );
+    assert!(
+        parse("{ backends: [], schedule: \"0 3 * * *\", duration_mins: 30 }")
+            .validate()
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_bad_maintenance_window_fails_startup() {
+    let cfg: Config = serde_yaml::from_str(
+        r#"
+server:
+  listen_addr: "127.0.0.1:0"
+static_files:
+  root: "public"
+  index: "index.html"
+proxy:
+  backends:
+    - { url: "http://10.0.0.1:8080", name: web-1 }
+  maintenance:
+    - { backends: [web-1], schedule: "every night", duration_mins: 30 }
+"#,
+    )
+    .unwrap();
+    assert!(cfg.proxy.as_ref().unwrap().validate().is_err());
+
+    let error = Server::new(cfg).run().await.unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("Invalid maintenance schedule 'every night'"),
+        "{:#}",
+        error
+    );
+}
@@ -0,0 +1,42 @@
+//! Tests for the per-connection request limit
+
+use sentinel::http::connection::Connection;
+use sentinel::http::handler::handler_fn;
+use sentinel::http::response::{Response, StatusCode};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+#[tokio::test]
+async fn test_closes_after_max_requests() {
+    let handler = Arc::new(handler_fn(|req| async move {
+        Response::new(StatusCode::Ok)
+            .header("Connection", "keep-alive")
+            .body(req.path.into_bytes())
+            .build()
+    }));
+
+    let (mut client, server) = tokio::io::duplex(4096);
+    let conn = tokio::spawn(async move {
+        Connection::with_handler(server, handler)
+            .with_max_requests(2)
+            .run()
+            .await
+    });
+
+    client
+        .write_all(b"GET /a HTTP/1.1\r\nHost: a\r\n\r\nGET /b HTTP/1.1\r\nHost: a\r\n\r\nGET /c HTTP/1.1\r\nHost: a\r\n\r\n")
+        .await
+        .unwrap();
+    let mut output = Vec::new();
+    client.read_to_end(&mut output).await.unwrap();
+    conn.await.unwrap().unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    let responses: Vec<&str> = output.split("HTTP/1.1 ").skip(1).collect();
+    assert_eq!(responses.len(), 2);
+    assert!(responses[0].contains("Connection: keep-alive"));
+    assert!(responses[0].ends_with("/a"));
+    assert!(responses[1].contains("Connection: close"));
+    assert!(!responses[1].contains("keep-alive"));
+    assert!(responses[1].ends_with("/b"));
+}
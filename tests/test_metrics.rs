@@ -0,0 +1,291 @@
+//! Tests for metrics recorders
+
+use sentinel::config::{BackendConfig, Config, MetricsConfig, MetricsListener};
+use sentinel::http::handler::{Handler, handler_fn};
+use sentinel::http::request::{Method, RequestBuilder};
+use sentinel::http::response::Response;
+use sentinel::metrics::{
+    Metrics, MetricsEndpoint, MetricsRecorder, NoopRecorder, PrometheusRecorder, RouteMetrics,
+    StatsdRecorder,
+};
+use sentinel::proxy::backend::BackendPool;
+use sentinel::proxy::upstream::ProxyHandler;
+use sentinel::server::Server;
+use sentinel::testing::{
+    MockAction, MockBackend, MockResponse, TEST_CONNECT_TIMEOUT, TEST_REQUEST_TIMEOUT,
+    backend_config,
+};
+use std::net::UdpSocket;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_util::sync::CancellationToken;
+
+#[test]
+fn test_prometheus_counter_rendering() {
+    let recorder = PrometheusRecorder::new();
+    recorder.increment_counter("requests_total", &[("status", "200"), ("method", "GET")], 1);
+    recorder.increment_counter("requests_total", &[("method", "GET"), ("status", "200")], 2);
+
+    let output = recorder.render();
+
+    assert!(output.contains("# TYPE requests_total counter"));
+    // Label order is normalized, so both increments hit the same series
+    assert!(output.contains("requests_total{method=\"GET\",status=\"200\"} 3"));
+}
+
+#[test]
+fn test_prometheus_gauge_overwrites() {
+    let recorder = PrometheusRecorder::new();
+    recorder.set_gauge("backend_up", &[("backend", "a")], 1.0);
+    recorder.set_gauge("backend_up", &[("backend", "a")], 0.0);
+
+    let output = recorder.render();
+
+    assert!(output.contains("# TYPE backend_up gauge"));
+    assert!(output.contains("backend_up{backend=\"a\"} 0"));
+}
+
+#[test]
+fn test_prometheus_histogram_buckets() {
+    let recorder = PrometheusRecorder::with_buckets(vec![0.1, 1.0]);
+    recorder.record_histogram("latency", &[], 0.05);
+    recorder.record_histogram("latency", &[], 0.5);
+    recorder.record_histogram("latency", &[], 5.0);
+
+    let output = recorder.render();
+
+    assert!(output.contains("latency_bucket{le=\"0.1\"} 1"));
+    assert!(output.contains("latency_bucket{le=\"1\"} 2"));
+    assert!(output.contains("latency_bucket{le=\"+Inf\"} 3"));
+    assert!(output.contains("latency_sum 5.55"));
+    assert!(output.contains("latency_count 3"));
+}
+
+#[test]
+fn test_prometheus_escapes_label_values() {
+    let recorder = PrometheusRecorder::new();
+    recorder.increment_counter("c", &[("path", "a\"b")], 1);
+
+    assert!(recorder.render().contains("c{path=\"a\\\"b\"} 1"));
+}
+
+#[test]
+fn test_statsd_line_format() {
+    let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+    agent
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+
+    let recorder = StatsdRecorder::new(agent.local_addr().unwrap(), "sentinel").unwrap();
+    recorder.increment_counter("requests_total", &[("status", "200")], 1);
+
+    let mut buf = [0u8; 256];
+    let n = agent.recv(&mut buf).unwrap();
+
+    assert_eq!(&buf[..n], b"sentinel.requests_total:1|c|#status:200");
+}
+
+#[test]
+fn test_noop_recorder_accepts_everything() {
+    let metrics = Metrics::new(Arc::new(NoopRecorder));
+    metrics.increment("anything", &[]);
+    metrics.gauge("anything", &[], 1.0);
+    metrics.histogram("anything", &[], 1.0);
+}
+
+#[tokio::test]
+async fn test_backend_pool_records_metrics() {
+    let recorder = Arc::new(PrometheusRecorder::new());
+    let pool = BackendPool::new(vec![BackendConfig {
+        url: "http://localhost:3000".to_string(),
+        name: Some("backend-1".to_string()),
+        ..Default::default()
+    }])
+    .with_metrics(Metrics::new(recorder.clone()));
+
+    pool.select_backend().await.unwrap();
+    for _ in 0..3 {
+        pool.mark_backend_failed("http://localhost:3000").await;
+    }
+
+    let output = recorder.render();
+    assert!(output.contains("sentinel_backend_selections_total{backend=\"backend-1\"} 1"));
+    assert!(output.contains("sentinel_backend_up{backend=\"backend-1\"} 0"));
+}
+
+#[tokio::test]
+async fn test_backend_labels_added_to_metrics() {
+    let recorder = Arc::new(PrometheusRecorder::new());
+    let pool = BackendPool::new(vec![BackendConfig {
+        url: "http://localhost:3000".to_string(),
+        name: Some("backend-1".to_string()),
+        labels: [("zone".to_string(), "eu-1".to_string())].into(),
+        ..Default::default()
+    }])
+    .with_metrics(Metrics::new(recorder.clone()));
+
+    pool.select_backend().await.unwrap();
+
+    assert!(
+        recorder
+            .render()
+            .contains("sentinel_backend_selections_total{backend=\"backend-1\",zone=\"eu-1\"} 1")
+    );
+}
+
+#[tokio::test]
+async fn test_in_flight_gauges_and_extra_recorders() {
+    let first = Arc::new(PrometheusRecorder::new());
+    let second = Arc::new(PrometheusRecorder::new());
+    let metrics = Metrics::new(first.clone()).with_recorder(second.clone());
+
+    let one = metrics.in_flight("jobs_in_flight", &[("queue", "a")]);
+    let two = metrics
+        .clone()
+        .in_flight("jobs_in_flight", &[("queue", "a")]);
+    for recorder in [&first, &second] {
+        assert!(recorder.render().contains("jobs_in_flight{queue=\"a\"} 2"));
+    }
+    drop(one);
+    drop(two);
+    assert!(first.render().contains("jobs_in_flight{queue=\"a\"} 0"));
+    assert!(second.render().contains("jobs_in_flight{queue=\"a\"} 0"));
+}
+
+#[tokio::test]
+async fn test_route_metrics_and_endpoint() {
+    let recorder = Arc::new(PrometheusRecorder::new());
+    let metrics = Metrics::new(recorder.clone());
+    let route = RouteMetrics::new(
+        handler_fn(|_req| async { Response::not_found() }),
+        "api",
+        metrics,
+    );
+    let get = |path: &str| {
+        RequestBuilder::new()
+            .method(Method::GET)
+            .path(path)
+            .build()
+            .unwrap()
+    };
+    route.handle(get("/api/missing")).await;
+
+    let endpoint = MetricsEndpoint::new(recorder.clone());
+    let response = endpoint.handle(get("/metrics")).await;
+    assert_eq!(response.status.as_u16(), 200);
+    assert_eq!(
+        response.headers.get("Content-Type").unwrap(),
+        "text/plain; version=0.0.4; charset=utf-8"
+    );
+    let body = String::from_utf8(response.body).unwrap();
+    assert!(body.contains("sentinel_route_requests_total{route=\"api\",status=\"404\"} 1"));
+    assert!(body.contains("sentinel_route_duration_seconds_count{route=\"api\"} 1"));
+    assert!(body.contains("sentinel_route_requests_in_flight{route=\"api\"} 0"));
+
+    let post = RequestBuilder::new()
+        .method(Method::POST)
+        .path("/metrics")
+        .build()
+        .unwrap();
+    assert_eq!(endpoint.handle(post).await.status.as_u16(), 405);
+}
+
+#[tokio::test]
+async fn test_upstream_metrics_and_initial_backend_state() {
+    let backend = MockBackend::start().await;
+    backend.push(MockAction::Respond(MockResponse::new(503)));
+    let recorder = Arc::new(PrometheusRecorder::new());
+    let metrics = Metrics::new(recorder.clone());
+    let pool = BackendPool::new(vec![BackendConfig {
+        name: Some("web".to_string()),
+        ..backend_config(&backend)
+    }])
+    .with_metrics(metrics.clone());
+    assert!(
+        recorder
+            .render()
+            .contains("sentinel_backend_up{backend=\"web\"} 1")
+    );
+
+    let handler =
+        ProxyHandler::new(pool, TEST_CONNECT_TIMEOUT, TEST_REQUEST_TIMEOUT).with_metrics(metrics);
+    let request = RequestBuilder::new()
+        .method(Method::GET)
+        .path("/")
+        .build()
+        .unwrap();
+    assert_eq!(handler.handle(request).await.status.as_u16(), 503);
+
+    let output = recorder.render();
+    assert!(output.contains("sentinel_upstream_responses_total{backend=\"web\",status=\"503\"} 1"));
+    assert!(output.contains("sentinel_upstream_requests_in_flight{backend=\"web\"} 0"));
+}
+
+#[tokio::test]
+async fn test_server_serves_metrics() {
+    // Find a free port for the server
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let cfg: Config = serde_yaml::from_str(&format!(
+        r#"
+server:
+  listen_addr: "127.0.0.1:{}"
+static_files:
+  root: "public"
+  index: "index.html"
+routes:
+  - prefix: /hello
+    name: hello
+    respond: {{ body: "hi" }}
+metrics:
+  path: /internal/metrics
+  buckets: [0.1, 1]
+"#,
+        port
+    ))
+    .unwrap();
+    let shutdown = CancellationToken::new();
+    tokio::spawn(Server::new(cfg).shutdown(shutdown.clone()).run());
+
+    let fetch = |path: &'static str| async move {
+        for _ in 0..50 {
+            if let Ok(mut stream) = TcpStream::connect(("127.0.0.1", port)).await {
+                let request = format!(
+                    "GET {} HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+                    path
+                );
+                stream.write_all(request.as_bytes()).await.unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).await.unwrap();
+                return response;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("server did not start");
+    };
+
+    assert!(fetch("/hello").await.ends_with("hi"));
+    let scraped = fetch("/internal/metrics").await;
+    assert!(scraped.starts_with("HTTP/1.1 200"), "{}", scraped);
+    assert!(scraped.contains("sentinel_route_requests_total{route=\"hello\",status=\"200\"} 1"));
+    assert!(scraped.contains("sentinel_requests_total{method=\"GET\",status=\"200\"} 1"));
+    assert!(
+        scraped.contains("sentinel_request_duration_seconds_bucket{method=\"GET\",le=\"0.1\"} 1")
+    );
+    // The scrape itself is in flight
+    assert!(scraped.contains("sentinel_requests_in_flight 1"));
+    shutdown.cancel();
+
+    let mut cfg = Config::load();
+    cfg.metrics = Some(MetricsConfig {
+        listener: MetricsListener::Admin,
+        ..Default::default()
+    });
+    let error = Server::new(cfg).run().await.unwrap_err();
+    assert!(error.to_string().contains("admin listener"), "{}", error);
+}
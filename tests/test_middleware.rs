@@ -0,0 +1,164 @@
+//! Tests for the middleware trait and chains
+
+use async_trait::async_trait;
+use sentinel::http::handler::{Handler, handler_fn};
+use sentinel::http::request::{Method, Request, RequestBuilder};
+use sentinel::http::response::{Response, StatusCode};
+use sentinel::middleware::{Middleware, MiddlewareChain};
+use std::sync::{Arc, Mutex};
+
+type Log = Arc<Mutex<Vec<String>>>;
+
+struct Recorder {
+    name: &'static str,
+    log: Log,
+    answer: bool,
+}
+
+#[async_trait]
+impl Middleware for Recorder {
+    async fn before_request(&self, req: &mut Request) -> Option<Response> {
+        self.log
+            .lock()
+            .unwrap()
+            .push(format!("before {}", self.name));
+        req.headers
+            .insert(format!("X-{}", self.name), "1".to_string());
+        self.answer
+            .then(|| Response::new(StatusCode::Forbidden).build())
+    }
+
+    async fn after_response(&self, req: &Request, response: &mut Response) {
+        let mut seen: Vec<_> = req.headers.keys().cloned().collect();
+        seen.sort();
+        self.log
+            .lock()
+            .unwrap()
+            .push(format!("after {} {}", self.name, seen.join(",")));
+        response
+            .headers
+            .insert(format!("X-After-{}", self.name), req.body.len().to_string());
+    }
+}
+
+fn recorder(name: &'static str, log: &Log, answer: bool) -> Recorder {
+    Recorder {
+        name,
+        log: log.clone(),
+        answer,
+    }
+}
+
+fn request() -> Request {
+    RequestBuilder::new()
+        .method(Method::POST)
+        .path("/")
+        .body(b"payload".to_vec())
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_hooks_run_in_order_around_the_handler() {
+    let log: Log = Arc::default();
+    let handled = log.clone();
+    let chain = MiddlewareChain::new(handler_fn(move |req: Request| {
+        let handled = handled.clone();
+        async move {
+            handled
+                .lock()
+                .unwrap()
+                .push(format!("handler {}", req.body.len()));
+            Response::ok(b"ok".to_vec())
+        }
+    }))
+    .with(recorder("a", &log, false))
+    .with(Arc::new(recorder("b", &log, false)));
+
+    let response = chain.handle(request()).await;
+    assert_eq!(response.status, StatusCode::Ok);
+    assert_eq!(
+        *log.lock().unwrap(),
+        [
+            "before a",
+            "before b",
+            "handler 7",
+            "after b X-a,X-b",
+            "after a X-a",
+        ]
+    );
+    // After hooks see the request without its body
+    assert_eq!(response.headers["X-After-a"], "0");
+}
+
+#[tokio::test]
+async fn test_answering_middleware_skips_the_rest() {
+    let log: Log = Arc::default();
+    let chain = MiddlewareChain::new(handler_fn(|_req| async {
+        panic!("the handler must not run")
+    }))
+    .with(recorder("a", &log, false))
+    .with(recorder("b", &log, true))
+    .with(recorder("c", &log, false));
+
+    let response = chain.handle(request()).await;
+    assert_eq!(response.status, StatusCode::Forbidden);
+    assert_eq!(
+        *log.lock().unwrap(),
+        ["before a", "before b", "after a X-a"]
+    );
+    assert!(!response.headers.contains_key("X-After-b"));
+    assert!(MiddlewareChain::new(handler_fn(|_req| async { Response::not_found() })).is_empty());
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Identity(&'static str);
+
+struct Auth;
+
+#[async_trait]
+impl Middleware for Auth {
+    async fn before_request(&self, req: &mut Request) -> Option<Response> {
+        req.context.extensions.insert(Identity("alice"));
+        None
+    }
+}
+
+struct AccessLog(Log);
+
+#[async_trait]
+impl Middleware for AccessLog {
+    async fn after_response(&self, req: &Request, response: &mut Response) {
+        let user = req.context.extensions.get::<Identity>().map(|id| id.0);
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("{} {:?}", response.status.as_u16(), user));
+    }
+}
+
+#[tokio::test]
+async fn test_extensions_pass_between_stages() {
+    let log: Log = Arc::default();
+    let chain = MiddlewareChain::new(handler_fn(|req: Request| async move {
+        match req.context.extensions.get::<Identity>() {
+            Some(Identity(user)) => Response::ok(user.as_bytes().to_vec()),
+            None => Response::new(StatusCode::Unauthorized).build(),
+        }
+    }))
+    .with(AccessLog(log.clone()))
+    .with(Auth);
+
+    let response = chain.handle(request()).await;
+    assert_eq!(response.body, b"alice");
+    // The log runs first, so it only sees what it passed on
+    assert_eq!(*log.lock().unwrap(), ["200 None"]);
+
+    let log: Log = Arc::default();
+    let chain = MiddlewareChain::new(handler_fn(|_req| async { Response::ok(b"ok".to_vec()) }))
+        .with(Auth)
+        .with(AccessLog(log.clone()));
+    chain.handle(request()).await;
+    assert_eq!(*log.lock().unwrap(), ["200 Some(\"alice\")"]);
+    assert!(request().context.extensions.get::<Identity>().is_none());
+}
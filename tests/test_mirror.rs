@@ -0,0 +1,140 @@
+//! Tests for traffic mirroring and response comparison
+
+use sentinel::config::MirrorCompareConfig;
+use sentinel::metrics::{Metrics, PrometheusRecorder};
+use sentinel::proxy::Mirror;
+use sentinel::proxy::mirror::{Comparison, compare_responses};
+use sentinel::testing::{MockAction, MockBackend, MockResponse, proxy_handler, send_request};
+use std::sync::Arc;
+use std::time::Duration;
+
+const GET: &[u8] = b"GET /api/items HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n";
+
+async fn backend(status: u16, body: &str) -> MockBackend {
+    let backend = MockBackend::start().await;
+    backend.set_default(MockAction::Respond(
+        MockResponse::new(status).reason("Mock").body(body),
+    ));
+    backend
+}
+
+/// Wait for the background comparison to be recorded
+async fn wait_for(recorder: &PrometheusRecorder, needle: &str) -> String {
+    for _ in 0..50 {
+        let output = recorder.render();
+        if output.contains(needle) {
+            return output;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    recorder.render()
+}
+
+#[tokio::test]
+async fn test_client_gets_primary_response_and_shadow_gets_copy() {
+    let primary = backend(200, "primary").await;
+    let shadow = backend(500, "shadow").await;
+    let mirror = Arc::new(Mirror::new(
+        Arc::new(proxy_handler(&[&primary])),
+        proxy_handler(&[&shadow]),
+    ));
+
+    let response = send_request(mirror, GET).await;
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "primary");
+
+    for _ in 0..50 {
+        if shadow.request_count() == 1 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(shadow.requests()[0].path, "/api/items");
+}
+
+#[tokio::test]
+async fn test_zero_percent_mirrors_nothing() {
+    let primary = backend(200, "primary").await;
+    let shadow = backend(200, "shadow").await;
+    let mirror = Arc::new(
+        Mirror::new(
+            Arc::new(proxy_handler(&[&primary])),
+            proxy_handler(&[&shadow]),
+        )
+        .with_percent(0),
+    );
+
+    for _ in 0..5 {
+        send_request(mirror.clone(), GET).await;
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(primary.request_count(), 5);
+    assert_eq!(shadow.request_count(), 0);
+}
+
+#[tokio::test]
+async fn test_status_mismatch_is_counted() {
+    let primary = backend(200, "{}").await;
+    let shadow = backend(500, "{}").await;
+    let recorder = Arc::new(PrometheusRecorder::new());
+    let mirror = Arc::new(
+        Mirror::new(
+            Arc::new(proxy_handler(&[&primary])),
+            proxy_handler(&[&shadow]),
+        )
+        .with_comparison(MirrorCompareConfig::default())
+        .with_metrics(Metrics::new(recorder.clone())),
+    );
+
+    send_request(mirror, GET).await;
+
+    let output = wait_for(
+        &recorder,
+        "sentinel_mirror_comparisons_total{result=\"status_mismatch\"} 1",
+    )
+    .await;
+    assert!(
+        output.contains("sentinel_mirror_comparisons_total{result=\"status_mismatch\"} 1"),
+        "{}",
+        output
+    );
+    assert!(output.contains("sentinel_mirror_requests_total{status=\"500\"} 1"));
+}
+
+#[test]
+fn test_bodies_are_compared_after_normalization() {
+    let compare = MirrorCompareConfig {
+        bodies: true,
+        ignore_fields: vec!["generated_at".to_string()],
+    };
+
+    // Key order and ignored fields do not matter, at any depth
+    assert_eq!(
+        compare_responses(
+            &compare,
+            200,
+            br#"{"id":1,"tags":[{"generated_at":1,"name":"a"}],"generated_at":"10:00"}"#,
+            200,
+            br#"{"generated_at":"10:01","tags":[{"name":"a","generated_at":2}],"id":1}"#,
+        ),
+        Comparison::Match
+    );
+    assert_eq!(
+        compare_responses(&compare, 200, br#"{"id":1}"#, 200, br#"{"id":2}"#),
+        Comparison::BodyMismatch
+    );
+    assert_eq!(
+        compare_responses(&compare, 200, b"hello\n", 200, b"hello"),
+        Comparison::Match
+    );
+    assert_eq!(
+        compare_responses(&compare, 200, b"a", 404, b"a"),
+        Comparison::StatusMismatch
+    );
+
+    // Bodies are ignored unless enabled
+    assert_eq!(
+        compare_responses(&MirrorCompareConfig::default(), 200, b"a", 200, b"b"),
+        Comparison::Match
+    );
+}
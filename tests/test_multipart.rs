@@ -0,0 +1,159 @@
+//! Tests for the streaming multipart/form-data parser
+
+use sentinel::config::RequestBodyConfig;
+use sentinel::http::multipart::{Multipart, boundary};
+use sentinel::http::request::{Method, RequestBuilder};
+use sentinel::http::spool::BodySpool;
+use std::sync::Arc;
+
+const BODY: &[u8] = b"preamble is ignored\r\n\
+--XyZ\r\n\
+Content-Disposition: form-data; name=\"title\"\r\n\
+\r\n\
+Holiday\r\n\
+--XyZ\r\n\
+Content-Disposition: form-data; name=\"photo\"; filename=\"beach \\\"1\\\".png\"\r\n\
+Content-Type: image/png\r\n\
+\r\n\
+\x89PNG\r\n--XyNot a boundary\r\n\
+--XyZ--\r\n\
+epilogue";
+
+/// Reads one byte at a time to exercise boundaries split across reads
+struct Trickle<'a>(&'a [u8]);
+
+impl tokio::io::AsyncRead for Trickle<'_> {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        if let Some((first, rest)) = self.0.split_first() {
+            buf.put_slice(&[*first]);
+            self.0 = rest;
+        }
+        std::task::Poll::Ready(Ok(()))
+    }
+}
+
+#[test]
+fn test_boundary_from_content_type() {
+    assert_eq!(
+        boundary("multipart/form-data; boundary=XyZ").as_deref(),
+        Some("XyZ")
+    );
+    assert_eq!(
+        boundary("Multipart/Form-Data; charset=utf-8; boundary=\"a b\"").as_deref(),
+        Some("a b")
+    );
+    assert_eq!(boundary("multipart/form-data"), None);
+    assert_eq!(boundary("multipart/mixed; boundary=XyZ"), None);
+    assert_eq!(boundary("text/plain; boundary=XyZ"), None);
+}
+
+#[tokio::test]
+async fn test_iterates_parts() {
+    for reader in [
+        Box::new(BODY) as Box<dyn tokio::io::AsyncRead + Unpin>,
+        Box::new(Trickle(BODY)),
+    ] {
+        let mut multipart = Multipart::new(reader, "XyZ");
+
+        let part = multipart.next_part().await.unwrap().unwrap();
+        assert_eq!(part.name().as_deref(), Some("title"));
+        assert_eq!(part.filename(), None);
+        assert_eq!(part.content_type(), "text/plain");
+        assert_eq!(multipart.read_to_end(1024).await.unwrap(), b"Holiday");
+
+        let part = multipart.next_part().await.unwrap().unwrap();
+        assert_eq!(part.name().as_deref(), Some("photo"));
+        assert_eq!(part.filename().as_deref(), Some("beach \"1\".png"));
+        assert_eq!(part.header("content-type"), Some("image/png"));
+        assert_eq!(
+            multipart.read_to_end(1024).await.unwrap(),
+            b"\x89PNG\r\n--XyNot a boundary"
+        );
+
+        assert!(multipart.next_part().await.unwrap().is_none());
+        assert!(multipart.next_part().await.unwrap().is_none());
+    }
+}
+
+#[tokio::test]
+async fn test_skips_unread_content() {
+    let mut multipart = Multipart::new(BODY, "XyZ");
+    multipart.next_part().await.unwrap().unwrap();
+    let part = multipart.next_part().await.unwrap().unwrap();
+    assert_eq!(part.name().as_deref(), Some("photo"));
+    assert!(multipart.next_part().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_limits_and_malformed_bodies() {
+    let mut multipart = Multipart::new(BODY, "XyZ").with_max_parts(1);
+    multipart.next_part().await.unwrap();
+    assert!(multipart.next_part().await.is_err());
+
+    let mut multipart = Multipart::new(BODY, "XyZ").with_max_header_bytes(32);
+    assert!(multipart.next_part().await.is_err());
+
+    let mut multipart = Multipart::new(BODY, "XyZ");
+    multipart.next_part().await.unwrap();
+    assert!(multipart.read_to_end(3).await.is_err());
+
+    let truncated = &BODY[..BODY.len() - 20];
+    let mut multipart = Multipart::new(truncated, "XyZ");
+    multipart.next_part().await.unwrap();
+    multipart.next_part().await.unwrap();
+    assert!(multipart.next_part().await.is_err());
+}
+
+#[tokio::test]
+async fn test_from_request() {
+    let req = RequestBuilder::new()
+        .method(Method::POST)
+        .path("/upload")
+        .header("Content-Type", "multipart/form-data; boundary=XyZ")
+        .body(BODY.to_vec())
+        .build()
+        .unwrap();
+    let mut multipart = Multipart::from_request(&req).await.unwrap().unwrap();
+    let part = multipart.next_part().await.unwrap().unwrap();
+    assert_eq!(part.name().as_deref(), Some("title"));
+
+    let plain = RequestBuilder::new()
+        .method(Method::POST)
+        .path("/upload")
+        .header("Content-Type", "application/json")
+        .build()
+        .unwrap();
+    assert!(Multipart::from_request(&plain).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_from_spooled_request() {
+    let dir = std::env::temp_dir().join(format!("sentinel-multipart-{}", std::process::id()));
+    let spool = BodySpool::new(&RequestBodyConfig {
+        spool_dir: Some(dir.clone()),
+        ..RequestBodyConfig::default()
+    })
+    .unwrap();
+    let mut file = spool.create().await.unwrap();
+    file.write(BODY).await.unwrap();
+
+    let mut req = RequestBuilder::new()
+        .method(Method::POST)
+        .path("/upload")
+        .header("content-type", "multipart/form-data; boundary=XyZ")
+        .build()
+        .unwrap();
+    req.spooled = Some(Arc::new(file.finish().await.unwrap()));
+
+    let mut multipart = Multipart::from_request(&req).await.unwrap().unwrap();
+    multipart.next_part().await.unwrap().unwrap();
+    let part = multipart.next_part().await.unwrap().unwrap();
+    assert_eq!(part.content_type(), "image/png");
+    drop(multipart);
+    drop(req);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
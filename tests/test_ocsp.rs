@@ -0,0 +1,456 @@
+//! Tests for OCSP stapling
+
+use rustls::DigitallySignedStruct;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use sentinel::config::{OcspConfig, TlsConfig, TlsVersion};
+use sentinel::metrics::{Metrics, PrometheusRecorder};
+use sentinel::tls::{self, CertResolver, OcspStapler};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+const SERIAL: [u8; 2] = [0x12, 0x34];
+
+/// A leaf certificate for `localhost` issued by a test CA, written as a
+/// PEM chain and key
+struct TestChain {
+    dir: PathBuf,
+    cert_file: PathBuf,
+    key_file: PathBuf,
+}
+
+impl TestChain {
+    /// Chain whose leaf names `responder` as its OCSP responder
+    fn new(name: &str, responder: Option<&str>, with_issuer: bool) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("sentinel-ocsp-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let ca_key = rcgen::KeyPair::generate().unwrap();
+        let mut ca_params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        ca_params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "Sentinel Test CA");
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let key = rcgen::KeyPair::generate().unwrap();
+        let mut params = rcgen::CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+        params.serial_number = Some(rcgen::SerialNumber::from(SERIAL.to_vec()));
+        if let Some(url) = responder {
+            params
+                .custom_extensions
+                .push(rcgen::CustomExtension::from_oid_content(
+                    &[1, 3, 6, 1, 5, 5, 7, 1, 1],
+                    authority_info_access(url),
+                ));
+        }
+        let leaf = params.signed_by(&key, &ca, &ca_key).unwrap();
+
+        let cert_file = dir.join("chain.pem");
+        let key_file = dir.join("key.pem");
+        let mut chain = leaf.pem();
+        if with_issuer {
+            chain.push_str(&ca.pem());
+        }
+        std::fs::write(&cert_file, chain).unwrap();
+        std::fs::write(&key_file, key.serialize_pem()).unwrap();
+        Self {
+            dir,
+            cert_file,
+            key_file,
+        }
+    }
+
+    fn config(&self, ocsp: OcspConfig) -> TlsConfig {
+        TlsConfig {
+            cert_file: self.cert_file.clone(),
+            key_file: self.key_file.clone(),
+            certificates: Default::default(),
+            client_auth: None,
+            reload_interval_secs: 0,
+            min_version: TlsVersion::Tls12,
+            max_version: TlsVersion::Tls13,
+            cipher_suites: Vec::new(),
+            alpn: vec!["http/1.1".to_string()],
+            key_log_file: None,
+            ocsp: Some(ocsp),
+        }
+    }
+}
+
+impl Drop for TestChain {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn der(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    if value.len() < 0x80 {
+        out.push(value.len() as u8);
+    } else {
+        out.extend([0x82, (value.len() >> 8) as u8, value.len() as u8]);
+    }
+    out.extend_from_slice(value);
+    out
+}
+
+fn seq(elements: &[&[u8]]) -> Vec<u8> {
+    der(0x30, &elements.concat())
+}
+
+fn time(text: &str) -> Vec<u8> {
+    der(0x18, text.as_bytes())
+}
+
+fn authority_info_access(url: &str) -> Vec<u8> {
+    let ocsp = [0x06, 0x08, 0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01];
+    seq(&[&seq(&[&ocsp, &der(0x86, url.as_bytes())])])
+}
+
+/// A successful `OCSPResponse` for the test leaf with the given status
+fn ocsp_response(status: &[u8], next_update: &str) -> Vec<u8> {
+    let sha1 = seq(&[&[0x06, 0x05, 0x2B, 0x0E, 0x03, 0x02, 0x1A], &[0x05, 0x00]]);
+    let cert_id = seq(&[
+        &sha1,
+        &der(0x04, &[0; 20]),
+        &der(0x04, &[0; 20]),
+        &der(0x02, &SERIAL),
+    ]);
+    let single = seq(&[
+        &cert_id,
+        status,
+        &time("20260101000000Z"),
+        &der(0xA0, &time(next_update)),
+    ]);
+    let data = seq(&[
+        &der(0xA1, &seq(&[])),
+        &time("20260101000000Z"),
+        &seq(&[&single]),
+    ]);
+    let sha256_rsa = [
+        0x06, 0x09, 0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x01, 0x0B,
+    ];
+    let basic = seq(&[&data, &seq(&[&sha256_rsa, &[0x05, 0x00]]), &der(0x03, &[0])]);
+    let basic_type = [
+        0x06, 0x09, 0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01,
+    ];
+    seq(&[
+        &[0x0A, 0x01, 0x00],
+        &der(0xA0, &seq(&[&basic_type, &der(0x04, &basic)])),
+    ])
+}
+
+fn good(next_update: &str) -> Vec<u8> {
+    ocsp_response(&[0x80, 0x00], next_update)
+}
+
+/// An OCSP responder answering every request with `response`, recording
+/// request bodies
+async fn responder(response: Vec<u8>) -> (String, Arc<Mutex<Vec<Vec<u8>>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/ocsp", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let seen = requests.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut raw = Vec::new();
+            let mut buf = [0; 4096];
+            let body = loop {
+                let n = socket.read(&mut buf).await.unwrap();
+                raw.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&raw).to_string();
+                if let Some(end) = text.find("\r\n\r\n") {
+                    assert!(text.starts_with("POST /ocsp HTTP/1.1"), "{}", text);
+                    assert!(
+                        text.contains("Content-Type: application/ocsp-request"),
+                        "{}",
+                        text
+                    );
+                    let length: usize = text
+                        .lines()
+                        .find_map(|l| l.strip_prefix("Content-Length: "))
+                        .unwrap()
+                        .parse()
+                        .unwrap();
+                    if raw.len() >= end + 4 + length {
+                        break raw[end + 4..end + 4 + length].to_vec();
+                    }
+                }
+                if n == 0 {
+                    return;
+                }
+            };
+            seen.lock().unwrap().push(body);
+            let head = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/ocsp-response\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                response.len()
+            );
+            socket.write_all(head.as_bytes()).await.unwrap();
+            socket.write_all(&response).await.unwrap();
+        }
+    });
+    (url, requests)
+}
+
+/// Accepts any certificate, keeping the stapled OCSP response
+#[derive(Debug, Default)]
+struct StapleRecorder {
+    staple: Mutex<Option<Vec<u8>>>,
+}
+
+impl ServerCertVerifier for StapleRecorder {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        *self.staple.lock().unwrap() = Some(ocsp_response.to_vec());
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        rustls::crypto::ring::default_provider()
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// Handshake with a server using `resolver` and return the staple it sent
+async fn stapled(config: &TlsConfig, resolver: Arc<CertResolver>) -> Vec<u8> {
+    let acceptor = TlsAcceptor::from(tls::server_config_with(config, resolver).unwrap());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (socket, _) = listener.accept().await.unwrap();
+        let _ = acceptor.accept(socket).await;
+    });
+
+    let verifier = Arc::new(StapleRecorder::default());
+    let client = rustls::ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(verifier.clone())
+        .with_no_client_auth();
+    let socket = tokio::net::TcpStream::connect(addr).await.unwrap();
+    TlsConnector::from(Arc::new(client))
+        .connect("localhost".try_into().unwrap(), socket)
+        .await
+        .unwrap();
+    verifier.staple.lock().unwrap().take().unwrap()
+}
+
+#[tokio::test]
+async fn test_staples_response_from_certificate_responder() {
+    let response = good("20991231000000Z");
+    let (url, requests) = responder(response.clone()).await;
+    let chain = TestChain::new("staple", Some(&url), true);
+    let config = chain.config(OcspConfig::default());
+    let resolver = Arc::new(CertResolver::load(&config).unwrap());
+    let recorder = Arc::new(PrometheusRecorder::new());
+    let stapler = OcspStapler::new(resolver.clone(), &config)
+        .unwrap()
+        .with_metrics(Metrics::new(recorder.clone()));
+    assert_eq!(stapler.responder().as_str(), url);
+
+    // Nothing is stapled before the first fetch
+    assert!(stapled(&config, resolver.clone()).await.is_empty());
+
+    let staple = stapler.refresh().await.unwrap();
+    assert_eq!(
+        staple.this_update,
+        UNIX_EPOCH + Duration::from_secs(1_767_225_600)
+    );
+    assert_eq!(stapler.staple(), Some(staple));
+    assert_eq!(stapled(&config, resolver.clone()).await, response);
+
+    // The request asks about the leaf's serial number
+    let request = requests.lock().unwrap()[0].clone();
+    assert_eq!(request[0], 0x30);
+    assert!(
+        request.ends_with(&[0x02, 0x02, 0x12, 0x34]),
+        "{:?}",
+        request
+    );
+
+    stapler.report(UNIX_EPOCH + Duration::from_secs(1_767_225_600 + 90));
+    let rendered = recorder.render();
+    let certificate = config.cert_file.display().to_string();
+    for series in [
+        format!(
+            r#"sentinel_ocsp_fetches_total{{certificate="{}",outcome="success"}} 1"#,
+            certificate
+        ),
+        format!(
+            r#"sentinel_ocsp_staple_valid{{certificate="{}"}} 1"#,
+            certificate
+        ),
+        format!(
+            r#"sentinel_ocsp_staple_age_seconds{{certificate="{}"}} 90"#,
+            certificate
+        ),
+    ] {
+        assert!(
+            rendered.contains(&series),
+            "{} not in\n{}",
+            series,
+            rendered
+        );
+    }
+    assert!(rendered.contains("sentinel_ocsp_staple_expiry_seconds{"));
+}
+
+#[tokio::test]
+async fn test_staples_follow_reloaded_certificates() {
+    let response = good("20991231000000Z");
+    let (url, _) = responder(response.clone()).await;
+    let chain = TestChain::new("reload", Some(&url), true);
+    let config = chain.config(OcspConfig::default());
+    let resolver = Arc::new(CertResolver::load(&config).unwrap());
+    let stapler = OcspStapler::new(resolver.clone(), &config).unwrap();
+    stapler.refresh().await.unwrap();
+
+    // The same certificate keeps its staple
+    resolver.reload(&config).unwrap();
+    assert_eq!(resolver.current().ocsp.as_ref(), Some(&response));
+
+    // A renewed one starts without, and the old stapler can't staple it
+    let renewed = TestChain::new("reload-renewed", Some(&url), true);
+    std::fs::copy(&renewed.cert_file, &chain.cert_file).unwrap();
+    std::fs::copy(&renewed.key_file, &chain.key_file).unwrap();
+    resolver.reload(&config).unwrap();
+    assert!(resolver.current().ocsp.is_none());
+    assert!(stapler.refresh().await.is_err());
+    assert!(resolver.current().ocsp.is_none());
+}
+
+#[tokio::test]
+async fn test_expired_staple_is_dropped() {
+    let (url, _) = responder(good("20991231000000Z")).await;
+    let chain = TestChain::new("expired", Some(&url), true);
+    let config = chain.config(OcspConfig::default());
+    let resolver = Arc::new(CertResolver::load(&config).unwrap());
+    let recorder = Arc::new(PrometheusRecorder::new());
+    let stapler = OcspStapler::new(resolver.clone(), &config)
+        .unwrap()
+        .with_metrics(Metrics::new(recorder.clone()));
+    stapler.refresh().await.unwrap();
+
+    // Past nextUpdate
+    stapler.report(UNIX_EPOCH + Duration::from_secs(4_102_444_800 + 1));
+    assert_eq!(stapler.staple(), None);
+    assert!(resolver.current().ocsp.is_none());
+    assert!(recorder.render().contains(&format!(
+        r#"sentinel_ocsp_staple_valid{{certificate="{}"}} 0"#,
+        config.cert_file.display()
+    )));
+}
+
+#[tokio::test]
+async fn test_stale_and_revoked_responses_are_not_stapled() {
+    let (url, _) = responder(good("20260102000000Z")).await;
+    let chain = TestChain::new("stale", Some(&url), true);
+    let config = chain.config(OcspConfig::default());
+    let resolver = Arc::new(CertResolver::load(&config).unwrap());
+    let stapler = OcspStapler::new(resolver.clone(), &config).unwrap();
+    let err = stapler.refresh().await.unwrap_err();
+    assert!(format!("{:#}", err).contains("expired"), "{:#}", err);
+    assert_eq!(stapler.staple(), None);
+
+    let revoked = ocsp_response(&der(0xA1, &time("20260101000000Z")), "20991231000000Z");
+    let (url, _) = responder(revoked).await;
+    let chain = TestChain::new("revoked", Some(&url), true);
+    let config = chain.config(OcspConfig::default());
+    let resolver = Arc::new(CertResolver::load(&config).unwrap());
+    let stapler = OcspStapler::new(resolver.clone(), &config).unwrap();
+    let err = stapler.refresh().await.unwrap_err();
+    assert!(format!("{:#}", err).contains("revoked"), "{:#}", err);
+    assert!(resolver.current().ocsp.is_none());
+}
+
+#[tokio::test]
+async fn test_responder_url_overrides_certificate() {
+    let (url, requests) = responder(good("20991231000000Z")).await;
+    let chain = TestChain::new("override", Some("http://127.0.0.1:9/ocsp"), true);
+    let config = chain.config(OcspConfig {
+        responder_url: Some(url.clone()),
+        ..OcspConfig::default()
+    });
+    let resolver = Arc::new(CertResolver::load(&config).unwrap());
+    let stapler = OcspStapler::new(resolver, &config).unwrap();
+    assert_eq!(stapler.responder().as_str(), url);
+    stapler.refresh().await.unwrap();
+    assert_eq!(requests.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_failed_fetch_is_counted() {
+    // Nothing listens on the discard port
+    let chain = TestChain::new("unreachable", Some("http://127.0.0.1:9/ocsp"), true);
+    let config = chain.config(OcspConfig {
+        timeout_ms: 500,
+        ..OcspConfig::default()
+    });
+    let resolver = Arc::new(CertResolver::load(&config).unwrap());
+    let recorder = Arc::new(PrometheusRecorder::new());
+    let stapler = OcspStapler::new(resolver, &config)
+        .unwrap()
+        .with_metrics(Metrics::new(recorder.clone()));
+    assert!(stapler.refresh().await.is_err());
+    assert!(recorder.render().contains(&format!(
+        r#"sentinel_ocsp_fetches_total{{certificate="{}",outcome="error"}} 1"#,
+        config.cert_file.display()
+    )));
+}
+
+#[test]
+fn test_stapling_needs_issuer_and_responder() {
+    let chain = TestChain::new("no-issuer", Some("http://127.0.0.1:9/ocsp"), false);
+    let config = chain.config(OcspConfig::default());
+    let resolver = Arc::new(CertResolver::load(&config).unwrap());
+    let err = OcspStapler::new(resolver, &config).err().unwrap();
+    assert!(format!("{:#}", err).contains("issuer"), "{:#}", err);
+
+    let chain = TestChain::new("no-responder", None, true);
+    let config = chain.config(OcspConfig::default());
+    let resolver = Arc::new(CertResolver::load(&config).unwrap());
+    let err = OcspStapler::new(resolver, &config).err().unwrap();
+    assert!(format!("{:#}", err).contains("responder_url"), "{:#}", err);
+}
+
+#[test]
+fn test_ocsp_config_defaults() {
+    let config: TlsConfig =
+        serde_yaml::from_str("cert_file: cert.pem\nkey_file: key.pem\nocsp: {}\n").unwrap();
+    let ocsp = config.ocsp.unwrap();
+    assert_eq!(ocsp.refresh_secs, 3600);
+    assert_eq!(ocsp.retry_secs, 60);
+    assert_eq!(ocsp.timeout_ms, 5000);
+    assert!(ocsp.responder_url.is_none());
+}
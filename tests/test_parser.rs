@@ -1,5 +1,14 @@
-use sentinel::http::parser::{ParseError, parse_http_request};
+use sentinel::http::connection::Connection;
+use sentinel::http::handler::handler_fn;
+use sentinel::http::parser::{
+    HeadLimits, ParseError, ParseMode, RequestParser, parse_http_request,
+    parse_http_request_with_mode,
+};
 use sentinel::http::request::Method;
+use sentinel::http::response::{Response, StatusCode};
+use sentinel::testing::send_request;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 #[test]
 fn test_parse_simple_get_request() {
@@ -53,7 +62,7 @@ fn test_parse_incomplete_request_missing_blank_line() {
 
 #[test]
 fn test_parse_incomplete_request_partial_body() {
-    let req = b"POST /api HTTP/1.1\r\nContent-Length: 10\r\n\r\nhello";
+    let req = b"POST /api HTTP/1.1\r\nHost: a\r\nContent-Length: 10\r\n\r\nhello";
     let result = parse_http_request(req);
 
     assert!(matches!(result, Err(ParseError::Incomplete)));
@@ -61,7 +70,7 @@ fn test_parse_incomplete_request_partial_body() {
 
 #[test]
 fn test_parse_invalid_http_method() {
-    let req = b"INVALID / HTTP/1.1\r\n\r\n";
+    let req = b"INVALID / HTTP/1.1\r\nHost: a\r\n\r\n";
     let result = parse_http_request(req);
 
     assert!(matches!(result, Err(ParseError::InvalidMethod)));
@@ -69,7 +78,7 @@ fn test_parse_invalid_http_method() {
 
 #[test]
 fn test_parse_malformed_header() {
-    let req = b"GET / HTTP/1.1\r\nBrokenHeader\r\n\r\n";
+    let req = b"GET / HTTP/1.1\r\nHost: a\r\nBrokenHeader\r\n\r\n";
     let result = parse_http_request(req);
 
     assert!(matches!(result, Err(ParseError::InvalidHeader)));
@@ -88,7 +97,7 @@ fn test_parse_various_http_methods() {
     ];
 
     for (method_str, expected_method) in methods {
-        let req = format!("{} / HTTP/1.1\r\n\r\n", method_str);
+        let req = format!("{} / HTTP/1.1\r\nHost: a\r\n\r\n", method_str);
         let (parsed, _) = parse_http_request(req.as_bytes()).unwrap();
         assert_eq!(parsed.method, expected_method);
     }
@@ -96,7 +105,7 @@ fn test_parse_various_http_methods() {
 
 #[test]
 fn test_parse_request_with_empty_body() {
-    let req = b"POST /api HTTP/1.1\r\nContent-Length: 0\r\n\r\n";
+    let req = b"POST /api HTTP/1.1\r\nHost: a\r\nContent-Length: 0\r\n\r\n";
     let (parsed, _) = parse_http_request(req).unwrap();
 
     assert_eq!(parsed.body.len(), 0);
@@ -104,7 +113,7 @@ fn test_parse_request_with_empty_body() {
 
 #[test]
 fn test_parse_request_with_binary_body() {
-    let req = b"POST /upload HTTP/1.1\r\nContent-Length: 4\r\n\r\n\x00\x01\x02\x03";
+    let req = b"POST /upload HTTP/1.1\r\nHost: a\r\nContent-Length: 4\r\n\r\n\x00\x01\x02\x03";
     let (parsed, _) = parse_http_request(req).unwrap();
 
     assert_eq!(parsed.body, vec![0, 1, 2, 3]);
@@ -112,9 +121,364 @@ fn test_parse_request_with_binary_body() {
 
 #[test]
 fn test_parse_header_case_preservation() {
-    let req = b"GET / HTTP/1.1\r\nContent-Type: application/json\r\n\r\n";
+    let req = b"GET / HTTP/1.1\r\nHost: a\r\nContent-Type: application/json\r\n\r\n";
     let (parsed, _) = parse_http_request(req).unwrap();
 
     // Headers are stored as-is with trimming
     assert!(parsed.headers.contains_key("Content-Type"));
 }
+
+#[test]
+fn test_parse_rejects_transfer_encoding_with_content_length() {
+    let req =
+        b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 4\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n";
+    let result = parse_http_request(req);
+
+    assert!(matches!(result, Err(ParseError::ConflictingLength)));
+
+    // Only chunked can be framed, and only on HTTP/1.1
+    for req in [
+        &b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: gzip, chunked\r\n\r\n"[..],
+        b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\ntransfer-encoding: chunked\r\n\r\n",
+        b"POST / HTTP/1.0\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+    ] {
+        assert!(matches!(
+            parse_http_request(req),
+            Err(ParseError::UnsupportedTransferEncoding)
+        ));
+    }
+}
+
+#[test]
+fn test_parse_decodes_chunked_bodies() {
+    let raw = b"POST /upload HTTP/1.1\r\nHost: a\r\ntransfer-encoding: Chunked\r\n\r\n\
+                4\r\nWiki\r\n5;name=value\r\npedia\r\n0\r\nX-Trailer: 1\r\n\r\nGET";
+    let mut parser = RequestParser::new(ParseMode::Strict, HeadLimits::default());
+
+    // Fed a byte at a time, the request completes with its trailers
+    let mut buf = Vec::new();
+    let mut parsed = None;
+    for &byte in raw.iter() {
+        buf.push(byte);
+        match parser.parse(&buf) {
+            Ok(result) => {
+                parsed = Some(result);
+                break;
+            }
+            Err(ParseError::Incomplete) => {}
+            Err(e) => panic!("unexpected {:?}", e),
+        }
+    }
+
+    let (request, consumed) = parsed.unwrap();
+    assert_eq!(request.body, b"Wikipedia");
+    assert_eq!(request.header("Content-Length"), Some("9"));
+    assert_eq!(request.header("Transfer-Encoding"), None);
+    assert_eq!(consumed, raw.len() - 3);
+
+    for body in [
+        &b"+4\r\nWiki\r\n0\r\n\r\n"[..],
+        b"4\nWiki\r\n0\r\n\r\n",
+        b"4\r\nWikiX\r\n0\r\n\r\n",
+        b"zz\r\n\r\n",
+    ] {
+        let mut req = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+        req.extend_from_slice(body);
+        assert!(matches!(
+            parse_http_request(&req),
+            Err(ParseError::InvalidRequest)
+        ));
+    }
+}
+
+#[tokio::test]
+async fn test_connection_answers_body_framing_errors() {
+    let cases = [
+        (
+            "POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 4\r\nContent-Length: 5\r\n\r\nbody",
+            "HTTP/1.1 400 Bad Request\r\n",
+        ),
+        (
+            "POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 4\r\nTransfer-Encoding: chunked\r\n\r\n",
+            "HTTP/1.1 400 Bad Request\r\n",
+        ),
+        (
+            "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: gzip\r\n\r\n",
+            "HTTP/1.1 501 Not Implemented\r\n",
+        ),
+        (
+            "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n\
+             3\r\nabc\r\n0\r\n\r\n",
+            "HTTP/1.1 200 OK\r\n",
+        ),
+    ];
+
+    for (request, status_line) in cases {
+        let handler = Arc::new(handler_fn(|req| async move { Response::ok(req.body) }));
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let conn =
+            tokio::spawn(async move { Connection::with_handler(server, handler).run().await });
+
+        client.write_all(request.as_bytes()).await.unwrap();
+        let mut output = Vec::new();
+        client.read_to_end(&mut output).await.unwrap();
+        conn.await.unwrap().unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with(status_line), "{}", output);
+        if status_line.contains("200") {
+            assert!(output.ends_with("abc"), "{}", output);
+        }
+    }
+}
+
+#[test]
+fn test_parse_rejects_repeated_or_malformed_content_length() {
+    for req in [
+        &b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 4\r\nContent-Length: 4\r\n\r\nbody"[..],
+        b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 4\r\ncontent-length: 5\r\n\r\nbody!",
+        b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 4, 4\r\n\r\nbody",
+        b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: +4\r\n\r\nbody",
+    ] {
+        assert!(matches!(
+            parse_http_request(req),
+            Err(ParseError::InvalidContentLength)
+        ));
+    }
+}
+
+#[test]
+fn test_parse_normalizes_content_length_name() {
+    let req = b"POST / HTTP/1.1\r\nHost: a\r\ncontent-length: 4\r\n\r\nbodyGET";
+    let (parsed, consumed) = parse_http_request(req).unwrap();
+
+    assert_eq!(parsed.body, b"body");
+    assert_eq!(consumed, req.len() - 3);
+}
+
+#[test]
+fn test_parse_rejects_invalid_header_names_and_bare_cr() {
+    for req in [
+        &b"GET / HTTP/1.1\r\nHost : a\r\n\r\n"[..],
+        b"GET / HTTP/1.1\r\nHost: a\r\nX-Bad\rName: a\r\n\r\n",
+        b"GET / HTTP/1.1\r\nHost: a\r\nX-Value: a\rb\r\n\r\n",
+        b"GET / HTTP/1.1\r\nHost: a\r\nX-Folded: a\r\n b\r\n\r\n",
+        b"GET / HTTP/1.1\r\nHost: a\r\nX(Paren): a\r\n\r\n",
+    ] {
+        assert!(matches!(
+            parse_http_request(req),
+            Err(ParseError::InvalidHeader)
+        ));
+    }
+
+    let req = b"GET /\r HTTP/1.1\r\nHost: a\r\n\r\n";
+    assert!(matches!(
+        parse_http_request(req),
+        Err(ParseError::InvalidRequest)
+    ));
+}
+
+#[test]
+fn test_head_limits_check_partial_heads() {
+    let limits = HeadLimits {
+        max_header_bytes: 64,
+        max_headers: 2,
+        max_uri_length: 8,
+    };
+
+    assert!(limits.check(b"GET /short HTTP/1.1\r\nHost: a\r\n").is_ok());
+    assert!(matches!(
+        limits.check(b"GET /much-too-long"),
+        Err(ParseError::UriTooLong)
+    ));
+    assert!(matches!(
+        limits.check(b"GET / HTTP/1.1\r\nHost: a\r\nA: 1\r\nB: 2\r\nC: 3\r\n"),
+        Err(ParseError::HeadersTooLarge)
+    ));
+    assert!(matches!(
+        limits.check(format!("GET / HTTP/1.1\r\nHost: a\r\nX: {}", "a".repeat(64)).as_bytes()),
+        Err(ParseError::HeadersTooLarge)
+    ));
+
+    // The body does not count towards the head
+    let body = "b".repeat(100);
+    let req = format!(
+        "POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 100\r\n\r\n{}",
+        body
+    );
+    assert!(limits.check(req.as_bytes()).is_ok());
+}
+
+#[tokio::test]
+async fn test_connection_refuses_heads_over_limits() {
+    let limits = HeadLimits {
+        max_header_bytes: 1024,
+        max_headers: 4,
+        max_uri_length: 16,
+    };
+    let cases = [
+        (
+            "GET /a-very-long-request-target HTTP/1.1\r\nHost: a\r\n\r\n".to_string(),
+            "HTTP/1.1 414 URI Too Long\r\n",
+        ),
+        (
+            format!("GET / HTTP/1.1\r\nHost: a\r\n{}\r\n", "X: 1\r\n".repeat(5)),
+            "HTTP/1.1 431 Request Header Fields Too Large\r\n",
+        ),
+        (
+            format!(
+                "GET / HTTP/1.1\r\nHost: a\r\nX: {}\r\n\r\n",
+                "a".repeat(2048)
+            ),
+            "HTTP/1.1 431 Request Header Fields Too Large\r\n",
+        ),
+    ];
+
+    for (request, status_line) in cases {
+        let handler = Arc::new(handler_fn(|_| async move {
+            Response::new(StatusCode::Ok).build()
+        }));
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let conn = tokio::spawn(async move {
+            Connection::with_handler(server, handler)
+                .with_head_limits(limits)
+                .run()
+                .await
+        });
+
+        client.write_all(request.as_bytes()).await.unwrap();
+        let mut output = Vec::new();
+        client.read_to_end(&mut output).await.unwrap();
+        conn.await.unwrap().unwrap();
+
+        assert!(String::from_utf8(output).unwrap().starts_with(status_line));
+    }
+}
+
+#[test]
+fn test_lenient_mode_accepts_lf_only_and_folded_headers() {
+    let req =
+        b"POST /legacy HTTP/1.0\nHost: a\nX-Note: first\n\t second\r\nContent-Length: 4\n\nbodyGET";
+    assert!(parse_http_request(req).is_err());
+
+    let (parsed, consumed) = parse_http_request_with_mode(req, ParseMode::Lenient).unwrap();
+    assert_eq!(parsed.path, "/legacy");
+    assert_eq!(parsed.headers.get("Host").unwrap(), "a");
+    assert_eq!(parsed.headers.get("X-Note").unwrap(), "first second");
+    assert_eq!(parsed.body, b"body");
+    assert_eq!(consumed, req.len() - 3);
+
+    let req = b"GET / HTTP/1.1\r\nHost: a\r\n\r\n";
+    let (_, consumed) = parse_http_request_with_mode(req, ParseMode::Lenient).unwrap();
+    assert_eq!(consumed, req.len());
+}
+
+#[test]
+fn test_lenient_mode_still_rejects_smuggling_forms() {
+    for req in [
+        &b"GET / HTTP/1.1\n continued\n\n"[..],
+        b"GET / HTTP/1.1\nX-Value: a\rb\n\n",
+        b"GET / HTTP/1.1\nHost : a\n\n",
+    ] {
+        assert!(matches!(
+            parse_http_request_with_mode(req, ParseMode::Lenient),
+            Err(ParseError::InvalidHeader)
+        ));
+    }
+
+    let req = b"POST / HTTP/1.1\nContent-Length: 4\nTransfer-Encoding: chunked\n\n";
+    assert!(matches!(
+        parse_http_request_with_mode(req, ParseMode::Lenient),
+        Err(ParseError::ConflictingLength)
+    ));
+    assert!(matches!(
+        parse_http_request_with_mode(b"GET / HTTP/1.1\nHost: a\n", ParseMode::Lenient),
+        Err(ParseError::Incomplete)
+    ));
+}
+
+#[test]
+fn test_parse_requires_valid_host_on_http11() {
+    for req in [
+        &b"GET / HTTP/1.1\r\n\r\n"[..],
+        b"GET / HTTP/1.1\r\nHost: a\r\nhost: b\r\n\r\n",
+        b"GET / HTTP/1.1\r\nHost: a/b\r\n\r\n",
+        b"GET / HTTP/1.1\r\nHost: a:80x\r\n\r\n",
+        b"GET / HTTP/1.1\r\nHost: [::1\r\n\r\n",
+        b"GET / HTTP/1.0\r\nHost: user@a\r\n\r\n",
+    ] {
+        assert!(matches!(
+            parse_http_request(req),
+            Err(ParseError::InvalidHost)
+        ));
+    }
+
+    for req in [
+        &b"GET / HTTP/1.0\r\n\r\n"[..],
+        b"GET / HTTP/1.1\r\nHost:\r\n\r\n",
+        b"GET / HTTP/1.1\r\nhost: Example.com:8080\r\n\r\n",
+        b"GET / HTTP/1.1\r\nHost: [2001:db8::1]:443\r\n\r\n",
+    ] {
+        assert!(parse_http_request(req).is_ok());
+    }
+
+    let (parsed, _) = parse_http_request(b"GET / HTTP/1.1\r\nhost: a:1\r\n\r\n").unwrap();
+    assert_eq!(parsed.host(), Some("a:1"));
+    let (parsed, _) = parse_http_request(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+    assert_eq!(parsed.host(), None);
+}
+
+#[tokio::test]
+async fn test_connection_answers_missing_host_with_400() {
+    let handler = Arc::new(handler_fn(|_| async move {
+        Response::new(StatusCode::Ok).build()
+    }));
+    let response = send_request(handler, b"GET / HTTP/1.1\r\n\r\n").await;
+
+    assert_eq!(response.status, 400);
+}
+
+#[test]
+fn test_request_parser_resumes_across_reads() {
+    let raw = b"POST /items?id=7 HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhello";
+    let mut parser = RequestParser::new(ParseMode::Strict, HeadLimits::default());
+
+    // Fed a byte at a time, the request completes exactly when its body does
+    let mut buf = Vec::new();
+    let mut parsed = None;
+    for &byte in raw.iter() {
+        buf.push(byte);
+        match parser.parse(&buf) {
+            Ok(result) => {
+                parsed = Some(result);
+                break;
+            }
+            Err(ParseError::Incomplete) => {}
+            Err(e) => panic!("unexpected {:?}", e),
+        }
+    }
+
+    let (request, consumed) = parsed.unwrap();
+    assert_eq!(request.path, "/items?id=7");
+    assert_eq!(request.body, b"hello");
+    assert_eq!(consumed, raw.len());
+
+    // The parser starts over for the next request
+    buf.drain(..consumed);
+    buf.extend_from_slice(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n");
+    let (request, _) = parser.parse(&buf).unwrap();
+    assert_eq!(request.method, Method::GET);
+}
+
+#[test]
+fn test_request_parser_errors_are_sticky() {
+    let limits = HeadLimits {
+        max_uri_length: 4,
+        ..HeadLimits::default()
+    };
+    let mut parser = RequestParser::new(ParseMode::Strict, limits);
+    let buf = b"GET /too-long";
+
+    assert!(matches!(parser.head(buf), Err(ParseError::UriTooLong)));
+    assert!(matches!(parser.parse(buf), Err(ParseError::UriTooLong)));
+}
@@ -0,0 +1,256 @@
+//! Tests for policy engine authorization
+
+use sentinel::config::{OpaConfig, PolicyConfig};
+use sentinel::http::handler::{Handler, handler_fn};
+use sentinel::http::request::{Method, Request, RequestBuilder};
+use sentinel::http::response::Response;
+use sentinel::metrics::{Metrics, PrometheusRecorder};
+use sentinel::middleware::PolicyHandler;
+use sentinel::middleware::policy::{OpaEngine, policy_input};
+use serde_json::{Value, json};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Serve an OPA data API answering each input with `decide(input)`
+async fn opa<F>(decide: F) -> String
+where
+    F: Fn(&Value) -> (u16, String) + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!(
+        "http://{}/v1/data/sentinel/authz",
+        listener.local_addr().unwrap()
+    );
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut raw = Vec::new();
+            let mut buf = [0; 4096];
+            let body = loop {
+                let n = socket.read(&mut buf).await.unwrap();
+                raw.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&raw).to_string();
+                if let Some(end) = text.find("\r\n\r\n") {
+                    assert!(
+                        text.starts_with("POST /v1/data/sentinel/authz HTTP/1.1"),
+                        "{}",
+                        text
+                    );
+                    let length: usize = text
+                        .lines()
+                        .find_map(|l| l.strip_prefix("Content-Length: "))
+                        .unwrap()
+                        .parse()
+                        .unwrap();
+                    if raw.len() >= end + 4 + length {
+                        break raw[end + 4..end + 4 + length].to_vec();
+                    }
+                }
+            };
+            let query: Value = serde_json::from_slice(&body).unwrap();
+            let (status, answer) = decide(&query["input"]);
+            let response = format!(
+                "HTTP/1.1 {} OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                answer.len(),
+                answer
+            );
+            let _ = socket.write_all(response.as_bytes()).await;
+        }
+    });
+    url
+}
+
+fn request(method: Method, path: &str) -> Request {
+    let mut req = RequestBuilder::new()
+        .method(method)
+        .path(path)
+        .header("User-Agent", "curl/8.0")
+        .build()
+        .unwrap();
+    req.context.peer = Some("10.0.0.7:4000".parse::<SocketAddr>().unwrap());
+    req
+}
+
+fn policy(url: &str) -> PolicyConfig {
+    PolicyConfig {
+        opa: Some(OpaConfig {
+            url: url.to_string(),
+            timeout_ms: 500,
+        }),
+        cedar: None,
+        fail_open: false,
+    }
+}
+
+fn origin() -> impl Handler {
+    handler_fn(|_req| async { Response::ok(b"origin".to_vec()) })
+}
+
+#[test]
+fn test_policy_input_describes_request() {
+    let input = policy_input(&request(Method::POST, "/orders?expand=items"));
+    assert_eq!(
+        input,
+        json!({
+            "method": "POST",
+            "path": "/orders",
+            "query": "expand=items",
+            "headers": {"user-agent": "curl/8.0"},
+            "client": {"ip": "10.0.0.7"},
+        })
+    );
+
+    // Unknown attributes are left out rather than null
+    let req = RequestBuilder::new()
+        .method(Method::GET)
+        .path("/")
+        .build()
+        .unwrap();
+    assert_eq!(
+        policy_input(&req),
+        json!({"method": "GET", "path": "/", "headers": {}})
+    );
+}
+
+#[tokio::test]
+async fn test_opa_allows_and_denies_with_reason() {
+    let url = opa(|input| {
+        let allow = input["method"] == "GET";
+        (
+            200,
+            json!({"result": {"allow": allow, "reason": "writes are disabled"}}).to_string(),
+        )
+    })
+    .await;
+    let recorder = Arc::new(PrometheusRecorder::new());
+    let handler = PolicyHandler::from_config(origin(), &policy(&url))
+        .unwrap()
+        .with_metrics(Metrics::new(recorder.clone()));
+    assert_eq!(handler.engine(), "opa");
+
+    let response = handler.handle(request(Method::GET, "/orders")).await;
+    assert_eq!(response.body, b"origin");
+    let response = handler.handle(request(Method::POST, "/orders")).await;
+    assert_eq!(response.status.as_u16(), 403);
+
+    let output = recorder.render();
+    assert!(output.contains(r#"sentinel_policy_decisions_total{decision="allow",engine="opa"} 1"#));
+    assert!(output.contains(r#"sentinel_policy_decisions_total{decision="deny",engine="opa"} 1"#));
+}
+
+#[tokio::test]
+async fn test_opa_boolean_and_undefined_results() {
+    let url = opa(|input| match input["path"].as_str().unwrap() {
+        "/public" => (200, r#"{"result": true}"#.to_string()),
+        "/private" => (200, r#"{"result": false}"#.to_string()),
+        _ => (200, "{}".to_string()),
+    })
+    .await;
+    let engine = OpaEngine::new(&policy(&url).opa.unwrap()).unwrap();
+    let handler = PolicyHandler::new(origin(), engine);
+
+    let response = handler.handle(request(Method::GET, "/public")).await;
+    assert_eq!(response.status.as_u16(), 200);
+    let response = handler.handle(request(Method::GET, "/private")).await;
+    assert_eq!(response.status.as_u16(), 403);
+    let response = handler.handle(request(Method::GET, "/unknown")).await;
+    assert_eq!(response.status.as_u16(), 403);
+}
+
+#[tokio::test]
+async fn test_engine_failures_fail_closed_unless_open() {
+    let url = opa(|_| (500, "{}".to_string())).await;
+    let recorder = Arc::new(PrometheusRecorder::new());
+    let closed = PolicyHandler::from_config(origin(), &policy(&url))
+        .unwrap()
+        .with_metrics(Metrics::new(recorder.clone()));
+    let response = closed.handle(request(Method::GET, "/")).await;
+    assert_eq!(response.status.as_u16(), 503);
+    assert!(
+        recorder
+            .render()
+            .contains(r#"sentinel_policy_decisions_total{decision="error",engine="opa"} 1"#)
+    );
+
+    // An unreachable engine fails the same way
+    let unreachable = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/v1/data/authz", unreachable.local_addr().unwrap());
+    drop(unreachable);
+    let mut config = policy(&url);
+    config.fail_open = true;
+    let open = PolicyHandler::from_config(origin(), &config).unwrap();
+    let response = open.handle(request(Method::GET, "/")).await;
+    assert_eq!(response.body, b"origin");
+}
+
+#[test]
+fn test_policy_config_validation() {
+    assert!(
+        policy("http://127.0.0.1:8181/v1/data/authz")
+            .validate()
+            .is_ok()
+    );
+    assert!(policy("https://opa:8181/v1/data/authz").validate().is_err());
+    assert!(policy("not a url").validate().is_err());
+    assert!(PolicyConfig::default().validate().is_err());
+
+    let cedar: PolicyConfig =
+        serde_yaml::from_str("cedar: {policies_file: policies.cedar}").unwrap();
+    assert_eq!(cedar.validate().is_ok(), cfg!(feature = "cedar"));
+
+    let both: PolicyConfig = serde_yaml::from_str(
+        "{opa: {url: 'http://127.0.0.1:8181/v1/data/authz'}, cedar: {policies_file: p.cedar}}",
+    )
+    .unwrap();
+    assert!(both.validate().is_err());
+}
+
+#[cfg(feature = "cedar")]
+#[tokio::test]
+async fn test_cedar_policies() {
+    use sentinel::config::CedarConfig;
+
+    let dir = std::env::temp_dir().join(format!("sentinel-cedar-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let policies_file = dir.join("policies.cedar");
+    std::fs::write(
+        &policies_file,
+        r#"
+permit(principal, action == Action::"GET", resource);
+
+@reason("scripts may not read admin pages")
+forbid(principal, action, resource == Path::"/admin")
+when { context.headers["user-agent"] like "curl/*" };
+
+permit(principal == Client::"10.0.0.7", action == Action::"POST", resource);
+"#,
+    )
+    .unwrap();
+    let config = PolicyConfig {
+        opa: None,
+        cedar: Some(CedarConfig {
+            policies_file,
+            entities_file: None,
+        }),
+        fail_open: false,
+    };
+    let handler = PolicyHandler::from_config(origin(), &config).unwrap();
+    assert_eq!(handler.engine(), "cedar");
+
+    let response = handler.handle(request(Method::GET, "/orders")).await;
+    assert_eq!(response.status.as_u16(), 200);
+    let response = handler.handle(request(Method::GET, "/admin")).await;
+    assert_eq!(response.status.as_u16(), 403);
+    let response = handler.handle(request(Method::POST, "/orders")).await;
+    assert_eq!(response.status.as_u16(), 200);
+
+    let mut other = request(Method::POST, "/orders");
+    other.context.peer = Some("10.0.0.8:4000".parse().unwrap());
+    let response = handler.handle(other).await;
+    assert_eq!(response.status.as_u16(), 403);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
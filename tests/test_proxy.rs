@@ -1,9 +1,24 @@
 //! Tests for proxy upstream request handling
 
+use sentinel::config::{
+    BackendConfig, CircuitBreakerConfig, HashKey, LoadBalancing, LoadFeedbackConfig,
+    LocationRewrite, ProxyConfig, RetryAfterConfig, RetryBackoffConfig, RetryPolicy, RouteTimeouts,
+    RoutingRule, UpstreamKeepaliveConfig,
+};
+use sentinel::http::connection::Connection;
+use sentinel::http::handler::Handler;
 use sentinel::http::request::{Method, RequestBuilder};
+use sentinel::http::writer::ResponseWriter;
 use sentinel::proxy::backend::BackendPool;
-use sentinel::proxy::upstream::ProxyHandler;
+use sentinel::proxy::upstream::{ProxyHandler, UpstreamTimeouts};
+use sentinel::testing::{
+    MockAction, MockBackend, MockResponse, TEST_CONNECT_TIMEOUT, TEST_REQUEST_TIMEOUT,
+    backend_config, proxy_handler, send_request,
+};
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
 
 #[test]
 fn test_build_http_request() {
@@ -108,3 +123,1084 @@ fn test_build_http_request_default_path() {
     // Empty path should default to "/"
     assert!(request_str.contains("GET / HTTP/1.1"));
 }
+
+fn labelled(backend: &MockBackend, key: &str, value: &str) -> BackendConfig {
+    BackendConfig {
+        labels: [(key.to_string(), value.to_string())].into(),
+        ..backend_config(backend)
+    }
+}
+
+fn canary_rule(fallback: bool) -> RoutingRule {
+    RoutingRule {
+        path_prefix: Some("/api".to_string()),
+        headers: [("X-Canary".to_string(), "1".to_string())].into(),
+        backend_labels: [("canary".to_string(), "true".to_string())].into(),
+        fallback,
+        percent: None,
+    }
+}
+
+const CANARY_GET: &[u8] =
+    b"GET /api/items HTTP/1.1\r\nHost: a\r\nx-canary: 1\r\nConnection: close\r\n\r\n";
+
+#[tokio::test]
+async fn test_routing_rule_selects_labelled_backends() {
+    let stable = MockBackend::start().await;
+    stable.set_default(MockAction::Respond(MockResponse::new(200).body("stable")));
+    let canary = MockBackend::start().await;
+    canary.set_default(MockAction::Respond(MockResponse::new(200).body("canary")));
+
+    let pool = BackendPool::new(vec![
+        labelled(&stable, "canary", "false"),
+        labelled(&canary, "canary", "true"),
+    ]);
+    let handler: Arc<dyn Handler> = Arc::new(
+        ProxyHandler::new(pool, TEST_CONNECT_TIMEOUT, TEST_REQUEST_TIMEOUT)
+            .with_routing_rules(vec![canary_rule(true)]),
+    );
+
+    for _ in 0..3 {
+        let response = send_request(handler.clone(), CANARY_GET).await;
+        assert_eq!(response.text(), "canary");
+    }
+
+    // Without the header both backends take turns
+    let mut bodies = Vec::new();
+    for _ in 0..2 {
+        let response = send_request(
+            handler.clone(),
+            b"GET /api/items HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        bodies.push(response.text());
+    }
+    bodies.sort();
+    assert_eq!(bodies, vec!["canary", "stable"]);
+}
+
+#[tokio::test]
+async fn test_routing_rule_splits_traffic_by_label() {
+    let stable = MockBackend::start().await;
+    stable.set_default(MockAction::Respond(MockResponse::new(200).body("v1")));
+    let next = MockBackend::start().await;
+    next.set_default(MockAction::Respond(MockResponse::new(200).body("v2")));
+
+    let pool = BackendPool::new(vec![
+        labelled(&stable, "version", "1"),
+        labelled(&next, "version", "2"),
+    ]);
+    let split = |percent| RoutingRule {
+        path_prefix: None,
+        headers: Default::default(),
+        backend_labels: [("version".to_string(), "2".to_string())].into(),
+        fallback: true,
+        percent: Some(percent),
+    };
+    let count_v2 = |handler: Arc<dyn Handler>| async move {
+        let mut v2 = 0;
+        for _ in 0..20 {
+            if send_request(handler.clone(), CANARY_GET).await.text() == "v2" {
+                v2 += 1;
+            }
+        }
+        v2
+    };
+
+    let none: Arc<dyn Handler> = Arc::new(
+        ProxyHandler::new(pool.clone(), TEST_CONNECT_TIMEOUT, TEST_REQUEST_TIMEOUT)
+            .with_routing_rules(vec![split(0)]),
+    );
+    assert_eq!(count_v2(none).await, 0);
+
+    let all: Arc<dyn Handler> = Arc::new(
+        ProxyHandler::new(pool, TEST_CONNECT_TIMEOUT, TEST_REQUEST_TIMEOUT)
+            .with_routing_rules(vec![split(100)]),
+    );
+    assert_eq!(count_v2(all).await, 20);
+
+    let config: ProxyConfig = serde_yaml::from_str(
+        "backends: []\nrouting_rules: [{backend_labels: {version: '2'}, percent: 101}]",
+    )
+    .unwrap();
+    assert!(config.validate().is_err());
+}
+
+#[tokio::test]
+async fn test_consistent_hash_by_cookie() {
+    let backends = [
+        MockBackend::start().await,
+        MockBackend::start().await,
+        MockBackend::start().await,
+    ];
+    for (i, backend) in backends.iter().enumerate() {
+        backend.set_default(MockAction::Respond(
+            MockResponse::new(200).body(format!("backend-{}", i)),
+        ));
+    }
+    let pool = BackendPool::new(backends.iter().map(backend_config).collect())
+        .with_balancing(LoadBalancing::ConsistentHash);
+    let handler: Arc<dyn Handler> = Arc::new(
+        ProxyHandler::new(pool, TEST_CONNECT_TIMEOUT, TEST_REQUEST_TIMEOUT)
+            .with_hash_key(HashKey::Cookie("session".to_string())),
+    );
+
+    for session in ["alice", "bob", "carol"] {
+        let raw = format!(
+            "GET /api/items?page={{}} HTTP/1.1\r\nHost: a\r\nCookie: theme=dark; session={}\r\nConnection: close\r\n\r\n",
+            session
+        );
+        let mut bodies = Vec::new();
+        for page in 0..3 {
+            let raw = raw.replace("{}", &page.to_string());
+            bodies.push(send_request(handler.clone(), raw.as_bytes()).await.text());
+        }
+        assert!(bodies.iter().all(|b| *b == bodies[0]), "{:?}", bodies);
+    }
+
+    // Without the cookie requests are spread round-robin
+    let mut bodies = Vec::new();
+    for _ in 0..3 {
+        let response = send_request(
+            handler.clone(),
+            b"GET /api/items HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        bodies.push(response.text());
+    }
+    bodies.sort();
+    assert_eq!(bodies, vec!["backend-0", "backend-1", "backend-2"]);
+}
+
+#[tokio::test]
+async fn test_forwarding_records_backend_latency() {
+    let backend = MockBackend::start().await;
+    backend.set_default(MockAction::Respond(
+        MockResponse::new(200).delay(Duration::from_millis(20)),
+    ));
+    let pool = BackendPool::new(vec![backend_config(&backend)]);
+    let handler: Arc<dyn Handler> = Arc::new(ProxyHandler::new(
+        pool.clone(),
+        TEST_CONNECT_TIMEOUT,
+        TEST_REQUEST_TIMEOUT,
+    ));
+
+    assert!(pool.get_backends().await[0].latency_ms().is_none());
+    send_request(
+        handler,
+        b"GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert!(pool.get_backends().await[0].latency_ms().unwrap() >= 20.0);
+}
+
+#[tokio::test]
+async fn test_routing_rule_without_fallback_fails_when_no_match() {
+    let stable = MockBackend::start().await;
+    let pool = BackendPool::new(vec![labelled(&stable, "canary", "false")]);
+
+    let strict: Arc<dyn Handler> = Arc::new(
+        ProxyHandler::new(pool.clone(), TEST_CONNECT_TIMEOUT, TEST_REQUEST_TIMEOUT)
+            .with_routing_rules(vec![canary_rule(false)]),
+    );
+    let response = send_request(strict, CANARY_GET).await;
+    assert!(response.status >= 500);
+    assert_eq!(stable.request_count(), 0);
+
+    let lenient: Arc<dyn Handler> = Arc::new(
+        ProxyHandler::new(pool, TEST_CONNECT_TIMEOUT, TEST_REQUEST_TIMEOUT)
+            .with_routing_rules(vec![canary_rule(true)]),
+    );
+    let response = send_request(lenient, CANARY_GET).await;
+    assert_eq!(response.status, 200);
+    assert_eq!(stable.request_count(), 1);
+}
+
+#[tokio::test]
+async fn test_load_report_header_updates_pool() {
+    let backend = MockBackend::start().await;
+    backend.set_default(MockAction::Respond(
+        MockResponse::new(200)
+            .header(
+                "Endpoint-Load-Metrics",
+                "TEXT mem_utilization=0.1, cpu_utilization=0.7",
+            )
+            .body("ok"),
+    ));
+
+    let feedback = LoadFeedbackConfig {
+        smoothing: 1.0,
+        ..Default::default()
+    };
+    let pool =
+        BackendPool::new(vec![backend_config(&backend)]).with_load_feedback(feedback.clone());
+    let handler: Arc<dyn Handler> = Arc::new(
+        ProxyHandler::new(pool.clone(), TEST_CONNECT_TIMEOUT, TEST_REQUEST_TIMEOUT)
+            .with_load_feedback(feedback),
+    );
+
+    let response = send_request(
+        handler,
+        b"GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+
+    assert_eq!(response.status, 200);
+    assert!(response.header("endpoint-load-metrics").is_none());
+    assert_eq!(pool.get_backends().await[0].load(), Some(0.7));
+}
+
+fn route_timeouts(
+    prefix: &str,
+    first_byte_ms: Option<u64>,
+    request_ms: Option<u64>,
+) -> RouteTimeouts {
+    RouteTimeouts {
+        path_prefix: prefix.to_string(),
+        connection_timeout_ms: None,
+        request_timeout_ms: request_ms,
+        first_byte_timeout_ms: first_byte_ms,
+        idle_timeout_ms: None,
+        stream: false,
+    }
+}
+
+#[test]
+fn test_route_timeouts_use_longest_matching_prefix() {
+    let handler = ProxyHandler::new(
+        BackendPool::new(Vec::new()),
+        Duration::from_secs(1),
+        Duration::from_secs(30),
+    )
+    .with_idle_timeout(Duration::from_secs(2))
+    .with_route_timeouts(vec![
+        route_timeouts("/api", Some(500), None),
+        route_timeouts("/api/export", None, Some(0)),
+    ]);
+    let timeouts_for = |path: &str| {
+        let request = RequestBuilder::new()
+            .method(Method::GET)
+            .path(path)
+            .build()
+            .unwrap();
+        handler.timeouts_for(&request)
+    };
+
+    let defaults = UpstreamTimeouts {
+        connect: Duration::from_secs(1),
+        total: Some(Duration::from_secs(30)),
+        first_byte: None,
+        idle: Some(Duration::from_secs(2)),
+    };
+    assert_eq!(timeouts_for("/static/app.js"), defaults);
+    assert_eq!(
+        timeouts_for("/api/items?page=2"),
+        UpstreamTimeouts {
+            first_byte: Some(Duration::from_millis(500)),
+            ..defaults
+        }
+    );
+    // Only the longest prefix applies, and 0 lifts the overall limit
+    assert_eq!(
+        timeouts_for("/api/export/all"),
+        UpstreamTimeouts {
+            total: None,
+            ..defaults
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_first_byte_timeout_with_route_override() {
+    let backend = MockBackend::start().await;
+    backend.set_default(MockAction::Respond(
+        MockResponse::new(200)
+            .body("slow")
+            .delay(Duration::from_millis(300)),
+    ));
+
+    let pool = BackendPool::new(vec![backend_config(&backend)]);
+    let handler: Arc<dyn Handler> = Arc::new(
+        ProxyHandler::new(pool, TEST_CONNECT_TIMEOUT, TEST_REQUEST_TIMEOUT)
+            .with_first_byte_timeout(Duration::from_millis(50))
+            .with_route_timeouts(vec![route_timeouts("/reports", Some(2000), None)]),
+    );
+
+    let response = send_request(
+        handler.clone(),
+        b"GET /api HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert_eq!(response.status, 504);
+
+    let response = send_request(
+        handler,
+        b"GET /reports/daily HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "slow");
+}
+
+#[tokio::test]
+async fn test_idle_timeout_fails_stalled_body() {
+    // Sends half the body and then stalls without closing
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut buf = [0u8; 1024];
+                let _ = socket.read(&mut buf).await;
+                let _ = socket
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello")
+                    .await;
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            });
+        }
+    });
+
+    let pool = BackendPool::new(vec![BackendConfig {
+        url: format!("http://{}", addr),
+        ..Default::default()
+    }]);
+    let handler: Arc<dyn Handler> = Arc::new(
+        ProxyHandler::new(pool, TEST_CONNECT_TIMEOUT, Duration::ZERO)
+            .with_idle_timeout(Duration::from_millis(100)),
+    );
+
+    let started = std::time::Instant::now();
+    let response = send_request(
+        handler,
+        b"GET /download HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert_eq!(response.status, 504);
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[tokio::test]
+async fn test_via_is_added_and_loops_are_refused() {
+    let backend = MockBackend::start().await;
+    backend.set_default(MockAction::Respond(
+        MockResponse::new(200)
+            .reason("OK")
+            .header("Via", "1.0 cache")
+            .body("ok"),
+    ));
+    let handler = proxy_handler(&[&backend]);
+    let request = |via: Option<&str>| {
+        let mut builder = RequestBuilder::new()
+            .method(Method::GET)
+            .path("/")
+            .version("HTTP/1.1");
+        if let Some(via) = via {
+            builder = builder.header("via", via);
+        }
+        builder.build().unwrap()
+    };
+
+    let response = handler.handle(request(Some("1.1 edge"))).await;
+    assert_eq!(response.status.as_u16(), 200);
+    assert_eq!(response.headers["Via"], "1.0 cache, 1.1 sentinel");
+    assert_eq!(
+        backend.requests()[0].header("Via"),
+        Some("1.1 edge, 1.1 sentinel")
+    );
+
+    // A request that already passed through is not forwarded again
+    let response = handler
+        .handle(request(Some("1.1 edge, 1.1 Sentinel (v1)")))
+        .await;
+    assert_eq!(response.status.as_u16(), 508);
+    assert!(response.generated);
+    assert_eq!(backend.request_count(), 1);
+
+    // Chained instances use different names
+    let inner = proxy_handler(&[&backend]).with_via("sentinel-internal");
+    let response = inner.handle(request(Some("1.1 sentinel"))).await;
+    assert_eq!(response.status.as_u16(), 200);
+    assert_eq!(
+        backend.requests()[1].header("Via"),
+        Some("1.1 sentinel, 1.1 sentinel-internal")
+    );
+}
+
+#[test]
+fn test_via_config() {
+    let config: ProxyConfig = serde_yaml::from_str("backends: []").unwrap();
+    assert_eq!(config.via, "sentinel");
+
+    let config: ProxyConfig =
+        serde_yaml::from_str("backends: [{url: 'http://localhost:3000'}]\nvia: 'edge proxy'")
+            .unwrap();
+    assert!(config.validate().is_err());
+}
+
+#[tokio::test]
+async fn test_backend_status_and_reason_are_preserved() {
+    let backend = MockBackend::start().await;
+    backend.push(MockAction::Respond(
+        MockResponse::new(503).reason("Backend Busy"),
+    ));
+    backend.push(MockAction::Respond(
+        MockResponse::new(301)
+            .reason("Moved Permanently")
+            .header("Location", "/new"),
+    ));
+    backend.push(MockAction::Respond(MockResponse::new(299).reason("Custom")));
+    backend.push(MockAction::Respond(MockResponse::new(999)));
+    let handler = proxy_handler(&[&backend]);
+
+    let mut seen = Vec::new();
+    for _ in 0..4 {
+        let request = RequestBuilder::new()
+            .method(Method::GET)
+            .path("/")
+            .version("HTTP/1.1")
+            .build()
+            .unwrap();
+        let response = handler.handle(request).await;
+        seen.push((
+            response.status.as_u16(),
+            response.reason.clone(),
+            String::from_utf8_lossy(ResponseWriter::new(&response).serialize())
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+        ));
+    }
+
+    assert_eq!(
+        seen[0],
+        (
+            503,
+            Some("Backend Busy".to_string()),
+            "HTTP/1.1 503 Backend Busy".to_string()
+        )
+    );
+    assert_eq!(seen[1].0, 301);
+    assert_eq!(seen[2].2, "HTTP/1.1 299 Custom");
+    // Not a status code at all
+    assert_eq!(seen[3].0, 502);
+    assert_eq!(seen[3].1, None);
+}
+
+#[tokio::test]
+async fn test_backend_cookies_are_all_passed_on() {
+    let backend = MockBackend::start().await;
+    backend.push(MockAction::Respond(
+        MockResponse::new(200)
+            .header("Set-Cookie", "session=abc; Path=/")
+            .header("set-cookie", "theme=dark"),
+    ));
+    let handler = proxy_handler(&[&backend]);
+
+    let request = RequestBuilder::new()
+        .method(Method::GET)
+        .path("/")
+        .version("HTTP/1.1")
+        .build()
+        .unwrap();
+    let response = handler.handle(request).await;
+
+    assert_eq!(response.status.as_u16(), 200);
+    let written = String::from_utf8_lossy(ResponseWriter::new(&response).serialize()).to_string();
+    assert!(
+        written.contains("Set-Cookie: session=abc; Path=/\r\n"),
+        "{}",
+        written
+    );
+    assert!(
+        written.contains("Set-Cookie: theme=dark\r\n"),
+        "{}",
+        written
+    );
+}
+
+#[tokio::test]
+async fn test_event_stream_is_passed_through() {
+    // Sends one event, then waits to be told to send the rest
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let resume = Arc::new(tokio::sync::Notify::new());
+    let backend_resume = resume.clone();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut buf = [0u8; 1024];
+        let _ = socket.read(&mut buf).await;
+        socket
+            .write_all(
+                b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\
+                  Transfer-Encoding: chunked\r\n\r\nb\r\ndata: one\n\n\r\n",
+            )
+            .await
+            .unwrap();
+        backend_resume.notified().await;
+        socket
+            .write_all(b"b\r\ndata: two\n\n\r\n0\r\n\r\n")
+            .await
+            .unwrap();
+    });
+
+    let pool = BackendPool::new(vec![BackendConfig {
+        url: format!("http://{}", addr),
+        ..Default::default()
+    }]);
+    // The stream outlives both limits
+    let handler: Arc<dyn Handler> = Arc::new(
+        ProxyHandler::new(pool, TEST_CONNECT_TIMEOUT, Duration::from_millis(200))
+            .with_idle_timeout(Duration::from_millis(100)),
+    );
+
+    let (mut client, server) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move {
+        let _ = Connection::with_handler(server, handler).run().await;
+    });
+    client
+        .write_all(b"GET /events HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+
+    // The first event arrives while the backend is still holding the rest
+    let mut received = Vec::new();
+    tokio::time::timeout(Duration::from_secs(2), async {
+        let mut buf = [0u8; 1024];
+        while !String::from_utf8_lossy(&received).contains("data: one") {
+            let n = client.read(&mut buf).await.unwrap();
+            assert!(n > 0, "connection closed before the first event");
+            received.extend_from_slice(&buf[..n]);
+        }
+    })
+    .await
+    .expect("first event was buffered");
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    resume.notify_one();
+    client.read_to_end(&mut received).await.unwrap();
+
+    let received = String::from_utf8(received).unwrap();
+    assert!(received.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(received.contains("Transfer-Encoding: chunked\r\n"));
+    assert!(!received.contains("Content-Length"));
+    assert!(received.ends_with("b\r\ndata: one\n\n\r\nb\r\ndata: two\n\n\r\n0\r\n\r\n"));
+}
+
+#[tokio::test]
+async fn test_stream_route_lifts_request_timeout() {
+    let backend = MockBackend::start().await;
+    backend.set_default(MockAction::Respond(
+        MockResponse::new(200)
+            .body("update")
+            .delay(Duration::from_millis(300)),
+    ));
+
+    let pool = BackendPool::new(vec![backend_config(&backend)]);
+    let handler: Arc<dyn Handler> = Arc::new(
+        ProxyHandler::new(pool, TEST_CONNECT_TIMEOUT, Duration::from_millis(100))
+            .with_route_timeouts(vec![RouteTimeouts {
+                stream: true,
+                ..route_timeouts("/poll", None, None)
+            }]),
+    );
+
+    let response = send_request(
+        handler.clone(),
+        b"GET /poll/updates HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "update");
+
+    let response = send_request(
+        handler,
+        b"GET /other HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert_eq!(response.status, 504);
+}
+
+#[test]
+fn test_config_route_timeouts() {
+    let config: sentinel::config::ProxyConfig = serde_yaml::from_str(
+        r#"
+backends: [{ url: "http://10.0.0.1:8080" }]
+idle_timeout_ms: 10000
+route_timeouts:
+  - path_prefix: /downloads
+    request_timeout_ms: 0
+"#,
+    )
+    .unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(config.idle_timeout_ms, Some(10000));
+    assert_eq!(config.first_byte_timeout_ms, None);
+    assert_eq!(config.route_timeouts[0].request_timeout_ms, Some(0));
+
+    let invalid: sentinel::config::ProxyConfig = serde_yaml::from_str(
+        r#"
+backends: [{ url: "http://10.0.0.1:8080" }]
+route_timeouts:
+  - path_prefix: downloads
+"#,
+    )
+    .unwrap();
+    assert!(invalid.validate().is_err());
+}
+
+fn location_rewrite(prefix: &str, from: Option<&str>, to: Option<&str>) -> LocationRewrite {
+    LocationRewrite {
+        path_prefix: prefix.to_string(),
+        from: from.map(str::to_string),
+        to: to.map(str::to_string),
+    }
+}
+
+#[tokio::test]
+async fn test_location_rewritten_to_client_origin() {
+    let backend = MockBackend::start().await;
+    backend.set_default(MockAction::Respond(
+        MockResponse::new(302)
+            .header(
+                "Location",
+                format!("http://{}/login?next=%2F", backend.addr()),
+            )
+            .header("Content-Location", "http://elsewhere.example/x"),
+    ));
+
+    let pool = BackendPool::new(vec![backend_config(&backend)]);
+    let handler: Arc<dyn Handler> = Arc::new(
+        ProxyHandler::new(pool, TEST_CONNECT_TIMEOUT, TEST_REQUEST_TIMEOUT)
+            .with_location_rewrites(vec![location_rewrite("/app", None, None)]),
+    );
+
+    let response = send_request(
+        handler.clone(),
+        b"GET /app HTTP/1.1\r\nHost: shop.example.com\r\nX-Forwarded-Proto: https\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert_eq!(
+        response.header("Location"),
+        Some("https://shop.example.com/login?next=%2F")
+    );
+    // Other origins are left alone
+    assert_eq!(
+        response.header("Content-Location"),
+        Some("http://elsewhere.example/x")
+    );
+
+    // No rule for this path
+    let response = send_request(
+        handler,
+        b"GET /other HTTP/1.1\r\nHost: shop.example.com\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert_eq!(
+        response.header("Location"),
+        Some(format!("http://{}/login?next=%2F", backend.addr()).as_str())
+    );
+}
+
+#[tokio::test]
+async fn test_location_rewrite_with_explicit_prefix() {
+    let backend = MockBackend::start().await;
+    backend.set_default(MockAction::Respond(
+        MockResponse::new(301).header("Location", "http://legacy.internal/docs/a"),
+    ));
+
+    let pool = BackendPool::new(vec![backend_config(&backend)]);
+    let handler: Arc<dyn Handler> = Arc::new(
+        ProxyHandler::new(pool, TEST_CONNECT_TIMEOUT, TEST_REQUEST_TIMEOUT).with_location_rewrites(
+            vec![
+                location_rewrite("/", None, None),
+                location_rewrite("/legacy", Some("http://legacy.internal/"), Some("/legacy/")),
+            ],
+        ),
+    );
+
+    let response = send_request(
+        handler,
+        b"GET /legacy/docs HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert_eq!(response.header("Location"), Some("/legacy/docs/a"));
+}
+
+#[tokio::test]
+async fn test_unavailable_response_carries_retry_after() {
+    let get = b"GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n";
+    let handler: Arc<dyn Handler> = Arc::new(
+        ProxyHandler::new(
+            BackendPool::new(vec![]),
+            TEST_CONNECT_TIMEOUT,
+            TEST_REQUEST_TIMEOUT,
+        )
+        .with_retry_after(RetryAfterConfig {
+            secs: 10,
+            jitter_secs: 5,
+        }),
+    );
+
+    let response = send_request(handler, get).await;
+    assert_eq!(response.status, 503);
+    assert_eq!(response.header("Content-Type"), Some("application/json"));
+    let secs: u64 = response.header("Retry-After").unwrap().parse().unwrap();
+    assert!((10..=15).contains(&secs));
+
+    let body: serde_json::Value = serde_json::from_str(&response.text()).unwrap();
+    assert_eq!(body["status"], 503);
+    assert_eq!(body["error"], "service_unavailable");
+    assert_eq!(body["retry_after"], secs);
+
+    // Without a hint configured the body is still machine-readable
+    let handler: Arc<dyn Handler> = Arc::new(ProxyHandler::new(
+        BackendPool::new(vec![]),
+        TEST_CONNECT_TIMEOUT,
+        TEST_REQUEST_TIMEOUT,
+    ));
+    let response = send_request(handler, get).await;
+    assert_eq!(response.header("Retry-After"), None);
+    let body: serde_json::Value = serde_json::from_str(&response.text()).unwrap();
+    assert_eq!(body["status"], 503);
+    assert!(body.get("retry_after").is_none());
+}
+
+#[tokio::test]
+async fn test_retry_backoff_between_backends() {
+    let backoff = RetryBackoffConfig {
+        base_ms: 50,
+        multiplier: 2.0,
+        jitter: 0.0,
+        max_ms: 1000,
+        max_total_ms: None,
+    };
+    let failing_pool = || async {
+        let backends = [
+            MockBackend::start().await,
+            MockBackend::start().await,
+            MockBackend::start().await,
+        ];
+        backends[0].push(MockAction::Reset);
+        backends[1].push(MockAction::Reset);
+        backends[2].set_default(MockAction::Respond(MockResponse::new(200).body("ok")));
+        let pool = BackendPool::new(backends.iter().map(backend_config).collect());
+        (backends, pool)
+    };
+    let request = RequestBuilder::new()
+        .method(Method::GET)
+        .path("/")
+        .build()
+        .unwrap();
+
+    // Two failures wait 50ms then 100ms before the third backend answers
+    let (_backends, pool) = failing_pool().await;
+    let proxy = ProxyHandler::new(pool, TEST_CONNECT_TIMEOUT, TEST_REQUEST_TIMEOUT)
+        .with_retry_backoff(backoff.clone());
+    let started = std::time::Instant::now();
+    assert_eq!(proxy.handle(request.clone()).await.status.as_u16(), 200);
+    assert!(started.elapsed() >= Duration::from_millis(150));
+
+    // The second wait would exceed the retry budget
+    let (_backends, pool) = failing_pool().await;
+    let proxy = ProxyHandler::new(pool, TEST_CONNECT_TIMEOUT, TEST_REQUEST_TIMEOUT)
+        .with_retry_backoff(RetryBackoffConfig {
+            max_total_ms: Some(120),
+            ..backoff
+        });
+    assert_eq!(proxy.handle(request).await.status.as_u16(), 502);
+}
+
+#[tokio::test]
+async fn test_retry_policy() {
+    let proxy = |backends: &[MockBackend], policy: RetryPolicy| {
+        ProxyHandler::new(
+            BackendPool::new(backends.iter().map(backend_config).collect()),
+            TEST_CONNECT_TIMEOUT,
+            TEST_REQUEST_TIMEOUT,
+        )
+        .with_retry_policy(policy)
+    };
+    let request = |method| {
+        RequestBuilder::new()
+            .method(method)
+            .path("/")
+            .build()
+            .unwrap()
+    };
+    let backends = || async {
+        let backends = [MockBackend::start().await, MockBackend::start().await];
+        backends[1].set_default(MockAction::Respond(MockResponse::new(200).body("ok")));
+        backends
+    };
+
+    // Only idempotent methods are retried by default
+    let pair = backends().await;
+    pair[0].push(MockAction::Reset);
+    let handler = proxy(&pair, RetryPolicy::default());
+    assert_eq!(
+        handler.handle(request(Method::POST)).await.status.as_u16(),
+        502
+    );
+    assert!(pair[1].requests().is_empty());
+
+    // Listed statuses are retried like connection errors
+    let pair = backends().await;
+    pair[0].push(MockAction::Respond(MockResponse::new(503)));
+    let statuses = RetryPolicy {
+        statuses: vec![503],
+        ..Default::default()
+    };
+    let handler = proxy(&pair, statuses.clone());
+    assert_eq!(
+        handler.handle(request(Method::GET)).await.status.as_u16(),
+        200
+    );
+
+    // ...unless no attempt is left, when the status is passed on
+    let pair = backends().await;
+    pair[0].push(MockAction::Respond(MockResponse::new(503)));
+    let handler = proxy(
+        &pair,
+        RetryPolicy {
+            max_attempts: Some(1),
+            ..statuses
+        },
+    );
+    assert_eq!(
+        handler.handle(request(Method::GET)).await.status.as_u16(),
+        503
+    );
+    assert!(pair[1].requests().is_empty());
+
+    // The budget allows one retry, then none until requests earn more
+    let pair = backends().await;
+    pair[0].set_default(MockAction::Reset);
+    let handler = proxy(
+        &pair,
+        RetryPolicy {
+            budget_percent: 0,
+            budget_burst: 1,
+            ..Default::default()
+        },
+    );
+    assert_eq!(
+        handler.handle(request(Method::GET)).await.status.as_u16(),
+        200
+    );
+    let mut statuses = Vec::new();
+    for _ in 0..4 {
+        statuses.push(handler.handle(request(Method::GET)).await.status.as_u16());
+    }
+    assert!(statuses.contains(&502), "{:?}", statuses);
+}
+
+#[test]
+fn test_retry_backoff_delay() {
+    let backoff = RetryBackoffConfig::default();
+    for _ in 0..20 {
+        let first = backoff.delay(1);
+        assert!(first >= Duration::from_millis(25) && first <= Duration::from_millis(50));
+        // Waits double up to max_ms
+        let third = backoff.delay(3);
+        assert!(third >= Duration::from_millis(100) && third <= Duration::from_millis(200));
+        assert!(backoff.delay(40) <= Duration::from_millis(1000));
+    }
+
+    let config: ProxyConfig =
+        serde_yaml::from_str("backends: []\nretry_backoff: {multiplier: 0.5}").unwrap();
+    assert!(config.validate().is_err());
+    let config: ProxyConfig =
+        serde_yaml::from_str("backends: []\nretry_backoff: {jitter: 2}").unwrap();
+    assert!(config.validate().is_err());
+}
+
+#[tokio::test]
+async fn test_down_backend_recovers_through_probe_requests() {
+    let backend = MockBackend::start().await;
+    for _ in 0..3 {
+        backend.push(MockAction::Reset);
+    }
+    let pool = BackendPool::new(vec![backend_config(&backend)]).with_circuit_breaker(
+        CircuitBreakerConfig {
+            cooldown_ms: 50,
+            half_open_requests: 1,
+        },
+    );
+    let proxy = ProxyHandler::new(pool.clone(), TEST_CONNECT_TIMEOUT, TEST_REQUEST_TIMEOUT);
+    let request = || {
+        RequestBuilder::new()
+            .method(Method::GET)
+            .path("/")
+            .build()
+            .unwrap()
+    };
+
+    for _ in 0..3 {
+        assert_eq!(proxy.handle(request()).await.status.as_u16(), 502);
+    }
+    assert_eq!(pool.available_count().await, 0);
+    assert_eq!(proxy.handle(request()).await.status.as_u16(), 503);
+    assert_eq!(backend.requests().len(), 3);
+
+    // Without health checks, live traffic probes the backend after the cooldown
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    assert_eq!(proxy.handle(request()).await.status.as_u16(), 200);
+    assert_eq!(pool.available_count().await, 1);
+}
+
+/// A backend keeping connections open, naming each in `X-Connection`
+///
+/// `/chunked` answers with a chunked body, `/lower` with a lowercase
+/// `content-length`, and `/bye` closes the connection after answering,
+/// without saying so.
+async fn keepalive_backend() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut id = 0;
+        while let Ok((mut socket, _)) = listener.accept().await {
+            id += 1;
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                loop {
+                    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                        match socket.read(&mut buf).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => request.extend_from_slice(&buf[..n]),
+                        }
+                    }
+                    let head = String::from_utf8_lossy(&request).to_string();
+                    request.clear();
+                    let response = if head.starts_with("GET /chunked ") {
+                        format!(
+                            "HTTP/1.1 200 OK\r\nX-Connection: {}\r\n\
+                             Transfer-Encoding: chunked\r\n\r\n2\r\nok\r\n0\r\n\r\n",
+                            id
+                        )
+                    } else if head.starts_with("GET /lower ") {
+                        format!(
+                            "HTTP/1.1 200 OK\r\nx-connection: {}\r\ncontent-length: 2\r\n\r\nok",
+                            id
+                        )
+                    } else if head.starts_with("HEAD ") {
+                        format!(
+                            "HTTP/1.1 200 OK\r\nX-Connection: {}\r\nContent-Length: 2\r\n\r\n",
+                            id
+                        )
+                    } else {
+                        format!(
+                            "HTTP/1.1 200 OK\r\nX-Connection: {}\r\nContent-Length: 2\r\n\r\nok",
+                            id
+                        )
+                    };
+                    if socket.write_all(response.as_bytes()).await.is_err()
+                        || head.starts_with("GET /bye ")
+                    {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+    format!("http://{}", addr)
+}
+
+#[tokio::test]
+async fn test_keepalive_reuses_backend_connections() {
+    let url = keepalive_backend().await;
+    let pool = BackendPool::new(vec![BackendConfig {
+        url,
+        ..Default::default()
+    }]);
+    let handler = ProxyHandler::new(pool, TEST_CONNECT_TIMEOUT, TEST_REQUEST_TIMEOUT)
+        .with_keepalive(&UpstreamKeepaliveConfig::default());
+
+    let mut seen = Vec::new();
+    for (method, path) in [
+        (Method::GET, "/"),
+        (Method::GET, "/chunked"),
+        (Method::HEAD, "/"),
+        (Method::GET, "/lower"),
+        (Method::GET, "/bye"),
+        (Method::GET, "/"),
+    ] {
+        let request = RequestBuilder::new()
+            .method(method)
+            .path(path)
+            .version("HTTP/1.1")
+            .build()
+            .unwrap();
+        let response = handler.handle(request).await;
+        assert_eq!(response.status.as_u16(), 200, "{}", path);
+        seen.push((
+            response
+                .headers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case("X-Connection"))
+                .map(|(_, value)| value.clone())
+                .unwrap(),
+            String::from_utf8(response.body).unwrap(),
+        ));
+    }
+
+    let seen: Vec<(&str, &str)> = seen.iter().map(|(c, b)| (c.as_str(), b.as_str())).collect();
+    assert_eq!(
+        seen,
+        [
+            ("1", "ok"),
+            ("1", "2\r\nok\r\n0\r\n\r\n"),
+            ("1", ""),
+            // A lowercase content-length still ends the body
+            ("1", "ok"),
+            ("1", "ok"),
+            // The backend closed the first connection
+            ("2", "ok"),
+        ]
+    );
+}
+
+#[test]
+fn test_config_keepalive() {
+    let config: ProxyConfig = serde_yaml::from_str(
+        r#"
+backends: [{ url: "http://10.0.0.1:8080" }]
+keepalive: { max_idle_per_backend: 4 }
+"#,
+    )
+    .unwrap();
+    assert!(config.validate().is_ok());
+    let keepalive = config.keepalive.unwrap();
+    assert_eq!(keepalive.max_idle_per_backend, 4);
+    assert_eq!(keepalive.idle_timeout_ms, 30_000);
+
+    let handler = ProxyHandler::new(
+        BackendPool::new(vec![]),
+        Duration::from_secs(5),
+        Duration::from_secs(30),
+    )
+    .with_keepalive(&keepalive);
+    let request = RequestBuilder::new()
+        .method(Method::GET)
+        .path("/")
+        .header("Connection", "close")
+        .build()
+        .unwrap();
+    let url = url::Url::parse("http://backend:8080").unwrap();
+    let built = handler.build_http_request(&request, &url).unwrap();
+    assert!(
+        String::from_utf8(built)
+            .unwrap()
+            .contains("Connection: keep-alive\r\n")
+    );
+
+    let invalid: ProxyConfig = serde_yaml::from_str(
+        r#"
+backends: [{ url: "http://10.0.0.1:8080" }]
+keepalive: { idle_timeout_ms: 0 }
+"#,
+    )
+    .unwrap();
+    assert!(invalid.validate().is_err());
+}
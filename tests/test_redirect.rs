@@ -0,0 +1,288 @@
+//! Tests for redirect rules and canonical host and trailing-slash redirects
+
+use sentinel::config::{CanonicalHost, RedirectConfig, RedirectRule, TrailingSlash};
+use sentinel::http::forwarded::TrustedProxies;
+use sentinel::http::handler::{Handler, handler_fn};
+use sentinel::http::request::{Method, RequestBuilder};
+use sentinel::http::response::{Response, StatusCode};
+use sentinel::middleware::RedirectHandler;
+use sentinel::testing::send_request;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+fn redirects(config: RedirectConfig) -> Arc<RedirectHandler> {
+    let inner = handler_fn(|_req| async { Response::ok(b"inner".to_vec()) });
+    Arc::new(RedirectHandler::new(inner, config))
+}
+
+fn request(method: &str, host: &str, path: &str) -> Vec<u8> {
+    format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        method, path, host
+    )
+    .into_bytes()
+}
+
+#[tokio::test]
+async fn test_canonical_host_redirects() {
+    let apex = redirects(RedirectConfig {
+        canonical_host: Some(CanonicalHost::Apex),
+        scheme: "https".to_string(),
+        ..Default::default()
+    });
+
+    let response = send_request(
+        apex.clone(),
+        &request("GET", "www.example.com:8443", "/a?b=1"),
+    )
+    .await;
+    assert_eq!(response.status, 301);
+    assert_eq!(
+        response.header("Location"),
+        Some("https://example.com:8443/a?b=1")
+    );
+
+    let response = send_request(apex.clone(), &request("GET", "example.com", "/a")).await;
+    assert_eq!(response.text(), "inner");
+
+    let www = redirects(RedirectConfig {
+        canonical_host: Some(CanonicalHost::Www),
+        ..Default::default()
+    });
+    let response = send_request(www.clone(), &request("POST", "example.com", "/pay")).await;
+    assert_eq!(response.status, 308);
+    assert_eq!(
+        response.header("Location"),
+        Some("http://www.example.com/pay")
+    );
+
+    // Local names and addresses are left alone
+    for host in ["localhost:8080", "127.0.0.1:8080", "[::1]:8080"] {
+        let response = send_request(www.clone(), &request("GET", host, "/")).await;
+        assert_eq!(response.text(), "inner", "{}", host);
+    }
+}
+
+#[tokio::test]
+async fn test_trailing_slash_policies() {
+    let add = redirects(RedirectConfig {
+        trailing_slash: Some(TrailingSlash::Add),
+        trailing_slash_exclude: vec!["/api".to_string()],
+        ..Default::default()
+    });
+
+    let response = send_request(add.clone(), &request("GET", "example.com", "/docs?x=1")).await;
+    assert_eq!(response.status, 301);
+    assert_eq!(response.header("Location"), Some("/docs/?x=1"));
+
+    for path in ["/docs/", "/app.js", "/api/items", "/"] {
+        let response = send_request(add.clone(), &request("GET", "example.com", path)).await;
+        assert_eq!(response.text(), "inner", "{}", path);
+    }
+
+    // A leading `//` must not become a protocol-relative Location
+    let response = send_request(
+        add.clone(),
+        &request("GET", "example.com", "//evil.example/x"),
+    )
+    .await;
+    assert_eq!(response.header("Location"), Some("/evil.example/x/"));
+
+    let remove = redirects(RedirectConfig {
+        trailing_slash: Some(TrailingSlash::Remove),
+        ..Default::default()
+    });
+    let response = send_request(remove.clone(), &request("GET", "example.com", "/docs/")).await;
+    assert_eq!(response.header("Location"), Some("/docs"));
+    let response = send_request(
+        remove.clone(),
+        &request("GET", "example.com", "//evil.example//"),
+    )
+    .await;
+    assert_eq!(response.header("Location"), Some("/evil.example"));
+    let response = send_request(remove, &request("GET", "example.com", "/")).await;
+    assert_eq!(response.text(), "inner");
+}
+
+#[tokio::test]
+async fn test_host_and_slash_fixed_in_one_redirect() {
+    let handler = redirects(RedirectConfig {
+        canonical_host: Some(CanonicalHost::Apex),
+        trailing_slash: Some(TrailingSlash::Remove),
+        scheme: "https".to_string(),
+        ..Default::default()
+    });
+
+    let response = send_request(handler, &request("HEAD", "WWW.example.com", "/docs/")).await;
+    assert_eq!(response.status, 301);
+    assert_eq!(
+        response.header("Location"),
+        Some("https://example.com/docs")
+    );
+}
+
+fn rule(location: &str) -> RedirectRule {
+    RedirectRule {
+        host: None,
+        scheme: None,
+        path_regex: None,
+        location: location.to_string(),
+        status: 301,
+    }
+}
+
+#[tokio::test]
+async fn test_rules_redirect_http_to_https() {
+    let config = RedirectConfig {
+        rules: vec![RedirectRule {
+            scheme: Some("http".to_string()),
+            status: 308,
+            ..rule("https://$host$path$query")
+        }],
+        ..Default::default()
+    };
+    let handler = redirects(config.clone());
+
+    let response = send_request(handler.clone(), &request("POST", "example.com", "/a?b=1")).await;
+    assert_eq!(response.status, 308);
+    assert_eq!(
+        response.header("Location"),
+        Some("https://example.com/a?b=1")
+    );
+
+    // X-Forwarded-Proto is not believed from an untrusted client
+    let forwarded = b"GET /a HTTP/1.1\r\nHost: example.com\r\nX-Forwarded-Proto: https\r\nConnection: close\r\n\r\n";
+    let response = send_request(handler, forwarded).await;
+    assert_eq!(response.status, 308);
+
+    // But is from a trusted proxy
+    let inner = handler_fn(|_req| async { Response::ok(b"inner".to_vec()) });
+    let trusted = TrustedProxies::new(&["10.0.0.0/8".to_string()]).unwrap();
+    let proxied =
+        RedirectHandler::new(inner, config.clone()).with_trusted_proxies(Arc::new(trusted));
+    let forwarded = |peer: &str| {
+        let mut req = RequestBuilder::new()
+            .method(Method::GET)
+            .path("/a")
+            .header("Host", "example.com")
+            .header("X-Forwarded-Proto", "https")
+            .build()
+            .unwrap();
+        req.context.peer = Some(peer.parse::<SocketAddr>().unwrap());
+        req
+    };
+    let response = proxied.handle(forwarded("10.1.2.3:4000")).await;
+    assert_eq!(response.body, b"inner");
+    let response = proxied.handle(forwarded("192.0.2.1:4000")).await;
+    assert_eq!(response.status, StatusCode::PermanentRedirect);
+
+    // A TLS listener's requests are HTTPS
+    let inner = handler_fn(|_req| async { Response::ok(b"inner".to_vec()) });
+    let tls = Arc::new(RedirectHandler::new(inner, config).with_tls(true));
+    let response = send_request(tls, &request("GET", "example.com", "/a")).await;
+    assert_eq!(response.text(), "inner");
+}
+
+#[tokio::test]
+async fn test_rules_match_host_and_path_with_captures() {
+    let handler = redirects(RedirectConfig {
+        rules: vec![
+            RedirectRule {
+                host: Some("*.old-brand.com".to_string()),
+                path_regex: Some(r"^/blog/(\d+)/(.*)$".to_string()),
+                status: 302,
+                ..rule("https://example.com/articles/$2?id=$1")
+            },
+            RedirectRule {
+                host: Some("old-brand.com".to_string()),
+                ..rule("$scheme://www.$host$path")
+            },
+        ],
+        canonical_host: Some(CanonicalHost::Apex),
+        ..Default::default()
+    });
+
+    let response = send_request(
+        handler.clone(),
+        &request("GET", "blog.OLD-brand.com", "/blog/7/hello"),
+    )
+    .await;
+    assert_eq!(response.status, 302);
+    assert_eq!(
+        response.header("Location"),
+        Some("https://example.com/articles/hello?id=7")
+    );
+
+    // The wildcard covers subdomains only; the second rule takes the apex
+    let response = send_request(handler.clone(), &request("GET", "old-brand.com:8080", "/x")).await;
+    assert_eq!(response.status, 301);
+    assert_eq!(
+        response.header("Location"),
+        Some("http://www.old-brand.com:8080/x")
+    );
+
+    // Requests no rule matches still get the canonical host policy
+    let response = send_request(
+        handler.clone(),
+        &request("GET", "blog.old-brand.com", "/about"),
+    )
+    .await;
+    assert_eq!(response.text(), "inner");
+    let response = send_request(handler, &request("GET", "www.example.com", "/about")).await;
+    assert_eq!(
+        response.header("Location"),
+        Some("http://example.com/about")
+    );
+}
+
+#[test]
+fn test_rules_are_validated() {
+    let valid = RedirectConfig {
+        rules: vec![rule("https://$host$path")],
+        ..Default::default()
+    };
+    assert!(valid.validate().is_ok());
+
+    for invalid in [
+        RedirectRule {
+            status: 200,
+            ..rule("/")
+        },
+        rule(""),
+        RedirectRule {
+            scheme: Some("ftp".to_string()),
+            ..rule("/")
+        },
+        RedirectRule {
+            path_regex: Some("(".to_string()),
+            ..rule("/")
+        },
+        RedirectRule {
+            host: Some(String::new()),
+            ..rule("/")
+        },
+    ] {
+        let config = RedirectConfig {
+            rules: vec![invalid],
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+    }
+}
+
+#[test]
+fn test_trusted_proxies_match_addresses_and_ranges() {
+    let entries = ["10.0.0.0/8", "192.0.2.7", "fd00::/8"].map(String::from);
+    let trusted = TrustedProxies::new(&entries).unwrap();
+    assert!(trusted.contains("10.200.0.1".parse().unwrap()));
+    assert!(trusted.contains("::ffff:10.0.0.9".parse().unwrap()));
+    assert!(trusted.contains("192.0.2.7".parse().unwrap()));
+    assert!(!trusted.contains("192.0.2.8".parse().unwrap()));
+    assert!(trusted.contains("fd12::1".parse().unwrap()));
+    assert!(!trusted.contains("fe80::1".parse().unwrap()));
+    assert!(!TrustedProxies::default().contains("127.0.0.1".parse().unwrap()));
+
+    for bad in ["10.0.0.0/33", "localhost", "10.0.0.0/x"] {
+        assert!(TrustedProxies::new(&[bad.to_string()]).is_err(), "{}", bad);
+    }
+}
@@ -1,4 +1,5 @@
-use sentinel::http::request::{Method, Request};
+use sentinel::http::context::RequestContext;
+use sentinel::http::request::{Method, Request, RequestBuilder};
 use std::collections::HashMap;
 
 #[test]
@@ -13,6 +14,8 @@ fn test_request_header_retrieval() {
         version: "HTTP/1.1".to_string(),
         headers,
         body: vec![],
+        spooled: None,
+        context: RequestContext::default(),
     };
 
     assert_eq!(req.header("Host"), Some("example.com"));
@@ -31,6 +34,8 @@ fn test_request_content_length_parsing() {
         version: "HTTP/1.1".to_string(),
         headers,
         body: vec![],
+        spooled: None,
+        context: RequestContext::default(),
     };
 
     assert_eq!(req.content_length(), 42);
@@ -44,6 +49,8 @@ fn test_request_content_length_missing() {
         version: "HTTP/1.1".to_string(),
         headers: HashMap::new(),
         body: vec![],
+        spooled: None,
+        context: RequestContext::default(),
     };
 
     assert_eq!(req.content_length(), 0);
@@ -60,6 +67,8 @@ fn test_request_content_length_invalid() {
         version: "HTTP/1.1".to_string(),
         headers,
         body: vec![],
+        spooled: None,
+        context: RequestContext::default(),
     };
 
     assert_eq!(req.content_length(), 0);
@@ -74,6 +83,8 @@ fn test_request_keep_alive_http11_default() {
         version: "HTTP/1.1".to_string(),
         headers: HashMap::new(),
         body: vec![],
+        spooled: None,
+        context: RequestContext::default(),
     };
 
     assert!(req.keep_alive());
@@ -90,6 +101,8 @@ fn test_request_keep_alive_explicit_header() {
         version: "HTTP/1.1".to_string(),
         headers,
         body: vec![],
+        spooled: None,
+        context: RequestContext::default(),
     };
 
     assert!(req.keep_alive());
@@ -106,6 +119,8 @@ fn test_request_keep_alive_close() {
         version: "HTTP/1.1".to_string(),
         headers,
         body: vec![],
+        spooled: None,
+        context: RequestContext::default(),
     };
 
     assert!(!req.keep_alive());
@@ -122,6 +137,8 @@ fn test_request_keep_alive_case_insensitive() {
         version: "HTTP/1.1".to_string(),
         headers,
         body: vec![],
+        spooled: None,
+        context: RequestContext::default(),
     };
 
     assert!(req.keep_alive());
@@ -150,7 +167,34 @@ fn test_request_with_body() {
         version: "HTTP/1.1".to_string(),
         headers: HashMap::new(),
         body: body_content.clone(),
+        spooled: None,
+        context: RequestContext::default(),
     };
 
     assert_eq!(req.body, body_content);
 }
+
+#[test]
+fn test_request_cookies() {
+    let req = RequestBuilder::new()
+        .method(Method::GET)
+        .path("/")
+        .header(
+            "cookie",
+            "session=abc; theme=\"dark\"; session=older; malformed; =x",
+        )
+        .build()
+        .unwrap();
+
+    let cookies = req.cookies();
+    assert_eq!(cookies.len(), 2);
+    assert_eq!(cookies.get("session"), Some(&"abc"));
+    assert_eq!(cookies.get("theme"), Some(&"dark"));
+
+    let req = RequestBuilder::new()
+        .method(Method::GET)
+        .path("/")
+        .build()
+        .unwrap();
+    assert!(req.cookies().is_empty());
+}
@@ -0,0 +1,108 @@
+//! Tests for cached backend hostname resolution
+
+use sentinel::http::handler::Handler;
+use sentinel::proxy::Resolver;
+use sentinel::proxy::backend::BackendPool;
+use sentinel::proxy::upstream::ProxyHandler;
+use sentinel::testing::{
+    MockAction, MockBackend, MockResponse, TEST_CONNECT_TIMEOUT, TEST_REQUEST_TIMEOUT,
+    backend_config, send_request,
+};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_resolver_passes_ip_literals_through() {
+    let resolver = Resolver::new(Duration::from_secs(30));
+    assert_eq!(
+        resolver.resolve("127.0.0.1", 8080).await.unwrap(),
+        vec!["127.0.0.1:8080".parse::<SocketAddr>().unwrap()]
+    );
+    assert_eq!(
+        resolver.resolve("[::1]", 443).await.unwrap(),
+        vec!["[::1]:443".parse::<SocketAddr>().unwrap()]
+    );
+}
+
+#[tokio::test]
+async fn test_resolver_caches_and_rotates() {
+    let resolver = Resolver::new(Duration::from_secs(30));
+    let first = resolver.resolve("localhost", 80).await.unwrap();
+    assert!(!first.is_empty());
+    assert!(
+        first
+            .iter()
+            .all(|addr| addr.ip().is_loopback() && addr.port() == 80)
+    );
+
+    // Later lookups start from the next address in turn
+    let second = resolver.resolve("localhost", 80).await.unwrap();
+    let mut expected = first.clone();
+    expected.rotate_left(1);
+    assert_eq!(second, expected);
+
+    assert!(resolver.resolve("nonexistent.invalid", 80).await.is_err());
+}
+
+#[tokio::test]
+async fn test_resolver_refreshes_expired_entries() {
+    let resolver = Resolver::new(Duration::from_millis(10));
+    let first = resolver.resolve("localhost", 80).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+
+    // Expired addresses are still served while the host is re-resolved
+    let stale = resolver.resolve("localhost", 80).await.unwrap();
+    assert_eq!(stale.len(), first.len());
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(
+        resolver.resolve("localhost", 80).await.unwrap().len(),
+        first.len()
+    );
+}
+
+#[tokio::test]
+async fn test_proxy_connects_by_hostname() {
+    let backend = MockBackend::start().await;
+    backend.set_default(MockAction::Respond(MockResponse::new(200).body("ok")));
+    let mut config = backend_config(&backend);
+    config.url = config.url.replace("127.0.0.1", "localhost");
+
+    let handler: Arc<dyn Handler> = Arc::new(
+        ProxyHandler::new(
+            BackendPool::new(vec![config]),
+            TEST_CONNECT_TIMEOUT,
+            TEST_REQUEST_TIMEOUT,
+        )
+        .with_resolver(Resolver::new(Duration::from_secs(30))),
+    );
+
+    // localhost may resolve to ::1 first, which the mock does not listen on
+    for _ in 0..3 {
+        let response = send_request(
+            handler.clone(),
+            b"GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_eq!(response.status, 200);
+    }
+}
+
+#[tokio::test]
+async fn test_resolver_connect_falls_through_refused_addresses() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    // A refused attempt (e.g. ::1) starts the next one without waiting out
+    // the delay
+    let resolver =
+        Resolver::new(Duration::from_secs(30)).with_happy_eyeballs_delay(Duration::from_secs(10));
+    let stream = tokio::time::timeout(Duration::from_secs(2), resolver.connect("localhost", port))
+        .await
+        .expect("connect waited for the delay")
+        .unwrap();
+    assert_eq!(stream.peer_addr().unwrap().port(), port);
+
+    drop(listener);
+    assert!(resolver.connect("localhost", port).await.is_err());
+}
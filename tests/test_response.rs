@@ -1,4 +1,6 @@
+use sentinel::http::cookie::{Cookie, SameSite};
 use sentinel::http::response::{Response, ResponseBuilder, StatusCode};
+use std::time::Duration;
 
 #[test]
 fn test_status_code_as_u16() {
@@ -28,6 +30,28 @@ fn test_status_code_reason_phrase() {
     );
 }
 
+#[test]
+fn test_status_code_numeric_fallback() {
+    assert_eq!(StatusCode::from_u16(429), Some(StatusCode::TooManyRequests));
+    assert_eq!(StatusCode::from_u16(418), Some(StatusCode::Other(418)));
+    assert_eq!(StatusCode::from_u16(99), None);
+    assert_eq!(StatusCode::from_u16(600), None);
+
+    // Codes compare by number whichever way they were built
+    assert_eq!(StatusCode::Other(404), StatusCode::NotFound);
+    assert_ne!(StatusCode::Other(418), StatusCode::Other(419));
+
+    assert_eq!(
+        StatusCode::Other(451).reason_phrase(),
+        "Unavailable For Legal Reasons"
+    );
+    assert_eq!(StatusCode::Other(599).reason_phrase(), "");
+    assert_eq!(
+        Response::error(StatusCode::Other(599), "").body,
+        b"599".to_vec()
+    );
+}
+
 #[test]
 fn test_response_builder_basic() {
     let response = ResponseBuilder::new(StatusCode::Ok)
@@ -149,3 +173,38 @@ fn test_response_internal_error_helper() {
     assert_eq!(response.status, StatusCode::InternalServerError);
     assert_eq!(response.body, b"500 Internal Server Error".to_vec());
 }
+
+#[test]
+fn test_set_cookie_attributes() {
+    let response = Response::new(StatusCode::Ok)
+        .cookie(
+            Cookie::new("session", "abc 123;x")
+                .path("/app")
+                .domain("example.com")
+                .max_age(Duration::from_secs(600))
+                .secure()
+                .http_only()
+                .same_site(SameSite::Strict),
+        )
+        .build();
+    assert_eq!(
+        response.headers["Set-Cookie"],
+        "session=abc123x; Path=/app; Domain=example.com; Max-Age=600; Secure; HttpOnly; SameSite=Strict"
+    );
+
+    assert_eq!(
+        Cookie::removal("session").path("/").to_string(),
+        "session=; Path=/; Max-Age=0"
+    );
+}
+
+#[test]
+fn test_add_cookie_keeps_existing_cookies() {
+    let mut response = Response::new(StatusCode::Ok)
+        .header("set-cookie", "backend=1")
+        .build();
+    response.add_cookie(Cookie::new("variant", "b"));
+
+    assert_eq!(response.headers["set-cookie"], "backend=1\nvariant=b");
+    assert!(!response.headers.contains_key("Set-Cookie"));
+}
@@ -0,0 +1,600 @@
+//! Tests for path-prefix and regex routes to upstreams and static files
+
+use sentinel::config::{
+    Config, ErrorPages, LoadBalancing, MatchMode, RedirectConfig, RouteConfig, RouteTarget,
+    StaticFilesConfig,
+};
+use sentinel::http::handler::handler_fn;
+use sentinel::http::response::Response;
+use sentinel::http::router::{Predicate, Router};
+use sentinel::http::static_files::StaticFileHandler;
+use sentinel::http::static_response::StaticResponse;
+use sentinel::middleware::{PathRewrite, RedirectHandler, RewriteHandler};
+use sentinel::server::Server;
+use sentinel::testing::{MockAction, MockBackend, MockResponse, proxy_handler, send_request};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_util::sync::CancellationToken;
+
+fn route(prefix: &str) -> RouteConfig {
+    RouteConfig {
+        name: None,
+        prefix: Some(prefix.to_string()),
+        path_regex: None,
+        rewrite: None,
+        strip_prefix: false,
+        pool: None,
+        backends: Vec::new(),
+        static_files: true,
+        static_dir: None,
+        redirect: None,
+        respond: None,
+        split: Vec::new(),
+        split_key: None,
+        methods: Vec::new(),
+        headers: Vec::new(),
+        match_mode: MatchMode::All,
+        request_headers: None,
+        response_headers: None,
+        security_headers: None,
+        cors: None,
+        client_cert: Default::default(),
+    }
+}
+
+#[test]
+fn test_config_parses_and_validates_routes() {
+    let cfg: Config = serde_yaml::from_str(
+        r#"
+server:
+  listen_addr: "127.0.0.1:8080"
+static_files:
+  root: "public"
+  index: "index.html"
+routes:
+  - prefix: /api/
+    pool: api
+    backends:
+      - url: "http://127.0.0.1:3000"
+  - prefix: /static/
+    static_files: true
+"#,
+    )
+    .unwrap();
+    assert_eq!(cfg.routes.len(), 2);
+    assert_eq!(cfg.routes[0].pool.as_deref(), Some("api"));
+    assert!(!cfg.routes[0].static_files);
+    assert!(cfg.routes.iter().all(|r| r.validate().is_ok()));
+
+    assert!(route("static/").validate().is_err());
+
+    let mut both = cfg.routes[0].clone();
+    both.static_files = true;
+    assert!(both.validate().is_err());
+
+    let mut unnamed = cfg.routes[0].clone();
+    unnamed.pool = None;
+    assert!(unnamed.validate().is_err());
+
+    let mut neither = route("/empty/");
+    neither.static_files = false;
+    assert!(neither.validate().is_err());
+}
+
+#[test]
+fn test_config_parses_route_conditions() {
+    let routes: Vec<RouteConfig> = serde_yaml::from_str(
+        r#"
+- prefix: /api/
+  static_files: true
+  methods: [GET, POST]
+  headers:
+    - name: X-Beta
+      value: "true"
+    - name: User-Agent
+      regex: "(?i)mobile"
+  match: any
+"#,
+    )
+    .unwrap();
+    assert_eq!(routes[0].match_mode, MatchMode::Any);
+    assert!(routes[0].validate().is_ok());
+    assert!(matches!(
+        routes[0].predicate().unwrap(),
+        Some(Predicate::Any(predicates)) if predicates.len() == 3
+    ));
+    assert!(route("/plain/").predicate().unwrap().is_none());
+
+    let mut unknown = routes[0].clone();
+    unknown.methods = vec!["FETCH".to_string()];
+    assert!(unknown.validate().is_err());
+
+    let mut bad_regex = routes[0].clone();
+    bad_regex.headers[1].regex = Some("(unclosed".to_string());
+    assert!(bad_regex.validate().is_err());
+
+    let mut both = routes[0].clone();
+    both.headers[0].regex = Some("t.*".to_string());
+    assert!(both.validate().is_err());
+}
+
+#[test]
+fn test_config_validates_regex_routes() {
+    let routes: Vec<RouteConfig> = serde_yaml::from_str(
+        r#"
+- path_regex: '^/v1/users/(\d+)'
+  rewrite: "/users/$1"
+  static_files: true
+"#,
+    )
+    .unwrap();
+    assert!(routes[0].validate().is_ok());
+    assert_eq!(routes[0].pattern(), r"^/v1/users/(\d+)");
+    let rewrite = routes[0].path_rewrite().unwrap().unwrap();
+    assert_eq!(rewrite.apply("/v1/users/42/posts"), "/users/42/posts");
+
+    let mut invalid = routes[0].clone();
+    invalid.path_regex = Some("^/v1/(".to_string());
+    assert!(invalid.validate().is_err());
+
+    let mut both = routes[0].clone();
+    both.prefix = Some("/v1/".to_string());
+    assert!(both.validate().is_err());
+
+    let mut neither = routes[0].clone();
+    neither.path_regex = None;
+    assert!(neither.validate().is_err());
+
+    let mut strip_regex = routes[0].clone();
+    strip_regex.rewrite = None;
+    strip_regex.strip_prefix = true;
+    assert!(strip_regex.validate().is_err());
+}
+
+#[test]
+fn test_config_strips_or_rewrites_prefixes() {
+    let mut strip = route("/api/");
+    strip.strip_prefix = true;
+    assert!(strip.validate().is_ok());
+    let rewrite = strip.path_rewrite().unwrap().unwrap();
+    assert_eq!(rewrite.apply("/api/foo"), "/foo");
+    assert_eq!(rewrite.apply("/api/"), "/");
+
+    let mut replace = route("/api/");
+    replace.rewrite = Some("/internal/v2/".to_string());
+    let rewrite = replace.path_rewrite().unwrap().unwrap();
+    assert_eq!(rewrite.apply("/api/foo/bar"), "/internal/v2/foo/bar");
+
+    let mut both = replace.clone();
+    both.strip_prefix = true;
+    assert!(both.validate().is_err());
+
+    assert!(route("/api/").path_rewrite().unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_stripped_prefix_is_not_forwarded() {
+    let api = MockBackend::start().await;
+    api.set_default(MockAction::Respond(
+        MockResponse::new(200).reason("OK").body("api"),
+    ));
+
+    let mut config = route("/api");
+    config.strip_prefix = true;
+    let handler = RewriteHandler::new(
+        proxy_handler(&[&api]),
+        config.path_rewrite().unwrap().unwrap(),
+    );
+    let router: Arc<Router> = Arc::new(Router::new().route_prefix("/api", handler));
+
+    for (raw, forwarded) in [
+        (
+            &b"GET /api/foo?x=1 HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n"[..],
+            "/foo?x=1",
+        ),
+        (
+            b"GET /api HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+            "/",
+        ),
+    ] {
+        let response = send_request(router.clone(), raw).await;
+        assert_eq!(response.text(), "api");
+        assert_eq!(api.requests().last().unwrap().path, forwarded);
+    }
+}
+
+#[test]
+fn test_path_rewrite_templates() {
+    let named = PathRewrite::Regex(
+        regex::Regex::new(r"^/legacy/(?P<section>\w+)/(\d+)\.html$").unwrap(),
+        "/${section}/items/$2".to_string(),
+    );
+    assert_eq!(named.apply("/legacy/shop/7.html"), "/shop/items/7");
+    assert_eq!(named.apply("/other"), "/other");
+
+    let relative = PathRewrite::Regex(regex::Regex::new("^/old/").unwrap(), String::new());
+    assert_eq!(relative.apply("/old/page"), "/page");
+}
+
+#[tokio::test]
+async fn test_regex_route_rewrites_path_before_forwarding() {
+    let users = MockBackend::start().await;
+    users.set_default(MockAction::Respond(
+        MockResponse::new(200).reason("OK").body("users"),
+    ));
+
+    let regex = regex::Regex::new(r"^/v1/users/(\d+)").unwrap();
+    let rewrite = PathRewrite::Regex(regex.clone(), "/users/$1".to_string());
+    let router: Arc<Router> = Arc::new(
+        Router::new()
+            .route_prefix("/", handler_fn(|_req| async { Response::not_found() }))
+            .route_regex(
+                regex,
+                RewriteHandler::new(proxy_handler(&[&users]), rewrite),
+            ),
+    );
+
+    let response = send_request(
+        router.clone(),
+        b"GET /v1/users/42/profile?full=1 HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert_eq!(response.text(), "users");
+    assert_eq!(users.requests()[0].path, "/users/42/profile?full=1");
+
+    // Regex routes win over prefixes, but only for paths they match
+    let response = send_request(
+        router,
+        b"GET /v1/users/me HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert_eq!(response.status, 404);
+    assert_eq!(users.request_count(), 1);
+}
+
+#[tokio::test]
+async fn test_prefixes_reach_their_pool_or_static_files() {
+    let api = MockBackend::start().await;
+    api.set_default(MockAction::Respond(
+        MockResponse::new(200).reason("OK").body("api"),
+    ));
+
+    let root = std::env::temp_dir().join(format!("sentinel-routes-{}", std::process::id()));
+    std::fs::create_dir_all(root.join("static")).unwrap();
+    std::fs::write(root.join("static/app.css"), "body {}").unwrap();
+    let static_files = StaticFilesConfig {
+        root: root.clone(),
+        index: "index.html".to_string(),
+        error_pages: ErrorPages::default(),
+        directory_listing: false,
+        images: None,
+    };
+
+    let router: Arc<Router> = Arc::new(
+        Router::new()
+            .route_prefix("/api/", proxy_handler(&[&api]))
+            .route_prefix("/static/", StaticFileHandler::new(static_files))
+            .fallback(handler_fn(|_req| async { Response::not_found() })),
+    );
+
+    let response = send_request(
+        router.clone(),
+        b"GET /api/users HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert_eq!(response.text(), "api");
+    assert_eq!(api.requests()[0].path, "/api/users");
+
+    let response = send_request(
+        router.clone(),
+        b"GET /static/app.css HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "body {}");
+
+    let response = send_request(
+        router,
+        b"GET /other HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert_eq!(response.status, 404);
+    assert_eq!(api.request_count(), 1);
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn test_config_selects_one_route_target() {
+    let routes: Vec<RouteConfig> = serde_yaml::from_str(
+        r#"
+- prefix: /api/
+  pool: api
+  backends:
+    - url: "http://127.0.0.1:3000"
+- prefix: /docs/
+  redirect: { location: "https://docs.example.com$path" }
+- prefix: /robots.txt
+  respond: { body: "User-agent: *" }
+- prefix: /files/
+  static_dir: "."
+"#,
+    )
+    .unwrap();
+    assert!(routes.iter().all(|r| r.validate().is_ok()));
+    assert!(matches!(
+        routes[0].target().unwrap(),
+        RouteTarget::Proxy("api")
+    ));
+    assert!(matches!(
+        routes[1].target().unwrap(),
+        RouteTarget::Redirect(redirect) if redirect.status == 301
+    ));
+    assert!(matches!(
+        routes[2].target().unwrap(),
+        RouteTarget::Respond(respond) if respond.status == 200
+    ));
+    assert!(matches!(
+        routes[3].target().unwrap(),
+        RouteTarget::Static(Some(_))
+    ));
+    assert!(matches!(
+        route("/s/").target().unwrap(),
+        RouteTarget::Static(None)
+    ));
+
+    let mut two = routes[1].clone();
+    two.respond = routes[2].respond.clone();
+    assert!(two.validate().is_err());
+
+    let mut stray_backends = routes[2].clone();
+    stray_backends.backends = routes[0].backends.clone();
+    assert!(stray_backends.validate().is_err());
+
+    let mut bad_status = routes[1].clone();
+    bad_status.redirect.as_mut().unwrap().status = 200;
+    assert!(bad_status.validate().is_err());
+
+    let mut body_and_file = routes[2].clone();
+    body_and_file.respond.as_mut().unwrap().file = Some("robots.txt".into());
+    assert!(body_and_file.validate().is_err());
+
+    let mut missing_dir = routes[3].clone();
+    missing_dir.static_dir = Some("/nonexistent/sentinel".into());
+    assert!(missing_dir.validate().is_err());
+}
+
+#[tokio::test]
+async fn test_redirect_and_fixed_response_routes() {
+    let routes: Vec<RouteConfig> = serde_yaml::from_str(
+        r#"
+- path_regex: '^/blog/(.*)'
+  redirect: { location: "https://blog.example.com/$1$query", status: 302 }
+- prefix: /robots.txt
+  respond:
+    headers: { Content-Type: "text/plain" }
+    body: "User-agent: *"
+"#,
+    )
+    .unwrap();
+    let redirect = RedirectHandler::new(
+        handler_fn(|_req| async { Response::not_found() }),
+        RedirectConfig {
+            rules: routes[0].redirect_rule().into_iter().collect(),
+            ..Default::default()
+        },
+    );
+    let robots =
+        StaticResponse::from_config(&routes[1].static_response().unwrap(), ".".as_ref()).unwrap();
+    let router: Arc<Router> = Arc::new(
+        Router::new()
+            .route_regex(routes[0].regex().unwrap().unwrap(), redirect)
+            .route_prefix("/robots.txt", robots),
+    );
+
+    let response = send_request(
+        router.clone(),
+        b"GET /blog/2024/post?ref=x HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert_eq!(response.status, 302);
+    assert_eq!(
+        response.header("location"),
+        Some("https://blog.example.com/2024/post?ref=x")
+    );
+
+    let response = send_request(
+        router,
+        b"GET /robots.txt HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "User-agent: *");
+    assert_eq!(response.header("content-type"), Some("text/plain"));
+}
+
+const UPSTREAMS: &str = r#"
+server:
+  listen_addr: "127.0.0.1:0"
+static_files:
+  root: "public"
+  index: "index.html"
+proxy:
+  upstreams:
+    api:
+      - url: "http://127.0.0.1:3000"
+    auth:
+      strategy: least_conn
+      health_check: { path: /healthz, interval_secs: 5 }
+      backends:
+        - url: "http://127.0.0.1:4000"
+routes:
+  - prefix: /api/
+    pool: api
+  - prefix: /auth/
+    pool: auth
+"#;
+
+#[test]
+fn test_config_parses_named_upstreams() {
+    let cfg: Config = serde_yaml::from_str(UPSTREAMS).unwrap();
+    let proxy = cfg.proxy.as_ref().unwrap();
+    assert!(proxy.backends.is_empty());
+    assert!(proxy.validate().is_ok());
+
+    let api = &proxy.upstreams["api"];
+    assert_eq!(api.backends.len(), 1);
+    assert!(api.strategy.is_none() && api.health_check.is_none());
+    let auth = &proxy.upstreams["auth"];
+    assert_eq!(auth.strategy, Some(LoadBalancing::LeastConn));
+    assert_eq!(auth.health_check.as_ref().unwrap().path, "/healthz");
+    assert!(cfg.routes.iter().all(|r| r.validate().is_ok()));
+
+    let mut empty = proxy.clone();
+    empty.upstreams.get_mut("api").unwrap().backends.clear();
+    assert!(empty.validate().is_err());
+
+    let mut bad_health = proxy.clone();
+    bad_health
+        .upstreams
+        .get_mut("auth")
+        .unwrap()
+        .health_check
+        .as_mut()
+        .unwrap()
+        .path = "healthz".to_string();
+    assert!(bad_health.validate().is_err());
+}
+
+#[test]
+fn test_config_validates_route_splits() {
+    let routes: Vec<RouteConfig> = serde_yaml::from_str(
+        r#"
+- prefix: /
+  split:
+    - { pool: stable, weight: 95 }
+    - { pool: canary, weight: 5 }
+  split_key:
+    cookie: session
+"#,
+    )
+    .unwrap();
+    assert!(routes[0].validate().is_ok());
+    assert!(matches!(
+        routes[0].target().unwrap(),
+        RouteTarget::Split(targets) if targets.len() == 2 && targets[1].pool == "canary"
+    ));
+
+    let mut duplicate = routes[0].clone();
+    duplicate.split[1].pool = "stable".to_string();
+    assert!(duplicate.validate().is_err());
+
+    let mut weightless = routes[0].clone();
+    weightless.split.iter_mut().for_each(|t| t.weight = 0);
+    assert!(weightless.validate().is_err());
+
+    let mut with_pool = routes[0].clone();
+    with_pool.pool = Some("stable".to_string());
+    assert!(with_pool.validate().is_err());
+}
+
+#[tokio::test]
+async fn test_unknown_upstream_fails_startup() {
+    let mut cfg: Config = serde_yaml::from_str(UPSTREAMS).unwrap();
+    cfg.routes[1].pool = Some("billing".to_string());
+
+    let error = Server::new(cfg).run().await.unwrap_err();
+    assert!(
+        error.to_string().contains("unknown upstream billing"),
+        "{}",
+        error
+    );
+
+    let mut cfg: Config = serde_yaml::from_str(UPSTREAMS).unwrap();
+    cfg.routes[1].pool = None;
+    cfg.routes[1].split =
+        serde_yaml::from_str("[{ pool: auth, weight: 9 }, { pool: billing, weight: 1 }]").unwrap();
+    let error = Server::new(cfg).run().await.unwrap_err();
+    assert!(
+        error.to_string().contains("unknown upstream billing"),
+        "{}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn test_admin_api_drains_route_pool_backends() {
+    let web = MockBackend::start().await;
+    web.set_default(MockAction::Respond(MockResponse::new(200).body("web")));
+    let api = MockBackend::start().await;
+    api.set_default(MockAction::Respond(MockResponse::new(200).body("api")));
+
+    let free_port = || {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    };
+    let (port, admin_port) = (free_port(), free_port());
+    let cfg: Config = serde_yaml::from_str(&format!(
+        r#"
+server:
+  listen_addr: "127.0.0.1:{}"
+static_files:
+  root: "public"
+  index: "index.html"
+admin:
+  listen_addr: "127.0.0.1:{}"
+proxy:
+  backends:
+    - url: "{}"
+routes:
+  - prefix: /api/
+    pool: api
+    backends:
+      - {{ url: "{}", name: api-1 }}
+"#,
+        port,
+        admin_port,
+        web.url(),
+        api.url()
+    ))
+    .unwrap();
+    let shutdown = CancellationToken::new();
+    tokio::spawn(Server::new(cfg).shutdown(shutdown.clone()).run());
+
+    let fetch = |port: u16, method: &'static str, path: &'static str| async move {
+        for _ in 0..50 {
+            if let Ok(mut stream) = TcpStream::connect(("127.0.0.1", port)).await {
+                let request = format!(
+                    "{} {} HTTP/1.1\r\nHost: a\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    method, path
+                );
+                stream.write_all(request.as_bytes()).await.unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).await.unwrap();
+                return response;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("server did not start");
+    };
+
+    assert!(fetch(port, "GET", "/api/x").await.ends_with("api"));
+    let drained = fetch(admin_port, "POST", "/backends/api-1/drain").await;
+    assert!(drained.starts_with("HTTP/1.1 200"), "{}", drained);
+    assert!(drained.contains(r#""state":"draining""#), "{}", drained);
+
+    let listed = fetch(admin_port, "GET", "/backends").await;
+    assert!(listed.contains(&web.url()), "{}", listed);
+    assert!(listed.contains(r#""name":"api-1""#), "{}", listed);
+
+    // The route's only backend is out of rotation; the default pool is not
+    let response = fetch(port, "GET", "/api/x").await;
+    assert!(response.starts_with("HTTP/1.1 503"), "{}", response);
+    assert!(fetch(port, "GET", "/").await.ends_with("web"));
+    shutdown.cancel();
+}
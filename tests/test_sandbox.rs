@@ -0,0 +1,86 @@
+//! Tests for confined static file access
+
+use sentinel::config::{ErrorPages, StaticFilesConfig};
+use sentinel::http::sandbox::read_beneath;
+use sentinel::http::static_files::StaticFileHandler;
+use sentinel::testing::send_request;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// A static root holding `index.html`, next to a secret file outside it
+fn setup(name: &str) -> (PathBuf, PathBuf) {
+    let base =
+        std::env::temp_dir().join(format!("sentinel-sandbox-{}-{}", std::process::id(), name));
+    let root = base.join("public");
+    std::fs::create_dir_all(root.join("docs")).unwrap();
+    std::fs::write(root.join("index.html"), "home").unwrap();
+    std::fs::write(root.join("docs/guide.txt"), "guide").unwrap();
+    std::fs::write(base.join("secret.txt"), "secret").unwrap();
+    (base, root)
+}
+
+#[test]
+fn test_reads_stay_beneath_root() {
+    let (base, root) = setup("read");
+    std::os::unix::fs::symlink(base.join("secret.txt"), root.join("leak.txt")).unwrap();
+    std::os::unix::fs::symlink("docs/guide.txt", root.join("alias.txt")).unwrap();
+
+    assert_eq!(
+        read_beneath(&root, Path::new("index.html")).unwrap(),
+        b"home"
+    );
+    // Symlinks that stay inside the root are followed
+    assert_eq!(
+        read_beneath(&root, Path::new("alias.txt")).unwrap(),
+        b"guide"
+    );
+
+    for escape in ["../secret.txt", "docs/../../secret.txt", "leak.txt"] {
+        let err = read_beneath(&root, Path::new(escape)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied, "{}", escape);
+    }
+    let absolute = base.join("secret.txt");
+    assert!(read_beneath(&root, &absolute).is_err());
+    assert_eq!(
+        read_beneath(&root, Path::new("missing.txt"))
+            .unwrap_err()
+            .kind(),
+        io::ErrorKind::NotFound
+    );
+
+    std::fs::remove_dir_all(&base).unwrap();
+}
+
+#[tokio::test]
+async fn test_static_handler_refuses_escapes() {
+    let (base, root) = setup("handler");
+    std::os::unix::fs::symlink(base.join("secret.txt"), root.join("leak.txt")).unwrap();
+    let handler = Arc::new(StaticFileHandler::new(StaticFilesConfig {
+        root: root.clone(),
+        index: "index.html".to_string(),
+        error_pages: ErrorPages::default(),
+        directory_listing: false,
+        images: None,
+    }));
+    let get = |path: &str| {
+        format!(
+            "GET {} HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+            path
+        )
+    };
+
+    let response = send_request(handler.clone(), get("/").as_bytes()).await;
+    assert_eq!(response.text(), "home");
+
+    let response = send_request(handler.clone(), get("/leak.txt").as_bytes()).await;
+    assert_eq!(response.status, 404);
+    assert!(!response.text().contains("secret"));
+
+    let secret = base.join("secret.txt");
+    let response = send_request(handler, get(&format!("/{}", secret.display())).as_bytes()).await;
+    assert_eq!(response.status, 404);
+    assert!(!response.text().contains("secret"));
+
+    std::fs::remove_dir_all(&base).unwrap();
+}
@@ -0,0 +1,174 @@
+//! Tests for the security headers middleware
+
+use sentinel::config::{Config, FrameOptions, SecurityHeadersConfig};
+use sentinel::http::forwarded::TrustedProxies;
+use sentinel::http::handler::{Handler, handler_fn};
+use sentinel::http::request::{Method, RequestBuilder};
+use sentinel::http::response::{Response, StatusCode};
+use sentinel::middleware::{MiddlewareChain, SecurityHeaders};
+use sentinel::testing::send_request;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+fn config(yaml: &str) -> SecurityHeadersConfig {
+    serde_yaml::from_str(yaml).unwrap()
+}
+
+fn chain(config: &SecurityHeadersConfig) -> Arc<dyn Handler> {
+    Arc::new(
+        MiddlewareChain::new(handler_fn(|_req| async {
+            Response::new(StatusCode::Ok)
+                .header("x-frame-options", "SAMEORIGIN")
+                .body(b"ok".to_vec())
+                .build()
+        }))
+        .with(SecurityHeaders::new(config)),
+    )
+}
+
+#[test]
+fn test_empty_section_is_the_preset() {
+    let preset = config("{}");
+    assert!(preset.validate().is_ok());
+    assert_eq!(
+        preset
+            .hsts
+            .as_ref()
+            .map(|hsts| hsts.header_value())
+            .as_deref(),
+        Some("max-age=31536000; includeSubDomains")
+    );
+    assert!(preset.content_type_options);
+    assert_eq!(preset.frame_options, Some(FrameOptions::Deny));
+    assert_eq!(
+        preset.referrer_policy.as_deref(),
+        Some("strict-origin-when-cross-origin")
+    );
+    assert_eq!(preset.content_security_policy, None);
+
+    let cfg: Config = serde_yaml::from_str(
+        r#"
+server: { listen_addr: "127.0.0.1:0" }
+static_files: { root: "public", index: "index.html" }
+security_headers:
+  hsts: null
+  frame_options: null
+  content_security_policy: "default-src 'self'"
+"#,
+    )
+    .unwrap();
+    let security = cfg.security_headers.unwrap();
+    assert!(security.hsts.is_none());
+    assert!(security.frame_options.is_none());
+    assert!(security.referrer_policy.is_some());
+}
+
+#[test]
+fn test_security_headers_are_validated() {
+    assert!(config("hsts: { preload: true }").validate().is_ok());
+    assert!(
+        config("hsts: { preload: true, include_subdomains: false }")
+            .validate()
+            .is_err()
+    );
+    assert!(
+        config("hsts: { preload: true, max_age_secs: 600 }")
+            .validate()
+            .is_err()
+    );
+    assert!(
+        config("referrer_policy: \"no-referrer, strict-origin\"")
+            .validate()
+            .is_ok()
+    );
+    assert!(config("referrer_policy: always").validate().is_err());
+    assert!(
+        config("content_security_policy: \"default-src 'self'\\r\\nX-Injected: 1\"")
+            .validate()
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_headers_are_added_and_existing_ones_kept() {
+    let handler = chain(&config("content_security_policy: \"default-src 'self'\""));
+    let response = send_request(
+        handler.clone(),
+        b"GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("X-Content-Type-Options"), Some("nosniff"));
+    assert_eq!(
+        response.header("Referrer-Policy"),
+        Some("strict-origin-when-cross-origin")
+    );
+    assert_eq!(
+        response.header("Content-Security-Policy"),
+        Some("default-src 'self'")
+    );
+    // The backend's own value wins, and HSTS is not sent over plain HTTP
+    assert_eq!(response.header("X-Frame-Options"), Some("SAMEORIGIN"));
+    assert_eq!(response.header("Strict-Transport-Security"), None);
+
+    let replacing = chain(&config("replace: true"));
+    let response = send_request(
+        replacing.clone(),
+        b"GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert_eq!(response.header("X-Frame-Options"), Some("DENY"));
+}
+
+#[tokio::test]
+async fn test_hsts_is_sent_on_https_requests() {
+    let preset = config("hsts: { max_age_secs: 63072000, preload: true }");
+    let handler = chain(&preset);
+    // X-Forwarded-Proto is not believed from an untrusted client
+    let response = send_request(
+        handler.clone(),
+        b"GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\nX-Forwarded-Proto: https\r\n\r\n",
+    )
+    .await;
+    assert_eq!(response.header("Strict-Transport-Security"), None);
+
+    let trusted = TrustedProxies::new(&["127.0.0.1".to_string()]).unwrap();
+    let proxied = MiddlewareChain::new(handler_fn(|_req| async { Response::not_found() }))
+        .with(SecurityHeaders::new(&preset).with_trusted_proxies(Arc::new(trusted)));
+    let mut req = RequestBuilder::new()
+        .method(Method::GET)
+        .path("/")
+        .header("X-Forwarded-Proto", "https")
+        .build()
+        .unwrap();
+    req.context.peer = Some("127.0.0.1:4000".parse::<SocketAddr>().unwrap());
+    let response = proxied.handle(req).await;
+    assert_eq!(
+        response
+            .headers
+            .get("Strict-Transport-Security")
+            .map(String::as_str),
+        Some("max-age=63072000; includeSubDomains; preload")
+    );
+
+    let tls: Arc<dyn Handler> = Arc::new(
+        MiddlewareChain::new(handler_fn(|_req| async { Response::not_found() }))
+            .with(SecurityHeaders::new(&preset).with_tls(true)),
+    );
+    let response = send_request(
+        tls.clone(),
+        b"GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert_eq!(response.status, 404);
+    assert!(response.header("Strict-Transport-Security").is_some());
+
+    let response = send_request(
+        tls.clone(),
+        b"GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\nX-Forwarded-Proto: http\r\n\r\n",
+    )
+    .await;
+    // An untrusted client cannot suppress HSTS on a TLS listener
+    assert!(response.header("Strict-Transport-Security").is_some());
+}
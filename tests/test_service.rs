@@ -0,0 +1,87 @@
+//! Tests for tower Service/Layer compatibility
+
+use sentinel::http::handler::{Handler, handler_fn};
+use sentinel::http::request::{Method, Request, RequestBuilder};
+use sentinel::http::response::{Response, StatusCode};
+use sentinel::http::service::{HandlerExt, ServiceHandler};
+use std::convert::Infallible;
+use std::time::Duration;
+use tower::ServiceExt;
+use tower::timeout::TimeoutLayer;
+use tower::util::MapRequestLayer;
+
+fn get(path: &str) -> Request {
+    RequestBuilder::new()
+        .method(Method::GET)
+        .path(path)
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_handler_as_service() {
+    let service = handler_fn(|_req| async { Response::ok(b"hi".to_vec()) }).into_service();
+
+    let response = service.oneshot(get("/")).await.unwrap();
+
+    assert_eq!(response.status, StatusCode::Ok);
+    assert_eq!(response.body, b"hi".to_vec());
+}
+
+#[tokio::test]
+async fn test_service_as_handler() {
+    let service = tower::service_fn(|req: Request| async move {
+        Ok::<_, Infallible>(Response::ok(req.path.into_bytes()))
+    });
+
+    let handler = ServiceHandler::new(service);
+    let response = handler.handle(get("/from-tower")).await;
+
+    assert_eq!(response.body, b"/from-tower".to_vec());
+}
+
+#[tokio::test]
+async fn test_service_error_becomes_500() {
+    let service = tower::service_fn(|_req: Request| async move {
+        Err::<Response, _>(std::io::Error::other("boom"))
+    });
+
+    let handler = ServiceHandler::new(service);
+    let response = handler.handle(get("/")).await;
+
+    assert_eq!(response.status, StatusCode::InternalServerError);
+}
+
+#[tokio::test]
+async fn test_timeout_layer_becomes_504() {
+    let slow = handler_fn(|_req| async {
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        Response::ok(b"late".to_vec())
+    });
+
+    let handler = slow.layer(TimeoutLayer::new(Duration::from_millis(20)));
+    let response = handler.handle(get("/")).await;
+
+    assert_eq!(response.status, StatusCode::GatewayTimeout);
+}
+
+#[tokio::test]
+async fn test_layer_can_modify_request() {
+    let echo_header = handler_fn(|req: Request| async move {
+        Response::ok(
+            req.header("X-Layer")
+                .unwrap_or("missing")
+                .as_bytes()
+                .to_vec(),
+        )
+    });
+
+    let handler = echo_header.layer(MapRequestLayer::new(|mut req: Request| {
+        req.headers
+            .insert("X-Layer".to_string(), "applied".to_string());
+        req
+    }));
+    let response = handler.handle(get("/")).await;
+
+    assert_eq!(response.body, b"applied".to_vec());
+}
@@ -0,0 +1,139 @@
+//! Tests for SLO tracking
+
+use sentinel::config::{LatencyObjective, SloConfig};
+use sentinel::http::handler::handler_fn;
+use sentinel::http::response::{Response, StatusCode};
+use sentinel::metrics::{Metrics, PrometheusRecorder};
+use sentinel::middleware::{SloHandler, SloTracker};
+use sentinel::testing::send_request;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+fn slo(name: &str, path_prefix: &str) -> SloConfig {
+    SloConfig {
+        name: name.to_string(),
+        path_prefix: path_prefix.to_string(),
+        availability: Some(99.0),
+        latency: Some(LatencyObjective {
+            threshold_ms: 100,
+            target: 90.0,
+        }),
+        windows_secs: vec![300, 3600],
+    }
+}
+
+fn at(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(1_700_000_000 + secs)
+}
+
+#[test]
+fn test_counts_roll_over_windows() {
+    let tracker = SloTracker::new(&[slo("api", "/api"), slo("all", "/")]);
+    let fast = Duration::from_millis(5);
+    let slow = Duration::from_millis(500);
+
+    tracker.record("/api/users", 200, fast, at(0));
+    tracker.record("/api/users?page=2", 503, fast, at(0));
+    tracker.record("/api/users", 200, slow, at(1000));
+    tracker.record("/index.html", 500, fast, at(1000));
+
+    let api = tracker.counts("api", 300, at(1000)).unwrap();
+    assert_eq!(
+        (api.total, api.errors, api.slow),
+        (1, 0, 1),
+        "older requests fall out of the short window"
+    );
+    let api = tracker.counts("api", 3600, at(1000)).unwrap();
+    assert_eq!((api.total, api.errors, api.slow), (3, 1, 1));
+    assert!((api.availability() - 2.0 / 3.0).abs() < 1e-9);
+
+    // The longest prefix wins, so "all" only saw the static request
+    let all = tracker.counts("all", 3600, at(1000)).unwrap();
+    assert_eq!((all.total, all.errors), (1, 1));
+    assert!(tracker.counts("missing", 300, at(0)).is_none());
+}
+
+#[test]
+fn test_report_publishes_burn_rates() {
+    let recorder = Arc::new(PrometheusRecorder::new());
+    let tracker =
+        SloTracker::new(&[slo("api", "/api")]).with_metrics(Metrics::new(recorder.clone()));
+
+    for i in 0..100 {
+        let status = if i < 2 { 500 } else { 200 };
+        let elapsed = Duration::from_millis(if i < 20 { 250 } else { 10 });
+        tracker.record("/api", status, elapsed, at(0));
+    }
+    tracker.report(at(5));
+
+    let rendered = recorder.render();
+    let value = |series: &str| -> f64 {
+        let line = rendered
+            .lines()
+            .find(|line| line.starts_with(series))
+            .unwrap_or_else(|| panic!("{} not in\n{}", series, rendered));
+        line.rsplit(' ').next().unwrap().parse().unwrap()
+    };
+    assert!(
+        (value(r#"sentinel_slo_availability_ratio{slo="api",window="5m"}"#) - 0.98).abs() < 1e-9
+    );
+    // 2% errors against a 1% budget
+    let burn = value(r#"sentinel_slo_burn_rate{objective="availability",slo="api",window="1h"}"#);
+    assert!((burn - 2.0).abs() < 1e-9);
+    // 20% slow (errors included) against a 10% budget
+    let burn = value(r#"sentinel_slo_burn_rate{objective="latency",slo="api",window="5m"}"#);
+    assert!((burn - 2.0).abs() < 1e-9);
+    assert!(rendered.contains(r#"sentinel_slo_requests_total{outcome="error",slo="api"} 2"#));
+
+    // Once the traffic ages out, nothing is burning
+    tracker.report(at(7200));
+    let rendered = recorder.render();
+    assert!(
+        rendered.contains(
+            r#"sentinel_slo_burn_rate{objective="availability",slo="api",window="1h"} 0"#
+        )
+    );
+    assert!(rendered.contains(r#"sentinel_slo_availability_ratio{slo="api",window="1h"} 1"#));
+}
+
+#[tokio::test]
+async fn test_handler_records_responses() {
+    let tracker = Arc::new(SloTracker::new(&[slo("api", "/api")]));
+    let inner = handler_fn(|req| async move {
+        if req.path == "/api/fail" {
+            Response::error(StatusCode::BadGateway, "")
+        } else {
+            Response::ok(b"ok".to_vec())
+        }
+    });
+    let handler = Arc::new(SloHandler::new(inner, tracker.clone()));
+
+    for path in ["/api/ok", "/api/fail", "/other"] {
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+            path
+        );
+        send_request(handler.clone(), request.as_bytes()).await;
+    }
+
+    let counts = tracker.counts("api", 300, SystemTime::now()).unwrap();
+    assert_eq!((counts.total, counts.errors, counts.slow), (2, 1, 0));
+}
+
+#[test]
+fn test_slo_validation() {
+    assert!(slo("api", "/api").validate().is_ok());
+
+    let mut invalid = slo("api", "/api");
+    invalid.availability = Some(100.0);
+    assert!(invalid.validate().is_err());
+
+    let mut invalid = slo("api", "/api");
+    invalid.availability = None;
+    invalid.latency = None;
+    assert!(invalid.validate().is_err());
+
+    let mut invalid = slo("api", "/api");
+    invalid.windows_secs = vec![1];
+    assert!(invalid.validate().is_err());
+}
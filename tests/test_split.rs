@@ -0,0 +1,89 @@
+//! Tests for weighted traffic splits between upstreams
+
+use sentinel::config::HashKey;
+use sentinel::http::handler::{Handler, handler_fn};
+use sentinel::http::request::{Method, Request, RequestBuilder};
+use sentinel::http::response::Response;
+use sentinel::metrics::{Metrics, PrometheusRecorder};
+use sentinel::proxy::TrafficSplit;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+fn target(body: &'static str) -> Arc<dyn Handler> {
+    Arc::new(handler_fn(move |_req| async move {
+        Response::ok(body.as_bytes().to_vec())
+    }))
+}
+
+fn request(peer: &str, session: Option<&str>) -> Request {
+    let mut builder = RequestBuilder::new().method(Method::GET).path("/");
+    if let Some(session) = session {
+        builder = builder.header("Cookie", format!("session={}", session));
+    }
+    let mut req = builder.build().unwrap();
+    req.context.peer = Some(peer.parse::<SocketAddr>().unwrap());
+    req
+}
+
+#[test]
+fn test_clients_stay_on_one_side_in_proportion() {
+    let split = TrafficSplit::new("/")
+        .with_target("stable", 90, target("stable"))
+        .with_target("canary", 10, target("canary"));
+
+    let mut canary = 0;
+    for i in 0..2000 {
+        let peer = format!("10.{}.{}.1:4000", i / 256, i % 256);
+        let side = split.target_for(&request(&peer, None)).unwrap();
+        // Another connection from the same address lands on the same side
+        let again = format!("10.{}.{}.1:5000", i / 256, i % 256);
+        assert_eq!(split.target_for(&request(&again, None)), Some(side));
+        if side == "canary" {
+            canary += 1;
+        }
+    }
+    assert!((120..=280).contains(&canary), "{} canary clients", canary);
+
+    // Raising the canary's share only moves clients onto it
+    let wider = TrafficSplit::new("/")
+        .with_target("stable", 80, target("stable"))
+        .with_target("canary", 20, target("canary"));
+    for i in 0..500 {
+        let req = request(&format!("10.0.{}.{}:4000", i / 250, i % 250), None);
+        if split.target_for(&req) == Some("canary") {
+            assert_eq!(wider.target_for(&req), Some("canary"));
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_split_by_cookie_records_metrics_per_target() {
+    let recorder = Arc::new(PrometheusRecorder::new());
+    let split = TrafficSplit::new("/app/")
+        .with_key(HashKey::Cookie("session".to_string()))
+        .with_target("stable", 1, target("stable"))
+        .with_target("canary", 1, target("canary"))
+        .with_metrics(Metrics::new(recorder.clone()));
+
+    // The session decides, wherever the client connects from
+    let side = split
+        .target_for(&request("10.0.0.1:4000", Some("abc")))
+        .unwrap();
+    for peer in ["10.0.0.2:4000", "192.168.1.9:4000"] {
+        assert_eq!(split.target_for(&request(peer, Some("abc"))), Some(side));
+    }
+
+    let response = split.handle(request("10.0.0.1:4000", Some("abc"))).await;
+    assert_eq!(String::from_utf8(response.body).unwrap(), side);
+
+    let output = recorder.render();
+    assert!(
+        output.contains(&format!(
+            "sentinel_split_requests_total{{route=\"/app/\",status=\"200\",target=\"{}\"}} 1",
+            side
+        )),
+        "{}",
+        output
+    );
+    assert!(output.contains("sentinel_split_request_duration_seconds"));
+}
@@ -0,0 +1,187 @@
+//! Tests for spooling large request bodies to disk
+
+use sentinel::config::RequestBodyConfig;
+use sentinel::http::connection::Connection;
+use sentinel::http::handler::{Handler, handler_fn};
+use sentinel::http::request::{Method, RequestBuilder};
+use sentinel::http::response::Response;
+use sentinel::http::spool::BodySpool;
+use sentinel::testing::{MockBackend, proxy_handler};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+fn spool_config(name: &str) -> RequestBodyConfig {
+    let dir = std::env::temp_dir().join(format!("sentinel-spool-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    RequestBodyConfig {
+        memory_limit_bytes: 16,
+        max_bytes: 1024,
+        spool_dir: Some(dir),
+    }
+}
+
+fn spool_files(dir: &PathBuf) -> usize {
+    std::fs::read_dir(dir).unwrap().count()
+}
+
+/// Send `raw` through a connection with the spool, returning the response
+async fn exchange(spool: Arc<BodySpool>, handler: Arc<dyn Handler>, raw: Vec<u8>) -> String {
+    let (mut client, server) = tokio::io::duplex(256);
+    let server = tokio::spawn(async move {
+        Connection::with_handler(server, handler)
+            .with_body_spool(spool)
+            .run()
+            .await
+    });
+    client.write_all(&raw).await.unwrap();
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).await.unwrap();
+    let _ = server.await.unwrap();
+    String::from_utf8_lossy(&response).into_owned()
+}
+
+#[tokio::test]
+async fn test_large_body_is_spooled() {
+    let config = spool_config("large");
+    let dir = config.spool_dir.clone().unwrap();
+    let spool = Arc::new(BodySpool::new(&config).unwrap());
+
+    let handler = Arc::new(handler_fn(|req| async move {
+        let summary = match &req.spooled {
+            Some(spooled) => format!(
+                "spooled {} {} {}",
+                req.body.len(),
+                req.body_len(),
+                std::fs::read_to_string(spooled.path()).unwrap()
+            ),
+            None => format!("memory {}", String::from_utf8_lossy(&req.body)),
+        };
+        Response::ok(summary.into_bytes())
+    }));
+
+    let body = "x".repeat(600);
+    let raw = format!(
+        "POST /upload HTTP/1.1\r\nHost: a\r\nContent-Length: {}\r\n\r\n{}\
+         POST /small HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello",
+        body.len(),
+        body
+    );
+    let response = exchange(spool, handler, raw.into_bytes()).await;
+
+    assert!(response.contains(&format!("spooled 0 600 {}", body)));
+    assert!(response.contains("memory hello"));
+    // The spool file is removed once the request is dropped
+    assert_eq!(spool_files(&dir), 0);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_oversize_body_is_refused() {
+    let config = spool_config("oversize");
+    let dir = config.spool_dir.clone().unwrap();
+    let spool = Arc::new(BodySpool::new(&config).unwrap());
+    let handler = Arc::new(handler_fn(|_req| async {
+        Response::ok(b"handled".to_vec())
+    }));
+
+    let raw = b"PUT /upload HTTP/1.1\r\nHost: a\r\nContent-Length: 4096\r\n\r\npartial".to_vec();
+    let response = exchange(spool, handler, raw).await;
+
+    assert!(response.starts_with("HTTP/1.1 413"));
+    assert!(!response.contains("handled"));
+    assert_eq!(spool_files(&dir), 0);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_oversize_chunked_body_is_refused() {
+    let config = spool_config("oversize-chunked");
+    let dir = config.spool_dir.clone().unwrap();
+    let spool = Arc::new(BodySpool::new(&config).unwrap());
+    let handler = Arc::new(handler_fn(|_req| async {
+        Response::ok(b"handled".to_vec())
+    }));
+
+    let mut raw = b"PUT /upload HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+    for _ in 0..3 {
+        raw.extend_from_slice(format!("200\r\n{}\r\n", "x".repeat(512)).as_bytes());
+    }
+    let response = exchange(spool, handler, raw).await;
+
+    assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+    assert!(!response.contains("handled"));
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[tokio::test]
+async fn test_proxy_streams_spooled_body() {
+    let config = spool_config("proxy");
+    let dir = config.spool_dir.clone().unwrap();
+    let spool = BodySpool::new(&config).unwrap();
+    let backend = MockBackend::start().await;
+    let proxy = proxy_handler(&[&backend]);
+
+    let mut file = spool.create().await.unwrap();
+    file.write(b"first chunk, ").await.unwrap();
+    file.write(b"second chunk").await.unwrap();
+    let spooled = file.finish().await.unwrap();
+    assert_eq!(spooled.len(), 25);
+
+    let mut req = RequestBuilder::new()
+        .method(Method::POST)
+        .path("/upload")
+        .header("Content-Length", "25")
+        .build()
+        .unwrap();
+    req.spooled = Some(Arc::new(spooled));
+
+    let response = proxy.handle(req).await;
+    assert_eq!(response.status.as_u16(), 200);
+
+    let received = backend.requests();
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0].body, b"first chunk, second chunk");
+    assert_eq!(spool_files(&dir), 0);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_stale_spool_files_are_removed() {
+    let config = spool_config("stale");
+    let dir = config.spool_dir.clone().unwrap();
+    std::fs::create_dir_all(&dir).unwrap();
+    // No process has this pid, so the file was left by a crash
+    let stale = dir.join("sentinel-body-4194303-00000000000000ff");
+    let own = dir.join(format!(
+        "sentinel-body-{}-00000000000000ff",
+        std::process::id()
+    ));
+    let other = dir.join("unrelated.txt");
+    for path in [&stale, &own, &other] {
+        std::fs::write(path, "x").unwrap();
+    }
+
+    BodySpool::new(&config).unwrap();
+
+    assert!(!stale.exists());
+    assert!(own.exists());
+    assert!(other.exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_request_body_config_validation() {
+    let mut config = RequestBodyConfig::default();
+    assert!(config.validate().is_ok());
+    config.memory_limit_bytes = 4096;
+    config.max_bytes = 1024;
+    assert!(config.validate().is_err());
+    config.max_bytes = 0;
+    config.memory_limit_bytes = 0;
+    assert!(config.validate().is_err());
+
+    let config: RequestBodyConfig = serde_yaml::from_str("max_bytes: 2048").unwrap();
+    assert_eq!(config.max_bytes, 2048);
+    assert_eq!(config.memory_limit_bytes, 1024 * 1024);
+}
@@ -0,0 +1,101 @@
+//! Tests for fixed responses defined in config
+
+use sentinel::config::{Config, StaticResponseConfig};
+use sentinel::http::response::StatusCode;
+use sentinel::http::static_response::StaticResponse;
+use sentinel::testing::send_request;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+const GET: &[u8] = b"GET /ping?x=1 HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n";
+
+fn route(path: &str) -> StaticResponseConfig {
+    StaticResponseConfig {
+        path: path.to_string(),
+        status: 200,
+        headers: BTreeMap::new(),
+        body: None,
+        file: None,
+    }
+}
+
+#[tokio::test]
+async fn test_inline_body_with_headers() {
+    let mut config = route("/ping");
+    config.body = Some("ok".to_string());
+    config
+        .headers
+        .insert("Cache-Control".to_string(), "no-store".to_string());
+    let handler = StaticResponse::from_config(&config, Path::new("public")).unwrap();
+
+    let response = send_request(Arc::new(handler), GET).await;
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "ok");
+    assert_eq!(response.header("Content-Type"), Some("text/plain"));
+    assert_eq!(response.header("Cache-Control"), Some("no-store"));
+}
+
+#[tokio::test]
+async fn test_file_body_is_read_once_at_startup() {
+    let root =
+        std::env::temp_dir().join(format!("sentinel-static-response-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("robots.txt"), "User-agent: *\nDisallow: /\n").unwrap();
+
+    let mut config = route("/robots.txt");
+    config.file = Some("robots.txt".into());
+    let handler = Arc::new(StaticResponse::from_config(&config, &root).unwrap());
+    std::fs::remove_dir_all(&root).unwrap();
+
+    let response = send_request(
+        handler,
+        b"GET /robots.txt HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert_eq!(response.text(), "User-agent: *\nDisallow: /\n");
+
+    // A missing file fails at startup rather than per request
+    assert!(StaticResponse::from_config(&config, &root).is_err());
+}
+
+#[tokio::test]
+async fn test_custom_status_and_empty_body() {
+    let handler = StaticResponse::new(StatusCode::NoContent, Vec::new());
+
+    let response = send_request(Arc::new(handler), GET).await;
+    assert_eq!(response.status, 204);
+    assert_eq!(response.text(), "");
+}
+
+#[test]
+fn test_config_parses_and_validates_static_responses() {
+    let cfg: Config = serde_yaml::from_str(
+        r#"
+server:
+  listen_addr: "127.0.0.1:8080"
+static_files:
+  root: "public"
+  index: "index.html"
+static_responses:
+  - path: /ping
+    body: ok
+  - path: /favicon.ico
+    status: 204
+"#,
+    )
+    .unwrap();
+    assert_eq!(cfg.static_responses.len(), 2);
+    assert_eq!(cfg.static_responses[0].status, 200);
+    assert!(cfg.static_responses.iter().all(|r| r.validate().is_ok()));
+
+    let mut both = route("/ping");
+    both.body = Some("ok".to_string());
+    both.file = Some("ok.txt".into());
+    assert!(both.validate().is_err());
+    assert!(route("ping").validate().is_err());
+
+    let mut unknown = route("/teapot");
+    unknown.status = 999;
+    assert!(unknown.validate().is_err());
+}
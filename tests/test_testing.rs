@@ -0,0 +1,103 @@
+//! Tests for the mock backend and test helpers
+
+use sentinel::http::handler::handler_fn;
+use sentinel::http::response::Response;
+use sentinel::testing::{
+    MockAction, MockBackend, MockResponse, proxy_handler, send_raw, send_request,
+};
+use std::sync::Arc;
+use std::time::Duration;
+
+const GET: &[u8] = b"GET /api HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n";
+
+#[tokio::test]
+async fn test_send_request_drives_connection() {
+    let handler = Arc::new(handler_fn(|req| async move {
+        Response::ok(req.path.into_bytes())
+    }));
+
+    let response = send_request(
+        handler,
+        b"GET /hello HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "/hello");
+}
+
+#[tokio::test]
+async fn test_send_raw_returns_pipelined_responses() {
+    let handler = Arc::new(handler_fn(|req| async move {
+        Response::ok(req.path.into_bytes())
+    }));
+
+    let responses = send_raw(
+        handler,
+        b"GET /a HTTP/1.1\r\nHost: a\r\n\r\nGET /b HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+
+    let bodies: Vec<_> = responses.iter().map(|r| r.text()).collect();
+    assert_eq!(bodies, vec!["/a", "/b"]);
+}
+
+#[tokio::test]
+async fn test_proxy_to_mock_backend() {
+    let backend = MockBackend::start().await;
+    backend.push(MockAction::Respond(
+        MockResponse::new(201)
+            .header("X-Mock", "yes")
+            .body("created"),
+    ));
+
+    let proxy = Arc::new(proxy_handler(&[&backend]));
+    let response = send_request(proxy, GET).await;
+
+    assert_eq!(response.status, 201);
+    assert_eq!(response.header("x-mock"), Some("yes"));
+    assert_eq!(response.text(), "created");
+
+    let requests = backend.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].path, "/api");
+}
+
+#[tokio::test]
+async fn test_proxy_fails_over_on_reset() {
+    let first = MockBackend::start().await;
+    let second = MockBackend::start().await;
+    first.set_default(MockAction::Reset);
+
+    let proxy = Arc::new(proxy_handler(&[&first, &second]));
+    let response = send_request(proxy, GET).await;
+
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "ok");
+    assert_eq!(first.request_count(), 1);
+    assert_eq!(second.request_count(), 1);
+}
+
+#[tokio::test]
+async fn test_proxy_times_out_on_slow_backend() {
+    let backend = MockBackend::start().await;
+    backend.push(MockAction::Respond(
+        MockResponse::new(200).delay(Duration::from_secs(10)),
+    ));
+
+    let proxy = Arc::new(proxy_handler(&[&backend]));
+    let response = send_request(proxy, GET).await;
+
+    assert_eq!(response.status, 504);
+}
+
+#[tokio::test]
+async fn test_proxy_returns_502_when_all_backends_reset() {
+    let backend = MockBackend::start().await;
+    backend.set_default(MockAction::Reset);
+
+    let proxy = Arc::new(proxy_handler(&[&backend]));
+    let response = send_request(proxy, GET).await;
+
+    assert_eq!(response.status, 502);
+}
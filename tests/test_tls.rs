@@ -0,0 +1,976 @@
+//! Tests for TLS termination, client certificates, certificate reloads,
+//! upstream TLS, and key logging
+
+use rustls::HandshakeKind;
+use sentinel::admin::AdminApi;
+use sentinel::config::{
+    BackendConfig, CertificateConfig, ClientAuthConfig, ClientAuthMode, ClientCertHeaders,
+    ClientCertRequirement, Config, TlsConfig, TlsVersion, UpstreamKeepaliveConfig,
+    UpstreamTlsConfig,
+};
+use sentinel::http::connection::Connection;
+use sentinel::http::handler::{Handler, handler_fn};
+use sentinel::http::request::{Method, RequestBuilder};
+use sentinel::http::response::Response;
+use sentinel::http::router::Router;
+use sentinel::middleware::{ClientCertPolicy, MiddlewareChain};
+use sentinel::proxy::{BackendPool, ProxyHandler};
+use sentinel::server::Server;
+use sentinel::testing::{MockBackend, send_request};
+use sentinel::tls::{self, ClientCertificate};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tokio_util::sync::CancellationToken;
+
+/// A self-signed certificate for `localhost`, written as PEM files
+struct TestCert {
+    dir: PathBuf,
+    cert_file: PathBuf,
+    key_file: PathBuf,
+}
+
+impl TestCert {
+    fn new(name: &str) -> Self {
+        Self::for_host(name, "localhost")
+    }
+
+    fn for_host(name: &str, host: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("sentinel-tls-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        let certified = rcgen::generate_simple_self_signed(vec![host.to_string()]).unwrap();
+        let cert_file = dir.join("cert.pem");
+        let key_file = dir.join("key.pem");
+        std::fs::write(&cert_file, certified.cert.pem()).unwrap();
+        std::fs::write(&key_file, certified.key_pair.serialize_pem()).unwrap();
+        Self {
+            dir,
+            cert_file,
+            key_file,
+        }
+    }
+
+    fn server_config(&self, key_log_file: Option<PathBuf>) -> TlsConfig {
+        TlsConfig {
+            cert_file: self.cert_file.clone(),
+            key_file: self.key_file.clone(),
+            certificates: Default::default(),
+            client_auth: None,
+            reload_interval_secs: 0,
+            min_version: TlsVersion::Tls12,
+            max_version: TlsVersion::Tls13,
+            cipher_suites: Vec::new(),
+            alpn: vec!["http/1.1".to_string()],
+            key_log_file,
+            ocsp: None,
+        }
+    }
+
+    fn certificate(&self) -> CertificateConfig {
+        CertificateConfig {
+            cert_file: self.cert_file.clone(),
+            key_file: self.key_file.clone(),
+        }
+    }
+
+    fn der(&self) -> Vec<u8> {
+        let pem = std::fs::read(&self.cert_file).unwrap();
+        rustls_pemfile::certs(&mut pem.as_slice())
+            .next()
+            .unwrap()
+            .unwrap()
+            .to_vec()
+    }
+}
+
+impl Drop for TestCert {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Check every line is `<label> <32-byte client random> <secret>` in hex
+fn assert_nss_key_log(path: &Path) {
+    let log = std::fs::read_to_string(path).unwrap();
+    assert!(log.contains("CLIENT_TRAFFIC_SECRET_0 "), "{}", log);
+    for line in log.lines() {
+        let fields: Vec<&str> = line.split(' ').collect();
+        assert_eq!(fields.len(), 3, "{}", line);
+        assert_eq!(fields[1].len(), 64, "{}", line);
+        assert!(
+            fields[1..]
+                .iter()
+                .all(|f| f.chars().all(|c| c.is_ascii_hexdigit())),
+            "{}",
+            line
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_terminated_tls_logs_session_keys() {
+    let cert = TestCert::new("server");
+    let key_log = cert.dir.join("server-keys.log");
+    let acceptor =
+        TlsAcceptor::from(tls::server_config(&cert.server_config(Some(key_log.clone()))).unwrap());
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (socket, _) = listener.accept().await.unwrap();
+        let stream = acceptor.accept(socket).await.unwrap();
+        let handler = Arc::new(handler_fn(|_req| async {
+            Response::ok(b"secure".to_vec())
+        }));
+        let _ = Connection::with_handler(stream, handler).run().await;
+    });
+
+    // Trust only the test certificate
+    let client = tls::client_config(&UpstreamTlsConfig {
+        ca_file: Some(cert.cert_file.clone()),
+        key_log_file: None,
+        session_cache_size: 256,
+    })
+    .unwrap();
+    let socket = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let mut stream = TlsConnector::from(client)
+        .connect("localhost".try_into().unwrap(), socket)
+        .await
+        .unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    let response = String::from_utf8_lossy(&response);
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.ends_with("secure"));
+
+    assert_nss_key_log(&key_log);
+}
+
+/// A TLS backend answering every request with `ok`
+async fn tls_backend(cert: &TestCert) -> String {
+    let acceptor = TlsAcceptor::from(tls::server_config(&cert.server_config(None)).unwrap());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                let Ok(mut stream) = acceptor.accept(socket).await else {
+                    return;
+                };
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let _ = stream
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+                    .await;
+                let _ = stream.shutdown().await;
+            });
+        }
+    });
+    format!("https://localhost:{}", port)
+}
+
+fn https_proxy(url: String) -> ProxyHandler {
+    let pool = BackendPool::new(vec![BackendConfig {
+        url,
+        ..Default::default()
+    }]);
+    ProxyHandler::new(pool, Duration::from_secs(2), Duration::from_secs(5))
+}
+
+#[tokio::test]
+async fn test_https_backends_are_verified_and_logged() {
+    let cert = TestCert::new("upstream");
+    let url = tls_backend(&cert).await;
+    let key_log = cert.dir.join("upstream-keys.log");
+    let request = b"GET /api HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n";
+
+    let trusted = tls::client_config(&UpstreamTlsConfig {
+        ca_file: Some(cert.cert_file.clone()),
+        key_log_file: Some(key_log.clone()),
+        session_cache_size: 256,
+    })
+    .unwrap();
+    let handler = Arc::new(https_proxy(url.clone()).with_tls(trusted));
+    let response = send_request(handler, request).await;
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "ok");
+    assert_nss_key_log(&key_log);
+
+    // The public roots do not include the test certificate
+    let handler = Arc::new(https_proxy(url));
+    let response = send_request(handler, request).await;
+    assert_eq!(response.status, 502);
+}
+
+/// A TLS backend keeping connections open, answering each request with
+/// whether its connection's handshake resumed a session, and the
+/// connection's number
+async fn resuming_backend(cert: &TestCert) -> String {
+    let acceptor = TlsAcceptor::from(tls::server_config(&cert.server_config(None)).unwrap());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let connections = Arc::new(AtomicUsize::new(0));
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            let acceptor = acceptor.clone();
+            let id = connections.fetch_add(1, Ordering::SeqCst) + 1;
+            tokio::spawn(async move {
+                let Ok(mut stream) = acceptor.accept(socket).await else {
+                    return;
+                };
+                let handshake = match stream.get_ref().1.handshake_kind() {
+                    Some(HandshakeKind::Resumed) => "resumed",
+                    _ => "full",
+                };
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                loop {
+                    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                        match stream.read(&mut buf).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => request.extend_from_slice(&buf[..n]),
+                        }
+                    }
+                    request.clear();
+                    let body = format!("{} {}", handshake, id);
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                        body.len(),
+                        body
+                    );
+                    if stream.write_all(response.as_bytes()).await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+    format!("https://localhost:{}", port)
+}
+
+#[tokio::test]
+async fn test_backend_sessions_and_connections_are_reused() {
+    let cert = TestCert::new("resumption");
+    let url = resuming_backend(&cert).await;
+    let request = b"GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n";
+    let client = tls::client_config(&UpstreamTlsConfig {
+        ca_file: Some(cert.cert_file.clone()),
+        key_log_file: None,
+        session_cache_size: 256,
+    })
+    .unwrap();
+    let fetch = |handler: &Arc<ProxyHandler>| send_request(handler.clone(), request);
+
+    // A new connection per request, resuming the first one's session
+    let handler = Arc::new(https_proxy(url.clone()).with_tls(client.clone()));
+    assert_eq!(fetch(&handler).await.text(), "full 1");
+    assert_eq!(fetch(&handler).await.text(), "resumed 2");
+
+    let handler = Arc::new(
+        https_proxy(url.clone())
+            .with_tls(client.clone())
+            .with_tls_session_cache(0),
+    );
+    assert_eq!(fetch(&handler).await.text(), "full 3");
+    assert_eq!(fetch(&handler).await.text(), "full 4");
+
+    // One connection for every request
+    let handler = Arc::new(
+        https_proxy(url)
+            .with_tls(client)
+            .with_keepalive(&UpstreamKeepaliveConfig::default()),
+    );
+    for _ in 0..3 {
+        let response = fetch(&handler).await;
+        assert_eq!(response.status, 200);
+        assert_eq!(response.text(), "full 5");
+    }
+}
+
+#[test]
+fn test_invalid_certificates_are_rejected() {
+    let cert = TestCert::new("invalid");
+    std::fs::write(&cert.key_file, "not a key").unwrap();
+    assert!(tls::server_config(&cert.server_config(None)).is_err());
+
+    let missing = UpstreamTlsConfig {
+        ca_file: Some(cert.dir.join("missing.pem")),
+        key_log_file: None,
+        session_cache_size: 256,
+    };
+    assert!(tls::client_config(&missing).is_err());
+}
+
+/// Handshake as `server_name`, trusting only `trusted`, and read the reply
+async fn fetch_over_sni(
+    addr: std::net::SocketAddr,
+    server_name: &str,
+    trusted: &TestCert,
+) -> String {
+    let client = tls::client_config(&UpstreamTlsConfig {
+        ca_file: Some(trusted.cert_file.clone()),
+        key_log_file: None,
+        session_cache_size: 256,
+    })
+    .unwrap();
+    let socket = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let mut stream = TlsConnector::from(client)
+        .connect(server_name.to_string().try_into().unwrap(), socket)
+        .await
+        .unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    String::from_utf8_lossy(&response).into_owned()
+}
+
+#[tokio::test]
+async fn test_certificates_are_chosen_by_sni() {
+    let default = TestCert::new("sni-default");
+    let shop = TestCert::for_host("sni-shop", "shop.example.test");
+    let tenants = TestCert::for_host("sni-tenants", "acme.tenants.example.test");
+    let mut config = default.server_config(None);
+    config
+        .certificates
+        .insert("Shop.Example.Test".to_string(), shop.certificate());
+    config
+        .certificates
+        .insert("*.tenants.example.test".to_string(), tenants.certificate());
+
+    let resolver = tls::CertResolver::load(&config).unwrap();
+    let served = |name: Option<&str>| resolver.for_name(name).cert[0].to_vec();
+    assert_eq!(served(Some("shop.example.test")), shop.der());
+    assert_eq!(served(Some("acme.tenants.example.test")), tenants.der());
+    assert_eq!(served(Some("a.b.tenants.example.test")), default.der());
+    assert_eq!(served(Some("tenants.example.test")), default.der());
+    assert_eq!(served(Some("other.test")), default.der());
+    assert_eq!(served(None), default.der());
+
+    let acceptor = TlsAcceptor::from(tls::server_config(&config).unwrap());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            let stream = acceptor.accept(socket).await.unwrap();
+            let handler = Arc::new(handler_fn(|_req| async { Response::ok(b"hi".to_vec()) }));
+            let _ = Connection::with_handler(stream, handler).run().await;
+        }
+    });
+
+    for (name, trusted) in [
+        ("shop.example.test", &shop),
+        ("acme.tenants.example.test", &tenants),
+        ("localhost", &default),
+    ] {
+        let response = fetch_over_sni(addr, name, trusted).await;
+        assert!(
+            response.starts_with("HTTP/1.1 200"),
+            "{}: {}",
+            name,
+            response
+        );
+    }
+
+    let mut invalid = default.server_config(None);
+    invalid
+        .certificates
+        .insert("bad host".to_string(), shop.certificate());
+    assert!(tls::CertResolver::load(&invalid).is_err());
+    let mut missing = default.server_config(None);
+    missing.certificates.insert(
+        "shop.example.test".to_string(),
+        CertificateConfig {
+            cert_file: default.dir.join("missing.pem"),
+            key_file: default.key_file.clone(),
+        },
+    );
+    assert!(tls::CertResolver::load(&missing).is_err());
+}
+
+/// A client CA and a certificate it issued for `billing`
+struct ClientPki {
+    dir: PathBuf,
+    ca_file: PathBuf,
+    cert: Vec<u8>,
+    key: Vec<u8>,
+}
+
+impl ClientPki {
+    fn new(name: &str) -> Self {
+        use rcgen::{
+            BasicConstraints, CertificateParams, DistinguishedName, DnType, IsCa, KeyPair,
+        };
+
+        let dir =
+            std::env::temp_dir().join(format!("sentinel-mtls-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca.distinguished_name
+            .push(DnType::CommonName, "Sentinel Test Clients");
+        let ca = ca.self_signed(&ca_key).unwrap();
+
+        let key = KeyPair::generate().unwrap();
+        let mut client = CertificateParams::new(Vec::<String>::new()).unwrap();
+        client.distinguished_name = DistinguishedName::new();
+        client
+            .distinguished_name
+            .push(DnType::OrganizationName, "Example, Inc");
+        client
+            .distinguished_name
+            .push(DnType::CommonName, "billing");
+        let client = client.signed_by(&key, &ca, &ca_key).unwrap();
+
+        let ca_file = dir.join("clients-ca.pem");
+        std::fs::write(&ca_file, ca.pem()).unwrap();
+        Self {
+            dir,
+            ca_file,
+            cert: client.der().to_vec(),
+            key: key.serialize_der(),
+        }
+    }
+}
+
+impl Drop for ClientPki {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn echo_subject() -> impl Handler {
+    handler_fn(|req| async move {
+        let subject = req.header("X-Client-Cert-Subject").unwrap_or("none");
+        Response::ok(subject.as_bytes().to_vec())
+    })
+}
+
+/// A TLS listener verifying client certificates, with `/required`
+/// needing one and everything else echoing the subject it was given
+async fn mtls_server(config: &TlsConfig) -> std::net::SocketAddr {
+    let acceptor = TlsAcceptor::from(tls::server_config(config).unwrap());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            let Ok(stream) = acceptor.accept(socket).await else {
+                continue;
+            };
+            let client_cert = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .map(|cert| Arc::new(ClientCertificate::from_der(cert)));
+            let handler = Arc::new(
+                Router::new()
+                    .route(
+                        "/required",
+                        MiddlewareChain::new(echo_subject())
+                            .with(ClientCertPolicy::new(ClientCertRequirement::Require)),
+                    )
+                    .fallback(
+                        MiddlewareChain::new(echo_subject())
+                            .with(ClientCertPolicy::new(ClientCertRequirement::Optional)),
+                    ),
+            );
+            let mut conn = Connection::with_handler(stream, handler);
+            if let Some(cert) = client_cert {
+                conn = conn.with_client_cert(cert);
+            }
+            let _ = conn.run().await;
+        }
+    });
+    addr
+}
+
+/// Request `path` over TLS, presenting the `pki` client certificate if
+/// given
+async fn fetch_with_client_cert(
+    addr: std::net::SocketAddr,
+    server: &TestCert,
+    pki: Option<&ClientPki>,
+    path: &str,
+) -> std::io::Result<String> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(server.der().into()).unwrap();
+    let builder = rustls::ClientConfig::builder().with_root_certificates(roots);
+    let client = match pki {
+        Some(pki) => builder
+            .with_client_auth_cert(
+                vec![pki.cert.clone().into()],
+                rustls::pki_types::PrivatePkcs8KeyDer::from(pki.key.clone()).into(),
+            )
+            .unwrap(),
+        None => builder.with_no_client_auth(),
+    };
+    let socket = tokio::net::TcpStream::connect(addr).await?;
+    let mut stream = TlsConnector::from(Arc::new(client))
+        .connect("localhost".try_into().unwrap(), socket)
+        .await?;
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: a\r\nConnection: close\r\nX-Client-Cert-Subject: CN=admin\r\n\r\n",
+        path
+    );
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    Ok(String::from_utf8_lossy(&response).into_owned())
+}
+
+#[tokio::test]
+async fn test_required_client_certificates_fail_handshakes_without_one() {
+    let server = TestCert::new("mtls-required");
+    let pki = ClientPki::new("required");
+    let mut config = server.server_config(None);
+    config.client_auth = Some(ClientAuthConfig {
+        ca_file: pki.ca_file.clone(),
+        mode: ClientAuthMode::Required,
+        headers: Default::default(),
+    });
+    let addr = mtls_server(&config).await;
+
+    let response = fetch_with_client_cert(addr, &server, Some(&pki), "/")
+        .await
+        .unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(
+        response.ends_with("CN=billing,O=Example\\, Inc"),
+        "{}",
+        response
+    );
+
+    // TLS 1.3 clients only learn of the rejection when they read
+    let refused = fetch_with_client_cert(addr, &server, None, "/").await;
+    assert!(refused.is_err(), "{:?}", refused);
+
+    // Certificates from other CAs are refused too
+    let stranger = ClientPki::new("stranger");
+    let refused = fetch_with_client_cert(addr, &server, Some(&stranger), "/").await;
+    assert!(refused.is_err(), "{:?}", refused);
+
+    let missing = ClientAuthConfig {
+        ca_file: pki.dir.join("missing.pem"),
+        mode: ClientAuthMode::Required,
+        headers: Default::default(),
+    };
+    config.client_auth = Some(missing);
+    assert!(tls::server_config(&config).is_err());
+}
+
+#[tokio::test]
+async fn test_routes_decide_with_optional_client_certificates() {
+    let server = TestCert::new("mtls-optional");
+    let pki = ClientPki::new("optional");
+    let mut config = server.server_config(None);
+    config.client_auth = Some(ClientAuthConfig {
+        ca_file: pki.ca_file.clone(),
+        mode: ClientAuthMode::Optional,
+        headers: Default::default(),
+    });
+    let addr = mtls_server(&config).await;
+
+    let anonymous = fetch_with_client_cert(addr, &server, None, "/")
+        .await
+        .unwrap();
+    assert!(anonymous.starts_with("HTTP/1.1 200"), "{}", anonymous);
+    // The client's own header never reaches the backend
+    assert!(anonymous.ends_with("none"), "{}", anonymous);
+
+    let refused = fetch_with_client_cert(addr, &server, None, "/required")
+        .await
+        .unwrap();
+    assert!(refused.starts_with("HTTP/1.1 403"), "{}", refused);
+
+    let allowed = fetch_with_client_cert(addr, &server, Some(&pki), "/required")
+        .await
+        .unwrap();
+    assert!(allowed.starts_with("HTTP/1.1 200"), "{}", allowed);
+    assert!(
+        allowed.ends_with("CN=billing,O=Example\\, Inc"),
+        "{}",
+        allowed
+    );
+}
+
+#[tokio::test]
+async fn test_listener_passes_client_identity_on_default_route() {
+    let server = TestCert::new("mtls-default-route");
+    let pki = ClientPki::new("default-route");
+    let backend = MockBackend::start().await;
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let cfg: Config = serde_yaml::from_str(&format!(
+        r#"
+server:
+  listen_addr: "127.0.0.1:{}"
+  tls:
+    cert_file: "{}"
+    key_file: "{}"
+    client_auth: {{ ca_file: "{}", mode: required }}
+static_files:
+  root: "public"
+  index: "index.html"
+proxy:
+  backends:
+    - url: "{}"
+"#,
+        port,
+        server.cert_file.display(),
+        server.key_file.display(),
+        pki.ca_file.display(),
+        backend.url()
+    ))
+    .unwrap();
+    let shutdown = CancellationToken::new();
+    tokio::spawn(Server::new(cfg).shutdown(shutdown.clone()).run());
+
+    let addr = ([127, 0, 0, 1], port).into();
+    let mut response = None;
+    for _ in 0..50 {
+        if let Ok(fetched) = fetch_with_client_cert(addr, &server, Some(&pki), "/").await {
+            response = Some(fetched);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let response = response.expect("server did not start");
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+    // The client's own X-Client-Cert-Subject is replaced by the verified one
+    let forwarded = &backend.requests()[0];
+    assert_eq!(
+        forwarded.header("X-Client-Cert-Subject"),
+        Some("CN=billing,O=Example\\, Inc")
+    );
+    assert_eq!(
+        forwarded.header("X-Client-Cert-Issuer"),
+        Some("CN=Sentinel Test Clients")
+    );
+    assert_eq!(
+        forwarded.header("X-Client-Cert-Sha256").map(str::len),
+        Some(64)
+    );
+    shutdown.cancel();
+}
+
+#[test]
+fn test_client_certificate_details() {
+    use sha2::{Digest, Sha256};
+
+    let pki = ClientPki::new("details");
+    let cert = ClientCertificate::from_der(&pki.cert);
+    assert_eq!(cert.subject, "CN=billing,O=Example\\, Inc");
+    let expected: String = Sha256::digest(&pki.cert)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    assert_eq!(cert.sha256, expected);
+
+    let garbage = ClientCertificate::from_der(b"not a certificate");
+    assert_eq!(garbage.subject, "");
+    assert_eq!(garbage.sha256.len(), 64);
+}
+
+/// Overwrite `cert`'s files with a new certificate for `localhost`
+fn renew(cert: &TestCert) {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    std::fs::write(&cert.cert_file, certified.cert.pem()).unwrap();
+    std::fs::write(&cert.key_file, certified.key_pair.serialize_pem()).unwrap();
+}
+
+#[tokio::test]
+async fn test_changed_certificates_are_reloaded() {
+    let cert = TestCert::new("reload");
+    let config = cert.server_config(None);
+    let resolver = Arc::new(tls::CertResolver::load(&config).unwrap());
+    let reloader = tls::CertReloader::new(&config, resolver.clone());
+    let original = cert.der();
+
+    assert!(!reloader.reload().unwrap());
+    assert_eq!(resolver.current().cert[0].to_vec(), original);
+
+    renew(&cert);
+    assert!(reloader.reload().unwrap());
+    let renewed = cert.der();
+    assert_ne!(renewed, original);
+    assert_eq!(resolver.current().cert[0].to_vec(), renewed);
+    assert!(!reloader.reload().unwrap());
+
+    // A certificate written before its key fails to load and leaves the
+    // renewed one in place until the key arrives
+    let next = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    std::fs::write(&cert.cert_file, next.cert.pem()).unwrap();
+    assert!(reloader.reload().is_err());
+    assert_eq!(resolver.current().cert[0].to_vec(), renewed);
+    std::fs::write(&cert.key_file, next.key_pair.serialize_pem()).unwrap();
+    assert!(reloader.reload().unwrap());
+    assert_eq!(
+        resolver.current().cert[0].to_vec(),
+        next.cert.der().to_vec()
+    );
+}
+
+#[tokio::test]
+async fn test_reloads_keep_established_connections() {
+    let cert = TestCert::new("reload-live");
+    let config = cert.server_config(None);
+    let resolver = Arc::new(tls::CertResolver::load(&config).unwrap());
+    let reloader = Arc::new(tls::CertReloader::new(&config, resolver.clone()));
+    let acceptor = TlsAcceptor::from(tls::server_config_with(&config, resolver).unwrap());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                let stream = acceptor.accept(socket).await.unwrap();
+                let handler = Arc::new(handler_fn(|_req| async { Response::ok(b"hi".to_vec()) }));
+                let _ = Connection::with_handler(stream, handler).run().await;
+            });
+        }
+    });
+
+    let client = tls::client_config(&UpstreamTlsConfig {
+        ca_file: Some(cert.cert_file.clone()),
+        key_log_file: None,
+        session_cache_size: 256,
+    })
+    .unwrap();
+    let socket = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let mut established = TlsConnector::from(client)
+        .connect("localhost".try_into().unwrap(), socket)
+        .await
+        .unwrap();
+    let request = b"GET / HTTP/1.1\r\nHost: a\r\n\r\n";
+    let mut buf = [0u8; 1024];
+    established.write_all(request).await.unwrap();
+    let n = established.read(&mut buf).await.unwrap();
+    assert!(buf[..n].starts_with(b"HTTP/1.1 200"));
+
+    renew(&cert);
+    let admin = Arc::new(AdminApi::new().tls(reloader));
+    let reload =
+        b"POST /tls/reload HTTP/1.1\r\nHost: a\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    let response = send_request(admin.clone(), reload).await;
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), r#"{"reloaded":true}"#);
+    let response = send_request(admin, reload).await;
+    assert_eq!(response.text(), r#"{"reloaded":false}"#);
+
+    // The established connection carries on, new ones get the new
+    // certificate
+    established.write_all(request).await.unwrap();
+    let n = established.read(&mut buf).await.unwrap();
+    assert!(buf[..n].starts_with(b"HTTP/1.1 200"));
+    let response = fetch_over_sni(addr, "localhost", &cert).await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+    let response = send_request(Arc::new(AdminApi::new()), reload).await;
+    assert_eq!(response.status, 404);
+}
+
+#[test]
+fn test_protocol_settings_are_validated() {
+    let cert = TestCert::new("protocols");
+    let parse = |extra: &str| {
+        serde_yaml::from_str::<TlsConfig>(&format!(
+            "cert_file: {}\nkey_file: {}\n{}",
+            cert.cert_file.display(),
+            cert.key_file.display(),
+            extra
+        ))
+    };
+
+    let defaults = parse("").unwrap();
+    assert_eq!(defaults.min_version, TlsVersion::Tls12);
+    assert_eq!(defaults.max_version, TlsVersion::Tls13);
+    assert_eq!(defaults.alpn, ["http/1.1"]);
+    assert!(defaults.cipher_suites.is_empty());
+    assert!(tls::server_config(&defaults).is_ok());
+
+    // Unquoted YAML versions are numbers
+    assert_eq!(
+        parse("min_version: 1.3").unwrap().min_version,
+        TlsVersion::Tls13
+    );
+    assert_eq!(
+        parse("min_version: TLSv1.3").unwrap().min_version,
+        TlsVersion::Tls13
+    );
+    for old in ["1.0", "1.1", "\"1.1\"", "2.0"] {
+        assert!(parse(&format!("min_version: {}", old)).is_err(), "{}", old);
+    }
+
+    for invalid in [
+        "min_version: 1.3\nmax_version: 1.2",
+        "alpn: [spdy/3]",
+        "alpn: [h2, h2]",
+        "cipher_suites: [TLS13_AES_128_GCM_SHA256, tls13_aes_128_gcm_sha256]",
+    ] {
+        assert!(parse(invalid).unwrap().validate().is_err(), "{}", invalid);
+    }
+    for unusable in [
+        "cipher_suites: [TLS_RSA_WITH_RC4_128_SHA]",
+        // Nothing left for TLS 1.2
+        "cipher_suites: [TLS13_AES_128_GCM_SHA256]",
+    ] {
+        let config = parse(unusable).unwrap();
+        assert!(config.validate().is_ok(), "{}", unusable);
+        assert!(tls::server_config(&config).is_err(), "{}", unusable);
+    }
+    let tls13 = parse("min_version: 1.3\ncipher_suites: [TLS13_AES_128_GCM_SHA256]").unwrap();
+    assert!(tls::server_config(&tls13).is_ok());
+}
+
+#[tokio::test]
+async fn test_handshakes_follow_protocol_settings() {
+    let cert = TestCert::new("negotiation");
+    let mut config = cert.server_config(None);
+    config.min_version = TlsVersion::Tls13;
+    config.cipher_suites = vec!["TLS13_CHACHA20_POLY1305_SHA256".to_string()];
+    config.alpn = vec!["h2".to_string(), "http/1.1".to_string()];
+    let acceptor = TlsAcceptor::from(tls::server_config(&config).unwrap());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                if let Ok(mut stream) = acceptor.accept(socket).await {
+                    let _ = stream.read(&mut [0u8; 16]).await;
+                }
+            });
+        }
+    });
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(cert.der().into()).unwrap();
+    let mut client = rustls::ClientConfig::builder()
+        .with_root_certificates(roots.clone())
+        .with_no_client_auth();
+    client.alpn_protocols = vec![b"http/1.1".to_vec(), b"h2".to_vec()];
+    let socket = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let stream = TlsConnector::from(Arc::new(client))
+        .connect("localhost".try_into().unwrap(), socket)
+        .await
+        .unwrap();
+    let (_, session) = stream.get_ref();
+    // The server's preference wins
+    assert_eq!(session.alpn_protocol(), Some(&b"h2"[..]));
+    assert_eq!(
+        session.protocol_version(),
+        Some(rustls::ProtocolVersion::TLSv1_3)
+    );
+    assert_eq!(
+        session.negotiated_cipher_suite().unwrap().suite(),
+        rustls::CipherSuite::TLS13_CHACHA20_POLY1305_SHA256
+    );
+
+    let tls12_only =
+        rustls::ClientConfig::builder_with_protocol_versions(&[&rustls::version::TLS12])
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+    let socket = tokio::net::TcpStream::connect(addr).await.unwrap();
+    assert!(
+        TlsConnector::from(Arc::new(tls12_only))
+            .connect("localhost".try_into().unwrap(), socket)
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_client_identity_headers_are_configurable() {
+    let pki = ClientPki::new("headers");
+    let cert = Arc::new(ClientCertificate::from_der(&pki.cert));
+    assert_eq!(cert.issuer, "CN=Sentinel Test Clients");
+    let parsed: Vec<_> = rustls_pemfile::certs(&mut cert.pem().as_bytes())
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(parsed[0].to_vec(), pki.cert);
+    let escaped = cert.escaped_pem();
+    assert!(
+        escaped.starts_with("-----BEGIN%20CERTIFICATE-----%0A"),
+        "{}",
+        escaped
+    );
+    assert!(!escaped.contains(['\n', ' ', '+', '/', '=']), "{}", escaped);
+
+    let headers: ClientCertHeaders =
+        serde_yaml::from_str("subject: X-Client-Cert-DN\nsha256: \"\"").unwrap();
+    assert!(headers.validate().is_ok());
+    assert_eq!(headers.issuer, "X-Client-Cert-Issuer");
+    assert_eq!(headers.pem, "X-Client-Cert-PEM");
+    for invalid in [
+        "pem: X-Client-Cert-DN\nsubject: x-client-cert-dn",
+        "issuer: \"Bad Name\"",
+    ] {
+        let headers: ClientCertHeaders = serde_yaml::from_str(invalid).unwrap();
+        assert!(headers.validate().is_err(), "{}", invalid);
+    }
+
+    let chain = MiddlewareChain::new(handler_fn(|req| async move {
+        let mut forwarded: Vec<String> = req
+            .headers
+            .iter()
+            .filter(|(name, _)| name.to_ascii_lowercase().starts_with("x-client-cert"))
+            .map(|(name, value)| format!("{}: {}", name, value))
+            .collect();
+        forwarded.sort();
+        Response::ok(forwarded.join("\n").into_bytes())
+    }))
+    .with(ClientCertPolicy::new(ClientCertRequirement::Optional).with_headers(headers));
+
+    let mut req = RequestBuilder::new()
+        .method(Method::GET)
+        .path("/")
+        .header("x-client-cert-dn", "CN=admin")
+        .header("X-Client-Cert-PEM", "forged")
+        .build()
+        .unwrap();
+    req.context.client_cert = Some(cert.clone());
+    let response = chain.handle(req).await;
+    assert_eq!(
+        String::from_utf8(response.body).unwrap(),
+        format!(
+            "X-Client-Cert-DN: CN=billing,O=Example\\, Inc\n\
+             X-Client-Cert-Issuer: CN=Sentinel Test Clients\n\
+             X-Client-Cert-PEM: {}",
+            escaped
+        )
+    );
+
+    // Without a certificate, spoofed values are still removed
+    let req = RequestBuilder::new()
+        .method(Method::GET)
+        .path("/")
+        .header("X-Client-Cert-Issuer", "CN=Trusted CA")
+        .build()
+        .unwrap();
+    let response = chain.handle(req).await;
+    assert!(response.body.is_empty());
+}
@@ -0,0 +1,311 @@
+//! Tests for HAR traffic capture and replay
+
+use sentinel::config::TrafficCaptureConfig;
+use sentinel::http::handler::{Handler, handler_fn};
+use sentinel::http::har::{Har, HarEntry, HarRecorder, iso8601};
+use sentinel::http::request::{Method, Request, RequestBuilder};
+use sentinel::http::response::Response;
+use sentinel::middleware::TrafficCaptureHandler;
+use sentinel::proxy::Replayer;
+use sentinel::proxy::replay::{ReplayReport, request_for};
+use sentinel::testing::{MockAction, MockBackend, MockResponse};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+fn capture_config(name: &str) -> TrafficCaptureConfig {
+    let dir = std::env::temp_dir().join(format!("sentinel-har-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    TrafficCaptureConfig {
+        dir,
+        sample_percent: 100,
+        entries_per_file: 100,
+        flush_secs: 60,
+        bodies: true,
+        max_body_bytes: 1024,
+        redact_headers: vec!["Authorization".to_string()],
+        redact_fields: vec!["password".to_string(), "token".to_string()],
+    }
+}
+
+fn har_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect();
+    files.sort();
+    files
+}
+
+fn request(method: Method, path: &str, headers: &[(&str, &str)], body: &[u8]) -> Request {
+    let mut builder = RequestBuilder::new()
+        .method(method)
+        .path(path)
+        .header("Host", "shop.example");
+    for (name, value) in headers {
+        builder = builder.header(*name, *value);
+    }
+    let mut req = builder.body(body.to_vec()).build().unwrap();
+    req.context.peer = Some("10.0.0.7:4000".parse().unwrap());
+    req
+}
+
+fn echo() -> impl Handler {
+    handler_fn(|req: Request| async move {
+        Response::new(sentinel::http::response::StatusCode::Created)
+            .header("Content-Type", "application/json")
+            .header("Set-Cookie", "session=abc")
+            .body(format!(r#"{{"path":"{}","token":"t0p"}}"#, req.path).into_bytes())
+            .build()
+    })
+}
+
+#[tokio::test]
+async fn test_capture_records_redacted_entries() {
+    let config = capture_config("record");
+    let recorder = Arc::new(HarRecorder::new(&config).unwrap());
+    let handler = TrafficCaptureHandler::new(echo(), recorder.clone()).with_tls(true);
+
+    let response = handler
+        .handle(request(
+            Method::POST,
+            "/login?user=ann&token=s3cret",
+            &[
+                ("Authorization", "Bearer abc"),
+                ("Content-Type", "application/json"),
+            ],
+            br#"{"user":"ann","password":"hunter2","nested":[{"token":"x"}]}"#,
+        ))
+        .await;
+    assert_eq!(response.status.as_u16(), 201);
+
+    let path = recorder.flush().unwrap().unwrap();
+    assert!(recorder.flush().unwrap().is_none());
+    let har = Har::load(&path).unwrap();
+    assert_eq!(har.log.version, "1.2");
+    assert_eq!(har.log.creator.name, "sentinel");
+    let entry = &har.log.entries[0];
+    assert_eq!(entry.client_address.as_deref(), Some("10.0.0.7"));
+    assert!(entry.started_date_time.ends_with('Z'));
+
+    let req = &entry.request;
+    assert_eq!(req.method, "POST");
+    assert_eq!(
+        req.url,
+        "https://shop.example/login?user=ann&token=%5BREDACTED%5D"
+    );
+    let header = |name: &str| {
+        req.headers
+            .iter()
+            .find(|h| h.name == name)
+            .map(|h| h.value.as_str())
+    };
+    assert_eq!(header("Authorization"), Some("[REDACTED]"));
+    assert_eq!(header("Host"), Some("shop.example"));
+    let body: serde_json::Value =
+        serde_json::from_str(&req.post_data.as_ref().unwrap().text).unwrap();
+    assert_eq!(body["user"], "ann");
+    assert_eq!(body["password"], "[REDACTED]");
+    assert_eq!(body["nested"][0]["token"], "[REDACTED]");
+
+    let resp = &entry.response;
+    assert_eq!(resp.status, 201);
+    assert_eq!(resp.content.mime_type, "application/json");
+    assert_eq!(
+        resp.content.text.as_deref(),
+        Some(r#"{"path":"/login?user=ann&token=s3cret","token":"[REDACTED]"}"#)
+    );
+
+    let _ = std::fs::remove_dir_all(&config.dir);
+}
+
+#[tokio::test]
+async fn test_capture_bodies_are_optional_truncated_and_binary_safe() {
+    let mut config = capture_config("bodies");
+    config.max_body_bytes = 4;
+    let recorder = Arc::new(HarRecorder::new(&config).unwrap());
+    let handler = TrafficCaptureHandler::new(
+        handler_fn(|_req| async { Response::ok(vec![0xff, 0xfe, 0x00]) }),
+        recorder.clone(),
+    );
+    handler
+        .handle(request(Method::PUT, "/upload", &[], b"0123456789"))
+        .await;
+    let har = Har::load(&recorder.flush().unwrap().unwrap()).unwrap();
+    let entry = &har.log.entries[0];
+    assert_eq!(entry.request.url, "http://shop.example/upload");
+    let post = entry.request.post_data.as_ref().unwrap();
+    assert_eq!(post.text, "0123");
+    assert_eq!(post.comment.as_deref(), Some("truncated from 10 bytes"));
+    assert_eq!(entry.request.body_size, 10);
+    assert_eq!(entry.response.content.encoding.as_deref(), Some("base64"));
+    assert_eq!(entry.response.content.text.as_deref(), Some("//4A"));
+
+    config.bodies = false;
+    let _ = std::fs::remove_dir_all(&config.dir);
+    let recorder = Arc::new(HarRecorder::new(&config).unwrap());
+    let handler = TrafficCaptureHandler::new(echo(), recorder.clone());
+    handler
+        .handle(request(Method::PUT, "/upload", &[], b"0123456789"))
+        .await;
+    let har = Har::load(&recorder.flush().unwrap().unwrap()).unwrap();
+    let entry = &har.log.entries[0];
+    assert!(entry.request.post_data.is_none());
+    assert!(entry.response.content.text.is_none());
+    assert!(entry.response.content.size > 0);
+
+    let _ = std::fs::remove_dir_all(&config.dir);
+}
+
+#[tokio::test]
+async fn test_capture_sampling_and_file_rotation() {
+    let mut config = capture_config("rotation");
+    config.entries_per_file = 2;
+    let recorder = Arc::new(HarRecorder::new(&config).unwrap());
+    let handler = TrafficCaptureHandler::new(echo(), recorder.clone());
+    for _ in 0..3 {
+        handler.handle(request(Method::GET, "/", &[], b"")).await;
+    }
+    assert_eq!(har_files(&config.dir).len(), 1);
+    recorder.flush().unwrap();
+    let files = har_files(&config.dir);
+    assert_eq!(files.len(), 2);
+    let counts: Vec<usize> = files
+        .iter()
+        .map(|f| Har::load(f).unwrap().log.entries.len())
+        .collect();
+    assert_eq!(counts.iter().sum::<usize>(), 3);
+    let _ = std::fs::remove_dir_all(&config.dir);
+
+    config.sample_percent = 0;
+    let recorder = Arc::new(HarRecorder::new(&config).unwrap());
+    let handler = TrafficCaptureHandler::new(echo(), recorder.clone());
+    for _ in 0..10 {
+        handler.handle(request(Method::GET, "/", &[], b"")).await;
+    }
+    assert!(recorder.flush().unwrap().is_none());
+    let _ = std::fs::remove_dir_all(&config.dir);
+}
+
+#[test]
+fn test_iso8601_timestamps() {
+    let time = UNIX_EPOCH + Duration::from_millis(1_760_601_600_123);
+    assert_eq!(iso8601(time), "2025-10-16T08:00:00.123Z");
+    assert_eq!(iso8601(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+}
+
+#[test]
+fn test_capture_config_validation() {
+    let mut config = capture_config("validation");
+    assert!(config.validate().is_ok());
+    config.sample_percent = 101;
+    assert!(config.validate().is_err());
+    config.sample_percent = 100;
+    config.entries_per_file = 0;
+    assert!(config.validate().is_err());
+}
+
+fn entry(json: serde_json::Value) -> HarEntry {
+    serde_json::from_value(json).unwrap()
+}
+
+#[test]
+fn test_browser_exports_are_replayable() {
+    // Browsers use HTTP/2 pseudo-headers and omit Sentinel's extensions
+    let entry = entry(serde_json::json!({
+        "startedDateTime": "2025-10-16T08:00:00.000Z",
+        "time": 12.5,
+        "request": {
+            "method": "POST",
+            "url": "https://shop.example/cart?id=7",
+            "httpVersion": "h2",
+            "headers": [
+                {"name": ":authority", "value": "shop.example"},
+                {"name": "content-type", "value": "text/plain"},
+                {"name": "content-length", "value": "99"},
+                {"name": "cookie", "value": "[REDACTED]"}
+            ],
+            "postData": {"mimeType": "text/plain", "text": "hello"}
+        },
+        "response": {"status": 200}
+    }));
+    let req = request_for(&entry).unwrap();
+    assert_eq!(req.method, Method::POST);
+    assert_eq!(req.path, "/cart?id=7");
+    assert_eq!(req.body, b"hello");
+    assert_eq!(req.header("content-type"), Some("text/plain"));
+    assert_eq!(req.header("Content-Length"), Some("5"));
+    assert_eq!(req.headers.len(), 2);
+}
+
+#[tokio::test]
+async fn test_replay_against_target() {
+    let target = MockBackend::start().await;
+    target.push(MockAction::Respond(MockResponse::new(201)));
+    target.push(MockAction::Respond(MockResponse::new(500)));
+
+    // Capture traffic, then replay it
+    let config = capture_config("replay");
+    let recorder = Arc::new(HarRecorder::new(&config).unwrap());
+    let handler = TrafficCaptureHandler::new(echo(), recorder.clone());
+    handler
+        .handle(request(
+            Method::POST,
+            "/orders",
+            &[("Authorization", "Bearer abc"), ("X-Trace", "1")],
+            b"qty=2",
+        ))
+        .await;
+    handler
+        .handle(request(Method::GET, "/orders/7", &[], b""))
+        .await;
+    let mut truncated = request(Method::PUT, "/big", &[], &[b'x'; 2048]);
+    truncated.context.peer = None;
+    handler.handle(truncated).await;
+    let har = Har::load(&recorder.flush().unwrap().unwrap()).unwrap();
+
+    let report = Replayer::new(&target.url())
+        .unwrap()
+        .replay(&har.log.entries)
+        .await;
+    assert_eq!(
+        report,
+        ReplayReport {
+            matched: 1,
+            mismatched: 1,
+            failed: 0,
+            skipped: 1,
+        }
+    );
+    assert_eq!(report.sent(), 2);
+
+    let requests = target.requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].method, Method::POST);
+    assert_eq!(requests[0].path, "/orders");
+    assert_eq!(requests[0].body, b"qty=2");
+    assert_eq!(requests[0].header("X-Trace"), Some("1"));
+    assert_eq!(requests[0].header("Authorization"), None);
+    assert_eq!(requests[1].path, "/orders/7");
+
+    let _ = std::fs::remove_dir_all(&config.dir);
+}
+
+#[tokio::test]
+async fn test_replay_counts_unreachable_target_as_failed() {
+    let target = MockBackend::start().await;
+    target.set_default(MockAction::Reset);
+    let entries = vec![entry(serde_json::json!({
+        "startedDateTime": "2025-10-16T08:00:00.000Z",
+        "time": 1.0,
+        "request": {"method": "GET", "url": "http://shop.example/"},
+        "response": {"status": 200}
+    }))];
+    let report = Replayer::new(&target.url())
+        .unwrap()
+        .with_concurrency(4)
+        .replay(&entries)
+        .await;
+    assert_eq!(report.failed, 1);
+    assert!(Replayer::new("ftp://example.com").is_err());
+}
@@ -0,0 +1,108 @@
+//! Tests for uwsgi protocol backends
+
+use sentinel::config::BackendConfig;
+use sentinel::http::handler::Handler;
+use sentinel::http::request::{Method, RequestBuilder};
+use sentinel::proxy::uwsgi::encode_request;
+use sentinel::proxy::{BackendPool, ProxyHandler};
+use sentinel::testing::{TEST_CONNECT_TIMEOUT, TEST_REQUEST_TIMEOUT, send_request};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Split a uwsgi packet into its variables and body
+fn decode_packet(packet: &[u8]) -> (HashMap<String, String>, Vec<u8>) {
+    assert_eq!(packet[0], 0);
+    assert_eq!(packet[3], 0);
+    let size = u16::from_le_bytes([packet[1], packet[2]]) as usize;
+
+    let mut vars = HashMap::new();
+    let mut data = &packet[4..4 + size];
+    while !data.is_empty() {
+        let key_len = u16::from_le_bytes([data[0], data[1]]) as usize;
+        let key = String::from_utf8(data[2..2 + key_len].to_vec()).unwrap();
+        data = &data[2 + key_len..];
+        let value_len = u16::from_le_bytes([data[0], data[1]]) as usize;
+        let value = String::from_utf8(data[2..2 + value_len].to_vec()).unwrap();
+        data = &data[2 + value_len..];
+        vars.insert(key, value);
+    }
+
+    (vars, packet[4 + size..].to_vec())
+}
+
+#[test]
+fn test_encode_request_vars() {
+    let request = RequestBuilder::new()
+        .method(Method::POST)
+        .path("/app/caf%C3%A9?x=1&y=2")
+        .version("HTTP/1.1")
+        .header("Host", "example.com:8080")
+        .header("Content-Type", "application/json")
+        .header("X-Request-Id", "abc")
+        .header("Connection", "keep-alive")
+        .body(b"{}".to_vec())
+        .build()
+        .unwrap();
+    let url = url::Url::parse("uwsgi://127.0.0.1:3031").unwrap();
+
+    let (vars, body) = decode_packet(&encode_request(&request, &url).unwrap());
+
+    assert_eq!(vars["REQUEST_METHOD"], "POST");
+    assert_eq!(vars["REQUEST_URI"], "/app/caf%C3%A9?x=1&y=2");
+    assert_eq!(vars["PATH_INFO"], "/app/café");
+    assert_eq!(vars["QUERY_STRING"], "x=1&y=2");
+    assert_eq!(vars["SERVER_NAME"], "example.com");
+    assert_eq!(vars["SERVER_PORT"], "8080");
+    assert_eq!(vars["CONTENT_TYPE"], "application/json");
+    assert_eq!(vars["CONTENT_LENGTH"], "2");
+    assert_eq!(vars["HTTP_X_REQUEST_ID"], "abc");
+    assert!(!vars.contains_key("HTTP_CONNECTION"));
+    assert_eq!(body, b"{}");
+}
+
+#[tokio::test]
+async fn test_proxy_to_uwsgi_backend() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    // A minimal uwsgi app server echoing the method and path
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut header = [0u8; 4];
+        socket.read_exact(&mut header).await.unwrap();
+        let size = u16::from_le_bytes([header[1], header[2]]) as usize;
+        let mut packet = header.to_vec();
+        packet.resize(4 + size, 0);
+        socket.read_exact(&mut packet[4..]).await.unwrap();
+
+        let (vars, _) = decode_packet(&packet);
+        let body = format!("{} {}", vars["REQUEST_METHOD"], vars["PATH_INFO"]);
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        socket.write_all(response.as_bytes()).await.unwrap();
+    });
+
+    let pool = BackendPool::new(vec![BackendConfig {
+        url: format!("uwsgi://{}", addr),
+        ..Default::default()
+    }]);
+    let handler: Arc<dyn Handler> = Arc::new(ProxyHandler::new(
+        pool,
+        TEST_CONNECT_TIMEOUT,
+        TEST_REQUEST_TIMEOUT,
+    ));
+
+    let response = send_request(
+        handler,
+        b"GET /hello HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "GET /hello");
+}
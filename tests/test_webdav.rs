@@ -0,0 +1,316 @@
+//! Tests for WebDAV mounts
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use sentinel::config::{ProxyUser, WebDavConfig};
+use sentinel::http::handler::Handler;
+use sentinel::http::parser::parse_http_request;
+use sentinel::http::request::{Method, RequestBuilder};
+use sentinel::http::response::Response;
+use sentinel::http::webdav::WebDavHandler;
+use std::path::PathBuf;
+
+fn mount(name: &str) -> WebDavConfig {
+    let root =
+        std::env::temp_dir().join(format!("sentinel-webdav-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(root.join("docs")).unwrap();
+    std::fs::write(root.join("docs/guide.txt"), "guide").unwrap();
+    WebDavConfig {
+        prefix: "/files".to_string(),
+        root,
+        users: vec![ProxyUser {
+            username: "alice".to_string(),
+            password: "s3cret".to_string(),
+        }],
+        read_only: false,
+    }
+}
+
+fn basic(user: &str, password: &str) -> String {
+    format!(
+        "Basic {}",
+        STANDARD.encode(format!("{}:{}", user, password))
+    )
+}
+
+async fn send(
+    handler: &WebDavHandler,
+    method: Method,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Response {
+    let mut builder = RequestBuilder::new()
+        .method(method)
+        .path(path)
+        .header("Authorization", basic("alice", "s3cret"));
+    for (name, value) in headers {
+        builder = builder.header(*name, *value);
+    }
+    handler
+        .handle(builder.body(body.to_vec()).build().unwrap())
+        .await
+}
+
+fn text(response: &Response) -> String {
+    String::from_utf8_lossy(&response.body).into_owned()
+}
+
+#[test]
+fn test_webdav_methods_parse() {
+    for name in ["PROPFIND", "MKCOL", "COPY", "MOVE"] {
+        let method = Method::from_str(name).unwrap();
+        assert_eq!(format!("{:?}", method), name);
+    }
+    let raw = b"PROPFIND /files/ HTTP/1.1\r\nHost: a\r\nDepth: 1\r\n\r\n";
+    let (req, _) = parse_http_request(raw).unwrap();
+    assert_eq!(req.method, Method::PROPFIND);
+}
+
+#[tokio::test]
+async fn test_requires_credentials() {
+    let config = mount("auth");
+    let handler = WebDavHandler::new(config.clone());
+
+    let anonymous = RequestBuilder::new()
+        .method(Method::GET)
+        .path("/files/docs/guide.txt")
+        .build()
+        .unwrap();
+    let response = handler.handle(anonymous).await;
+    assert_eq!(response.status.as_u16(), 401);
+    assert_eq!(
+        response.headers.get("WWW-Authenticate").map(String::as_str),
+        Some("Basic realm=\"sentinel\"")
+    );
+
+    let wrong = RequestBuilder::new()
+        .method(Method::GET)
+        .path("/files/docs/guide.txt")
+        .header("authorization", basic("alice", "guess"))
+        .build()
+        .unwrap();
+    assert_eq!(handler.handle(wrong).await.status.as_u16(), 401);
+
+    let response = send(&handler, Method::GET, "/files/docs/guide.txt", &[], b"").await;
+    assert_eq!(response.status.as_u16(), 200);
+    assert_eq!(response.body, b"guide");
+
+    let response = send(&handler, Method::OPTIONS, "/files", &[], b"").await;
+    assert_eq!(response.headers.get("DAV").map(String::as_str), Some("1"));
+    assert!(response.headers["Allow"].contains("PROPFIND"));
+
+    std::fs::remove_dir_all(&config.root).unwrap();
+}
+
+#[tokio::test]
+async fn test_upload_and_list() {
+    let config = mount("list");
+    let handler = WebDavHandler::new(config.clone());
+
+    let response = send(&handler, Method::MKCOL, "/files/photos", &[], b"").await;
+    assert_eq!(response.status.as_u16(), 201);
+    let response = send(&handler, Method::MKCOL, "/files/photos", &[], b"").await;
+    assert_eq!(response.status.as_u16(), 405);
+    let response = send(&handler, Method::MKCOL, "/files/a/b", &[], b"").await;
+    assert_eq!(response.status.as_u16(), 409);
+
+    let response = send(
+        &handler,
+        Method::PUT,
+        "/files/photos/my%20cat.txt",
+        &[],
+        b"meow",
+    )
+    .await;
+    assert_eq!(response.status.as_u16(), 201);
+    let response = send(
+        &handler,
+        Method::PUT,
+        "/files/photos/my%20cat.txt",
+        &[],
+        b"purr",
+    )
+    .await;
+    assert_eq!(response.status.as_u16(), 204);
+    assert_eq!(
+        std::fs::read(config.root.join("photos/my cat.txt")).unwrap(),
+        b"purr"
+    );
+    let response = send(&handler, Method::PUT, "/files/missing/x.txt", &[], b"x").await;
+    assert_eq!(response.status.as_u16(), 409);
+
+    let response = send(
+        &handler,
+        Method::PROPFIND,
+        "/files/",
+        &[("Depth", "1")],
+        b"",
+    )
+    .await;
+    assert_eq!(response.status.as_u16(), 207);
+    let xml = text(&response);
+    assert!(xml.contains("<D:href>/files/</D:href>"));
+    assert!(xml.contains("<D:href>/files/docs/</D:href>"));
+    assert!(xml.contains("<D:href>/files/photos/</D:href>"));
+    assert!(!xml.contains("my%20cat"));
+    assert!(xml.contains("<D:collection/>"));
+
+    let response = send(
+        &handler,
+        Method::PROPFIND,
+        "/files/photos",
+        &[("Depth", "1")],
+        b"",
+    )
+    .await;
+    let xml = text(&response);
+    assert!(xml.contains("<D:href>/files/photos/my%20cat.txt</D:href>"));
+    assert!(xml.contains("<D:getcontentlength>4</D:getcontentlength>"));
+    assert!(xml.contains(" GMT</D:getlastmodified>"));
+
+    let response = send(
+        &handler,
+        Method::PROPFIND,
+        "/files/photos",
+        &[("Depth", "0")],
+        b"",
+    )
+    .await;
+    assert!(!text(&response).contains("my%20cat"));
+    let response = send(&handler, Method::PROPFIND, "/files/nope", &[], b"").await;
+    assert_eq!(response.status.as_u16(), 404);
+
+    std::fs::remove_dir_all(&config.root).unwrap();
+}
+
+#[tokio::test]
+async fn test_copy_move_and_delete() {
+    let config = mount("manage");
+    let handler = WebDavHandler::new(config.clone());
+    let exists = |path: &str| config.root.join(path).exists();
+
+    let response = send(
+        &handler,
+        Method::COPY,
+        "/files/docs",
+        &[("Destination", "http://share.example/files/backup")],
+        b"",
+    )
+    .await;
+    assert_eq!(response.status.as_u16(), 201);
+    assert!(exists("backup/guide.txt"));
+    assert!(exists("docs/guide.txt"));
+
+    let response = send(
+        &handler,
+        Method::MOVE,
+        "/files/docs/guide.txt",
+        &[
+            ("Destination", "/files/backup/guide.txt"),
+            ("Overwrite", "F"),
+        ],
+        b"",
+    )
+    .await;
+    assert_eq!(response.status.as_u16(), 412);
+
+    let response = send(
+        &handler,
+        Method::MOVE,
+        "/files/docs/guide.txt",
+        &[("Destination", "/files/backup/guide.txt")],
+        b"",
+    )
+    .await;
+    assert_eq!(response.status.as_u16(), 204);
+    assert!(!exists("docs/guide.txt"));
+
+    let response = send(
+        &handler,
+        Method::COPY,
+        "/files/backup",
+        &[("Destination", "/files/backup/inner")],
+        b"",
+    )
+    .await;
+    assert_eq!(response.status.as_u16(), 403);
+    let response = send(
+        &handler,
+        Method::MOVE,
+        "/files/backup",
+        &[("Destination", "/elsewhere/backup")],
+        b"",
+    )
+    .await;
+    assert_eq!(response.status.as_u16(), 403);
+    let response = send(&handler, Method::MOVE, "/files/backup", &[], b"").await;
+    assert_eq!(response.status.as_u16(), 400);
+
+    let response = send(&handler, Method::DELETE, "/files/backup", &[], b"").await;
+    assert_eq!(response.status.as_u16(), 204);
+    assert!(!exists("backup"));
+    let response = send(&handler, Method::DELETE, "/files/backup", &[], b"").await;
+    assert_eq!(response.status.as_u16(), 404);
+    let response = send(&handler, Method::DELETE, "/files/", &[], b"").await;
+    assert_eq!(response.status.as_u16(), 403);
+
+    std::fs::remove_dir_all(&config.root).unwrap();
+}
+
+#[tokio::test]
+async fn test_stays_beneath_root() {
+    let config = mount("escape");
+    let outside: PathBuf = config.root.with_extension("outside");
+    std::fs::create_dir_all(&outside).unwrap();
+    std::os::unix::fs::symlink(&outside, config.root.join("link")).unwrap();
+    let handler = WebDavHandler::new(config.clone());
+
+    for path in ["/files/%2e%2e/secret", "/files/..", "/files/a%2fb"] {
+        let response = send(&handler, Method::PUT, path, &[], b"x").await;
+        assert_eq!(response.status.as_u16(), 400, "{}", path);
+    }
+    let response = send(&handler, Method::PUT, "/files/link/escaped.txt", &[], b"x").await;
+    assert_eq!(response.status.as_u16(), 403);
+    let response = send(&handler, Method::DELETE, "/files/link", &[], b"").await;
+    assert_eq!(response.status.as_u16(), 403);
+    assert!(!outside.join("escaped.txt").exists());
+    assert!(outside.exists());
+
+    // Symlinks are not listed
+    let response = send(&handler, Method::PROPFIND, "/files", &[("Depth", "1")], b"").await;
+    assert!(!text(&response).contains("link"));
+
+    std::fs::remove_dir_all(&config.root).unwrap();
+    std::fs::remove_dir_all(&outside).unwrap();
+}
+
+#[tokio::test]
+async fn test_read_only_mount() {
+    let mut config = mount("readonly");
+    config.read_only = true;
+    let handler = WebDavHandler::new(config.clone());
+
+    let response = send(&handler, Method::PUT, "/files/new.txt", &[], b"x").await;
+    assert_eq!(response.status.as_u16(), 403);
+    let response = send(&handler, Method::DELETE, "/files/docs", &[], b"").await;
+    assert_eq!(response.status.as_u16(), 403);
+    let response = send(&handler, Method::PROPFIND, "/files/docs", &[], b"").await;
+    assert_eq!(response.status.as_u16(), 207);
+    assert!(config.root.join("docs/guide.txt").exists());
+
+    std::fs::remove_dir_all(&config.root).unwrap();
+}
+
+#[test]
+fn test_webdav_config_validation() {
+    let mut config = mount("validation");
+    assert!(config.validate().is_ok());
+    config.prefix = "/files/".to_string();
+    assert!(config.validate().is_err());
+    config.prefix = "/files".to_string();
+    config.users.clear();
+    assert!(config.validate().is_err());
+    std::fs::remove_dir_all(&config.root).unwrap();
+}
@@ -0,0 +1,27 @@
+//! Tests for the Windows service subcommands
+
+use std::process::Command;
+
+#[cfg(not(windows))]
+#[test]
+fn test_service_commands_need_windows() {
+    let output = Command::new(env!("CARGO_BIN_EXE_sentinel"))
+        .args(["service", "install"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("only available on Windows"));
+}
+
+#[cfg(windows)]
+#[test]
+fn test_service_run_outside_scm_fails() {
+    // The dispatcher refuses processes the SCM did not start
+    let dir = std::env::temp_dir();
+    let output = Command::new(env!("CARGO_BIN_EXE_sentinel"))
+        .args(["service", "run", &dir.display().to_string()])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Service Control Manager"));
+}
@@ -0,0 +1,254 @@
+//! Tests for HTTP response serialization and writing
+
+use bytes::Bytes;
+use sentinel::config::{BackendServerHeader, ServerConfig};
+use sentinel::http::connection::Connection;
+use sentinel::http::cookie::Cookie;
+use sentinel::http::handler::handler_fn;
+use sentinel::http::parser::HeadLimits;
+use sentinel::http::response::{Response, ResponseBuilder, StatusCode};
+use sentinel::http::writer::{ResponseWriter, ServerIdentity, write_body_stream};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+#[test]
+fn test_serialize_status_line_and_body() {
+    let response = ResponseBuilder::new(StatusCode::NotFound)
+        .body(b"missing".to_vec())
+        .build();
+
+    let writer = ResponseWriter::new(&response);
+    let wire = String::from_utf8_lossy(writer.serialize());
+
+    assert!(wire.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    assert!(wire.contains("Content-Length: 7\r\n"));
+    assert!(wire.ends_with("\r\n\r\nmissing"));
+}
+
+#[tokio::test]
+async fn test_streamed_body_is_chunked() {
+    let (tx, rx) = tokio::sync::mpsc::channel(4);
+    let mut response = ResponseBuilder::new(StatusCode::Ok)
+        .body(b"first".to_vec())
+        .build();
+    response.headers.remove("Content-Length");
+    response.stream = Some(rx);
+
+    let mut wire = ResponseWriter::new(&response).serialize().to_vec();
+    tx.send(Bytes::from_static(b"second")).await.unwrap();
+    drop(tx);
+    write_body_stream(&mut wire, response.stream.as_mut().unwrap())
+        .await
+        .unwrap();
+
+    let wire = String::from_utf8(wire).unwrap();
+    assert!(wire.contains("Transfer-Encoding: chunked\r\n"));
+    assert!(!wire.contains("Content-Length"));
+    assert!(wire.ends_with("\r\n\r\n5\r\nfirst\r\n6\r\nsecond\r\n0\r\n\r\n"));
+}
+
+#[test]
+fn test_serialize_other_status_code() {
+    let response = ResponseBuilder::new(StatusCode::Other(418)).build();
+
+    let writer = ResponseWriter::new(&response);
+    let wire = String::from_utf8_lossy(writer.serialize());
+
+    assert!(wire.starts_with("HTTP/1.1 418 I'm a teapot\r\n"));
+}
+
+#[test]
+fn test_serialize_adds_connection_close_by_default() {
+    let response = ResponseBuilder::new(StatusCode::Ok).build();
+
+    let writer = ResponseWriter::new(&response);
+    let wire = String::from_utf8_lossy(writer.serialize());
+
+    assert!(wire.contains("Connection: close\r\n"));
+}
+
+#[test]
+fn test_serialize_keeps_explicit_connection_header() {
+    let response = ResponseBuilder::new(StatusCode::Ok)
+        .header("Connection", "keep-alive")
+        .build();
+
+    let writer = ResponseWriter::new(&response);
+    let wire = String::from_utf8_lossy(writer.serialize());
+
+    assert!(wire.contains("Connection: keep-alive\r\n"));
+    assert!(!wire.contains("Connection: close"));
+}
+
+#[tokio::test]
+async fn test_write_to_vec_sink() {
+    let response = ResponseBuilder::new(StatusCode::Ok)
+        .header("Content-Type", "text/plain")
+        .body(b"hello".to_vec())
+        .build();
+
+    let mut writer = ResponseWriter::new(&response);
+    let mut sink: Vec<u8> = Vec::new();
+    writer.write_to_stream(&mut sink).await.unwrap();
+
+    assert_eq!(sink, writer.serialize());
+}
+
+#[tokio::test]
+async fn test_write_to_duplex_stream() {
+    let response = ResponseBuilder::new(StatusCode::Created)
+        .body(b"done".to_vec())
+        .build();
+
+    let (mut client, mut server) = tokio::io::duplex(16);
+
+    let expected = ResponseWriter::new(&response).serialize().to_vec();
+    let write = tokio::spawn(async move {
+        let mut writer = ResponseWriter::new(&response);
+        writer.write_to_stream(&mut server).await
+    });
+
+    let mut received = vec![0u8; expected.len()];
+    client.read_exact(&mut received).await.unwrap();
+    write.await.unwrap().unwrap();
+
+    assert_eq!(received, expected);
+}
+
+#[test]
+fn test_serialize_writes_each_cookie_on_its_own_line() {
+    let response = ResponseBuilder::new(StatusCode::Ok)
+        .cookie(Cookie::new("a", "1"))
+        .cookie(Cookie::new("b", "2").http_only())
+        .build();
+
+    let writer = ResponseWriter::new(&response);
+    let wire = String::from_utf8_lossy(writer.serialize());
+
+    assert!(wire.contains("\r\nSet-Cookie: a=1\r\n"));
+    assert!(wire.contains("\r\nSet-Cookie: b=2; HttpOnly\r\n"));
+}
+
+fn backend_response() -> Response {
+    ResponseBuilder::new(StatusCode::Ok)
+        .header("server", "Apache/2.4.1")
+        .header("X-Powered-By", "PHP/8.1")
+        .header("X-Cache", "HIT")
+        .build()
+}
+
+#[test]
+fn test_server_identity_keeps_replaces_or_strips_server() {
+    let identity = ServerIdentity::default()
+        .with_server("sentinel/1.0")
+        .with_scrubbed("x-powered-by");
+
+    let mut response = backend_response();
+    identity.apply(&mut response);
+    assert_eq!(response.headers["server"], "Apache/2.4.1");
+    assert!(!response.headers.contains_key("X-Powered-By"));
+    assert_eq!(response.headers["X-Cache"], "HIT");
+
+    let mut response = backend_response();
+    identity
+        .clone()
+        .with_backend(BackendServerHeader::Replace)
+        .apply(&mut response);
+    assert!(!response.headers.contains_key("server"));
+    assert_eq!(response.headers["Server"], "sentinel/1.0");
+
+    let mut response = backend_response();
+    identity
+        .with_backend(BackendServerHeader::Strip)
+        .apply(&mut response);
+    assert!(
+        !response
+            .headers
+            .keys()
+            .any(|k| k.eq_ignore_ascii_case("Server"))
+    );
+
+    let mut response = backend_response();
+    ServerIdentity::default().apply(&mut response);
+    assert_eq!(response.headers, backend_response().headers);
+}
+
+#[test]
+fn test_server_config_builds_identity() {
+    let server: ServerConfig = serde_yaml::from_str("listen_addr: \"127.0.0.1:0\"").unwrap();
+    assert_eq!(
+        server.server_header,
+        format!("sentinel/{}", env!("CARGO_PKG_VERSION"))
+    );
+    assert_eq!(server.backend_server_header, BackendServerHeader::Keep);
+    let mut response = ResponseBuilder::new(StatusCode::Ok).build();
+    server.server_identity().unwrap().apply(&mut response);
+    assert_eq!(response.headers["Server"], server.server_header);
+
+    let server: ServerConfig = serde_yaml::from_str(
+        r#"
+listen_addr: "127.0.0.1:0"
+server_header: ""
+backend_server_header: replace
+scrub_response_headers: [X-Powered-By]
+"#,
+    )
+    .unwrap();
+    let mut response = backend_response();
+    server.server_identity().unwrap().apply(&mut response);
+    let mut names: Vec<_> = response.headers.keys().collect();
+    names.sort();
+    assert_eq!(names, ["Content-Length", "X-Cache"]);
+
+    let invalid: ServerConfig =
+        serde_yaml::from_str("listen_addr: x\nscrub_response_headers: [\"Bad Name\"]").unwrap();
+    assert!(invalid.server_identity().is_err());
+    let invalid: ServerConfig =
+        serde_yaml::from_str("listen_addr: x\nserver_header: \"a\\r\\nb\"").unwrap();
+    assert!(invalid.server_identity().is_err());
+}
+
+#[tokio::test]
+async fn test_connection_identifies_every_response() {
+    let identity = Arc::new(
+        ServerIdentity::default()
+            .with_server("sentinel/1.0")
+            .with_backend(BackendServerHeader::Replace)
+            .with_scrubbed("X-Powered-By"),
+    );
+    for (request, status_line) in [
+        (
+            "GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 200 OK\r\n",
+        ),
+        (
+            "GET /too-long HTTP/1.1\r\nHost: a\r\n\r\n",
+            "HTTP/1.1 414 URI Too Long\r\n",
+        ),
+    ] {
+        let handler = Arc::new(handler_fn(|_| async { backend_response() }));
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let identity = identity.clone();
+        let conn = tokio::spawn(async move {
+            Connection::with_handler(server, handler)
+                .with_head_limits(HeadLimits {
+                    max_uri_length: 4,
+                    ..HeadLimits::default()
+                })
+                .with_server_identity(identity)
+                .run()
+                .await
+        });
+
+        client.write_all(request.as_bytes()).await.unwrap();
+        let mut output = Vec::new();
+        client.read_to_end(&mut output).await.unwrap();
+        conn.await.unwrap().unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with(status_line), "{}", output);
+        assert!(output.contains("\r\nServer: sentinel/1.0\r\n"));
+        assert!(!output.contains("Apache"));
+        assert!(!output.contains("X-Powered-By"));
+    }
+}